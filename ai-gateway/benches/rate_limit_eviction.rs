@@ -0,0 +1,77 @@
+//! Measures how long rate limit checks stall while expired in-memory rate
+//! limit state is being evicted.
+//!
+//! Run with `cargo bench -p ai-gateway --bench rate_limit_eviction`.
+use std::{
+    num::NonZeroU32,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use ai_gateway::{
+    config::rate_limit::GcraConfig,
+    middleware::rate_limit::in_memory::InMemoryRateLimiter,
+};
+
+const KEYS: [u64; 3] = [100_000, 1_000_000, 4_000_000];
+const REFILL_FREQUENCY: Duration = Duration::from_millis(100);
+
+fn main() {
+    for keys in KEYS {
+        bench(keys);
+    }
+}
+
+fn bench(keys: u64) {
+    let limiter = Arc::new(
+        InMemoryRateLimiter::<u64>::new(&GcraConfig {
+            refill_frequency: REFILL_FREQUENCY,
+            capacity: NonZeroU32::new(10).unwrap(),
+        })
+        .unwrap(),
+    );
+    let start = Instant::now();
+    for key in 0..keys {
+        let _ = limiter.check(&key, start);
+    }
+    // let all the state expire
+    thread::sleep(REFILL_FREQUENCY);
+
+    let done = Arc::new(AtomicBool::new(false));
+    let collector = {
+        let limiter = Arc::clone(&limiter);
+        let done = Arc::clone(&done);
+        thread::spawn(move || {
+            let gc_start = Instant::now();
+            let evicted = limiter.evict_expired(Instant::now());
+            done.store(true, Ordering::Release);
+            (evicted, gc_start.elapsed())
+        })
+    };
+
+    let mut latencies = Vec::new();
+    let mut key = keys;
+    while !done.load(Ordering::Acquire) {
+        let check_start = Instant::now();
+        let _ = limiter.check(&key, Instant::now());
+        latencies.push(check_start.elapsed());
+        key += 1;
+    }
+    let (evicted, gc_duration) = collector.join().unwrap();
+
+    latencies.sort_unstable();
+    let p99 = latencies
+        .get(latencies.len() * 99 / 100)
+        .copied()
+        .unwrap_or_default();
+    let max = latencies.last().copied().unwrap_or_default();
+    println!(
+        "keys={keys} evicted={evicted} gc={gc_duration:?} checks={} \
+         p99={p99:?} max={max:?}",
+        latencies.len()
+    );
+}
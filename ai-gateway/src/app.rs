@@ -11,10 +11,13 @@ use futures::future::BoxFuture;
 use http_cache::MokaManager;
 use meltdown::Token;
 use moka::future::Cache;
-use opentelemetry::global;
+use opentelemetry::{global, metrics::Meter};
 use rustc_hash::FxHashMap as HashMap;
-use telemetry::{make_span::SpanFactory, tracing::MakeRequestId};
-use tokio::sync::RwLock;
+use telemetry::{
+    make_span::{RecordStatus, SpanFactory},
+    tracing::MakeRequestId,
+};
+use tokio::sync::{OnceCell, RwLock};
 use tower::{ServiceBuilder, buffer::BufferLayer, util::BoxCloneService};
 use tower_http::{
     ServiceBuilderExt,
@@ -27,27 +30,59 @@ use tower_http::{
     trace::TraceLayer,
 };
 use tracing::{Level, info};
+use typed_builder::TypedBuilder;
 
 use crate::{
     app_state::{AppState, InnerAppState},
     cache::{CacheClient, RedisCacheManager},
     cli,
-    config::{Config, cache::CacheStore, server::TlsConfig},
+    config::{Config, cache::CacheStore, conversation, server::TlsConfig},
     control_plane::control_plane_state::StateWithMetadata,
     discover::monitor::{
         health::provider::HealthMonitorMap, metrics::EndpointMetricsRegistry,
-        rate_limit::RateLimitMonitorMap,
+        rate_limit::RateLimitMonitorMap, state_sync::StateSync,
+    },
+    dispatcher::{
+        adaptive_limit::AdaptiveLimiters, bulkhead::Bulkheads, chaos::Faults,
+        gemini_cache::CachedContents,
     },
     error::{init::InitError, runtime::RuntimeError},
-    logger::service::JawnClient,
-    metrics::{self, Metrics, attribute_extractor::AttributeExtractor},
-    middleware::response_headers::ResponseHeaderLayer,
-    router::meta::MetaRouter,
-    store::{connect, minio::BaseMinioClient, router::RouterStore},
+    logger::{
+        batch::LogBatcher, correlation::Correlations, dlq::DeadLetterQueue,
+        journal::RequestJournal, queue::LogQueue, service::JawnClient,
+        usage::UsageAggregator,
+    },
+    metrics::{
+        self, Metrics, attribute_extractor::AttributeExtractor,
+        body_size::SizeAnomalies, spend::SpendAnomalies,
+        system::SystemPressure, throughput::Throughputs,
+    },
+    middleware::{
+        auth::Authenticator, mapper::model::StoredModelMappings,
+        rate_limit::in_memory::InMemoryRateLimiter,
+        response_headers::ResponseHeaderLayer,
+    },
+    router::{meta::MetaRouter, models::ModelLists},
+    store::{
+        connect,
+        conversation::ConversationStore,
+        leader::Leadership,
+        minio::BaseMinioClient,
+        model_mapping::{DbModelMapping, ModelMappingStore},
+        router::RouterStore,
+    },
     types::provider::ProviderKeys,
     utils::{
-        catch_panic::PanicResponder, handle_error::ErrorHandlerLayer,
-        health_check::HealthCheckLayer, timer::TimerLayer,
+        admin::AdminLayer,
+        catch_panic::PanicResponder,
+        client_ip::ClientIpLayer,
+        deadline::DeadlineLayer,
+        deployment_info::DeploymentInfoLayer,
+        freeze::{FreezeLayer, Freezes},
+        handle_error::ErrorHandlerLayer,
+        header_hygiene::HeaderHygieneLayer,
+        health_check::HealthCheckLayer,
+        timer::TimerLayer,
         validate_config::ValidateRouterConfigLayer,
     },
 };
@@ -179,12 +214,41 @@ impl tower::Service<crate::types::request::Request> for App {
     }
 }
 
+/// Hooks for applications embedding the gateway.
+#[derive(Debug, Default, TypedBuilder)]
+#[builder(field_defaults(default, setter(strip_option)))]
+pub struct AppOptions {
+    /// Authenticates requests in place of the gateway's own authentication.
+    pub authenticator: Option<Arc<dyn Authenticator>>,
+    /// The meter the gateway's metrics are recorded with, instead of the
+    /// global meter.
+    pub meter: Option<Meter>,
+}
+
 impl App {
     pub async fn new(config: Config) -> Result<Self, InitError> {
+        Self::with_options(config, AppOptions::default()).await
+    }
+
+    /// Creates the app with the hooks of an application embedding it.
+    ///
+    /// The app doesn't bind a listener of its own: it's a
+    /// [`tower::Service`] which the host serves, e.g. with
+    /// [`App::into_router`]. The background services run by the
+    /// `ai-gateway` binary, such as the control plane client and the
+    /// provider monitors, are left to the host as well.
+    pub async fn with_options(
+        config: Config,
+        options: AppOptions,
+    ) -> Result<Self, InitError> {
         tracing::debug!("creating app");
-        let app_state = Self::build_app_state(config).await?;
+        let meter =
+            options.meter.unwrap_or_else(|| global::meter(SERVICE_NAME));
+        let app_state =
+            Self::build_app_state(config, &meter, options.authenticator)
+                .await?;
         let service_stack =
-            Self::build_service_stack(app_state.clone()).await?;
+            Self::build_service_stack(app_state.clone(), meter).await?;
 
         let app = Self {
             state: app_state,
@@ -197,7 +261,11 @@ impl App {
     /// Initializes all the clients, managers, and other stateful components
     /// that are shared across the application. This includes setting up
     /// metrics, monitoring, caching, and API keys.
-    async fn build_app_state(config: Config) -> Result<AppState, InitError> {
+    async fn build_app_state(
+        config: Config,
+        meter: &Meter,
+        authenticator: Option<Arc<dyn Authenticator>>,
+    ) -> Result<AppState, InitError> {
         let minio = BaseMinioClient::new(config.minio.clone())?;
         let router_store = if config.deployment_target.is_cloud() {
             let pg_pool = connect(&config.database).await?;
@@ -208,8 +276,55 @@ impl App {
         };
         let jawn_http_client = JawnClient::new()?;
 
-        let meter = global::meter(SERVICE_NAME);
-        let metrics = metrics::Metrics::new(&meter);
+        let metrics =
+            metrics::Metrics::new(meter, &config.telemetry.histogram_buckets);
+        let log_queue = LogQueue::new(&config.logger, metrics.logger.clone());
+        let dlq = config
+            .logger
+            .dlq
+            .clone()
+            .map(|dlq_config| {
+                DeadLetterQueue::open(dlq_config, metrics.logger.clone())
+            })
+            .transpose()?;
+        let journal = config
+            .logger
+            .journal
+            .as_ref()
+            .map(|journal_config| {
+                RequestJournal::open(
+                    journal_config,
+                    &metrics.logger.unknown_outcomes,
+                )
+            })
+            .transpose()?;
+        let log_batcher = match &config.logger.batch {
+            Some(batch_config) => Some(LogBatcher::new(
+                batch_config.clone(),
+                jawn_http_client.request_client.clone(),
+                config
+                    .helicone
+                    .base_url
+                    .join("/v1/log/request")
+                    .map_err(InitError::HeliconeLogUrl)?,
+                metrics.logger.clone(),
+                metrics.error_count.clone(),
+                dlq.clone(),
+            )),
+            None => None,
+        };
+        let usage = match &config.logger.usage_report {
+            Some(_) if !config.deployment_target.is_cloud() => {
+                return Err(InitError::UsageReportOnlyCloud);
+            }
+            Some(_) => Some(UsageAggregator::default()),
+            None => None,
+        };
+        let size_anomalies =
+            config.size_anomalies.clone().map(SizeAnomalies::new);
+        let spend_anomalies =
+            config.spend_anomalies.clone().map(SpendAnomalies::new);
+        let chaos = config.chaos.clone().map(Faults::new);
         let endpoint_metrics = EndpointMetricsRegistry::new(&config);
         let health_monitor = HealthMonitorMap::default();
         let rate_limit_monitor = RateLimitMonitorMap::default();
@@ -219,12 +334,33 @@ impl App {
             .rate_limit
             .as_ref()
             .map(|rl| {
-                crate::config::rate_limit::limiter_config(&rl.limits)
-                    .map(Arc::new)
+                InMemoryRateLimiter::new(&rl.limits.per_api_key).map(Arc::new)
             })
             .transpose()?;
 
         let cache_manager = setup_cache(&config, metrics.clone());
+        let conversation_store =
+            setup_conversation_store(&config, router_store.as_ref()).await?;
+        let state_sync = config
+            .discover
+            .state_sync
+            .as_ref()
+            .map(StateSync::new)
+            .transpose()?;
+        let leadership = if config.deployment_target.is_cloud()
+            && config.database.leader_election.is_some()
+        {
+            Leadership::elected()
+        } else {
+            Leadership::unelected()
+        };
+
+        let bulkheads = config.dispatcher.bulkhead.clone().map(Bulkheads::new);
+        let adaptive_limiters = config
+            .dispatcher
+            .adaptive_concurrency
+            .clone()
+            .map(AdaptiveLimiters::new);
 
         let helicone_api_keys = if config.deployment_target.is_cloud()
             && let Some(router_store_ref) = router_store.as_ref()
@@ -246,26 +382,70 @@ impl App {
         } else {
             None
         };
+        let model_mappings = StoredModelMappings::default();
+        if let Some(router_store) = router_store.as_ref() {
+            let stored = ModelMappingStore::new(router_store.pool.clone())
+                .all()
+                .await
+                .map_err(|e| InitError::InitModelMappings(e.to_string()))?;
+            tracing::info!("loaded initial {} model mappings", stored.len());
+            model_mappings
+                .replace(stored.into_iter().filter_map(DbModelMapping::parse));
+        }
         let provider_keys = ProviderKeys::new(&config, &metrics);
+        let deployment = config.deployment.info();
+        tracing::info!(
+            version = %deployment.version,
+            region = deployment.region.as_deref().unwrap_or("unknown"),
+            availability_zone =
+                deployment.availability_zone.as_deref().unwrap_or("unknown"),
+            instance = deployment.instance.as_deref().unwrap_or("unknown"),
+            "identified deployment"
+        );
 
         let app_state = AppState(Arc::new(InnerAppState {
             config,
+            deployment,
+            authenticator,
             minio,
             router_store,
             jawn_http_client,
+            log_queue,
+            log_batcher,
+            dlq,
+            journal,
+            usage,
             control_plane_state: Arc::new(RwLock::new(
                 StateWithMetadata::default(),
             )),
             provider_keys,
             global_rate_limit,
             router_rate_limits: RwLock::new(HashMap::default()),
+            retry_budgets: RwLock::new(HashMap::default()),
+            webhooks: std::sync::Mutex::default(),
+            size_anomalies,
+            spend_anomalies,
+            correlations: Correlations::default(),
+            throughputs: Throughputs::default(),
+            chaos,
+            freezes: Freezes::default(),
             metrics,
+            system_pressure: SystemPressure::default(),
             endpoint_metrics,
             health_monitors: health_monitor,
             rate_limit_monitors: rate_limit_monitor,
             rate_limit_senders: RwLock::new(HashMap::default()),
             rate_limit_receivers: RwLock::new(HashMap::default()),
+            state_sync,
+            leadership,
+            bulkheads,
+            adaptive_limiters,
+            gemini_cached_contents: CachedContents::default(),
+            model_lists: ModelLists::default(),
+            direct_proxies: OnceCell::new(),
+            model_mappings,
             cache_manager,
+            conversation_store,
             router_tx: RwLock::new(None),
             helicone_api_keys: RwLock::new(helicone_api_keys),
             router_organization_map: RwLock::new(HashMap::default()),
@@ -278,13 +458,13 @@ impl App {
     /// layers and the main router.
     async fn build_service_stack(
         app_state: AppState,
+        meter: Meter,
     ) -> Result<BoxedServiceStack, InitError> {
-        let meter = global::meter(SERVICE_NAME);
         let otel_metrics_layer =
             tower_otel_http_metrics::HTTPMetricsLayerBuilder::builder()
                 .with_meter(meter)
                 .with_response_extractor::<_, axum_core::body::Body>(
-                    AttributeExtractor,
+                    AttributeExtractor::new(app_state.0.metrics.labels.clone()),
                 )
                 .build()?;
 
@@ -313,9 +493,13 @@ impl App {
                         Level::INFO,
                         app_state.config().telemetry.propagate,
                     ))
+                    .on_response(RecordStatus::default())
                     .on_body_chunk(())
                     .on_eos(()),
             )
+            .layer(ClientIpLayer::new(
+                app_state.config().server.trusted_proxies.clone(),
+            ))
             .layer(otel_metrics_layer)
             .set_x_request_id(MakeRequestId)
             .propagate_x_request_id()
@@ -323,9 +507,16 @@ impl App {
             .layer(metrics::request_count::Layer::new(app_state.clone()))
             .layer(compression_layer)
             .layer(cors_layer)
+            .layer(HeaderHygieneLayer::new(
+                app_state.config().server.header_limits.clone(),
+            ))
             .layer(HealthCheckLayer::new())
+            .layer(AdminLayer::new(app_state.clone()))
+            .layer(FreezeLayer::new(app_state.clone()))
+            .layer(DeploymentInfoLayer::new(app_state.0.deployment.clone()))
             .layer(ValidateRouterConfigLayer::new())
             .layer(TimerLayer::new())
+            .layer(DeadlineLayer::new())
             .layer(ErrorHandlerLayer::new(app_state.clone()))
             .layer(ResponseHeaderLayer::new(
                 app_state.response_headers_config(),
@@ -394,6 +585,52 @@ impl meltdown::Service for App {
     }
 }
 
+/// An [`App`] which can be mounted in an axum [`Router`](axum::Router).
+///
+/// axum requires services to be `Sync`, which the app's service stack
+/// isn't. The app is only locked when it's cloned, since the service is
+/// otherwise accessed through `&mut self`.
+#[cfg(feature = "axum")]
+pub struct EmbeddedApp(std::sync::Mutex<App>);
+
+#[cfg(feature = "axum")]
+impl Clone for EmbeddedApp {
+    fn clone(&self) -> Self {
+        let app = self.0.lock().expect("app lock poisoned").clone();
+        Self(std::sync::Mutex::new(app))
+    }
+}
+
+#[cfg(feature = "axum")]
+impl tower::Service<crate::types::request::Request> for EmbeddedApp {
+    type Response = AppResponse;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        ctx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.0.get_mut().expect("app lock poisoned").poll_ready(ctx)
+    }
+
+    fn call(&mut self, req: crate::types::request::Request) -> Self::Future {
+        self.0.get_mut().expect("app lock poisoned").call(req)
+    }
+}
+
+#[cfg(feature = "axum")]
+impl App {
+    /// Returns an axum router serving the gateway's routes, which a host
+    /// application can [`merge`](axum::Router::merge) or
+    /// [`nest`](axum::Router::nest) into its own router.
+    #[must_use]
+    pub fn into_router(self) -> axum::Router {
+        axum::Router::new()
+            .fallback_service(EmbeddedApp(std::sync::Mutex::new(self)))
+    }
+}
+
 #[derive(Clone)]
 pub struct HyperApp {
     pub state: AppState,
@@ -513,6 +750,29 @@ fn setup_redis_cache(
     RedisCacheManager::new(host_url)
 }
 
+async fn setup_conversation_store(
+    config: &Config,
+    router_store: Option<&RouterStore>,
+) -> Result<Option<ConversationStore>, InitError> {
+    match &config.conversation_store {
+        Some(conversation::ConversationStore::Redis { host_url }) => {
+            let client = redis::Client::open(host_url.clone())?;
+            let conn = client.get_connection_manager().await?;
+            tracing::debug!("Using redis conversation store");
+            Ok(Some(ConversationStore::Redis(conn)))
+        }
+        Some(conversation::ConversationStore::Postgres) => {
+            let pool = match router_store {
+                Some(router_store) => router_store.pool.clone(),
+                None => connect(&config.database).await?,
+            };
+            tracing::debug!("Using postgres conversation store");
+            Ok(Some(ConversationStore::Postgres(pool)))
+        }
+        None => Ok(None),
+    }
+}
+
 fn setup_cache(config: &Config, metrics: Metrics) -> Option<CacheClient> {
     match &config.cache_store {
         Some(CacheStore::InMemory { max_size }) => {
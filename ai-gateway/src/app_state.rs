@@ -1,9 +1,12 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+};
 
 use opentelemetry::KeyValue;
 use rustc_hash::FxHashMap as HashMap;
 use tokio::sync::{
-    RwLock,
+    OnceCell, RwLock,
     mpsc::{Receiver, Sender},
 };
 use tower::discover::Change;
@@ -11,19 +14,41 @@ use tower::discover::Change;
 use crate::{
     cache::CacheClient,
     config::{
-        Config, rate_limit::RateLimiterConfig,
-        response_headers::ResponseHeadersConfig, router::RouterConfig,
+        Config, deployment::DeploymentInfo,
+        response_headers::ResponseHeadersConfig, retry::RetryBudgetConfig,
+        router::RouterConfig,
     },
     control_plane::{control_plane_state::StateWithMetadata, types::Key},
     discover::monitor::{
         health::provider::HealthMonitorMap, metrics::EndpointMetricsRegistry,
-        rate_limit::RateLimitMonitorMap,
+        rate_limit::RateLimitMonitorMap, state_sync::StateSync,
+        webhook::Webhooks,
+    },
+    dispatcher::{
+        adaptive_limit::AdaptiveLimiters, bulkhead::Bulkheads, chaos::Faults,
+        gemini_cache::CachedContents, retry_budget::RetryBudget,
     },
     error::init::InitError,
-    logger::service::JawnClient,
-    metrics::Metrics,
-    router::service::Router,
-    store::{minio::BaseMinioClient, router::RouterStore},
+    logger::{
+        batch::LogBatcher, correlation::Correlations, dlq::DeadLetterQueue,
+        journal::RequestJournal, queue::LogQueue, service::JawnClient,
+        usage::UsageAggregator,
+    },
+    metrics::{
+        Metrics, body_size::SizeAnomalies, spend::SpendAnomalies,
+        system::SystemPressure, throughput::Throughputs,
+    },
+    middleware::{
+        auth::Authenticator, mapper::model::StoredModelMappings,
+        rate_limit::in_memory::InMemoryRateLimiter,
+    },
+    router::{
+        direct::DirectProxiesWithoutMapper, models::ModelLists, service::Router,
+    },
+    store::{
+        conversation::ConversationStore, leader::Leadership,
+        minio::BaseMinioClient, router::RouterStore,
+    },
     types::{
         org::OrgId,
         provider::{ProviderKeyMap, ProviderKeys},
@@ -32,6 +57,7 @@ use crate::{
         },
         router::RouterId,
     },
+    utils::freeze::Freezes,
 };
 
 #[derive(Debug, Clone)]
@@ -52,14 +78,52 @@ impl AppState {
 #[derive(Debug)]
 pub struct InnerAppState {
     pub config: Config,
+    /// The region, availability zone and name of this instance.
+    pub deployment: DeploymentInfo,
+    /// Replaces the gateway's authentication, if set by an application
+    /// embedding the gateway.
+    pub authenticator: Option<Arc<dyn Authenticator>>,
     pub minio: BaseMinioClient,
     pub router_store: Option<RouterStore>,
     pub jawn_http_client: JawnClient,
+    /// Bounds the deliveries of logs to Helicone.
+    pub log_queue: LogQueue,
+    /// Batches the logs sent to Helicone, if configured.
+    pub log_batcher: Option<LogBatcher>,
+    /// Keeps logs which failed to be delivered for a retry, if configured.
+    pub dlq: Option<DeadLetterQueue>,
+    /// Journals requests in flight, if configured.
+    pub journal: Option<RequestJournal>,
+    /// Aggregates the usage of logged requests for usage reports, if
+    /// configured.
+    pub usage: Option<UsageAggregator>,
     pub cache_manager: Option<CacheClient>,
-    pub global_rate_limit: Option<Arc<RateLimiterConfig>>,
-    pub router_rate_limits: RwLock<HashMap<RouterId, Arc<RateLimiterConfig>>>,
+    /// Stores conversations for routers with conversations enabled, if
+    /// configured.
+    pub conversation_store: Option<ConversationStore>,
+    pub global_rate_limit: Option<Arc<InMemoryRateLimiter>>,
+    pub router_rate_limits: RwLock<HashMap<RouterId, Arc<InMemoryRateLimiter>>>,
+    /// Retry budgets shared by the dispatchers of a router.
+    pub retry_budgets: RwLock<HashMap<RouterId, Arc<RetryBudget>>>,
+    /// Webhook notifiers shared by the monitors of a router.
+    pub webhooks: Mutex<HashMap<RouterId, Arc<Webhooks>>>,
     /// Top level metrics which are exported to OpenTelemetry.
     pub metrics: Metrics,
+    /// Detects unusually large bodies, if configured.
+    pub size_anomalies: Option<SizeAnomalies>,
+    /// Freezes API keys and routers whose spend rises, if configured.
+    pub spend_anomalies: Option<SpendAnomalies>,
+    /// The IDs of recent requests in the gateway, Helicone, and providers.
+    pub correlations: Correlations,
+    /// Tokens per second of each provider's models, for latency based
+    /// routing.
+    pub throughputs: Throughputs,
+    /// Faults injected into requests to providers, if chaos is enabled.
+    pub chaos: Option<Faults>,
+    /// Traffic frozen through the admin API.
+    pub freezes: Freezes,
+    /// Sampled by the system metrics service, for load shedding.
+    pub system_pressure: SystemPressure,
     /// Metrics to track provider health and rate limits.
     /// Not used for OpenTelemetry, only used for the load balancer to be
     /// dynamically updated based on provider health and rate limits.
@@ -68,6 +132,25 @@ pub struct InnerAppState {
     pub rate_limit_monitors: RateLimitMonitorMap,
     pub rate_limit_senders: RateLimitEventSenders,
     pub rate_limit_receivers: RateLimitEventReceivers,
+    /// Shares rate limit and health events with other replicas, if
+    /// configured.
+    pub state_sync: Option<StateSync>,
+    /// Whether this replica should run singleton jobs.
+    pub leadership: Leadership,
+    /// Per provider concurrency limits, if configured.
+    pub bulkheads: Option<Bulkheads>,
+    /// Per provider concurrency limits which adapt to provider latency, if
+    /// configured.
+    pub adaptive_limiters: Option<AdaptiveLimiters>,
+    /// Owners of the Gemini cached contents created through the gateway.
+    pub gemini_cached_contents: CachedContents,
+    /// The cached model lists of the providers.
+    pub model_lists: ModelLists,
+    /// The direct proxies used by the token counting and model list
+    /// endpoints, created on first use.
+    pub direct_proxies: OnceCell<DirectProxiesWithoutMapper>,
+    /// The model mappings stored in the database, in cloud deployments.
+    pub model_mappings: StoredModelMappings,
     pub router_tx: RwLock<Option<Sender<Change<RouterId, Router>>>>,
 
     pub control_plane_state: Arc<RwLock<StateWithMetadata>>,
@@ -108,6 +191,73 @@ impl AppState {
         rate_limit_channels.insert(router_id, rate_limit_rx);
     }
 
+    /// Returns the direct proxies without a mapper, shared by the unified
+    /// API and every router.
+    pub async fn direct_proxies(
+        &self,
+    ) -> Result<DirectProxiesWithoutMapper, InitError> {
+        self.0
+            .direct_proxies
+            .get_or_try_init(|| DirectProxiesWithoutMapper::new(self))
+            .await
+            .cloned()
+    }
+
+    /// Returns the retry budget of a router, replacing it if its
+    /// configuration changed.
+    pub async fn retry_budget(
+        &self,
+        router_id: &RouterId,
+        config: &RetryBudgetConfig,
+    ) -> Arc<RetryBudget> {
+        let mut retry_budgets = self.0.retry_budgets.write().await;
+        if let Some(budget) = retry_budgets.get(router_id)
+            && budget.config() == config
+        {
+            return budget.clone();
+        }
+        let budget = Arc::new(RetryBudget::new(
+            router_id.clone(),
+            config.clone(),
+            self.0.metrics.retry_budget.clone(),
+        ));
+        retry_budgets.insert(router_id.clone(), budget.clone());
+        budget
+    }
+
+    /// Returns the webhook notifier of a router, replacing it if its
+    /// configuration changed, or `None` if the router has no webhooks.
+    #[must_use]
+    pub fn webhooks(
+        &self,
+        router_id: &RouterId,
+        router_config: &RouterConfig,
+    ) -> Option<Arc<Webhooks>> {
+        let config = router_config.webhooks.as_ref()?;
+        let mut webhooks = self.0.webhooks.lock().expect("lock poisoned");
+        if let Some(notifier) = webhooks.get(router_id)
+            && notifier.config() == config
+        {
+            return Some(notifier.clone());
+        }
+        let notifier = Webhooks::new(
+            router_id.clone(),
+            config.clone(),
+            self.config().dispatcher.egress.as_ref(),
+        )
+        .inspect_err(|error| {
+            tracing::error!(
+                %router_id,
+                %error,
+                "failed to create webhooks"
+            );
+        })
+        .ok()
+        .map(Arc::new)?;
+        webhooks.insert(router_id.clone(), notifier.clone());
+        Some(notifier)
+    }
+
     pub async fn get_router_tx(
         &self,
     ) -> Option<Sender<Change<RouterId, Router>>> {
@@ -132,16 +282,21 @@ impl AppState {
             .cloned()
     }
 
+    /// Sets an API key, replacing the key with the same hash, if any.
     pub async fn set_helicone_api_key(
         &self,
         api_key: Key,
     ) -> Result<Option<HashSet<Key>>, InitError> {
         let mut helicone_api_keys = self.0.helicone_api_keys.write().await;
-        helicone_api_keys
+        let keys = helicone_api_keys
             .as_mut()
-            .ok_or_else(|| InitError::RouterApiKeysNotInitialized)?
-            .insert(api_key.clone());
-        self.0.metrics.routers.helicone_api_keys.add(1, &[]);
+            .ok_or_else(|| InitError::RouterApiKeysNotInitialized)?;
+        let len = keys.len();
+        keys.retain(|k| k.key_hash != api_key.key_hash);
+        if keys.len() == len {
+            self.0.metrics.routers.helicone_api_keys.add(1, &[]);
+        }
+        keys.insert(api_key);
         Ok(helicone_api_keys.clone())
     }
 
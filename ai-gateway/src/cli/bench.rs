@@ -0,0 +1,238 @@
+//! `ai-gateway bench`: compares the latency and cost of providers and
+//! models, to pick balance weights with data rather than guesses.
+//!
+//! Probe prompts are sent through the unified API of a running gateway, so
+//! that every provider is called the way the gateway calls it, with its
+//! request mapping and keys.
+use std::{
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use futures::future::join_all;
+use rust_decimal::Decimal;
+use serde_json::json;
+use url::Url;
+
+use crate::{
+    config::{Config, model_capabilities::ModelCapabilitiesConfig},
+    logger::usage::Usage,
+    types::model_id::ModelId,
+};
+
+const DEFAULT_PROMPTS: [&str; 2] = [
+    "Reply with the single word: pong",
+    "Summarize the plot of Romeo and Juliet in three sentences.",
+];
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// What to benchmark.
+#[derive(Debug, Clone)]
+pub struct BenchConfig {
+    /// Base URL of the gateway.
+    pub url: Url,
+    /// Helicone API key, if the gateway requires authentication.
+    pub api_key: Option<String>,
+    /// `{provider}/{model}` to benchmark. Defaults to the first model of
+    /// each configured provider.
+    pub models: Vec<String>,
+    /// Defaults to a short and a longer prompt.
+    pub prompts: Vec<String>,
+    /// Requests per model and prompt.
+    pub runs: u32,
+    pub max_tokens: u32,
+}
+
+/// The results of a model.
+#[derive(Debug, Clone)]
+pub struct ModelResult {
+    pub model: String,
+    pub latencies: Vec<Duration>,
+    pub failures: u32,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Price in USD per million input tokens, if known.
+    pub input_cost_per_mtok: Option<Decimal>,
+}
+
+impl ModelResult {
+    /// Returns the given percentile (in `0.0..=1.0`) of the latencies.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn latency(&self, percentile: f64) -> Option<Duration> {
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        let rank = (percentile * latencies.len() as f64).ceil() as usize;
+        latencies.get(rank.saturating_sub(1)).copied()
+    }
+
+    /// The mean cost of the prompt of a successful request, in USD.
+    #[must_use]
+    pub fn input_cost_per_request(&self) -> Option<Decimal> {
+        let requests = u64::try_from(self.latencies.len()).ok()?;
+        if requests == 0 {
+            return None;
+        }
+        let cost_per_mtok = self.input_cost_per_mtok?;
+        Some(
+            Decimal::from(self.prompt_tokens) * cost_per_mtok
+                / Decimal::from(1_000_000)
+                / Decimal::from(requests),
+        )
+    }
+}
+
+/// The models benchmarked by default: the first of each configured
+/// provider.
+#[must_use]
+pub fn default_models(config: &Config) -> Vec<String> {
+    super::configured_providers(config)
+        .into_iter()
+        .filter_map(|provider| {
+            let model = config.providers[&provider].models.first()?;
+            Some(format!("{provider}/{model}"))
+        })
+        .collect()
+}
+
+/// Benchmarks the models concurrently, and the requests of each model one
+/// after the other, so that a model's latency isn't inflated by its own
+/// rate limits.
+pub async fn run(
+    bench: &BenchConfig,
+    capabilities: &ModelCapabilitiesConfig,
+) -> Vec<ModelResult> {
+    let client = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .expect("client without custom TLS always builds");
+    let prompts = if bench.prompts.is_empty() {
+        DEFAULT_PROMPTS.iter().map(ToString::to_string).collect()
+    } else {
+        bench.prompts.clone()
+    };
+    join_all(
+        bench.models.iter().map(|model| {
+            run_model(&client, bench, &prompts, model, capabilities)
+        }),
+    )
+    .await
+}
+
+async fn run_model(
+    client: &reqwest::Client,
+    bench: &BenchConfig,
+    prompts: &[String],
+    model: &str,
+    capabilities: &ModelCapabilitiesConfig,
+) -> ModelResult {
+    let url = bench
+        .url
+        .join("/ai/chat/completions")
+        .expect("path is a valid url");
+    let input_cost_per_mtok = ModelId::from_str(model)
+        .ok()
+        .and_then(|model| capabilities.get(&model))
+        .map(|capability| capability.input_cost_per_mtok);
+    let mut result = ModelResult {
+        model: model.to_string(),
+        latencies: Vec::new(),
+        failures: 0,
+        prompt_tokens: 0,
+        completion_tokens: 0,
+        input_cost_per_mtok,
+    };
+    for prompt in prompts {
+        for _ in 0..bench.runs {
+            let mut request = client.post(url.clone()).json(&json!({
+                "model": model,
+                "messages": [{ "role": "user", "content": prompt }],
+                "max_tokens": bench.max_tokens,
+            }));
+            if let Some(api_key) = &bench.api_key {
+                request = request.bearer_auth(api_key);
+            }
+            let start = Instant::now();
+            let response = async {
+                request.send().await?.error_for_status()?.bytes().await
+            }
+            .await;
+            match response {
+                Ok(body) => {
+                    result.latencies.push(start.elapsed());
+                    let usage = Usage::from_response(&body, false);
+                    result.prompt_tokens += usage.prompt_tokens;
+                    result.completion_tokens += usage.completion_tokens;
+                }
+                Err(error) => {
+                    tracing::debug!(model, error = %error, "probe failed");
+                    result.failures += 1;
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Prints a table of the results, fastest median first.
+pub fn print(results: &mut [ModelResult]) {
+    results.sort_by_key(|result| result.latency(0.5).unwrap_or(Duration::MAX));
+    let width = results
+        .iter()
+        .map(|result| result.model.len())
+        .chain([5])
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:width$}  {:>4}  {:>6}  {:>9}  {:>9}  {:>14}",
+        "model", "ok", "failed", "p50", "p95", "input $/1k req"
+    );
+    let millis = |latency: Option<Duration>| {
+        latency.map_or_else(
+            || "-".to_string(),
+            |latency| format!("{}ms", latency.as_millis()),
+        )
+    };
+    for result in results.iter() {
+        let cost = result.input_cost_per_request().map_or_else(
+            || "-".to_string(),
+            |cost| (cost * Decimal::from(1000)).round_dp(4).to_string(),
+        );
+        println!(
+            "{:width$}  {:>4}  {:>6}  {:>9}  {:>9}  {:>14}",
+            result.model,
+            result.latencies.len(),
+            result.failures,
+            millis(result.latency(0.5)),
+            millis(result.latency(0.95)),
+            cost,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_and_cost() {
+        let result = ModelResult {
+            model: "openai/gpt-4o-mini".to_string(),
+            latencies: (1..=10)
+                .map(|ms| Duration::from_millis(ms * 100))
+                .collect(),
+            failures: 0,
+            prompt_tokens: 20_000,
+            completion_tokens: 0,
+            input_cost_per_mtok: Some(Decimal::new(15, 2)),
+        };
+        assert_eq!(result.latency(0.5), Some(Duration::from_millis(500)));
+        assert_eq!(result.latency(0.95), Some(Duration::from_millis(1000)));
+        // 2000 tokens per request at $0.15 per million
+        assert_eq!(result.input_cost_per_request(), Some(Decimal::new(3, 4)));
+    }
+}
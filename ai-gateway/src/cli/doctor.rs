@@ -0,0 +1,328 @@
+//! `ai-gateway doctor`: checks that the gateway can reach everything its
+//! config depends on, without starting it.
+//!
+//! Checks are only run for the dependencies the config uses, e.g. Postgres
+//! only for the cloud deployment target or a Postgres conversation store.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::future::join_all;
+use indexmap::IndexSet;
+use reqwest::header::DATE;
+use sqlx::{Connection, PgConnection};
+use url::Url;
+
+use crate::{
+    config::{
+        Config, cache::CacheStore, conversation::ConversationStore,
+        rate_limit::RateLimitStore,
+    },
+    types::provider::{InferenceProvider, ProviderKey},
+};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Clock skew above which signed requests may be rejected. `SigV4`
+/// signatures are rejected beyond 5 minutes.
+const MAX_CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+/// Clock skew worth a warning, well before requests are rejected.
+const WARN_CLOCK_SKEW: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Skipped,
+    Ok,
+    Warning,
+    Failed,
+}
+
+impl Status {
+    fn symbol(self) -> &'static str {
+        match self {
+            Self::Skipped => "\x1b[2m-\x1b[0m",
+            Self::Ok => "\x1b[32m✓\x1b[0m",
+            Self::Warning => "\x1b[33m!\x1b[0m",
+            Self::Failed => "\x1b[31m✗\x1b[0m",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(
+        name: impl Into<String>,
+        status: Status,
+        detail: impl Into<String>,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+
+    fn from_result(
+        name: impl Into<String>,
+        result: Result<String, String>,
+    ) -> Self {
+        match result {
+            Ok(detail) => Self::new(name, Status::Ok, detail),
+            Err(detail) => Self::new(name, Status::Failed, detail),
+        }
+    }
+}
+
+/// The outcome of the checks.
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    #[must_use]
+    pub fn passed(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| check.status != Status::Failed)
+    }
+
+    /// Prints the report with a color-coded line per check.
+    pub fn print(&self) {
+        let width = self
+            .checks
+            .iter()
+            .map(|check| check.name.len())
+            .max()
+            .unwrap_or_default();
+        for check in &self.checks {
+            println!(
+                "{} {:width$}  {}",
+                check.status.symbol(),
+                check.name,
+                check.detail
+            );
+        }
+        let failed = self
+            .checks
+            .iter()
+            .filter(|check| check.status == Status::Failed)
+            .count();
+        let warnings = self
+            .checks
+            .iter()
+            .filter(|check| check.status == Status::Warning)
+            .count();
+        println!("\n{failed} failed, {warnings} warnings");
+    }
+}
+
+/// Runs every check relevant to `config`.
+pub async fn run(config: &Config) -> Report {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .expect("client without custom TLS always builds");
+    let mut checks = Vec::new();
+
+    let redis_urls = redis_urls(config);
+    if redis_urls.is_empty() {
+        checks.push(Check::new("redis", Status::Skipped, "not configured"));
+    }
+    for url in redis_urls {
+        let name = format!("redis {}", display_host(&url));
+        checks.push(Check::from_result(name, check_redis(url).await));
+    }
+
+    let uses_postgres = config.deployment_target.is_cloud()
+        || matches!(
+            config.conversation_store,
+            Some(ConversationStore::Postgres)
+        );
+    checks.push(if uses_postgres {
+        Check::from_result(
+            "postgres",
+            check_postgres(config.database.url.expose()).await,
+        )
+    } else {
+        Check::new("postgres", Status::Skipped, "not configured")
+    });
+
+    let mut dates = Vec::new();
+    checks.push(if config.helicone.is_observability_enabled() {
+        let result = check_http(&client, config.minio.host.clone()).await;
+        Check::from_result("minio", record_date(result, &mut dates))
+    } else {
+        Check::new("minio", Status::Skipped, "observability disabled")
+    });
+    checks.push(if config.helicone.is_auth_enabled() {
+        let result =
+            check_http(&client, config.helicone.base_url.clone()).await;
+        Check::from_result("control plane", record_date(result, &mut dates))
+    } else {
+        Check::new("control plane", Status::Skipped, "auth disabled")
+    });
+
+    let providers = super::configured_providers(config);
+    let results = join_all(providers.iter().map(|provider| {
+        let base_url = config.providers[provider].base_url.clone();
+        check_http(&client, base_url)
+    }))
+    .await;
+    for (provider, result) in providers.iter().zip(results) {
+        let key = if *provider == InferenceProvider::Ollama
+            || ProviderKey::from_env(provider).is_some()
+        {
+            ""
+        } else {
+            ", no API key in the environment"
+        };
+        let name = format!("provider {provider}");
+        let check = match record_date(result, &mut dates) {
+            Ok(detail) if key.is_empty() => {
+                Check::new(name, Status::Ok, detail)
+            }
+            Ok(detail) => Check::new(name, Status::Warning, detail + key),
+            Err(detail) => Check::new(name, Status::Failed, detail),
+        };
+        checks.push(check);
+    }
+
+    checks.push(check_clock_skew(&dates, Utc::now()));
+    Report { checks }
+}
+
+fn redis_urls(config: &Config) -> IndexSet<Url> {
+    let mut urls = IndexSet::new();
+    if let Some(CacheStore::Redis { host_url }) = &config.cache_store {
+        urls.insert(host_url.clone());
+    }
+    if let Some(RateLimitStore::Redis(redis)) = &config.rate_limit_store {
+        urls.insert(redis.host_url.expose().clone());
+    }
+    if let Some(ConversationStore::Redis { host_url }) =
+        &config.conversation_store
+    {
+        urls.insert(host_url.clone());
+    }
+    if let Some(state_sync) = &config.discover.state_sync {
+        urls.insert(state_sync.redis.host_url.expose().clone());
+    }
+    urls
+}
+
+/// The host of `url`, without credentials which may be in it.
+fn display_host(url: &Url) -> String {
+    match url.port() {
+        Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+        None => url.host_str().unwrap_or_default().to_string(),
+    }
+}
+
+async fn check_redis(url: Url) -> Result<String, String> {
+    let ping = async {
+        let client = redis::Client::open(url)?;
+        let mut connection = client.get_multiplexed_async_connection().await?;
+        redis::cmd("PING")
+            .query_async::<String>(&mut connection)
+            .await
+    };
+    match tokio::time::timeout(TIMEOUT, ping).await {
+        Ok(Ok(_)) => Ok("PING succeeded".to_string()),
+        Ok(Err(error)) => Err(error.to_string()),
+        Err(_) => Err(format!("timed out after {TIMEOUT:?}")),
+    }
+}
+
+async fn check_postgres(url: &str) -> Result<String, String> {
+    let ping = async {
+        let mut connection = PgConnection::connect(url).await?;
+        connection.ping().await?;
+        connection.close().await
+    };
+    match tokio::time::timeout(TIMEOUT, ping).await {
+        Ok(Ok(())) => Ok("connected".to_string()),
+        Ok(Err(error)) => Err(error.to_string()),
+        Err(_) => Err(format!("timed out after {TIMEOUT:?}")),
+    }
+}
+
+/// Any response counts as reachable, since most base URLs have nothing to
+/// serve at their root.
+async fn check_http(
+    client: &reqwest::Client,
+    url: Url,
+) -> Result<reqwest::Response, String> {
+    client
+        .get(url)
+        .send()
+        .await
+        .map_err(|error| format!("{error:#}"))
+}
+
+/// Keeps the `Date` header of a response, for checking clock skew.
+fn record_date(
+    result: Result<reqwest::Response, String>,
+    dates: &mut Vec<DateTime<Utc>>,
+) -> Result<String, String> {
+    let response = result?;
+    if let Some(date) = response
+        .headers()
+        .get(DATE)
+        .and_then(|date| date.to_str().ok())
+        .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+    {
+        dates.push(date.to_utc());
+    }
+    Ok(format!("reachable, HTTP {}", response.status().as_u16()))
+}
+
+/// Compares the local clock to the `Date` headers of the responses, which
+/// are only precise to the second.
+fn check_clock_skew(dates: &[DateTime<Utc>], now: DateTime<Utc>) -> Check {
+    const NAME: &str = "clock skew";
+    let Some(skew) = dates
+        .iter()
+        .map(|date| (now - *date).abs().to_std().unwrap_or_default())
+        .min()
+    else {
+        return Check::new(NAME, Status::Skipped, "no server dates to compare");
+    };
+    let detail = format!("{}s", skew.as_secs());
+    if skew > MAX_CLOCK_SKEW {
+        Check::new(
+            NAME,
+            Status::Failed,
+            format!("{detail}, signed requests such as Bedrock's will fail"),
+        )
+    } else if skew > WARN_CLOCK_SKEW {
+        Check::new(NAME, Status::Warning, detail)
+    } else {
+        Check::new(NAME, Status::Ok, detail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clock_skew_is_the_smallest_difference() {
+        let now = Utc::now();
+        let check = check_clock_skew(&[], now);
+        assert_eq!(check.status, Status::Skipped);
+        let check =
+            check_clock_skew(&[now - chrono::Duration::minutes(10), now], now);
+        assert_eq!(check.status, Status::Ok);
+        let check =
+            check_clock_skew(&[now + chrono::Duration::minutes(10)], now);
+        assert_eq!(check.status, Status::Failed);
+        let check =
+            check_clock_skew(&[now - chrono::Duration::minutes(1)], now);
+        assert_eq!(check.status, Status::Warning);
+    }
+}
@@ -1 +1,31 @@
+pub mod bench;
+pub mod doctor;
 pub mod helpers;
+
+use indexmap::IndexSet;
+
+use crate::{
+    config::Config,
+    types::provider::{InferenceProvider, ProviderKey},
+};
+
+/// The providers of the routers, and those with an API key for direct
+/// proxy and unified API requests.
+pub(crate) fn configured_providers(
+    config: &Config,
+) -> IndexSet<InferenceProvider> {
+    let mut providers = config
+        .routers
+        .values()
+        .flat_map(|router| router.load_balance.providers())
+        .collect::<IndexSet<_>>();
+    providers.extend(
+        config
+            .providers
+            .keys()
+            .filter(|provider| ProviderKey::from_env(provider).is_some())
+            .cloned(),
+    );
+    providers.retain(|provider| config.providers.contains_key(provider));
+    providers
+}
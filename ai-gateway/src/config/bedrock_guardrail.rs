@@ -0,0 +1,29 @@
+use serde::{Deserialize, Serialize};
+
+/// A Bedrock guardrail applied to a router's Bedrock converse calls, so that
+/// Bedrock's content filters, denied topics and PII redaction apply to
+/// requests made through the gateway.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BedrockGuardrailConfig {
+    /// The id or ARN of the guardrail.
+    pub identifier: String,
+    /// The version of the guardrail, e.g. `1` or `DRAFT`.
+    pub version: String,
+    /// Whether Bedrock returns the guardrail's assessment, which is added
+    /// to responses as `guardrail`.
+    #[serde(default)]
+    pub trace: GuardrailTrace,
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, Eq, PartialEq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum GuardrailTrace {
+    #[default]
+    Disabled,
+    Enabled,
+    /// Also includes the assessments of filters which didn't intervene.
+    EnabledFull,
+}
@@ -1,11 +1,11 @@
 use serde::{Deserialize, Serialize};
 
+use crate::utils::default_true;
+
 pub(crate) const MAX_BUCKET_SIZE: u8 = 10;
 pub(crate) const DEFAULT_BUCKETS: u8 = 1;
 
-#[derive(
-    Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq, Hash,
-)]
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash)]
 #[serde(default, rename_all = "kebab-case")]
 pub struct CacheConfig {
     /// Cache-control header: <https://developer.mozilla.org/en-US/docs/Web/HTTP/Reference/Headers/Cache-Control>
@@ -15,6 +15,56 @@ pub struct CacheConfig {
     pub buckets: u8,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub seed: Option<String>,
+    /// Whether to honor the `no-store`, `no-cache` and `max-age` directives
+    /// of a client's `Cache-Control` request header. If disabled, the header
+    /// is ignored and only `directive` applies.
+    #[serde(default = "default_true")]
+    pub trust_client_directives: bool,
+    /// Which parts of the request body make up the cache key. If unset, the
+    /// whole body is used as is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<CacheKeyConfig>,
+}
+
+/// Selects the request body fields which make up the cache key, so that
+/// requests which only differ in irrelevant fields share cache entries.
+#[derive(
+    Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq, Hash,
+)]
+#[serde(default, rename_all = "kebab-case")]
+pub struct CacheKeyConfig {
+    /// Top level fields of the request body to include. All fields are
+    /// included if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include: Option<Vec<String>>,
+    /// Top level fields of the request body to exclude, e.g. `user`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Whether runs of whitespace in string values are treated as a single
+    /// space, and leading and trailing whitespace is ignored.
+    pub normalize_whitespace: bool,
+}
+
+impl CacheKeyConfig {
+    #[must_use]
+    pub fn includes(&self, field: &str) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.iter().any(|f| f == field))
+            && !self.exclude.iter().any(|f| f == field)
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            directive: None,
+            buckets: default_buckets(),
+            seed: None,
+            trust_client_directives: true,
+            key: None,
+        }
+    }
 }
 
 #[cfg(feature = "testing")]
@@ -24,6 +74,8 @@ impl crate::tests::TestDefault for CacheConfig {
             directive: None,
             buckets: DEFAULT_BUCKETS,
             seed: None,
+            trust_client_directives: true,
+            key: None,
         }
     }
 }
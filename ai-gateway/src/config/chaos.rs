@@ -0,0 +1,24 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Enables fault injection through the admin API, to test balance
+/// strategies and the resilience of clients in staging.
+///
+/// Faults are added with `POST /admin/v1/chaos/faults` and expire after
+/// their TTL, which is at most `max-ttl`, so that a forgotten fault can't
+/// outlive the experiment.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ChaosConfig {
+    #[serde(with = "humantime_serde")]
+    pub max_ttl: Duration,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            max_ttl: Duration::from_secs(60 * 60),
+        }
+    }
+}
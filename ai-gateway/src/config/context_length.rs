@@ -0,0 +1,30 @@
+use serde::{Deserialize, Serialize};
+
+/// Context-length aware routing.
+///
+/// Requests whose estimated number of tokens exceeds the context window of
+/// the requested model are routed to the cheapest mapped model whose context
+/// window fits them, and rejected if there is none.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ContextLengthConfig {
+    /// Tokens added to the estimate of each request, for when the tokenizer
+    /// of the provider counts more tokens than the gateway's.
+    #[serde(default)]
+    pub headroom: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_length_config_from_yaml() {
+        let config =
+            serde_yml::from_str::<ContextLengthConfig>("headroom: 500")
+                .unwrap();
+        assert_eq!(config.headroom, 500);
+        let config = serde_yml::from_str::<ContextLengthConfig>("{}").unwrap();
+        assert_eq!(config.headroom, 0);
+    }
+}
@@ -3,7 +3,7 @@ use std::time::Duration;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::config::retry::RetryConfig;
+use crate::config::retry::{RetryConfig, StreamRetryMode};
 
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 #[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
@@ -19,6 +19,10 @@ impl Default for ControlPlaneConfig {
                 max_delay: Duration::from_secs(60),
                 max_retries: 15,
                 factor: Decimal::from(2),
+                attempt_timeout: None,
+                deadline: None,
+                hedge_after: None,
+                stream_mode: StreamRetryMode::default(),
             },
         }
     }
@@ -0,0 +1,101 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Where conversations are stored, for routers with conversations enabled.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum ConversationStore {
+    Redis {
+        #[serde(rename = "host-url", default = "default_host_url")]
+        host_url: url::Url,
+    },
+    /// Stored in the `gateway_conversations` table of the configured
+    /// database.
+    Postgres,
+}
+
+/// Per-router conversation state.
+///
+/// Clients continue a conversation by passing the `id` of its last response
+/// as `previous_response_id`, instead of resending the full history. The
+/// gateway prepends the stored messages to the request before it is
+/// dispatched, and stores the new turn once the response completes.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConversationsConfig {
+    /// How long a conversation is kept after its last turn.
+    #[serde(default = "default_ttl", with = "humantime_serde")]
+    pub ttl: Duration,
+    /// The maximum number of messages kept per conversation. The oldest
+    /// messages, other than system messages, are dropped first.
+    #[serde(default = "default_max_messages")]
+    pub max_messages: usize,
+    /// The maximum size in bytes of the messages kept per conversation.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+impl Default for ConversationsConfig {
+    fn default() -> Self {
+        Self {
+            ttl: default_ttl(),
+            max_messages: default_max_messages(),
+            max_bytes: default_max_bytes(),
+        }
+    }
+}
+
+fn default_host_url() -> url::Url {
+    "redis://localhost:6340".parse().unwrap()
+}
+
+fn default_ttl() -> Duration {
+    Duration::from_secs(60 * 60 * 24)
+}
+
+fn default_max_messages() -> usize {
+    100
+}
+
+fn default_max_bytes() -> usize {
+    // 1MB
+    1024 * 1024
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversations_config_from_yaml() {
+        let config: ConversationsConfig = serde_yml::from_str("{}").unwrap();
+        assert_eq!(config, ConversationsConfig::default());
+
+        let yaml = r"
+ttl: 1h
+max-messages: 10
+max-bytes: 4096
+";
+        let config: ConversationsConfig = serde_yml::from_str(yaml).unwrap();
+        assert_eq!(config.ttl, Duration::from_secs(60 * 60));
+        assert_eq!(config.max_messages, 10);
+        assert_eq!(config.max_bytes, 4096);
+    }
+
+    #[test]
+    fn conversation_store_from_yaml() {
+        let store: ConversationStore =
+            serde_yml::from_str("type: postgres").unwrap();
+        assert_eq!(store, ConversationStore::Postgres);
+        let store: ConversationStore =
+            serde_yml::from_str("type: redis\nhost-url: redis://redis:6379")
+                .unwrap();
+        assert_eq!(
+            store,
+            ConversationStore::Redis {
+                host_url: "redis://redis:6379".parse().unwrap()
+            }
+        );
+    }
+}
@@ -0,0 +1,77 @@
+use indexmap::IndexSet;
+use serde::{Deserialize, Serialize};
+
+use crate::types::provider::InferenceProvider;
+
+/// Where a router's requests may be processed, such as to keep data of EU
+/// customers in the EU.
+///
+/// The region of a provider is its `region` in the providers config, or
+/// the AWS region of its base URL, e.g. `us-east-1` for Bedrock. Providers
+/// and failover regions which the policy doesn't allow are removed from the
+/// router, and requests to an endpoint which none of its providers may
+/// serve are rejected.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DataResidencyConfig {
+    /// Regions requests may be processed in, or any region if empty. A
+    /// region also allows its sub-regions, so `eu` allows `eu-west-1`.
+    /// Providers with no known region are not in any of them.
+    #[serde(skip_serializing_if = "IndexSet::is_empty")]
+    pub allowed_regions: IndexSet<String>,
+    /// Providers requests may be sent to, or any provider if empty.
+    #[serde(skip_serializing_if = "IndexSet::is_empty")]
+    pub allowed_providers: IndexSet<InferenceProvider>,
+}
+
+impl DataResidencyConfig {
+    #[must_use]
+    pub fn allows_region(&self, region: Option<&str>) -> bool {
+        if self.allowed_regions.is_empty() {
+            return true;
+        }
+        let Some(region) = region else {
+            return false;
+        };
+        self.allowed_regions.iter().any(|allowed| {
+            region == allowed
+                || region
+                    .strip_prefix(allowed.as_str())
+                    .is_some_and(|sub_region| sub_region.starts_with('-'))
+        })
+    }
+
+    #[must_use]
+    pub fn allows(
+        &self,
+        provider: &InferenceProvider,
+        region: Option<&str>,
+    ) -> bool {
+        (self.allowed_providers.is_empty()
+            || self.allowed_providers.contains(provider))
+            && self.allows_region(region)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_allow_their_sub_regions() {
+        let policy = DataResidencyConfig {
+            allowed_regions: IndexSet::from(["eu".to_string()]),
+            allowed_providers: IndexSet::from([
+                InferenceProvider::Bedrock,
+                InferenceProvider::OpenAI,
+            ]),
+        };
+        assert!(policy.allows(&InferenceProvider::Bedrock, Some("eu")));
+        assert!(policy.allows(&InferenceProvider::Bedrock, Some("eu-west-1")));
+        assert!(!policy.allows(&InferenceProvider::Bedrock, Some("europe")));
+        assert!(!policy.allows(&InferenceProvider::Bedrock, Some("us-east-1")));
+        assert!(!policy.allows(&InferenceProvider::OpenAI, None));
+        assert!(!policy.allows(&InferenceProvider::Anthropic, Some("eu")));
+        assert!(DataResidencyConfig::default().allows_region(None));
+    }
+}
@@ -0,0 +1,159 @@
+//! Identification of the deployment a gateway instance runs in.
+//!
+//! Fleets spanning several regions need to tell instances apart in
+//! dashboards. The region, availability zone and instance name are taken
+//! from the config, falling back to the environment variables set by the
+//! common platforms. The instance metadata service is never queried, so
+//! hosts which enforce IMDSv2, or block it entirely, behave the same.
+use std::collections::BTreeMap;
+
+use http::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+const REGION_ENV_VARS: &[&str] = &[
+    "AWS_REGION",
+    "AWS_DEFAULT_REGION",
+    "FLY_REGION",
+    "GOOGLE_CLOUD_REGION",
+];
+const AVAILABILITY_ZONE_ENV_VARS: &[&str] =
+    &["AVAILABILITY_ZONE", "AWS_AVAILABILITY_ZONE"];
+const INSTANCE_ENV_VARS: &[&str] = &["POD_NAME", "HOSTNAME"];
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DeploymentConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub availability_zone: Option<String>,
+    /// The name of this instance, e.g. the pod name.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+impl DeploymentConfig {
+    /// Resolves the deployment info, falling back to the environment for
+    /// values that aren't configured.
+    #[must_use]
+    pub fn info(&self) -> DeploymentInfo {
+        self.info_from_env(|key| std::env::var(key).ok())
+    }
+
+    fn info_from_env(
+        &self,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> DeploymentInfo {
+        let resolve = |configured: &Option<String>, keys: &[&str]| {
+            configured.clone().or_else(|| {
+                keys.iter()
+                    .filter_map(|key| env(key))
+                    .find(|value| !value.is_empty())
+            })
+        };
+        DeploymentInfo {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            region: resolve(&self.region, REGION_ENV_VARS),
+            availability_zone: resolve(
+                &self.availability_zone,
+                AVAILABILITY_ZONE_ENV_VARS,
+            ),
+            instance: resolve(&self.instance, INSTANCE_ENV_VARS),
+        }
+    }
+}
+
+/// The resolved identity of this gateway instance.
+#[derive(Debug, Default, Clone, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeploymentInfo {
+    pub version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub availability_zone: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+}
+
+impl DeploymentInfo {
+    fn fields(&self) -> [(&'static str, Option<&str>); 4] {
+        [
+            ("version", Some(self.version.as_str())),
+            ("region", self.region.as_deref()),
+            ("availability-zone", self.availability_zone.as_deref()),
+            ("instance", self.instance.as_deref()),
+        ]
+    }
+
+    /// OpenTelemetry resource attributes, following the semantic
+    /// conventions.
+    #[must_use]
+    pub fn resource_attributes(&self) -> BTreeMap<String, String> {
+        let keys = [
+            "service.version",
+            "cloud.region",
+            "cloud.availability_zone",
+            "service.instance.id",
+        ];
+        keys.into_iter()
+            .zip(self.fields())
+            .filter_map(|(key, (_, value))| {
+                value.map(|value| (key.to_string(), value.to_string()))
+            })
+            .collect()
+    }
+
+    /// Headers identifying this instance to the control plane.
+    #[must_use]
+    pub fn headers(&self) -> HeaderMap {
+        self.fields()
+            .into_iter()
+            .filter_map(|(field, value)| {
+                let name = HeaderName::try_from(format!(
+                    "helicone-deployment-{field}"
+                ))
+                .ok()?;
+                let value = HeaderValue::from_str(value?).ok()?;
+                Some((name, value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(key: &str) -> Option<String> {
+        match key {
+            "AWS_DEFAULT_REGION" => Some("us-east-1".to_string()),
+            "POD_NAME" => Some("ai-gateway-7d9f".to_string()),
+            "HOSTNAME" => Some("ip-10-0-0-1".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn info_falls_back_to_env() {
+        let config = DeploymentConfig {
+            availability_zone: Some("us-east-1a".to_string()),
+            ..Default::default()
+        };
+        let info = config.info_from_env(env);
+        assert_eq!(info.region.as_deref(), Some("us-east-1"));
+        assert_eq!(info.availability_zone.as_deref(), Some("us-east-1a"));
+        assert_eq!(info.instance.as_deref(), Some("ai-gateway-7d9f"));
+    }
+
+    #[test]
+    fn info_is_exported() {
+        let info = DeploymentConfig::default().info_from_env(env);
+        let attributes = info.resource_attributes();
+        assert_eq!(attributes["cloud.region"], "us-east-1");
+        assert!(!attributes.contains_key("cloud.availability_zone"));
+        let headers = info.headers();
+        assert_eq!(headers["helicone-deployment-instance"], "ai-gateway-7d9f");
+        assert!(headers.contains_key("helicone-deployment-version"));
+    }
+}
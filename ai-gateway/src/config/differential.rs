@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::types::model_id::ModelId;
+
+/// Per-router differential testing.
+///
+/// A sample of the requests for each configured model is additionally sent
+/// to a candidate model in the background. The candidate's response is
+/// compared with the one returned to the client and the result is logged, so
+/// that a model migration can be evaluated against real traffic before any
+/// client sees the candidate's responses.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DifferentialConfig {
+    /// Map of requested model to the candidate model it is compared with.
+    pub candidates: HashMap<ModelId, ModelId>,
+    /// How the two responses are compared.
+    #[serde(default)]
+    pub comparison: Comparison,
+    /// Fraction of eligible requests, between 0 and 1, that are also sent to
+    /// the candidate model.
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: Decimal,
+}
+
+impl DifferentialConfig {
+    #[must_use]
+    pub fn candidate(&self, model: &ModelId) -> Option<&ModelId> {
+        self.candidates.get(model)
+    }
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, Eq, PartialEq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Comparison {
+    /// The completions must be identical.
+    #[default]
+    Exact,
+    /// The completions are parsed as JSON and compared structurally, ignoring
+    /// formatting and the order of object keys.
+    Json,
+    /// The cosine similarity of the completions' term frequency vectors.
+    ///
+    /// This measures the overlap of the words used rather than of meaning,
+    /// so a correct answer that is worded differently scores low.
+    TermOverlap,
+}
+
+fn default_sample_rate() -> Decimal {
+    Decimal::ONE
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn differential_config_from_yaml() {
+        let yaml = r"
+candidates:
+  openai/gpt-4o: anthropic/claude-3-5-sonnet
+comparison: json
+sample-rate: 0.1
+";
+        let config = serde_yml::from_str::<DifferentialConfig>(yaml).unwrap();
+        assert_eq!(config.comparison, Comparison::Json);
+        assert_eq!(config.sample_rate, Decimal::from_str("0.1").unwrap());
+        assert_eq!(
+            config.candidate(&ModelId::from_str("openai/gpt-4o").unwrap()),
+            Some(&ModelId::from_str("anthropic/claude-3-5-sonnet").unwrap())
+        );
+        let config = serde_yml::from_str::<DifferentialConfig>(
+            "candidates: {}\ncomparison: term-overlap\n",
+        )
+        .unwrap();
+        assert_eq!(config.comparison, Comparison::TermOverlap);
+    }
+}
@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
-use super::monitor::MonitorConfig;
+use super::{monitor::MonitorConfig, state_sync::StateSyncConfig};
 
 #[derive(
     Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize, Serialize,
@@ -22,6 +22,10 @@ pub struct DiscoverConfig {
     pub default_rtt: Duration,
     #[serde(default)]
     pub monitor: MonitorConfig,
+    /// If set, provider rate limit and health events are shared with other
+    /// gateway replicas.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_sync: Option<StateSyncConfig>,
 }
 
 impl Default for DiscoverConfig {
@@ -30,6 +34,7 @@ impl Default for DiscoverConfig {
             discover_decay: default_discover_decay(),
             default_rtt: default_rtt(),
             monitor: MonitorConfig::default(),
+            state_sync: None,
         }
     }
 }
@@ -58,6 +63,7 @@ impl crate::tests::TestDefault for DiscoverConfig {
             discover_decay: Duration::from_millis(100),
             default_rtt: Duration::from_millis(10),
             monitor: MonitorConfig::test_default(),
+            state_sync: None,
         }
     }
 }
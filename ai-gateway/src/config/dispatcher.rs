@@ -1,13 +1,35 @@
 use std::time::Duration;
 
+use indexmap::{IndexMap, IndexSet};
 use serde::{Deserialize, Serialize};
+use url::Url;
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+use crate::types::provider::InferenceProvider;
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
 pub struct DispatcherConfig {
     #[serde(default = "default_timeout", with = "humantime_serde")]
     pub timeout: Duration,
     #[serde(default = "default_connection_timeout", with = "humantime_serde")]
     pub connection_timeout: Duration,
+    /// Per provider concurrency limits. Disabled if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bulkhead: Option<BulkheadConfig>,
+    /// Per provider concurrency limits which adapt to provider latency.
+    /// Disabled if unset.
+    #[serde(
+        default,
+        rename = "adaptive-concurrency",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub adaptive_concurrency: Option<AdaptiveConcurrencyConfig>,
+    /// Settings for the connections to providers.
+    #[serde(default)]
+    pub connections: ConnectionsConfig,
+    /// Restricts the hosts requests may be dispatched to, and webhook
+    /// notifications sent to. Any host is allowed if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub egress: Option<EgressConfig>,
 }
 
 impl Default for DispatcherConfig {
@@ -15,6 +37,10 @@ impl Default for DispatcherConfig {
         Self {
             timeout: default_timeout(),
             connection_timeout: default_connection_timeout(),
+            bulkhead: None,
+            adaptive_concurrency: None,
+            connections: ConnectionsConfig::default(),
+            egress: None,
         }
     }
 }
@@ -26,6 +52,231 @@ impl crate::tests::TestDefault for DispatcherConfig {
     }
 }
 
+/// Limits the number of concurrent requests to each provider, so that a slow
+/// provider can't tie up all connections and tasks at the expense of
+/// requests to healthy providers.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct BulkheadConfig {
+    /// Maximum number of concurrent requests to a single provider.
+    pub max_concurrent_requests: usize,
+    /// Overrides `max-concurrent-requests` for specific providers.
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub providers: IndexMap<InferenceProvider, usize>,
+    /// How long a request may wait for capacity before it is rejected.
+    #[serde(with = "humantime_serde")]
+    pub max_wait: Duration,
+}
+
+impl BulkheadConfig {
+    #[must_use]
+    pub fn max_concurrent_requests(
+        &self,
+        provider: &InferenceProvider,
+    ) -> usize {
+        self.providers
+            .get(provider)
+            .copied()
+            .unwrap_or(self.max_concurrent_requests)
+    }
+}
+
+impl Default for BulkheadConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent_requests: 512,
+            providers: IndexMap::new(),
+            max_wait: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Bounds for the concurrency limit of each provider, which is continuously
+/// adjusted based on how the provider's latency responds to load.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AdaptiveConcurrencyConfig {
+    /// The limit before any latency has been observed.
+    pub initial_limit: usize,
+    pub min_limit: usize,
+    pub max_limit: usize,
+}
+
+impl Default for AdaptiveConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            initial_limit: 20,
+            min_limit: 1,
+            max_limit: 1000,
+        }
+    }
+}
+
+/// Settings for the connections to providers, which may be overridden for
+/// specific providers.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ConnectionsConfig {
+    /// How long connections are reused before they are replaced, which
+    /// re-resolves the provider's address. Connections are reused for as
+    /// long as they are alive if unset.
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<Duration>,
+    pub address_family: AddressFamily,
+    /// Whether connection attempts to addresses of the other family are
+    /// raced against slow attempts to the preferred family. If disabled,
+    /// the other family is only used if the provider has no address of the
+    /// preferred family.
+    pub happy_eyeballs: bool,
+    pub redirects: RedirectPolicy,
+    #[serde(skip_serializing_if = "IndexMap::is_empty")]
+    pub providers: IndexMap<InferenceProvider, ProviderConnectionsConfig>,
+}
+
+impl ConnectionsConfig {
+    #[must_use]
+    pub fn max_age(&self, provider: &InferenceProvider) -> Option<Duration> {
+        self.providers
+            .get(provider)
+            .and_then(|config| config.max_age)
+            .or(self.max_age)
+    }
+
+    #[must_use]
+    pub fn address_family(
+        &self,
+        provider: &InferenceProvider,
+    ) -> AddressFamily {
+        self.providers
+            .get(provider)
+            .and_then(|config| config.address_family)
+            .unwrap_or(self.address_family)
+    }
+
+    #[must_use]
+    pub fn happy_eyeballs(&self, provider: &InferenceProvider) -> bool {
+        self.providers
+            .get(provider)
+            .and_then(|config| config.happy_eyeballs)
+            .unwrap_or(self.happy_eyeballs)
+    }
+
+    #[must_use]
+    pub fn redirects(&self, provider: &InferenceProvider) -> RedirectPolicy {
+        self.providers
+            .get(provider)
+            .and_then(|config| config.redirects)
+            .unwrap_or(self.redirects)
+    }
+}
+
+impl Default for ConnectionsConfig {
+    fn default() -> Self {
+        Self {
+            max_age: None,
+            address_family: AddressFamily::default(),
+            happy_eyeballs: true,
+            redirects: RedirectPolicy::default(),
+            providers: IndexMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProviderConnectionsConfig {
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub max_age: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address_family: Option<AddressFamily>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub happy_eyeballs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirects: Option<RedirectPolicy>,
+}
+
+/// The IP address families used to connect to a provider, in order of
+/// preference.
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum AddressFamily {
+    /// The order returned by the resolver.
+    #[default]
+    Any,
+    PreferIpv4,
+    PreferIpv6,
+    Ipv4Only,
+    Ipv6Only,
+}
+
+/// Which redirects returned by a provider are followed.
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum RedirectPolicy {
+    /// Redirects are returned to the client.
+    None,
+    /// Up to 10 redirects are followed. Credentials are never sent to
+    /// another origin: the `authorization` header is dropped on redirects to
+    /// other origins, and providers authenticated with other headers, such
+    /// as `x-api-key`, have those redirects returned to the client instead.
+    #[default]
+    Limited,
+    /// Like `limited`, but only redirects to the same scheme, host and port
+    /// are followed. Other redirects are returned to the client.
+    SameHost,
+}
+
+/// Deny-by-default allowlist of the hosts the gateway may send requests to,
+/// including hosts redirected to. Protects against base urls injected
+/// through the config or dynamically registered providers.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct EgressConfig {
+    /// Allowed hosts, optionally with a port, e.g. `api.openai.com` or
+    /// `localhost:11434`. A leading `*.` allows any subdomain.
+    pub allowed_hosts: IndexSet<String>,
+}
+
+impl EgressConfig {
+    /// Whether requests may be sent to `url`.
+    #[must_use]
+    pub fn allows(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let host = host.trim_end_matches('.');
+        let port = url.port_or_known_default();
+        self.allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+            // ipv6 addresses must be bracketed, as in urls
+            let (allowed_host, allowed_port) = match allowed.rsplit_once(':') {
+                Some((allowed_host, allowed_port))
+                    if !allowed.ends_with(']') =>
+                {
+                    let Ok(allowed_port) = allowed_port.parse::<u16>() else {
+                        return false;
+                    };
+                    (allowed_host, Some(allowed_port))
+                }
+                _ => (allowed.as_str(), None),
+            };
+            if allowed_port.is_some() && allowed_port != port {
+                return false;
+            }
+            match allowed_host.strip_prefix("*.") {
+                Some(domain) => host
+                    .strip_suffix(domain)
+                    .is_some_and(|subdomain| subdomain.ends_with('.')),
+                None => host == allowed_host,
+            }
+        })
+    }
+}
+
 fn default_timeout() -> Duration {
     Duration::from_secs(60 * 15)
 }
@@ -33,3 +284,89 @@ fn default_timeout() -> Duration {
 fn default_connection_timeout() -> Duration {
     Duration::from_secs(10)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bulkhead_provider_overrides() {
+        let yaml = r"
+max-concurrent-requests: 10
+providers:
+  openai: 100
+max-wait: 1s
+";
+        let config = serde_yml::from_str::<BulkheadConfig>(yaml).unwrap();
+        assert_eq!(config.max_wait, Duration::from_secs(1));
+        assert_eq!(
+            config.max_concurrent_requests(&InferenceProvider::OpenAI),
+            100
+        );
+        assert_eq!(
+            config.max_concurrent_requests(&InferenceProvider::Anthropic),
+            10
+        );
+    }
+
+    #[test]
+    fn connection_provider_overrides() {
+        let yaml = r"
+max-age: 5m
+providers:
+  openai:
+    max-age: 30s
+  anthropic:
+    address-family: ipv4-only
+";
+        let config = serde_yml::from_str::<ConnectionsConfig>(yaml).unwrap();
+        assert_eq!(
+            config.max_age(&InferenceProvider::OpenAI),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            config.max_age(&InferenceProvider::Anthropic),
+            Some(Duration::from_secs(300))
+        );
+        assert_eq!(
+            config.address_family(&InferenceProvider::Anthropic),
+            AddressFamily::Ipv4Only
+        );
+        assert_eq!(
+            config.address_family(&InferenceProvider::OpenAI),
+            AddressFamily::Any
+        );
+        assert!(config.happy_eyeballs(&InferenceProvider::OpenAI));
+        assert_eq!(
+            config.redirects(&InferenceProvider::Bedrock),
+            RedirectPolicy::None
+        );
+        assert_eq!(
+            config.redirects(&InferenceProvider::OpenAI),
+            RedirectPolicy::Limited
+        );
+    }
+
+    #[test]
+    fn egress_allowlist() {
+        let yaml = r"
+allowed-hosts:
+  - api.openai.com
+  - '*.openai.azure.com'
+  - localhost:11434
+  - '[::1]:8080'
+";
+        let config = serde_yml::from_str::<EgressConfig>(yaml).unwrap();
+        let allows = |url: &str| config.allows(&Url::parse(url).unwrap());
+        assert!(allows("https://api.openai.com/v1/chat/completions"));
+        assert!(allows("https://API.openai.com./v1/models"));
+        assert!(allows("https://my-resource.openai.azure.com/openai"));
+        assert!(!allows("https://openai.azure.com/openai"));
+        assert!(!allows("https://evilopenai.azure.com/openai"));
+        assert!(allows("http://localhost:11434/api/chat"));
+        assert!(!allows("http://localhost:8080/api/chat"));
+        assert!(allows("http://[::1]:8080/"));
+        assert!(!allows("http://169.254.169.254/latest/meta-data"));
+        assert!(!allows("https://api.openai.com.evil.com/v1/models"));
+    }
+}
@@ -0,0 +1,41 @@
+use serde::{Deserialize, Serialize};
+
+/// Validation of the output of streamed completions requesting JSON with
+/// `response_format`, once their stream ends.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct JsonModeConfig {
+    #[serde(default)]
+    pub on_invalid: OnInvalidJson,
+}
+
+/// How a streamed completion whose output isn't valid JSON, or doesn't match
+/// the requested schema, is handled.
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, Eq, PartialEq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnInvalidJson {
+    /// The completion streams as usual, and its stream ends with an error
+    /// event.
+    #[default]
+    Error,
+    /// The completion is held back until its output is validated. Invalid
+    /// output is replaced by that of the request retried without streaming
+    /// and at temperature 0, sent as a single chunk.
+    Retry,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_mode_config_from_yaml() {
+        let config =
+            serde_yml::from_str::<JsonModeConfig>("on-invalid: retry").unwrap();
+        assert_eq!(config.on_invalid, OnInvalidJson::Retry);
+        let config = serde_yml::from_str::<JsonModeConfig>("{}").unwrap();
+        assert_eq!(config.on_invalid, OnInvalidJson::Error);
+    }
+}
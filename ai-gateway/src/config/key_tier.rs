@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Caps on the requests of API keys, by the tier of the key.
+///
+/// A key's tier is the one the control plane assigns it, else the one
+/// assigned to its hash in `keys`, else `default-tier`. Keys without a tier
+/// aren't capped.
+///
+/// ```yaml
+/// key-tiers:
+///   default-tier: free
+///   keys:
+///     <sha256 of the key>: pro
+///   tiers:
+///     free:
+///       max-tokens: 1024
+///       temperature: { min: 0, max: 1 }
+///       endpoints: [/v1/chat/completions]
+///     pro:
+///       max-tokens: 16384
+/// ```
+#[derive(Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct KeyTiersConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_tier: Option<String>,
+    /// Tiers of local keys, by the hash of the key.
+    pub keys: HashMap<String, String>,
+    pub tiers: HashMap<String, TierPolicy>,
+}
+
+impl KeyTiersConfig {
+    /// The name and policy of the tier of a key, given the tier the control
+    /// plane assigned it, if any, and the key's hash.
+    #[must_use]
+    pub fn tier(
+        &self,
+        assigned: Option<&str>,
+        key_hash: &str,
+    ) -> Option<(&str, &TierPolicy)> {
+        let name = assigned
+            .or_else(|| self.keys.get(key_hash).map(String::as_str))
+            .or(self.default_tier.as_deref())?;
+        let (name, policy) = self.tiers.get_key_value(name)?;
+        Some((name.as_str(), policy))
+    }
+}
+
+/// The caps of a tier. Caps which aren't set aren't enforced.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TierPolicy {
+    /// The highest `max_tokens` (or `max_completion_tokens`) a request may
+    /// set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<TemperatureRange>,
+    /// The API paths requests may be sent to, such as
+    /// `/v1/chat/completions`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoints: Option<Vec<String>>,
+}
+
+/// An inclusive range of allowed temperatures.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TemperatureRange {
+    #[serde(default)]
+    pub min: Decimal,
+    pub max: Decimal,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tier_precedence() {
+        let config: KeyTiersConfig = serde_yml::from_str(
+            r"
+default-tier: free
+keys:
+  abc: pro
+tiers:
+  free:
+    max-tokens: 1024
+    temperature: { max: 1 }
+  pro:
+    max-tokens: 16384
+",
+        )
+        .unwrap();
+        let name = |assigned, hash| {
+            config
+                .tier(assigned, hash)
+                .map(|(name, _)| name.to_string())
+        };
+        assert_eq!(name(Some("pro"), "def"), Some("pro".to_string()));
+        assert_eq!(name(None, "abc"), Some("pro".to_string()));
+        assert_eq!(name(None, "def"), Some("free".to_string()));
+        assert_eq!(name(Some("unknown"), "abc"), None);
+        let (_, free) = config.tier(None, "def").unwrap();
+        assert_eq!(
+            free.temperature,
+            Some(TemperatureRange {
+                min: Decimal::ZERO,
+                max: Decimal::ONE,
+            })
+        );
+    }
+}
@@ -0,0 +1,85 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::model_id::ModelId;
+
+/// Per-router latency SLO policy.
+///
+/// When the recent p95 latency of a requested model reaches `p95`, requests
+/// for that model are transparently rewritten to use the configured fallback
+/// model until the observed latency recovers.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LatencySloConfig {
+    /// The p95 latency at which requests are downgraded. Streamed responses
+    /// are measured to their first chunk, and failed requests count as at
+    /// least this latency.
+    #[serde(with = "humantime_serde")]
+    pub p95: Duration,
+    /// The window over which latency samples are considered.
+    #[serde(default = "default_window", with = "humantime_serde")]
+    pub window: Duration,
+    /// The minimum number of samples in the window before the SLO is
+    /// evaluated.
+    #[serde(default = "default_min_requests")]
+    pub min_requests: u32,
+    /// Map of requested model to the model used while the SLO is breached.
+    pub fallbacks: HashMap<ModelId, ModelId>,
+}
+
+impl LatencySloConfig {
+    #[must_use]
+    pub fn fallback(&self, model: &ModelId) -> Option<&ModelId> {
+        self.fallbacks.get(model)
+    }
+}
+
+fn default_window() -> Duration {
+    Duration::from_secs(60)
+}
+
+fn default_min_requests() -> u32 {
+    20
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn latency_slo_config_round_trip() {
+        let config = LatencySloConfig {
+            p95: Duration::from_secs(5),
+            window: default_window(),
+            min_requests: default_min_requests(),
+            fallbacks: HashMap::from([(
+                ModelId::from_str("openai/gpt-4o").unwrap(),
+                ModelId::from_str("openai/gpt-4o-mini").unwrap(),
+            )]),
+        };
+        let serialized = serde_json::to_string(&config).unwrap();
+        let deserialized =
+            serde_json::from_str::<LatencySloConfig>(&serialized).unwrap();
+        assert_eq!(config, deserialized);
+    }
+
+    #[test]
+    fn latency_slo_config_defaults() {
+        let yaml = r"
+p95: 3s
+fallbacks:
+  openai/gpt-4o: openai/gpt-4o-mini
+";
+        let config: LatencySloConfig = serde_yml::from_str(yaml).unwrap();
+        assert_eq!(config.p95, Duration::from_secs(3));
+        assert_eq!(config.window, default_window());
+        assert_eq!(config.min_requests, default_min_requests());
+        assert_eq!(
+            config.fallback(&ModelId::from_str("openai/gpt-4o").unwrap()),
+            Some(&ModelId::from_str("openai/gpt-4o-mini").unwrap())
+        );
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Configuration for electing a single leader among gateway replicas sharing
+/// a database, using a Postgres advisory lock.
+///
+/// Only the leader runs jobs on shared state which would otherwise be
+/// needlessly repeated by every replica, such as deleting expired
+/// conversations. Jobs on replica-local state, such as polling the database
+/// for missed router and API key changes, run on every replica.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LeaderElectionConfig {
+    /// Key of the advisory lock held by the leader. Replicas only compete
+    /// with other replicas using the same key.
+    #[serde(default = "default_lock_id")]
+    pub lock_id: i64,
+    /// How often followers try to acquire leadership, and how often the
+    /// leader checks that it still holds it.
+    #[serde(default = "default_renew_interval", with = "humantime_serde")]
+    pub renew_interval: Duration,
+}
+
+impl Default for LeaderElectionConfig {
+    fn default() -> Self {
+        Self {
+            lock_id: default_lock_id(),
+            renew_interval: default_renew_interval(),
+        }
+    }
+}
+
+fn default_lock_id() -> i64 {
+    // "aigw" in ascii
+    0x6169_6777
+}
+
+fn default_renew_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leader_election_config_defaults() {
+        let config: LeaderElectionConfig = serde_yml::from_str("{}").unwrap();
+        assert_eq!(config, LeaderElectionConfig::default());
+
+        let yaml = r"
+lock-id: 42
+renew-interval: 1s
+";
+        let config: LeaderElectionConfig = serde_yml::from_str(yaml).unwrap();
+        assert_eq!(config.lock_id, 42);
+        assert_eq!(config.renew_interval, Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,181 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// Settings for logging requests to Helicone.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LoggerConfig {
+    /// Response bodies larger than this many bytes are written to a
+    /// temporary file while they're collected, and streamed from it to
+    /// object storage, instead of being held in memory.
+    pub spill_threshold: usize,
+    /// Directory of the temporary files. Defaults to the system's temporary
+    /// directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub spill_dir: Option<PathBuf>,
+    /// Maximum number of logs waiting to be delivered.
+    pub queue_capacity: usize,
+    /// Number of logs delivered concurrently.
+    pub delivery_concurrency: usize,
+    /// What happens to logs once the queue is full.
+    pub overflow: OverflowPolicy,
+    /// Send logs to Helicone in gzip compressed batches rather than one
+    /// request per log.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<LogBatchConfig>,
+    /// Keep logs which failed to be delivered on disk and retry them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dlq: Option<DlqConfig>,
+    /// Journal requests in flight on disk, so that requests in flight
+    /// during a crash are reported with an unknown outcome on the next
+    /// start.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub journal: Option<JournalConfig>,
+    /// Emit an event when a request completes, which is exported as an
+    /// OpenTelemetry log record of the request's trace when the telemetry
+    /// exporter is `otlp`. Unlike Helicone logs, the events don't contain
+    /// the request and response bodies.
+    pub request_events: bool,
+    /// Aggregate the usage of logged requests per provider, model and day
+    /// in the database, for reconciling it against provider invoices. Only
+    /// supported by the cloud deployment target.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_report: Option<UsageReportConfig>,
+}
+
+/// Batching of logs sent to Helicone. A batch is sent once it's full, or
+/// once its first log has waited `max-delay`, whichever comes first, so
+/// batches are large under heavy load and logs aren't held back for long
+/// under light load.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LogBatchConfig {
+    /// Maximum number of logs in a batch.
+    pub max_logs: usize,
+    /// Maximum size of a batch in bytes, before compression.
+    pub max_bytes: usize,
+    /// Maximum time a log waits for its batch to fill up.
+    #[serde(with = "humantime_serde")]
+    pub max_delay: Duration,
+}
+
+impl Default for LogBatchConfig {
+    fn default() -> Self {
+        Self {
+            max_logs: 100,
+            max_bytes: 1024 * 1024,
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// What happens to logs once the delivery queue is full.
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, PartialEq, Eq,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum OverflowPolicy {
+    /// Wait for space in the queue. Provider concurrency permits are held
+    /// while waiting, so slow delivery eventually slows down requests.
+    Block,
+    /// Drop the oldest queued log.
+    #[default]
+    DropOldest,
+    /// Drop the log being queued.
+    DropNew,
+}
+
+/// The dead-letter queue of logs which failed to be delivered.
+///
+/// Entries contain the Helicone API key the log is delivered with, so the
+/// directory should only be readable by the gateway.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DlqConfig {
+    /// Directory the entries are stored in.
+    pub dir: PathBuf,
+    /// Maximum number of entries. Logs failing once the queue is full are
+    /// dropped.
+    pub max_entries: usize,
+    /// Delay before the first retry of an entry, doubled after every failed
+    /// retry.
+    #[serde(with = "humantime_serde")]
+    pub initial_backoff: Duration,
+    /// Maximum delay between retries of an entry.
+    #[serde(with = "humantime_serde")]
+    pub max_backoff: Duration,
+}
+
+impl Default for DlqConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("dlq"),
+            max_entries: 10_000,
+            initial_backoff: Duration::from_secs(30),
+            max_backoff: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// The journal of requests in flight.
+///
+/// Each gateway instance needs a directory of its own, since entries found
+/// in it on startup are assumed to be left behind by a crash.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct JournalConfig {
+    /// Directory the entries are stored in.
+    pub dir: PathBuf,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        Self {
+            dir: PathBuf::from("journal"),
+        }
+    }
+}
+
+/// Aggregation of the usage of logged requests into the
+/// `gateway_usage_daily` table, which is served by
+/// `GET /admin/v1/usage/report`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct UsageReportConfig {
+    /// How often the usage aggregated in memory is added to the table.
+    #[serde(with = "humantime_serde")]
+    pub flush_interval: Duration,
+}
+
+impl Default for UsageReportConfig {
+    fn default() -> Self {
+        Self {
+            flush_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+impl LoggerConfig {
+    #[must_use]
+    pub fn spill_dir(&self) -> PathBuf {
+        self.spill_dir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+}
+
+impl Default for LoggerConfig {
+    fn default() -> Self {
+        Self {
+            spill_threshold: 4 * 1024 * 1024,
+            spill_dir: None,
+            queue_capacity: 10_000,
+            delivery_concurrency: 64,
+            overflow: OverflowPolicy::default(),
+            batch: None,
+            dlq: None,
+            journal: None,
+            request_events: false,
+            usage_report: None,
+        }
+    }
+}
@@ -1,22 +1,47 @@
 pub mod balance;
+pub mod bedrock_guardrail;
 pub mod cache;
+pub mod chaos;
+pub mod context_length;
 pub mod control_plane;
+pub mod conversation;
+pub mod data_residency;
 pub mod database;
+pub mod deployment;
 pub mod deployment_target;
+pub mod differential;
 pub mod discover;
 pub mod dispatcher;
 pub mod helicone;
+pub mod json_mode;
+pub mod key_tier;
+pub mod latency_slo;
+pub mod leader_election;
+pub mod logger;
 pub mod minio;
+pub mod model_capabilities;
+pub mod model_list;
 pub mod model_mapping;
 pub mod monitor;
+pub mod output_limits;
+pub mod provenance;
 pub mod providers;
+pub mod query;
 pub mod rate_limit;
 pub mod redis;
 pub mod response_headers;
 pub mod retry;
 pub mod router;
+pub mod script;
 pub mod server;
+pub mod size_anomaly;
+pub mod spend_anomaly;
+pub mod state_sync;
+pub mod stream_transform;
+pub mod throughput;
 pub mod validation;
+pub mod wasm_filter;
+pub mod webhook;
 use std::path::PathBuf;
 
 use config::ConfigError;
@@ -25,8 +50,10 @@ use json_patch::merge;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
 use thiserror::Error;
+use typed_builder::TypedBuilder;
 use url::Url;
 
+use self::validation::{ValidationError, ValidationErrors};
 use crate::{
     error::init::InitError,
     types::{provider::InferenceProvider, secret::Secret},
@@ -49,8 +76,11 @@ pub enum Error {
     UrlParse(#[from] url::ParseError),
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[derive(
+    Debug, Default, Deserialize, Serialize, PartialEq, Eq, Hash, TypedBuilder,
+)]
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
+#[builder(field_defaults(default, setter(strip_option)))]
 pub struct MiddlewareConfig {
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub cache: Option<self::cache::CacheConfig>,
@@ -60,8 +90,16 @@ pub struct MiddlewareConfig {
     pub retries: Option<self::retry::RetryConfig>,
 }
 
-#[derive(Debug, Default, Deserialize, Serialize, PartialEq, Eq)]
+/// The configuration of the gateway.
+///
+/// Besides being read from files and the environment with
+/// [`Config::try_read`], configs can be built in code with
+/// [`Config::builder`], where every field not set is defaulted.
+#[derive(
+    Debug, Default, Deserialize, Serialize, PartialEq, Eq, TypedBuilder,
+)]
 #[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+#[builder(field_defaults(default))]
 pub struct Config {
     pub telemetry: telemetry::Config,
     pub server: self::server::ServerConfig,
@@ -71,19 +109,61 @@ pub struct Config {
     pub discover: self::discover::DiscoverConfig,
     pub response_headers: self::response_headers::ResponseHeadersConfig,
     pub deployment_target: self::deployment_target::DeploymentTarget,
+    /// Identifies this instance in telemetry and to the control plane.
+    pub deployment: self::deployment::DeploymentConfig,
     pub control_plane: self::control_plane::ControlPlaneConfig,
 
     /// If a request is made with a model that is not in the `RouterConfig`
     /// model mapping, then we fallback to this.
     pub default_model_mapping: self::model_mapping::ModelMappingConfig,
+    /// Maps models without a mapping to the most similar model of the
+    /// target provider. Disabled if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub fuzzy_model_mapping:
+        Option<self::model_mapping::FuzzyModelMappingConfig>,
+    /// Groups of equivalent models across providers, which requests can
+    /// name instead of a model.
+    pub model_groups: self::model_mapping::ModelGroupsConfig,
+    /// Context windows and pricing of known models, used to route
+    /// long-context requests to a model that can accommodate them.
+    pub model_capabilities: self::model_capabilities::ModelCapabilitiesConfig,
+    /// Caching of the providers' model lists served on `/v1/models`.
+    pub model_list: self::model_list::ModelListConfig,
     pub helicone: self::helicone::HeliconeConfig,
+    /// Buffering of the request logs sent to Helicone.
+    pub logger: self::logger::LoggerConfig,
     /// *ALL* supported providers, independent of router configuration.
     pub providers: self::providers::ProvidersConfig,
+    /// Warnings about unusually large request and response bodies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub size_anomalies: Option<self::size_anomaly::SizeAnomalyConfig>,
+    /// Freezes of API keys and routers whose spend suddenly rises. Disabled
+    /// if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub spend_anomalies: Option<self::spend_anomaly::SpendAnomalyConfig>,
+    /// Caps on the requests of API keys, by the tier of the key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub key_tiers: Option<self::key_tier::KeyTiersConfig>,
+    /// Fault injection through the admin API. Disabled if unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub chaos: Option<self::chaos::ChaosConfig>,
 
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
     pub cache_store: Option<self::cache::CacheStore>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
     pub rate_limit_store: Option<self::rate_limit::RateLimitStore>,
+    /// Where conversations are stored, for routers with conversations
+    /// enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub conversation_store: Option<self::conversation::ConversationStore>,
     /// Global middleware configuration, e.g. rate limiting, caching, etc.
     ///
     /// This configuration will be for middleware that is applied to ALL
@@ -175,18 +255,57 @@ impl Config {
         Ok(config)
     }
 
+    /// Validates the config, reporting every problem found rather than only
+    /// the first.
     pub fn validate(&self) -> Result<(), InitError> {
         let router_id_regex =
             Regex::new(ROUTER_ID_REGEX).expect("always valid if tests pass");
+        let mut errors = ValidationErrors::default();
         for (router_id, router_config) in self.routers.as_ref() {
-            router_config.validate()?;
             if !router_id_regex.is_match(router_id.as_ref()) {
-                return Err(InitError::InvalidRouterId(router_id.to_string()));
+                errors.push(ValidationError::InvalidRouterId(
+                    router_id.to_string(),
+                ));
+            }
+            let mut router_errors =
+                router_config.validate().err().unwrap_or_default();
+            if let Some(rate_limit) = &router_config.rate_limit
+                && rate_limit.store.is_none()
+                && self.rate_limit_store.is_none()
+            {
+                router_errors.push(
+                    ValidationError::RateLimitStoreNotConfigured {
+                        scope: "router",
+                    },
+                );
+            }
+            errors.extend_router(router_id, router_errors);
+        }
+        for (scope, middleware) in
+            [("global", &self.global), ("unified-api", &self.unified_api)]
+        {
+            if middleware.rate_limit.is_some()
+                && self.rate_limit_store.is_none()
+            {
+                errors.push(ValidationError::RateLimitStoreNotConfigured {
+                    scope,
+                });
+            }
+        }
+        for (group, members) in self.model_groups.as_ref() {
+            for (provider, model) in members {
+                if model.inference_provider().as_ref() != Some(provider) {
+                    errors.push(ValidationError::GroupMemberProvider {
+                        group: group.clone(),
+                        provider: provider.clone(),
+                        model: model.clone(),
+                    });
+                }
             }
         }
         // TODO: merged configs make this brittle. bring it back after we've
         // improved that self.validate_model_mappings()?;
-        Ok(())
+        errors.into_result().map_err(InitError::from)
     }
 }
 
@@ -207,15 +326,27 @@ impl crate::tests::TestDefault for Config {
             control_plane: self::control_plane::ControlPlaneConfig::default(),
             default_model_mapping:
                 self::model_mapping::ModelMappingConfig::default(),
+            fuzzy_model_mapping: None,
+            model_groups: self::model_mapping::ModelGroupsConfig::default(),
+            model_capabilities:
+                self::model_capabilities::ModelCapabilitiesConfig::default(),
+            model_list: self::model_list::ModelListConfig::default(),
             global: MiddlewareConfig::default(),
             unified_api: MiddlewareConfig::default(),
             providers: self::providers::ProvidersConfig::default(),
+            size_anomalies: None,
+            spend_anomalies: None,
+            key_tiers: None,
+            chaos: None,
             helicone: self::helicone::HeliconeConfig::test_default(),
+            logger: self::logger::LoggerConfig::default(),
             deployment_target:
                 self::deployment_target::DeploymentTarget::Sidecar,
+            deployment: self::deployment::DeploymentConfig::default(),
             discover: self::discover::DiscoverConfig::test_default(),
             cache_store: Some(self::cache::CacheStore::default()),
             rate_limit_store: Some(self::rate_limit::RateLimitStore::default()),
+            conversation_store: None,
             routers: self::router::RouterConfigs::test_default(),
             response_headers:
                 self::response_headers::ResponseHeadersConfig::default(),
@@ -228,13 +359,63 @@ mod tests {
     use std::time::Duration;
 
     use super::*;
-    use crate::config::deployment_target::DeploymentTarget;
+    use crate::{
+        config::deployment_target::DeploymentTarget, types::router::RouterId,
+    };
 
     #[test]
     fn router_id_regex_is_valid() {
         assert!(Regex::new(ROUTER_ID_REGEX).is_ok());
     }
 
+    #[test]
+    fn built_config_matches_yaml() {
+        let yaml = r"
+routers:
+  my-router:
+    load-balance:
+      chat:
+        strategy: provider-weighted
+        providers:
+          - provider: openai
+            weight: '1'
+    providers:
+      openai:
+        base-url: https://openai.example.com
+global:
+  cache:
+    directive: max-age=60
+";
+        let from_yaml = serde_yml::from_str::<Config>(yaml).unwrap();
+        let router = self::router::RouterConfig::builder()
+            .load_balance(self::balance::BalanceConfig::openai_chat())
+            .providers(
+                [(
+                    InferenceProvider::OpenAI,
+                    self::router::RouterProviderConfig::builder()
+                        .base_url("https://openai.example.com".parse().unwrap())
+                        .build(),
+                )]
+                .into_iter()
+                .collect(),
+            )
+            .build();
+        let config = Config::builder()
+            .routers(
+                [(RouterId::Named("my-router".into()), router)]
+                    .into_iter()
+                    .collect(),
+            )
+            .global(
+                MiddlewareConfig::builder()
+                    .cache(from_yaml.global.cache.clone().unwrap())
+                    .build(),
+            )
+            .build();
+        assert_eq!(config, from_yaml);
+        assert!(config.validate().is_ok());
+    }
+
     #[test]
     fn default_config_is_serializable() {
         // if it doesn't panic, it's good
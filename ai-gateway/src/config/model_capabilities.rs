@@ -0,0 +1,87 @@
+use derive_more::AsRef;
+use indexmap::IndexMap;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::types::model_id::{ModelId, ModelIdWithoutVersion};
+
+const MODEL_CAPABILITIES_YAML: &str =
+    include_str!("../../config/embedded/model-capabilities.yaml");
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ModelCapability {
+    /// The maximum number of tokens (prompt and completion) the model
+    /// accepts.
+    pub context_window: u32,
+    /// Price in USD per million input tokens.
+    pub input_cost_per_mtok: Decimal,
+    /// Price in USD per million output tokens. The input price if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_cost_per_mtok: Option<Decimal>,
+    /// Whether the model returns log probabilities of its output tokens.
+    #[serde(default)]
+    pub logprobs: bool,
+}
+
+/// Capability matrix of known models, used for context-length aware routing
+/// and to tell which request parameters a model supports.
+#[derive(Debug, Clone, Deserialize, Serialize, AsRef, PartialEq, Eq)]
+pub struct ModelCapabilitiesConfig(
+    pub(crate) IndexMap<ModelId, ModelCapability>,
+);
+
+impl ModelCapabilitiesConfig {
+    /// Look up the capabilities of a model, ignoring any version suffix on
+    /// the given model id.
+    #[must_use]
+    pub fn get(&self, model: &ModelId) -> Option<&ModelCapability> {
+        if let Some(capability) = self.0.get(model) {
+            return Some(capability);
+        }
+        let model = ModelIdWithoutVersion::from(model.clone());
+        self.0.iter().find_map(|(known, capability)| {
+            (ModelIdWithoutVersion::from(known.clone()) == model)
+                .then_some(capability)
+        })
+    }
+}
+
+impl Default for ModelCapabilitiesConfig {
+    fn default() -> Self {
+        serde_yml::from_str(MODEL_CAPABILITIES_YAML)
+            .expect("Always valid if tests pass")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_default_model_capabilities_config_loads_from_yaml_string() {
+        let config = ModelCapabilitiesConfig::default();
+        let gpt_4o = ModelId::from_str("openai/gpt-4o").unwrap();
+        assert_eq!(config.get(&gpt_4o).unwrap().context_window, 128_000);
+    }
+
+    #[test]
+    fn model_capabilities_round_trip() {
+        let config = ModelCapabilitiesConfig::default();
+        let serialized = serde_json::to_string(&config).unwrap();
+        let deserialized =
+            serde_json::from_str::<ModelCapabilitiesConfig>(&serialized)
+                .unwrap();
+        assert_eq!(config, deserialized);
+    }
+
+    #[test]
+    fn lookup_ignores_version() {
+        let config = ModelCapabilitiesConfig::default();
+        let versioned =
+            ModelId::from_str("anthropic/claude-3-5-sonnet-20241022").unwrap();
+        assert_eq!(config.get(&versioned).unwrap().context_window, 200_000);
+    }
+}
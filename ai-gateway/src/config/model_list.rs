@@ -0,0 +1,20 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Caching of the providers' model lists served on `/v1/models`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ModelListConfig {
+    /// How long a provider's model list is served before it's refreshed.
+    #[serde(with = "humantime_serde")]
+    pub ttl: Duration,
+}
+
+impl Default for ModelListConfig {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(10 * 60),
+        }
+    }
+}
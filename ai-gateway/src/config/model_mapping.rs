@@ -1,8 +1,14 @@
+use std::collections::HashMap;
+
 use derive_more::AsRef;
 use nonempty_collections::{NEMap, NESet};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::types::model_id::{ModelId, ModelName};
+use crate::types::{
+    model_id::{ModelId, ModelName},
+    provider::InferenceProvider,
+};
 
 const MODEL_MAPPING_YAML: &str =
     include_str!("../../config/embedded/model-mapping.yaml");
@@ -20,6 +26,51 @@ impl Default for ModelMappingConfig {
     }
 }
 
+/// Named groups of equivalent models, such as `small-fast` or `frontier`,
+/// where each provider declares its member model.
+///
+/// A request for a group's name is mapped to the member of whichever
+/// provider it's dispatched to, so routers balancing across many providers
+/// don't need a model mapping for each of them.
+#[derive(
+    Debug, Clone, Default, Deserialize, Serialize, AsRef, PartialEq, Eq,
+)]
+pub struct ModelGroupsConfig(
+    pub(crate) HashMap<String, HashMap<InferenceProvider, ModelId>>,
+);
+
+impl ModelGroupsConfig {
+    /// The member model of each provider in `group`, or `None` if there is
+    /// no such group.
+    #[must_use]
+    pub fn members(
+        &self,
+        group: &str,
+    ) -> Option<&HashMap<InferenceProvider, ModelId>> {
+        self.0.get(group)
+    }
+}
+
+/// Maps models without an explicit mapping to the most similar model the
+/// target provider offers, by the family and version in their names,
+/// instead of rejecting the request. Responses of substituted models have
+/// the requested model in the `helicone-substituted-from` header.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FuzzyModelMappingConfig {
+    /// The lowest similarity, from 0 to 1, of a model that may be
+    /// substituted.
+    pub min_score: Decimal,
+}
+
+impl Default for FuzzyModelMappingConfig {
+    fn default() -> Self {
+        Self {
+            min_score: Decimal::new(6, 1),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -29,4 +80,116 @@ mod tests {
         let _default_config = ModelMappingConfig::default();
         // just want to make sure we don't panic...
     }
+
+    #[test]
+    fn model_groups_have_a_member_per_provider() {
+        let groups: ModelGroupsConfig =
+            serde_json::from_value(serde_json::json!({
+                "small-fast": {
+                    "openai": "openai/gpt-4o-mini",
+                    "anthropic": "anthropic/claude-3-5-haiku",
+                },
+            }))
+            .unwrap();
+        let members = groups.members("small-fast").unwrap();
+        assert_eq!(
+            members
+                .get(&InferenceProvider::Anthropic)
+                .map(ToString::to_string),
+            Some("claude-3-5-haiku".to_string())
+        );
+        assert!(members.get(&InferenceProvider::GoogleGemini).is_none());
+        assert!(groups.members("frontier").is_none());
+    }
+
+    #[test]
+    fn xai_and_openai_models_fall_back_to_each_other() {
+        let mapping = ModelMappingConfig::default();
+        let providers = crate::config::providers::ProvidersConfig::default();
+        for (source, target) in [
+            (
+                InferenceProvider::OpenAI,
+                InferenceProvider::Named("xai".into()),
+            ),
+            (
+                InferenceProvider::Named("xai".into()),
+                InferenceProvider::OpenAI,
+            ),
+        ] {
+            for model in &providers.get(&source).unwrap().models {
+                let targets = mapping
+                    .0
+                    .get(&ModelName::from_model(model))
+                    .unwrap_or_else(|| panic!("{model} has no mapping"));
+                assert!(
+                    targets.iter().any(|target_model| {
+                        target_model.inference_provider().as_ref()
+                            == Some(&target)
+                    }),
+                    "{model} has no {target} mapping"
+                );
+            }
+        }
+    }
+
+    /// Asserts that every model `provider` offers maps to an OpenAI model,
+    /// and that each of `sources` maps to a model `provider` offers.
+    fn assert_maps_to_and_from(provider: &str, sources: &[&str]) {
+        let mapping = ModelMappingConfig::default();
+        let providers = crate::config::providers::ProvidersConfig::default();
+        let provider = InferenceProvider::Named(provider.into());
+        let offered = &providers.get(&provider).unwrap().models;
+        for model in offered {
+            let targets = mapping
+                .0
+                .get(&ModelName::from_model(model))
+                .unwrap_or_else(|| panic!("{model} has no mapping"));
+            assert!(
+                targets.iter().any(|target_model| {
+                    target_model.inference_provider().as_ref()
+                        == Some(&InferenceProvider::OpenAI)
+                }),
+                "{model} has no openai mapping"
+            );
+        }
+        for model in sources {
+            let targets = mapping.0.get(&ModelName::borrowed(model)).unwrap();
+            let target_model = targets
+                .iter()
+                .find(|target_model| {
+                    target_model.inference_provider().as_ref()
+                        == Some(&provider)
+                })
+                .unwrap_or_else(|| panic!("{model} has no {provider} mapping"));
+            assert!(
+                offered.iter().any(|offered| {
+                    ModelName::from_model(offered)
+                        == ModelName::from_model(target_model)
+                }),
+                "{model} maps to {target_model}, which {provider} does not \
+                 offer"
+            );
+        }
+    }
+
+    #[test]
+    fn together_models_map_to_and_from_other_providers() {
+        assert_maps_to_and_from(
+            "together",
+            &["gpt-4o", "gpt-4o-mini", "deepseek-chat"],
+        );
+    }
+
+    #[test]
+    fn fireworks_models_map_to_and_from_other_providers() {
+        assert_maps_to_and_from(
+            "fireworks",
+            &[
+                "gpt-4o",
+                "gpt-4o-mini",
+                "deepseek-chat",
+                "deepseek-reasoner",
+            ],
+        );
+    }
 }
@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+
+/// Limits on the output of every completion through a router, enforced by
+/// the gateway.
+///
+/// The maximum output tokens are also passed on to the provider, but both
+/// limits are enforced on the response as well, for providers that ignore or
+/// lack the corresponding parameters.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OutputLimitsConfig {
+    /// Completions end before the first of these sequences.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+    /// The maximum number of tokens in a completion. Tokens are estimated
+    /// from the length of the completion when enforced by the gateway.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_limits_config_from_yaml() {
+        let yaml = r"
+stop: [END]
+max-output-tokens: 256
+";
+        let config = serde_yml::from_str::<OutputLimitsConfig>(yaml).unwrap();
+        assert_eq!(
+            config,
+            OutputLimitsConfig {
+                stop: vec!["END".to_string()],
+                max_output_tokens: Some(256),
+            }
+        );
+    }
+}
@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{types::secret::Secret, utils::default_true};
+
+/// Provenance metadata attached to a router's responses, so that they can be
+/// traced back to the model and provider which produced them.
+///
+/// The metadata is the selected model and provider and the time the response
+/// was produced, optionally signed with HMAC-SHA256. Message content is never
+/// altered.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProvenanceConfig {
+    /// Whether the metadata is sent in the `helicone-provenance` response
+    /// header.
+    #[serde(default = "default_true")]
+    pub header: bool,
+    /// A top level field of JSON responses to add the metadata to, e.g.
+    /// `x_provenance`. Streamed responses only carry the header.
+    #[serde(default)]
+    pub body_field: Option<String>,
+    /// The key signing the metadata, so that its origin can be verified.
+    #[serde(default)]
+    pub signing_key: Option<Secret<String>>,
+}
+
+impl Default for ProvenanceConfig {
+    fn default() -> Self {
+        Self {
+            header: true,
+            body_field: None,
+            signing_key: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provenance_config_from_yaml() {
+        let yaml = r"
+body-field: x_provenance
+signing-key: provenance-secret
+";
+        let config = serde_yml::from_str::<ProvenanceConfig>(yaml).unwrap();
+        assert!(config.header);
+        assert_eq!(config.body_field.as_deref(), Some("x_provenance"));
+        assert_eq!(
+            config.signing_key.as_ref().map(|key| key.expose().as_str()),
+            Some("provenance-secret")
+        );
+    }
+}
@@ -6,9 +6,13 @@ use serde::{
     Deserialize, Deserializer, Serialize, Serializer,
     de::{self, MapAccess, Visitor},
 };
+use typed_builder::TypedBuilder;
 use url::Url;
 
-use crate::types::{model_id::ModelId, provider::InferenceProvider};
+use crate::{
+    config::query::QueryRulesConfig,
+    types::{model_id::ModelId, provider::InferenceProvider},
+};
 
 const PROVIDERS_YAML: &str =
     include_str!("../../config/embedded/providers.yaml");
@@ -17,15 +21,107 @@ pub(crate) const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
 /// Global configuration for providers, shared across all routers.
 ///
 /// For router-specific provider configuration, see [`RouterProviderConfig`]
-#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, TypedBuilder)]
 #[serde(rename_all = "kebab-case")]
 pub struct GlobalProviderConfig {
     /// NOTE: In the future we can delete the `model` field and
     /// instead load the models from the provider's respective APIs
+    ///
+    /// Custom providers which don't list their models are sent whichever
+    /// model is requested of them.
+    #[serde(default)]
+    #[builder(default)]
     pub models: IndexSet<ModelId>,
     pub base_url: Url,
     #[serde(default)]
+    #[builder(default, setter(strip_option, into))]
     pub version: Option<String>,
+    /// Where the provider processes requests, e.g. `us` or `eu-west-1`, for
+    /// data residency policies.
+    #[serde(default)]
+    #[builder(default, setter(strip_option, into))]
+    pub region: Option<String>,
+    /// The Google Cloud project of Vertex AI, if not that of its service
+    /// account.
+    #[serde(default)]
+    #[builder(default, setter(strip_option, into))]
+    pub project: Option<String>,
+    /// The Cloudflare account of Workers AI, if not that of the
+    /// `CLOUDFLARE_ACCOUNT_ID` environment variable.
+    #[serde(default)]
+    #[builder(default, setter(strip_option, into))]
+    pub account_id: Option<String>,
+    /// Where Gemini's API key is sent, for proxies which strip the others.
+    #[serde(default)]
+    #[builder(default)]
+    pub api_key_location: ApiKeyLocation,
+    /// The header the API key of a custom `OpenAI` compatible provider is
+    /// sent in, if not as a bearer token.
+    #[serde(default)]
+    #[builder(default, setter(strip_option))]
+    pub auth_header: Option<AuthHeaderTemplate>,
+    /// A path requests are sent under, after that of `base-url`, for
+    /// providers served under a sub-path, e.g. behind an ingress.
+    #[serde(default)]
+    #[builder(default, setter(strip_option, into))]
+    pub path_prefix: Option<String>,
+    /// Rules for the query parameters of requests to the provider.
+    #[serde(default)]
+    #[builder(default)]
+    pub query: QueryRulesConfig,
+}
+
+/// Where an API key is sent to the provider.
+#[derive(
+    Debug, Clone, Copy, Default, Deserialize, Serialize, Eq, PartialEq, Hash,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum ApiKeyLocation {
+    /// The `Authorization` header, as a bearer token.
+    #[default]
+    Bearer,
+    /// The `x-goog-api-key` header.
+    Header,
+    /// The `key` query parameter.
+    Query,
+}
+
+/// A header an API key is sent in, such as `x-api-key: {api-key}`.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct AuthHeaderTemplate {
+    pub name: String,
+    /// The value of the header, in which [`Self::API_KEY_PLACEHOLDER`] is
+    /// replaced by the API key.
+    #[serde(default = "default_auth_header_value")]
+    pub value: String,
+}
+
+impl AuthHeaderTemplate {
+    pub const API_KEY_PLACEHOLDER: &str = "{api-key}";
+
+    #[must_use]
+    pub fn render(&self, api_key: &str) -> String {
+        self.value.replace(Self::API_KEY_PLACEHOLDER, api_key)
+    }
+}
+
+fn default_auth_header_value() -> String {
+    AuthHeaderTemplate::API_KEY_PLACEHOLDER.to_string()
+}
+
+impl GlobalProviderConfig {
+    /// The region of the provider, which is the AWS region of its base URL
+    /// if not configured.
+    #[must_use]
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref().or_else(|| {
+            let host = self.base_url.host_str()?;
+            let (_, region) =
+                host.strip_suffix(".amazonaws.com")?.split_once('.')?;
+            Some(region)
+        })
+    }
 }
 
 /// Map of *ALL* supported providers.
@@ -44,10 +140,25 @@ impl<'de> Deserialize<'de> for ProvidersConfig {
         #[derive(Deserialize)]
         #[serde(rename_all = "kebab-case")]
         struct RawGlobalProviderConfig {
+            #[serde(default)]
             models: IndexSet<String>,
             base_url: Url,
             #[serde(default)]
             version: Option<String>,
+            #[serde(default)]
+            region: Option<String>,
+            #[serde(default)]
+            project: Option<String>,
+            #[serde(default)]
+            account_id: Option<String>,
+            #[serde(default)]
+            api_key_location: ApiKeyLocation,
+            #[serde(default)]
+            auth_header: Option<AuthHeaderTemplate>,
+            #[serde(default)]
+            path_prefix: Option<String>,
+            #[serde(default)]
+            query: QueryRulesConfig,
         }
 
         impl<'de> Visitor<'de> for ProvidersConfigVisitor {
@@ -97,6 +208,13 @@ impl<'de> Deserialize<'de> for ProvidersConfig {
                         models,
                         base_url: raw_config.base_url,
                         version: raw_config.version,
+                        region: raw_config.region,
+                        project: raw_config.project,
+                        account_id: raw_config.account_id,
+                        api_key_location: raw_config.api_key_location,
+                        auth_header: raw_config.auth_header,
+                        path_prefix: raw_config.path_prefix,
+                        query: raw_config.query,
                     };
 
                     providers.insert(provider, config);
@@ -123,6 +241,19 @@ impl Serialize for ProvidersConfig {
             base_url: Url,
             #[serde(skip_serializing_if = "Option::is_none")]
             version: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            region: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            project: Option<String>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            account_id: Option<String>,
+            api_key_location: ApiKeyLocation,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            auth_header: Option<AuthHeaderTemplate>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            path_prefix: Option<String>,
+            #[serde(skip_serializing_if = "QueryRulesConfig::is_empty")]
+            query: QueryRulesConfig,
         }
 
         let mut map = serializer.serialize_map(Some(self.0.len()))?;
@@ -136,6 +267,13 @@ impl Serialize for ProvidersConfig {
                 models: models_as_strings,
                 base_url: config.base_url.clone(),
                 version: config.version.clone(),
+                region: config.region.clone(),
+                project: config.project.clone(),
+                account_id: config.account_id.clone(),
+                api_key_location: config.api_key_location,
+                auth_header: config.auth_header.clone(),
+                path_prefix: config.path_prefix.clone(),
+                query: config.query.clone(),
             };
 
             map.serialize_entry(provider, &serialized_config)?;
@@ -235,4 +373,44 @@ anthropic:
             }
         );
     }
+
+    #[test]
+    fn path_prefix_round_trip() {
+        let yaml = r"
+ollama:
+  models:
+    - llama3
+  base-url: http://ingress.internal/
+  path-prefix: /llm
+";
+        let config: ProvidersConfig = serde_yml::from_str(yaml).unwrap();
+        let ollama_config = config.get(&InferenceProvider::Ollama).unwrap();
+        assert_eq!(ollama_config.path_prefix.as_deref(), Some("/llm"));
+        let serialized = serde_yml::to_string(&config).unwrap();
+        let deserialized: ProvidersConfig =
+            serde_yml::from_str(&serialized).unwrap();
+        assert_eq!(config, deserialized);
+    }
+
+    #[test]
+    fn custom_provider_needs_only_a_base_url() {
+        let yaml = r#"
+my-vllm:
+  base-url: http://vllm.internal:8000/
+  auth-header:
+    name: x-api-key
+"#;
+        let config: ProvidersConfig = serde_yml::from_str(yaml).unwrap();
+        let custom_config = config
+            .get(&InferenceProvider::Named("my-vllm".into()))
+            .unwrap();
+        assert!(custom_config.models.is_empty());
+        let auth_header = custom_config.auth_header.as_ref().unwrap();
+        assert_eq!(auth_header.name, "x-api-key");
+        assert_eq!(auth_header.render("secret"), "secret");
+        let serialized = serde_yml::to_string(&config).unwrap();
+        let deserialized: ProvidersConfig =
+            serde_yml::from_str(&serialized).unwrap();
+        assert_eq!(config, deserialized);
+    }
 }
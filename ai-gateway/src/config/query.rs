@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+
+use indexmap::IndexSet;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::types::secret::Secret;
+
+/// Rules for the query parameters of requests sent to a provider, such as
+/// Azure's `api-version` or Gemini's `key`.
+///
+/// Parameters of the request are forwarded unless dropped, and those set
+/// here replace any of the same name.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct QueryRulesConfig {
+    /// If set, only these parameters of the request are forwarded.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub forward: Option<IndexSet<String>>,
+    /// Parameters of the request which aren't forwarded.
+    #[serde(skip_serializing_if = "IndexSet::is_empty")]
+    pub drop: IndexSet<String>,
+    /// Parameters added to every request.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub set: BTreeMap<String, String>,
+    /// Parameters added to every request, such as API keys, which are kept
+    /// out of the target URL that is logged.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub set_secret: BTreeMap<String, Secret<String>>,
+}
+
+impl QueryRulesConfig {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Rewrites the query of `url`, apart from the secret parameters, which
+    /// are added to the request by [`Self::secret_params`].
+    pub fn apply(&self, url: &mut Url) {
+        if self.is_empty() {
+            return;
+        }
+        let forwarded = url
+            .query_pairs()
+            .filter(|(name, _)| self.forwards(name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect::<Vec<_>>();
+        url.query_pairs_mut()
+            .clear()
+            .extend_pairs(forwarded)
+            .extend_pairs(&self.set);
+        if url.query() == Some("") {
+            url.set_query(None);
+        }
+    }
+
+    #[must_use]
+    pub fn secret_params(&self) -> Vec<(&str, &str)> {
+        self.set_secret
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.expose().as_str()))
+            .collect()
+    }
+
+    fn forwards(&self, name: &str) -> bool {
+        !self.drop.contains(name)
+            && !self.set.contains_key(name)
+            && !self.set_secret.contains_key(name)
+            && self
+                .forward
+                .as_ref()
+                .is_none_or(|forward| forward.contains(name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_rewrite_query() {
+        let rules: QueryRulesConfig =
+            serde_json::from_value(serde_json::json!({
+                "drop": ["debug"],
+                "set": { "api-version": "2024-10-21" },
+                "set-secret": { "key": "secret-key" },
+            }))
+            .unwrap();
+        let mut url = Url::parse(
+            "https://example.com/v1/chat?api-version=old&debug=1&alt=sse&key=x",
+        )
+        .unwrap();
+        rules.apply(&mut url);
+        assert_eq!(url.query(), Some("alt=sse&api-version=2024-10-21"));
+        assert_eq!(rules.secret_params(), vec![("key", "secret-key")]);
+
+        let rules = QueryRulesConfig {
+            forward: Some(IndexSet::new()),
+            ..QueryRulesConfig::default()
+        };
+        rules.apply(&mut url);
+        assert_eq!(url.query(), None);
+    }
+}
@@ -1,27 +1,8 @@
 use std::{num::NonZeroU32, time::Duration};
 
-use axum_core::response::IntoResponse;
-use http::StatusCode;
 use serde::{Deserialize, Serialize};
-use tower_governor::governor::{GovernorConfig, GovernorConfigBuilder};
-
-use crate::{
-    config::redis::RedisConfig,
-    error::{
-        api::{ErrorDetails, ErrorResponse},
-        init::InitError,
-    },
-    middleware::{
-        mapper::openai::SERVER_ERROR_TYPE,
-        rate_limit::extractor::RateLimitKeyExtractor,
-    },
-    types::json::Json,
-};
-
-pub type RateLimiterConfig = GovernorConfig<
-    RateLimitKeyExtractor,
-    governor::middleware::StateInformationMiddleware,
->;
+
+use crate::config::redis::RedisConfig;
 
 #[derive(
     Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq, Hash,
@@ -36,84 +17,6 @@ pub struct RateLimitConfig {
     pub limits: LimitsConfig,
 }
 
-pub(crate) fn limiter_config(
-    limits: &LimitsConfig,
-) -> Result<RateLimiterConfig, InitError> {
-    let gcra = &limits.per_api_key;
-    let per_cell_duration = gcra
-        .refill_frequency
-        .checked_div(gcra.capacity.into())
-        .unwrap_or_else(|| {
-            tracing::warn!(
-                "fill_frequency is too small for capacity, using default fill \
-                 frequency"
-            );
-            default_refill_frequency()
-        });
-
-    GovernorConfigBuilder::default()
-        .period(per_cell_duration)
-        .burst_size(gcra.capacity.get())
-        .use_headers()
-        .key_extractor(RateLimitKeyExtractor)
-        .error_handler(|mut e| match &e {
-            tower_governor::GovernorError::TooManyRequests { .. } => {
-                tracing::debug!("rate limite exceeded");
-                let governor_response: http::Response<String> = e.as_response();
-                let (parts, _) = governor_response.into_parts();
-                let body = ErrorResponse {
-                    error: ErrorDetails {
-                        message: "Too many requests".to_string(),
-                        r#type: Some("rate_limit_exceeded".to_string()),
-                        param: None,
-                        code: None,
-                    },
-                };
-                let json = Json(body);
-                let openai_response = json.into_response();
-                let (_, body) = openai_response.into_parts();
-                http::Response::from_parts(parts, body)
-            }
-            tower_governor::GovernorError::UnableToExtractKey => {
-                tracing::warn!(
-                    "unable to extract key, rate limiting enabled without \
-                     enabling authentication!!!"
-                );
-                let body = ErrorResponse {
-                    error: ErrorDetails {
-                        message: "Internal error, server misconfigured"
-                            .to_string(),
-                        r#type: Some(SERVER_ERROR_TYPE.to_string()),
-                        param: None,
-                        code: None,
-                    },
-                };
-                let json = Json(body);
-                (StatusCode::INTERNAL_SERVER_ERROR, json).into_response()
-            }
-            tower_governor::GovernorError::Other { code, msg, .. } => {
-                tracing::error!(
-                    msg = msg.as_deref().unwrap_or("Unknown error"),
-                    "Other error"
-                );
-                let body = ErrorResponse {
-                    error: ErrorDetails {
-                        message: "Internal error".to_string(),
-                        r#type: Some(SERVER_ERROR_TYPE.to_string()),
-                        param: None,
-                        code: None,
-                    },
-                };
-                let json = Json(body);
-                (*code, json).into_response()
-            }
-        })
-        .finish()
-        .ok_or(InitError::InvalidRateLimitConfig(
-            "either burst size or period interval are zero",
-        ))
-}
-
 #[derive(
     Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq, Hash,
 )]
@@ -176,6 +79,10 @@ pub fn store_enabled_for_test_redis() -> RateLimitStore {
 #[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct LimitsConfig {
     pub per_api_key: GcraConfig,
+    /// Limit on the requests of all API keys combined. Only enforced with
+    /// the in-memory store.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total: Option<TotalLimitConfig>,
 }
 
 #[cfg(feature = "testing")]
@@ -183,6 +90,7 @@ impl crate::tests::TestDefault for LimitsConfig {
     fn test_default() -> Self {
         Self {
             per_api_key: GcraConfig::test_default(),
+            total: None,
         }
     }
 }
@@ -216,3 +124,49 @@ impl crate::tests::TestDefault for GcraConfig {
         }
     }
 }
+
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TotalLimitConfig {
+    /// The duration it takes to refill the entire rate limit quota.
+    #[serde(with = "humantime_serde", default = "default_refill_frequency")]
+    pub refill_frequency: Duration,
+    /// The rate limit quota capacity.
+    #[serde(default = "default_capacity")]
+    pub capacity: NonZeroU32,
+    #[serde(default)]
+    pub fairness: Fairness,
+    /// How long requests may wait for capacity with fair queuing.
+    #[serde(with = "humantime_serde", default = "default_max_wait")]
+    pub max_wait: Duration,
+}
+
+impl TotalLimitConfig {
+    #[must_use]
+    pub fn gcra(&self) -> GcraConfig {
+        GcraConfig {
+            refill_frequency: self.refill_frequency,
+            capacity: self.capacity,
+        }
+    }
+}
+
+/// How the capacity of a saturated total limit is shared between keys.
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, Eq, PartialEq, Hash,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Fairness {
+    /// Requests are rejected as soon as the limit is exhausted, so a key
+    /// sending a burst of requests may use up the entire quota.
+    #[default]
+    ArrivalOrder,
+    /// Requests wait for capacity for up to `max-wait`, and keys with
+    /// waiting requests are granted capacity in turn, however many requests
+    /// each of them sends.
+    FairQueuing,
+}
+
+fn default_max_wait() -> Duration {
+    Duration::from_secs(1)
+}
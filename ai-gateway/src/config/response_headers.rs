@@ -10,6 +10,13 @@ pub struct ResponseHeadersConfig {
     pub provider: bool,
     #[serde(default = "default_true")]
     pub provider_request_id: bool,
+    /// The provider which served the request, as
+    /// `helicone-selected-provider`.
+    #[serde(default = "default_true")]
+    pub selected_provider: bool,
+    /// The model which served the request, as `helicone-selected-model`.
+    #[serde(default = "default_true")]
+    pub selected_model: bool,
 }
 
 impl Default for ResponseHeadersConfig {
@@ -17,6 +24,8 @@ impl Default for ResponseHeadersConfig {
         Self {
             provider: true,
             provider_request_id: true,
+            selected_provider: true,
+            selected_model: true,
         }
     }
 }
@@ -1,6 +1,7 @@
 use std::time::Duration;
 
 use backon::{BackoffBuilder, ConstantBuilder, ExponentialBuilder};
+use indexmap::IndexSet;
 use rust_decimal::{Decimal, prelude::ToPrimitive};
 use serde::{Deserialize, Serialize};
 
@@ -26,16 +27,112 @@ pub enum RetryConfig {
         max_retries: u8,
         #[serde(default = "default_factor")]
         factor: Decimal,
+        /// Bounds each attempt until the provider responds.
+        #[serde(
+            with = "humantime_serde",
+            rename = "attempt-timeout",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        attempt_timeout: Option<Duration>,
+        /// Bounds all attempts together, including the delays between them.
+        #[serde(
+            with = "humantime_serde",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        deadline: Option<Duration>,
+        /// Sends a second attempt if the first hasn't completed by then,
+        /// taking whichever completes first.
+        #[serde(
+            with = "humantime_serde",
+            rename = "hedge-after",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        hedge_after: Option<Duration>,
+        #[serde(rename = "stream-mode", default)]
+        stream_mode: StreamRetryMode,
     },
     Constant {
         #[serde(with = "humantime_serde", default = "default_min_delay")]
         delay: Duration,
         #[serde(rename = "max-retries", default = "default_max_retries")]
         max_retries: u8,
+        /// Bounds each attempt until the provider responds.
+        #[serde(
+            with = "humantime_serde",
+            rename = "attempt-timeout",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        attempt_timeout: Option<Duration>,
+        /// Bounds all attempts together, including the delays between them.
+        #[serde(
+            with = "humantime_serde",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        deadline: Option<Duration>,
+        /// Sends a second attempt if the first hasn't completed by then,
+        /// taking whichever completes first.
+        #[serde(
+            with = "humantime_serde",
+            rename = "hedge-after",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        hedge_after: Option<Duration>,
+        #[serde(rename = "stream-mode", default)]
+        stream_mode: StreamRetryMode,
     },
 }
 
 impl RetryConfig {
+    #[must_use]
+    pub fn max_retries(&self) -> u8 {
+        match self {
+            Self::Exponential { max_retries, .. }
+            | Self::Constant { max_retries, .. } => *max_retries,
+        }
+    }
+
+    #[must_use]
+    pub fn stream_mode(&self) -> StreamRetryMode {
+        match self {
+            Self::Exponential { stream_mode, .. }
+            | Self::Constant { stream_mode, .. } => *stream_mode,
+        }
+    }
+
+    #[must_use]
+    pub fn attempt_timeout(&self) -> Option<Duration> {
+        match self {
+            Self::Exponential {
+                attempt_timeout, ..
+            }
+            | Self::Constant {
+                attempt_timeout, ..
+            } => *attempt_timeout,
+        }
+    }
+
+    #[must_use]
+    pub fn hedge_after(&self) -> Option<Duration> {
+        match self {
+            Self::Exponential { hedge_after, .. }
+            | Self::Constant { hedge_after, .. } => *hedge_after,
+        }
+    }
+
+    #[must_use]
+    pub fn deadline(&self) -> Option<Duration> {
+        match self {
+            Self::Exponential { deadline, .. }
+            | Self::Constant { deadline, .. } => *deadline,
+        }
+    }
+
     #[must_use]
     pub fn as_iterator(
         &self,
@@ -46,6 +143,7 @@ impl RetryConfig {
                 max_delay,
                 max_retries,
                 factor,
+                ..
             } => {
                 let backoff = ExponentialBuilder::default()
                     .with_min_delay(*min_delay)
@@ -58,7 +156,9 @@ impl RetryConfig {
                     .build();
                 Box::new(backoff)
             }
-            Self::Constant { delay, max_retries } => {
+            Self::Constant {
+                delay, max_retries, ..
+            } => {
                 let backoff = ConstantBuilder::default()
                     .with_delay(*delay)
                     .with_max_times(usize::from(*max_retries))
@@ -69,6 +169,57 @@ impl RetryConfig {
     }
 }
 
+/// How streams which fail after they started are retried.
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, Eq, PartialEq, Hash,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum StreamRetryMode {
+    /// Streams are only retried if nothing was sent to the client yet, so
+    /// that the client never receives content twice.
+    #[default]
+    FirstByte,
+    /// Streams are also retried after content was sent to the client, by
+    /// replaying the request with the streamed content as a prefill of the
+    /// response. Only supported by providers which continue prefills.
+    Resume,
+}
+
+/// Bounds the retries of a router to a share of its requests, so that
+/// retries during a provider incident don't multiply the traffic sent to it.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, Hash)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RetryBudgetConfig {
+    /// Share of the requests in the window which may be retried.
+    pub ratio: Decimal,
+    /// Retries allowed in the window regardless of the ratio, so that
+    /// routers with little traffic can still retry.
+    pub min_retries: u32,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            ratio: Decimal::new(2, 1),
+            min_retries: 10,
+            window: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Provider error codes to retry on, in addition to server errors.
+///
+/// Codes are matched against the `type` and `code` of the error in the
+/// body of a failed response, e.g. Anthropic's `overloaded_error`. Content
+/// policy errors are never retried.
+#[derive(Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RetryOnConfig {
+    pub error_codes: IndexSet<String>,
+}
+
 fn default_factor() -> Decimal {
     Decimal::try_from(DEFAULT_RETRY_FACTOR).expect("always valid if tests pass")
 }
@@ -91,6 +242,10 @@ impl crate::tests::TestDefault for RetryConfig {
         Self::Constant {
             delay: Duration::from_millis(5),
             max_retries: 2,
+            attempt_timeout: None,
+            deadline: None,
+            hedge_after: None,
+            stream_mode: StreamRetryMode::default(),
         }
     }
 }
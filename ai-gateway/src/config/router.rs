@@ -1,18 +1,36 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
 
 use derive_more::{AsMut, AsRef};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use typed_builder::TypedBuilder;
 use url::Url;
 
 use super::{
     balance::{BalanceConfig, BalanceConfigInner},
+    bedrock_guardrail::BedrockGuardrailConfig,
+    context_length::ContextLengthConfig,
+    conversation::ConversationsConfig,
+    data_residency::DataResidencyConfig,
+    differential::DifferentialConfig,
+    json_mode::JsonModeConfig,
+    latency_slo::LatencySloConfig,
     model_mapping::ModelMappingConfig,
-    retry::RetryConfig,
+    output_limits::OutputLimitsConfig,
+    provenance::ProvenanceConfig,
+    retry::{RetryBudgetConfig, RetryConfig, RetryOnConfig},
+    script::ScriptConfig,
+    stream_transform::StreamTransformsConfig,
+    throughput::ThroughputConfig,
+    validation::{ValidationError, ValidationErrors},
+    wasm_filter::WasmFilterConfig,
+    webhook::WebhooksConfig,
 };
 use crate::{
     config::{cache::CacheConfig, rate_limit::RateLimitConfig},
-    error::init::InitError,
     types::{provider::InferenceProvider, router::RouterId},
 };
 
@@ -28,6 +46,15 @@ impl RouterConfigs {
     }
 }
 
+impl FromIterator<(RouterId, RouterConfig)> for RouterConfigs {
+    fn from_iter<T>(iter: T) -> Self
+    where
+        T: IntoIterator<Item = (RouterId, RouterConfig)>,
+    {
+        Self(HashMap::from_iter(iter))
+    }
+}
+
 impl std::ops::Deref for RouterConfigs {
     type Target = HashMap<RouterId, RouterConfig>;
     fn deref(&self) -> &Self::Target {
@@ -35,50 +62,146 @@ impl std::ops::Deref for RouterConfigs {
     }
 }
 
-#[derive(Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[derive(
+    Debug, Default, Clone, Deserialize, Serialize, Eq, PartialEq, TypedBuilder,
+)]
 #[serde(default, rename_all = "kebab-case")]
+#[builder(field_defaults(default))]
 pub struct RouterConfig {
     pub load_balance: BalanceConfig,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
     pub model_mappings: Option<ModelMappingConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
     pub cache: Option<CacheConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
     pub retries: Option<RetryConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
     pub rate_limit: Option<RateLimitConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
     pub providers: Option<HashMap<InferenceProvider, RouterProviderConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub latency_slo: Option<LatencySloConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub differential: Option<DifferentialConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub stream_transforms: Option<StreamTransformsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub output_limits: Option<OutputLimitsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub conversations: Option<ConversationsConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub retry_budget: Option<RetryBudgetConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub retry_on: Option<RetryOnConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub webhooks: Option<WebhooksConfig>,
+    /// Filters run in order on each request, and in reverse order on each
+    /// response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub wasm_filters: Option<Vec<WasmFilterConfig>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub script: Option<ScriptConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub prefer_throughput: Option<ThroughputConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub bedrock_guardrail: Option<BedrockGuardrailConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub data_residency: Option<DataResidencyConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub json_mode: Option<JsonModeConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub provenance: Option<ProvenanceConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(setter(strip_option))]
+    pub context_length: Option<ContextLengthConfig>,
 }
 
 impl RouterConfig {
-    pub fn validate(&self) -> Result<(), InitError> {
-        for balance_config in self.load_balance.0.values() {
-            match balance_config {
+    /// Checks the balance and model mapping configs, reporting every
+    /// problem found.
+    pub fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut errors = ValidationErrors::default();
+        for (endpoint, balance_config) in &self.load_balance.0 {
+            let weights = match balance_config {
                 BalanceConfigInner::ProviderWeighted { providers } => {
-                    let total =
-                        providers.iter().map(|t| t.weight).sum::<Decimal>();
-                    if total != Decimal::from(1) {
-                        return Err(InitError::InvalidBalancer(format!(
-                            "Balance weights dont sum to 1: {total}"
-                        )));
+                    let mut seen = HashSet::new();
+                    for target in providers.iter() {
+                        if !seen.insert(&target.provider) {
+                            errors.push(ValidationError::DuplicateProvider {
+                                endpoint: *endpoint,
+                                provider: target.provider.clone(),
+                            });
+                        }
                     }
+                    providers.iter().map(|t| t.weight).collect::<Vec<_>>()
                 }
                 BalanceConfigInner::ModelWeighted { models } => {
-                    let total =
-                        models.iter().map(|m| m.weight).sum::<Decimal>();
-                    if total != Decimal::from(1) {
-                        return Err(InitError::InvalidBalancer(format!(
-                            "Balance weights dont sum to 1: {total}"
-                        )));
+                    let mut seen = HashSet::new();
+                    for target in models.iter() {
+                        if !seen.insert(&target.model) {
+                            errors.push(ValidationError::DuplicateModel {
+                                endpoint: *endpoint,
+                                model: target.model.clone(),
+                            });
+                        }
                     }
+                    models.iter().map(|m| m.weight).collect()
                 }
                 BalanceConfigInner::BalancedLatency { .. }
-                | BalanceConfigInner::ModelLatency { .. } => {}
+                | BalanceConfigInner::ModelLatency { .. } => continue,
+            };
+            let total = weights.into_iter().sum::<Decimal>();
+            if total != Decimal::from(1) {
+                errors.push(ValidationError::WeightSum {
+                    endpoint: *endpoint,
+                    total,
+                });
             }
         }
 
-        Ok(())
+        if let Some(model_mappings) = &self.model_mappings {
+            let providers = self
+                .load_balance
+                .0
+                .values()
+                .flat_map(BalanceConfigInner::providers)
+                .collect::<HashSet<_>>();
+            for (source_model, targets) in model_mappings.as_ref().iter() {
+                // targets without a known provider may be reachable
+                let unreachable = targets.iter().all(|target| {
+                    target
+                        .inference_provider()
+                        .is_some_and(|provider| !providers.contains(&provider))
+                });
+                if unreachable {
+                    errors.push(ValidationError::UnreachableMapping {
+                        source_model: source_model.to_string(),
+                    });
+                }
+            }
+        }
+
+        errors.into_result()
     }
 
     #[must_use]
@@ -106,17 +229,75 @@ impl crate::tests::TestDefault for RouterConfigs {
                 retries: None,
                 rate_limit: None,
                 providers: None,
+                latency_slo: None,
+                differential: None,
+                stream_transforms: None,
+                output_limits: None,
+                conversations: None,
+                retry_budget: None,
+                retry_on: None,
+                webhooks: None,
+                wasm_filters: None,
+                script: None,
+                prefer_throughput: None,
+                bedrock_guardrail: None,
+                data_residency: None,
+                json_mode: None,
+                provenance: None,
+                context_length: None,
             },
         )]))
     }
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq, TypedBuilder)]
 #[serde(rename_all = "kebab-case")]
 pub struct RouterProviderConfig {
     pub base_url: Url,
     #[serde(default)]
+    #[builder(default, setter(strip_option, into))]
     pub version: Option<String>,
+    /// A path requests are sent under, after that of `base-url` or of a
+    /// region.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option, into))]
+    pub path_prefix: Option<String>,
+    /// Other base URLs of the provider, such as other regions. Requests are
+    /// balanced across `base-url` and the regions, and fail over to the
+    /// next one when a region is unreachable or failing.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub regions: Vec<ProviderRegionConfig>,
+    /// Client certificate presented to the provider, for private inference
+    /// clusters requiring mutual TLS. Each router has its own connections,
+    /// so routers can reach different clusters under the same provider name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub tls: Option<ProviderTlsConfig>,
+}
+
+/// PEM files of the client identity used to connect to a provider.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProviderTlsConfig {
+    pub cert: PathBuf,
+    /// PKCS#8 private key of `cert`.
+    pub key: PathBuf,
+    /// CA certificate the provider's certificate is verified against, in
+    /// addition to the system's roots.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ProviderRegionConfig {
+    pub name: String,
+    pub base_url: Url,
+    /// Environment variable holding the API key for this region. The
+    /// provider's key is used if not set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_env: Option<String>,
 }
 
 #[cfg(test)]
@@ -124,13 +305,18 @@ mod tests {
     use std::time::Duration;
 
     use super::*;
-    use crate::config::cache::CacheConfig;
+    use crate::{
+        config::{cache::CacheConfig, retry::StreamRetryMode},
+        endpoints::EndpointType,
+    };
 
     fn test_router_config() -> RouterConfig {
         let cache = CacheConfig {
             directive: Some("max-age=3600, max-stale=1800".to_string()),
             buckets: 10,
             seed: Some("test-seed".to_string()),
+            trust_client_directives: true,
+            key: None,
         };
 
         let balance = BalanceConfig::default();
@@ -139,6 +325,10 @@ mod tests {
             max_delay: Duration::from_secs(10),
             max_retries: 3,
             factor: Decimal::from(2),
+            attempt_timeout: None,
+            deadline: None,
+            hedge_after: None,
+            stream_mode: StreamRetryMode::default(),
         };
 
         RouterConfig {
@@ -148,6 +338,22 @@ mod tests {
             retries: Some(retries),
             rate_limit: None,
             providers: None,
+            latency_slo: None,
+            differential: None,
+            stream_transforms: None,
+            output_limits: None,
+            conversations: None,
+            retry_budget: None,
+            retry_on: None,
+            webhooks: None,
+            wasm_filters: None,
+            script: None,
+            prefer_throughput: None,
+            bedrock_guardrail: None,
+            data_residency: None,
+            json_mode: None,
+            provenance: None,
+            context_length: None,
         }
     }
 
@@ -168,4 +374,40 @@ mod tests {
             serde_json::from_str::<RouterConfigs>(&serialized).unwrap();
         assert_eq!(config, deserialized);
     }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let yaml = r"
+load-balance:
+  chat:
+    strategy: provider-weighted
+    providers:
+      - provider: openai
+        weight: '0.5'
+      - provider: openai
+        weight: '0.3'
+model-mappings:
+  gpt-4o:
+    - anthropic/claude-3-7-sonnet
+";
+        let config = serde_yml::from_str::<RouterConfig>(yaml).unwrap();
+        let errors = config.validate().unwrap_err();
+        let errors = errors.iter().map(|(_, e)| e).collect::<Vec<_>>();
+        assert_eq!(
+            errors,
+            [
+                &ValidationError::DuplicateProvider {
+                    endpoint: EndpointType::Chat,
+                    provider: InferenceProvider::OpenAI,
+                },
+                &ValidationError::WeightSum {
+                    endpoint: EndpointType::Chat,
+                    total: Decimal::new(8, 1),
+                },
+                &ValidationError::UnreachableMapping {
+                    source_model: "gpt-4o".to_string(),
+                },
+            ]
+        );
+    }
 }
@@ -0,0 +1,53 @@
+use std::{path::PathBuf, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+/// A Rhai script run on every request of a router, for request policies too
+/// small to be worth a WASM filter.
+///
+/// The script is given a `request` object map with the request's `method`,
+/// `path`, `headers`, JSON `body`, and `model`, and may change its headers,
+/// body, and model. Throwing rejects the request with the thrown message.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ScriptConfig {
+    pub path: PathBuf,
+    /// Maximum time a script may run per request.
+    #[serde(default = "default_timeout", with = "humantime_serde")]
+    pub timeout: Duration,
+    /// Maximum operations a script may run per request.
+    #[serde(default = "default_max_operations")]
+    pub max_operations: u64,
+    /// Maximum length in bytes of any string a script builds.
+    #[serde(default = "default_max_string_size")]
+    pub max_string_size: usize,
+    /// Maximum number of elements of any array a script builds.
+    #[serde(default = "default_max_collection_size")]
+    pub max_array_size: usize,
+    /// Maximum number of properties of any object map a script builds.
+    #[serde(default = "default_max_collection_size")]
+    pub max_map_size: usize,
+    /// Maximum depth of nested function calls.
+    #[serde(default = "default_max_call_levels")]
+    pub max_call_levels: usize,
+}
+
+fn default_timeout() -> Duration {
+    Duration::from_millis(10)
+}
+
+fn default_max_operations() -> u64 {
+    100_000
+}
+
+fn default_max_string_size() -> usize {
+    4 * 1024 * 1024
+}
+
+fn default_max_collection_size() -> usize {
+    10_000
+}
+
+fn default_max_call_levels() -> usize {
+    16
+}
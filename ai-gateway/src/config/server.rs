@@ -5,8 +5,11 @@ use std::{
     time::Duration,
 };
 
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 
+use crate::types::secret::Secret;
+
 #[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
 #[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
 pub struct ServerConfig {
@@ -18,6 +21,19 @@ pub struct ServerConfig {
     pub tls: TlsConfig,
     #[serde(with = "humantime_serde", default = "default_shutdown_timeout")]
     pub shutdown_timeout: Duration,
+    #[serde(default)]
+    pub header_limits: HeaderLimitsConfig,
+    /// Bearer token of the `/admin` endpoints, which are disabled if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<Secret<String>>,
+    /// Reject requests while the gateway is overloaded, before it degrades
+    /// for every request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub load_shedding: Option<LoadSheddingConfig>,
+    /// Proxies in front of the gateway, such as load balancers or CDNs,
+    /// whose forwarding headers are trusted for the client's IP address.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trusted_proxies: Option<TrustedProxiesConfig>,
 }
 
 impl Default for ServerConfig {
@@ -27,6 +43,10 @@ impl Default for ServerConfig {
             port: default_port(),
             tls: TlsConfig::default(),
             shutdown_timeout: default_shutdown_timeout(),
+            header_limits: HeaderLimitsConfig::default(),
+            admin_token: None,
+            load_shedding: None,
+            trusted_proxies: None,
         }
     }
 }
@@ -61,6 +81,103 @@ impl Display for TlsConfig {
     }
 }
 
+/// Thresholds of the gateway's pressure above which requests are rejected
+/// with a `503`. `low` priority requests are rejected from 80% of any
+/// threshold, `normal` priority requests from 100%, and `high` priority
+/// requests are never rejected.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct LoadSheddingConfig {
+    /// Maximum number of requests in flight, including streaming responses.
+    pub max_in_flight: usize,
+    /// Maximum delay of the async runtime in waking a task, which grows
+    /// once it has more work than it can keep up with.
+    #[serde(with = "humantime_serde")]
+    pub max_scheduler_delay: Duration,
+    /// Maximum resident memory of the process in bytes.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_memory: Option<u64>,
+    /// The `Retry-After` of rejected requests.
+    #[serde(with = "humantime_serde")]
+    pub retry_after: Duration,
+}
+
+impl Default for LoadSheddingConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 10_000,
+            max_scheduler_delay: Duration::from_millis(100),
+            max_memory: None,
+            retry_after: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Which proxies' forwarding headers are trusted for the client's IP
+/// address, used by rate limits and logs instead of the address of the
+/// connection.
+///
+/// Headers are read in the order of `headers`, from the first one which is
+/// set. Forwarding chains are walked from the gateway backwards, skipping
+/// trusted proxies, so that addresses prepended by clients are ignored.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TrustedProxiesConfig {
+    /// Address ranges of the trusted proxies, such as `10.0.0.0/8`.
+    pub cidrs: Vec<IpNet>,
+    pub headers: Vec<ForwardingHeader>,
+}
+
+impl Default for TrustedProxiesConfig {
+    fn default() -> Self {
+        Self {
+            cidrs: Vec::new(),
+            headers: vec![
+                ForwardingHeader::Forwarded,
+                ForwardingHeader::XForwardedFor,
+            ],
+        }
+    }
+}
+
+impl TrustedProxiesConfig {
+    #[must_use]
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.cidrs.iter().any(|cidr| cidr.contains(&ip))
+    }
+}
+
+/// A header through which proxies forward the client's IP address.
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum ForwardingHeader {
+    /// The standard `Forwarded` header, of RFC 7239.
+    Forwarded,
+    XForwardedFor,
+    /// Cloudflare's `CF-Connecting-IP`.
+    CfConnectingIp,
+}
+
+/// Limits on the headers of client requests. Requests exceeding them are
+/// rejected before they're routed.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HeaderLimitsConfig {
+    /// Maximum number of headers.
+    pub max_count: usize,
+    /// Maximum combined size of the header names and values, in bytes.
+    pub max_size: usize,
+}
+
+impl Default for HeaderLimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_count: 100,
+            max_size: 64 * 1024,
+        }
+    }
+}
+
 fn default_shutdown_timeout() -> Duration {
     Duration::from_secs(30)
 }
@@ -0,0 +1,37 @@
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Warnings about request and response bodies sent to and received from
+/// providers that are unusually large, such as prompts bloated by a client
+/// regression.
+///
+/// A body is anomalous if it's larger than the absolute limit of its
+/// direction, or more than `spike-factor` times the median size of the
+/// provider and model's bodies over `window`.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SizeAnomalyConfig {
+    /// Requests larger than this many bytes are anomalous.
+    pub request_bytes: Option<u64>,
+    /// Responses larger than this many bytes are anomalous.
+    pub response_bytes: Option<u64>,
+    pub spike_factor: Decimal,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+    /// Spikes are only detected once the window has this many bodies.
+    pub min_samples: usize,
+}
+
+impl Default for SizeAnomalyConfig {
+    fn default() -> Self {
+        Self {
+            request_bytes: None,
+            response_bytes: None,
+            spike_factor: Decimal::from(4),
+            window: Duration::from_secs(60 * 60),
+            min_samples: 20,
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+/// Automatic freezes of API keys and routers whose spend suddenly rises,
+/// such as from a leaked key or a client stuck in a retry loop.
+///
+/// Spend is estimated from the usage providers report and the prices of the
+/// model capabilities, and summed over consecutive windows. An API key or
+/// router whose spend in a window is more than `factor` times its average
+/// spend per window over the trailing `baseline` is frozen, and its
+/// router's webhooks are notified. Freezes are lifted through the admin
+/// API.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SpendAnomalyConfig {
+    pub factor: Decimal,
+    #[serde(with = "humantime_serde")]
+    pub window: Duration,
+    /// Spend is only compared to its baseline once it's been tracked for
+    /// this long, so that every key isn't frozen after a restart.
+    #[serde(with = "humantime_serde")]
+    pub baseline: Duration,
+    /// Spend in USD below which a window is never anomalous.
+    pub min_spend: Decimal,
+}
+
+impl Default for SpendAnomalyConfig {
+    fn default() -> Self {
+        Self {
+            factor: Decimal::from(5),
+            window: Duration::from_secs(5 * 60),
+            baseline: Duration::from_secs(60 * 60),
+            min_spend: Decimal::ONE,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::redis::RedisConfig;
+
+/// Configuration for sharing provider rate limit, health, and latency events
+/// across gateway replicas over a Redis pub/sub channel.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StateSyncConfig {
+    #[serde(default)]
+    pub redis: RedisConfig,
+    /// The pub/sub channel events are published to.
+    #[serde(default = "default_channel")]
+    pub channel: String,
+    /// How long a provider reported as unhealthy by another replica is
+    /// considered unhealthy locally, unless it is reported as recovered
+    /// sooner.
+    #[serde(default = "default_unhealthy_ttl", with = "humantime_serde")]
+    pub unhealthy_ttl: Duration,
+    /// Whether to share the latencies observed by model latency routers, so
+    /// that replicas don't each have to learn that a provider is slow.
+    #[serde(default = "default_share_latency")]
+    pub share_latency: bool,
+    /// How often each replica publishes its latency estimate for a model.
+    #[serde(
+        default = "default_latency_publish_interval",
+        with = "humantime_serde"
+    )]
+    pub latency_publish_interval: Duration,
+    /// How long a latency reported by another replica is taken into account
+    /// after it was received.
+    #[serde(default = "default_latency_ttl", with = "humantime_serde")]
+    pub latency_ttl: Duration,
+}
+
+impl Default for StateSyncConfig {
+    fn default() -> Self {
+        Self {
+            redis: RedisConfig::default(),
+            channel: default_channel(),
+            unhealthy_ttl: default_unhealthy_ttl(),
+            share_latency: default_share_latency(),
+            latency_publish_interval: default_latency_publish_interval(),
+            latency_ttl: default_latency_ttl(),
+        }
+    }
+}
+
+fn default_channel() -> String {
+    "ai-gateway:provider-events".to_string()
+}
+
+fn default_unhealthy_ttl() -> Duration {
+    Duration::from_secs(30)
+}
+
+fn default_share_latency() -> bool {
+    true
+}
+
+fn default_latency_publish_interval() -> Duration {
+    Duration::from_secs(5)
+}
+
+fn default_latency_ttl() -> Duration {
+    Duration::from_secs(30)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_sync_config_defaults() {
+        let yaml = r"
+redis:
+  host-url: redis://redis.internal:6379
+";
+        let config: StateSyncConfig = serde_yml::from_str(yaml).unwrap();
+        assert_eq!(
+            config.redis.host_url.expose().as_str(),
+            "redis://redis.internal:6379"
+        );
+        assert_eq!(config.channel, default_channel());
+        assert_eq!(config.unhealthy_ttl, default_unhealthy_ttl());
+        assert!(config.share_latency);
+        assert_eq!(
+            config.latency_publish_interval,
+            default_latency_publish_interval()
+        );
+    }
+
+    #[test]
+    fn state_sync_config_latency_sharing() {
+        let yaml = r"
+share-latency: false
+latency-publish-interval: 1s
+latency-ttl: 10s
+";
+        let config: StateSyncConfig = serde_yml::from_str(yaml).unwrap();
+        assert!(!config.share_latency);
+        assert_eq!(config.latency_publish_interval, Duration::from_secs(1));
+        assert_eq!(config.latency_ttl, Duration::from_secs(10));
+    }
+}
@@ -0,0 +1,73 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Transformations applied to the content of streamed completions, in order,
+/// as each chunk is forwarded to the client.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StreamTransformsConfig {
+    /// The time each transform may spend on a single chunk. A transform that
+    /// exceeds it is bypassed for the rest of the stream.
+    #[serde(default = "default_latency_budget", with = "humantime_serde")]
+    pub latency_budget: Duration,
+    pub transforms: Vec<StreamTransformConfig>,
+}
+
+impl Default for StreamTransformsConfig {
+    fn default() -> Self {
+        Self {
+            latency_budget: default_latency_budget(),
+            transforms: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case", tag = "type")]
+pub enum StreamTransformConfig {
+    /// Replaces each character of the given words with `*`, ignoring case.
+    Mask { words: Vec<String> },
+    /// Removes markdown emphasis, inline code and heading markers.
+    StripMarkdown,
+    /// Ends the completion before the first of the given sequences, for
+    /// providers that don't support stop sequences or support too few.
+    StopSequences { sequences: Vec<String> },
+}
+
+fn default_latency_budget() -> Duration {
+    Duration::from_millis(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_transforms_config_from_yaml() {
+        let yaml = r"
+latency-budget: 5ms
+transforms:
+  - type: mask
+    words: [darn]
+  - type: strip-markdown
+  - type: stop-sequences
+    sequences: [END]
+";
+        let config =
+            serde_yml::from_str::<StreamTransformsConfig>(yaml).unwrap();
+        assert_eq!(config.latency_budget, Duration::from_millis(5));
+        assert_eq!(
+            config.transforms,
+            vec![
+                StreamTransformConfig::Mask {
+                    words: vec!["darn".to_string()]
+                },
+                StreamTransformConfig::StripMarkdown,
+                StreamTransformConfig::StopSequences {
+                    sequences: vec!["END".to_string()]
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,22 @@
+use serde::{Deserialize, Serialize};
+
+/// Makes the `model-latency` strategy prefer the models which generate
+/// tokens fastest, rather than the ones with the lowest time to first
+/// token, for routers serving long generations.
+///
+/// The latency of each model becomes its time to first token plus the time
+/// it takes to stream `completion-tokens` at its observed tokens per second.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct ThroughputConfig {
+    /// The typical length of the router's completions.
+    pub completion_tokens: u32,
+}
+
+impl Default for ThroughputConfig {
+    fn default() -> Self {
+        Self {
+            completion_tokens: 1000,
+        }
+    }
+}
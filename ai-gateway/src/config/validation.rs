@@ -1,8 +1,13 @@
+use std::fmt;
+
 use indexmap::IndexSet;
+use rust_decimal::Decimal;
+use serde::Serialize;
 use thiserror::Error;
 
 use crate::{
-    config::{Config, router::RouterConfig},
+    config::{Config, Error as ReadError, router::RouterConfig},
+    endpoints::EndpointType,
     types::{
         model_id::{ModelId, ModelName},
         provider::InferenceProvider,
@@ -10,6 +15,262 @@ use crate::{
     },
 };
 
+/// A problem found by validating a config.
+#[derive(Debug, Clone, Error, PartialEq, Eq)]
+pub enum ValidationError {
+    #[error(
+        "Balance weights for {} sum to {total}, but must sum to 1",
+        .endpoint.as_ref()
+    )]
+    WeightSum {
+        endpoint: EndpointType,
+        total: Decimal,
+    },
+
+    #[error(
+        "Provider {provider} is listed more than once in the balance config \
+         for {}",
+        .endpoint.as_ref()
+    )]
+    DuplicateProvider {
+        endpoint: EndpointType,
+        provider: InferenceProvider,
+    },
+
+    #[error(
+        "Model {model} is listed more than once in the balance config for {}",
+        .endpoint.as_ref()
+    )]
+    DuplicateModel {
+        endpoint: EndpointType,
+        model: ModelId,
+    },
+
+    #[error(
+        "Model mapping of {source_model} is unreachable: none of its targets \
+         are served by a provider the router balances across"
+    )]
+    UnreachableMapping { source_model: String },
+
+    #[error(
+        "Member {model} of model group {group} is declared for provider \
+         {provider}, but isn't one of its models"
+    )]
+    GroupMemberProvider {
+        group: String,
+        provider: InferenceProvider,
+        model: ModelId,
+    },
+
+    #[error(
+        "Rate limiting of {scope} is enabled, but no rate limit store is \
+         configured"
+    )]
+    RateLimitStoreNotConfigured { scope: &'static str },
+
+    #[error("Invalid router id: {0}")]
+    InvalidRouterId(String),
+}
+
+/// Every problem found by validating a config, so that they can all be
+/// fixed at once rather than one per attempt.
+#[derive(Debug, Default, Error)]
+pub struct ValidationErrors(Vec<(Option<RouterId>, ValidationError)>);
+
+impl ValidationErrors {
+    pub fn push(&mut self, error: ValidationError) {
+        self.0.push((None, error));
+    }
+
+    /// Adds the problems found in the config of `router_id`.
+    pub fn extend_router(&mut self, router_id: &RouterId, other: Self) {
+        self.0.extend(
+            other
+                .0
+                .into_iter()
+                .map(|(_, error)| (Some(router_id.clone()), error)),
+        );
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(
+        &self,
+    ) -> impl Iterator<Item = (Option<&RouterId>, &ValidationError)> {
+        self.0
+            .iter()
+            .map(|(router_id, error)| (router_id.as_ref(), error))
+    }
+
+    pub fn into_result(self) -> Result<(), Self> {
+        if self.is_empty() { Ok(()) } else { Err(self) }
+    }
+}
+
+impl ValidationError {
+    /// A stable identifier of the kind of problem, for tooling to match on.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::WeightSum { .. } => "balance-weight-sum",
+            Self::DuplicateProvider { .. } => "balance-duplicate-provider",
+            Self::DuplicateModel { .. } => "balance-duplicate-model",
+            Self::UnreachableMapping { .. } => "unreachable-model-mapping",
+            Self::GroupMemberProvider { .. } => "model-group-member-provider",
+            Self::RateLimitStoreNotConfigured { .. } => {
+                "rate-limit-store-not-configured"
+            }
+            Self::InvalidRouterId(_) => "invalid-router-id",
+        }
+    }
+
+    /// Where the problem is, relative to the router's config for problems
+    /// found in one.
+    fn path(&self) -> String {
+        match self {
+            Self::WeightSum { endpoint, .. } => {
+                format!("load-balance.{}", endpoint.as_ref())
+            }
+            Self::DuplicateProvider { endpoint, .. } => {
+                format!("load-balance.{}.providers", endpoint.as_ref())
+            }
+            Self::DuplicateModel { endpoint, .. } => {
+                format!("load-balance.{}.models", endpoint.as_ref())
+            }
+            Self::UnreachableMapping { source_model } => {
+                format!("model-mappings.{source_model}")
+            }
+            Self::GroupMemberProvider {
+                group, provider, ..
+            } => format!("model-groups.{group}.{provider}"),
+            Self::RateLimitStoreNotConfigured { scope } => match *scope {
+                "router" => "rate-limit".to_string(),
+                scope => format!("{scope}.rate-limit"),
+            },
+            Self::InvalidRouterId(router_id) => format!("routers.{router_id}"),
+        }
+    }
+
+    /// How the problem is usually fixed.
+    #[must_use]
+    pub fn suggestion(&self) -> &'static str {
+        match self {
+            Self::WeightSum { .. } => {
+                "adjust the weights so that they add up to exactly 1"
+            }
+            Self::DuplicateProvider { .. } | Self::DuplicateModel { .. } => {
+                "list each target once, combining the weights of duplicates"
+            }
+            Self::UnreachableMapping { .. } => {
+                "map the model to one served by a provider in the router's \
+                 load-balance config, or remove the mapping"
+            }
+            Self::GroupMemberProvider { .. } => {
+                "declare each member under the provider in its model id, such \
+                 as `openai: openai/gpt-4o-mini`"
+            }
+            Self::RateLimitStoreNotConfigured { .. } => {
+                "configure `rate-limit-store`, or a `store` in the rate limit \
+                 config"
+            }
+            Self::InvalidRouterId(_) => {
+                "use 1 to 12 letters, digits, `-` or `_` for router ids"
+            }
+        }
+    }
+}
+
+/// A problem with a config, in a form for tooling to consume.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Issue {
+    pub code: &'static str,
+    /// Dotted path of the offending config key, if known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub suggestion: Option<&'static str>,
+}
+
+impl From<&ReadError> for Issue {
+    fn from(error: &ReadError) -> Self {
+        let (code, path) = match error {
+            ReadError::Source(_) => ("config-source", None),
+            ReadError::InputConfigDeserialization(e) => {
+                ("invalid-input-config", Some(e.path().to_string()))
+            }
+            ReadError::MergedConfigDeserialization(e) => {
+                ("invalid-config", Some(e.path().to_string()))
+            }
+            ReadError::UrlParse(_) => ("invalid-url", None),
+        };
+        Self {
+            code,
+            // `.` is the path of the root, which doesn't help locate anything
+            path: path.filter(|path| path != "."),
+            message: error.to_string(),
+            suggestion: None,
+        }
+    }
+}
+
+/// The outcome of validating a config.
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Report {
+    pub valid: bool,
+    pub errors: Vec<Issue>,
+}
+
+impl Report {
+    #[must_use]
+    pub fn new(errors: Vec<Issue>) -> Self {
+        Self {
+            valid: errors.is_empty(),
+            errors,
+        }
+    }
+}
+
+impl ValidationErrors {
+    #[must_use]
+    pub fn issues(&self) -> Vec<Issue> {
+        self.0
+            .iter()
+            .map(|(router_id, error)| {
+                let path = match router_id {
+                    Some(router_id) => {
+                        format!("routers.{router_id}.{}", error.path())
+                    }
+                    None => error.path(),
+                };
+                Issue {
+                    code: error.code(),
+                    path: Some(path),
+                    message: error.to_string(),
+                    suggestion: Some(error.suggestion()),
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} problem(s) found", self.0.len())?;
+        for (router_id, error) in &self.0 {
+            match router_id {
+                Some(router_id) => write!(f, "\n  - router {router_id}: ")?,
+                None => write!(f, "\n  - ")?,
+            }
+            write!(f, "{error}")?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ModelMappingValidationError {
     #[error(
@@ -76,6 +337,11 @@ impl Config {
             // For each provider this router might route to
             for target_provider in &router_providers {
                 let target_provider_config = &self.providers[target_provider];
+                if target_provider_config.models.is_empty() {
+                    // custom providers which don't list their models are sent
+                    // any model
+                    continue;
+                }
 
                 let target_models = target_provider_config
                     .models
@@ -198,4 +464,62 @@ mod tests {
             Err(ModelMappingValidationError::NoValidMapping { .. })
         ));
     }
+
+    #[test]
+    fn custom_providers_without_models_are_sent_any_model() {
+        let yaml = r"
+providers:
+  openai:
+    models:
+      - gpt-4o
+    base-url: https://api.openai.com
+  my-vllm:
+    base-url: http://vllm.internal:8000
+routers:
+  my-router:
+    load-balance:
+      chat:
+        strategy: provider-weighted
+        providers:
+          - provider: openai
+            weight: '0.5'
+          - provider: my-vllm
+            weight: '0.5'
+";
+        let config = serde_yml::from_str::<Config>(yaml).unwrap();
+        assert!(config.validate_model_mappings().is_ok());
+    }
+
+    #[test]
+    fn issues_locate_problems_in_routers() {
+        let mut router_errors = ValidationErrors::default();
+        router_errors.push(ValidationError::WeightSum {
+            endpoint: EndpointType::Chat,
+            total: Decimal::new(8, 1),
+        });
+        let mut errors = ValidationErrors::default();
+        errors.push(ValidationError::RateLimitStoreNotConfigured {
+            scope: "global",
+        });
+        errors.extend_router(
+            &RouterId::Named(CompactString::new("my-router")),
+            router_errors,
+        );
+
+        let issues = errors.issues();
+        assert_eq!(
+            issues
+                .iter()
+                .map(|issue| (issue.code, issue.path.as_deref()))
+                .collect::<Vec<_>>(),
+            [
+                ("rate-limit-store-not-configured", Some("global.rate-limit")),
+                (
+                    "balance-weight-sum",
+                    Some("routers.my-router.load-balance.chat")
+                ),
+            ]
+        );
+        assert!(issues.iter().all(|issue| issue.suggestion.is_some()));
+    }
 }
@@ -0,0 +1,36 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+/// A request and response filter compiled to WASM. Experimental, and only
+/// available in builds with the `wasm-filters` feature.
+///
+/// The module must export its `memory`, an `alloc(len: i32) -> i32`
+/// function, and at least one of `on_request` and `on_response`, both
+/// `(ptr: i32, len: i32) -> i64`. They're called with a JSON description of
+/// the request or response, and return the location of a JSON verdict in
+/// memory, packed as `ptr << 32 | len`. Modules can't import anything from
+/// the gateway.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct WasmFilterConfig {
+    pub path: PathBuf,
+    /// Instructions a filter may execute per request or response, roughly.
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+    /// Maximum memory of a filter, in bytes.
+    #[serde(default = "default_max_memory")]
+    pub max_memory: usize,
+    /// Let requests through when the filter fails, rather than failing
+    /// them.
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+fn default_fuel() -> u64 {
+    10_000_000
+}
+
+fn default_max_memory() -> usize {
+    16 * 1024 * 1024
+}
@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+/// Notifications of a router's provider health events, sent to webhooks.
+///
+/// Notifications are sent when providers are removed from or restored to
+/// the router's load balancer by the health or rate limit monitors, and
+/// when a provider's error budget burn crosses one of `burn-thresholds`.
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct WebhooksConfig {
+    pub endpoints: Vec<WebhookEndpointConfig>,
+    /// Identical notifications within this window are only sent once.
+    #[serde(with = "humantime_serde")]
+    pub dedup_window: Duration,
+    /// Maximum notifications per minute. Notifications beyond it are
+    /// dropped, so that a flapping provider doesn't flood a channel.
+    pub max_per_minute: u32,
+    /// Fractions of the health monitor's error ratio threshold, the error
+    /// budget, at which a notification is sent as a provider's error ratio
+    /// rises past them.
+    pub burn_thresholds: Vec<Decimal>,
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        Self {
+            endpoints: Vec::new(),
+            dedup_window: Duration::from_secs(5 * 60),
+            max_per_minute: 10,
+            burn_thresholds: vec![Decimal::new(5, 1), Decimal::new(9, 1)],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct WebhookEndpointConfig {
+    pub url: Url,
+    #[serde(default)]
+    pub format: WebhookFormat,
+}
+
+#[derive(
+    Debug, Default, Clone, Copy, Deserialize, Serialize, Eq, PartialEq, Hash,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum WebhookFormat {
+    /// A JSON object describing the event.
+    #[default]
+    Generic,
+    /// A Slack incoming webhook message.
+    Slack,
+}
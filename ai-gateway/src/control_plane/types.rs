@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use ts_rs::TS;
 
-use crate::types::{org::OrgId, user::UserId};
+use crate::types::{org::OrgId, priority::Priority, user::UserId};
 
 /// Computes the hash of an API key for storage and lookup in the control plane.
 /// This function adds a "Bearer " prefix to the key before hashing to match
@@ -51,6 +51,25 @@ pub struct Key {
     pub key_hash: String,
     pub owner_id: UserId,
     pub organization_id: OrgId,
+    /// The tier whose policy caps the key's requests, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(default)]
+    #[ts(optional)]
+    pub tier: Option<String>,
+    /// The highest priority the key's requests may ask for, if not
+    /// [`Priority::Normal`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[sqlx(skip)]
+    #[ts(optional)]
+    pub max_priority: Option<Priority>,
+}
+
+impl Key {
+    /// The highest priority the key's requests may ask for.
+    #[must_use]
+    pub fn max_priority(&self) -> Priority {
+        self.max_priority.unwrap_or_default()
+    }
 }
 
 #[derive(TS, Serialize, Deserialize, Debug, Clone)]
@@ -87,6 +106,8 @@ impl crate::tests::TestDefault for ControlPlaneState {
                 key_hash: key_hash.clone(),
                 owner_id: UserId::new(user_id),
                 organization_id: OrgId::new(organization_id),
+                tier: None,
+                max_priority: None,
             }],
         }
     }
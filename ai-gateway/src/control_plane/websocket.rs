@@ -24,8 +24,8 @@ use super::{
 use crate::{
     app_state::AppState,
     config::{
-        control_plane::ControlPlaneConfig, helicone::HeliconeConfig,
-        retry::RetryConfig,
+        control_plane::ControlPlaneConfig, deployment::DeploymentInfo,
+        helicone::HeliconeConfig, retry::RetryConfig,
     },
     error::{init::InitError, runtime::RuntimeError},
 };
@@ -93,8 +93,14 @@ impl IntoClientRequest for &HeliconeConfig {
 
 async fn connect_async_and_split(
     helicone_config: &HeliconeConfig,
+    deployment: &DeploymentInfo,
 ) -> Result<WebsocketChannel, InitError> {
-    let (tx, rx) = connect_async(helicone_config)
+    let mut request = helicone_config
+        .into_client_request()
+        .map_err(|e| InitError::WebsocketConnection(Box::new(e)))?;
+    // identifies this instance among the fleet in the control plane
+    request.headers_mut().extend(deployment.headers());
+    let (tx, rx) = connect_async(request)
         .await
         .map_err(|e| InitError::WebsocketConnection(Box::new(e)))?
         .0
@@ -108,6 +114,7 @@ async fn connect_async_and_split(
 
 async fn connect_with_retry(
     helicone_config: &HeliconeConfig,
+    deployment: &DeploymentInfo,
     retry_config: &RetryConfig,
 ) -> Result<WebsocketChannel, InitError> {
     match retry_config {
@@ -116,6 +123,7 @@ async fn connect_with_retry(
             max_delay,
             max_retries,
             factor,
+            ..
         } => {
             let retry_strategy = ExponentialBuilder::default()
                 .with_max_delay(*max_delay)
@@ -128,7 +136,7 @@ async fn connect_with_retry(
                 )
                 .with_jitter()
                 .build();
-            (|| async { connect_async_and_split(helicone_config).await })
+            (|| async { connect_async_and_split(helicone_config, deployment).await })
             .retry(retry_strategy)
             .sleep(tokio::time::sleep)
             .when(|e: &InitError| {
@@ -144,12 +152,14 @@ async fn connect_with_retry(
                 }
             }).await
         }
-        RetryConfig::Constant { delay, max_retries } => {
+        RetryConfig::Constant {
+            delay, max_retries, ..
+        } => {
             let retry_strategy = ConstantBuilder::default()
                 .with_max_times(usize::from(*max_retries))
                 .with_delay(*delay)
                 .build();
-            (|| async { connect_async_and_split(helicone_config).await })
+            (|| async { connect_async_and_split(helicone_config, deployment).await })
             .retry(retry_strategy)
             .sleep(tokio::time::sleep)
             .when(|e: &InitError| {
@@ -170,8 +180,12 @@ async fn connect_with_retry(
 
 impl ControlPlaneClient {
     async fn reconnect_websocket(&mut self) -> Result<(), InitError> {
-        let channel =
-            connect_with_retry(&self.config, &self.retry_config).await?;
+        let channel = connect_with_retry(
+            &self.config,
+            &self.app_state.0.deployment,
+            &self.retry_config,
+        )
+        .await?;
         self.channel = channel;
         tracing::info!("Successfully reconnected to control plane");
         Ok(())
@@ -183,8 +197,12 @@ impl ControlPlaneClient {
         control_plane_config: ControlPlaneConfig,
         app_state: AppState,
     ) -> Result<Self, InitError> {
-        let channel =
-            connect_with_retry(&config, &control_plane_config.retry).await?;
+        let channel = connect_with_retry(
+            &config,
+            &app_state.0.deployment,
+            &control_plane_config.retry,
+        )
+        .await?;
         Ok(Self {
             channel,
             config,
@@ -7,11 +7,7 @@ use std::{
 use futures::future::BoxFuture;
 use tokio::sync::mpsc::Receiver;
 use tokio_stream::wrappers::ReceiverStream;
-use tower::{
-    Service,
-    discover::Change,
-    load::{CompleteOnResponse, PeakEwmaDiscover},
-};
+use tower::{Service, discover::Change};
 
 use crate::{
     app_state::AppState,
@@ -19,6 +15,7 @@ use crate::{
     discover::{
         ServiceMap,
         dispatcher::{DispatcherDiscovery, factory::DispatcherDiscoverFactory},
+        model::shared_ewma::{PreferThroughput, SharedPeakEwmaDiscover},
     },
     dispatcher::{Dispatcher, DispatcherService},
     endpoints::EndpointType,
@@ -98,7 +95,7 @@ impl DispatcherDiscovery<Key> {
 impl Service<Receiver<Change<Key, DispatcherService>>>
     for DispatcherDiscoverFactory
 {
-    type Response = PeakEwmaDiscover<DispatcherDiscovery<Key>>;
+    type Response = SharedPeakEwmaDiscover<DispatcherDiscovery<Key>>;
     type Error = InitError;
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
@@ -124,11 +121,17 @@ impl Service<Receiver<Change<Key, DispatcherService>>>
                 rx,
             )
             .await?;
-            let discovery = PeakEwmaDiscover::new(
+            let discovery = SharedPeakEwmaDiscover::new(
                 discovery,
                 app_state.0.config.discover.default_rtt,
                 app_state.0.config.discover.discover_decay,
-                CompleteOnResponse::default(),
+                app_state.0.state_sync.clone(),
+                router_config.prefer_throughput.as_ref().map(|config| {
+                    PreferThroughput {
+                        throughputs: app_state.0.throughputs.clone(),
+                        completion_tokens: config.completion_tokens,
+                    }
+                }),
             );
 
             Ok(discovery)
@@ -1,2 +1,3 @@
 pub mod key;
+pub mod shared_ewma;
 pub mod weighted_key;
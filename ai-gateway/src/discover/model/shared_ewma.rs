@@ -0,0 +1,462 @@
+//! A peak-EWMA load metric which takes the latencies observed by other
+//! gateway replicas into account.
+//!
+//! This mirrors [`tower::load::PeakEwma`], but additionally publishes the
+//! local latency estimate via [`StateSync`] and blends in the estimates
+//! published by other replicas when computing the load of a service.
+//! Routers preferring throughput also add the time it takes to stream a
+//! typical completion at the model's observed tokens per second.
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::{Future, Stream, ready};
+use pin_project_lite::pin_project;
+use tower::{
+    Service,
+    discover::{Change, Discover},
+    load::Load,
+};
+
+use crate::{
+    discover::{model::key::Key, monitor::state_sync::StateSync},
+    endpoints::EndpointType,
+    metrics::throughput::Throughputs,
+    types::model_id::ModelId,
+};
+
+const NANOS_PER_MILLI: f64 = 1_000_000.0;
+
+fn nanos(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1_000_000_000.0
+}
+
+/// Makes a [`SharedPeakEwma`] prefer the models with the highest
+/// throughput for long generations.
+#[derive(Debug, Clone)]
+pub struct PreferThroughput {
+    pub throughputs: Throughputs,
+    /// The typical length of a completion.
+    pub completion_tokens: u32,
+}
+
+/// The load of a [`SharedPeakEwma`] service.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Cost(f64);
+
+#[derive(Debug)]
+struct RttEstimate {
+    update_at: Instant,
+    rtt_ns: f64,
+    /// Whether a response has been observed by this replica yet. Until then,
+    /// the estimate is just the configured default.
+    observed: bool,
+    last_published: Option<Instant>,
+}
+
+impl RttEstimate {
+    fn new(default_rtt: Duration) -> Self {
+        Self {
+            update_at: Instant::now(),
+            rtt_ns: nanos(default_rtt),
+            observed: false,
+            last_published: None,
+        }
+    }
+
+    /// Decays the estimate towards `rtt_ns`, or jumps straight to it if it is
+    /// higher than the current estimate.
+    fn update(&mut self, rtt_ns: f64, decay_ns: f64, now: Instant) -> f64 {
+        if self.rtt_ns < rtt_ns {
+            self.rtt_ns = rtt_ns;
+        } else {
+            let elapsed = nanos(now.saturating_duration_since(self.update_at));
+            let decay = (-elapsed / decay_ns).exp();
+            let recency = 1.0 - decay;
+            self.rtt_ns = self.rtt_ns * decay + rtt_ns * recency;
+        }
+        self.update_at = now;
+        self.rtt_ns
+    }
+
+    /// Returns whether the estimate is due to be published again.
+    fn should_publish(&mut self, interval: Duration, now: Instant) -> bool {
+        let due = self
+            .last_published
+            .is_none_or(|last| now.saturating_duration_since(last) >= interval);
+        if due {
+            self.last_published = Some(now);
+        }
+        due
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    model: ModelId,
+    endpoint_type: EndpointType,
+    state_sync: Option<StateSync>,
+    throughput: Option<PreferThroughput>,
+    decay_ns: f64,
+    estimate: Mutex<RttEstimate>,
+}
+
+impl Shared {
+    /// The time it takes to stream a typical completion, if the router
+    /// prefers throughput and the model's throughput has been observed.
+    /// Models whose throughput is unknown are thus tried early on.
+    fn generation_ns(&self) -> Option<f64> {
+        let throughput = self.throughput.as_ref()?;
+        let provider = self.model.inference_provider()?;
+        let tokens_per_second =
+            throughput.throughputs.get(&provider, &self.model)?;
+        Some(
+            f64::from(throughput.completion_tokens) / tokens_per_second
+                * 1_000_000_000.0,
+        )
+    }
+
+    fn record(&self, sent_at: Instant) {
+        let now = Instant::now();
+        let rtt_ns = nanos(now.saturating_duration_since(sent_at));
+        let mut estimate =
+            self.estimate.lock().expect("rtt estimate lock poisoned");
+        let rtt_ns = estimate.update(rtt_ns, self.decay_ns, now);
+        estimate.observed = true;
+        let Some(state_sync) = self.state_sync.as_ref() else {
+            return;
+        };
+        if estimate.should_publish(state_sync.latency_publish_interval(), now) {
+            drop(estimate);
+            state_sync.report_latency(
+                &self.model,
+                self.endpoint_type,
+                Duration::from_secs_f64(rtt_ns / 1_000_000_000.0),
+            );
+        }
+    }
+}
+
+/// Wraps a service with a peak-EWMA load metric that is shared with other
+/// replicas.
+#[derive(Debug)]
+pub struct SharedPeakEwma<S> {
+    service: S,
+    /// Every in-flight request holds a reference to this, so the strong count
+    /// tells us how many requests are pending.
+    shared: Arc<Shared>,
+}
+
+impl<S> SharedPeakEwma<S> {
+    fn new(
+        service: S,
+        key: &Key,
+        default_rtt: Duration,
+        decay_ns: f64,
+        state_sync: Option<StateSync>,
+        throughput: Option<PreferThroughput>,
+    ) -> Self {
+        Self {
+            service,
+            shared: Arc::new(Shared {
+                model: key.model_id.clone(),
+                endpoint_type: key.endpoint_type,
+                state_sync,
+                throughput,
+                decay_ns,
+                estimate: Mutex::new(RttEstimate::new(default_rtt)),
+            }),
+        }
+    }
+}
+
+impl<S, Request> Service<Request> for SharedPeakEwma<S>
+where
+    S: Service<Request>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request) -> Self::Future {
+        ResponseFuture {
+            inner: self.service.call(request),
+            sent_at: Instant::now(),
+            shared: Some(Arc::clone(&self.shared)),
+        }
+    }
+}
+
+impl<S> Load for SharedPeakEwma<S> {
+    type Metric = Cost;
+
+    #[allow(clippy::cast_precision_loss)]
+    fn load(&self) -> Self::Metric {
+        let pending = Arc::strong_count(&self.shared) - 1;
+        let (local_ns, observed) = {
+            let mut estimate = self
+                .shared
+                .estimate
+                .lock()
+                .expect("rtt estimate lock poisoned");
+            // decay the estimate as if we'd just received a zero latency
+            // response, just like tower's peak ewma does
+            let local_ns =
+                estimate.update(0.0, self.shared.decay_ns, Instant::now());
+            (local_ns, estimate.observed)
+        };
+        let remote_ns =
+            self.shared.state_sync.as_ref().and_then(|state_sync| {
+                state_sync
+                    .remote_latency(
+                        &self.shared.model,
+                        self.shared.endpoint_type,
+                    )
+                    .map(nanos)
+            });
+        let rtt_ns = match remote_ns {
+            // until we've seen a response ourselves, trust the other replicas
+            Some(remote_ns) if !observed => remote_ns,
+            Some(remote_ns) => (local_ns + remote_ns) / 2.0,
+            None => local_ns,
+        };
+        let generation_ns = self.shared.generation_ns();
+        let rtt_ns = rtt_ns + generation_ns.unwrap_or_default();
+        let cost = if pending == 0 {
+            rtt_ns
+        } else {
+            rtt_ns * (pending + 1) as f64
+        };
+        tracing::trace!(
+            model = %self.shared.model,
+            pending,
+            local_ms = local_ns / NANOS_PER_MILLI,
+            remote_ms = remote_ns.map(|ns| ns / NANOS_PER_MILLI),
+            generation_ms = generation_ns.map(|ns| ns / NANOS_PER_MILLI),
+            cost,
+            "shared peak ewma load"
+        );
+        Cost(cost)
+    }
+}
+
+pin_project! {
+    #[derive(Debug)]
+    pub struct ResponseFuture<F> {
+        #[pin]
+        inner: F,
+        sent_at: Instant,
+        shared: Option<Arc<Shared>>,
+    }
+}
+
+impl<F, T, E> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<T, E>>,
+{
+    type Output = Result<T, E>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result = ready!(this.inner.poll(cx));
+        if let Some(shared) = this.shared.take() {
+            shared.record(*this.sent_at);
+        }
+        Poll::Ready(result)
+    }
+}
+
+pin_project! {
+    /// Wraps a [`Discover`] of model latency services with
+    /// [`SharedPeakEwma`].
+    #[derive(Debug)]
+    pub struct SharedPeakEwmaDiscover<D> {
+        #[pin]
+        discover: D,
+        default_rtt: Duration,
+        decay_ns: f64,
+        state_sync: Option<StateSync>,
+        throughput: Option<PreferThroughput>,
+    }
+}
+
+impl<D> SharedPeakEwmaDiscover<D> {
+    /// Latencies are only shared if `state_sync` is given and configured to
+    /// share them; otherwise this behaves like [`tower::load::PeakEwma`].
+    #[must_use]
+    pub fn new(
+        discover: D,
+        default_rtt: Duration,
+        decay: Duration,
+        state_sync: Option<StateSync>,
+        throughput: Option<PreferThroughput>,
+    ) -> Self {
+        Self {
+            discover,
+            default_rtt,
+            decay_ns: nanos(decay),
+            state_sync: state_sync.filter(StateSync::shares_latency),
+            throughput,
+        }
+    }
+}
+
+impl<D> Stream for SharedPeakEwmaDiscover<D>
+where
+    D: Discover<Key = Key>,
+{
+    type Item = Result<Change<Key, SharedPeakEwma<D::Service>>, D::Error>;
+
+    fn poll_next(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = self.project();
+        let change =
+            match ready!(this.discover.poll_discover(cx)).transpose()? {
+                None => return Poll::Ready(None),
+                Some(Change::Remove(key)) => Change::Remove(key),
+                Some(Change::Insert(key, service)) => {
+                    let service = SharedPeakEwma::new(
+                        service,
+                        &key,
+                        *this.default_rtt,
+                        *this.decay_ns,
+                        this.state_sync.clone(),
+                        this.throughput.clone(),
+                    );
+                    Change::Insert(key, service)
+                }
+            };
+        Poll::Ready(Some(Ok(change)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, str::FromStr};
+
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::config::state_sync::StateSyncConfig;
+
+    fn key() -> Key {
+        Key::new(
+            ModelId::from_str("openai/gpt-4o").unwrap(),
+            EndpointType::Chat,
+        )
+    }
+
+    fn service(
+        state_sync: Option<StateSync>,
+    ) -> SharedPeakEwma<impl Service<(), Response = (), Error = Infallible>>
+    {
+        service_preferring(state_sync, None)
+    }
+
+    fn service_preferring(
+        state_sync: Option<StateSync>,
+        throughput: Option<PreferThroughput>,
+    ) -> SharedPeakEwma<impl Service<(), Response = (), Error = Infallible>>
+    {
+        SharedPeakEwma::new(
+            tower::service_fn(|()| async { Ok::<_, Infallible>(()) }),
+            &key(),
+            Duration::from_millis(10),
+            nanos(Duration::from_secs(10)),
+            state_sync,
+            throughput,
+        )
+    }
+
+    #[test]
+    fn peak_is_taken_immediately() {
+        let mut estimate = RttEstimate::new(Duration::from_millis(10));
+        let now = Instant::now();
+        let rtt_ns =
+            estimate.update(nanos(Duration::from_millis(100)), 1.0, now);
+        assert!((rtt_ns - nanos(Duration::from_millis(100))).abs() < 1.0);
+    }
+
+    #[test]
+    fn publishing_is_throttled() {
+        let mut estimate = RttEstimate::new(Duration::from_millis(10));
+        let now = Instant::now();
+        let interval = Duration::from_secs(5);
+        assert!(estimate.should_publish(interval, now));
+        assert!(!estimate.should_publish(interval, now));
+        assert!(estimate.should_publish(interval, now + interval));
+    }
+
+    #[tokio::test]
+    async fn pending_requests_increase_cost() {
+        let mut svc = service(None);
+        let idle = svc.load();
+        let _pending = svc.ready().await.unwrap().call(());
+        assert!(svc.load() > idle);
+    }
+
+    #[test]
+    fn remote_latency_used_until_observed_locally() {
+        let state_sync = StateSync::new(&StateSyncConfig::default()).unwrap();
+        let key = key();
+        state_sync.record_remote_latency(
+            uuid::Uuid::now_v7(),
+            key.model_id.clone(),
+            key.endpoint_type,
+            Duration::from_secs(2),
+        );
+        let svc = service(Some(state_sync));
+        let Cost(cost) = svc.load();
+        assert!((cost - nanos(Duration::from_secs(2))).abs() < 1.0);
+    }
+
+    #[tokio::test]
+    async fn remote_latency_is_blended_with_local() {
+        let state_sync = StateSync::new(&StateSyncConfig::default()).unwrap();
+        let key = key();
+        state_sync.record_remote_latency(
+            uuid::Uuid::now_v7(),
+            key.model_id.clone(),
+            key.endpoint_type,
+            Duration::from_secs(2),
+        );
+        let mut svc = service(Some(state_sync));
+        svc.ready().await.unwrap().call(()).await.unwrap();
+        let Cost(cost) = svc.load();
+        // the local estimate is tiny, so the blend is roughly half the remote
+        // latency
+        assert!(cost < nanos(Duration::from_secs(2)));
+        assert!(cost >= nanos(Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn generation_time_is_added_when_preferring_throughput() {
+        let throughputs = Throughputs::default();
+        let model = key().model_id;
+        let provider = model.inference_provider().unwrap();
+        let svc = service_preferring(
+            None,
+            Some(PreferThroughput {
+                throughputs: throughputs.clone(),
+                completion_tokens: 1000,
+            }),
+        );
+        let Cost(unobserved) = svc.load();
+        assert!(unobserved < nanos(Duration::from_secs(1)));
+        throughputs.observe(&provider, &model, 100.0);
+        let Cost(cost) = svc.load();
+        assert!(cost >= nanos(Duration::from_secs(10)));
+    }
+}
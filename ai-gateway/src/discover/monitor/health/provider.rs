@@ -24,6 +24,7 @@ use crate::{
         model::{
             key::Key as ModelKey, weighted_key::WeightedKey as ModelWeightedKey,
         },
+        monitor::webhook::{Event, Reason, Webhooks},
         provider::{
             key::Key as ProviderKey,
             weighted_key::WeightedKey as ProviderWeightedKey,
@@ -150,6 +151,11 @@ async fn check_provider_weighted_monitor(
 
                     if !is_healthy && !was_unhealthy {
                         trace!(provider = ?provider, endpoint_type = ?endpoint_type, "Provider became unhealthy, removing");
+                        inner.notify(Event::ProviderRemoved {
+                            provider: provider.clone(),
+                            endpoint: *endpoint_type,
+                            reason: Reason::Unhealthy,
+                        });
                         if let Err(e) =
                             inner.tx.send(Change::Remove(key.clone())).await
                         {
@@ -158,6 +164,11 @@ async fn check_provider_weighted_monitor(
                         inner.unhealthy_keys.insert(key);
                     } else if is_healthy && was_unhealthy {
                         trace!(provider = ?provider, endpoint_type = ?endpoint_type, "Provider became healthy, adding back");
+                        inner.notify(Event::ProviderRestored {
+                            provider: provider.clone(),
+                            endpoint: *endpoint_type,
+                            reason: Reason::Unhealthy,
+                        });
                         inner.unhealthy_keys.remove(&key);
 
                         let service = Dispatcher::new(
@@ -246,6 +257,11 @@ async fn check_model_weighted_monitor(
 
                     if !is_healthy && !was_unhealthy {
                         trace!(provider = ?provider, endpoint_type = ?endpoint_type, "Provider became unhealthy, removing");
+                        inner.notify(Event::ProviderRemoved {
+                            provider: provider.clone(),
+                            endpoint: *endpoint_type,
+                            reason: Reason::Unhealthy,
+                        });
                         let all_models_of_unhealthy_provider = models
                             .iter()
                             .filter(|m| {
@@ -298,6 +314,11 @@ async fn check_model_weighted_monitor(
                         }
                     } else if is_healthy && was_unhealthy {
                         trace!(provider = ?provider, endpoint_type = ?endpoint_type, "Provider became healthy, adding back");
+                        inner.notify(Event::ProviderRestored {
+                            provider: provider.clone(),
+                            endpoint: *endpoint_type,
+                            reason: Reason::Unhealthy,
+                        });
                         let all_models_of_now_healthy_provider = models
                             .iter()
                             .filter(|m| {
@@ -397,6 +418,11 @@ async fn check_provider_latency_monitor(
 
                     if !is_healthy && !was_unhealthy {
                         trace!(provider = ?provider, endpoint_type = ?endpoint_type, "Provider became unhealthy, removing");
+                        inner.notify(Event::ProviderRemoved {
+                            provider: provider.clone(),
+                            endpoint: *endpoint_type,
+                            reason: Reason::Unhealthy,
+                        });
                         if let Err(e) =
                             inner.tx.send(Change::Remove(key.clone())).await
                         {
@@ -405,6 +431,11 @@ async fn check_provider_latency_monitor(
                         inner.unhealthy_keys.insert(key);
                     } else if is_healthy && was_unhealthy {
                         trace!(provider = ?provider, endpoint_type = ?endpoint_type, "Provider became healthy, adding back");
+                        inner.notify(Event::ProviderRestored {
+                            provider: provider.clone(),
+                            endpoint: *endpoint_type,
+                            reason: Reason::Unhealthy,
+                        });
                         inner.unhealthy_keys.remove(&key);
 
                         let service = Dispatcher::new(
@@ -479,6 +510,11 @@ async fn check_model_latency_monitor(
 
                     if !is_healthy && !was_unhealthy {
                         trace!(provider = ?provider, endpoint_type = ?endpoint_type, "Provider became unhealthy, removing");
+                        inner.notify(Event::ProviderRemoved {
+                            provider: provider.clone(),
+                            endpoint: *endpoint_type,
+                            reason: Reason::Unhealthy,
+                        });
                         let all_models_of_unhealthy_provider = models
                             .iter()
                             .filter(|m| {
@@ -521,6 +557,11 @@ async fn check_model_latency_monitor(
                         }
                     } else if is_healthy && was_unhealthy {
                         trace!(provider = ?provider, endpoint_type = ?endpoint_type, "Provider became healthy, adding back");
+                        inner.notify(Event::ProviderRestored {
+                            provider: provider.clone(),
+                            endpoint: *endpoint_type,
+                            reason: Reason::Unhealthy,
+                        });
                         let all_models_of_now_healthy_provider = models
                             .iter()
                             .filter(|m| {
@@ -598,6 +639,7 @@ pub struct ProviderMonitorInner<K> {
     router_config: Arc<RouterConfig>,
     app_state: AppState,
     unhealthy_keys: HashSet<K>,
+    webhooks: Option<Arc<Webhooks>>,
 }
 
 impl<K> ProviderMonitorInner<K> {
@@ -607,12 +649,20 @@ impl<K> ProviderMonitorInner<K> {
         router_config: Arc<RouterConfig>,
         app_state: AppState,
     ) -> Self {
+        let webhooks = app_state.webhooks(&router_id, &router_config);
         Self {
             tx,
             router_id,
             router_config,
             app_state,
             unhealthy_keys: HashSet::default(),
+            webhooks,
+        }
+    }
+
+    fn notify(&self, event: Event) {
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.notify(event);
         }
     }
 
@@ -624,6 +674,7 @@ impl<K> ProviderMonitorInner<K> {
         let config = self.app_state.config();
         let grace_period = config.discover.monitor.grace_period();
         let mut all_healthy = true;
+        let mut burn = 0.0_f64;
         for endpoint in provider_endpoints {
             let endpoint_metrics =
                 self.app_state.0.endpoint_metrics.health_metrics(endpoint)?;
@@ -638,11 +689,23 @@ impl<K> ProviderMonitorInner<K> {
 
             let errors = endpoint_metrics.remote_internal_error_count.total();
             let error_ratio = f64::from(errors) / f64::from(requests);
+            let error_threshold = config.discover.monitor.error_threshold();
+            burn = burn.max(error_ratio / error_threshold);
 
-            if error_ratio > config.discover.monitor.error_threshold() {
+            if error_ratio > error_threshold {
                 all_healthy = false;
             }
         }
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.observe_burn(provider, burn);
+        }
+
+        if let Some(state_sync) = self.app_state.0.state_sync.as_ref() {
+            state_sync.report_health(provider, all_healthy);
+            if state_sync.is_unhealthy_elsewhere(provider) {
+                return Ok(false);
+            }
+        }
 
         Ok(all_healthy)
     }
@@ -715,6 +778,17 @@ impl meltdown::Service for HealthMonitor {
 }
 
 impl AppState {
+    /// Checks the health of the providers of every router right away, rather
+    /// than at the next interval.
+    pub async fn check_provider_health(&self) {
+        let mut monitors = self.0.health_monitors.write().await;
+        for (router_id, monitor) in monitors.iter_mut() {
+            if let Err(e) = monitor.check_monitor().await {
+                error!(router_id = ?router_id, error = ?e, "Provider health monitor check failed");
+            }
+        }
+    }
+
     pub async fn add_provider_weighted_router_health_monitor(
         &self,
         router_id: RouterId,
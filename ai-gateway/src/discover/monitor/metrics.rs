@@ -110,3 +110,18 @@ impl EndpointMetrics {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::provider::InferenceProvider;
+
+    #[test]
+    fn embedded_providers_are_monitored() {
+        let registry = EndpointMetricsRegistry::new(&Config::default());
+        let together = InferenceProvider::Named("together".into());
+        for endpoint in together.endpoints() {
+            assert!(registry.health_metrics(endpoint).is_ok());
+        }
+    }
+}
@@ -1,3 +1,5 @@
 pub mod health;
 pub mod metrics;
 pub mod rate_limit;
+pub mod state_sync;
+pub mod webhook;
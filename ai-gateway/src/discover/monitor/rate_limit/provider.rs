@@ -27,6 +27,7 @@ use crate::{
         model::{
             key::Key as ModelKey, weighted_key::WeightedKey as ModelWeightedKey,
         },
+        monitor::webhook::{Event, Reason, Webhooks},
         provider::{
             key::Key as ProviderKey,
             weighted_key::WeightedKey as ProviderWeightedKey,
@@ -130,6 +131,7 @@ pub struct ProviderMonitorInner<K> {
     router_id: RouterId,
     router_config: Arc<RouterConfig>,
     app_state: AppState,
+    webhooks: Option<Arc<Webhooks>>,
 }
 
 impl<K> ProviderMonitorInner<K> {
@@ -139,11 +141,19 @@ impl<K> ProviderMonitorInner<K> {
         router_config: Arc<RouterConfig>,
         app_state: AppState,
     ) -> Self {
+        let webhooks = app_state.webhooks(&router_id, &router_config);
         Self {
             tx,
             router_id,
             router_config,
             app_state,
+            webhooks,
+        }
+    }
+
+    fn notify(&self, event: Event) {
+        if let Some(webhooks) = &self.webhooks {
+            webhooks.notify(event);
         }
     }
 }
@@ -186,6 +196,11 @@ impl ProviderMonitorInner<ProviderKey> {
                             "Removing rate-limited provider from P2C balancer"
                         );
 
+                        self.notify(Event::ProviderRemoved {
+                            provider: event.api_endpoint.provider(),
+                            endpoint: event.api_endpoint.endpoint_type(),
+                            reason: Reason::RateLimited,
+                        });
                         if let Err(e) = self.tx.send(Change::Remove(key.clone())).await {
                             error!(error = ?e, "Failed to send remove event for rate-limited provider");
                         }
@@ -235,6 +250,11 @@ impl ProviderMonitorInner<ProviderKey> {
                             );
                         })?;
 
+                    self.notify(Event::ProviderRestored {
+                        provider: api_endpoint.provider(),
+                        endpoint: api_endpoint.endpoint_type(),
+                        reason: Reason::RateLimited,
+                    });
                     self.tx.send(Change::Insert(key.clone(), service)).await.map_err(|e| {
                         error!(error = ?e, router_id = ?self.router_id, "Failed to send insert event for recovered provider");
                         RuntimeError::ChannelSendFailed
@@ -325,6 +345,11 @@ impl ProviderMonitorInner<ProviderWeightedKey> {
                             "Removing rate-limited provider from Weighted balancer"
                         );
 
+                        self.notify(Event::ProviderRemoved {
+                            provider: event.api_endpoint.provider(),
+                            endpoint: event.api_endpoint.endpoint_type(),
+                            reason: Reason::RateLimited,
+                        });
                         if let Err(e) = self.tx.send(Change::Remove(key.clone())).await {
                             error!(error = ?e, "Failed to send remove event for rate-limited provider");
                         }
@@ -382,6 +407,11 @@ impl ProviderMonitorInner<ProviderWeightedKey> {
                             "Failed to create dispatcher for recovered provider"
                         );
                     })?;
+                    self.notify(Event::ProviderRestored {
+                        provider: api_endpoint.provider(),
+                        endpoint: api_endpoint.endpoint_type(),
+                        reason: Reason::RateLimited,
+                    });
                     self.tx.send(Change::Insert(key.clone(), service))
                         .await
                         .map_err(|e| {
@@ -470,6 +500,11 @@ impl ProviderMonitorInner<ModelWeightedKey> {
                             "Removing rate-limited provider from Weighted balancer"
                         );
 
+                        self.notify(Event::ProviderRemoved {
+                            provider: event.api_endpoint.provider(),
+                            endpoint: event.api_endpoint.endpoint_type(),
+                            reason: Reason::RateLimited,
+                        });
                         if let Err(e) = self.tx.send(Change::Remove(key.clone())).await {
                             error!(error = ?e, "Failed to send remove event for rate-limited provider");
                         }
@@ -527,6 +562,11 @@ impl ProviderMonitorInner<ModelWeightedKey> {
                             "Failed to create dispatcher for recovered provider"
                         );
                     })?;
+                    self.notify(Event::ProviderRestored {
+                        provider: api_endpoint.provider(),
+                        endpoint: api_endpoint.endpoint_type(),
+                        reason: Reason::RateLimited,
+                    });
                     self.tx.send(Change::Insert(key.clone(), service))
                         .await
                         .map_err(|e| {
@@ -587,6 +627,11 @@ impl ProviderMonitorInner<ModelKey> {
                             "Removing rate-limited provider from Weighted balancer"
                         );
 
+                        self.notify(Event::ProviderRemoved {
+                            provider: event.api_endpoint.provider(),
+                            endpoint: event.api_endpoint.endpoint_type(),
+                            reason: Reason::RateLimited,
+                        });
                         if let Err(e) = self.tx.send(Change::Remove(key.clone())).await {
                             error!(error = ?e, "Failed to send remove event for rate-limited provider");
                         }
@@ -644,6 +689,11 @@ impl ProviderMonitorInner<ModelKey> {
                             "Failed to create dispatcher for recovered provider"
                         );
                     })?;
+                    self.notify(Event::ProviderRestored {
+                        provider: api_endpoint.provider(),
+                        endpoint: api_endpoint.endpoint_type(),
+                        reason: Reason::RateLimited,
+                    });
                     self.tx.send(Change::Insert(key.clone(), service))
                         .await
                         .map_err(|e| {
@@ -0,0 +1,601 @@
+//! Share provider rate limit, health, and latency events across gateway
+//! replicas.
+//!
+//! Without this, each replica independently has to discover that a provider
+//! is rate limiting us, failing, or slow. With it, the first replica to
+//! observe a rate limit or health transition publishes it to a Redis pub/sub
+//! channel, and every other replica applies it to its own load balancers.
+//! Model latency routers additionally publish their latency estimates
+//! periodically, which other replicas fold into their load metric.
+use std::{
+    sync::{Arc, Mutex, RwLock},
+    time::{Duration, Instant},
+};
+
+use futures::{StreamExt, future::BoxFuture};
+use meltdown::Token;
+use redis::{AsyncCommands, aio::ConnectionManager};
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use serde::{Deserialize, Serialize};
+use tokio::sync::OnceCell;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    config::state_sync::StateSyncConfig,
+    endpoints::{ApiEndpoint, EndpointType},
+    error::{init::InitError, runtime::RuntimeError},
+    types::{
+        model_id::ModelId, provider::InferenceProvider,
+        rate_limit::RateLimitEvent, router::RouterId,
+    },
+    utils::task,
+};
+
+/// How long to wait before resubscribing after losing the subscription.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum ProviderEvent {
+    RateLimited {
+        router_id: RouterId,
+        provider: InferenceProvider,
+        endpoint_type: EndpointType,
+        retry_after_seconds: Option<u64>,
+    },
+    Unhealthy {
+        provider: InferenceProvider,
+    },
+    Healthy {
+        provider: InferenceProvider,
+    },
+    Latency {
+        model: ModelId,
+        endpoint_type: EndpointType,
+        rtt_micros: u64,
+    },
+}
+
+impl ProviderEvent {
+    #[must_use]
+    pub fn rate_limited(
+        router_id: RouterId,
+        api_endpoint: &ApiEndpoint,
+        retry_after_seconds: Option<u64>,
+    ) -> Self {
+        Self::RateLimited {
+            router_id,
+            provider: api_endpoint.provider(),
+            endpoint_type: api_endpoint.endpoint_type(),
+            retry_after_seconds,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Envelope {
+    /// The replica that published the event, so that replicas can ignore
+    /// their own events.
+    origin: Uuid,
+    event: ProviderEvent,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct RemoteLatency {
+    rtt: Duration,
+    received_at: Instant,
+}
+
+/// Latest latency reported by each replica.
+type LatencyReports = HashMap<Uuid, RemoteLatency>;
+
+/// When the unhealthy report of each replica expires.
+type UnhealthyReports = HashMap<Uuid, Instant>;
+
+#[derive(Debug)]
+struct Inner {
+    client: redis::Client,
+    channel: String,
+    instance_id: Uuid,
+    unhealthy_ttl: Duration,
+    share_latency: bool,
+    latency_publish_interval: Duration,
+    latency_ttl: Duration,
+    /// Reconnects on its own if the connection is lost.
+    connection: OnceCell<ConnectionManager>,
+    /// Providers we've observed to be unhealthy from this replica.
+    locally_unhealthy: Mutex<HashSet<InferenceProvider>>,
+    /// Providers other replicas have reported as unhealthy, along with when
+    /// each replica's report expires.
+    remotely_unhealthy: RwLock<HashMap<InferenceProvider, UnhealthyReports>>,
+    /// The latest latency each other replica reported for a model.
+    remote_latencies: RwLock<HashMap<(ModelId, EndpointType), LatencyReports>>,
+}
+
+/// Handle used to publish provider events to, and query the state reported
+/// by, other gateway replicas.
+#[derive(Debug, Clone)]
+pub struct StateSync {
+    inner: Arc<Inner>,
+}
+
+impl StateSync {
+    pub fn new(config: &StateSyncConfig) -> Result<Self, InitError> {
+        let client =
+            redis::Client::open(config.redis.host_url.expose().clone())?;
+        Ok(Self {
+            inner: Arc::new(Inner {
+                client,
+                channel: config.channel.clone(),
+                instance_id: Uuid::now_v7(),
+                unhealthy_ttl: config.unhealthy_ttl,
+                share_latency: config.share_latency,
+                latency_publish_interval: config.latency_publish_interval,
+                latency_ttl: config.latency_ttl,
+                connection: OnceCell::new(),
+                locally_unhealthy: Mutex::default(),
+                remotely_unhealthy: RwLock::default(),
+                remote_latencies: RwLock::default(),
+            }),
+        })
+    }
+
+    /// Publish an event to other replicas in the background.
+    pub fn publish(&self, event: ProviderEvent) {
+        let envelope = Envelope {
+            origin: self.inner.instance_id,
+            event,
+        };
+        let payload = match serde_json::to_string(&envelope) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!(error = %e, "failed to serialize provider event");
+                return;
+            }
+        };
+        let inner = Arc::clone(&self.inner);
+        task::spawn("state-sync-publish", async move {
+            let result = async {
+                let mut connection = inner
+                    .connection
+                    .get_or_try_init(|| inner.client.get_connection_manager())
+                    .await?
+                    .clone();
+                connection
+                    .publish::<_, _, ()>(&inner.channel, payload)
+                    .await
+            }
+            .await;
+            if let Err(e) = result {
+                error!(error = %e, "failed to publish provider event");
+            }
+        });
+    }
+
+    /// Record the locally observed health of a provider, publishing an event
+    /// if it changed.
+    pub fn report_health(&self, provider: &InferenceProvider, healthy: bool) {
+        let changed = {
+            let mut locally_unhealthy = self
+                .inner
+                .locally_unhealthy
+                .lock()
+                .expect("state sync lock poisoned");
+            if healthy {
+                locally_unhealthy.remove(provider)
+            } else {
+                locally_unhealthy.insert(provider.clone())
+            }
+        };
+        if changed {
+            let provider = provider.clone();
+            self.publish(if healthy {
+                ProviderEvent::Healthy { provider }
+            } else {
+                ProviderEvent::Unhealthy { provider }
+            });
+        }
+    }
+
+    /// Whether another replica has recently reported the provider as
+    /// unhealthy.
+    #[must_use]
+    pub fn is_unhealthy_elsewhere(&self, provider: &InferenceProvider) -> bool {
+        let now = Instant::now();
+        self.inner
+            .remotely_unhealthy
+            .read()
+            .expect("state sync lock poisoned")
+            .get(provider)
+            .is_some_and(|reports| {
+                reports.values().any(|expires_at| now < *expires_at)
+            })
+    }
+
+    /// Whether latency estimates should be shared with other replicas.
+    #[must_use]
+    pub fn shares_latency(&self) -> bool {
+        self.inner.share_latency
+    }
+
+    #[must_use]
+    pub fn latency_publish_interval(&self) -> Duration {
+        self.inner.latency_publish_interval
+    }
+
+    /// Publish the locally observed latency estimate for a model.
+    pub fn report_latency(
+        &self,
+        model: &ModelId,
+        endpoint_type: EndpointType,
+        rtt: Duration,
+    ) {
+        self.publish(ProviderEvent::Latency {
+            model: model.clone(),
+            endpoint_type,
+            rtt_micros: u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX),
+        });
+    }
+
+    /// The mean of the latencies recently reported for a model by other
+    /// replicas, if any.
+    #[must_use]
+    pub fn remote_latency(
+        &self,
+        model: &ModelId,
+        endpoint_type: EndpointType,
+    ) -> Option<Duration> {
+        let now = Instant::now();
+        let remote_latencies = self
+            .inner
+            .remote_latencies
+            .read()
+            .expect("state sync lock poisoned");
+        let reports = remote_latencies.get(&(model.clone(), endpoint_type))?;
+        let (count, total) = reports
+            .values()
+            .filter(|report| {
+                now.duration_since(report.received_at) < self.inner.latency_ttl
+            })
+            .fold((0u32, Duration::ZERO), |(count, total), report| {
+                (count + 1, total + report.rtt)
+            });
+        (count > 0).then(|| total / count)
+    }
+
+    pub(crate) fn record_remote_latency(
+        &self,
+        origin: Uuid,
+        model: ModelId,
+        endpoint_type: EndpointType,
+        rtt: Duration,
+    ) {
+        let now = Instant::now();
+        let latency_ttl = self.inner.latency_ttl;
+        let mut remote_latencies = self
+            .inner
+            .remote_latencies
+            .write()
+            .expect("state sync lock poisoned");
+        let reports =
+            remote_latencies.entry((model, endpoint_type)).or_default();
+        // replicas come and go, so drop reports from those we haven't heard
+        // from in a while
+        reports.retain(|_, report| {
+            now.duration_since(report.received_at) < latency_ttl
+        });
+        reports.insert(
+            origin,
+            RemoteLatency {
+                rtt,
+                received_at: now,
+            },
+        );
+    }
+
+    async fn handle_event(
+        &self,
+        app_state: &AppState,
+        origin: Uuid,
+        event: ProviderEvent,
+    ) {
+        match event {
+            ProviderEvent::RateLimited {
+                router_id,
+                provider,
+                endpoint_type,
+                retry_after_seconds,
+            } => {
+                let Some(api_endpoint) = provider
+                    .endpoints()
+                    .into_iter()
+                    .find(|e| e.endpoint_type() == endpoint_type)
+                else {
+                    warn!(provider = %provider, endpoint_type = ?endpoint_type, "no endpoint for shared rate limit event");
+                    return;
+                };
+                let Ok(rate_limit_tx) =
+                    app_state.get_rate_limit_tx(&router_id).await
+                else {
+                    debug!(router_id = %router_id, "ignoring shared rate limit event for unknown router");
+                    return;
+                };
+                let event =
+                    RateLimitEvent::new(api_endpoint, retry_after_seconds);
+                if let Err(e) = rate_limit_tx.send(event).await {
+                    error!(error = %e, "failed to send shared rate limit event");
+                }
+            }
+            ProviderEvent::Unhealthy { provider } => {
+                self.mark_unhealthy_elsewhere(origin, provider);
+                // remove the provider from the load balancers right away,
+                // rather than at the next health check
+                app_state.check_provider_health().await;
+            }
+            ProviderEvent::Healthy { provider } => {
+                self.mark_healthy_elsewhere(origin, &provider);
+            }
+            ProviderEvent::Latency {
+                model,
+                endpoint_type,
+                rtt_micros,
+            } => {
+                if self.inner.share_latency {
+                    self.record_remote_latency(
+                        origin,
+                        model,
+                        endpoint_type,
+                        Duration::from_micros(rtt_micros),
+                    );
+                }
+            }
+        }
+    }
+
+    fn mark_unhealthy_elsewhere(
+        &self,
+        origin: Uuid,
+        provider: InferenceProvider,
+    ) {
+        let now = Instant::now();
+        let mut remotely_unhealthy = self
+            .inner
+            .remotely_unhealthy
+            .write()
+            .expect("state sync lock poisoned");
+        let reports = remotely_unhealthy.entry(provider).or_default();
+        // replicas come and go, so drop their expired reports
+        reports.retain(|_, expires_at| now < *expires_at);
+        reports.insert(origin, now + self.inner.unhealthy_ttl);
+    }
+
+    /// Clears the report of `origin` only, as other replicas may still see
+    /// the provider as unhealthy.
+    fn mark_healthy_elsewhere(
+        &self,
+        origin: Uuid,
+        provider: &InferenceProvider,
+    ) {
+        let mut remotely_unhealthy = self
+            .inner
+            .remotely_unhealthy
+            .write()
+            .expect("state sync lock poisoned");
+        if let Some(reports) = remotely_unhealthy.get_mut(provider) {
+            reports.remove(&origin);
+            if reports.is_empty() {
+                remotely_unhealthy.remove(provider);
+            }
+        }
+    }
+
+    async fn subscribe(
+        &self,
+        app_state: &AppState,
+    ) -> Result<(), redis::RedisError> {
+        let mut pubsub = self.inner.client.get_async_pubsub().await?;
+        pubsub.subscribe(&self.inner.channel).await?;
+        info!(channel = %self.inner.channel, "subscribed to provider events");
+        let mut messages = pubsub.on_message();
+        while let Some(message) = messages.next().await {
+            let payload = message.get_payload::<String>()?;
+            let envelope = match serde_json::from_str::<Envelope>(&payload) {
+                Ok(envelope) => envelope,
+                Err(e) => {
+                    warn!(error = %e, "received invalid provider event");
+                    continue;
+                }
+            };
+            if envelope.origin == self.inner.instance_id {
+                continue;
+            }
+            debug!(event = ?envelope.event, "received provider event");
+            self.handle_event(app_state, envelope.origin, envelope.event)
+                .await;
+        }
+        Ok(())
+    }
+}
+
+/// Background service which applies provider events published by other
+/// replicas.
+#[derive(Debug, Clone)]
+pub struct StateSyncListener {
+    app_state: AppState,
+    state_sync: StateSync,
+}
+
+impl StateSyncListener {
+    #[must_use]
+    pub fn new(app_state: AppState, state_sync: StateSync) -> Self {
+        Self {
+            app_state,
+            state_sync,
+        }
+    }
+
+    pub async fn run_forever(self) -> Result<(), RuntimeError> {
+        loop {
+            if let Err(e) = self.state_sync.subscribe(&self.app_state).await {
+                error!(error = %e, "provider event subscription failed");
+            } else {
+                warn!("provider event subscription ended");
+            }
+            tokio::time::sleep(RESUBSCRIBE_DELAY).await;
+        }
+    }
+}
+
+impl meltdown::Service for StateSyncListener {
+    type Future = BoxFuture<'static, Result<(), RuntimeError>>;
+
+    fn run(self, mut token: Token) -> Self::Future {
+        Box::pin(async move {
+            tokio::select! {
+                result = self.run_forever() => {
+                    if let Err(e) = result {
+                        error!(name = "state-sync-listener-task", error = ?e, "Listener encountered error, shutting down");
+                    } else {
+                        debug!(name = "state-sync-listener-task", "Listener shut down successfully");
+                    }
+                    token.trigger();
+                }
+                () = &mut token => {
+                    debug!(name = "state-sync-listener-task", "task shut down successfully");
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn provider_event_round_trip() {
+        let event = ProviderEvent::RateLimited {
+            router_id: RouterId::Named("my-router".into()),
+            provider: InferenceProvider::OpenAI,
+            endpoint_type: EndpointType::Chat,
+            retry_after_seconds: Some(10),
+        };
+        let envelope = Envelope {
+            origin: Uuid::now_v7(),
+            event: event.clone(),
+        };
+        let serialized = serde_json::to_string(&envelope).unwrap();
+        let deserialized =
+            serde_json::from_str::<Envelope>(&serialized).unwrap();
+        assert_eq!(deserialized.origin, envelope.origin);
+        assert_eq!(deserialized.event, event);
+    }
+
+    #[tokio::test]
+    async fn remote_health_events_expire() {
+        let state_sync = StateSync::new(&StateSyncConfig {
+            unhealthy_ttl: Duration::from_millis(20),
+            ..Default::default()
+        })
+        .unwrap();
+        let provider = InferenceProvider::Anthropic;
+        assert!(!state_sync.is_unhealthy_elsewhere(&provider));
+
+        state_sync.mark_unhealthy_elsewhere(Uuid::now_v7(), provider.clone());
+        assert!(state_sync.is_unhealthy_elsewhere(&provider));
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!state_sync.is_unhealthy_elsewhere(&provider));
+    }
+
+    #[test]
+    fn remote_latency_is_averaged_across_replicas() {
+        let state_sync = StateSync::new(&StateSyncConfig::default()).unwrap();
+        let model = ModelId::from_str("openai/gpt-4o").unwrap();
+        assert_eq!(state_sync.remote_latency(&model, EndpointType::Chat), None);
+
+        let replica = Uuid::now_v7();
+        state_sync.record_remote_latency(
+            replica,
+            model.clone(),
+            EndpointType::Chat,
+            Duration::from_millis(100),
+        );
+        state_sync.record_remote_latency(
+            Uuid::now_v7(),
+            model.clone(),
+            EndpointType::Chat,
+            Duration::from_millis(300),
+        );
+        assert_eq!(
+            state_sync.remote_latency(&model, EndpointType::Chat),
+            Some(Duration::from_millis(200))
+        );
+
+        // a newer report from the same replica replaces its previous one
+        state_sync.record_remote_latency(
+            replica,
+            model.clone(),
+            EndpointType::Chat,
+            Duration::from_millis(500),
+        );
+        assert_eq!(
+            state_sync.remote_latency(&model, EndpointType::Chat),
+            Some(Duration::from_millis(400))
+        );
+    }
+
+    #[tokio::test]
+    async fn remote_latency_expires() {
+        let state_sync = StateSync::new(&StateSyncConfig {
+            latency_ttl: Duration::from_millis(20),
+            ..Default::default()
+        })
+        .unwrap();
+        let model = ModelId::from_str("anthropic/claude-3-5-sonnet").unwrap();
+        state_sync.record_remote_latency(
+            Uuid::now_v7(),
+            model.clone(),
+            EndpointType::Chat,
+            Duration::from_millis(100),
+        );
+        assert!(
+            state_sync
+                .remote_latency(&model, EndpointType::Chat)
+                .is_some()
+        );
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(state_sync.remote_latency(&model, EndpointType::Chat), None);
+    }
+
+    #[test]
+    fn remote_recovery_clears_unhealthy() {
+        let state_sync = StateSync::new(&StateSyncConfig::default()).unwrap();
+        let provider = InferenceProvider::OpenAI;
+        let replica = Uuid::now_v7();
+        state_sync.mark_unhealthy_elsewhere(replica, provider.clone());
+        assert!(state_sync.is_unhealthy_elsewhere(&provider));
+        state_sync.mark_healthy_elsewhere(replica, &provider);
+        assert!(!state_sync.is_unhealthy_elsewhere(&provider));
+    }
+
+    #[test]
+    fn recovery_only_clears_the_reporting_replica() {
+        let state_sync = StateSync::new(&StateSyncConfig::default()).unwrap();
+        let provider = InferenceProvider::OpenAI;
+        let (first, second) = (Uuid::now_v7(), Uuid::now_v7());
+        state_sync.mark_unhealthy_elsewhere(first, provider.clone());
+        state_sync.mark_unhealthy_elsewhere(second, provider.clone());
+
+        state_sync.mark_healthy_elsewhere(first, &provider);
+        assert!(state_sync.is_unhealthy_elsewhere(&provider));
+        state_sync.mark_healthy_elsewhere(second, &provider);
+        assert!(!state_sync.is_unhealthy_elsewhere(&provider));
+    }
+}
@@ -0,0 +1,381 @@
+//! Webhook notifications of a router's provider health and spend events.
+//!
+//! Notifications are sent in the background, so that a slow webhook never
+//! holds up the monitors. Identical notifications within the dedup window
+//! are dropped, as are notifications beyond the per minute limit, so that a
+//! flapping provider doesn't flood a channel.
+//!
+//! Webhook URLs are subject to the dispatcher's egress allowlist, and
+//! redirects are never followed, so that a webhook can't be used to reach
+//! hosts the gateway otherwise wouldn't.
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chrono::Utc;
+use rust_decimal::{Decimal, prelude::FromPrimitive};
+use rustc_hash::FxHashMap as HashMap;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::{
+    config::{
+        dispatcher::EgressConfig,
+        webhook::{WebhookFormat, WebhooksConfig},
+    },
+    endpoints::EndpointType,
+    error::init::InitError,
+    types::{provider::InferenceProvider, router::RouterId},
+    utils::task,
+};
+
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Why a provider was removed from a load balancer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reason {
+    Unhealthy,
+    RateLimited,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    ProviderRemoved {
+        provider: InferenceProvider,
+        endpoint: EndpointType,
+        reason: Reason,
+    },
+    ProviderRestored {
+        provider: InferenceProvider,
+        endpoint: EndpointType,
+        reason: Reason,
+    },
+    /// The provider's error ratio rose past `threshold` of the error budget.
+    ErrorBudgetBurn {
+        provider: InferenceProvider,
+        threshold: Decimal,
+    },
+    /// The spend of an API key, or of the router if `key` is `None`, rose
+    /// past its baseline and was frozen.
+    SpendAnomaly {
+        key: Option<String>,
+        spend: Decimal,
+        baseline: Decimal,
+    },
+}
+
+impl Event {
+    fn message(&self, router_id: &RouterId) -> String {
+        let reason = |reason: &Reason| match reason {
+            Reason::Unhealthy => "unhealthy",
+            Reason::RateLimited => "rate limited",
+        };
+        match self {
+            Self::ProviderRemoved {
+                provider,
+                endpoint,
+                reason: r,
+            } => format!(
+                "Provider {provider} was removed from router {router_id} \
+                 ({}): {}",
+                endpoint.as_ref(),
+                reason(r)
+            ),
+            Self::ProviderRestored {
+                provider,
+                endpoint,
+                reason: r,
+            } => format!(
+                "Provider {provider} was restored to router {router_id} ({}) \
+                 after being {}",
+                endpoint.as_ref(),
+                reason(r)
+            ),
+            Self::ErrorBudgetBurn {
+                provider,
+                threshold,
+            } => format!(
+                "Provider {provider} has burned {}% of its error budget on \
+                 router {router_id}",
+                (threshold * Decimal::ONE_HUNDRED).normalize()
+            ),
+            Self::SpendAnomaly {
+                key,
+                spend,
+                baseline,
+            } => {
+                let subject = key.as_ref().map_or_else(
+                    || format!("router {router_id}"),
+                    |key| {
+                        format!(
+                            "API key {} on router {router_id}",
+                            key.get(..8).unwrap_or(key)
+                        )
+                    },
+                );
+                format!(
+                    "Spend of {subject} rose to ${} against a baseline of ${} \
+                     per window, and it was frozen",
+                    spend.round_dp(2),
+                    baseline.round_dp(2)
+                )
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct State {
+    /// When each notification was last sent, for deduplication.
+    sent: HashMap<Event, Instant>,
+    window_start: Option<Instant>,
+    sent_in_window: u32,
+    /// The number of burn thresholds each provider's error ratio is past.
+    burn_levels: HashMap<InferenceProvider, usize>,
+}
+
+/// Sends the notifications of a router.
+#[derive(Debug)]
+pub struct Webhooks {
+    router_id: RouterId,
+    config: WebhooksConfig,
+    client: reqwest::Client,
+    state: Mutex<State>,
+}
+
+impl Webhooks {
+    /// Fails if an endpoint's host isn't on the `egress` allowlist.
+    pub fn new(
+        router_id: RouterId,
+        mut config: WebhooksConfig,
+        egress: Option<&EgressConfig>,
+    ) -> Result<Self, InitError> {
+        if let Some(egress) = egress
+            && let Some(endpoint) =
+                config.endpoints.iter().find(|e| !egress.allows(&e.url))
+        {
+            return Err(InitError::WebhookEgressDenied(
+                endpoint.url.host_str().unwrap_or_default().to_string(),
+            ));
+        }
+        let client = reqwest::Client::builder()
+            .timeout(SEND_TIMEOUT)
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(InitError::CreateReqwestClient)?;
+        config.burn_thresholds.sort();
+        Ok(Self {
+            router_id,
+            config,
+            client,
+            state: Mutex::default(),
+        })
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &WebhooksConfig {
+        &self.config
+    }
+
+    /// Sends `event` to the webhooks, unless it's a duplicate or the rate
+    /// limit is reached.
+    pub fn notify(&self, event: Event) {
+        if !self.admit(&event, Instant::now()) {
+            return;
+        }
+        let message = event.message(&self.router_id);
+        tracing::debug!(router_id = %self.router_id, %message, "sending webhook notification");
+        for endpoint in &self.config.endpoints {
+            let body = match endpoint.format {
+                WebhookFormat::Generic => json!({
+                    "router": self.router_id,
+                    "event": event,
+                    "message": message,
+                    "timestamp": Utc::now(),
+                }),
+                WebhookFormat::Slack => json!({ "text": message }),
+            };
+            let request = self.client.post(endpoint.url.clone()).json(&body);
+            let url = endpoint.url.clone();
+            task::spawn("webhook", async move {
+                let result = request
+                    .send()
+                    .await
+                    .and_then(reqwest::Response::error_for_status);
+                if let Err(error) = result {
+                    tracing::warn!(
+                        %url,
+                        error = %error,
+                        "failed to send webhook notification"
+                    );
+                }
+            });
+        }
+    }
+
+    /// Notifies when `provider`'s error budget `burn`, its error ratio as a
+    /// fraction of the health monitor's threshold, rises past one of the
+    /// burn thresholds.
+    pub fn observe_burn(&self, provider: &InferenceProvider, burn: f64) {
+        if let Some(threshold) = self.crossed_threshold(provider, burn) {
+            self.notify(Event::ErrorBudgetBurn {
+                provider: provider.clone(),
+                threshold,
+            });
+        }
+    }
+
+    fn crossed_threshold(
+        &self,
+        provider: &InferenceProvider,
+        burn: f64,
+    ) -> Option<Decimal> {
+        let burn = Decimal::from_f64(burn)?;
+        let level = self
+            .config
+            .burn_thresholds
+            .iter()
+            .take_while(|threshold| **threshold <= burn)
+            .count();
+        let mut state = self.state.lock().expect("webhook lock poisoned");
+        let previous = state.burn_levels.insert(provider.clone(), level);
+        (level > previous.unwrap_or_default())
+            .then(|| self.config.burn_thresholds[level - 1])
+    }
+
+    fn admit(&self, event: &Event, now: Instant) -> bool {
+        let mut state = self.state.lock().expect("webhook lock poisoned");
+        let dedup_window = self.config.dedup_window;
+        state
+            .sent
+            .retain(|_, sent_at| now.duration_since(*sent_at) < dedup_window);
+        if state.sent.contains_key(event) {
+            return false;
+        }
+        let window_expired = state.window_start.is_none_or(|window_start| {
+            now.duration_since(window_start) >= RATE_LIMIT_WINDOW
+        });
+        if window_expired {
+            state.window_start = Some(now);
+            state.sent_in_window = 0;
+        }
+        if state.sent_in_window >= self.config.max_per_minute {
+            tracing::warn!(
+                router_id = %self.router_id,
+                "webhook notification rate limit reached, dropping notification"
+            );
+            return false;
+        }
+        state.sent_in_window += 1;
+        state.sent.insert(event.clone(), now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compact_str::CompactString;
+
+    use super::*;
+    use crate::config::webhook::WebhookEndpointConfig;
+
+    fn webhooks(max_per_minute: u32) -> Webhooks {
+        Webhooks::new(
+            RouterId::Named(CompactString::new("my-router")),
+            WebhooksConfig {
+                max_per_minute,
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap()
+    }
+
+    fn removed(provider: InferenceProvider) -> Event {
+        Event::ProviderRemoved {
+            provider,
+            endpoint: EndpointType::Chat,
+            reason: Reason::Unhealthy,
+        }
+    }
+
+    #[test]
+    fn duplicates_are_dropped_within_window() {
+        let webhooks = webhooks(10);
+        let now = Instant::now();
+        let event = removed(InferenceProvider::OpenAI);
+        assert!(webhooks.admit(&event, now));
+        assert!(!webhooks.admit(&event, now + Duration::from_secs(60)));
+        assert!(webhooks.admit(&event, now + Duration::from_secs(5 * 60)));
+    }
+
+    #[test]
+    fn notifications_are_rate_limited() {
+        let webhooks = webhooks(1);
+        let now = Instant::now();
+        assert!(webhooks.admit(&removed(InferenceProvider::OpenAI), now));
+        assert!(!webhooks.admit(&removed(InferenceProvider::Anthropic), now));
+        assert!(webhooks.admit(
+            &removed(InferenceProvider::Anthropic),
+            now + RATE_LIMIT_WINDOW
+        ));
+    }
+
+    #[test]
+    fn endpoints_must_be_on_the_egress_allowlist() {
+        let egress = EgressConfig {
+            allowed_hosts: ["hooks.slack.com".to_string()]
+                .into_iter()
+                .collect(),
+        };
+        let config = |url: &str| WebhooksConfig {
+            endpoints: vec![WebhookEndpointConfig {
+                url: url.parse().unwrap(),
+                format: WebhookFormat::Slack,
+            }],
+            ..Default::default()
+        };
+        let router_id = RouterId::Named(CompactString::new("my-router"));
+        assert!(
+            Webhooks::new(
+                router_id.clone(),
+                config("https://hooks.slack.com/services/T0/B0/x"),
+                Some(&egress),
+            )
+            .is_ok()
+        );
+        assert!(matches!(
+            Webhooks::new(
+                router_id,
+                config("http://169.254.169.254/latest/meta-data"),
+                Some(&egress),
+            ),
+            Err(InitError::WebhookEgressDenied(host)) if host == "169.254.169.254"
+        ));
+    }
+
+    #[test]
+    fn burn_notifies_once_per_threshold_crossed() {
+        let webhooks = webhooks(10);
+        let provider = InferenceProvider::OpenAI;
+        assert_eq!(webhooks.crossed_threshold(&provider, 0.2), None);
+        assert_eq!(
+            webhooks.crossed_threshold(&provider, 0.6),
+            Some(Decimal::new(5, 1))
+        );
+        assert_eq!(webhooks.crossed_threshold(&provider, 0.7), None);
+        assert_eq!(
+            webhooks.crossed_threshold(&provider, 1.5),
+            Some(Decimal::new(9, 1))
+        );
+        assert_eq!(webhooks.crossed_threshold(&provider, 0.1), None);
+        assert_eq!(
+            webhooks.crossed_threshold(&provider, 0.5),
+            Some(Decimal::new(5, 1))
+        );
+    }
+}
@@ -0,0 +1,304 @@
+//! Adaptive per provider concurrency limits.
+//!
+//! The limit of each provider is adjusted with the gradient algorithm from
+//! Netflix's `concurrency-limits`: while latency stays close to its long term
+//! average the limit keeps growing, and as soon as latency starts rising with
+//! load the limit is reduced, before the provider starts rejecting requests
+//! or timing out. Rejections and timeouts reduce the limit multiplicatively.
+use std::{
+    sync::{
+        Arc, Mutex, RwLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Instant,
+};
+
+use rustc_hash::FxHashMap as HashMap;
+
+use crate::{
+    config::dispatcher::AdaptiveConcurrencyConfig,
+    types::provider::InferenceProvider,
+};
+
+/// How much latency may rise above its long term average before the limit
+/// is reduced.
+const RTT_TOLERANCE: f64 = 1.5;
+/// Weight of each sample in the long term latency average.
+const LONG_RTT_WEIGHT: f64 = 0.05;
+/// Weight of each new limit in the smoothed limit.
+const SMOOTHING: f64 = 0.2;
+/// Factor the limit is multiplied by when a request is dropped.
+const BACKOFF: f64 = 0.9;
+/// Lower bound on samples, so that instant responses can't divide by zero.
+const MIN_RTT_SECS: f64 = 1e-6;
+
+#[derive(Debug)]
+struct Gradient {
+    limit: f64,
+    /// Long term average latency in seconds.
+    long_rtt: Option<f64>,
+}
+
+impl Gradient {
+    fn sample(&mut self, rtt: f64, in_flight: usize) {
+        let rtt = rtt.max(MIN_RTT_SECS);
+        let mut long_rtt = self.long_rtt.map_or(rtt, |long_rtt| {
+            long_rtt + (rtt - long_rtt) * LONG_RTT_WEIGHT
+        });
+        // after a period of overload, let the average recover quickly
+        if long_rtt / rtt > 2.0 {
+            long_rtt *= 0.95;
+        }
+        self.long_rtt = Some(long_rtt);
+
+        // with this little load, latency says nothing about the limit
+        #[allow(clippy::cast_precision_loss)]
+        if (in_flight as f64) < self.limit / 2.0 {
+            return;
+        }
+        let gradient = (RTT_TOLERANCE * long_rtt / rtt).clamp(0.5, 1.0);
+        let new_limit = self.limit * gradient + self.limit.sqrt();
+        self.limit = self.limit * (1.0 - SMOOTHING) + new_limit * SMOOTHING;
+    }
+
+    fn back_off(&mut self) {
+        self.limit *= BACKOFF;
+    }
+}
+
+/// The adaptive concurrency limit of a single provider.
+#[derive(Debug)]
+pub struct AdaptiveLimiter {
+    min_limit: usize,
+    max_limit: usize,
+    gradient: Mutex<Gradient>,
+    limit: AtomicUsize,
+    in_flight: AtomicUsize,
+}
+
+impl AdaptiveLimiter {
+    #[must_use]
+    pub fn new(config: &AdaptiveConcurrencyConfig) -> Self {
+        let limit = config
+            .initial_limit
+            .clamp(config.min_limit, config.max_limit.max(config.min_limit));
+        #[allow(clippy::cast_precision_loss)]
+        let gradient = Gradient {
+            limit: limit as f64,
+            long_rtt: None,
+        };
+        Self {
+            min_limit: config.min_limit,
+            max_limit: config.max_limit.max(config.min_limit),
+            gradient: Mutex::new(gradient),
+            limit: AtomicUsize::new(limit),
+            in_flight: AtomicUsize::new(0),
+        }
+    }
+
+    #[must_use]
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Acquire)
+    }
+
+    #[must_use]
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Admits a request if fewer requests than the current limit are in
+    /// flight.
+    #[must_use]
+    pub fn try_acquire(self: &Arc<Self>) -> Option<AdaptivePermit> {
+        let limit = self.limit();
+        let in_flight = self
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                (in_flight < limit).then_some(in_flight + 1)
+            })
+            .ok()?
+            + 1;
+        Some(AdaptivePermit {
+            limiter: Arc::clone(self),
+            started_at: Instant::now(),
+            in_flight,
+            recorded: false,
+        })
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Gradient)) {
+        let mut gradient =
+            self.gradient.lock().expect("gradient lock poisoned");
+        f(&mut gradient);
+        #[allow(clippy::cast_precision_loss)]
+        let bounds = (self.min_limit as f64, self.max_limit as f64);
+        gradient.limit = gradient.limit.clamp(bounds.0, bounds.1);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        self.limit
+            .store(gradient.limit.round() as usize, Ordering::Release);
+    }
+}
+
+/// A request admitted by an [`AdaptiveLimiter`], which counts as in flight
+/// until dropped.
+#[derive(Debug)]
+pub struct AdaptivePermit {
+    limiter: Arc<AdaptiveLimiter>,
+    started_at: Instant,
+    /// Number of requests in flight when this one was admitted, including
+    /// itself.
+    in_flight: usize,
+    recorded: bool,
+}
+
+impl AdaptivePermit {
+    /// Records that the provider responded normally, using the time since
+    /// the request was admitted as a latency sample.
+    pub fn record_success(&mut self) {
+        if std::mem::replace(&mut self.recorded, true) {
+            return;
+        }
+        let rtt = self.started_at.elapsed().as_secs_f64();
+        let in_flight = self.in_flight;
+        self.limiter
+            .update(|gradient| gradient.sample(rtt, in_flight));
+    }
+
+    /// Records that the provider rejected or timed out the request.
+    pub fn record_dropped(&mut self) {
+        if std::mem::replace(&mut self.recorded, true) {
+            return;
+        }
+        self.limiter.update(Gradient::back_off);
+    }
+
+    #[must_use]
+    pub fn limiter(&self) -> &AdaptiveLimiter {
+        &self.limiter
+    }
+}
+
+impl Drop for AdaptivePermit {
+    fn drop(&mut self) {
+        self.limiter.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The adaptive limiters of all providers.
+#[derive(Debug)]
+pub struct AdaptiveLimiters {
+    config: AdaptiveConcurrencyConfig,
+    limiters: RwLock<HashMap<InferenceProvider, Arc<AdaptiveLimiter>>>,
+}
+
+impl AdaptiveLimiters {
+    #[must_use]
+    pub fn new(config: AdaptiveConcurrencyConfig) -> Self {
+        Self {
+            config,
+            limiters: RwLock::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn get(&self, provider: &InferenceProvider) -> Arc<AdaptiveLimiter> {
+        if let Some(limiter) = self
+            .limiters
+            .read()
+            .expect("adaptive limiter lock poisoned")
+            .get(provider)
+        {
+            return Arc::clone(limiter);
+        }
+        let mut limiters = self
+            .limiters
+            .write()
+            .expect("adaptive limiter lock poisoned");
+        Arc::clone(
+            limiters.entry(provider.clone()).or_insert_with(|| {
+                Arc::new(AdaptiveLimiter::new(&self.config))
+            }),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(initial_limit: usize) -> Arc<AdaptiveLimiter> {
+        Arc::new(AdaptiveLimiter::new(&AdaptiveConcurrencyConfig {
+            initial_limit,
+            min_limit: 1,
+            max_limit: 100,
+        }))
+    }
+
+    #[test]
+    fn requests_above_limit_are_rejected() {
+        let limiter = limiter(2);
+        let first = limiter.try_acquire().unwrap();
+        let _second = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+        drop(first);
+        assert_eq!(limiter.in_flight(), 1);
+        assert!(limiter.try_acquire().is_some());
+    }
+
+    #[test]
+    fn drops_reduce_limit() {
+        let limiter = limiter(20);
+        let mut permit = limiter.try_acquire().unwrap();
+        permit.record_dropped();
+        assert_eq!(limiter.limit(), 18);
+        // outcomes are only recorded once
+        permit.record_dropped();
+        assert_eq!(limiter.limit(), 18);
+    }
+
+    #[test]
+    fn steady_latency_under_load_grows_limit() {
+        let mut gradient = Gradient {
+            limit: 20.0,
+            long_rtt: None,
+        };
+        for _ in 0..10 {
+            gradient.sample(0.1, 20);
+        }
+        assert!(gradient.limit > 20.0);
+    }
+
+    #[test]
+    fn rising_latency_shrinks_limit() {
+        let mut gradient = Gradient {
+            limit: 20.0,
+            long_rtt: Some(0.1),
+        };
+        gradient.sample(0.5, 20);
+        assert!(gradient.limit < 20.0);
+    }
+
+    #[test]
+    fn light_load_keeps_limit() {
+        let mut gradient = Gradient {
+            limit: 20.0,
+            long_rtt: Some(0.1),
+        };
+        gradient.sample(0.5, 2);
+        assert!((gradient.limit - 20.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn limit_is_bounded() {
+        let limiter =
+            Arc::new(AdaptiveLimiter::new(&AdaptiveConcurrencyConfig {
+                initial_limit: 2,
+                min_limit: 2,
+                max_limit: 100,
+            }));
+        for _ in 0..10 {
+            limiter.try_acquire().unwrap().record_dropped();
+        }
+        assert_eq!(limiter.limit(), 2);
+    }
+}
@@ -0,0 +1,228 @@
+//! Timeouts of the attempts to send a request to a provider.
+//!
+//! With retries, a provider that is slow to respond can hold a request for
+//! the whole retry window on a single attempt. The `attempt-timeout` of a
+//! retry strategy gives up on an attempt, which is then retried, while its
+//! `deadline` bounds all of the attempts together, as does the deadline the
+//! client set, if any.
+//!
+//! Rather than waiting out a slow attempt, its `hedge-after` sends a second
+//! attempt alongside it, unless the request is of [`Priority::Low`].
+use std::time::Duration;
+
+use futures::future::{self, Either};
+use tokio::time::Instant;
+
+use crate::{
+    config::retry::RetryConfig,
+    error::{api::ApiError, internal::InternalError},
+    types::{extensions::RequestDeadline, priority::Priority},
+};
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AttemptTimeouts {
+    attempt_timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    hedge_after: Option<Duration>,
+}
+
+impl AttemptTimeouts {
+    /// Starts the deadline of a request's attempts, which is the earliest
+    /// of the retry strategy's and the client's.
+    #[must_use]
+    pub fn start(
+        retry_config: &RetryConfig,
+        request_deadline: Option<RequestDeadline>,
+        priority: Priority,
+    ) -> Self {
+        let deadline = retry_config
+            .deadline()
+            .map(|deadline| Instant::now() + deadline);
+        let request_deadline = request_deadline.map(|deadline| deadline.0);
+        Self {
+            attempt_timeout: retry_config.attempt_timeout(),
+            hedge_after: retry_config
+                .hedge_after()
+                .filter(|_| priority.may_hedge()),
+            deadline: match (deadline, request_deadline) {
+                (Some(deadline), Some(request)) => Some(deadline.min(request)),
+                (deadline, request) => deadline.or(request),
+            },
+        }
+    }
+
+    /// The timeout of the next attempt, if any.
+    fn timeout(&self) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        match (self.attempt_timeout, remaining) {
+            (Some(timeout), Some(remaining)) => Some(timeout.min(remaining)),
+            (timeout, remaining) => timeout.or(remaining),
+        }
+    }
+
+    /// Runs an attempt, failing it with
+    /// [`InternalError::AttemptTimeout`] if it times out.
+    pub async fn run<T>(
+        &self,
+        attempt: impl Future<Output = Result<T, ApiError>>,
+    ) -> Result<T, ApiError> {
+        let Some(timeout) = self.timeout() else {
+            return attempt.await;
+        };
+        tokio::time::timeout(timeout, attempt)
+            .await
+            .unwrap_or_else(|_| {
+                Err(ApiError::Internal(InternalError::AttemptTimeout(timeout)))
+            })
+    }
+
+    /// Runs an attempt like [`Self::run`], hedging it with a second attempt
+    /// if it hasn't completed after `hedge-after`. The first attempt to
+    /// succeed wins, and the other is cancelled.
+    pub async fn run_hedged<T, F>(
+        &self,
+        attempt: impl Fn() -> F,
+    ) -> Result<T, ApiError>
+    where
+        F: Future<Output = Result<T, ApiError>>,
+    {
+        match self.hedge_after {
+            Some(hedge_after) => self.run(hedged(hedge_after, attempt)).await,
+            None => self.run(attempt()).await,
+        }
+    }
+
+    /// Stops `backoff` once the next attempt would start past the deadline.
+    pub fn limit<B>(&self, backoff: B) -> WithinDeadline<B> {
+        WithinDeadline {
+            backoff,
+            deadline: self.deadline,
+        }
+    }
+}
+
+async fn hedged<T, F>(
+    hedge_after: Duration,
+    attempt: impl Fn() -> F,
+) -> Result<T, ApiError>
+where
+    F: Future<Output = Result<T, ApiError>>,
+{
+    let first = attempt();
+    tokio::pin!(first);
+    if let Ok(result) = tokio::time::timeout(hedge_after, &mut first).await {
+        return result;
+    }
+    tracing::debug!(?hedge_after, "attempt is slow, hedging it");
+    let second = attempt();
+    tokio::pin!(second);
+    match future::select(first, second).await {
+        Either::Left((Ok(response), _)) | Either::Right((Ok(response), _)) => {
+            Ok(response)
+        }
+        Either::Left((Err(_), other)) | Either::Right((Err(_), other)) => {
+            other.await
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WithinDeadline<B> {
+    backoff: B,
+    deadline: Option<Instant>,
+}
+
+impl<B: Iterator<Item = Duration>> Iterator for WithinDeadline<B> {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        let delay = self.backoff.next()?;
+        match self.deadline {
+            Some(deadline) if Instant::now() + delay >= deadline => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new(
+        attempt_timeout: Option<Duration>,
+        deadline: Option<Duration>,
+    ) -> AttemptTimeouts {
+        AttemptTimeouts {
+            attempt_timeout,
+            deadline: deadline.map(|deadline| Instant::now() + deadline),
+            hedge_after: None,
+        }
+    }
+
+    #[test]
+    fn attempts_end_by_the_deadline() {
+        let timeouts =
+            new(Some(Duration::from_secs(10)), Some(Duration::from_secs(1)));
+        assert!(timeouts.timeout().unwrap() <= Duration::from_secs(1));
+        let timeouts = new(Some(Duration::from_secs(10)), None);
+        assert_eq!(timeouts.timeout(), Some(Duration::from_secs(10)));
+        assert_eq!(new(None, None).timeout(), None);
+    }
+
+    #[tokio::test]
+    async fn slow_attempts_time_out() {
+        let timeouts = new(Some(Duration::from_millis(10)), None);
+        let result = timeouts
+            .run(std::future::pending::<Result<(), ApiError>>())
+            .await;
+        assert!(matches!(
+            result,
+            Err(ApiError::Internal(InternalError::AttemptTimeout(_)))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_attempts_are_hedged() {
+        let timeouts = AttemptTimeouts {
+            hedge_after: Some(Duration::from_millis(100)),
+            ..new(None, None)
+        };
+        let attempts = std::sync::atomic::AtomicU32::new(0);
+        let result = timeouts
+            .run_hedged(|| {
+                let attempt =
+                    attempts.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                async move {
+                    // the first attempt hangs
+                    if attempt == 0 {
+                        std::future::pending::<()>().await;
+                    }
+                    Ok::<_, ApiError>(attempt)
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 1);
+
+        let config = RetryConfig::Constant {
+            delay: Duration::from_millis(5),
+            max_retries: 2,
+            attempt_timeout: None,
+            deadline: None,
+            hedge_after: Some(Duration::from_millis(100)),
+            stream_mode: crate::config::retry::StreamRetryMode::default(),
+        };
+        let low = AttemptTimeouts::start(&config, None, Priority::Low);
+        assert_eq!(low.hedge_after, None);
+        let normal = AttemptTimeouts::start(&config, None, Priority::Normal);
+        assert_eq!(normal.hedge_after, Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn retries_stop_at_the_deadline() {
+        let timeouts = new(None, Some(Duration::from_millis(100)));
+        let backoff = [Duration::from_millis(10), Duration::from_secs(1)];
+        assert_eq!(timeouts.limit(backoff.into_iter()).count(), 1);
+    }
+}
@@ -0,0 +1,154 @@
+//! Azure `OpenAI` serves the `OpenAI` API per deployment, at
+//! `openai/deployments/{deployment}/{route}?api-version={version}`, and
+//! authenticates requests with an `api-key` header.
+//!
+//! Requests are mapped to Azure like to any OpenAI compatible provider, with
+//! the deployment named by the target model, so that balance configs and
+//! model mappings target deployments as `azure/{deployment}`.
+use http::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::ClientBuilder;
+
+use crate::{
+    app_state::AppState,
+    error::{init::InitError, provider::ProviderError},
+    types::{
+        provider::{InferenceProvider, ProviderKey},
+        secret::Secret,
+    },
+    utils::host_header,
+};
+
+/// The API version sent if the provider config doesn't set one.
+pub(crate) const DEFAULT_AZURE_API_VERSION: &str = "2024-10-21";
+const API_KEY_HEADER: HeaderName = HeaderName::from_static("api-key");
+const API_VERSION_PARAM: &str = "api-version";
+/// The prefix of `OpenAI` paths, which Azure replaces with the deployment.
+const OPENAI_PATH_PREFIX: &str = "v1/";
+
+#[derive(Debug, Clone, Default)]
+pub struct Client(pub(super) reqwest::Client);
+
+impl Client {
+    pub fn new(
+        app_state: &AppState,
+        client_builder: ClientBuilder,
+        provider_key: Option<&ProviderKey>,
+    ) -> Result<Self, InitError> {
+        let base_url = app_state
+            .0
+            .config
+            .providers
+            .get(&InferenceProvider::AzureOpenAI)
+            .ok_or(ProviderError::ProviderNotConfigured(
+                InferenceProvider::AzureOpenAI,
+            ))?
+            .base_url
+            .clone();
+
+        let mut default_headers = HeaderMap::new();
+        if let Some(ProviderKey::Secret(key)) = provider_key {
+            default_headers.insert(
+                API_KEY_HEADER,
+                HeaderValue::from_str(key.expose()).unwrap(),
+            );
+        }
+        default_headers.insert(http::header::HOST, host_header(&base_url));
+        default_headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_str(mime::APPLICATION_JSON.essence_str())
+                .unwrap(),
+        );
+        let inner = client_builder
+            .default_headers(default_headers)
+            .build()
+            .map_err(InitError::CreateReqwestClient)?;
+        Ok(Self(inner))
+    }
+
+    pub fn set_auth_header(
+        request_builder: reqwest::RequestBuilder,
+        key: &Secret<String>,
+    ) -> reqwest::RequestBuilder {
+        request_builder.header(
+            API_KEY_HEADER,
+            HeaderValue::from_str(key.expose()).unwrap(),
+        )
+    }
+}
+
+/// Rewrites the `OpenAI` path of a mapped request to that of `deployment`,
+/// and sets the API version unless the request already does.
+///
+/// Paths which aren't `OpenAI`'s, such as those of direct proxy requests
+/// already addressing a deployment, are kept as they are.
+pub(crate) fn deployment_path_and_query(
+    path_and_query: &str,
+    deployment: Option<&str>,
+    api_version: &str,
+) -> String {
+    let (path, query) = path_and_query
+        .split_once('?')
+        .map_or((path_and_query, None), |(path, query)| (path, Some(query)));
+    let path = match (
+        path.trim_start_matches('/')
+            .strip_prefix(OPENAI_PATH_PREFIX),
+        deployment,
+    ) {
+        (Some(route), Some(deployment)) => {
+            format!("openai/deployments/{deployment}/{route}")
+        }
+        _ => path.to_string(),
+    };
+    let has_api_version = query.is_some_and(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .any(|(key, _)| key == API_VERSION_PARAM)
+    });
+    match query.filter(|query| !query.is_empty()) {
+        Some(query) if has_api_version => format!("{path}?{query}"),
+        Some(query) => {
+            format!("{path}?{query}&{API_VERSION_PARAM}={api_version}")
+        }
+        None => format!("{path}?{API_VERSION_PARAM}={api_version}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapped_path_addresses_deployment() {
+        assert_eq!(
+            deployment_path_and_query(
+                "v1/chat/completions",
+                Some("gpt-4o-prod"),
+                DEFAULT_AZURE_API_VERSION,
+            ),
+            "openai/deployments/gpt-4o-prod/chat/completions?api-version=\
+             2024-10-21"
+        );
+        assert_eq!(
+            deployment_path_and_query(
+                "v1/chat/completions?user=test",
+                Some("gpt-4o-prod"),
+                "2025-01-01-preview",
+            ),
+            "openai/deployments/gpt-4o-prod/chat/completions?user=test&\
+             api-version=2025-01-01-preview"
+        );
+    }
+
+    #[test]
+    fn direct_path_is_kept() {
+        let path = "openai/deployments/gpt-4o-prod/chat/completions?\
+                    api-version=2024-06-01";
+        assert_eq!(
+            deployment_path_and_query(
+                path,
+                Some("gpt-4o"),
+                DEFAULT_AZURE_API_VERSION
+            ),
+            path
+        );
+    }
+}
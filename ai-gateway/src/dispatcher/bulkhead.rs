@@ -0,0 +1,304 @@
+//! Per provider concurrency bulkheads.
+//!
+//! Requests waiting for capacity are queued by [`Priority`], and in arrival
+//! order within a priority. Only the request at the front of the queue waits
+//! on the bulkhead's semaphore, so released capacity always goes to the
+//! highest priority request waiting.
+use std::{
+    cmp::Reverse,
+    collections::BTreeSet,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use rustc_hash::FxHashMap as HashMap;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    config::dispatcher::BulkheadConfig,
+    types::{priority::Priority, provider::InferenceProvider},
+};
+
+/// A place in a bulkhead's queue, ordered highest priority first and then
+/// in arrival order.
+type Place = (Reverse<Priority>, u64);
+
+#[derive(Debug, Default)]
+struct Queue {
+    waiting: BTreeSet<Place>,
+    next_seq: u64,
+}
+
+#[derive(Debug)]
+struct Bulkhead {
+    semaphore: Arc<Semaphore>,
+    queue: Mutex<Queue>,
+    /// Notified whenever the front of the queue changes.
+    front_changed: Notify,
+}
+
+impl Bulkhead {
+    fn new(max_concurrent_requests: usize) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent_requests)),
+            queue: Mutex::default(),
+            front_changed: Notify::new(),
+        }
+    }
+
+    fn queue(&self) -> std::sync::MutexGuard<'_, Queue> {
+        self.queue.lock().expect("bulkhead queue lock poisoned")
+    }
+
+    /// Takes capacity right away, unless it's out or other requests are
+    /// already waiting for it.
+    fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        if !self.queue().waiting.is_empty() {
+            return None;
+        }
+        Arc::clone(&self.semaphore).try_acquire_owned().ok()
+    }
+
+    /// Waits in the queue for capacity. Leaves the queue if cancelled.
+    async fn acquire(
+        &self,
+        priority: Priority,
+    ) -> Option<OwnedSemaphorePermit> {
+        let place = {
+            let mut queue = self.queue();
+            let place = (Reverse(priority), queue.next_seq);
+            queue.next_seq += 1;
+            queue.waiting.insert(place);
+            place
+        };
+        let _queued = Queued {
+            bulkhead: self,
+            place,
+        };
+        // a higher priority request may have jumped the queue
+        self.front_changed.notify_waiters();
+        loop {
+            let front_changed = self.front_changed.notified();
+            if self.queue().waiting.first() != Some(&place) {
+                front_changed.await;
+                continue;
+            }
+            tokio::select! {
+                permit = Arc::clone(&self.semaphore).acquire_owned() => {
+                    return permit.ok();
+                }
+                () = front_changed => {}
+            }
+        }
+    }
+}
+
+/// Removes a request from the queue once it stops waiting.
+struct Queued<'a> {
+    bulkhead: &'a Bulkhead,
+    place: Place,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.bulkhead.queue().waiting.remove(&self.place);
+        self.bulkhead.front_changed.notify_waiters();
+    }
+}
+
+/// Isolates providers from each other by bounding the number of requests
+/// in flight to each of them.
+#[derive(Debug)]
+pub struct Bulkheads {
+    config: BulkheadConfig,
+    bulkheads: RwLock<HashMap<InferenceProvider, Arc<Bulkhead>>>,
+}
+
+impl Bulkheads {
+    #[must_use]
+    pub fn new(config: BulkheadConfig) -> Self {
+        Self {
+            config,
+            bulkheads: RwLock::default(),
+        }
+    }
+
+    fn bulkhead(&self, provider: &InferenceProvider) -> Arc<Bulkhead> {
+        if let Some(bulkhead) = self
+            .bulkheads
+            .read()
+            .expect("bulkhead lock poisoned")
+            .get(provider)
+        {
+            return Arc::clone(bulkhead);
+        }
+        let mut bulkheads =
+            self.bulkheads.write().expect("bulkhead lock poisoned");
+        Arc::clone(bulkheads.entry(provider.clone()).or_insert_with(|| {
+            Arc::new(Bulkhead::new(
+                self.config.max_concurrent_requests(provider),
+            ))
+        }))
+    }
+
+    /// Acquires capacity for a request to `provider`, waiting at most the
+    /// configured `max-wait` for it behind higher priority requests.
+    /// [`Priority::Low`] requests don't wait, so that waiting capacity goes
+    /// to higher priority requests. The capacity is released once the
+    /// returned permit is dropped.
+    ///
+    /// Returns `None` if the provider's bulkhead is full.
+    pub async fn acquire(
+        &self,
+        provider: &InferenceProvider,
+        priority: Priority,
+    ) -> Option<OwnedSemaphorePermit> {
+        let bulkhead = self.bulkhead(provider);
+        if let Some(permit) = bulkhead.try_acquire() {
+            return Some(permit);
+        }
+        if priority == Priority::Low {
+            return None;
+        }
+        tokio::time::timeout(self.config.max_wait, bulkhead.acquire(priority))
+            .await
+            .ok()?
+    }
+
+    /// Number of requests currently in flight to `provider`.
+    #[must_use]
+    pub fn in_flight(&self, provider: &InferenceProvider) -> usize {
+        self.config
+            .max_concurrent_requests(provider)
+            .saturating_sub(
+                self.bulkhead(provider).semaphore.available_permits(),
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use indexmap::IndexMap;
+
+    use super::*;
+
+    fn bulkheads() -> Bulkheads {
+        Bulkheads::new(BulkheadConfig {
+            max_concurrent_requests: 2,
+            providers: IndexMap::from([(InferenceProvider::Anthropic, 1)]),
+            max_wait: Duration::from_millis(10),
+        })
+    }
+
+    #[tokio::test]
+    async fn full_bulkhead_rejects() {
+        let bulkheads = bulkheads();
+        let openai = InferenceProvider::OpenAI;
+        let _first =
+            bulkheads.acquire(&openai, Priority::Normal).await.unwrap();
+        let second =
+            bulkheads.acquire(&openai, Priority::Normal).await.unwrap();
+        assert_eq!(bulkheads.in_flight(&openai), 2);
+        assert!(bulkheads.acquire(&openai, Priority::Normal).await.is_none());
+
+        drop(second);
+        assert!(bulkheads.acquire(&openai, Priority::Normal).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn providers_are_isolated() {
+        let bulkheads = bulkheads();
+        let anthropic = InferenceProvider::Anthropic;
+        let _permit = bulkheads
+            .acquire(&anthropic, Priority::Normal)
+            .await
+            .unwrap();
+        assert!(
+            bulkheads
+                .acquire(&anthropic, Priority::Normal)
+                .await
+                .is_none()
+        );
+        assert!(
+            bulkheads
+                .acquire(&InferenceProvider::OpenAI, Priority::Normal)
+                .await
+                .is_some()
+        );
+    }
+
+    #[tokio::test]
+    async fn waiting_requests_get_released_capacity() {
+        let bulkheads = Arc::new(Bulkheads::new(BulkheadConfig {
+            max_concurrent_requests: 1,
+            providers: IndexMap::new(),
+            max_wait: Duration::from_secs(5),
+        }));
+        let provider = InferenceProvider::OpenAI;
+        let permit = bulkheads
+            .acquire(&provider, Priority::Normal)
+            .await
+            .unwrap();
+        let waiting = tokio::spawn({
+            let bulkheads = Arc::clone(&bulkheads);
+            async move {
+                bulkheads
+                    .acquire(&provider, Priority::Normal)
+                    .await
+                    .is_some()
+            }
+        });
+        tokio::task::yield_now().await;
+        drop(permit);
+        assert!(waiting.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn higher_priority_requests_are_served_first() {
+        let bulkheads = Arc::new(Bulkheads::new(BulkheadConfig {
+            max_concurrent_requests: 1,
+            providers: IndexMap::new(),
+            max_wait: Duration::from_secs(5),
+        }));
+        let provider = InferenceProvider::OpenAI;
+        let permit = bulkheads
+            .acquire(&provider, Priority::Normal)
+            .await
+            .unwrap();
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        for priority in [Priority::Normal, Priority::High] {
+            tokio::spawn({
+                let bulkheads = Arc::clone(&bulkheads);
+                let provider = provider.clone();
+                let tx = tx.clone();
+                async move {
+                    let permit =
+                        bulkheads.acquire(&provider, priority).await.unwrap();
+                    tx.send(priority).unwrap();
+                    tokio::task::yield_now().await;
+                    drop(permit);
+                }
+            });
+            tokio::task::yield_now().await;
+        }
+        drop(permit);
+        assert_eq!(rx.recv().await, Some(Priority::High));
+        assert_eq!(rx.recv().await, Some(Priority::Normal));
+    }
+
+    #[tokio::test]
+    async fn low_priority_requests_dont_wait() {
+        let bulkheads = Bulkheads::new(BulkheadConfig {
+            max_concurrent_requests: 1,
+            providers: IndexMap::new(),
+            max_wait: Duration::from_secs(5),
+        });
+        let provider = InferenceProvider::OpenAI;
+        let _permit = bulkheads
+            .acquire(&provider, Priority::Normal)
+            .await
+            .unwrap();
+        assert!(bulkheads.acquire(&provider, Priority::Low).await.is_none());
+    }
+}
@@ -0,0 +1,273 @@
+//! Faults injected into requests to providers, if chaos is enabled.
+//!
+//! Faults are added through the admin API, each for a share of the
+//! requests to a provider, or to any provider, until its TTL expires.
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::StreamExt;
+use http::StatusCode;
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::{
+    config::chaos::ChaosConfig,
+    error::{
+        api::ApiError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    types::{
+        body::{Body, BodyReader},
+        provider::InferenceProvider,
+    },
+};
+
+/// A fault to inject, as added through the admin API.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub struct Fault {
+    /// The provider whose requests are faulted. Any provider's if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<InferenceProvider>,
+    /// The share of requests which are faulted, from 0 to 1.
+    pub rate: Decimal,
+    #[serde(with = "humantime_serde")]
+    pub ttl: Duration,
+    #[serde(flatten)]
+    pub kind: FaultKind,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum FaultKind {
+    /// Delays the request before it's sent to the provider.
+    Latency {
+        #[serde(with = "humantime_serde")]
+        delay: Duration,
+    },
+    /// Responds with `status` instead of sending the request.
+    Error { status: u16 },
+    /// Ends streamed responses with an error after `after-chunks` chunks.
+    DropStream {
+        #[serde(rename = "after-chunks", default)]
+        after_chunks: usize,
+    },
+}
+
+impl Fault {
+    /// Checks that the fault can be injected.
+    pub fn validate(&self) -> Result<(), InvalidRequestError> {
+        if self.rate < Decimal::ZERO || self.rate > Decimal::ONE {
+            return Err(InvalidRequestError::InvalidFault(
+                "rate must be from 0 to 1".to_string(),
+            ));
+        }
+        if let FaultKind::Error { status } = self.kind
+            && !StatusCode::from_u16(status).is_ok_and(|status| {
+                status.is_client_error() || status.is_server_error()
+            })
+        {
+            return Err(InvalidRequestError::InvalidFault(format!(
+                "{status} is not an error status"
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// A fault which is being injected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ActiveFault {
+    pub id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub fault: Fault,
+}
+
+#[derive(Debug, Clone)]
+pub struct Faults {
+    config: ChaosConfig,
+    faults: Arc<RwLock<Vec<ActiveFault>>>,
+}
+
+impl Faults {
+    #[must_use]
+    pub fn new(config: ChaosConfig) -> Self {
+        Self {
+            config,
+            faults: Arc::default(),
+        }
+    }
+
+    /// Starts injecting `fault`, with its TTL capped at `max-ttl`.
+    pub fn add(&self, mut fault: Fault) -> ActiveFault {
+        fault.ttl = fault.ttl.min(self.config.max_ttl);
+        let active = ActiveFault {
+            id: Uuid::new_v4(),
+            expires_at: Utc::now()
+                + chrono::Duration::from_std(fault.ttl).unwrap_or_default(),
+            fault,
+        };
+        self.faults
+            .write()
+            .expect("faults lock poisoned")
+            .push(active.clone());
+        tracing::warn!(
+            id = %active.id,
+            fault = ?active.fault,
+            "injecting fault"
+        );
+        active
+    }
+
+    /// Stops injecting the fault with `id`, or every fault if `None`.
+    /// Returns the number of faults removed.
+    pub fn remove(&self, id: Option<Uuid>) -> usize {
+        let mut faults = self.faults.write().expect("faults lock poisoned");
+        let before = faults.len();
+        faults.retain(|active| id.is_some_and(|id| active.id != id));
+        before - faults.len()
+    }
+
+    /// The faults which haven't expired.
+    #[must_use]
+    pub fn active(&self) -> Vec<ActiveFault> {
+        let now = Utc::now();
+        let mut faults = self.faults.write().expect("faults lock poisoned");
+        faults.retain(|active| active.expires_at > now);
+        faults.clone()
+    }
+
+    /// Draws the fault to inject into a request to `provider`, if any.
+    #[must_use]
+    pub fn draw(&self, provider: &InferenceProvider) -> Option<FaultKind> {
+        self.active().into_iter().find_map(|active| {
+            let fault = active.fault;
+            let applies = fault
+                .provider
+                .as_ref()
+                .is_none_or(|target| target == provider);
+            let rate = fault.rate.to_f64().unwrap_or_default();
+            (applies && rand::random::<f64>() < rate).then_some(fault.kind)
+        })
+    }
+}
+
+/// The response of an injected [`FaultKind::Error`], as it would be
+/// dispatched.
+#[must_use]
+pub fn error_response(
+    status: u16,
+) -> (http::Response<Body>, BodyReader, oneshot::Receiver<()>) {
+    let status =
+        StatusCode::from_u16(status).unwrap_or(StatusCode::SERVICE_UNAVAILABLE);
+    let body = serde_json::json!({
+        "error": {
+            "message": "fault injected by the gateway",
+            "type": "server_error",
+        }
+    });
+    let stream = futures::stream::once(async move {
+        Ok::<_, ApiError>(Bytes::from(body.to_string()))
+    });
+    let (body, body_reader, tfft_rx) = BodyReader::wrap_stream(stream, false);
+    let response = http::Response::builder()
+        .status(status)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(body)
+        .expect("response with a valid status always builds");
+    (response, body_reader, tfft_rx)
+}
+
+/// Ends `body` with an error after `after_chunks` chunks, as a provider
+/// dropping the connection mid-stream would.
+#[must_use]
+pub fn drop_stream(body: Body, after_chunks: usize) -> Body {
+    let dropped = futures::stream::once(async {
+        Err::<Bytes, ApiError>(ApiError::Internal(InternalError::Internal))
+    });
+    let stream = body
+        .into_data_stream()
+        .map(|chunk| {
+            chunk.map_err(|e| InternalError::CollectBodyError(e).into())
+        })
+        .take(after_chunks)
+        .chain(dropped);
+    Body::from_stream(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    fn fault(provider: Option<InferenceProvider>, rate: i64) -> Fault {
+        Fault {
+            provider,
+            rate: Decimal::from(rate),
+            ttl: Duration::from_secs(60 * 60 * 24),
+            kind: FaultKind::Error { status: 503 },
+        }
+    }
+
+    #[test]
+    fn faults_apply_to_their_provider_until_removed() {
+        let faults = Faults::new(ChaosConfig::default());
+        let active = faults.add(fault(Some(InferenceProvider::OpenAI), 1));
+        assert!(active.expires_at <= Utc::now() + chrono::Duration::hours(1));
+        faults.add(fault(None, 0));
+        assert_eq!(
+            faults.draw(&InferenceProvider::OpenAI),
+            Some(FaultKind::Error { status: 503 })
+        );
+        assert_eq!(faults.draw(&InferenceProvider::Anthropic), None);
+        assert_eq!(faults.remove(Some(active.id)), 1);
+        assert_eq!(faults.draw(&InferenceProvider::OpenAI), None);
+        assert_eq!(faults.remove(None), 1);
+        assert!(faults.active().is_empty());
+    }
+
+    #[tokio::test]
+    async fn dropped_streams_end_with_an_error() {
+        let chunks = ["a", "b", "c"].map(Ok::<_, ApiError>);
+        let body = Body::from_stream(futures::stream::iter(chunks));
+        let mut body = drop_stream(body, 2);
+        let mut received = Vec::new();
+        while let Some(frame) = body.frame().await {
+            match frame {
+                Ok(frame) => received.push(frame.into_data().unwrap()),
+                Err(_) => break,
+            }
+        }
+        assert_eq!(received, ["a", "b"]);
+    }
+
+    #[test]
+    fn faults_are_parsed_from_json() {
+        let mut fault: Fault = serde_json::from_value(serde_json::json!({
+            "provider": "openai",
+            "rate": 0.1,
+            "ttl": "10m",
+            "type": "latency",
+            "delay": "2s",
+        }))
+        .unwrap();
+        assert_eq!(
+            fault.kind,
+            FaultKind::Latency {
+                delay: Duration::from_secs(2)
+            }
+        );
+        assert!(fault.validate().is_ok());
+        fault.kind = FaultKind::Error { status: 200 };
+        assert!(fault.validate().is_err());
+    }
+}
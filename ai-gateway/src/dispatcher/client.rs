@@ -1,18 +1,32 @@
+use std::sync::Arc;
+
 use bytes::Bytes;
 use futures::StreamExt;
 use http_body_util::BodyExt;
-use reqwest::RequestBuilder;
+use reqwest::{RequestBuilder, redirect};
 use reqwest_eventsource::{Event, EventSource, RequestBuilderExt};
 use tracing::{Instrument, info_span};
 
 use crate::{
     app_state::AppState,
+    config::{
+        dispatcher::{EgressConfig, RedirectPolicy},
+        providers::{ApiKeyLocation, ProvidersConfig},
+    },
     discover::monitor::metrics::EndpointMetricsRegistry,
     dispatcher::{
-        SSEStream, anthropic_client::Client as AnthropicClient,
+        SSEStream,
+        anthropic_client::Client as AnthropicClient,
+        azure_openai_client::Client as AzureOpenAIClient,
         bedrock_client::Client as BedrockClient,
+        custom_client::Client as CustomClient,
+        gemini_client::Client as GeminiClient,
         ollama_client::Client as OllamaClient,
         openai_compatible_client::Client as OpenAICompatibleClient,
+        resolve::AddressFamilyResolver,
+        tls::ProviderTls,
+        vertex_client::Client as VertexClient,
+        workers_ai_client::{Client as WorkersAiClient, WORKERS_AI},
     },
     endpoints::ApiEndpoint,
     error::{
@@ -22,9 +36,14 @@ use crate::{
     types::{
         extensions::AuthContext,
         provider::{InferenceProvider, ProviderKey},
+        secret::Secret,
     },
+    utils::task,
 };
 
+/// The number of redirects followed by reqwest's default policy.
+const MAX_REDIRECTS: usize = 10;
+
 pub trait ProviderClient {
     async fn authenticate(
         &self,
@@ -48,7 +67,18 @@ impl ProviderClient for Client {
         match self {
             Client::Bedrock(inner) => inner
                 .extract_and_sign_aws_headers(request_builder, req_body_bytes),
-            Client::OpenAICompatible(_) | Client::Anthropic(_) => {
+            Client::Vertex(inner) => inner.authenticate(request_builder).await,
+            Client::Gemini(inner)
+                if !app_state.0.config.deployment_target.is_cloud() =>
+            {
+                Ok(inner.authenticate(request_builder))
+            }
+            Client::OpenAICompatible(_)
+            | Client::Anthropic(_)
+            | Client::AzureOpenAI(_)
+            | Client::Gemini(_)
+            | Client::WorkersAi(_)
+            | Client::Custom(_) => {
                 self.authenticate_inner(
                     app_state,
                     request_builder,
@@ -68,9 +98,43 @@ pub enum Client {
     Anthropic(AnthropicClient),
     Ollama(OllamaClient),
     Bedrock(BedrockClient),
+    AzureOpenAI(AzureOpenAIClient),
+    Vertex(VertexClient),
+    Gemini(GeminiClient),
+    WorkersAi(WorkersAiClient),
+    Custom(CustomClient),
 }
 
 impl Client {
+    /// Authenticates a request with `key`, for providers authenticated with
+    /// an API key.
+    pub fn set_auth_header(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+        key: &Secret<String>,
+    ) -> reqwest::RequestBuilder {
+        match self {
+            Client::OpenAICompatible(_) | Client::WorkersAi(_) => {
+                OpenAICompatibleClient::set_auth_header(request_builder, key)
+            }
+            Client::Anthropic(_) => {
+                AnthropicClient::set_auth_header(request_builder, key)
+            }
+            Client::AzureOpenAI(_) => {
+                AzureOpenAIClient::set_auth_header(request_builder, key)
+            }
+            Client::Gemini(inner) => {
+                inner.set_auth_header(request_builder, key)
+            }
+            Client::Custom(inner) => {
+                inner.set_auth_header(request_builder, key)
+            }
+            Client::Ollama(_) | Client::Bedrock(_) | Client::Vertex(_) => {
+                request_builder
+            }
+        }
+    }
+
     async fn authenticate_inner(
         &self,
         app_state: &AppState,
@@ -91,23 +155,7 @@ impl Client {
                 if let Some(ProviderKey::Secret(key)) = provider_key
                     && key.expose() != ""
                 {
-                    let request_builder = match self {
-                        Client::OpenAICompatible(_) => {
-                            OpenAICompatibleClient::set_auth_header(
-                                request_builder,
-                                &key,
-                            )
-                        }
-                        Client::Anthropic(_) => {
-                            AnthropicClient::set_auth_header(
-                                request_builder,
-                                &key,
-                            )
-                        }
-                        _ => request_builder,
-                    };
-
-                    return Ok(request_builder);
+                    return Ok(self.set_auth_header(request_builder, &key));
                 }
 
                 let refetched_org_provider_keys = app_state
@@ -129,23 +177,7 @@ impl Client {
                     .await;
 
                 if let Some(ProviderKey::Secret(key)) = provider_key {
-                    let request_builder = match self {
-                        Client::OpenAICompatible(_) => {
-                            OpenAICompatibleClient::set_auth_header(
-                                request_builder,
-                                key,
-                            )
-                        }
-                        Client::Anthropic(_) => {
-                            AnthropicClient::set_auth_header(
-                                request_builder,
-                                key,
-                            )
-                        }
-                        _ => request_builder,
-                    };
-
-                    return Ok(request_builder);
+                    return Ok(self.set_auth_header(request_builder, key));
                 }
 
                 return Err(ApiError::Authentication(
@@ -177,12 +209,14 @@ impl Client {
         Ok(stream)
     }
 
+    /// `tls` is the client identity presented to the provider, if any.
     pub(crate) async fn new(
         app_state: &AppState,
         inference_provider: InferenceProvider,
+        tls: Option<&ProviderTls>,
     ) -> Result<Self, InitError> {
         if inference_provider == InferenceProvider::Ollama {
-            return Self::new_inner(app_state, inference_provider, None);
+            return Self::new_inner(app_state, inference_provider, None, tls);
         }
         let api_key = &app_state
             .0
@@ -190,23 +224,68 @@ impl Client {
             .get_provider_key(&inference_provider, None)
             .await;
 
-        Self::new_inner(app_state, inference_provider, api_key.as_ref())
+        Self::new_inner(app_state, inference_provider, api_key.as_ref(), tls)
     }
 
     fn new_inner(
         app_state: &AppState,
         inference_provider: InferenceProvider,
         api_key: Option<&ProviderKey>,
+        tls: Option<&ProviderTls>,
     ) -> Result<Self, InitError> {
         // connection timeout, timeout, etc.
-        let base_client = reqwest::Client::builder()
+        let mut base_client = reqwest::Client::builder()
             .connect_timeout(app_state.0.config.dispatcher.connection_timeout)
             .timeout(app_state.0.config.dispatcher.timeout)
             .tcp_nodelay(true);
+        let connections = &app_state.0.config.dispatcher.connections;
+        if let Some(max_age) = connections.max_age(&inference_provider) {
+            // connections idle for longer would be replaced as soon as they
+            // are reused
+            base_client = base_client.pool_idle_timeout(max_age);
+        }
+        base_client = base_client.redirect(redirect_policy(
+            connections.redirects(&inference_provider),
+            app_state.0.config.dispatcher.egress.clone(),
+            authenticates_with_authorization(
+                &app_state.0.config.providers,
+                &inference_provider,
+            ),
+        ));
+        if let Some(resolver) = AddressFamilyResolver::new(
+            connections.address_family(&inference_provider),
+            connections.happy_eyeballs(&inference_provider),
+        ) {
+            base_client = base_client.dns_resolver(Arc::new(resolver));
+        }
+        if let Some(tls) = tls {
+            base_client = tls.apply(base_client);
+        }
 
         match inference_provider {
+            InferenceProvider::Named(ref name) if name == WORKERS_AI => {
+                Ok(Self::WorkersAi(WorkersAiClient::new(
+                    app_state,
+                    base_client,
+                    api_key,
+                )?))
+            }
+            InferenceProvider::Named(_)
+                if app_state
+                    .config()
+                    .providers
+                    .get(&inference_provider)
+                    .is_some_and(|config| config.auth_header.is_some()) =>
+            {
+                Ok(Self::Custom(CustomClient::new(
+                    app_state,
+                    base_client,
+                    inference_provider,
+                    api_key,
+                )?))
+            }
             InferenceProvider::OpenAI
-            | InferenceProvider::GoogleGemini
+            | InferenceProvider::Cohere
             | InferenceProvider::Named(_) => {
                 let openai_compatible_client = OpenAICompatibleClient::new(
                     app_state,
@@ -216,6 +295,9 @@ impl Client {
                 )?;
                 Ok(Self::OpenAICompatible(openai_compatible_client))
             }
+            InferenceProvider::GoogleGemini => Ok(Self::Gemini(
+                GeminiClient::new(app_state, base_client, api_key)?,
+            )),
             InferenceProvider::Anthropic => Ok(Self::Anthropic(
                 AnthropicClient::new(app_state, base_client, api_key)?,
             )),
@@ -225,10 +307,88 @@ impl Client {
             InferenceProvider::Ollama => {
                 Ok(Self::Ollama(OllamaClient::new(app_state, base_client)?))
             }
+            InferenceProvider::AzureOpenAI => Ok(Self::AzureOpenAI(
+                AzureOpenAIClient::new(app_state, base_client, api_key)?,
+            )),
+            InferenceProvider::Vertex => Ok(Self::Vertex(VertexClient::new(
+                app_state,
+                base_client,
+                api_key,
+            )?)),
         }
     }
 }
 
+/// Whether the credentials of `provider` are only sent in the
+/// `authorization` header, which reqwest drops on redirects to other
+/// origins, unlike provider specific headers such as `x-api-key`.
+fn authenticates_with_authorization(
+    providers: &ProvidersConfig,
+    provider: &InferenceProvider,
+) -> bool {
+    let config = providers.get(provider);
+    match provider {
+        InferenceProvider::Anthropic
+        | InferenceProvider::AzureOpenAI
+        | InferenceProvider::Bedrock => false,
+        InferenceProvider::GoogleGemini => config.is_none_or(|config| {
+            config.api_key_location != ApiKeyLocation::Header
+        }),
+        InferenceProvider::Named(_) => config
+            .and_then(|config| config.auth_header.as_ref())
+            .is_none_or(|auth_header| {
+                auth_header
+                    .name
+                    .eq_ignore_ascii_case(http::header::AUTHORIZATION.as_str())
+            }),
+        InferenceProvider::OpenAI
+        | InferenceProvider::Cohere
+        | InferenceProvider::Ollama
+        | InferenceProvider::Vertex => true,
+    }
+}
+
+/// Follows redirects according to `policy`, refusing redirects to hosts
+/// which aren't on the egress allowlist.
+///
+/// Redirects to other origins are only followed if the credentials of the
+/// provider are dropped on the way, that is if `strips_credentials`.
+fn redirect_policy(
+    policy: RedirectPolicy,
+    egress: Option<EgressConfig>,
+    strips_credentials: bool,
+) -> redirect::Policy {
+    if policy == RedirectPolicy::None {
+        return redirect::Policy::none();
+    }
+    redirect::Policy::custom(move |attempt| {
+        if let Some(egress) = &egress
+            && !egress.allows(attempt.url())
+        {
+            let host = attempt.url().host_str().unwrap_or_default();
+            let error =
+                InternalError::EgressDenied(host.to_string()).to_string();
+            return attempt.error(error);
+        }
+        let same_host = attempt.previous().first().is_some_and(|original| {
+            original.origin() == attempt.url().origin()
+        });
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            attempt.error("too many redirects")
+        } else if !same_host
+            && (policy == RedirectPolicy::SameHost || !strips_credentials)
+        {
+            tracing::debug!(
+                url = %attempt.url(),
+                "not following redirect to another host"
+            );
+            attempt.stop()
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
 impl AsRef<reqwest::Client> for Client {
     fn as_ref(&self) -> &reqwest::Client {
         match self {
@@ -236,6 +396,11 @@ impl AsRef<reqwest::Client> for Client {
             Client::Anthropic(client) => &client.0,
             Client::Ollama(client) => &client.0,
             Client::Bedrock(client) => &client.inner,
+            Client::AzureOpenAI(client) => &client.0,
+            Client::Vertex(client) => &client.inner,
+            Client::Gemini(client) => &client.inner,
+            Client::WorkersAi(client) => &client.inner,
+            Client::Custom(client) => &client.inner,
         }
     }
 }
@@ -267,7 +432,8 @@ pub(super) async fn sse_stream(
         None => {}
     }
 
-    tokio::spawn(
+    task::spawn(
+        "stream-forwarder",
         async move {
             while let Some(ev) = event_source.next().await {
                 match ev {
@@ -426,3 +592,29 @@ fn record_stream_err_metrics(
         }).ok();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn credentials_in_other_headers_are_not_redirected() {
+        let yaml = r"
+my-vllm:
+  base-url: http://vllm.internal:8000/
+  auth-header:
+    name: x-api-key
+my-litellm:
+  base-url: http://litellm.internal:4000/
+";
+        let providers: ProvidersConfig = serde_yml::from_str(yaml).unwrap();
+        let authorization = |provider: InferenceProvider| {
+            authenticates_with_authorization(&providers, &provider)
+        };
+        assert!(authorization(InferenceProvider::OpenAI));
+        assert!(!authorization(InferenceProvider::Anthropic));
+        assert!(!authorization(InferenceProvider::AzureOpenAI));
+        assert!(authorization(InferenceProvider::Named("my-litellm".into())));
+        assert!(!authorization(InferenceProvider::Named("my-vllm".into())));
+    }
+}
@@ -0,0 +1,110 @@
+//! Custom `OpenAI` compatible providers, such as a self-hosted vLLM or
+//! `LiteLLM` server, whose API key is sent in a configured header.
+use http::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::ClientBuilder;
+
+use crate::{
+    app_state::AppState,
+    config::providers::AuthHeaderTemplate,
+    error::{init::InitError, provider::ProviderError},
+    types::{
+        provider::{InferenceProvider, ProviderKey},
+        secret::Secret,
+    },
+    utils::host_header,
+};
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub(super) inner: reqwest::Client,
+    auth_header: HeaderName,
+    auth_header_template: AuthHeaderTemplate,
+}
+
+impl Client {
+    pub fn new(
+        app_state: &AppState,
+        client_builder: ClientBuilder,
+        provider: InferenceProvider,
+        provider_key: Option<&ProviderKey>,
+    ) -> Result<Self, InitError> {
+        let config =
+            app_state.config().providers.get(&provider).ok_or_else(|| {
+                ProviderError::ProviderNotConfigured(provider.clone())
+            })?;
+        let auth_header_template = config
+            .auth_header
+            .clone()
+            .ok_or_else(|| InitError::InvalidAuthHeader(provider.clone()))?;
+        let auth_header = HeaderName::try_from(&auth_header_template.name)
+            .map_err(|_| InitError::InvalidAuthHeader(provider.clone()))?;
+
+        let mut default_headers = HeaderMap::new();
+        if let Some(ProviderKey::Secret(key)) = provider_key {
+            let value = HeaderValue::from_str(
+                &auth_header_template.render(key.expose()),
+            )
+            .map_err(|_| InitError::InvalidAuthHeader(provider.clone()))?;
+            default_headers.insert(auth_header.clone(), value);
+        }
+        default_headers
+            .insert(http::header::HOST, host_header(&config.base_url));
+        default_headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_str(mime::APPLICATION_JSON.essence_str())
+                .unwrap(),
+        );
+        let inner = client_builder
+            .default_headers(default_headers)
+            .build()
+            .map_err(InitError::CreateReqwestClient)?;
+        Ok(Self {
+            inner,
+            auth_header,
+            auth_header_template,
+        })
+    }
+
+    /// Authenticates a request with `key`, in the configured header.
+    #[must_use]
+    pub fn set_auth_header(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+        key: &Secret<String>,
+    ) -> reqwest::RequestBuilder {
+        request_builder.header(
+            self.auth_header.clone(),
+            self.auth_header_template.render(key.expose()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn api_key_is_sent_in_the_configured_header() {
+        let client = Client {
+            inner: reqwest::Client::new(),
+            auth_header: HeaderName::from_static("x-litellm-key"),
+            auth_header_template: AuthHeaderTemplate {
+                name: "x-litellm-key".to_string(),
+                value: "Bearer {api-key}".to_string(),
+            },
+        };
+        let request = client
+            .set_auth_header(
+                client
+                    .inner
+                    .post("http://litellm.internal/v1/chat/completions"),
+                &Secret::from("sk-1234".to_string()),
+            )
+            .build()
+            .unwrap();
+        assert_eq!(
+            request.headers().get("x-litellm-key").unwrap(),
+            "Bearer sk-1234"
+        );
+    }
+}
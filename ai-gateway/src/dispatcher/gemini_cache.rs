@@ -0,0 +1,276 @@
+//! Gemini context caching.
+//!
+//! Cached contents created through the gateway are all created with the same
+//! provider key, so without further checks any client could use or delete
+//! the cached contents of any other client. The gateway therefore tracks
+//! which API key created each cached content, and only lets that key
+//! reference it.
+//!
+//! Ownership is kept in memory: cached contents created before a restart, or
+//! through another replica, can no longer be referenced through the gateway.
+use std::sync::RwLock;
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::Value;
+
+use crate::{control_plane::types::hash_key, types::extensions::AuthContext};
+
+/// Path of the cached contents collection on the Gemini API.
+const CACHED_CONTENTS_PATH: &str = "v1beta/cachedContents";
+
+#[derive(Debug)]
+struct Owner {
+    key_hash: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// Owners of the Gemini cached contents created through the gateway.
+#[derive(Debug, Default)]
+pub struct CachedContents {
+    owners: RwLock<HashMap<String, Owner>>,
+}
+
+impl CachedContents {
+    /// Records that `auth_ctx` created the cached content described by
+    /// `cached_content`, the response of a create request.
+    pub fn record(&self, auth_ctx: &AuthContext, cached_content: &Value) {
+        let Some(name) = cached_content.get("name").and_then(Value::as_str)
+        else {
+            return;
+        };
+        let expires_at = cached_content
+            .get("expireTime")
+            .and_then(Value::as_str)
+            .and_then(|expire_time| {
+                DateTime::parse_from_rfc3339(expire_time).ok()
+            })
+            .map(|expires_at| expires_at.with_timezone(&Utc));
+        let now = Utc::now();
+        let mut owners =
+            self.owners.write().expect("cached contents lock poisoned");
+        owners.retain(|_, owner| !owner.is_expired(now));
+        owners.insert(
+            name.to_string(),
+            Owner {
+                key_hash: owner_key(auth_ctx),
+                expires_at,
+            },
+        );
+    }
+
+    /// Whether `auth_ctx` created the cached content `name`.
+    #[must_use]
+    pub fn is_owned_by(&self, name: &str, auth_ctx: &AuthContext) -> bool {
+        self.owners
+            .read()
+            .expect("cached contents lock poisoned")
+            .get(name)
+            .is_some_and(|owner| {
+                !owner.is_expired(Utc::now())
+                    && owner.key_hash == owner_key(auth_ctx)
+            })
+    }
+
+    /// Removes the cached contents not created by `auth_ctx` from `list`, the
+    /// response of a list request.
+    pub fn retain_owned(&self, auth_ctx: &AuthContext, list: &mut Value) {
+        if let Some(cached_contents) =
+            list.get_mut("cachedContents").and_then(Value::as_array_mut)
+        {
+            cached_contents.retain(|cached_content| {
+                cached_content
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .is_some_and(|name| self.is_owned_by(name, auth_ctx))
+            });
+        }
+    }
+}
+
+impl Owner {
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+fn owner_key(auth_ctx: &AuthContext) -> String {
+    hash_key(auth_ctx.api_key.expose())
+}
+
+/// A request to the Gemini cached contents API.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CachedContentsRequest {
+    Create,
+    List,
+}
+
+impl CachedContentsRequest {
+    #[must_use]
+    pub fn new(method: &http::Method, path: &str) -> Option<Self> {
+        if path.trim_matches('/') != CACHED_CONTENTS_PATH {
+            return None;
+        }
+        match *method {
+            http::Method::POST => Some(Self::Create),
+            http::Method::GET => Some(Self::List),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the name of the cached content referenced by a request to Gemini,
+/// either in its path or in its body.
+///
+/// Native requests reference cached contents with `cachedContent`, requests
+/// to the OpenAI compatible API with `extra_body.google.cached_content`.
+#[must_use]
+pub fn referenced_cached_content(path: &str, body: &Bytes) -> Option<String> {
+    let path = path.trim_start_matches('/');
+    if let Some(id) = path
+        .strip_prefix(CACHED_CONTENTS_PATH)
+        .and_then(|rest| rest.strip_prefix('/'))
+    {
+        let id = id.split([':', '?']).next().unwrap_or(id);
+        return Some(format!("cachedContents/{id}"));
+    }
+    let body = serde_json::from_slice::<Value>(body).ok()?;
+    body.get("cachedContent")
+        .or_else(|| body.pointer("/extra_body/google/cached_content"))
+        .and_then(Value::as_str)
+        .map(ToString::to_string)
+}
+
+/// Returns the cached content referenced by an OpenAI style request, either
+/// as `cached_content` or, as with Gemini's OpenAI compatible API, as
+/// `extra_body.google.cached_content`.
+#[must_use]
+pub fn openai_cached_content(body: &Value) -> Option<&str> {
+    body.get("cached_content")
+        .or_else(|| body.pointer("/extra_body/google/cached_content"))
+        .and_then(Value::as_str)
+}
+
+/// Adds a reference to `cached_content` to a request to Gemini's OpenAI
+/// compatible API.
+pub fn with_cached_content(body: &mut Value, cached_content: &str) {
+    if let Value::Object(body) = body {
+        body.insert(
+            "extra_body".to_string(),
+            serde_json::json!({
+                "google": { "cached_content": cached_content }
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::types::{
+        org::OrgId, priority::Priority, secret::Secret, user::UserId,
+    };
+
+    fn auth_ctx(api_key: &str) -> AuthContext {
+        AuthContext {
+            api_key: Secret::from(api_key.to_string()),
+            user_id: UserId::new(uuid::Uuid::new_v4()),
+            org_id: OrgId::new(uuid::Uuid::new_v4()),
+            max_priority: Priority::High,
+            tier: None,
+        }
+    }
+
+    #[test]
+    fn cached_contents_are_owned_by_their_creator() {
+        let cached_contents = CachedContents::default();
+        let (alice, bob) = (auth_ctx("alice"), auth_ctx("bob"));
+        cached_contents.record(&alice, &json!({"name": "cachedContents/a"}));
+        assert!(cached_contents.is_owned_by("cachedContents/a", &alice));
+        assert!(!cached_contents.is_owned_by("cachedContents/a", &bob));
+        assert!(!cached_contents.is_owned_by("cachedContents/b", &alice));
+
+        let mut list = json!({"cachedContents": [
+            {"name": "cachedContents/a"},
+            {"name": "cachedContents/b"},
+        ]});
+        cached_contents.retain_owned(&alice, &mut list);
+        assert_eq!(
+            list,
+            json!({"cachedContents": [{"name": "cachedContents/a"}]})
+        );
+    }
+
+    #[test]
+    fn expired_cached_contents_are_not_owned() {
+        let cached_contents = CachedContents::default();
+        let alice = auth_ctx("alice");
+        cached_contents.record(
+            &alice,
+            &json!({
+                "name": "cachedContents/a",
+                "expireTime": "2020-01-01T00:00:00Z",
+            }),
+        );
+        assert!(!cached_contents.is_owned_by("cachedContents/a", &alice));
+    }
+
+    #[test]
+    fn references() {
+        let empty = Bytes::new();
+        assert_eq!(
+            referenced_cached_content("v1beta/cachedContents/abc", &empty)
+                .as_deref(),
+            Some("cachedContents/abc")
+        );
+        assert_eq!(
+            referenced_cached_content("v1beta/cachedContents", &empty),
+            None
+        );
+        let native = Bytes::from_static(
+            br#"{"cachedContent": "cachedContents/abc", "contents": []}"#,
+        );
+        assert_eq!(
+            referenced_cached_content(
+                "v1beta/models/gemini-2.0-flash:generateContent",
+                &native
+            )
+            .as_deref(),
+            Some("cachedContents/abc")
+        );
+        let mut openai = json!({"model": "gemini-2.0-flash", "messages": []});
+        with_cached_content(&mut openai, "cachedContents/abc");
+        let openai = Bytes::from(serde_json::to_vec(&openai).unwrap());
+        assert_eq!(
+            referenced_cached_content(
+                "v1beta/openai/chat/completions",
+                &openai
+            )
+            .as_deref(),
+            Some("cachedContents/abc")
+        );
+    }
+
+    #[test]
+    fn cached_contents_requests() {
+        let path = "v1beta/cachedContents";
+        assert_eq!(
+            CachedContentsRequest::new(&http::Method::POST, path),
+            Some(CachedContentsRequest::Create)
+        );
+        assert_eq!(
+            CachedContentsRequest::new(&http::Method::GET, path),
+            Some(CachedContentsRequest::List)
+        );
+        assert_eq!(
+            CachedContentsRequest::new(
+                &http::Method::GET,
+                "v1beta/cachedContents/abc"
+            ),
+            None
+        );
+    }
+}
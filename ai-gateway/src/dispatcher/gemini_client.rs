@@ -0,0 +1,131 @@
+//! Gemini, whose API key is sent as a bearer token, in the `x-goog-api-key`
+//! header or in the `key` query parameter, as configured by
+//! [`ApiKeyLocation`].
+use http::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::ClientBuilder;
+
+use crate::{
+    app_state::AppState,
+    config::providers::ApiKeyLocation,
+    error::{init::InitError, provider::ProviderError},
+    types::{
+        provider::{InferenceProvider, ProviderKey},
+        secret::Secret,
+    },
+    utils::host_header,
+};
+
+const API_KEY_HEADER: HeaderName = HeaderName::from_static("x-goog-api-key");
+const API_KEY_QUERY_PARAM: &str = "key";
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub(super) inner: reqwest::Client,
+    api_key_location: ApiKeyLocation,
+    /// The key of requests not authenticated with a key of their own.
+    api_key: Option<Secret<String>>,
+}
+
+impl Client {
+    pub fn new(
+        app_state: &AppState,
+        client_builder: ClientBuilder,
+        provider_key: Option<&ProviderKey>,
+    ) -> Result<Self, InitError> {
+        let config = app_state
+            .config()
+            .providers
+            .get(&InferenceProvider::GoogleGemini)
+            .ok_or(ProviderError::ProviderNotConfigured(
+                InferenceProvider::GoogleGemini,
+            ))?;
+
+        let mut default_headers = HeaderMap::new();
+        default_headers
+            .insert(http::header::HOST, host_header(&config.base_url));
+        default_headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_str(mime::APPLICATION_JSON.essence_str())
+                .unwrap(),
+        );
+        let inner = client_builder
+            .default_headers(default_headers)
+            .build()
+            .map_err(InitError::CreateReqwestClient)?;
+        let api_key = match provider_key {
+            Some(ProviderKey::Secret(key)) => Some(key.clone()),
+            _ => None,
+        };
+        Ok(Self {
+            inner,
+            api_key_location: config.api_key_location,
+            api_key,
+        })
+    }
+
+    /// Authenticates a request with the configured key, if any.
+    #[must_use]
+    pub fn authenticate(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+    ) -> reqwest::RequestBuilder {
+        match &self.api_key {
+            Some(key) => self.set_auth_header(request_builder, key),
+            None => request_builder,
+        }
+    }
+
+    /// Authenticates a request with `key`, where the configuration sends
+    /// it.
+    #[must_use]
+    pub fn set_auth_header(
+        &self,
+        request_builder: reqwest::RequestBuilder,
+        key: &Secret<String>,
+    ) -> reqwest::RequestBuilder {
+        match self.api_key_location {
+            ApiKeyLocation::Bearer => request_builder.bearer_auth(key.expose()),
+            ApiKeyLocation::Header => {
+                request_builder.header(API_KEY_HEADER, key.expose())
+            }
+            ApiKeyLocation::Query => request_builder
+                .query(&[(API_KEY_QUERY_PARAM, key.expose().as_str())]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(api_key_location: ApiKeyLocation) -> reqwest::Request {
+        let client = Client {
+            inner: reqwest::Client::new(),
+            api_key_location,
+            api_key: Some(Secret::from("gemini-key".to_string())),
+        };
+        client
+            .authenticate(client.inner.post(
+                "https://generativelanguage.googleapis.com/v1beta/models",
+            ))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn api_key_is_sent_where_configured() {
+        let bearer = request(ApiKeyLocation::Bearer);
+        assert_eq!(
+            bearer.headers().get(http::header::AUTHORIZATION).unwrap(),
+            "Bearer gemini-key"
+        );
+
+        let header = request(ApiKeyLocation::Header);
+        assert_eq!(header.headers().get(API_KEY_HEADER).unwrap(), "gemini-key");
+        assert!(header.headers().get(http::header::AUTHORIZATION).is_none());
+
+        let query = request(ApiKeyLocation::Query);
+        assert_eq!(query.url().query(), Some("key=gemini-key"));
+        assert!(query.headers().get(API_KEY_HEADER).is_none());
+    }
+}
@@ -1,10 +1,27 @@
+pub mod adaptive_limit;
 pub mod anthropic_client;
+pub mod attempt;
+pub mod azure_openai_client;
 mod bedrock_client;
+pub mod bulkhead;
+pub mod chaos;
 pub mod client;
+pub mod custom_client;
 mod extensions;
+pub mod gemini_cache;
+pub mod gemini_client;
 pub mod ollama_client;
 pub mod openai_compatible_client;
+pub mod recycle;
+pub mod regions;
+pub mod resolve;
+pub mod retry_budget;
+pub mod retry_on;
 pub mod service;
+pub mod stream_retry;
+pub mod tls;
+pub mod vertex_client;
+pub mod workers_ai_client;
 
 use std::pin::Pin;
 
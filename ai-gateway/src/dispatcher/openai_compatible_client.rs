@@ -11,6 +11,10 @@ use crate::{
     utils::host_header,
 };
 
+/// The account Fireworks serves its own models under. Models deployed to
+/// other accounts are only named by their full path.
+pub(crate) const FIREWORKS_MODEL_PREFIX: &str = "accounts/fireworks/models/";
+
 #[derive(Debug, Clone, Default)]
 pub struct Client(pub(super) reqwest::Client);
 
@@ -61,3 +65,29 @@ impl Client {
         )
     }
 }
+
+/// The path Fireworks names `model` by, if it isn't a path already.
+pub(crate) fn fireworks_model(model: &str) -> String {
+    if model.starts_with("accounts/") {
+        model.to_string()
+    } else {
+        format!("{FIREWORKS_MODEL_PREFIX}{model}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fireworks_models_are_named_by_path() {
+        assert_eq!(
+            fireworks_model("llama-v3p3-70b-instruct"),
+            "accounts/fireworks/models/llama-v3p3-70b-instruct"
+        );
+        assert_eq!(
+            fireworks_model("accounts/my-team/models/fine-tuned"),
+            "accounts/my-team/models/fine-tuned"
+        );
+    }
+}
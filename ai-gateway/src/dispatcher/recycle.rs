@@ -0,0 +1,97 @@
+//! Connection recycling.
+//!
+//! Pooled connections keep the address the provider's host resolved to when
+//! they were established, so a provider that moves to new addresses, or a
+//! DNS based failover, isn't picked up while connections are being reused.
+//! Replacing a provider's client once its connections reach their maximum
+//! age forces new connections, and with them a new resolution. Requests in
+//! flight keep the previous client until they complete.
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    app_state::AppState,
+    dispatcher::{client::Client, tls::ProviderTls},
+    error::init::InitError,
+    types::provider::InferenceProvider,
+};
+
+#[derive(Debug)]
+struct Generation {
+    client: Client,
+    created_at: Instant,
+}
+
+/// The client of a provider, replaced once it's older than the configured
+/// connection `max-age`.
+#[derive(Debug, Clone)]
+pub struct RecycledClient {
+    provider: InferenceProvider,
+    tls: Option<Arc<ProviderTls>>,
+    max_age: Option<Duration>,
+    current: Arc<RwLock<Generation>>,
+}
+
+impl RecycledClient {
+    pub async fn new(
+        app_state: &AppState,
+        provider: InferenceProvider,
+        tls: Option<ProviderTls>,
+    ) -> Result<Self, InitError> {
+        let client =
+            Client::new(app_state, provider.clone(), tls.as_ref()).await?;
+        let max_age =
+            app_state.config().dispatcher.connections.max_age(&provider);
+        Ok(Self {
+            provider,
+            tls: tls.map(Arc::new),
+            max_age,
+            current: Arc::new(RwLock::new(Generation {
+                client,
+                created_at: Instant::now(),
+            })),
+        })
+    }
+
+    /// Returns the current client, replacing it first if it has reached its
+    /// maximum age.
+    pub async fn get(&self, app_state: &AppState) -> Client {
+        let (client, created_at) = {
+            let current = self.current.read().expect("client lock poisoned");
+            (current.client.clone(), current.created_at)
+        };
+        let Some(max_age) = self.max_age else {
+            return client;
+        };
+        if created_at.elapsed() < max_age {
+            return client;
+        }
+        match Client::new(app_state, self.provider.clone(), self.tls.as_deref())
+            .await
+        {
+            Ok(new_client) => {
+                let mut current =
+                    self.current.write().expect("client lock poisoned");
+                // another request may have replaced it in the meantime
+                if current.created_at == created_at {
+                    tracing::debug!(provider = %self.provider, "recycling provider connections");
+                    *current = Generation {
+                        client: new_client,
+                        created_at: Instant::now(),
+                    };
+                }
+                current.client.clone()
+            }
+            Err(error) => {
+                tracing::warn!(
+                    provider = %self.provider,
+                    error = %error,
+                    "failed to recycle provider connections"
+                );
+                client
+            }
+        }
+    }
+}
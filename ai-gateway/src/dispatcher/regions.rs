@@ -0,0 +1,248 @@
+//! Balancing and failover across the regions of a provider.
+//!
+//! A provider configured with `regions` is served by several base URLs,
+//! each with its own health. Requests are spread round-robin across the
+//! healthy regions and fail over to the next one when a region can't be
+//! reached or fails. A region that fails repeatedly is ejected for a while,
+//! so that a regional outage doesn't take the whole provider out.
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use url::Url;
+
+use crate::{
+    config::router::RouterConfig,
+    error::init::InitError,
+    types::{provider::InferenceProvider, secret::Secret},
+    utils::join_url,
+};
+
+/// Name of the region served by the provider's `base-url`.
+const DEFAULT_REGION: &str = "default";
+/// Consecutive failures after which a region is ejected.
+const FAILURE_THRESHOLD: u32 = 3;
+/// How long an ejected region is only used as a last resort.
+const EJECTION_DURATION: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+struct Health {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+#[derive(Debug)]
+pub struct Region {
+    name: String,
+    base_url: Url,
+    path_prefix: Option<String>,
+    /// If `None`, the provider's key is used.
+    api_key: Option<Secret<String>>,
+    health: Mutex<Health>,
+}
+
+impl Region {
+    fn new(
+        name: String,
+        base_url: Url,
+        path_prefix: Option<String>,
+        api_key: Option<Secret<String>>,
+    ) -> Self {
+        Self {
+            name,
+            base_url,
+            path_prefix,
+            api_key,
+            health: Mutex::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    #[must_use]
+    pub fn api_key(&self) -> Option<&Secret<String>> {
+        self.api_key.as_ref()
+    }
+
+    #[must_use]
+    pub fn target_url(&self, path_and_query: &str) -> Url {
+        join_url(&self.base_url, self.path_prefix.as_deref(), path_and_query)
+    }
+
+    /// Records the outcome of a request to this region.
+    pub fn record(&self, failed: bool) {
+        let mut health = self.health.lock().expect("region lock poisoned");
+        if !failed {
+            *health = Health::default();
+            return;
+        }
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= FAILURE_THRESHOLD {
+            health.consecutive_failures = 0;
+            health.ejected_until = Some(Instant::now() + EJECTION_DURATION);
+            tracing::warn!(region = %self.name, "ejecting failing region");
+        }
+    }
+
+    fn ejected_until(&self, now: Instant) -> Option<Instant> {
+        self.health
+            .lock()
+            .expect("region lock poisoned")
+            .ejected_until
+            .filter(|ejected_until| *ejected_until > now)
+    }
+}
+
+/// The regions of a provider, for a router.
+#[derive(Debug)]
+pub struct Regions {
+    regions: Vec<Region>,
+    next: AtomicUsize,
+}
+
+impl Regions {
+    /// Returns `None` if the router doesn't configure regions for
+    /// `provider`.
+    pub fn for_provider(
+        router_config: &RouterConfig,
+        provider: &InferenceProvider,
+    ) -> Result<Option<Self>, InitError> {
+        let Some(provider_config) = router_config
+            .providers
+            .as_ref()
+            .and_then(|providers| providers.get(provider))
+            .filter(|provider_config| !provider_config.regions.is_empty())
+        else {
+            return Ok(None);
+        };
+        let mut regions = vec![Region::new(
+            DEFAULT_REGION.to_string(),
+            provider_config.base_url.clone(),
+            provider_config.path_prefix.clone(),
+            None,
+        )];
+        for region in &provider_config.regions {
+            let api_key = region
+                .api_key_env
+                .as_ref()
+                .map(|env| {
+                    std::env::var(env).map(Secret::from).map_err(|_| {
+                        InitError::RegionKeyNotFound(region.name.clone())
+                    })
+                })
+                .transpose()?;
+            regions.push(Region::new(
+                region.name.clone(),
+                region.base_url.clone(),
+                provider_config.path_prefix.clone(),
+                api_key,
+            ));
+        }
+        Ok(Some(Self {
+            regions,
+            next: AtomicUsize::new(0),
+        }))
+    }
+
+    /// The regions to try a request on, in order: the healthy regions
+    /// starting with the next one in turn, then the ejected regions, the
+    /// soonest to be restored first.
+    #[must_use]
+    pub fn candidates(&self) -> Vec<&Region> {
+        let now = Instant::now();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let (mut healthy, mut ejected) = (Vec::new(), Vec::new());
+        for i in 0..self.regions.len() {
+            let region = &self.regions[(start + i) % self.regions.len()];
+            match region.ejected_until(now) {
+                Some(ejected_until) => ejected.push((ejected_until, region)),
+                None => healthy.push(region),
+            }
+        }
+        ejected.sort_by_key(|(ejected_until, _)| *ejected_until);
+        healthy.extend(ejected.into_iter().map(|(_, region)| region));
+        healthy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn regions() -> Regions {
+        let yaml = r"
+base-url: https://eastus.example.com
+regions:
+  - name: westus
+    base-url: https://westus.example.com
+  - name: westeurope
+    base-url: https://westeurope.example.com
+";
+        let router_config = RouterConfig {
+            providers: Some(
+                [(
+                    InferenceProvider::OpenAI,
+                    serde_yml::from_str(yaml).unwrap(),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+            ..RouterConfig::default()
+        };
+        Regions::for_provider(&router_config, &InferenceProvider::OpenAI)
+            .unwrap()
+            .unwrap()
+    }
+
+    fn names(candidates: &[&Region]) -> Vec<String> {
+        candidates.iter().map(|r| r.name().to_string()).collect()
+    }
+
+    #[test]
+    fn requests_rotate_across_regions() {
+        let regions = regions();
+        assert_eq!(
+            names(&regions.candidates()),
+            ["default", "westus", "westeurope"]
+        );
+        assert_eq!(
+            names(&regions.candidates()),
+            ["westus", "westeurope", "default"]
+        );
+    }
+
+    #[test]
+    fn failing_regions_are_tried_last() {
+        let regions = regions();
+        let westus = &regions.regions[1];
+        for _ in 0..FAILURE_THRESHOLD {
+            westus.record(true);
+        }
+        assert_eq!(
+            names(&regions.candidates()),
+            ["default", "westeurope", "westus"]
+        );
+        westus.health.lock().unwrap().ejected_until = None;
+        assert_eq!(
+            names(&regions.candidates()),
+            ["westus", "westeurope", "default"]
+        );
+    }
+
+    #[test]
+    fn providers_without_regions() {
+        let router_config = RouterConfig::default();
+        assert!(
+            Regions::for_provider(&router_config, &InferenceProvider::OpenAI)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,107 @@
+//! Address family preferences for connections to providers.
+//!
+//! Connections are attempted in the order the resolver returns addresses,
+//! racing the first address family against the other one after a short
+//! delay ("happy eyeballs"). Some deployments have broken paths to a
+//! provider over one of the families, where every attempt stalls until the
+//! race gives up on it; ordering or filtering the resolved addresses avoids
+//! those stalls.
+use std::net::SocketAddr;
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+
+use crate::config::dispatcher::AddressFamily;
+
+#[derive(Debug, Clone, Copy)]
+pub struct AddressFamilyResolver {
+    family: AddressFamily,
+    happy_eyeballs: bool,
+}
+
+impl AddressFamilyResolver {
+    /// Returns `None` if the system resolver's behavior is wanted as is.
+    #[must_use]
+    pub fn new(family: AddressFamily, happy_eyeballs: bool) -> Option<Self> {
+        if family == AddressFamily::Any && happy_eyeballs {
+            return None;
+        }
+        Some(Self {
+            family,
+            happy_eyeballs,
+        })
+    }
+
+    fn order(&self, addrs: &[SocketAddr]) -> Vec<SocketAddr> {
+        let (v4, v6): (Vec<_>, Vec<_>) =
+            addrs.iter().copied().partition(|addr| addr.is_ipv4());
+        let (preferred, other) = match self.family {
+            AddressFamily::Any => match addrs.first() {
+                Some(first) if first.is_ipv6() => (v6, v4),
+                _ => (v4, v6),
+            },
+            AddressFamily::PreferIpv4 => (v4, v6),
+            AddressFamily::PreferIpv6 => (v6, v4),
+            AddressFamily::Ipv4Only => (v4, Vec::new()),
+            AddressFamily::Ipv6Only => (v6, Vec::new()),
+        };
+        if self.happy_eyeballs || preferred.is_empty() {
+            preferred.into_iter().chain(other).collect()
+        } else {
+            preferred
+        }
+    }
+}
+
+impl Resolve for AddressFamilyResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let this = *self;
+        Box::pin(async move {
+            // the port is replaced with that of the request url
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .collect::<Vec<_>>();
+            let addrs: Addrs = Box::new(this.order(&addrs).into_iter());
+            Ok(addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addrs() -> Vec<SocketAddr> {
+        ["[::1]:0", "127.0.0.1:0", "[::2]:0", "127.0.0.2:0"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect()
+    }
+
+    fn order(family: AddressFamily, happy_eyeballs: bool) -> Vec<String> {
+        AddressFamilyResolver::new(family, happy_eyeballs)
+            .unwrap()
+            .order(&addrs())
+            .iter()
+            .map(|addr| addr.ip().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn addresses_are_ordered_by_preference() {
+        assert_eq!(
+            order(AddressFamily::PreferIpv4, true),
+            ["127.0.0.1", "127.0.0.2", "::1", "::2"]
+        );
+        assert_eq!(
+            order(AddressFamily::PreferIpv4, false),
+            ["127.0.0.1", "127.0.0.2"]
+        );
+        assert_eq!(order(AddressFamily::Ipv6Only, true), ["::1", "::2"]);
+        assert_eq!(order(AddressFamily::Any, false), ["::1", "::2"]);
+    }
+
+    #[test]
+    fn system_behavior_needs_no_resolver() {
+        assert!(AddressFamilyResolver::new(AddressFamily::Any, true).is_none());
+    }
+}
@@ -0,0 +1,152 @@
+//! Router wide retry budgets.
+//!
+//! Retries are bounded per request by the retry strategy, but during a
+//! provider incident every request of a router retries at once, multiplying
+//! the traffic sent to a provider that is already struggling. A retry budget
+//! bounds the retries of the whole router to a share of its recent requests.
+use std::{sync::Arc, time::Duration};
+
+use opentelemetry::{KeyValue, metrics::Counter};
+use rust_decimal::prelude::ToPrimitive;
+
+use crate::{
+    config::retry::RetryBudgetConfig, metrics::RollingCounter,
+    types::router::RouterId,
+};
+
+const BUCKETS: u32 = 10;
+
+#[derive(Debug)]
+pub struct RetryBudget {
+    config: RetryBudgetConfig,
+    requests: RollingCounter,
+    retries: RollingCounter,
+    /// labels:
+    /// - `router_id`
+    /// - `outcome`: `allowed` or `exhausted`
+    metric: Counter<u64>,
+    router_id: RouterId,
+}
+
+impl RetryBudget {
+    #[must_use]
+    pub fn new(
+        router_id: RouterId,
+        config: RetryBudgetConfig,
+        metric: Counter<u64>,
+    ) -> Self {
+        Self {
+            requests: RollingCounter::new(config.window, BUCKETS),
+            retries: RollingCounter::new(config.window, BUCKETS),
+            config,
+            metric,
+            router_id,
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> &RetryBudgetConfig {
+        &self.config
+    }
+
+    /// Records a request sent by the router, growing the budget.
+    pub fn record_request(&self) {
+        self.requests.incr();
+    }
+
+    /// Withdraws a retry from the budget, returning `false` if it is
+    /// exhausted.
+    pub fn try_retry(&self) -> bool {
+        let ratio = self.config.ratio.to_f64().unwrap_or_default();
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let allowed = (f64::from(self.requests.total()) * ratio) as u32;
+        let allowed = allowed.max(self.config.min_retries);
+        let has_budget = self.retries.total() < allowed;
+        if has_budget {
+            self.retries.incr();
+        } else {
+            tracing::warn!(router_id = %self.router_id, "retry budget exhausted");
+        }
+        let outcome = if has_budget { "allowed" } else { "exhausted" };
+        self.metric.add(
+            1,
+            &[
+                KeyValue::new("router_id", self.router_id.to_string()),
+                KeyValue::new("outcome", outcome),
+            ],
+        );
+        has_budget
+    }
+}
+
+/// A retry strategy which stops retrying once the retry budget, if any, is
+/// exhausted.
+#[derive(Debug)]
+pub struct Budgeted<B> {
+    backoff: B,
+    budget: Option<Arc<RetryBudget>>,
+}
+
+impl<B> Budgeted<B> {
+    pub fn new(backoff: B, budget: Option<Arc<RetryBudget>>) -> Self {
+        Self { backoff, budget }
+    }
+}
+
+impl<B: Iterator<Item = Duration>> Iterator for Budgeted<B> {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        let delay = self.backoff.next()?;
+        match &self.budget {
+            Some(budget) if !budget.try_retry() => None,
+            _ => Some(delay),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use compact_str::CompactString;
+    use rust_decimal::Decimal;
+
+    use super::*;
+
+    fn budget(ratio: Decimal, min_retries: u32) -> RetryBudget {
+        let config = RetryBudgetConfig {
+            ratio,
+            min_retries,
+            ..RetryBudgetConfig::default()
+        };
+        let metric = opentelemetry::global::meter("test")
+            .u64_counter("retry_budget")
+            .build();
+        let router_id = RouterId::Named(CompactString::new("my-router"));
+        RetryBudget::new(router_id, config, metric)
+    }
+
+    #[test]
+    fn retries_are_bounded_by_the_ratio() {
+        let budget = budget(Decimal::new(2, 1), 0);
+        for _ in 0..10 {
+            budget.record_request();
+        }
+        assert!(budget.try_retry());
+        assert!(budget.try_retry());
+        assert!(!budget.try_retry());
+    }
+
+    #[test]
+    fn min_retries_are_always_allowed() {
+        let budget = budget(Decimal::new(2, 1), 1);
+        assert!(budget.try_retry());
+        assert!(!budget.try_retry());
+    }
+
+    #[test]
+    fn exhausted_budgets_stop_the_backoff() {
+        let budget = Arc::new(budget(Decimal::ZERO, 1));
+        let backoff = std::iter::repeat(Duration::from_millis(1)).take(3);
+        assert_eq!(Budgeted::new(backoff, Some(budget)).count(), 1);
+    }
+}
@@ -0,0 +1,211 @@
+//! Retrying on provider error codes.
+//!
+//! By default only server errors are retried. Providers also signal
+//! transient failures in the body of an error response, such as Anthropic's
+//! `overloaded_error`, so routers configured with `retry-on` read the body
+//! of failed responses and retry on the configured codes. Content policy
+//! errors are never retried, as the provider would reject the request again.
+use bytes::Bytes;
+use http::StatusCode;
+use http_body_util::BodyExt;
+use serde_json::Value;
+
+use crate::{
+    config::retry::RetryOnConfig,
+    error::{api::ApiError, stream::StreamError},
+};
+
+/// Error codes of providers' content policy rejections.
+const CONTENT_POLICY_CODES: &[&str] = &[
+    "content_filter",
+    "content_policy_violation",
+    "invalid_prompt",
+];
+
+/// The error codes in the body of a failed provider response.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProviderErrorCodes(Vec<String>);
+
+impl ProviderErrorCodes {
+    /// Reads the `type` and `code` of the `error` object of `body`.
+    #[must_use]
+    pub fn from_body(body: &[u8]) -> Self {
+        let Ok(body) = serde_json::from_slice::<Value>(body) else {
+            return Self::default();
+        };
+        let Some(error) = body.get("error") else {
+            return Self::default();
+        };
+        let codes = ["type", "code"]
+            .iter()
+            .filter_map(|field| error.get(field))
+            .filter_map(Value::as_str)
+            .map(ToString::to_string)
+            .collect();
+        Self(codes)
+    }
+
+    fn is_content_policy(&self) -> bool {
+        self.0
+            .iter()
+            .any(|code| CONTENT_POLICY_CODES.contains(&code.as_str()))
+    }
+}
+
+/// Whether a failed response should be retried.
+///
+/// `codes` are only read from the response body if the router configures
+/// `retry-on`.
+#[must_use]
+pub fn is_retryable(
+    status: StatusCode,
+    codes: Option<&ProviderErrorCodes>,
+    retry_on: Option<&RetryOnConfig>,
+) -> bool {
+    let (Some(codes), Some(retry_on)) = (codes, retry_on) else {
+        return status.is_server_error();
+    };
+    if codes.is_content_policy() {
+        return false;
+    }
+    status.is_server_error()
+        || codes
+            .0
+            .iter()
+            .any(|code| retry_on.error_codes.contains(code))
+}
+
+#[must_use]
+pub fn is_retryable_response<B>(
+    response: &http::Response<B>,
+    retry_on: Option<&RetryOnConfig>,
+) -> bool {
+    is_retryable(
+        response.status(),
+        response.extensions().get::<ProviderErrorCodes>(),
+        retry_on,
+    )
+}
+
+#[must_use]
+pub fn is_retryable_stream_error(
+    error: &StreamError,
+    retry_on: Option<&RetryOnConfig>,
+) -> bool {
+    if let StreamError::StreamError(error) = error
+        && let reqwest_eventsource::Error::InvalidStatusCode(status, response) =
+            &**error
+    {
+        return is_retryable(
+            *status,
+            response.extensions().get::<ProviderErrorCodes>(),
+            retry_on,
+        );
+    }
+    error.is_retryable()
+}
+
+/// Reads the error codes of a stream which failed with an error status,
+/// keeping its body for the response to the client.
+pub async fn inspect_stream_error(error: ApiError) -> ApiError {
+    let ApiError::StreamError(StreamError::StreamError(error)) = error else {
+        return error;
+    };
+    let error = match *error {
+        reqwest_eventsource::Error::InvalidStatusCode(status, response) => {
+            let (mut parts, body) = http::Response::from(response).into_parts();
+            let body = body
+                .collect()
+                .await
+                .map(|body| body.to_bytes())
+                .unwrap_or_else(|error| {
+                    tracing::debug!(error = %error, "failed to read error body");
+                    Bytes::new()
+                });
+            parts
+                .extensions
+                .insert(ProviderErrorCodes::from_body(&body));
+            let response = http::Response::from_parts(parts, body);
+            reqwest_eventsource::Error::InvalidStatusCode(
+                status,
+                response.into(),
+            )
+        }
+        error => error,
+    };
+    ApiError::StreamError(StreamError::StreamError(Box::new(error)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retry_on() -> RetryOnConfig {
+        RetryOnConfig {
+            error_codes: ["overloaded_error", "rate_limit_exceeded"]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn error_codes_are_read_from_the_body() {
+        let anthropic = br#"{"type": "error", "error": {
+            "type": "overloaded_error", "message": "Overloaded"
+        }}"#;
+        assert_eq!(
+            ProviderErrorCodes::from_body(anthropic),
+            ProviderErrorCodes(vec!["overloaded_error".to_string()])
+        );
+        let openai = br#"{"error": {
+            "type": "invalid_request_error", "code": "content_policy_violation"
+        }}"#;
+        assert_eq!(
+            ProviderErrorCodes::from_body(openai),
+            ProviderErrorCodes(vec![
+                "invalid_request_error".to_string(),
+                "content_policy_violation".to_string(),
+            ])
+        );
+        assert_eq!(
+            ProviderErrorCodes::from_body(b"upstream connect error"),
+            ProviderErrorCodes::default()
+        );
+    }
+
+    #[test]
+    fn configured_codes_are_retried() {
+        let rate_limited =
+            ProviderErrorCodes(vec!["rate_limit_exceeded".to_string()]);
+        assert!(is_retryable(
+            StatusCode::TOO_MANY_REQUESTS,
+            Some(&rate_limited),
+            Some(&retry_on())
+        ));
+        let bad_request =
+            ProviderErrorCodes(vec!["invalid_request_error".to_string()]);
+        assert!(!is_retryable(
+            StatusCode::BAD_REQUEST,
+            Some(&bad_request),
+            Some(&retry_on())
+        ));
+    }
+
+    #[test]
+    fn content_policy_errors_are_never_retried() {
+        let content_filter =
+            ProviderErrorCodes(vec!["content_filter".to_string()]);
+        assert!(!is_retryable(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Some(&content_filter),
+            Some(&retry_on())
+        ));
+    }
+
+    #[test]
+    fn server_errors_are_retried_without_retry_on() {
+        assert!(is_retryable(StatusCode::BAD_GATEWAY, None, None));
+        assert!(!is_retryable(StatusCode::TOO_MANY_REQUESTS, None, None));
+    }
+}
@@ -11,11 +11,12 @@ use chrono::{DateTime, Utc};
 use futures::{TryStreamExt, future::BoxFuture};
 use http::{HeaderMap, HeaderName, HeaderValue, StatusCode, uri::PathAndQuery};
 use http_body_util::BodyExt;
+use indexmap::IndexMap;
 use opentelemetry::KeyValue;
 use reqwest::RequestBuilder;
 use rust_decimal::prelude::ToPrimitive;
 use tokio::{
-    sync::{mpsc::Sender, oneshot},
+    sync::{OwnedSemaphorePermit, mpsc::Sender, oneshot},
     time::Instant,
 };
 use tower::{Service, ServiceBuilder};
@@ -24,16 +25,47 @@ use uuid::Uuid;
 
 use crate::{
     app_state::AppState,
-    config::{retry::RetryConfig, router::RouterConfig},
-    discover::monitor::metrics::EndpointMetricsRegistry,
+    config::{
+        retry::{RetryConfig, RetryOnConfig},
+        router::RouterConfig,
+    },
+    discover::monitor::{
+        metrics::EndpointMetricsRegistry, state_sync::ProviderEvent,
+    },
     dispatcher::{
+        adaptive_limit::AdaptivePermit,
+        attempt::AttemptTimeouts,
+        azure_openai_client,
+        chaos::{self, FaultKind},
         client::{Client, ProviderClient},
         extensions::ExtensionsCopier,
+        gemini_cache::{self, CachedContentsRequest},
+        recycle::RecycledClient,
+        regions::{Region, Regions},
+        retry_budget::{Budgeted, RetryBudget},
+        retry_on,
+        stream_retry::{StreamRetry, StreamRetryPolicy},
+        tls::ProviderTls,
     },
     endpoints::ApiEndpoint,
-    error::{api::ApiError, init::InitError, internal::InternalError},
-    logger::service::LoggerService,
-    metrics::tfft::TFFTFuture,
+    error::{
+        api::ApiError, init::InitError, internal::InternalError,
+        invalid_req::InvalidRequestError, stream::StreamError,
+    },
+    logger::{
+        correlation::{self, Correlation},
+        event::RequestEvent,
+        journal::{JournalEntry, JournalGuard},
+        properties::{self, RequestMetadata},
+        service::LoggerService,
+        usage::Usage,
+    },
+    metrics::{
+        body_size::{self, Direction},
+        spend::SpendScope,
+        tfft::{self, TFFTFuture},
+        throughput,
+    },
     middleware::{
         add_extension::{AddExtensions, AddExtensionsLayer},
         mapper::{model::ModelMapper, registry::EndpointConverterRegistry},
@@ -41,15 +73,20 @@ use crate::{
     types::{
         body::BodyReader,
         extensions::{
-            MapperContext, PromptContext, RequestContext, RequestKind,
+            AuthContext, ClientIp, MapperContext, PromptContext,
+            RequestContext, RequestDeadline, RequestKind, Shadow,
         },
         model_id::ModelId,
+        priority::Priority,
         provider::InferenceProvider,
         rate_limit::RateLimitEvent,
         request::Request,
         router::RouterId,
     },
-    utils::handle_error::{ErrorHandler, ErrorHandlerLayer},
+    utils::{
+        handle_error::{ErrorHandler, ErrorHandlerLayer},
+        host_header, join_url, task,
+    },
 };
 
 pub type DispatcherFuture = BoxFuture<
@@ -61,33 +98,65 @@ pub type DispatcherService =
 pub type DispatcherServiceWithoutMapper =
     AddExtensions<ErrorHandler<Dispatcher>>;
 
+/// Capacity held by a request while it is in flight to a provider, released
+/// when dropped.
+#[derive(Debug, Default)]
+struct ConcurrencyPermits {
+    bulkhead: Option<OwnedSemaphorePermit>,
+    adaptive: Option<AdaptivePermit>,
+}
+
 /// Leaf service that dispatches requests to the correct provider.
 #[derive(Debug, Clone)]
 pub struct Dispatcher {
-    client: Client,
+    client: RecycledClient,
     app_state: AppState,
     provider: InferenceProvider,
     /// Is `Some` for load balanced routers, `None` for direct proxies.
     rate_limit_tx: Option<Sender<RateLimitEvent>>,
+    /// Is `Some` if the router configures regions for the provider.
+    regions: Option<Arc<Regions>>,
+    /// Is `Some` if the router configures a retry budget.
+    retry_budget: Option<Arc<RetryBudget>>,
+    /// Is `Some` if the router retries on provider error codes.
+    retry_on: Option<Arc<RetryOnConfig>>,
 }
 
 impl Dispatcher {
     async fn new_inner(
         app_state: AppState,
         router_id: &RouterId,
+        router_config: &RouterConfig,
         provider: InferenceProvider,
         model_mapper: ModelMapper,
     ) -> Result<DispatcherService, InitError> {
-        let client = Client::new(&app_state, provider.clone()).await?;
+        let tls = ProviderTls::for_provider(router_config, &provider)?;
+        let client =
+            RecycledClient::new(&app_state, provider.clone(), tls).await?;
         let rate_limit_tx = app_state.get_rate_limit_tx(router_id).await?;
+        let regions =
+            Regions::for_provider(router_config, &provider)?.map(Arc::new);
+        let retry_budget = match &router_config.retry_budget {
+            Some(config) => {
+                Some(app_state.retry_budget(router_id, config).await)
+            }
+            None => None,
+        };
 
         let dispatcher = Self {
             client,
             app_state: app_state.clone(),
             provider: provider.clone(),
             rate_limit_tx: Some(rate_limit_tx),
+            regions,
+            retry_budget,
+            retry_on: router_config.retry_on.clone().map(Arc::new),
         };
         let converter_registry = EndpointConverterRegistry::new(&model_mapper);
+        let mapper_layer = crate::middleware::mapper::Layer::new(
+            converter_registry,
+            &app_state,
+        );
 
         let extensions_layer = AddExtensionsLayer::builder()
             .inference_provider(provider.clone())
@@ -97,7 +166,7 @@ impl Dispatcher {
         Ok(ServiceBuilder::new()
             .layer(extensions_layer)
             .layer(ErrorHandlerLayer::new(app_state))
-            .layer(crate::middleware::mapper::Layer::new(converter_registry))
+            .layer(mapper_layer)
             // other middleware: rate limiting, logging, etc, etc
             // will be added here as well
             .service(dispatcher))
@@ -111,9 +180,17 @@ impl Dispatcher {
     ) -> Result<DispatcherService, InitError> {
         let model_mapper = ModelMapper::new_for_router(
             app_state.clone(),
+            router_id.clone(),
             router_config.clone(),
         );
-        Self::new_inner(app_state, router_id, provider, model_mapper).await
+        Self::new_inner(
+            app_state,
+            router_id,
+            router_config,
+            provider,
+            model_mapper,
+        )
+        .await
     }
 
     pub async fn new_with_model_id(
@@ -128,23 +205,38 @@ impl Dispatcher {
             router_config.clone(),
             model_id,
         );
-        Self::new_inner(app_state, router_id, provider, model_mapper).await
+        Self::new_inner(
+            app_state,
+            router_id,
+            router_config,
+            provider,
+            model_mapper,
+        )
+        .await
     }
 
     pub async fn new_direct_proxy(
         app_state: AppState,
         provider: &InferenceProvider,
     ) -> Result<DispatcherService, InitError> {
-        let client = Client::new(&app_state, provider.clone()).await?;
+        let client =
+            RecycledClient::new(&app_state, provider.clone(), None).await?;
 
         let dispatcher = Self {
             client,
             app_state: app_state.clone(),
             provider: provider.clone(),
             rate_limit_tx: None,
+            regions: None,
+            retry_budget: None,
+            retry_on: None,
         };
         let model_mapper = ModelMapper::new(app_state.clone());
         let converter_registry = EndpointConverterRegistry::new(&model_mapper);
+        let mapper_layer = crate::middleware::mapper::Layer::new(
+            converter_registry,
+            &app_state,
+        );
 
         let extensions_layer = AddExtensionsLayer::builder()
             .inference_provider(provider.clone())
@@ -154,7 +246,7 @@ impl Dispatcher {
         Ok(ServiceBuilder::new()
             .layer(extensions_layer)
             .layer(ErrorHandlerLayer::new(app_state))
-            .layer(crate::middleware::mapper::Layer::new(converter_registry))
+            .layer(mapper_layer)
             // other middleware: rate limiting, logging, etc, etc
             // will be added here as well
             .service(dispatcher))
@@ -164,13 +256,17 @@ impl Dispatcher {
         app_state: AppState,
         provider: &InferenceProvider,
     ) -> Result<DispatcherServiceWithoutMapper, InitError> {
-        let client = Client::new(&app_state, provider.clone()).await?;
+        let client =
+            RecycledClient::new(&app_state, provider.clone(), None).await?;
 
         let dispatcher = Self {
             client,
             app_state: app_state.clone(),
             provider: provider.clone(),
             rate_limit_tx: None,
+            regions: None,
+            retry_budget: None,
+            retry_on: None,
         };
 
         let extensions_layer = AddExtensionsLayer::builder()
@@ -228,8 +324,21 @@ impl Dispatcher {
             request_kind,
             prompt_ctx,
         ) = Self::extract_request_context(&mut req)?;
-
         let auth_ctx = req_ctx.auth_context.as_ref();
+        self.app_state.0.freezes.check(
+            &self.provider,
+            router_id.as_ref(),
+            auth_ctx,
+        )?;
+        let priority = req
+            .extensions()
+            .get::<Priority>()
+            .copied()
+            .unwrap_or_default();
+        let request_deadline =
+            req.extensions().get::<RequestDeadline>().copied();
+        let shadow = req.extensions().get::<Shadow>().is_some();
+        let client_ip = req.extensions().get::<ClientIp>().copied();
         let target_provider = &self.provider;
         {
             let h = req.headers_mut();
@@ -246,11 +355,7 @@ impl Dispatcher {
         }
         let method = req.method().clone();
         let headers = req.headers().clone();
-        let target_url = self.build_target_url(
-            &req_ctx,
-            target_provider,
-            extracted_path_and_query.as_str(),
-        )?;
+        let request_metadata = req.extensions_mut().remove::<RequestMetadata>();
         // TODO: could change request type of dispatcher to
         // http::Request<reqwest::Body>
         // to avoid collecting the body twice
@@ -260,24 +365,44 @@ impl Dispatcher {
             .await
             .map_err(|e| InternalError::RequestBodyError(Box::new(e)))?
             .to_bytes();
+        // direct proxy requests are not converted, so their metadata is read
+        // from the body sent to the provider
+        let mut properties = properties::merge(
+            &headers,
+            request_metadata
+                .unwrap_or_else(|| RequestMetadata::from_body(&req_body_bytes)),
+        );
+        body_size::record(
+            &self.app_state,
+            &self.provider,
+            mapper_ctx.model.as_ref(),
+            Direction::Request,
+            req_body_bytes.len() as u64,
+        );
+        let cached_contents_request = self.authorize_cached_contents(
+            auth_ctx,
+            &method,
+            extracted_path_and_query.path(),
+            &req_body_bytes,
+        )?;
 
-        let request_builder = self
-            .client
-            .as_ref()
-            .request(method.clone(), target_url.clone())
-            .headers(headers.clone());
-
-        let request_builder = self
-            .client
-            .authenticate(
-                &self.app_state,
-                request_builder,
-                &req_body_bytes,
-                auth_ctx,
-                self.provider.clone(),
-            )
-            .await?;
-
+        let mut permits = self.acquire_concurrency_permits(priority).await?;
+        let helicone_request_id = Uuid::new_v4();
+        // removed once the response body has been read in full, or on error
+        let journal = match &self.app_state.0.journal {
+            Some(journal) => {
+                journal
+                    .start(&JournalEntry::new(
+                        helicone_request_id,
+                        start_time,
+                        &self.provider,
+                        mapper_ctx.model.as_ref(),
+                        router_id.as_ref(),
+                    ))
+                    .await
+            }
+            None => None,
+        };
         let metrics_for_stream = self.app_state.0.endpoint_metrics.clone();
         if let Some(ref api_endpoint) = api_endpoint {
             let endpoint_metrics = self
@@ -287,40 +412,176 @@ impl Dispatcher {
                 .health_metrics(api_endpoint.clone())?;
             endpoint_metrics.incr_req_count();
         }
+        if let Some(retry_budget) = &self.retry_budget {
+            retry_budget.record_request();
+        }
+
+        // without regions, the request is sent to the provider's base url
+        let candidates = self.regions.as_ref().map_or_else(
+            || vec![None],
+            |regions| regions.candidates().into_iter().map(Some).collect(),
+        );
+        let last_candidate = candidates.len() - 1;
+        let fault = self
+            .app_state
+            .0
+            .chaos
+            .as_ref()
+            .and_then(|faults| faults.draw(&self.provider));
+        if let Some(FaultKind::Latency { delay }) = fault {
+            tracing::debug!(delay = ?delay, "injecting latency");
+            tokio::time::sleep(delay).await;
+        }
+        let client = self.client.get(&self.app_state).await;
+        let path_and_query = self.provider_path_and_query(
+            &client,
+            extracted_path_and_query.as_str(),
+            mapper_ctx.model.as_ref(),
+        );
+        let query_rules = self
+            .app_state
+            .config()
+            .providers
+            .get(target_provider)
+            .map(|provider_config| &provider_config.query);
+        let mut attempt = None;
+        for (i, region) in candidates.into_iter().enumerate() {
+            let mut target_url = match region {
+                Some(region) => region.target_url(&path_and_query),
+                None => self.build_target_url(
+                    &req_ctx,
+                    target_provider,
+                    &path_and_query,
+                )?,
+            };
+            if let Some(query_rules) = query_rules {
+                query_rules.apply(&mut target_url);
+            }
+            if let Some(egress) = &self.app_state.config().dispatcher.egress
+                && !egress.allows(&target_url)
+            {
+                tracing::warn!(
+                    target_url = %target_url,
+                    "refusing to dispatch to host not on egress allowlist"
+                );
+                return Err(InternalError::EgressDenied(
+                    target_url.host_str().unwrap_or_default().to_string(),
+                )
+                .into());
+            }
+            let mut request_builder = client
+                .as_ref()
+                .request(method.clone(), target_url.clone())
+                .headers(headers.clone());
+            if let Some(query_rules) = query_rules
+                && !query_rules.set_secret.is_empty()
+            {
+                request_builder =
+                    request_builder.query(&query_rules.secret_params());
+            }
+            if region.is_some() {
+                // the client's default host header is that of the provider's
+                // base url
+                request_builder = request_builder
+                    .header(http::header::HOST, host_header(&target_url));
+            }
+            let request_builder = match region.and_then(Region::api_key) {
+                Some(api_key) => {
+                    client.set_auth_header(request_builder, api_key)
+                }
+                None => {
+                    client
+                        .authenticate(
+                            &self.app_state,
+                            request_builder,
+                            &req_body_bytes,
+                            auth_ctx,
+                            self.provider.clone(),
+                        )
+                        .await?
+                }
+            };
 
+            let result = if let Some(FaultKind::Error { status }) = fault {
+                tracing::debug!(status, "injecting error response");
+                Ok(chaos::error_response(status))
+            } else if mapper_ctx.is_stream {
+                dispatch_stream_with_retry(
+                    &self.app_state,
+                    request_builder,
+                    req_body_bytes.clone(),
+                    api_endpoint.clone(),
+                    metrics_for_stream.clone(),
+                    &req_ctx,
+                    request_kind,
+                    request_deadline,
+                    priority,
+                    self.retry_budget.clone(),
+                    self.retry_on.clone(),
+                )
+                .await
+            } else {
+                self.dispatch_sync_with_retry(
+                    request_builder,
+                    req_body_bytes.clone(),
+                    &req_ctx,
+                    request_kind,
+                    request_deadline,
+                    priority,
+                )
+                .instrument(info_span!("dispatch_sync"))
+                .await
+            };
+            if let Some(region) = region {
+                let failed = is_region_failure(&result);
+                region.record(failed);
+                if failed && i < last_candidate {
+                    tracing::warn!(
+                        region = region.name(),
+                        "provider region failed, failing over"
+                    );
+                    continue;
+                }
+            }
+            attempt = Some((target_url, result));
+            break;
+        }
+        let (target_url, result) =
+            attempt.expect("there is always at least one candidate");
         let (mut client_response, response_body_for_logger, tfft_rx): (
             http::Response<crate::types::body::Body>,
             crate::types::body::BodyReader,
             oneshot::Receiver<()>,
-        ) = if mapper_ctx.is_stream {
-            dispatch_stream_with_retry(
-                &self.app_state,
-                request_builder,
-                req_body_bytes.clone(),
-                api_endpoint.clone(),
-                metrics_for_stream,
-                &req_ctx,
-                request_kind,
-            )
-            .await?
-        } else {
-            self.dispatch_sync_with_retry(
-                request_builder,
-                req_body_bytes.clone(),
-                &req_ctx,
-                request_kind,
-            )
-            .instrument(info_span!("dispatch_sync"))
-            .await?
-        };
+        ) = result.inspect_err(|e| {
+            self.record_adaptive_outcome(&mut permits, is_overload_error(e));
+        })?;
+        self.record_adaptive_outcome(
+            &mut permits,
+            is_overload_status(client_response.status()),
+        );
+        if let Some(FaultKind::DropStream { after_chunks }) = fault
+            && mapper_ctx.is_stream
+        {
+            tracing::debug!(after_chunks, "injecting dropped stream");
+            client_response = client_response
+                .map(|body| chaos::drop_stream(body, after_chunks));
+        }
         tracing::info!(
             method = %method,
             target_url = %target_url,
             is_stream = %mapper_ctx.is_stream,
             response_status = %client_response.status(),
+            properties = ?properties,
             "proxied request"
         );
-        let helicone_request_id = Uuid::new_v4();
+        if let (Some(request), Some(auth_ctx)) =
+            (cached_contents_request, auth_ctx)
+            && client_response.status().is_success()
+        {
+            client_response = self
+                .track_cached_contents(&request, auth_ctx, client_response)
+                .await?;
+        }
         let provider_request_id = {
             let headers = client_response.headers_mut();
             headers.insert(
@@ -332,6 +593,22 @@ impl Dispatcher {
             headers.remove("x-request-id")
         };
         tracing::debug!(provider_req_id = ?provider_request_id, status = %client_response.status(), "received response");
+        let correlation = Correlation {
+            helicone_id: helicone_request_id,
+            request_id: headers
+                .get("x-request-id")
+                .and_then(|id| id.to_str().ok())
+                .map(str::to_string),
+            provider_request_id: provider_request_id
+                .as_ref()
+                .and_then(|id| id.to_str().ok())
+                .map(str::to_string),
+            trace_id: correlation::current_trace_id(),
+            provider: self.provider.clone(),
+            created_at: Utc::now(),
+        };
+        properties.extend(correlation.properties());
+        self.app_state.0.correlations.record(correlation);
         let extensions_copier = ExtensionsCopier::builder()
             .inference_provider(inference_provider)
             .router_id(router_id.clone())
@@ -354,6 +631,7 @@ impl Dispatcher {
             response_status,
             response_headers,
             api_endpoint.clone(),
+            router_id.as_ref(),
         )
         .await?;
 
@@ -372,11 +650,155 @@ impl Dispatcher {
             router_id,
             helicone_request_id,
             prompt_ctx,
+            properties,
+            client_ip,
+            permits,
+            journal,
+            shadow,
         );
 
         Ok(client_response)
     }
 
+    /// Only lets clients reference the Gemini cached contents they created.
+    ///
+    /// Returns the cached contents request, if `path` is the cached contents
+    /// collection.
+    fn authorize_cached_contents(
+        &self,
+        auth_ctx: Option<&AuthContext>,
+        method: &http::Method,
+        path: &str,
+        body: &Bytes,
+    ) -> Result<Option<CachedContentsRequest>, ApiError> {
+        let Some(auth_ctx) = auth_ctx else {
+            return Ok(None);
+        };
+        if self.provider != InferenceProvider::GoogleGemini {
+            return Ok(None);
+        }
+        if let Some(name) = gemini_cache::referenced_cached_content(path, body)
+            && !self
+                .app_state
+                .0
+                .gemini_cached_contents
+                .is_owned_by(&name, auth_ctx)
+        {
+            tracing::debug!(
+                cached_content = %name,
+                "rejecting reference to cached content of another api key"
+            );
+            return Err(InvalidRequestError::NotFound(name).into());
+        }
+        Ok(CachedContentsRequest::new(method, path))
+    }
+
+    /// Records the owner of created Gemini cached contents, and hides the
+    /// cached contents of other API keys from listings.
+    async fn track_cached_contents(
+        &self,
+        request: &CachedContentsRequest,
+        auth_ctx: &AuthContext,
+        response: http::Response<crate::types::body::Body>,
+    ) -> Result<http::Response<crate::types::body::Body>, ApiError> {
+        let (parts, body) = response.into_parts();
+        let body = body
+            .collect()
+            .await
+            .map_err(InternalError::CollectBodyError)?
+            .to_bytes();
+        let Ok(mut value) = serde_json::from_slice::<serde_json::Value>(&body)
+        else {
+            return Ok(http::Response::from_parts(parts, body.into()));
+        };
+        let cached_contents = &self.app_state.0.gemini_cached_contents;
+        let body = match request {
+            CachedContentsRequest::Create => {
+                cached_contents.record(auth_ctx, &value);
+                body
+            }
+            CachedContentsRequest::List => {
+                cached_contents.retain_owned(auth_ctx, &mut value);
+                serde_json::to_vec(&value).map(Bytes::from).map_err(
+                    |error| InternalError::Serialize {
+                        ty: "serde_json::Value",
+                        error,
+                    },
+                )?
+            }
+        };
+        Ok(http::Response::from_parts(parts, body.into()))
+    }
+
+    /// Acquires capacity for a request to this dispatcher's provider from
+    /// the static and adaptive bulkheads, if configured.
+    async fn acquire_concurrency_permits(
+        &self,
+        priority: Priority,
+    ) -> Result<ConcurrencyPermits, ApiError> {
+        let mut permits = ConcurrencyPermits::default();
+        if let Some(bulkheads) = self.app_state.0.bulkheads.as_ref() {
+            let Some(permit) =
+                bulkheads.acquire(&self.provider, priority).await
+            else {
+                tracing::warn!(
+                    provider = %self.provider,
+                    %priority,
+                    in_flight = bulkheads.in_flight(&self.provider),
+                    "provider bulkhead full, rejecting request"
+                );
+                return Err(self.bulkhead_full("static"));
+            };
+            permits.bulkhead = Some(permit);
+        }
+        if let Some(limiters) = self.app_state.0.adaptive_limiters.as_ref() {
+            let limiter = limiters.get(&self.provider);
+            let Some(permit) = limiter.try_acquire() else {
+                tracing::warn!(
+                    provider = %self.provider,
+                    limit = limiter.limit(),
+                    "provider adaptive concurrency limit reached, rejecting \
+                     request"
+                );
+                return Err(self.bulkhead_full("adaptive"));
+            };
+            permits.adaptive = Some(permit);
+        }
+        Ok(permits)
+    }
+
+    fn bulkhead_full(&self, bulkhead: &'static str) -> ApiError {
+        self.app_state.0.metrics.bulkhead_rejections.add(
+            1,
+            &[
+                KeyValue::new("provider", self.provider.to_string()),
+                KeyValue::new("bulkhead", bulkhead),
+            ],
+        );
+        InternalError::BulkheadFull(self.provider.clone()).into()
+    }
+
+    /// Feeds the outcome of a request into the provider's adaptive
+    /// concurrency limit.
+    fn record_adaptive_outcome(
+        &self,
+        permits: &mut ConcurrencyPermits,
+        overloaded: bool,
+    ) {
+        let Some(permit) = permits.adaptive.as_mut() else {
+            return;
+        };
+        if overloaded {
+            permit.record_dropped();
+        } else {
+            permit.record_success();
+        }
+        self.app_state.0.metrics.adaptive_concurrency_limit.record(
+            u64::try_from(permit.limiter().limit()).unwrap_or(u64::MAX),
+            &[KeyValue::new("provider", self.provider.to_string())],
+        );
+    }
+
     /// Extracts request context and extensions from the request
     #[allow(clippy::type_complexity)]
     fn extract_request_context(
@@ -464,6 +886,7 @@ impl Dispatcher {
         response_status: StatusCode,
         response_headers: &HeaderMap,
         api_endpoint: Option<ApiEndpoint>,
+        router_id: Option<&RouterId>,
     ) -> Result<(), ApiError> {
         if response_status.is_server_error() {
             if let Some(api_endpoint) = api_endpoint {
@@ -494,6 +917,15 @@ impl Dispatcher {
                     {
                         tracing::error!(error = %e, "failed to send rate limit event");
                     }
+                    if let (Some(state_sync), Some(router_id)) =
+                        (self.app_state.0.state_sync.as_ref(), router_id)
+                    {
+                        state_sync.publish(ProviderEvent::rate_limited(
+                            router_id.clone(),
+                            api_endpoint,
+                            retry_after,
+                        ));
+                    }
                 }
             }
         }
@@ -517,10 +949,26 @@ impl Dispatcher {
         router_id: Option<RouterId>,
         helicone_request_id: Uuid,
         prompt_ctx: Option<PromptContext>,
+        properties: IndexMap<String, String>,
+        client_ip: Option<ClientIp>,
+        permits: ConcurrencyPermits,
+        journal: Option<JournalGuard>,
+        shadow: bool,
     ) {
+        // the permits are held until the response body has been read in
+        // full, since the upstream connection is busy until then
         let deployment_target =
             self.app_state.config().deployment_target.clone();
-        if self.app_state.config().helicone.is_observability_enabled() {
+        let logger_lag = response_body_for_logger.lag();
+        // shadow requests only record metrics, so that they are not billed
+        let spend = if shadow {
+            None
+        } else {
+            SpendScope::new(&self.app_state, req_ctx, router_id.as_ref())
+        };
+        if !shadow
+            && self.app_state.config().helicone.is_observability_enabled()
+        {
             if let Some(auth_ctx) = req_ctx.auth_context.clone() {
                 let response_logger = LoggerService::builder()
                     .app_state(self.app_state.clone())
@@ -539,11 +987,17 @@ impl Dispatcher {
                     .deployment_target(deployment_target)
                     .request_id(helicone_request_id)
                     .prompt_ctx(prompt_ctx)
+                    .properties(properties)
+                    .spend(spend)
+                    .client_ip(client_ip)
                     .build();
 
                 let app_state = self.app_state.clone();
-                tokio::spawn(
+                task::spawn(
+                    "response-logger",
                     async move {
+                        let _permits = permits;
+                        let _journal = journal;
                         if let Err(e) = response_logger.log().await {
                             let error_str = e.as_ref().to_string();
                             app_state
@@ -552,37 +1006,134 @@ impl Dispatcher {
                                 .error_count
                                 .add(1, &[KeyValue::new("type", error_str)]);
                         }
+                        logger_lag.record(&app_state.0.metrics);
                     }
                     .instrument(tracing::Span::current()),
                 );
             }
         } else {
             let app_state = self.app_state.clone();
-            let model = mapper_ctx.model.as_ref().map_or_else(
-                || "unknown".to_string(),
-                std::string::ToString::to_string,
-            );
-            let path = target_url.path().to_string();
-            let provider_string = self.provider.to_string();
-            tokio::spawn(
-                    async move {
-                        let tfft_future = TFFTFuture::new(start_instant, tfft_rx);
-                        let collect_future = response_body_for_logger.collect();
-                        let (_response_body, tfft_duration) = tokio::join!(collect_future, tfft_future);
-                        if let Ok(tfft_duration) = tfft_duration {
-                            tracing::trace!(tfft_duration = ?tfft_duration, "tfft_duration");
-                            let attributes = [
-                                KeyValue::new("provider", provider_string),
-                                KeyValue::new("model", model),
-                                KeyValue::new("path", path),
-                            ];
-                            #[allow(clippy::cast_precision_loss)]
-                            app_state.0.metrics.tfft_duration.record(tfft_duration.as_millis() as f64, &attributes);
-                        } else { tracing::error!("Failed to get TFFT signal") }
+            let provider = self.provider.clone();
+            let mapper_ctx = mapper_ctx.clone();
+            let status = client_response.status();
+            task::spawn(
+                "response-metrics",
+                async move {
+                    let _permits = permits;
+                    let _journal = journal;
+                    let tfft_future = TFFTFuture::new(start_instant, tfft_rx);
+                    let collect_future = response_body_for_logger.collect();
+                    let (response_body, tfft_duration) =
+                        tokio::join!(collect_future, tfft_future);
+                    logger_lag.record(&app_state.0.metrics);
+                    let Ok(tfft_duration) = tfft_duration else {
+                        tracing::error!("Failed to get TFFT signal");
+                        return;
+                    };
+                    tracing::trace!(tfft_duration = ?tfft_duration, "tfft_duration");
+                    let Ok(response_body) = response_body;
+                    let response_body = response_body.to_bytes();
+                    body_size::record(
+                        &app_state,
+                        &provider,
+                        mapper_ctx.model.as_ref(),
+                        Direction::Response,
+                        response_body.len() as u64,
+                    );
+                    if mapper_ctx.is_stream {
+                        throughput::record(
+                            &app_state,
+                            &provider,
+                            mapper_ctx.model.as_ref(),
+                            &response_body,
+                            tfft_duration,
+                            start_instant.elapsed(),
+                        );
                     }
-                    .instrument(tracing::Span::current()),
-                );
+                    if let Some(spend) = &spend
+                        && status.is_success()
+                    {
+                        spend.record(
+                            &app_state,
+                            mapper_ctx.model.as_ref(),
+                            &Usage::from_response(
+                                &response_body,
+                                mapper_ctx.is_stream,
+                            ),
+                        );
+                    }
+                    let model = mapper_ctx.model.as_ref().map_or_else(
+                        || "unknown".to_string(),
+                        std::string::ToString::to_string,
+                    );
+                    let attributes = [
+                        KeyValue::new("provider", provider.to_string()),
+                        KeyValue::new("model", model),
+                        KeyValue::new("path", target_url.path().to_string()),
+                    ];
+                    tfft::record_tfft(
+                        &app_state.0.metrics,
+                        tfft_duration,
+                        &attributes,
+                    );
+                    tfft::record_request_duration(
+                        &app_state.0.metrics,
+                        start_instant.elapsed(),
+                        &attributes,
+                    );
+                    if !shadow && app_state.config().logger.request_events {
+                        RequestEvent::builder()
+                            .request_id(helicone_request_id)
+                            .provider(&provider)
+                            .model(mapper_ctx.model.as_ref())
+                            .path(target_url.path())
+                            .status(status)
+                            .is_stream(mapper_ctx.is_stream)
+                            .request_body_size(req_body_bytes.len() as u64)
+                            .response_body_size(response_body.len() as u64)
+                            .tfft(tfft_duration)
+                            .duration(start_instant.elapsed())
+                            .router_id(router_id.as_ref())
+                            .build()
+                            .emit();
+                    }
+                }
+                .instrument(tracing::Span::current()),
+            );
+        }
+    }
+
+    /// The path and query of the request to the provider, which for Azure
+    /// addresses the deployment of the target model, for Vertex the endpoint
+    /// of the project, and for Workers AI the account.
+    fn provider_path_and_query(
+        &self,
+        client: &Client,
+        extracted_path_and_query: &str,
+        model: Option<&ModelId>,
+    ) -> String {
+        match client {
+            Client::AzureOpenAI(_) => {}
+            Client::Vertex(vertex) => {
+                return vertex.path_and_query(extracted_path_and_query);
+            }
+            Client::WorkersAi(workers_ai) => {
+                return workers_ai.path_and_query(extracted_path_and_query);
+            }
+            _ => return extracted_path_and_query.to_string(),
         }
+        let api_version = self
+            .app_state
+            .config()
+            .providers
+            .get(&InferenceProvider::AzureOpenAI)
+            .and_then(|config| config.version.as_deref())
+            .unwrap_or(azure_openai_client::DEFAULT_AZURE_API_VERSION);
+        azure_openai_client::deployment_path_and_query(
+            extracted_path_and_query,
+            model.map(ToString::to_string).as_deref(),
+            api_version,
+        )
     }
 
     fn build_target_url(
@@ -598,21 +1149,21 @@ impl Dispatcher {
             && let Some(provider_config) =
                 router_provider_config.get(target_provider)
         {
-            return Ok(provider_config
-                .base_url
-                .join(extracted_path_and_query)
-                .expect(
-                    "PathAndQuery joined with valid url will always succeed",
-                ));
+            return Ok(join_url(
+                &provider_config.base_url,
+                provider_config.path_prefix.as_deref(),
+                extracted_path_and_query,
+            ));
         }
         let provider_config =
             config.providers.get(target_provider).ok_or_else(|| {
                 InternalError::ProviderNotConfigured(target_provider.clone())
             })?;
-        Ok(provider_config
-            .base_url
-            .join(extracted_path_and_query)
-            .expect("PathAndQuery joined with valid url will always succeed"))
+        Ok(join_url(
+            &provider_config.base_url,
+            provider_config.path_prefix.as_deref(),
+            extracted_path_and_query,
+        ))
     }
 
     /// We take a `&RequestBuilder` so that `dispatch_stream` implements `FnMut`
@@ -622,6 +1173,7 @@ impl Dispatcher {
         req_body_bytes: Bytes,
         api_endpoint: Option<ApiEndpoint>,
         metrics_registry: EndpointMetricsRegistry,
+        retry_policy: Option<&StreamRetryPolicy>,
     ) -> Result<
         (
             http::Response<crate::types::body::Body>,
@@ -630,21 +1182,44 @@ impl Dispatcher {
         ),
         ApiError,
     > {
-        let request_builder = request_builder.try_clone().ok_or_else(|| {
-            // in theory, this should never happen, as we'll have already
-            // collected the request body
-            tracing::error!(
-                "failed to clone request builder, cannot dispatch stream"
-            );
-            ApiError::Internal(InternalError::Internal)
-        })?;
-        let response_stream = Client::sse_stream(
-            request_builder,
-            req_body_bytes,
-            api_endpoint,
+        let try_clone = || {
+            request_builder.try_clone().ok_or_else(|| {
+                // in theory, this should never happen, as we'll have already
+                // collected the request body
+                tracing::error!(
+                    "failed to clone request builder, cannot dispatch stream"
+                );
+                ApiError::Internal(InternalError::Internal)
+            })
+        };
+        let response_stream = match Client::sse_stream(
+            try_clone()?,
+            req_body_bytes.clone(),
+            api_endpoint.clone(),
             &metrics_registry,
         )
-        .await?;
+        .await
+        {
+            Ok(response_stream) => response_stream,
+            Err(error)
+                if retry_policy
+                    .is_some_and(|policy| policy.retry_on.is_some()) =>
+            {
+                return Err(retry_on::inspect_stream_error(error).await);
+            }
+            Err(error) => return Err(error),
+        };
+        let response_stream = match retry_policy {
+            Some(policy) => StreamRetry::new(
+                policy.clone(),
+                try_clone()?,
+                req_body_bytes,
+                api_endpoint,
+                metrics_registry,
+            )
+            .wrap(response_stream),
+            None => response_stream,
+        };
         let mut resp_builder = http::Response::builder();
         *resp_builder.headers_mut().unwrap() = stream_response_headers();
         resp_builder = resp_builder.status(StatusCode::OK);
@@ -658,9 +1233,12 @@ impl Dispatcher {
         Ok((response, body_reader, tfft_rx))
     }
 
+    /// If `inspect_errors` is set, the body of error responses is read for
+    /// the provider's error codes.
     async fn dispatch_sync(
         request_builder: &RequestBuilder,
         req_body_bytes: Bytes,
+        inspect_errors: bool,
     ) -> Result<
         (
             http::Response<crate::types::body::Body>,
@@ -687,13 +1265,19 @@ impl Dispatcher {
         let mut resp_builder = http::Response::builder().status(status);
         *resp_builder.headers_mut().unwrap() = response.headers().clone();
 
-        // this is compiled out in release builds
-        #[cfg(debug_assertions)]
-        if status.is_server_error() || status.is_client_error() {
-            let body =
-                response.text().await.map_err(InternalError::ReqwestError)?;
-            tracing::debug!(status_code = %status, error_resp = %body, "received error response");
-            let bytes = bytes::Bytes::from(body);
+        // error bodies are only buffered in release builds if inspected
+        if (cfg!(debug_assertions) || inspect_errors)
+            && (status.is_server_error() || status.is_client_error())
+        {
+            let bytes = response
+                .bytes()
+                .await
+                .map_err(InternalError::ReqwestError)?;
+            tracing::debug!(status_code = %status, error_resp = %String::from_utf8_lossy(&bytes), "received error response");
+            if inspect_errors {
+                resp_builder = resp_builder
+                    .extension(retry_on::ProviderErrorCodes::from_body(&bytes));
+            }
             let stream = futures::stream::once(futures::future::ok::<
                 _,
                 ApiError,
@@ -726,6 +1310,8 @@ impl Dispatcher {
         req_body_bytes: Bytes,
         req_ctx: &RequestContext,
         request_kind: RequestKind,
+        request_deadline: Option<RequestDeadline>,
+        priority: Priority,
     ) -> Result<
         (
             http::Response<crate::types::body::Body>,
@@ -737,12 +1323,18 @@ impl Dispatcher {
         let retry_config =
             get_retry_config(&self.app_state, request_kind, req_ctx);
         if let Some(retry_config) = retry_config {
+            let timeouts = AttemptTimeouts::start(
+                retry_config,
+                request_deadline,
+                priority,
+            );
             match retry_config {
                 RetryConfig::Exponential {
                     min_delay,
                     max_delay,
                     max_retries,
                     factor,
+                    ..
                 } => {
                     let retry_strategy = ExponentialBuilder::default()
                         .with_max_delay(*max_delay)
@@ -753,28 +1345,40 @@ impl Dispatcher {
                         ))
                         .with_jitter()
                         .build();
+                    let retry_strategy = Budgeted::new(
+                        timeouts.limit(retry_strategy),
+                        self.retry_budget.clone(),
+                    );
                     let future_fn = || async {
-                        let result = Self::dispatch_sync(
-                            &request_builder,
-                            req_body_bytes.clone(),
-                        )
-                        .await?;
+                        let result = timeouts
+                            .run_hedged(|| {
+                                Self::dispatch_sync(
+                                    &request_builder,
+                                    req_body_bytes.clone(),
+                                    self.retry_on.is_some(),
+                                )
+                            })
+                            .await?;
 
                         Ok(result)
                     };
 
                     crate::utils::retry::RetryWithResult::new(future_fn, retry_strategy)
                     .when(|result: &Result<_, _>| match result {
-                        Ok(response) => response.0.status().is_server_error(),
+                        Ok(response) => retry_on::is_retryable_response(
+                            &response.0,
+                            self.retry_on.as_deref(),
+                        ),
                         Err(e) => match e {
                             ApiError::Internal(InternalError::ReqwestError(
                                 reqwest_error,
                             )) => reqwest_error.is_connect() || reqwest_error.status().is_some_and(|s| s.is_server_error()),
+                            ApiError::Internal(InternalError::AttemptTimeout(_)) => true,
                             _ => false,
                         },
                     })
                     .notify(|result: &Result<_, _>, dur: Duration| match result {
-                        Ok(result) if result.0.status().is_server_error() => {
+                        Ok(result) => {
                                 tracing::warn!(
                                     error = %result.0.status(),
                                     retry_in = ?dur,
@@ -790,36 +1394,57 @@ impl Dispatcher {
                                     "got error dispatching sync request, retrying...",
                                 );
                             }
+                        Err(error @ ApiError::Internal(InternalError::AttemptTimeout(_))) => {
+                                tracing::warn!(
+                                    error = %error,
+                                    retry_in = ?dur,
+                                    "got error dispatching sync request, retrying...",
+                                );
+                            }
                         _ => {}
                     })
                     .await
                 }
-                RetryConfig::Constant { delay, max_retries } => {
+                RetryConfig::Constant {
+                    delay, max_retries, ..
+                } => {
                     let retry_strategy = ConstantBuilder::default()
                         .with_delay(*delay)
                         .with_max_times(usize::from(*max_retries))
                         .with_jitter()
                         .build();
+                    let retry_strategy = Budgeted::new(
+                        timeouts.limit(retry_strategy),
+                        self.retry_budget.clone(),
+                    );
                     let future_fn = || async {
-                        Self::dispatch_sync(
-                            &request_builder,
-                            req_body_bytes.clone(),
-                        )
-                        .await
+                        timeouts
+                            .run_hedged(|| {
+                                Self::dispatch_sync(
+                                    &request_builder,
+                                    req_body_bytes.clone(),
+                                    self.retry_on.is_some(),
+                                )
+                            })
+                            .await
                     };
 
                     crate::utils::retry::RetryWithResult::new(future_fn, retry_strategy)
                     .when(|result: &Result<_, _>| match result {
-                        Ok(response) => response.0.status().is_server_error(),
+                        Ok(response) => retry_on::is_retryable_response(
+                            &response.0,
+                            self.retry_on.as_deref(),
+                        ),
                         Err(e) => match e {
                             ApiError::Internal(InternalError::ReqwestError(
                                 reqwest_error,
                             )) => reqwest_error.is_connect() || reqwest_error.status().is_some_and(|s| s.is_server_error()),
+                            ApiError::Internal(InternalError::AttemptTimeout(_)) => true,
                             _ => false,
                         },
                     })
                     .notify(|result: &Result<_, _>, dur: Duration| match result {
-                        Ok(result) if result.0.status().is_server_error() => {
+                        Ok(result) => {
                                 tracing::warn!(
                                     error = %result.0.status(),
                                     retry_in = ?dur,
@@ -835,17 +1460,26 @@ impl Dispatcher {
                                     "got error dispatching sync request, retrying...",
                                 );
                             }
+                        Err(error @ ApiError::Internal(InternalError::AttemptTimeout(_))) => {
+                                tracing::warn!(
+                                    error = %error,
+                                    retry_in = ?dur,
+                                    "got error dispatching sync request, retrying...",
+                                );
+                            }
                         _ => {}
                     })
                     .await
                 }
             }
         } else {
-            Self::dispatch_sync(&request_builder, req_body_bytes.clone()).await
+            Self::dispatch_sync(&request_builder, req_body_bytes.clone(), false)
+                .await
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn dispatch_stream_with_retry(
     app_state: &AppState,
     request_builder: RequestBuilder,
@@ -854,6 +1488,10 @@ async fn dispatch_stream_with_retry(
     metrics_registry: EndpointMetricsRegistry,
     request_ctx: &RequestContext,
     request_kind: RequestKind,
+    request_deadline: Option<RequestDeadline>,
+    priority: Priority,
+    retry_budget: Option<Arc<RetryBudget>>,
+    retry_on: Option<Arc<RetryOnConfig>>,
 ) -> Result<
     (
         http::Response<crate::types::body::Body>,
@@ -865,12 +1503,21 @@ async fn dispatch_stream_with_retry(
     let retry_config = get_retry_config(app_state, request_kind, request_ctx);
 
     if let Some(retry_config) = retry_config {
+        let timeouts =
+            AttemptTimeouts::start(retry_config, request_deadline, priority);
+        let retry_policy = StreamRetryPolicy {
+            mode: retry_config.stream_mode(),
+            max_retries: retry_config.max_retries(),
+            retry_on: retry_on.clone(),
+            retry_budget: retry_budget.clone(),
+        };
         match retry_config {
             RetryConfig::Exponential {
                 min_delay,
                 max_delay,
                 max_retries,
                 factor,
+                ..
             } => {
                 let retry_strategy =
                     ExponentialBuilder::default()
@@ -882,23 +1529,43 @@ async fn dispatch_stream_with_retry(
                         ))
                         .with_jitter()
                         .build();
+                let retry_strategy = Budgeted::new(
+                    timeouts.limit(retry_strategy),
+                    retry_budget.clone(),
+                );
                 (|| async {
-                    Dispatcher::dispatch_stream(
-                        &request_builder,
-                        req_body_bytes.clone(),
-                        api_endpoint.clone(),
-                        metrics_registry.clone(),
-                    )
-                    .await
+                    timeouts
+                        .run_hedged(|| {
+                            Dispatcher::dispatch_stream(
+                                &request_builder,
+                                req_body_bytes.clone(),
+                                api_endpoint.clone(),
+                                metrics_registry.clone(),
+                                Some(&retry_policy),
+                            )
+                        })
+                        .await
                 })
                 .retry(retry_strategy)
                 .sleep(tokio::time::sleep)
                 .when(|e: &ApiError| match e {
-                    ApiError::StreamError(s) => s.is_retryable(),
+                    ApiError::StreamError(s) => {
+                        retry_on::is_retryable_stream_error(
+                            s,
+                            retry_on.as_deref(),
+                        )
+                    }
+                    ApiError::Internal(InternalError::AttemptTimeout(_)) => {
+                        true
+                    }
                     _ => false,
                 })
                 .notify(|err: &ApiError, dur: Duration| {
-                    if let ApiError::StreamError(_s) = err {
+                    if let ApiError::StreamError(_)
+                    | ApiError::Internal(InternalError::AttemptTimeout(
+                        _,
+                    )) = err
+                    {
                         tracing::warn!(
                             error = %err,
                             retry_in = ?dur,
@@ -908,29 +1575,51 @@ async fn dispatch_stream_with_retry(
                 })
                 .await
             }
-            RetryConfig::Constant { delay, max_retries } => {
+            RetryConfig::Constant {
+                delay, max_retries, ..
+            } => {
                 let retry_strategy = ConstantBuilder::default()
                     .with_delay(*delay)
                     .with_max_times(usize::from(*max_retries))
                     .with_jitter()
                     .build();
+                let retry_strategy = Budgeted::new(
+                    timeouts.limit(retry_strategy),
+                    retry_budget.clone(),
+                );
                 (|| async {
-                    Dispatcher::dispatch_stream(
-                        &request_builder,
-                        req_body_bytes.clone(),
-                        api_endpoint.clone(),
-                        metrics_registry.clone(),
-                    )
-                    .await
+                    timeouts
+                        .run_hedged(|| {
+                            Dispatcher::dispatch_stream(
+                                &request_builder,
+                                req_body_bytes.clone(),
+                                api_endpoint.clone(),
+                                metrics_registry.clone(),
+                                Some(&retry_policy),
+                            )
+                        })
+                        .await
                 })
                 .retry(retry_strategy)
                 .sleep(tokio::time::sleep)
                 .when(|e: &ApiError| match e {
-                    ApiError::StreamError(s) => s.is_retryable(),
+                    ApiError::StreamError(s) => {
+                        retry_on::is_retryable_stream_error(
+                            s,
+                            retry_on.as_deref(),
+                        )
+                    }
+                    ApiError::Internal(InternalError::AttemptTimeout(_)) => {
+                        true
+                    }
                     _ => false,
                 })
                 .notify(|err: &ApiError, dur: Duration| {
-                    if let ApiError::StreamError(_s) = err {
+                    if let ApiError::StreamError(_)
+                    | ApiError::Internal(InternalError::AttemptTimeout(
+                        _,
+                    )) = err
+                    {
                         tracing::warn!(
                             error = %err,
                             retry_in = ?dur,
@@ -947,11 +1636,59 @@ async fn dispatch_stream_with_retry(
             req_body_bytes.clone(),
             api_endpoint,
             metrics_registry,
+            None,
         )
         .await
     }
 }
 
+/// Whether the outcome of a request to a provider region counts against the
+/// region's health.
+fn is_region_failure<T>(
+    result: &Result<
+        (http::Response<T>, BodyReader, oneshot::Receiver<()>),
+        ApiError,
+    >,
+) -> bool {
+    match result {
+        Ok((response, _, _)) => response.status().is_server_error(),
+        Err(ApiError::StreamError(error)) => error.is_retryable(),
+        Err(error) => is_overload_error(error),
+    }
+}
+
+/// Whether a response status indicates that the provider is overloaded.
+fn is_overload_status(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Whether a dispatch error indicates that the provider is overloaded.
+fn is_overload_error(error: &ApiError) -> bool {
+    match error {
+        ApiError::Internal(InternalError::ReqwestError(error)) => {
+            error.is_timeout() || error.is_connect()
+        }
+        ApiError::Internal(InternalError::AttemptTimeout(_)) => true,
+        ApiError::StreamError(StreamError::StreamError(error)) => {
+            match &**error {
+                reqwest_eventsource::Error::Transport(error) => {
+                    error.is_timeout() || error.is_connect()
+                }
+                reqwest_eventsource::Error::InvalidStatusCode(status, _) => {
+                    is_overload_status(*status)
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
 fn extract_retry_after(headers: &HeaderMap) -> Option<u64> {
     let retry_after_str = headers
         .get(http::header::RETRY_AFTER)
@@ -0,0 +1,269 @@
+//! Retries of streams which fail after they started.
+//!
+//! A stream is only retried as a whole while the provider hasn't sent its
+//! first event. A stream which fails later is retried only if nothing was
+//! sent to the client yet, since the client would otherwise receive the same
+//! content twice. In the `resume` stream mode, a stream which already sent
+//! content is instead resumed: the request is replayed with the streamed
+//! content as a prefill of the response, which the provider continues.
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use reqwest::RequestBuilder;
+use serde_json::{Value, json};
+
+use super::{SSEStream, client::Client, retry_budget::RetryBudget, retry_on};
+use crate::{
+    config::retry::{RetryOnConfig, StreamRetryMode},
+    discover::monitor::metrics::EndpointMetricsRegistry,
+    endpoints::ApiEndpoint,
+    error::api::ApiError,
+};
+
+/// Anthropic stream events which precede the content of a message.
+const ANTHROPIC_PREAMBLE_EVENTS: &[&str] =
+    &["message_start", "content_block_start", "ping"];
+
+#[derive(Debug, Clone)]
+pub struct StreamRetryPolicy {
+    pub mode: StreamRetryMode,
+    pub max_retries: u8,
+    pub retry_on: Option<Arc<RetryOnConfig>>,
+    pub retry_budget: Option<Arc<RetryBudget>>,
+}
+
+pub struct StreamRetry {
+    policy: StreamRetryPolicy,
+    request_builder: RequestBuilder,
+    body: Bytes,
+    api_endpoint: Option<ApiEndpoint>,
+    metrics_registry: EndpointMetricsRegistry,
+    retries: u8,
+}
+
+/// The progress of a stream sent to the client.
+struct Progress {
+    retry: StreamRetry,
+    stream: SSEStream,
+    sent: bool,
+    /// The content sent to the client, if the stream may be resumed.
+    streamed: String,
+    /// Whether the preamble of a resumed stream is being skipped.
+    resuming: bool,
+}
+
+impl StreamRetry {
+    #[must_use]
+    pub fn new(
+        policy: StreamRetryPolicy,
+        request_builder: RequestBuilder,
+        body: Bytes,
+        api_endpoint: Option<ApiEndpoint>,
+        metrics_registry: EndpointMetricsRegistry,
+    ) -> Self {
+        Self {
+            policy,
+            request_builder,
+            body,
+            api_endpoint,
+            metrics_registry,
+            retries: 0,
+        }
+    }
+
+    fn can_resume(&self) -> bool {
+        self.policy.mode == StreamRetryMode::Resume
+            && matches!(self.api_endpoint, Some(ApiEndpoint::Anthropic(_)))
+    }
+
+    /// Retries the stream after `error`, returning `None` if it can't be.
+    async fn reconnect(
+        &mut self,
+        error: &ApiError,
+        sent: bool,
+        streamed: &str,
+    ) -> Option<SSEStream> {
+        let ApiError::StreamError(stream_error) = error else {
+            return None;
+        };
+        if !retry_on::is_retryable_stream_error(
+            stream_error,
+            self.policy.retry_on.as_deref(),
+        ) || self.retries >= self.policy.max_retries
+        {
+            return None;
+        }
+        let body = if !sent {
+            self.body.clone()
+        } else if self.can_resume() && !streamed.is_empty() {
+            with_prefill(&self.body, streamed)?
+        } else {
+            return None;
+        };
+        if let Some(retry_budget) = &self.policy.retry_budget
+            && !retry_budget.try_retry()
+        {
+            return None;
+        }
+        self.retries += 1;
+        tracing::warn!(
+            error = %error,
+            resume = sent,
+            "stream failed after it started, retrying..."
+        );
+        let request_builder = self.request_builder.try_clone()?;
+        Client::sse_stream(
+            request_builder,
+            body,
+            self.api_endpoint.clone(),
+            &self.metrics_registry,
+        )
+        .await
+        .inspect_err(|error| {
+            tracing::warn!(error = %error, "failed to retry stream");
+        })
+        .ok()
+    }
+
+    /// Retries `stream` when it fails, as far as the policy allows.
+    #[must_use]
+    pub fn wrap(self, stream: SSEStream) -> SSEStream {
+        let progress = Progress {
+            retry: self,
+            stream,
+            sent: false,
+            streamed: String::new(),
+            resuming: false,
+        };
+        Box::pin(futures::stream::unfold(
+            progress,
+            |mut progress| async move {
+                loop {
+                    match progress.stream.next().await? {
+                        Ok(chunk) => {
+                            if progress.resuming {
+                                if is_preamble(&chunk) {
+                                    continue;
+                                }
+                                progress.resuming = false;
+                            }
+                            if progress.retry.can_resume() {
+                                progress
+                                    .streamed
+                                    .push_str(&streamed_text(&chunk));
+                            }
+                            progress.sent = true;
+                            return Some((Ok(chunk), progress));
+                        }
+                        Err(error) => {
+                            let Some(stream) = progress
+                                .retry
+                                .reconnect(
+                                    &error,
+                                    progress.sent,
+                                    &progress.streamed,
+                                )
+                                .await
+                            else {
+                                return Some((Err(error), progress));
+                            };
+                            progress.resuming = progress.sent;
+                            progress.stream = stream;
+                        }
+                    }
+                }
+            },
+        ))
+    }
+}
+
+/// The text content of an Anthropic stream event.
+fn streamed_text(chunk: &Bytes) -> String {
+    serde_json::from_slice::<Value>(chunk)
+        .ok()
+        .and_then(|event| {
+            event
+                .pointer("/delta/text")
+                .and_then(Value::as_str)
+                .map(ToString::to_string)
+        })
+        .unwrap_or_default()
+}
+
+fn is_preamble(chunk: &Bytes) -> bool {
+    serde_json::from_slice::<Value>(chunk).is_ok_and(|event| {
+        event
+            .get("type")
+            .and_then(Value::as_str)
+            .is_some_and(|ty| ANTHROPIC_PREAMBLE_EVENTS.contains(&ty))
+    })
+}
+
+/// Adds `streamed` to the request `body` as a prefill of the assistant's
+/// response.
+fn with_prefill(body: &Bytes, streamed: &str) -> Option<Bytes> {
+    let mut body = serde_json::from_slice::<Value>(body).ok()?;
+    let messages = body.get_mut("messages")?.as_array_mut()?;
+    // providers reject prefills ending with whitespace
+    let streamed = streamed.trim_end();
+    match messages.last_mut() {
+        Some(last)
+            if last.get("role").and_then(Value::as_str)
+                == Some("assistant")
+                && last.get("content").is_some_and(Value::is_string) =>
+        {
+            let content = last["content"].as_str().unwrap_or_default();
+            last["content"] = Value::String(format!("{content}{streamed}"));
+        }
+        _ => messages.push(json!({"role": "assistant", "content": streamed})),
+    }
+    serde_json::to_vec(&body).ok().map(Bytes::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn streamed_content_is_prefilled() {
+        let body = Bytes::from_static(
+            br#"{"model": "claude-3-5-sonnet", "messages": [
+                {"role": "user", "content": "Count to five"}
+            ]}"#,
+        );
+        let body = with_prefill(&body, "1, 2, ").unwrap();
+        let body = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(
+            body["messages"][1],
+            json!({"role": "assistant", "content": "1, 2,"})
+        );
+    }
+
+    #[test]
+    fn client_prefills_are_extended() {
+        let body = Bytes::from_static(
+            br#"{"messages": [
+                {"role": "user", "content": "Count to five"},
+                {"role": "assistant", "content": "1"}
+            ]}"#,
+        );
+        let body = with_prefill(&body, ", 2").unwrap();
+        let body = serde_json::from_slice::<Value>(&body).unwrap();
+        assert_eq!(body["messages"].as_array().unwrap().len(), 2);
+        assert_eq!(body["messages"][1]["content"], "1, 2");
+    }
+
+    #[test]
+    fn anthropic_events_are_read() {
+        let delta = Bytes::from_static(
+            br#"{"type": "content_block_delta", "index": 0,
+                "delta": {"type": "text_delta", "text": "Hello"}}"#,
+        );
+        assert_eq!(streamed_text(&delta), "Hello");
+        assert!(!is_preamble(&delta));
+        let ping = Bytes::from_static(br#"{"type": "ping"}"#);
+        assert!(is_preamble(&ping));
+        assert_eq!(streamed_text(&ping), "");
+    }
+}
@@ -0,0 +1,112 @@
+//! Client certificates for providers requiring mutual TLS.
+//!
+//! Private inference clusters are commonly exposed under the same provider
+//! name as the public API, but only accept connections presenting a client
+//! certificate of the tenant. Routers configure the identity along with the
+//! provider's `base-url`, and their clients present it on every connection.
+use std::path::Path;
+
+use reqwest::{Certificate, ClientBuilder, Identity};
+
+use crate::{
+    config::router::{ProviderTlsConfig, RouterConfig},
+    error::init::InitError,
+    types::provider::InferenceProvider,
+};
+
+/// The client identity of a router for a provider, read once so that
+/// recycled clients don't go back to the filesystem.
+#[derive(Debug, Clone)]
+pub struct ProviderTls {
+    identity: Identity,
+    ca: Option<Certificate>,
+}
+
+impl ProviderTls {
+    /// Returns `None` if the router doesn't configure a client certificate
+    /// for `provider`.
+    pub fn for_provider(
+        router_config: &RouterConfig,
+        provider: &InferenceProvider,
+    ) -> Result<Option<Self>, InitError> {
+        router_config
+            .providers
+            .as_ref()
+            .and_then(|providers| providers.get(provider))
+            .and_then(|provider_config| provider_config.tls.as_ref())
+            .map(Self::new)
+            .transpose()
+    }
+
+    fn new(config: &ProviderTlsConfig) -> Result<Self, InitError> {
+        let cert = read(&config.cert)?;
+        let key = read(&config.key)?;
+        let identity = Identity::from_pkcs8_pem(&cert, &key)
+            .map_err(InitError::InvalidProviderTls)?;
+        let ca = config
+            .ca
+            .as_deref()
+            .map(|ca| {
+                Certificate::from_pem(&read(ca)?)
+                    .map_err(InitError::InvalidProviderTls)
+            })
+            .transpose()?;
+        Ok(Self { identity, ca })
+    }
+
+    pub fn apply(&self, mut builder: ClientBuilder) -> ClientBuilder {
+        builder = builder.identity(self.identity.clone());
+        if let Some(ca) = &self.ca {
+            builder = builder.add_root_certificate(ca.clone());
+        }
+        builder
+    }
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, InitError> {
+    std::fs::read(path)
+        .map_err(|e| InitError::ProviderTls(path.display().to_string(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unreadable_certificate_is_an_error() {
+        let yaml = r"
+base-url: https://inference.tenant-a.internal
+tls:
+  cert: /nonexistent/client.pem
+  key: /nonexistent/client.key
+";
+        let router_config = RouterConfig {
+            providers: Some(
+                [(
+                    InferenceProvider::OpenAI,
+                    serde_yml::from_str(yaml).unwrap(),
+                )]
+                .into_iter()
+                .collect(),
+            ),
+            ..RouterConfig::default()
+        };
+        let result = ProviderTls::for_provider(
+            &router_config,
+            &InferenceProvider::OpenAI,
+        );
+        assert!(matches!(
+            result,
+            Err(InitError::ProviderTls(path, _))
+                if path == "/nonexistent/client.pem"
+        ));
+        assert!(
+            ProviderTls::for_provider(
+                &router_config,
+                &InferenceProvider::Anthropic
+            )
+            .unwrap()
+            .is_none()
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Cloudflare Workers AI, whose `OpenAI` compatible endpoints are scoped to
+//! an account.
+use reqwest::ClientBuilder;
+
+use crate::{
+    app_state::AppState,
+    dispatcher::openai_compatible_client::Client as OpenAICompatibleClient,
+    error::init::InitError,
+    types::provider::{InferenceProvider, ProviderKey},
+};
+
+/// The name Workers AI is configured under.
+pub(crate) const WORKERS_AI: &str = "cloudflare";
+/// The environment variable of the account, if not configured.
+const ACCOUNT_ID_ENV: &str = "CLOUDFLARE_ACCOUNT_ID";
+/// The prefix of paths which already address an account, such as those of
+/// direct proxy requests.
+const ACCOUNTS_PATH_PREFIX: &str = "accounts/";
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub(super) inner: reqwest::Client,
+    /// Is `None` if no account is configured, in which case requests are
+    /// sent to the paths they were made to.
+    account_id: Option<String>,
+}
+
+impl Client {
+    pub fn new(
+        app_state: &AppState,
+        client_builder: ClientBuilder,
+        provider_key: Option<&ProviderKey>,
+    ) -> Result<Self, InitError> {
+        let provider = InferenceProvider::Named(WORKERS_AI.into());
+        let account_id = app_state
+            .config()
+            .providers
+            .get(&provider)
+            .and_then(|config| config.account_id.clone())
+            .or_else(|| std::env::var(ACCOUNT_ID_ENV).ok());
+        // authenticated with a bearer token like `OpenAI` compatible
+        // providers
+        let OpenAICompatibleClient(inner) = OpenAICompatibleClient::new(
+            app_state,
+            client_builder,
+            provider,
+            provider_key,
+        )?;
+        Ok(Self { inner, account_id })
+    }
+
+    /// Scopes the path of a request to the account.
+    #[must_use]
+    pub fn path_and_query(&self, path_and_query: &str) -> String {
+        account_path_and_query(path_and_query, self.account_id.as_deref())
+    }
+}
+
+fn account_path_and_query(
+    path_and_query: &str,
+    account_id: Option<&str>,
+) -> String {
+    let path_and_query = path_and_query.trim_start_matches('/');
+    match account_id {
+        Some(account_id)
+            if !path_and_query.starts_with(ACCOUNTS_PATH_PREFIX) =>
+        {
+            format!("accounts/{account_id}/ai/{path_and_query}")
+        }
+        _ => path_and_query.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_scoped_to_the_account() {
+        assert_eq!(
+            account_path_and_query("v1/chat/completions", Some("abc123")),
+            "accounts/abc123/ai/v1/chat/completions"
+        );
+        assert_eq!(
+            account_path_and_query(
+                "/accounts/other/ai/run/@cf/meta/llama-3.1-8b-instruct",
+                Some("abc123")
+            ),
+            "accounts/other/ai/run/@cf/meta/llama-3.1-8b-instruct"
+        );
+        assert_eq!(
+            account_path_and_query("v1/chat/completions", None),
+            "v1/chat/completions"
+        );
+    }
+}
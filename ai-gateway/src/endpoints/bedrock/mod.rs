@@ -1,8 +1,10 @@
 pub(crate) mod converse;
 
+use url::form_urlencoded;
+
 use super::EndpointType;
 pub(crate) use crate::endpoints::bedrock::converse::Converse;
-use crate::types::model_id::ModelId;
+use crate::types::model_id::{BedrockModelId, ModelId};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumIter)]
 pub enum Bedrock {
@@ -12,6 +14,13 @@ pub enum Bedrock {
 impl Bedrock {
     #[must_use]
     pub fn path(self, model_id: &ModelId, is_stream: bool) -> String {
+        // ARNs contain a `/`, so they must be encoded to be a path segment
+        let model_id = match model_id {
+            ModelId::Bedrock(BedrockModelId { arn: Some(arn), .. }) => {
+                form_urlencoded::byte_serialize(arn.as_bytes()).collect()
+            }
+            model_id => model_id.to_string(),
+        };
         match self {
             Self::Converse(_) => {
                 if is_stream {
@@ -0,0 +1,65 @@
+pub mod chat;
+pub mod rerank;
+
+use serde::{Deserialize, Serialize};
+
+use super::{Endpoint, EndpointType};
+pub use crate::endpoints::cohere::{chat::Chat, rerank::Rerank};
+use crate::error::invalid_req::InvalidRequestError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::EnumIter)]
+pub enum Cohere {
+    Chat(Chat),
+    Rerank(Rerank),
+}
+
+impl Cohere {
+    #[must_use]
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Chat(_) => Chat::PATH,
+            Self::Rerank(_) => Rerank::PATH,
+        }
+    }
+
+    #[must_use]
+    pub fn chat() -> Self {
+        Self::Chat(Chat)
+    }
+
+    #[must_use]
+    pub fn rerank() -> Self {
+        Self::Rerank(Rerank)
+    }
+
+    #[must_use]
+    pub fn endpoint_type(&self) -> EndpointType {
+        match self {
+            Self::Chat(_) => EndpointType::Chat,
+            Self::Rerank(_) => EndpointType::Rerank,
+        }
+    }
+}
+
+impl TryFrom<&str> for Cohere {
+    type Error = InvalidRequestError;
+
+    fn try_from(path: &str) -> Result<Self, Self::Error> {
+        match path {
+            Chat::PATH => Ok(Self::Chat(Chat)),
+            Rerank::PATH => Ok(Self::Rerank(Rerank)),
+            path => {
+                tracing::debug!(path = %path, "unsupported cohere path");
+                Err(InvalidRequestError::NotFound(path.to_string()))
+            }
+        }
+    }
+}
+
+/// The body of Cohere's error responses.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CohereApiError {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub message: String,
+}
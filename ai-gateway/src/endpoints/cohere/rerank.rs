@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::{
+    endpoints::{AiRequest, Endpoint, cohere::CohereApiError},
+    error::mapper::MapperError,
+    types::{model_id::ModelId, provider::InferenceProvider},
+};
+
+/// Cohere's rerank, which has no chat completions equivalent and so is only
+/// served through the provider's direct proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Rerank;
+
+impl Endpoint for Rerank {
+    // https://docs.cohere.com/v1/reference/rerank
+    const PATH: &'static str = "v1/rerank";
+    type RequestBody = RerankRequest;
+    type ResponseBody = RerankResponse;
+    // rerank responses are never streamed
+    type StreamResponseBody = RerankResponse;
+    type ErrorResponseBody = CohereApiError;
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct RerankRequest {
+    pub model: String,
+    pub query: String,
+    pub documents: Vec<RerankDocument>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_n: Option<u32>,
+    /// The fields of structured documents to rerank them by.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rank_fields: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_documents: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_chunks_per_doc: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum RerankDocument {
+    Text(String),
+    Fields(Map<String, Value>),
+}
+
+impl AiRequest for RerankRequest {
+    fn is_stream(&self) -> bool {
+        false
+    }
+
+    fn model(&self) -> Result<ModelId, MapperError> {
+        ModelId::from_str_and_provider(InferenceProvider::Cohere, &self.model)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RerankResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub results: Vec<RerankResult>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct RerankResult {
+    /// The index of the document in the request.
+    pub index: u32,
+    pub relevance_score: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub document: Option<Value>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rerank_round_trip() {
+        let request = serde_json::json!({
+            "model": "rerank-v3.5",
+            "query": "capital of the United States",
+            "documents": [
+                "Carson City is the capital of Nevada.",
+                {"title": "Washington, D.C.", "text": "The capital."},
+            ],
+            "top_n": 1,
+            "rank_fields": ["title", "text"],
+        });
+        let parsed =
+            serde_json::from_value::<RerankRequest>(request.clone()).unwrap();
+        assert!(matches!(parsed.documents[0], RerankDocument::Text(_)));
+        assert!(matches!(parsed.documents[1], RerankDocument::Fields(_)));
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), request);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::endpoints::{
-    anthropic::Anthropic, bedrock::Bedrock, google::Google, ollama::Ollama,
-    openai::OpenAI,
+    anthropic::Anthropic, bedrock::Bedrock, cohere::Cohere, google::Google,
+    ollama::Ollama, openai::OpenAI,
 };
 
 impl From<Anthropic> for OpenAI {
@@ -57,3 +57,11 @@ impl From<OpenAI> for Bedrock {
         }
     }
 }
+
+impl From<OpenAI> for Cohere {
+    fn from(value: OpenAI) -> Self {
+        match value {
+            OpenAI::ChatCompletions(_) => Self::chat(),
+        }
+    }
+}
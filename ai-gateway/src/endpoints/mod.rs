@@ -1,5 +1,6 @@
 pub mod anthropic;
 pub(crate) mod bedrock;
+pub mod cohere;
 pub mod google;
 pub mod mappings;
 pub mod ollama;
@@ -9,8 +10,8 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     endpoints::{
-        anthropic::Anthropic, bedrock::Bedrock, google::Google, ollama::Ollama,
-        openai::OpenAI,
+        anthropic::Anthropic, bedrock::Bedrock, cohere::Cohere, google::Google,
+        ollama::Ollama, openai::OpenAI,
     },
     error::{
         internal::InternalError, invalid_req::InvalidRequestError,
@@ -71,6 +72,7 @@ pub enum ApiEndpoint {
     Google(Google),
     Ollama(Ollama),
     Bedrock(Bedrock),
+    Cohere(Cohere),
     OpenAICompatible {
         provider: InferenceProvider,
         openai_endpoint: OpenAI,
@@ -104,12 +106,27 @@ impl ApiEndpoint {
             (Self::OpenAI(source), InferenceProvider::Bedrock) => {
                 Ok(Self::Bedrock(Bedrock::from(source)))
             }
+            (Self::OpenAI(source), InferenceProvider::Cohere) => {
+                Ok(Self::Cohere(Cohere::from(source)))
+            }
             (Self::OpenAI(source), InferenceProvider::Named(name)) => {
                 Ok(Self::OpenAICompatible {
                     provider: InferenceProvider::Named(name.clone()),
                     openai_endpoint: source,
                 })
             }
+            (Self::OpenAI(source), InferenceProvider::AzureOpenAI) => {
+                Ok(Self::OpenAICompatible {
+                    provider: InferenceProvider::AzureOpenAI,
+                    openai_endpoint: source,
+                })
+            }
+            (Self::OpenAI(source), InferenceProvider::Vertex) => {
+                Ok(Self::OpenAICompatible {
+                    provider: InferenceProvider::Vertex,
+                    openai_endpoint: source,
+                })
+            }
             _ => Err(InvalidRequestError::UnsupportedProvider(
                 target_provider.clone(),
             )),
@@ -124,6 +141,7 @@ impl ApiEndpoint {
             Self::Google(_) => InferenceProvider::GoogleGemini,
             Self::Ollama(_) => InferenceProvider::Ollama,
             Self::Bedrock(_) => InferenceProvider::Bedrock,
+            Self::Cohere(_) => InferenceProvider::Cohere,
             Self::OpenAICompatible { provider, .. } => provider.clone(),
         }
     }
@@ -141,6 +159,7 @@ impl ApiEndpoint {
             Self::Anthropic(anthropic) => Ok(anthropic.path().to_string()),
             Self::Google(google) => Ok(google.path().to_string()),
             Self::Ollama(ollama) => Ok(ollama.path().to_string()),
+            Self::Cohere(cohere) => Ok(cohere.path().to_string()),
             Self::Bedrock(bedrock) => {
                 if let Some(model_id) = model_id {
                     Ok(bedrock.path(model_id, is_stream))
@@ -163,6 +182,7 @@ impl ApiEndpoint {
             Self::Google(google) => google.endpoint_type(),
             Self::Ollama(ollama) => ollama.endpoint_type(),
             Self::Bedrock(bedrock) => bedrock.endpoint_type(),
+            Self::Cohere(cohere) => cohere.endpoint_type(),
         }
     }
 }
@@ -184,4 +204,5 @@ pub enum EndpointType {
     Chat,
     Image,
     Audio,
+    Rerank,
 }
@@ -3,12 +3,15 @@ use telemetry::TelemetryError;
 use thiserror::Error;
 
 use crate::{
-    config::validation::ModelMappingValidationError,
+    config::validation::{
+        Issue, ModelMappingValidationError, ValidationErrors,
+    },
     types::{provider::InferenceProvider, router::RouterId},
 };
 
 /// Errors that can occur during initialization.
-#[derive(Debug, Error, Display)]
+#[derive(Debug, Error, Display, strum::IntoStaticStr)]
+#[strum(serialize_all = "kebab-case")]
 pub enum InitError {
     /// Default router not found
     DefaultRouterNotFound,
@@ -22,6 +25,12 @@ pub enum InitError {
     InvalidBucketConfig(#[from] rusty_s3::BucketError),
     /// OAuth config: {0}
     OAuthConfig(url::ParseError),
+    /// Invalid Helicone log url: {0}
+    HeliconeLogUrl(url::ParseError),
+    /// Failed to open dead-letter queue: {0}
+    OpenDlq(std::io::Error),
+    /// Failed to open request journal: {0}
+    OpenJournal(std::io::Error),
     /// Failed to create reqwest client: {0}
     CreateReqwestClient(reqwest::Error),
     /// Failed to create balancer: {0}
@@ -32,6 +41,27 @@ pub enum InitError {
     InvalidWeight(InferenceProvider),
     /// Invalid balancer: {0}
     InvalidBalancer(String),
+    /// Invalid config: {0}
+    InvalidConfig(#[from] ValidationErrors),
+    /// API key not found for provider region: {0}
+    RegionKeyNotFound(String),
+    /// Invalid Vertex AI service account: {0}
+    InvalidServiceAccount(String),
+    /// Invalid auth header of provider: {0}
+    InvalidAuthHeader(InferenceProvider),
+    /// Failed to read provider client certificate {0}: {1}
+    ProviderTls(String, std::io::Error),
+    /// Invalid provider client certificate: {0}
+    InvalidProviderTls(reqwest::Error),
+    /// WASM filters are configured, but the gateway was built without the
+    /// `wasm-filters` feature
+    WasmFiltersDisabled,
+    /// Failed to load WASM filter: {0}
+    WasmFilter(String),
+    /// Failed to compile script {0}: {1}
+    Script(String, String),
+    /// Webhook host '{0}' is not on the egress allowlist
+    WebhookEgressDenied(String),
     /// Converter registry endpoints not configured for provider: {0}
     EndpointsNotConfigured(InferenceProvider),
     /// Failed to create redis pool: {0}
@@ -54,8 +84,6 @@ pub enum InitError {
     RateLimitChannelsNotInitialized(RouterId),
     /// Failed to build websocket request: {0}
     WebsocketRequestBuild(#[from] http::Error),
-    /// Invalid router id: {0}
-    InvalidRouterId(String),
     /// Cache not configured
     CacheNotConfigured,
     /// Minio not configured
@@ -78,8 +106,29 @@ pub enum InitError {
     RouterTxNotSet,
     /// Database listener only compatible with cloud deployment target
     DatabaseListenerOnlyCloud,
+    /// Usage reports only compatible with cloud deployment target
+    UsageReportOnlyCloud,
     /// Failed to load initial helicone api keys from db: {0}
     InitHeliconeKeys(String),
+    /// Failed to load initial model mappings from db: {0}
+    InitModelMappings(String),
     /// Failed to load initial routers from db: {0}
     InitRouters(String),
 }
+
+impl InitError {
+    /// The problems behind this error, for tooling to consume. Each kind of
+    /// error has a distinct code.
+    #[must_use]
+    pub fn issues(&self) -> Vec<Issue> {
+        if let Self::InvalidConfig(errors) = self {
+            return errors.issues();
+        }
+        vec![Issue {
+            code: self.into(),
+            path: None,
+            message: self.to_string(),
+            suggestion: None,
+        }]
+    }
+}
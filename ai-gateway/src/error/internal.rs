@@ -1,6 +1,8 @@
+use std::time::Duration;
+
 use axum_core::response::{IntoResponse, Response};
 use displaydoc::Display;
-use http::StatusCode;
+use http::{HeaderValue, StatusCode};
 use thiserror::Error;
 use tower::BoxError;
 use tracing::error;
@@ -67,6 +69,8 @@ pub enum InternalError {
     MetricsNotConfigured(ApiEndpoint),
     /// Failed to sign AWS request: {0}
     AwsRequestSigningError(String),
+    /// Failed to get Vertex AI access token: {0}
+    VertexTokenError(String),
     /// Dynamic router discovery error: {0}
     DynamicRouterDiscoveryError(BoxError),
     /// Cache error: {0}
@@ -79,14 +83,61 @@ pub enum InternalError {
     PromptError(#[from] crate::error::prompts::PromptError),
     /// Failed to complete prompt task: {0}
     PromptTaskError(tokio::task::JoinError),
+    /// Failed to complete token count task: {0}
+    TokenCountTaskError(tokio::task::JoinError),
     /// Auth data not ready
     AuthDataNotReady,
     /// Database error: {0}
     DatabaseError(#[from] sqlx::Error),
+    /// Too many concurrent requests to provider: {0}
+    BulkheadFull(InferenceProvider),
+    /// Provider did not respond within {0:?}
+    AttemptTimeout(Duration),
+    /// Request deadline exceeded
+    DeadlineExceeded,
+    /// {0}
+    Frozen(String),
+    /// Host '{0}' is not on the egress allowlist
+    EgressDenied(String),
+    /// Gateway is overloaded
+    Overloaded { retry_after: Duration },
+    /// Filter failed: {0}
+    FilterFailed(String),
 }
 
 impl IntoResponse for InternalError {
     fn into_response(self) -> Response {
+        let unavailable = match self {
+            Self::BulkheadFull(_)
+            | Self::Overloaded { .. }
+            | Self::Frozen(_) => Some(StatusCode::SERVICE_UNAVAILABLE),
+            Self::AttemptTimeout(_) | Self::DeadlineExceeded => {
+                Some(StatusCode::GATEWAY_TIMEOUT)
+            }
+            Self::EgressDenied(_) => Some(StatusCode::FORBIDDEN),
+            _ => None,
+        };
+        if let Some(status) = unavailable {
+            let mut response = (
+                status,
+                Json(ErrorResponse {
+                    error: ErrorDetails {
+                        message: self.to_string(),
+                        r#type: Some(SERVER_ERROR_TYPE.to_string()),
+                        param: None,
+                        code: None,
+                    },
+                }),
+            )
+                .into_response();
+            if let Self::Overloaded { retry_after } = self {
+                response.headers_mut().insert(
+                    http::header::RETRY_AFTER,
+                    HeaderValue::from(retry_after.as_secs().max(1)),
+                );
+            }
+            return response;
+        }
         error!(error = %self, "internal error");
         (
             StatusCode::INTERNAL_SERVER_ERROR,
@@ -152,6 +203,8 @@ pub enum InternalErrorMetric {
     MetricsNotConfigured,
     /// Failed to sign AWS request
     AwsRequestSigningError,
+    /// Failed to get Vertex AI access token
+    VertexTokenError,
     /// Cache error
     CacheError,
     /// Dynamic router discovery error
@@ -166,6 +219,20 @@ pub enum InternalErrorMetric {
     AuthDataNotReady,
     /// Database error
     DatabaseError,
+    /// Provider bulkhead full
+    BulkheadFull,
+    /// Provider attempt timed out
+    AttemptTimeout,
+    /// Request deadline exceeded
+    DeadlineExceeded,
+    /// Traffic frozen by an operator
+    Frozen,
+    /// Host not on the egress allowlist
+    EgressDenied,
+    /// Gateway is overloaded
+    Overloaded,
+    /// Filter failed
+    FilterFailed,
 }
 
 impl From<&InternalError> for InternalErrorMetric {
@@ -192,7 +259,8 @@ impl From<&InternalError> for InternalErrorMetric {
             InternalError::InvalidUri(_) => Self::InvalidUri,
             InternalError::InvalidHeader(_) => Self::InvalidHeader,
             InternalError::MappingTaskError(_)
-            | InternalError::PromptTaskError(_) => Self::TokioTaskError,
+            | InternalError::PromptTaskError(_)
+            | InternalError::TokenCountTaskError(_) => Self::TokioTaskError,
             InternalError::InvalidConverter(_, _) => Self::InvalidConverter,
             InternalError::Provider5xxError(_) => Self::Provider5xxError,
             InternalError::MetricsNotConfigured(_) => {
@@ -201,6 +269,7 @@ impl From<&InternalError> for InternalErrorMetric {
             InternalError::AwsRequestSigningError(_) => {
                 Self::AwsRequestSigningError
             }
+            InternalError::VertexTokenError(_) => Self::VertexTokenError,
             InternalError::CacheError(_) => Self::CacheError,
             InternalError::RedisError(_) => Self::RedisError,
             InternalError::PoolError(_) => Self::PoolError,
@@ -210,6 +279,13 @@ impl From<&InternalError> for InternalErrorMetric {
             }
             InternalError::AuthDataNotReady => Self::AuthDataNotReady,
             InternalError::DatabaseError(_) => Self::DatabaseError,
+            InternalError::BulkheadFull(_) => Self::BulkheadFull,
+            InternalError::AttemptTimeout(_) => Self::AttemptTimeout,
+            InternalError::DeadlineExceeded => Self::DeadlineExceeded,
+            InternalError::Frozen(_) => Self::Frozen,
+            InternalError::EgressDenied(_) => Self::EgressDenied,
+            InternalError::Overloaded { .. } => Self::Overloaded,
+            InternalError::FilterFailed(_) => Self::FilterFailed,
         }
     }
 }
@@ -1,13 +1,14 @@
 use axum_core::response::IntoResponse;
 use displaydoc::Display;
 use http::{HeaderMap, StatusCode};
+use rust_decimal::Decimal;
 use thiserror::Error;
 use tracing::debug;
 
 use crate::{
     error::api::{ErrorDetails, ErrorResponse},
     middleware::mapper::openai::INVALID_REQUEST_ERROR_TYPE,
-    types::{json::Json, provider::InferenceProvider},
+    types::{json::Json, priority::Priority, provider::InferenceProvider},
 };
 
 #[derive(Debug, Display)]
@@ -55,6 +56,38 @@ pub enum InvalidRequestError {
     InvalidRequestHeader(http::header::ToStrError),
     /// Invalid prompt inputs: {0}
     InvalidPromptInputs(String),
+    /// Estimated {0} tokens exceeds every available context window
+    ContextLengthExceeded(u32),
+    /// Ambiguous request framing: {0}
+    AmbiguousFraming(&'static str),
+    /// Request headers exceed the {0}
+    HeadersTooLarge(&'static str),
+    /// Invalid priority: {0}
+    InvalidPriority(String),
+    /// Priority not allowed for this API key: {0}
+    PriorityNotAllowed(Priority),
+    /// Request rejected by filter: {0}
+    FilterRejected(String),
+    /// Endpoint {1} is not allowed by the {0} tier policy
+    TierEndpointNotAllowed(String, String),
+    /// Request exceeds the {0} tier policy: {1}
+    TierPolicyViolated(String, String),
+    /// Invalid {0} header
+    InvalidDeadline(&'static str),
+    /// Invalid fault: {0}
+    InvalidFault(String),
+    /// Excluded provider {0} is not a provider of this router
+    UnknownExcludedProvider(InferenceProvider),
+    /// Every provider of this router is excluded
+    AllProvidersExcluded,
+    /// The data residency policy of this router allows no provider for {0}
+    DataResidencyViolated(String),
+    /// Invalid helicone-max-cost-usd header: {0}
+    InvalidMaxCost(String),
+    /// The price of model {0} is unknown, so its cost can't be capped
+    CostCapUnknownPrice(String),
+    /// Estimated prompt cost of ${0} leaves no completion under the ${1} cap
+    CostCapExceeded(Decimal, Decimal),
 }
 
 impl IntoResponse for InvalidRequestError {
@@ -74,6 +107,48 @@ impl IntoResponse for InvalidRequestError {
                 }),
             )
                 .into_response(),
+            Self::PriorityNotAllowed(_)
+            | Self::FilterRejected(_)
+            | Self::DataResidencyViolated(_)
+            | Self::TierEndpointNotAllowed(..) => (
+                StatusCode::FORBIDDEN,
+                Json(ErrorResponse {
+                    error: ErrorDetails {
+                        message,
+                        r#type: Some(INVALID_REQUEST_ERROR_TYPE.to_string()),
+                        param: None,
+                        code: None,
+                    },
+                }),
+            )
+                .into_response(),
+            Self::HeadersTooLarge(_) => (
+                StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE,
+                Json(ErrorResponse {
+                    error: ErrorDetails {
+                        message,
+                        r#type: Some(INVALID_REQUEST_ERROR_TYPE.to_string()),
+                        param: None,
+                        code: None,
+                    },
+                }),
+            )
+                .into_response(),
+            Self::CostCapExceeded(..) => (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: ErrorDetails {
+                        message,
+                        r#type: Some(INVALID_REQUEST_ERROR_TYPE.to_string()),
+                        param: Some(
+                            crate::middleware::cost_cap::MAX_COST_HEADER
+                                .to_string(),
+                        ),
+                        code: Some("cost_cap_exceeded".to_string()),
+                    },
+                }),
+            )
+                .into_response(),
             Self::Provider4xxError(status) => (
                 status,
                 Json(ErrorResponse {
@@ -171,6 +246,22 @@ impl From<&InvalidRequestError> for InvalidRequestErrorMetric {
             | InvalidRequestError::UnsupportedEndpoint(_)
             | InvalidRequestError::InvalidCacheConfig
             | InvalidRequestError::InvalidPromptInputs(_)
+            | InvalidRequestError::ContextLengthExceeded(_)
+            | InvalidRequestError::AmbiguousFraming(_)
+            | InvalidRequestError::HeadersTooLarge(_)
+            | InvalidRequestError::InvalidPriority(_)
+            | InvalidRequestError::PriorityNotAllowed(_)
+            | InvalidRequestError::FilterRejected(_)
+            | InvalidRequestError::TierEndpointNotAllowed(..)
+            | InvalidRequestError::TierPolicyViolated(..)
+            | InvalidRequestError::InvalidDeadline(_)
+            | InvalidRequestError::InvalidFault(_)
+            | InvalidRequestError::UnknownExcludedProvider(_)
+            | InvalidRequestError::AllProvidersExcluded
+            | InvalidRequestError::DataResidencyViolated(_)
+            | InvalidRequestError::InvalidMaxCost(_)
+            | InvalidRequestError::CostCapUnknownPrice(_)
+            | InvalidRequestError::CostCapExceeded(..)
             | InvalidRequestError::MissingModelId
             | InvalidRequestError::InvalidModelId => Self::InvalidRequest,
             InvalidRequestError::InvalidUrl(_) => Self::InvalidUrl,
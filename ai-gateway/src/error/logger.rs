@@ -20,4 +20,12 @@ pub enum LoggerError {
     NoAuthContextSet,
     /// Unexpected response: {0}
     UnexpectedResponse(String),
+    /// Failed to spill body to disk: {0}
+    SpillFailed(std::io::Error),
+    /// Spilled body is not valid utf8
+    SpilledBodyNotUtf8,
+    /// Failed to compress log batch: {0}
+    CompressionFailed(std::io::Error),
+    /// Failed to store dead letter: {0}
+    DeadLetterFailed(std::io::Error),
 }
@@ -14,4 +14,6 @@ pub enum PromptError {
     FailedToGetProductionVersion(reqwest::Error),
     /// Unexpected response: {0}
     UnexpectedResponse(String),
+    /// Prompt body does not match its stored checksum
+    ChecksumMismatch,
 }
@@ -0,0 +1,274 @@
+//! Batching of logs sent to Helicone.
+//!
+//! Logs are batched per Helicone API key, since a request to Helicone is
+//! authorized by a single key. Each batch is sent as a gzip compressed JSON
+//! array once it's full, or by a timer started with its first log once it
+//! has waited `max-delay`.
+use std::{
+    io::Write,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use flate2::{Compression, write::GzEncoder};
+use http::header;
+use opentelemetry::{KeyValue, metrics::Counter};
+use reqwest::Client;
+use rustc_hash::FxHashMap as HashMap;
+use tokio::time::Instant;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    config::logger::LogBatchConfig, error::logger::LoggerError,
+    metrics::LoggerMetrics, types::logger::LogMessage, utils::task,
+};
+
+#[derive(Debug)]
+struct BatchedLog {
+    request_id: Uuid,
+    org_id: OrgId,
+    log: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Batch {
+    /// Tells a batch's timer apart from the timers of later batches for the
+    /// same key.
+    id: u64,
+    logs: Vec<BatchedLog>,
+    bytes: usize,
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: LogBatchConfig,
+    client: Client,
+    url: Url,
+    metrics: LoggerMetrics,
+    error_count: Counter<u64>,
+    dlq: Option<DeadLetterQueue>,
+    batches: Mutex<HashMap<String, Batch>>,
+    next_id: AtomicU64,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogBatcher {
+    inner: Arc<Inner>,
+}
+
+impl LogBatcher {
+    #[must_use]
+    pub fn new(
+        config: LogBatchConfig,
+        client: Client,
+        url: Url,
+        metrics: LoggerMetrics,
+        error_count: Counter<u64>,
+        dlq: Option<DeadLetterQueue>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                client,
+                url,
+                metrics,
+                error_count,
+                dlq,
+                batches: Mutex::default(),
+                next_id: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Adds `message` to the batch of its API key, sending the batch if
+    /// it's full.
+    pub async fn add(
+        &self,
+        auth_ctx: &AuthContext,
+        request_id: Uuid,
+        message: &LogMessage,
+    ) -> Result<(), LoggerError> {
+        let api_key = auth_ctx.api_key.expose().as_str();
+        let log = serde_json::to_vec(message).map_err(|e| {
+            tracing::error!(error = %e, "failed to serialize log message");
+            LoggerError::InvalidLogMessage
+        })?;
+        let config = &self.inner.config;
+        let full = {
+            let mut batches =
+                self.inner.batches.lock().expect("log batches poisoned");
+            let batch =
+                batches.entry(api_key.to_string()).or_insert_with(|| {
+                    let id = self.inner.next_id.fetch_add(1, Ordering::Relaxed);
+                    task::spawn(
+                        "log-batch-flush",
+                        self.clone().flush_later(api_key.to_string(), id),
+                    );
+                    Batch {
+                        id,
+                        logs: Vec::new(),
+                        bytes: 0,
+                    }
+                });
+            batch.bytes += log.len();
+            batch.logs.push(BatchedLog {
+                request_id,
+                org_id: auth_ctx.org_id,
+                log,
+            });
+            if batch.logs.len() >= config.max_logs
+                || batch.bytes >= config.max_bytes
+            {
+                batches.remove(api_key)
+            } else {
+                None
+            }
+        };
+        match full {
+            Some(batch) => self.send(api_key, batch).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the batch `id` of `api_key` after `max-delay`, unless it was
+    /// sent for being full by then.
+    async fn flush_later(self, api_key: String, id: u64) {
+        tokio::time::sleep(self.inner.config.max_delay).await;
+        let batch = {
+            let mut batches =
+                self.inner.batches.lock().expect("log batches poisoned");
+            if batches.get(&api_key).is_none_or(|batch| batch.id != id) {
+                return;
+            }
+            batches.remove(&api_key)
+        };
+        if let Some(batch) = batch
+            && let Err(e) = self.send(&api_key, batch).await
+        {
+            let error_str = e.as_ref().to_string();
+            self.inner
+                .error_count
+                .add(1, &[KeyValue::new("type", error_str)]);
+        }
+    }
+
+    async fn send(
+        &self,
+        api_key: &str,
+        batch: Batch,
+    ) -> Result<(), LoggerError> {
+        let body = encode(batch.logs.iter().map(|log| log.log.as_slice()))?;
+        let metrics = &self.inner.metrics;
+        metrics.batch_size.record(batch.logs.len() as u64, &[]);
+        let start = Instant::now();
+        let response = self
+            .inner
+            .client
+            .post(self.inner.url.clone())
+            .header(header::CONTENT_TYPE, "application/json")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .header(header::AUTHORIZATION, format!("Bearer {api_key}"))
+            .body(body)
+            .send()
+            .await;
+        metrics.delivery_latency.record(
+            start.elapsed().as_secs_f64() * 1000.0,
+            &[KeyValue::new("batched", true)],
+        );
+        let result = response
+            .map_err(|e| {
+                tracing::debug!(error = %e, "failed to send log batch to helicone");
+                LoggerError::FailedToSendRequest(e)
+            })
+            .and_then(|response| {
+                response.error_for_status().map_err(|e| {
+                    tracing::error!(error = %e, "failed to log batch to helicone");
+                    LoggerError::ResponseError(e)
+                })
+            });
+        if let Err(e) = result {
+            self.dead_letter(api_key, batch, &e).await;
+            return Err(e);
+        }
+        tracing::debug!(logs = batch.logs.len(), "successfully logged batch");
+        Ok(())
+    }
+
+    /// Stores the logs of a batch which failed to be sent in the dead-letter
+    /// queue, if configured.
+    async fn dead_letter(
+        &self,
+        api_key: &str,
+        batch: Batch,
+        error: &LoggerError,
+    ) {
+        let Some(dlq) = &self.inner.dlq else {
+            return;
+        };
+        for log in batch.logs {
+            let Ok(message) = serde_json::from_slice(&log.log) else {
+                continue;
+            };
+            let failed = FailedDelivery {
+                request_id: log.request_id,
+                org_id: log.org_id,
+                api_key,
+                message,
+                payload: None,
+            };
+            dlq.push(failed, error).await;
+        }
+    }
+}
+
+/// The gzip compressed JSON array of the serialized `logs`.
+fn encode<'a>(
+    logs: impl IntoIterator<Item = &'a [u8]>,
+) -> Result<Vec<u8>, LoggerError> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::fast());
+    encoder
+        .write_all(b"[")
+        .map_err(LoggerError::CompressionFailed)?;
+    for (i, log) in logs.into_iter().enumerate() {
+        if i > 0 {
+            encoder
+                .write_all(b",")
+                .map_err(LoggerError::CompressionFailed)?;
+        }
+        encoder
+            .write_all(log)
+            .map_err(LoggerError::CompressionFailed)?;
+    }
+    encoder
+        .write_all(b"]")
+        .map_err(LoggerError::CompressionFailed)?;
+    encoder.finish().map_err(LoggerError::CompressionFailed)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+
+    use super::*;
+
+    #[test]
+    fn batch_is_a_compressed_json_array() {
+        let logs = vec![
+            serde_json::to_vec(&serde_json::json!({"id": 1})).unwrap(),
+            serde_json::to_vec(&serde_json::json!({"id": 2})).unwrap(),
+        ];
+        let mut decoded = String::new();
+        let encoded = encode(logs.iter().map(Vec::as_slice)).unwrap();
+        GzDecoder::new(encoded.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        let decoded: serde_json::Value =
+            serde_json::from_str(&decoded).unwrap();
+        assert_eq!(decoded, serde_json::json!([{"id": 1}, {"id": 2}]));
+    }
+}
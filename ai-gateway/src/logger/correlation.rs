@@ -0,0 +1,127 @@
+//! Correlation of the IDs a request is known by in different systems.
+//!
+//! A request has a gateway request ID, the `x-request-id` header, which is
+//! the client's if it sent one and the trace ID otherwise, the Helicone
+//! request ID of its log, and the ID the provider assigned it. The IDs of
+//! recent requests are kept in memory, so that support engineers can find
+//! the others from any one of them with `GET /admin/v1/requests/{id}`.
+use std::{collections::VecDeque, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use opentelemetry::trace::TraceContextExt;
+use serde::{Deserialize, Serialize};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use uuid::Uuid;
+
+use crate::types::provider::InferenceProvider;
+
+/// The number of requests whose IDs are kept.
+const CAPACITY: usize = 10_000;
+
+/// The IDs of a request.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Correlation {
+    pub helicone_id: Uuid,
+    pub request_id: Option<String>,
+    pub provider_request_id: Option<String>,
+    pub trace_id: Option<String>,
+    pub provider: InferenceProvider,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Correlation {
+    /// The IDs logged as properties of the request, besides the Helicone
+    /// request ID which is the log's.
+    pub fn properties(&self) -> impl Iterator<Item = (String, String)> {
+        [
+            ("gateway-request-id", &self.request_id),
+            ("provider-request-id", &self.provider_request_id),
+            ("trace-id", &self.trace_id),
+        ]
+        .into_iter()
+        .filter_map(|(name, id)| Some((name.to_string(), id.clone()?)))
+    }
+
+    fn matches(&self, id: &str) -> bool {
+        self.helicone_id.to_string() == id
+            || self.request_id.as_deref() == Some(id)
+            || self.provider_request_id.as_deref() == Some(id)
+            || self.trace_id.as_deref() == Some(id)
+    }
+}
+
+/// Returns the trace ID of the current span, if it's being traced.
+#[must_use]
+pub fn current_trace_id() -> Option<String> {
+    let context = tracing::Span::current().context();
+    let span = context.span();
+    let span_context = span.span_context();
+    span_context
+        .is_valid()
+        .then(|| span_context.trace_id().to_string())
+}
+
+/// The IDs of the most recent requests.
+#[derive(Debug, Default)]
+pub struct Correlations {
+    entries: Mutex<VecDeque<Correlation>>,
+}
+
+impl Correlations {
+    pub fn record(&self, correlation: Correlation) {
+        let mut entries =
+            self.entries.lock().expect("correlations lock poisoned");
+        if entries.len() >= CAPACITY {
+            entries.pop_front();
+        }
+        entries.push_back(correlation);
+    }
+
+    /// Returns the requests known by `id`, most recent first. A gateway
+    /// request ID set by the client may be shared by several requests.
+    #[must_use]
+    pub fn lookup(&self, id: &str) -> Vec<Correlation> {
+        self.entries
+            .lock()
+            .expect("correlations lock poisoned")
+            .iter()
+            .rev()
+            .filter(|correlation| correlation.matches(id))
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn correlation(request_id: &str, provider_request_id: &str) -> Correlation {
+        Correlation {
+            helicone_id: Uuid::new_v4(),
+            request_id: Some(request_id.to_string()),
+            provider_request_id: Some(provider_request_id.to_string()),
+            trace_id: None,
+            provider: InferenceProvider::OpenAI,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn lookup_by_any_id() {
+        let correlations = Correlations::default();
+        let first = correlation("client-1", "req_abc");
+        let second = correlation("client-1", "req_def");
+        correlations.record(first.clone());
+        correlations.record(second.clone());
+
+        assert_eq!(correlations.lookup("req_abc"), vec![first.clone()]);
+        assert_eq!(
+            correlations.lookup(&second.helicone_id.to_string()),
+            vec![second.clone()]
+        );
+        assert_eq!(correlations.lookup("client-1"), vec![second, first]);
+        assert!(correlations.lookup("unknown").is_empty());
+    }
+}
@@ -0,0 +1,582 @@
+//! Dead-letter queue of logs which failed to be delivered.
+//!
+//! Each entry is a JSON file in the configured directory, next to the
+//! payload of its bodies if they weren't stored yet. Entries are retried
+//! with exponential backoff by the [`DlqRetrier`] service, and are removed
+//! once they're delivered, so logs survive Helicone or object storage
+//! outages as well as restarts of the gateway.
+//!
+//! Entries hold the API key their log is sent with, so the directory and the
+//! entries are only accessible by the gateway's user.
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use meltdown::Token;
+use opentelemetry::KeyValue;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{Mutex, Notify},
+};
+use tracing::info;
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    config::logger::DlqConfig,
+    error::{init::InitError, logger::LoggerError, runtime::RuntimeError},
+    metrics::LoggerMetrics,
+    store::minio::{MinioClient, Payload},
+    types::{extensions::AuthContext, logger::LogMessage, org::OrgId},
+};
+
+const ENTRY_EXTENSION: &str = "json";
+const PAYLOAD_EXTENSION: &str = "payload";
+/// How often entries are checked for being due for a retry.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A log which failed to be delivered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetter {
+    pub id: Uuid,
+    pub request_id: Uuid,
+    pub org_id: OrgId,
+    pub failed_at: DateTime<Utc>,
+    pub attempts: u32,
+    pub next_attempt_at: DateTime<Utc>,
+    pub last_error: String,
+    /// Whether the bodies weren't stored yet, in which case their payload
+    /// is kept next to the entry.
+    pub has_payload: bool,
+}
+
+/// A dead letter as stored on disk, along with what's needed to deliver it.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    #[serde(flatten)]
+    dead_letter: DeadLetter,
+    api_key: String,
+    message: serde_json::Value,
+}
+
+/// A log which failed to be delivered.
+#[derive(Debug)]
+pub struct FailedDelivery<'a> {
+    pub request_id: Uuid,
+    pub org_id: OrgId,
+    pub api_key: &'a str,
+    pub message: serde_json::Value,
+    /// The bodies, if they weren't stored.
+    pub payload: Option<&'a Payload>,
+}
+
+impl<'a> FailedDelivery<'a> {
+    /// Returns `None` if the log message can't be serialized.
+    #[must_use]
+    pub fn new(
+        auth_ctx: &'a AuthContext,
+        request_id: Uuid,
+        log_message: &LogMessage,
+        payload: Option<&'a Payload>,
+    ) -> Option<Self> {
+        let message = serde_json::to_value(log_message)
+            .inspect_err(|e| {
+                tracing::error!(error = %e, "failed to serialize log message");
+            })
+            .ok()?;
+        Some(Self {
+            request_id,
+            org_id: auth_ctx.org_id,
+            api_key: auth_ctx.api_key.expose(),
+            message,
+            payload,
+        })
+    }
+}
+
+#[derive(Debug)]
+struct Inner {
+    config: DlqConfig,
+    metrics: LoggerMetrics,
+    /// Serializes changes to the entries on disk.
+    lock: Mutex<()>,
+    len: AtomicUsize,
+    /// Notified when entries are scheduled for an immediate retry.
+    replay: Notify,
+}
+
+#[derive(Debug, Clone)]
+pub struct DeadLetterQueue {
+    inner: Arc<Inner>,
+}
+
+impl DeadLetterQueue {
+    /// Opens the queue in the configured directory, creating it if needed.
+    pub fn open(
+        config: DlqConfig,
+        metrics: LoggerMetrics,
+    ) -> Result<Self, InitError> {
+        std::fs::create_dir_all(&config.dir).map_err(InitError::OpenDlq)?;
+        restrict_dir(&config.dir).map_err(InitError::OpenDlq)?;
+        let len = std::fs::read_dir(&config.dir)
+            .map_err(InitError::OpenDlq)?
+            .filter_map(Result::ok)
+            .filter(|entry| is_entry(&entry.path()))
+            .count();
+        if len > 0 {
+            info!(entries = len, "found logs in dead-letter queue");
+        }
+        metrics
+            .dead_letters
+            .add(i64::try_from(len).unwrap_or(i64::MAX), &[]);
+        Ok(Self {
+            inner: Arc::new(Inner {
+                config,
+                metrics,
+                lock: Mutex::new(()),
+                len: AtomicUsize::new(len),
+                replay: Notify::new(),
+            }),
+        })
+    }
+
+    /// Stores `failed` for a retry, dropping it if the queue is full.
+    pub async fn push(&self, failed: FailedDelivery<'_>, error: &LoggerError) {
+        let inner = &self.inner;
+        let _guard = inner.lock.lock().await;
+        if inner.len.load(Ordering::Relaxed) >= inner.config.max_entries {
+            tracing::warn!("dead-letter queue full, dropping log");
+            inner
+                .metrics
+                .dropped_logs
+                .add(1, &[KeyValue::new("policy", "dlq-full")]);
+            return;
+        }
+        let now = Utc::now();
+        let entry = Entry {
+            dead_letter: DeadLetter {
+                id: Uuid::new_v4(),
+                request_id: failed.request_id,
+                org_id: failed.org_id,
+                failed_at: now,
+                attempts: 0,
+                next_attempt_at: now + self.backoff(1),
+                last_error: error.to_string(),
+                has_payload: failed.payload.is_some(),
+            },
+            api_key: failed.api_key.to_string(),
+            message: failed.message,
+        };
+        let id = entry.dead_letter.id;
+        if let Some(payload) = failed.payload
+            && let Err(e) = payload.persist(&self.payload_path(id)).await
+        {
+            tracing::error!(error = %e, "failed to store dead letter payload");
+            return;
+        }
+        if let Err(e) = self.write(&entry).await {
+            tracing::error!(error = %e, "failed to store dead letter");
+            self.remove(id).await;
+            return;
+        }
+        inner.len.fetch_add(1, Ordering::Relaxed);
+        inner.metrics.dead_letters.add(1, &[]);
+        tracing::debug!(id = %id, "stored dead letter");
+    }
+
+    /// The dead letters, oldest first.
+    pub async fn entries(&self) -> Result<Vec<DeadLetter>, LoggerError> {
+        let _guard = self.inner.lock.lock().await;
+        let mut entries = self
+            .read_entries()
+            .await?
+            .into_iter()
+            .map(|entry| entry.dead_letter)
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.failed_at);
+        Ok(entries)
+    }
+
+    /// Schedules the entry `id`, or all entries if `None`, for an immediate
+    /// retry. Returns the number of scheduled entries.
+    pub async fn replay(&self, id: Option<Uuid>) -> Result<usize, LoggerError> {
+        let scheduled = {
+            let _guard = self.inner.lock.lock().await;
+            let now = Utc::now();
+            let mut scheduled = 0;
+            for mut entry in self.read_entries().await? {
+                if id.is_some_and(|id| id != entry.dead_letter.id) {
+                    continue;
+                }
+                entry.dead_letter.next_attempt_at = now;
+                self.write(&entry).await?;
+                scheduled += 1;
+            }
+            scheduled
+        };
+        if scheduled > 0 {
+            self.inner.replay.notify_one();
+        }
+        Ok(scheduled)
+    }
+
+    /// Retries the entries which are due. Entries are only locked while
+    /// they're read and updated, so that failing deliveries aren't held up
+    /// by the retries.
+    async fn retry_due(&self, app_state: &AppState) -> Result<(), LoggerError> {
+        let entries = {
+            let _guard = self.inner.lock.lock().await;
+            self.read_entries().await?
+        };
+        let now = Utc::now();
+        for mut entry in entries {
+            if entry.dead_letter.next_attempt_at > now {
+                continue;
+            }
+            let id = entry.dead_letter.id;
+            let result = self.retry(app_state, &mut entry).await;
+            self.inner
+                .metrics
+                .dead_letter_retries
+                .add(1, &[KeyValue::new("success", result.is_ok())]);
+            let _guard = self.inner.lock.lock().await;
+            // the entry may have been removed or replayed while it was
+            // retried without the lock
+            let Some(stored) = self.read_entry(id).await? else {
+                continue;
+            };
+            match result {
+                Ok(()) => {
+                    tracing::debug!(id = %id, "delivered dead letter");
+                    self.remove(id).await;
+                    self.inner.len.fetch_sub(1, Ordering::Relaxed);
+                    self.inner.metrics.dead_letters.add(-1, &[]);
+                }
+                Err(e) => {
+                    tracing::debug!(id = %id, error = %e, "dead letter retry failed");
+                    let replayed = stored.dead_letter.next_attempt_at
+                        != entry.dead_letter.next_attempt_at;
+                    let dead_letter = &mut entry.dead_letter;
+                    dead_letter.attempts += 1;
+                    dead_letter.last_error = e.to_string();
+                    dead_letter.next_attempt_at = if replayed {
+                        stored.dead_letter.next_attempt_at
+                    } else {
+                        Utc::now() + self.backoff(dead_letter.attempts + 1)
+                    };
+                    self.write(&entry).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn retry(
+        &self,
+        app_state: &AppState,
+        entry: &mut Entry,
+    ) -> Result<(), LoggerError> {
+        let dead_letter = &entry.dead_letter;
+        if dead_letter.has_payload {
+            let payload_path = self.payload_path(dead_letter.id);
+            let payload = tokio::fs::read(&payload_path)
+                .await
+                .map_err(LoggerError::DeadLetterFailed)?;
+            MinioClient::new(app_state)
+                .upload(
+                    app_state,
+                    &dead_letter.org_id,
+                    &entry.api_key,
+                    dead_letter.request_id,
+                    &Payload::from_bytes(payload.into()),
+                )
+                .await?;
+            // the bodies aren't uploaded again if sending the log fails
+            entry.dead_letter.has_payload = false;
+            {
+                let _guard = self.inner.lock.lock().await;
+                if let Some(mut stored) =
+                    self.read_entry(entry.dead_letter.id).await?
+                {
+                    stored.dead_letter.has_payload = false;
+                    self.write(&stored).await?;
+                }
+                remove_file(&payload_path).await;
+            }
+        }
+        let url = app_state
+            .config()
+            .helicone
+            .base_url
+            .join("/v1/log/request")?;
+        app_state
+            .0
+            .jawn_http_client
+            .send_log(url, &entry.api_key, &entry.message)
+            .await
+    }
+
+    /// The delay before the `attempt`th retry.
+    fn backoff(&self, attempt: u32) -> chrono::Duration {
+        let config = &self.inner.config;
+        let backoff = config
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(config.max_backoff);
+        chrono::Duration::from_std(backoff).unwrap_or(chrono::Duration::MAX)
+    }
+
+    async fn read_entries(&self) -> Result<Vec<Entry>, LoggerError> {
+        let mut dir = tokio::fs::read_dir(&self.inner.config.dir)
+            .await
+            .map_err(LoggerError::DeadLetterFailed)?;
+        let mut entries = Vec::new();
+        while let Some(file) = dir
+            .next_entry()
+            .await
+            .map_err(LoggerError::DeadLetterFailed)?
+        {
+            let path = file.path();
+            if !is_entry(&path) {
+                continue;
+            }
+            let contents = tokio::fs::read(&path)
+                .await
+                .map_err(LoggerError::DeadLetterFailed)?;
+            match serde_json::from_slice::<Entry>(&contents) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => {
+                    tracing::warn!(
+                        error = %e,
+                        path = %path.display(),
+                        "skipping invalid dead letter"
+                    );
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Reads the entry `id`, if it's still queued.
+    async fn read_entry(&self, id: Uuid) -> Result<Option<Entry>, LoggerError> {
+        let contents = match tokio::fs::read(self.entry_path(id)).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(None);
+            }
+            Err(e) => return Err(LoggerError::DeadLetterFailed(e)),
+        };
+        Ok(serde_json::from_slice::<Entry>(&contents).ok())
+    }
+
+    /// Writes `entry` to a temporary file first, so that a crash can't
+    /// leave a partially written entry behind.
+    async fn write(&self, entry: &Entry) -> Result<(), LoggerError> {
+        let contents = serde_json::to_vec(entry).map_err(|e| {
+            tracing::error!(error = %e, "failed to serialize dead letter");
+            LoggerError::InvalidLogMessage
+        })?;
+        let path = self.entry_path(entry.dead_letter.id);
+        let tmp_path = path.with_extension("tmp");
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options
+            .open(&tmp_path)
+            .await
+            .map_err(LoggerError::DeadLetterFailed)?;
+        file.write_all(&contents)
+            .await
+            .map_err(LoggerError::DeadLetterFailed)?;
+        file.flush().await.map_err(LoggerError::DeadLetterFailed)?;
+        tokio::fs::rename(&tmp_path, &path)
+            .await
+            .map_err(LoggerError::DeadLetterFailed)
+    }
+
+    async fn remove(&self, id: Uuid) {
+        remove_file(&self.entry_path(id)).await;
+        remove_file(&self.payload_path(id)).await;
+    }
+
+    fn entry_path(&self, id: Uuid) -> PathBuf {
+        self.inner
+            .config
+            .dir
+            .join(format!("{id}.{ENTRY_EXTENSION}"))
+    }
+
+    fn payload_path(&self, id: Uuid) -> PathBuf {
+        self.inner
+            .config
+            .dir
+            .join(format!("{id}.{PAYLOAD_EXTENSION}"))
+    }
+}
+
+#[cfg(unix)]
+fn restrict_dir(dir: &Path) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    std::fs::set_permissions(dir, std::fs::Permissions::from_mode(0o700))
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+fn restrict_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn is_entry(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == ENTRY_EXTENSION)
+}
+
+async fn remove_file(path: &Path) {
+    match tokio::fs::remove_file(path).await {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            tracing::warn!(
+                error = %e,
+                path = %path.display(),
+                "failed to remove dead letter file"
+            );
+        }
+    }
+}
+
+/// Retries the deliveries of the logs in the dead-letter queue.
+#[derive(Debug)]
+pub struct DlqRetrier {
+    app_state: AppState,
+    dlq: DeadLetterQueue,
+}
+
+impl DlqRetrier {
+    #[must_use]
+    pub fn new(app_state: AppState, dlq: DeadLetterQueue) -> Self {
+        Self { app_state, dlq }
+    }
+}
+
+impl meltdown::Service for DlqRetrier {
+    type Future = BoxFuture<'static, Result<(), RuntimeError>>;
+
+    fn run(self, mut token: Token) -> Self::Future {
+        Box::pin(async move {
+            let mut interval = tokio::time::interval(POLL_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    () = self.dlq.inner.replay.notified() => {}
+                    () = &mut token => {
+                        info!(name = "dlq-retrier", "task shutting down");
+                        break;
+                    }
+                }
+                if let Err(e) = self.dlq.retry_due(&self.app_state).await {
+                    tracing::error!(error = %e, "failed to retry dead letters");
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dlq() -> DeadLetterQueue {
+        let config = DlqConfig {
+            dir: std::env::temp_dir()
+                .join(format!("ai-gateway-dlq-{}", Uuid::new_v4())),
+            ..Default::default()
+        };
+        let meter = opentelemetry::global::meter("test");
+        DeadLetterQueue::open(config, LoggerMetrics::new(&meter)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn stores_and_replays_dead_letters() {
+        let dlq = dlq();
+        let request_id = Uuid::new_v4();
+        let payload = Payload::from_bytes("{}".into());
+        dlq.push(
+            FailedDelivery {
+                request_id,
+                org_id: OrgId::default(),
+                api_key: "sk-helicone-test",
+                message: serde_json::json!({"log": {}}),
+                payload: Some(&payload),
+            },
+            &LoggerError::InvalidLogMessage,
+        )
+        .await;
+
+        let entries = dlq.entries().await.unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].request_id, request_id);
+        assert!(entries[0].has_payload);
+        assert!(entries[0].next_attempt_at > entries[0].failed_at);
+        assert!(dlq.payload_path(entries[0].id).exists());
+
+        assert_eq!(dlq.replay(Some(Uuid::new_v4())).await.unwrap(), 0);
+        assert_eq!(dlq.replay(Some(entries[0].id)).await.unwrap(), 1);
+        let entries = dlq.entries().await.unwrap();
+        assert!(entries[0].next_attempt_at <= Utc::now());
+
+        std::fs::remove_dir_all(&dlq.inner.config.dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn dead_letters_are_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dlq = dlq();
+        dlq.push(
+            FailedDelivery {
+                request_id: Uuid::new_v4(),
+                org_id: OrgId::default(),
+                api_key: "sk-helicone-test",
+                message: serde_json::json!({"log": {}}),
+                payload: None,
+            },
+            &LoggerError::InvalidLogMessage,
+        )
+        .await;
+        let id = dlq.entries().await.unwrap()[0].id;
+        let mode = |path: &Path| {
+            std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+        };
+        assert_eq!(mode(&dlq.inner.config.dir), 0o700);
+        assert_eq!(mode(&dlq.entry_path(id)), 0o600);
+
+        // retries in flight re-read their entry before updating it
+        dlq.remove(id).await;
+        assert!(dlq.read_entry(id).await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&dlq.inner.config.dir).unwrap();
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        let dlq = dlq();
+        assert_eq!(dlq.backoff(1), chrono::Duration::seconds(30));
+        assert_eq!(dlq.backoff(2), chrono::Duration::seconds(60));
+        assert_eq!(dlq.backoff(30), chrono::Duration::hours(1));
+        std::fs::remove_dir_all(&dlq.inner.config.dir).unwrap();
+    }
+}
@@ -0,0 +1,56 @@
+//! Request completion events, for exporting request logs to an
+//! OpenTelemetry backend instead of, or along with, Helicone.
+//!
+//! Events are emitted with `tracing` in the span of their request, so the
+//! telemetry pipeline exports them as log records correlated with the
+//! request's trace.
+use std::time::Duration;
+
+use http::StatusCode;
+use typed_builder::TypedBuilder;
+use uuid::Uuid;
+
+use crate::types::{
+    model_id::ModelId, provider::InferenceProvider, router::RouterId,
+};
+
+/// Target of the events, for filtering them with `RUST_LOG` directives.
+pub const REQUEST_EVENT_TARGET: &str = "ai_gateway::request_event";
+
+/// The completion of a request, once its response body has been sent in
+/// full.
+#[derive(Debug, TypedBuilder)]
+pub struct RequestEvent<'a> {
+    request_id: Uuid,
+    provider: &'a InferenceProvider,
+    model: Option<&'a ModelId>,
+    path: &'a str,
+    status: StatusCode,
+    is_stream: bool,
+    request_body_size: u64,
+    response_body_size: u64,
+    tfft: Duration,
+    duration: Duration,
+    router_id: Option<&'a RouterId>,
+}
+
+impl RequestEvent<'_> {
+    pub fn emit(&self) {
+        tracing::info!(
+            target: REQUEST_EVENT_TARGET,
+            request_id = %self.request_id,
+            provider = %self.provider,
+            model = self.model.map(tracing::field::display),
+            path = self.path,
+            status = self.status.as_u16(),
+            is_stream = self.is_stream,
+            request_body_size = self.request_body_size,
+            response_body_size = self.response_body_size,
+            tfft_ms = u64::try_from(self.tfft.as_millis()).unwrap_or(u64::MAX),
+            duration_ms =
+                u64::try_from(self.duration.as_millis()).unwrap_or(u64::MAX),
+            router_id = self.router_id.map(tracing::field::display),
+            "request completed"
+        );
+    }
+}
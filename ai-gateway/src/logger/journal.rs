@@ -0,0 +1,217 @@
+//! Journal of requests in flight.
+//!
+//! An entry is written to the configured directory when a request is
+//! dispatched and removed once its response body has been read in full, or
+//! once it fails. Entries left behind by a crash are reconciled when the
+//! gateway next starts: each is emitted as an event with an unknown outcome
+//! and counted, so the requests don't vanish from observability data.
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use opentelemetry::metrics::Counter;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    config::logger::JournalConfig,
+    error::init::InitError,
+    logger::event::REQUEST_EVENT_TARGET,
+    types::{model_id::ModelId, provider::InferenceProvider, router::RouterId},
+};
+
+const ENTRY_EXTENSION: &str = "json";
+
+/// A request in flight.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JournalEntry {
+    pub request_id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub provider: InferenceProvider,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub router_id: Option<String>,
+}
+
+impl JournalEntry {
+    #[must_use]
+    pub fn new(
+        request_id: Uuid,
+        started_at: DateTime<Utc>,
+        provider: &InferenceProvider,
+        model: Option<&ModelId>,
+        router_id: Option<&RouterId>,
+    ) -> Self {
+        Self {
+            request_id,
+            started_at,
+            provider: provider.clone(),
+            model: model.map(ToString::to_string),
+            router_id: router_id.map(ToString::to_string),
+        }
+    }
+
+    fn emit_unknown_outcome(&self) {
+        tracing::warn!(
+            target: REQUEST_EVENT_TARGET,
+            request_id = %self.request_id,
+            started_at = %self.started_at,
+            provider = %self.provider,
+            model = self.model.as_deref(),
+            router_id = self.router_id.as_deref(),
+            outcome = "unknown",
+            "request in flight during a crash"
+        );
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestJournal {
+    dir: Arc<Path>,
+}
+
+impl RequestJournal {
+    /// Opens the journal in the configured directory, creating it if
+    /// needed, and reconciles the entries left behind by a crash.
+    pub fn open(
+        config: &JournalConfig,
+        unknown_outcomes: &Counter<u64>,
+    ) -> Result<Self, InitError> {
+        std::fs::create_dir_all(&config.dir).map_err(InitError::OpenJournal)?;
+        let journal = Self {
+            dir: Arc::from(config.dir.as_path()),
+        };
+        let reconciled = journal.reconcile()?;
+        if reconciled > 0 {
+            tracing::warn!(
+                requests = reconciled,
+                "found requests in flight during a crash"
+            );
+            unknown_outcomes.add(reconciled, &[]);
+        }
+        Ok(journal)
+    }
+
+    /// Journals `entry` until the returned guard is dropped.
+    pub async fn start(&self, entry: &JournalEntry) -> Option<JournalGuard> {
+        let path = self.entry_path(entry.request_id);
+        let contents = match serde_json::to_vec(entry) {
+            Ok(contents) => contents,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to encode journal entry");
+                return None;
+            }
+        };
+        if let Err(e) = tokio::fs::write(&path, contents).await {
+            tracing::error!(error = %e, "failed to write journal entry");
+            return None;
+        }
+        Some(JournalGuard { path })
+    }
+
+    /// Emits and removes every entry, returning their number.
+    fn reconcile(&self) -> Result<u64, InitError> {
+        let mut reconciled = 0;
+        for file in std::fs::read_dir(&self.dir)
+            .map_err(InitError::OpenJournal)?
+            .filter_map(Result::ok)
+        {
+            let path = file.path();
+            if path.extension().is_none_or(|ext| ext != ENTRY_EXTENSION) {
+                continue;
+            }
+            // entries are small enough to never be partially written, but
+            // the process may have crashed while creating the file
+            match std::fs::read(&path).ok().and_then(|contents| {
+                serde_json::from_slice::<JournalEntry>(&contents).ok()
+            }) {
+                Some(entry) => {
+                    entry.emit_unknown_outcome();
+                    reconciled += 1;
+                }
+                None => {
+                    tracing::warn!(
+                        path = %path.display(),
+                        "skipping invalid journal entry"
+                    );
+                }
+            }
+            remove_file(&path);
+        }
+        Ok(reconciled)
+    }
+
+    fn entry_path(&self, request_id: Uuid) -> PathBuf {
+        self.dir.join(format!("{request_id}.{ENTRY_EXTENSION}"))
+    }
+}
+
+/// Removes its journal entry when dropped.
+#[derive(Debug)]
+pub struct JournalGuard {
+    path: PathBuf,
+}
+
+impl Drop for JournalGuard {
+    fn drop(&mut self) {
+        remove_file(&self.path);
+    }
+}
+
+fn remove_file(path: &Path) {
+    match std::fs::remove_file(path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            tracing::warn!(
+                error = %e,
+                path = %path.display(),
+                "failed to remove journal entry"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn entries_left_behind_are_reconciled() {
+        let dir =
+            std::env::temp_dir().join(format!("journal-{}", Uuid::new_v4()));
+        let config = JournalConfig { dir: dir.clone() };
+        let counter = opentelemetry::global::meter("test")
+            .u64_counter("test")
+            .build();
+        let journal = RequestJournal::open(&config, &counter).unwrap();
+
+        let entry = JournalEntry::new(
+            Uuid::new_v4(),
+            Utc::now(),
+            &InferenceProvider::OpenAI,
+            None,
+            None,
+        );
+        let guard = journal.start(&entry).await.unwrap();
+        let other = JournalEntry {
+            request_id: Uuid::new_v4(),
+            ..entry.clone()
+        };
+        let crashed = journal.start(&other).await.unwrap();
+        drop(guard);
+        // a crash doesn't run destructors
+        std::mem::forget(crashed);
+        assert!(!journal.entry_path(entry.request_id).exists());
+        assert!(journal.entry_path(other.request_id).exists());
+
+        assert_eq!(journal.reconcile().unwrap(), 1);
+        assert!(!journal.entry_path(other.request_id).exists());
+        assert_eq!(journal.reconcile().unwrap(), 0);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1 +1,10 @@
+pub mod batch;
+pub mod correlation;
+pub mod dlq;
+pub mod event;
+pub mod journal;
+pub mod properties;
+pub mod queue;
 pub mod service;
+pub mod spill;
+pub mod usage;
@@ -0,0 +1,116 @@
+//! Custom properties attached to request logs.
+//!
+//! Properties are set with `helicone-property-{name}` request headers or with
+//! the `metadata` object of the request body, and are logged under `{name}`
+//! and the metadata key respectively. When both set the same property, the
+//! header wins.
+use bytes::Bytes;
+use http::HeaderMap;
+use indexmap::IndexMap;
+use serde_json::Value;
+
+pub const PROPERTY_HEADER_PREFIX: &str = "helicone-property-";
+
+/// The `metadata` of a request body before it was converted for the target
+/// provider, which may not support it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequestMetadata(pub IndexMap<String, String>);
+
+impl RequestMetadata {
+    /// Reads the scalar fields of the `metadata` object of `body`.
+    #[must_use]
+    pub fn from_body(body: &Bytes) -> Self {
+        // most requests have no metadata, so avoid parsing their body
+        if !body.windows(10).any(|window| window == b"\"metadata\"") {
+            return Self::default();
+        }
+        let Ok(Value::Object(mut body)) = serde_json::from_slice(body) else {
+            return Self::default();
+        };
+        let Some(Value::Object(metadata)) = body.remove("metadata") else {
+            return Self::default();
+        };
+        let metadata = metadata
+            .into_iter()
+            .filter_map(|(key, value)| {
+                let value = match value {
+                    Value::String(value) => value,
+                    Value::Number(value) => value.to_string(),
+                    Value::Bool(value) => value.to_string(),
+                    Value::Null | Value::Array(_) | Value::Object(_) => {
+                        return None;
+                    }
+                };
+                Some((key, value))
+            })
+            .collect();
+        Self(metadata)
+    }
+}
+
+/// Merges the properties set by the `helicone-property-*` headers into
+/// `metadata`.
+#[must_use]
+pub fn merge(
+    headers: &HeaderMap,
+    metadata: RequestMetadata,
+) -> IndexMap<String, String> {
+    let mut properties = metadata.0;
+    for (name, value) in headers {
+        let Some(property) = name.as_str().strip_prefix(PROPERTY_HEADER_PREFIX)
+        else {
+            continue;
+        };
+        if let Ok(value) = value.to_str() {
+            properties.insert(property.to_string(), value.to_string());
+        }
+    }
+    properties
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn headers_and_metadata_are_merged() {
+        let body = Bytes::from_static(
+            br#"{"model": "gpt-4o", "metadata": {
+                "feature": "search", "experiment": "a", "cohort": 3,
+                "beta": true, "tags": ["x"]
+            }}"#,
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "helicone-property-experiment",
+            HeaderValue::from_static("b"),
+        );
+        headers.insert("helicone-user-id", HeaderValue::from_static("u"));
+        let properties = merge(&headers, RequestMetadata::from_body(&body));
+        assert_eq!(
+            properties.into_iter().collect::<Vec<_>>(),
+            vec![
+                ("feature".to_string(), "search".to_string()),
+                ("experiment".to_string(), "b".to_string()),
+                ("cohort".to_string(), "3".to_string()),
+                ("beta".to_string(), "true".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn requests_without_metadata() {
+        let body = Bytes::from_static(br#"{"model": "gpt-4o"}"#);
+        assert_eq!(
+            RequestMetadata::from_body(&body),
+            RequestMetadata::default()
+        );
+        let body = Bytes::from_static(br#"{"metadata": "not an object"}"#);
+        assert_eq!(
+            RequestMetadata::from_body(&body),
+            RequestMetadata::default()
+        );
+    }
+}
@@ -0,0 +1,257 @@
+//! Bounded delivery of logs to Helicone.
+//!
+//! Delivering a log uploads its bodies to object storage and posts it to
+//! Helicone, either of which may be slow or down. Deliveries run at most
+//! `delivery-concurrency` at a time, and at most `queue-capacity` more wait
+//! for their turn, so an observability outage can't grow the number of
+//! tasks and the memory they hold without bound. Once the queue is full, the
+//! configured [`OverflowPolicy`] applies.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use futures::future::BoxFuture;
+use opentelemetry::KeyValue;
+use tokio::sync::Notify;
+
+use crate::{
+    config::logger::{LoggerConfig, OverflowPolicy},
+    metrics::LoggerMetrics,
+    utils::task,
+};
+
+pub type Delivery = BoxFuture<'static, ()>;
+
+#[derive(Default)]
+struct State {
+    queued: VecDeque<Delivery>,
+    in_flight: usize,
+}
+
+struct Inner {
+    state: Mutex<State>,
+    /// Notified whenever a delivery finishes, freeing up space.
+    space: Notify,
+    capacity: usize,
+    concurrency: usize,
+    overflow: OverflowPolicy,
+    metrics: LoggerMetrics,
+}
+
+#[derive(Clone)]
+pub struct LogQueue {
+    inner: Arc<Inner>,
+}
+
+impl std::fmt::Debug for LogQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogQueue")
+            .field("capacity", &self.inner.capacity)
+            .field("concurrency", &self.inner.concurrency)
+            .field("overflow", &self.inner.overflow)
+            .finish_non_exhaustive()
+    }
+}
+
+impl LogQueue {
+    #[must_use]
+    pub fn new(config: &LoggerConfig, metrics: LoggerMetrics) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                state: Mutex::default(),
+                space: Notify::new(),
+                capacity: config.queue_capacity,
+                concurrency: config.delivery_concurrency.max(1),
+                overflow: config.overflow,
+                metrics,
+            }),
+        }
+    }
+
+    /// Queues `delivery`, starting it right away if fewer than
+    /// `delivery-concurrency` deliveries are running.
+    pub async fn push(&self, delivery: Delivery) {
+        let inner = &self.inner;
+        let mut delivery = Some(delivery);
+        loop {
+            let space = inner.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+            {
+                let mut state = inner.state.lock().expect("log queue poisoned");
+                if state.in_flight < inner.concurrency {
+                    state.in_flight += 1;
+                    drop(state);
+                    let delivery = delivery.take().expect("queued once");
+                    task::spawn(
+                        "log-delivery",
+                        deliver(self.inner.clone(), delivery),
+                    );
+                    return;
+                }
+                if state.queued.len() < inner.capacity {
+                    state
+                        .queued
+                        .push_back(delivery.take().expect("queued once"));
+                    inner.metrics.queued_logs.add(1, &[]);
+                    return;
+                }
+                match inner.overflow {
+                    OverflowPolicy::Block => {}
+                    OverflowPolicy::DropOldest => {
+                        if state.queued.pop_front().is_some() {
+                            state.queued.push_back(
+                                delivery.take().expect("queued once"),
+                            );
+                        }
+                        drop(state);
+                        self.record_drop("drop-oldest");
+                        return;
+                    }
+                    OverflowPolicy::DropNew => {
+                        drop(state);
+                        self.record_drop("drop-new");
+                        return;
+                    }
+                }
+            }
+            space.await;
+        }
+    }
+
+    fn record_drop(&self, policy: &'static str) {
+        tracing::warn!(policy, "log queue full, dropping log");
+        self.inner
+            .metrics
+            .dropped_logs
+            .add(1, &[KeyValue::new("policy", policy)]);
+    }
+}
+
+/// Runs `delivery`, and then queued deliveries until there are none left.
+async fn deliver(inner: Arc<Inner>, mut delivery: Delivery) {
+    loop {
+        delivery.await;
+        let next = {
+            let mut state = inner.state.lock().expect("log queue poisoned");
+            let next = state.queued.pop_front();
+            if next.is_none() {
+                state.in_flight -= 1;
+            }
+            next
+        };
+        inner.space.notify_one();
+        match next {
+            Some(next) => {
+                inner.metrics.queued_logs.add(-1, &[]);
+                delivery = next;
+            }
+            None => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    fn queue(overflow: OverflowPolicy) -> LogQueue {
+        let config = LoggerConfig {
+            queue_capacity: 1,
+            delivery_concurrency: 1,
+            overflow,
+            ..Default::default()
+        };
+        let meter = opentelemetry::global::meter("test");
+        LogQueue::new(&config, LoggerMetrics::new(&meter))
+    }
+
+    /// Fills the queue with a running delivery which waits for the returned
+    /// sender, and a queued one.
+    async fn fill(
+        queue: &LogQueue,
+        delivered: &Arc<AtomicUsize>,
+    ) -> oneshot::Sender<()> {
+        let (tx, rx) = oneshot::channel::<()>();
+        queue
+            .push(Box::pin(async move {
+                let _ = rx.await;
+            }))
+            .await;
+        push_counted(queue, delivered, 1).await;
+        tx
+    }
+
+    async fn push_counted(
+        queue: &LogQueue,
+        delivered: &Arc<AtomicUsize>,
+        value: usize,
+    ) {
+        let delivered = delivered.clone();
+        queue
+            .push(Box::pin(async move {
+                delivered.fetch_add(value, Ordering::SeqCst);
+            }))
+            .await;
+    }
+
+    async fn settle(queue: &LogQueue) {
+        for _ in 0..100 {
+            if queue.inner.state.lock().unwrap().in_flight == 0 {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        panic!("deliveries didn't finish");
+    }
+
+    #[tokio::test]
+    async fn drop_new_keeps_queued_logs() {
+        let queue = queue(OverflowPolicy::DropNew);
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let tx = fill(&queue, &delivered).await;
+        push_counted(&queue, &delivered, 10).await;
+        tx.send(()).unwrap();
+        settle(&queue).await;
+        assert_eq!(delivered.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn drop_oldest_replaces_queued_logs() {
+        let queue = queue(OverflowPolicy::DropOldest);
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let tx = fill(&queue, &delivered).await;
+        push_counted(&queue, &delivered, 10).await;
+        tx.send(()).unwrap();
+        settle(&queue).await;
+        assert_eq!(delivered.load(Ordering::SeqCst), 10);
+    }
+
+    #[tokio::test]
+    async fn block_waits_for_space() {
+        let queue = queue(OverflowPolicy::Block);
+        let delivered = Arc::new(AtomicUsize::new(0));
+        let tx = fill(&queue, &delivered).await;
+        let blocked = {
+            let queue = queue.clone();
+            let delivered = delivered.clone();
+            tokio::spawn(async move {
+                push_counted(&queue, &delivered, 10).await;
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!blocked.is_finished());
+        tx.send(()).unwrap();
+        blocked.await.unwrap();
+        settle(&queue).await;
+        assert_eq!(delivered.load(Ordering::SeqCst), 11);
+    }
+}
@@ -3,11 +3,12 @@ use std::time::Duration;
 use bytes::Bytes;
 use chrono::{DateTime, Utc};
 use http::{HeaderMap, StatusCode};
-use http_body_util::BodyExt;
 use indexmap::IndexMap;
 use opentelemetry::KeyValue;
 use reqwest::Client;
+use serde::Serialize;
 use tokio::{sync::oneshot, time::Instant};
+use tracing::Instrument;
 use typed_builder::TypedBuilder;
 use url::Url;
 use uuid::Uuid;
@@ -16,11 +17,23 @@ use crate::{
     app_state::AppState,
     config::deployment_target::DeploymentTarget,
     error::{init::InitError, logger::LoggerError},
-    metrics::tfft::TFFTFuture,
-    store::minio::MinioClient,
+    logger::{
+        dlq::FailedDelivery,
+        event::RequestEvent,
+        properties::{self, RequestMetadata},
+        spill::CollectedBody,
+        usage::Usage,
+    },
+    metrics::{
+        body_size::{self, Direction},
+        spend::SpendScope,
+        tfft::{self, TFFTFuture},
+        throughput,
+    },
+    store::minio::{MinioClient, Payload},
     types::{
         body::BodyReader,
-        extensions::{AuthContext, MapperContext, PromptContext},
+        extensions::{AuthContext, ClientIp, MapperContext, PromptContext},
         logger::{
             HeliconeLogMetadata, Log, LogMessage, RequestLog, ResponseLog,
         },
@@ -46,6 +59,31 @@ impl JawnClient {
                 .map_err(InitError::CreateReqwestClient)?,
         })
     }
+
+    /// Sends a log to Helicone.
+    pub async fn send_log<T: Serialize + ?Sized>(
+        &self,
+        url: Url,
+        api_key: &str,
+        log: &T,
+    ) -> Result<(), LoggerError> {
+        self.request_client
+            .post(url)
+            .json(log)
+            .header("authorization", format!("Bearer {api_key}"))
+            .send()
+            .await
+            .map_err(|e| {
+                tracing::debug!(error = %e, "failed to send request to helicone");
+                LoggerError::FailedToSendRequest(e)
+            })?
+            .error_for_status()
+            .map_err(|e| {
+                tracing::error!(error = %e, "failed to log request to helicone");
+                LoggerError::ResponseError(e)
+            })?;
+        Ok(())
+    }
 }
 
 #[derive(Debug, TypedBuilder)]
@@ -54,7 +92,8 @@ pub struct LoggerService {
     auth_ctx: AuthContext,
     start_time: DateTime<Utc>,
     start_instant: Instant,
-    response_body: BodyReader,
+    #[builder(setter(strip_option))]
+    response_body: Option<BodyReader>,
     request_body: Bytes,
     target_url: Url,
     request_headers: HeaderMap,
@@ -63,7 +102,8 @@ pub struct LoggerService {
     mapper_ctx: MapperContext,
     router_id: Option<RouterId>,
     deployment_target: DeploymentTarget,
-    tfft_rx: oneshot::Receiver<()>,
+    #[builder(setter(strip_option))]
+    tfft_rx: Option<oneshot::Receiver<()>>,
     request_id: Uuid,
     #[builder(default)]
     cache_enabled: Option<bool>,
@@ -75,44 +115,60 @@ pub struct LoggerService {
     cache_reference_id: Option<String>,
     #[builder(default)]
     prompt_ctx: Option<PromptContext>,
+    /// If `None`, the properties are read from the request headers and body.
+    #[builder(default, setter(strip_option))]
+    properties: Option<IndexMap<String, String>>,
+    #[builder(default)]
+    spend: Option<SpendScope>,
+    #[builder(default)]
+    client_ip: Option<ClientIp>,
 }
 
 impl LoggerService {
+    /// Collects the response body, and then queues the log for delivery.
     #[tracing::instrument(skip_all)]
-    #[allow(clippy::cast_precision_loss, clippy::too_many_lines)]
+    #[allow(clippy::cast_precision_loss)]
     pub async fn log(mut self) -> Result<(), LoggerError> {
         tracing::trace!("logging request");
-        let tfft_future = TFFTFuture::new(self.start_instant, self.tfft_rx);
-        let collect_future = self.response_body.collect();
+        let response_body = self
+            .response_body
+            .take()
+            .expect("always set by the builder");
+        let tfft_rx = self.tfft_rx.take().expect("always set by the builder");
+        let tfft_future = TFFTFuture::new(self.start_instant, tfft_rx);
+        let collect_future = CollectedBody::collect(
+            response_body,
+            &self.app_state.config().logger,
+            &self.app_state.0.metrics.logger,
+            self.request_id,
+        );
         let (response_body, tfft_duration) =
             tokio::join!(collect_future, tfft_future);
-        let response_body = response_body
-            .inspect_err(|_| tracing::error!("infallible errored"))
-            .expect("infallible never errors")
-            .to_bytes();
+        let response_body = response_body?;
         let tfft_duration = tfft_duration.unwrap_or_else(|_| {
             tracing::error!("Failed to get TFFT signal");
             Duration::from_secs(0)
         });
         tracing::trace!(tfft_duration = ?tfft_duration, "tfft_duration");
-        let req_body_len = self.request_body.len();
-        let resp_body_len = response_body.len();
-        let s3_client = if self.app_state.config().deployment_target.is_cloud()
+        body_size::record(
+            &self.app_state,
+            &self.provider,
+            self.mapper_ctx.model.as_ref(),
+            Direction::Response,
+            response_body.len(),
+        );
+        if self.mapper_ctx.is_stream
+            && let CollectedBody::Memory(body) = &response_body
         {
-            MinioClient::cloud(&self.app_state.0.minio)
-        } else {
-            MinioClient::sidecar(&self.app_state.0.jawn_http_client)
-        };
-        s3_client
-            .log_bodies(
+            throughput::record(
                 &self.app_state,
-                &self.auth_ctx,
-                self.request_id,
-                self.request_body,
-                response_body,
-            )
-            .await?;
-
+                &self.provider,
+                self.mapper_ctx.model.as_ref(),
+                body,
+                tfft_duration,
+                self.start_instant.elapsed(),
+            );
+        }
         let model = self
             .mapper_ctx
             .model
@@ -123,11 +179,103 @@ impl LoggerService {
             KeyValue::new("model", model),
             KeyValue::new("path", self.target_url.path().to_string()),
         ];
-        self.app_state
-            .0
-            .metrics
-            .tfft_duration
-            .record(tfft_duration.as_millis() as f64, &attributes);
+        tfft::record_tfft(
+            &self.app_state.0.metrics,
+            tfft_duration,
+            &attributes,
+        );
+        tfft::record_request_duration(
+            &self.app_state.0.metrics,
+            self.start_instant.elapsed(),
+            &attributes,
+        );
+        if self.app_state.config().logger.request_events {
+            RequestEvent::builder()
+                .request_id(self.request_id)
+                .provider(&self.provider)
+                .model(self.mapper_ctx.model.as_ref())
+                .path(self.target_url.path())
+                .status(self.response_status)
+                .is_stream(self.mapper_ctx.is_stream)
+                .request_body_size(self.request_body.len() as u64)
+                .response_body_size(response_body.len())
+                .tfft(tfft_duration)
+                .duration(self.start_instant.elapsed())
+                .router_id(self.router_id.as_ref())
+                .build()
+                .emit();
+        }
+
+        if self.response_status.is_success()
+            && (self.app_state.0.usage.is_some() || self.spend.is_some())
+        {
+            // spilled bodies are too large to parse, so only their request
+            // is counted
+            let body = match &response_body {
+                CollectedBody::Memory(bytes) => bytes.as_ref(),
+                CollectedBody::Spilled(_) => &[],
+            };
+            let usage = Usage::from_response(body, self.mapper_ctx.is_stream);
+            if let Some(aggregator) = &self.app_state.0.usage {
+                aggregator.record(
+                    self.start_time,
+                    &self.provider,
+                    self.mapper_ctx.model.as_ref(),
+                    &usage,
+                );
+            }
+            if let Some(spend) = &self.spend {
+                spend.record(
+                    &self.app_state,
+                    self.mapper_ctx.model.as_ref(),
+                    &usage,
+                );
+            }
+        }
+
+        let log_queue = self.app_state.0.log_queue.clone();
+        let app_state = self.app_state.clone();
+        let delivery = async move {
+            if let Err(e) = self.deliver(response_body, tfft_duration).await {
+                let error_str = e.as_ref().to_string();
+                app_state
+                    .0
+                    .metrics
+                    .error_count
+                    .add(1, &[KeyValue::new("type", error_str)]);
+            }
+        }
+        .instrument(tracing::Span::current());
+        log_queue.push(Box::pin(delivery)).await;
+        Ok(())
+    }
+
+    /// Uploads the bodies and sends the log to Helicone.
+    #[allow(clippy::cast_precision_loss)]
+    async fn deliver(
+        mut self,
+        response_body: CollectedBody,
+        tfft_duration: Duration,
+    ) -> Result<(), LoggerError> {
+        let req_body_len = self.request_body.len();
+        let mut properties = self.properties.take().unwrap_or_else(|| {
+            properties::merge(
+                &self.request_headers,
+                RequestMetadata::from_body(&self.request_body),
+            )
+        });
+        if let Some(client_ip) = self.client_ip {
+            properties.insert("client-ip".to_string(), client_ip.to_string());
+        }
+        let resp_body_len = response_body.len();
+        let payload = Payload::new(
+            &self.app_state.config().logger,
+            self.request_id,
+            std::mem::take(&mut self.request_body),
+            response_body,
+        )
+        .await?;
+        let payload_checksum = payload.checksum();
 
         let helicone_metadata = HeliconeLogMetadata::from_headers(
             &mut self.request_headers,
@@ -142,16 +290,6 @@ impl LoggerService {
             provider => provider.to_string().to_uppercase(),
         };
 
-        // Extract helicone-properties-* headers
-        let mut properties = IndexMap::new();
-        for (name, value) in &self.request_headers {
-            if name.as_str().starts_with("helicone-property-") {
-                if let Ok(value_str) = value.to_str() {
-                    properties.insert(name.to_string(), value_str.to_string());
-                }
-            }
-        }
-
         let request_log = RequestLog::builder()
             .id(self.request_id)
             .user_id(self.auth_ctx.user_id)
@@ -166,6 +304,7 @@ impl LoggerService {
             .cache_bucket_max_size(self.cache_bucket_max_size)
             .cache_control(self.cache_control)
             .cache_reference_id(self.cache_reference_id)
+            .payload_sha256(payload_checksum.as_str().to_string())
             .build();
         let response_log = ResponseLog::builder()
             .id(self.request_id)
@@ -181,6 +320,33 @@ impl LoggerService {
             .log(log)
             .build();
 
+        let api_key = self.auth_ctx.api_key.expose();
+        if let Err(e) = MinioClient::new(&self.app_state)
+            .upload(
+                &self.app_state,
+                &self.auth_ctx.org_id,
+                api_key,
+                self.request_id,
+                &payload,
+            )
+            .await
+        {
+            let failed = FailedDelivery::new(
+                &self.auth_ctx,
+                self.request_id,
+                &log_message,
+                Some(&payload),
+            );
+            dead_letter(&self.app_state, failed, &e).await;
+            return Err(e);
+        }
+
+        if let Some(batcher) = &self.app_state.0.log_batcher {
+            return batcher
+                .add(&self.auth_ctx, self.request_id, &log_message)
+                .await;
+        }
+
         let helicone_url = self
             .app_state
             .config()
@@ -188,30 +354,43 @@ impl LoggerService {
             .base_url
             .join("/v1/log/request")?;
 
-        let _helicone_response = self
+        let start = Instant::now();
+        let result = self
             .app_state
             .0
             .jawn_http_client
-            .request_client
-            .post(helicone_url)
-            .json(&log_message)
-            .header(
-                "authorization",
-                format!("Bearer {}", self.auth_ctx.api_key.expose()),
-            )
-            .send()
-            .await
-            .map_err(|e| {
-                tracing::debug!(error = %e, "failed to send request to helicone");
-                LoggerError::FailedToSendRequest(e)
-            })?
-            .error_for_status()
-            .map_err(|e| {
-                tracing::error!(error = %e, "failed to log request to helicone");
-                LoggerError::ResponseError(e)
-            })?;
+            .send_log(helicone_url, api_key, &log_message)
+            .await;
+        self.app_state.0.metrics.logger.delivery_latency.record(
+            start.elapsed().as_secs_f64() * 1000.0,
+            &[KeyValue::new("batched", false)],
+        );
+        if let Err(e) = result {
+            let failed = FailedDelivery::new(
+                &self.auth_ctx,
+                self.request_id,
+                &log_message,
+                None,
+            );
+            dead_letter(&self.app_state, failed, &e).await;
+            return Err(e);
+        }
 
         tracing::debug!("successfully logged request");
         Ok(())
     }
 }
+
+/// Stores a log which failed to be delivered in the dead-letter queue, if
+/// configured.
+async fn dead_letter(
+    app_state: &AppState,
+    failed: Option<FailedDelivery<'_>>,
+    error: &LoggerError,
+) {
+    if let Some(dlq) = &app_state.0.dlq
+        && let Some(failed) = failed
+    {
+        dlq.push(failed, error).await;
+    }
+}
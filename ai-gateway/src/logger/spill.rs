@@ -0,0 +1,355 @@
+//! Collection of response bodies for logging, spilling large bodies to
+//! disk.
+//!
+//! Streamed responses are collected while they're sent to the client, which
+//! may take minutes. Bodies larger than the configured threshold are written
+//! to a temporary file instead of being held in memory for that time, and
+//! the logged payload is then built and uploaded from disk as well.
+use std::{
+    io::SeekFrom,
+    path::{Path, PathBuf},
+};
+
+use bytes::{Bytes, BytesMut};
+use futures::Stream;
+use http_body_util::BodyExt;
+use opentelemetry::metrics::UpDownCounter;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{File, OpenOptions},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter},
+};
+use uuid::Uuid;
+
+use crate::{
+    config::logger::LoggerConfig, error::logger::LoggerError,
+    metrics::LoggerMetrics, store::minio::Checksum, types::body::BodyReader,
+};
+
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A temporary file which is removed when dropped.
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl SpillFile {
+    async fn create(dir: &Path, name: &str) -> Result<Self, LoggerError> {
+        let path = dir.join(format!("ai-gateway-{name}"));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await
+            .map_err(LoggerError::SpillFailed)?;
+        Ok(Self { path, file, len: 0 })
+    }
+
+    async fn write_all(&mut self, data: &[u8]) -> Result<(), LoggerError> {
+        self.file
+            .write_all(data)
+            .await
+            .map_err(LoggerError::SpillFailed)?;
+        self.len += data.len() as u64;
+        Ok(())
+    }
+
+    /// Reads the file from the start.
+    async fn rewind(&mut self) -> Result<(), LoggerError> {
+        self.file.flush().await.map_err(LoggerError::SpillFailed)?;
+        self.file
+            .seek(SeekFrom::Start(0))
+            .await
+            .map_err(LoggerError::SpillFailed)?;
+        Ok(())
+    }
+
+    async fn read_chunk(&mut self) -> Result<Option<Bytes>, LoggerError> {
+        let mut chunk = BytesMut::with_capacity(READ_CHUNK_SIZE);
+        let read = self
+            .file
+            .read_buf(&mut chunk)
+            .await
+            .map_err(LoggerError::SpillFailed)?;
+        Ok((read > 0).then(|| chunk.freeze()))
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        if let Err(error) = std::fs::remove_file(&self.path) {
+            tracing::warn!(
+                error = %error,
+                path = %self.path.display(),
+                "failed to remove spill file"
+            );
+        }
+    }
+}
+
+/// Accounts bytes held in memory in the `logger_buffered_bytes` metric
+/// until dropped.
+struct BufferedBytes {
+    counter: UpDownCounter<i64>,
+    bytes: i64,
+}
+
+impl BufferedBytes {
+    fn add(&mut self, bytes: usize) {
+        let bytes = i64::try_from(bytes).unwrap_or(i64::MAX);
+        self.counter.add(bytes, &[]);
+        self.bytes += bytes;
+    }
+
+    fn clear(&mut self) {
+        self.counter.add(-self.bytes, &[]);
+        self.bytes = 0;
+    }
+}
+
+impl Drop for BufferedBytes {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// A collected response body.
+#[derive(Debug)]
+pub enum CollectedBody {
+    Memory(Bytes),
+    Spilled(SpillFile),
+}
+
+impl CollectedBody {
+    /// Collects `body`, spilling it to disk once it exceeds the configured
+    /// threshold.
+    pub async fn collect(
+        mut body: BodyReader,
+        config: &LoggerConfig,
+        metrics: &LoggerMetrics,
+        request_id: Uuid,
+    ) -> Result<Self, LoggerError> {
+        let mut buffer = BytesMut::new();
+        let mut buffered = BufferedBytes {
+            counter: metrics.buffered_bytes.clone(),
+            bytes: 0,
+        };
+        let mut spill_file: Option<SpillFile> = None;
+        while let Some(frame) = body.frame().await {
+            let Ok(frame) = frame;
+            let Ok(data) = frame.into_data() else {
+                continue;
+            };
+            if let Some(spill_file) = &mut spill_file {
+                spill_file.write_all(&data).await?;
+                continue;
+            }
+            buffer.extend_from_slice(&data);
+            buffered.add(data.len());
+            if buffer.len() > config.spill_threshold {
+                let mut file = SpillFile::create(
+                    &config.spill_dir(),
+                    &format!("{request_id}-response"),
+                )
+                .await?;
+                file.write_all(&buffer).await?;
+                buffer = BytesMut::new();
+                buffered.clear();
+                metrics.spilled_bodies.add(1, &[]);
+                spill_file = Some(file);
+            }
+        }
+        match spill_file {
+            Some(file) => Ok(Self::Spilled(file)),
+            None => Ok(Self::Memory(buffer.freeze())),
+        }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Memory(bytes) => bytes.len() as u64,
+            Self::Spilled(file) => file.len,
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A JSON encoded [`S3Log`](crate::types::logger::S3Log) written to disk.
+#[derive(Debug)]
+pub struct SpilledPayload {
+    file: SpillFile,
+    pub checksum: Checksum,
+}
+
+impl SpilledPayload {
+    /// Writes the payload of `request_body` and the spilled response body
+    /// in `dir`.
+    pub async fn write(
+        dir: &Path,
+        request_id: Uuid,
+        request_body: &[u8],
+        mut response_body: SpillFile,
+    ) -> Result<Self, LoggerError> {
+        let request_body = std::str::from_utf8(request_body)
+            .map_err(|_| LoggerError::SpilledBodyNotUtf8)?;
+        let mut file =
+            SpillFile::create(dir, &format!("{request_id}-payload")).await?;
+        let mut writer = PayloadWriter {
+            writer: BufWriter::new(&mut file.file),
+            digest: Sha256::new(),
+            len: 0,
+        };
+        writer.write(br#"{"request":"#).await?;
+        writer.write(&escape(request_body)?).await?;
+        writer.write(br#","response":""#).await?;
+        response_body.rewind().await?;
+        let mut incomplete = BytesMut::new();
+        while let Some(chunk) = response_body.read_chunk().await? {
+            incomplete.extend_from_slice(&chunk);
+            let valid_up_to = match std::str::from_utf8(&incomplete) {
+                Ok(_) => incomplete.len(),
+                // a character is split across chunks
+                Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                Err(_) => return Err(LoggerError::SpilledBodyNotUtf8),
+            };
+            let valid = incomplete.split_to(valid_up_to);
+            let valid = std::str::from_utf8(&valid)
+                .map_err(|_| LoggerError::SpilledBodyNotUtf8)?;
+            let escaped = escape(valid)?;
+            // without the quotes
+            writer.write(&escaped[1..escaped.len() - 1]).await?;
+        }
+        if !incomplete.is_empty() {
+            return Err(LoggerError::SpilledBodyNotUtf8);
+        }
+        writer.write(br#""}"#).await?;
+        writer
+            .writer
+            .flush()
+            .await
+            .map_err(LoggerError::SpillFailed)?;
+        let checksum = Checksum::from_digest(writer.digest);
+        file.len = writer.len;
+        Ok(Self { file, checksum })
+    }
+
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        self.file.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Streams the payload from disk.
+    pub async fn stream(
+        &self,
+    ) -> Result<
+        impl Stream<Item = Result<Bytes, LoggerError>> + use<>,
+        LoggerError,
+    > {
+        let file = File::open(&self.file.path)
+            .await
+            .map_err(LoggerError::SpillFailed)?;
+        Ok(futures::stream::try_unfold(file, |mut file| async move {
+            let mut chunk = BytesMut::with_capacity(READ_CHUNK_SIZE);
+            let read = file
+                .read_buf(&mut chunk)
+                .await
+                .map_err(LoggerError::SpillFailed)?;
+            Ok((read > 0).then(|| (chunk.freeze(), file)))
+        }))
+    }
+
+    /// Copies the payload to `path`.
+    pub async fn copy_to(&self, path: &Path) -> std::io::Result<()> {
+        tokio::fs::copy(&self.file.path, path).await.map(|_| ())
+    }
+}
+
+struct PayloadWriter<'a> {
+    writer: BufWriter<&'a mut File>,
+    digest: Sha256,
+    len: u64,
+}
+
+impl PayloadWriter<'_> {
+    async fn write(&mut self, data: &[u8]) -> Result<(), LoggerError> {
+        self.writer
+            .write_all(data)
+            .await
+            .map_err(LoggerError::SpillFailed)?;
+        self.digest.update(data);
+        self.len += data.len() as u64;
+        Ok(())
+    }
+}
+
+/// `value` as a JSON string.
+fn escape(value: &str) -> Result<Vec<u8>, LoggerError> {
+    serde_json::to_vec(value).map_err(|e| {
+        tracing::error!(error = %e, "failed to serialize s3 log");
+        LoggerError::InvalidLogMessage
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::TryStreamExt;
+
+    use super::*;
+    use crate::types::logger::S3Log;
+
+    #[tokio::test]
+    async fn spilled_payload_matches_in_memory_payload() {
+        let dir = std::env::temp_dir();
+        let request_id = Uuid::new_v4();
+        let request_body = br#"{"model": "gpt-4o", "stream": true}"#;
+        // multi-byte characters split across read chunks
+        let response_body = "data: {\"content\": \"h\u{e9}llo \u{1f600}\"}\n\n"
+            .repeat(READ_CHUNK_SIZE / 10);
+        let mut spill_file =
+            SpillFile::create(&dir, &format!("{request_id}-response"))
+                .await
+                .unwrap();
+        spill_file
+            .write_all(response_body.as_bytes())
+            .await
+            .unwrap();
+
+        let payload =
+            SpilledPayload::write(&dir, request_id, request_body, spill_file)
+                .await
+                .unwrap();
+        let expected = serde_json::to_vec(&S3Log::new(
+            String::from_utf8(request_body.to_vec()).unwrap(),
+            response_body,
+        ))
+        .unwrap();
+        assert_eq!(payload.len(), expected.len() as u64);
+        assert_eq!(payload.checksum, Checksum::sha256(&expected));
+
+        let streamed = payload
+            .stream()
+            .await
+            .unwrap()
+            .try_fold(Vec::new(), |mut streamed, chunk| async move {
+                streamed.extend_from_slice(&chunk);
+                Ok(streamed)
+            })
+            .await
+            .unwrap();
+        assert_eq!(streamed, expected);
+    }
+}
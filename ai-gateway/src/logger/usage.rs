@@ -0,0 +1,264 @@
+//! Aggregation of the usage of logged requests per provider, model and day,
+//! for reconciling it against provider invoices.
+//!
+//! Usage is aggregated in memory and periodically added to the database by
+//! the [`UsageReporter`] service. Since every replica adds its own usage,
+//! the stored usage is the total of all replicas.
+use std::{
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use futures::future::BoxFuture;
+use meltdown::Token;
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::Value;
+use tracing::info;
+
+use crate::{
+    config::logger::UsageReportConfig,
+    error::runtime::RuntimeError,
+    store::usage::UsageStore,
+    types::{model_id::ModelId, provider::InferenceProvider},
+};
+
+/// The usage of requests.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Usage {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+}
+
+impl Usage {
+    /// The usage of a request whose response body is `body`. Tokens are
+    /// only counted if the provider reported them in the body.
+    #[must_use]
+    pub fn from_response(body: &[u8], is_stream: bool) -> Self {
+        let mut usage = Self {
+            requests: 1,
+            ..Self::default()
+        };
+        if is_stream {
+            // usage is reported in the last chunk by OpenAI, and spread
+            // over the start and end events by Anthropic, with cumulative
+            // counts
+            let events = body
+                .split(|byte| *byte == b'\n')
+                .filter_map(|line| line.strip_prefix(b"data:"))
+                .filter_map(|data| serde_json::from_slice::<Value>(data).ok());
+            for event in events {
+                usage.observe(&event);
+            }
+        } else if let Ok(body) = serde_json::from_slice::<Value>(body) {
+            usage.observe(&body);
+        }
+        usage
+    }
+
+    fn observe(&mut self, body: &Value) {
+        let reported = body
+            .get("usage")
+            .or_else(|| body.pointer("/message/usage"))
+            .or_else(|| body.get("usageMetadata"))
+            .filter(|usage| usage.is_object());
+        let Some(reported) = reported else {
+            return;
+        };
+        let count = |fields: &[&str]| {
+            fields
+                .iter()
+                .find_map(|field| reported.get(*field).and_then(Value::as_u64))
+                .unwrap_or_default()
+        };
+        self.prompt_tokens = self.prompt_tokens.max(count(&[
+            "prompt_tokens",
+            "input_tokens",
+            "promptTokenCount",
+        ]));
+        self.completion_tokens = self.completion_tokens.max(count(&[
+            "completion_tokens",
+            "output_tokens",
+            "candidatesTokenCount",
+        ]));
+    }
+
+    fn add(&mut self, other: &Self) {
+        self.requests = self.requests.saturating_add(other.requests);
+        self.prompt_tokens =
+            self.prompt_tokens.saturating_add(other.prompt_tokens);
+        self.completion_tokens = self
+            .completion_tokens
+            .saturating_add(other.completion_tokens);
+    }
+}
+
+/// What usage is aggregated by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct UsageKey {
+    pub day: NaiveDate,
+    pub provider: String,
+    pub model: String,
+}
+
+/// The usage recorded since it was last added to the database.
+#[derive(Debug, Clone, Default)]
+pub struct UsageAggregator(Arc<Mutex<HashMap<UsageKey, Usage>>>);
+
+impl UsageAggregator {
+    pub fn record(
+        &self,
+        started_at: DateTime<Utc>,
+        provider: &InferenceProvider,
+        model: Option<&ModelId>,
+        usage: &Usage,
+    ) {
+        let key = UsageKey {
+            day: started_at.date_naive(),
+            provider: provider.to_string(),
+            model: model
+                .map_or_else(|| "unknown".to_string(), ToString::to_string),
+        };
+        self.0
+            .lock()
+            .expect("usage aggregator poisoned")
+            .entry(key)
+            .or_default()
+            .add(usage);
+    }
+
+    fn take(&self) -> HashMap<UsageKey, Usage> {
+        mem::take(&mut *self.0.lock().expect("usage aggregator poisoned"))
+    }
+
+    /// Puts back usage which failed to be stored, to be retried with the
+    /// next flush.
+    fn restore(&self, usage: HashMap<UsageKey, Usage>) {
+        let mut aggregated = self.0.lock().expect("usage aggregator poisoned");
+        for (key, usage) in usage {
+            aggregated.entry(key).or_default().add(&usage);
+        }
+    }
+}
+
+/// Background service which periodically adds the aggregated usage to the
+/// database.
+#[derive(Debug)]
+pub struct UsageReporter {
+    aggregator: UsageAggregator,
+    store: UsageStore,
+    config: UsageReportConfig,
+}
+
+impl UsageReporter {
+    #[must_use]
+    pub fn new(
+        aggregator: UsageAggregator,
+        store: UsageStore,
+        config: UsageReportConfig,
+    ) -> Self {
+        Self {
+            aggregator,
+            store,
+            config,
+        }
+    }
+
+    async fn flush(&self) {
+        let usage = self.aggregator.take();
+        if usage.is_empty() {
+            return;
+        }
+        if let Err(e) = self.store.add(&usage).await {
+            tracing::error!(error = %e, "failed to store usage");
+            self.aggregator.restore(usage);
+        }
+    }
+}
+
+impl meltdown::Service for UsageReporter {
+    type Future = BoxFuture<'static, Result<(), RuntimeError>>;
+
+    fn run(self, mut token: Token) -> Self::Future {
+        Box::pin(async move {
+            let mut interval =
+                tokio::time::interval(self.config.flush_interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => self.flush().await,
+                    () = &mut token => {
+                        // don't lose the usage since the last flush
+                        self.flush().await;
+                        info!(name = "usage-reporter", "task shutting down");
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn usage_from_responses() {
+        let body = serde_json::to_vec(&json!({
+            "choices": [],
+            "usage": {"prompt_tokens": 12, "completion_tokens": 3},
+        }))
+        .unwrap();
+        let usage = Usage::from_response(&body, false);
+        assert_eq!(
+            usage,
+            Usage {
+                requests: 1,
+                prompt_tokens: 12,
+                completion_tokens: 3,
+            }
+        );
+
+        let body = b"event: message_start\n\
+            data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":25,\"output_tokens\":1}}}\n\n\
+            event: message_delta\n\
+            data: {\"type\":\"message_delta\",\"usage\":{\"output_tokens\":15}}\n\n";
+        let usage = Usage::from_response(body, true);
+        assert_eq!(usage.prompt_tokens, 25);
+        assert_eq!(usage.completion_tokens, 15);
+
+        let usage = Usage::from_response(b"not json", false);
+        assert_eq!(usage.requests, 1);
+        assert_eq!(usage.prompt_tokens, 0);
+    }
+
+    #[test]
+    fn restored_usage_is_merged() {
+        let aggregator = UsageAggregator::default();
+        let provider = InferenceProvider::OpenAI;
+        let usage = Usage {
+            requests: 1,
+            prompt_tokens: 10,
+            completion_tokens: 5,
+        };
+        let started_at = Utc::now();
+        aggregator.record(started_at, &provider, None, &usage);
+        let taken = aggregator.take();
+        aggregator.record(started_at, &provider, None, &usage);
+        aggregator.restore(taken);
+        let aggregated = aggregator.take();
+        assert_eq!(aggregated.len(), 1);
+        assert_eq!(
+            aggregated.values().next(),
+            Some(&Usage {
+                requests: 2,
+                prompt_tokens: 20,
+                completion_tokens: 10,
+            })
+        );
+    }
+}
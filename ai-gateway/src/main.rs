@@ -2,23 +2,31 @@ use std::{path::PathBuf, time::Duration};
 
 use ai_gateway::{
     app::App,
-    config::Config,
+    cli::{bench, doctor},
+    config::{
+        Config,
+        validation::{Issue, Report},
+    },
     control_plane::websocket::ControlPlaneClient,
     discover::monitor::{
         health::provider::HealthMonitor, rate_limit::RateLimitMonitor,
+        state_sync::StateSyncListener,
     },
     error::{init::InitError, runtime::RuntimeError},
+    logger::{dlq::DlqRetrier, usage::UsageReporter},
     metrics::system::SystemMetrics,
     middleware::rate_limit,
-    store::db_listener::DatabaseListener,
+    store::{
+        conversation::{ConversationReaper, ConversationStore},
+        db_listener::DatabaseListener,
+        leader::LeaderElector,
+        usage::UsageStore,
+    },
     utils::meltdown::TaggedService,
 };
 use clap::Parser;
 use meltdown::Meltdown;
-use opentelemetry_sdk::{
-    logs::SdkLoggerProvider, metrics::SdkMeterProvider,
-    trace::SdkTracerProvider,
-};
+use telemetry::Providers;
 use tracing::{debug, info};
 
 #[global_allocator]
@@ -35,6 +43,58 @@ pub struct Args {
     /// Enable verbose logging
     #[arg(short, long)]
     verbose: bool,
+
+    /// Validate the config and exit, without starting the gateway.
+    #[arg(long)]
+    validate: bool,
+
+    /// Output format of `--validate`.
+    #[arg(long, value_enum, default_value_t, requires = "validate")]
+    format: Format,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, clap::Subcommand)]
+enum Command {
+    /// Check connectivity to the stores, the control plane and the
+    /// providers the config uses, and clock skew, without starting the
+    /// gateway.
+    Doctor,
+    /// Send probe prompts to each model through a running gateway and
+    /// compare their latency and cost, to help pick balance weights.
+    Bench {
+        /// Base URL of the gateway. Defaults to the configured port on
+        /// localhost.
+        #[arg(long)]
+        url: Option<url::Url>,
+        /// A `{provider}/{model}` to benchmark, repeatable. Defaults to the
+        /// first model of each configured provider.
+        #[arg(long = "model")]
+        models: Vec<String>,
+        /// A probe prompt, repeatable. Defaults to a short and a longer
+        /// prompt.
+        #[arg(long = "prompt")]
+        prompts: Vec<String>,
+        /// Requests per model and prompt.
+        #[arg(long, default_value_t = 3)]
+        runs: u32,
+        #[arg(long, default_value_t = 64)]
+        max_tokens: u32,
+        /// Helicone API key, if the gateway requires authentication.
+        #[arg(long, env = "HELICONE_API_KEY", hide_env_values = true)]
+        api_key: Option<String>,
+    },
+}
+
+#[derive(Debug, Default, Clone, Copy, clap::ValueEnum)]
+enum Format {
+    #[default]
+    Text,
+    /// A report with a code, path, message and suggestion per problem, for
+    /// tooling.
+    Json,
 }
 
 #[tokio::main]
@@ -43,22 +103,60 @@ async fn main() -> Result<(), RuntimeError> {
     rustls::crypto::ring::default_provider()
         .install_default()
         .expect("Failed to install rustls crypto provider");
-    let config = load_and_validate_config()?;
-    let (logger_provider, tracer_provider, metrics_provider) =
-        init_telemetry(&config)?;
+    dotenvy::dotenv().ok();
+    let args = Args::parse();
+    match args.command {
+        Some(Command::Doctor) => doctor_and_exit(args.config).await,
+        Some(Command::Bench {
+            url,
+            models,
+            prompts,
+            runs,
+            max_tokens,
+            api_key,
+        }) => {
+            let config = read_config_or_exit(args.config);
+            let url = url.unwrap_or_else(|| {
+                format!("http://127.0.0.1:{}", config.server.port)
+                    .parse()
+                    .expect("localhost url is valid")
+            });
+            let models = if models.is_empty() {
+                bench::default_models(&config)
+            } else {
+                models
+            };
+            let bench = bench::BenchConfig {
+                url,
+                api_key,
+                models,
+                prompts,
+                runs,
+                max_tokens,
+            };
+            let mut results =
+                bench::run(&bench, &config.model_capabilities).await;
+            bench::print(&mut results);
+            return Ok(());
+        }
+        None => {}
+    }
+    let config = load_and_validate_config(args)?;
+    let providers = init_telemetry(&config)?;
 
     run_app(config).await?;
 
-    shutdown_telemetry(logger_provider, &tracer_provider, metrics_provider);
+    shutdown_telemetry(providers);
 
     println!("shut down");
 
     Ok(())
 }
 
-fn load_and_validate_config() -> Result<Config, RuntimeError> {
-    dotenvy::dotenv().ok();
-    let args = Args::parse();
+fn load_and_validate_config(args: Args) -> Result<Config, RuntimeError> {
+    if args.validate {
+        validate_and_exit(args.config, args.format);
+    }
     let mut config = match Config::try_read(args.config) {
         Ok(config) => config,
         Err(error) => {
@@ -79,18 +177,73 @@ fn load_and_validate_config() -> Result<Config, RuntimeError> {
     Ok(config)
 }
 
-fn init_telemetry(
-    config: &Config,
-) -> Result<
-    (
-        Option<SdkLoggerProvider>,
-        SdkTracerProvider,
-        Option<SdkMeterProvider>,
-    ),
-    InitError,
-> {
-    let (logger_provider, tracer_provider, metrics_provider) =
-        telemetry::init_telemetry(&config.telemetry)?;
+/// Prints the problems found in the config, exiting with `1` if there are
+/// any.
+fn validate_and_exit(config_path: Option<PathBuf>, format: Format) -> ! {
+    let issues = match Config::try_read(config_path) {
+        Ok(config) => config
+            .validate()
+            .map_or_else(|error| error.issues(), |()| Vec::new()),
+        Err(error) => vec![Issue::from(error.as_ref())],
+    };
+    let report = Report::new(issues);
+    match format {
+        Format::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&report)
+                .expect("report is serializable")
+        ),
+        Format::Text if report.valid => println!("config is valid"),
+        Format::Text => {
+            for issue in &report.errors {
+                match &issue.path {
+                    Some(path) => {
+                        eprintln!(
+                            "error[{}] {path}: {}",
+                            issue.code, issue.message
+                        );
+                    }
+                    None => {
+                        eprintln!("error[{}]: {}", issue.code, issue.message);
+                    }
+                }
+                if let Some(suggestion) = issue.suggestion {
+                    eprintln!("  help: {suggestion}");
+                }
+            }
+        }
+    }
+    std::process::exit(i32::from(!report.valid));
+}
+
+/// Prints the outcome of the connectivity checks, exiting with `1` if any
+/// failed.
+async fn doctor_and_exit(config_path: Option<PathBuf>) -> ! {
+    let config = read_config_or_exit(config_path);
+    let report = doctor::run(&config).await;
+    report.print();
+    std::process::exit(i32::from(!report.passed()));
+}
+
+fn read_config_or_exit(config_path: Option<PathBuf>) -> Config {
+    match Config::try_read(config_path) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("failed to read config: {error}");
+            std::process::exit(1);
+        }
+    }
+}
+
+fn init_telemetry(config: &Config) -> Result<Providers, InitError> {
+    let mut telemetry_config = config.telemetry.clone();
+    for (key, value) in config.deployment.info().resource_attributes() {
+        telemetry_config
+            .resource_attributes
+            .entry(key)
+            .or_insert(value);
+    }
+    let providers = telemetry::init_telemetry(&telemetry_config)?;
 
     debug!("telemetry initialized");
     let pretty_config = serde_yml::to_string(&config)
@@ -100,7 +253,7 @@ fn init_telemetry(
     #[cfg(debug_assertions)]
     tracing::warn!("running in debug mode");
 
-    Ok((logger_provider, tracer_provider, metrics_provider))
+    Ok(providers)
 }
 
 async fn run_app(config: Config) -> Result<(), RuntimeError> {
@@ -109,10 +262,17 @@ async fn run_app(config: Config) -> Result<(), RuntimeError> {
     let mut shutting_down = false;
     let helicone_config = config.helicone.clone();
     let app = App::new(config).await?;
+    ai_gateway::utils::task::install_panic_hook(
+        app.state.0.metrics.error_count.clone(),
+    );
     let config = app.state.config();
     let health_monitor = HealthMonitor::new(app.state.clone());
     let rate_limit_monitor = RateLimitMonitor::new(app.state.clone());
     let control_plane_state = app.state.0.control_plane_state.clone();
+    let state_sync_listener =
+        app.state.0.state_sync.clone().map(|state_sync| {
+            StateSyncListener::new(app.state.clone(), state_sync)
+        });
 
     let rate_limiting_cleanup_service =
         config.global.rate_limit.as_ref().map(|_| {
@@ -121,6 +281,37 @@ async fn run_app(config: Config) -> Result<(), RuntimeError> {
                 CLEANUP_INTERVAL,
             )
         });
+    let usage_reporter = app
+        .state
+        .0
+        .usage
+        .clone()
+        .zip(config.logger.usage_report.clone())
+        .zip(app.state.0.router_store.as_ref())
+        .map(|((usage, usage_report), router_store)| {
+            UsageReporter::new(
+                usage,
+                UsageStore::new(router_store.pool.clone()),
+                usage_report,
+            )
+        });
+    let conversation_reaper = app
+        .state
+        .0
+        .conversation_store
+        .clone()
+        .filter(|store| matches!(store, ConversationStore::Postgres(_)))
+        .map(|store| {
+            ConversationReaper::new(app.state.clone(), store, CLEANUP_INTERVAL)
+        });
+    let system_metrics =
+        SystemMetrics::new(app.state.0.system_pressure.clone());
+    let dlq_retrier = app
+        .state
+        .0
+        .dlq
+        .clone()
+        .map(|dlq| DlqRetrier::new(app.state.clone(), dlq));
 
     let mut tasks = vec![
         "shutdown-signals",
@@ -160,6 +351,14 @@ async fn run_app(config: Config) -> Result<(), RuntimeError> {
             .await?,
         ));
         tasks.push("database-listener");
+
+        if let Some(leader_election) = config.database.leader_election.clone() {
+            meltdown = meltdown.register(TaggedService::new(
+                "leader-election",
+                LeaderElector::new(app.state.clone(), leader_election),
+            ));
+            tasks.push("leader-election");
+        }
     }
 
     meltdown = meltdown
@@ -172,7 +371,7 @@ async fn run_app(config: Config) -> Result<(), RuntimeError> {
             "provider-rate-limit-monitor",
             rate_limit_monitor,
         ))
-        .register(TaggedService::new("system-metrics", SystemMetrics));
+        .register(TaggedService::new("system-metrics", system_metrics));
 
     if let Some(rate_limiting_cleanup_service) = rate_limiting_cleanup_service {
         meltdown = meltdown.register(TaggedService::new(
@@ -182,6 +381,34 @@ async fn run_app(config: Config) -> Result<(), RuntimeError> {
         tasks.push("rate-limiting-cleanup");
     }
 
+    if let Some(conversation_reaper) = conversation_reaper {
+        meltdown = meltdown.register(TaggedService::new(
+            "conversation-reaper",
+            conversation_reaper,
+        ));
+        tasks.push("conversation-reaper");
+    }
+
+    if let Some(usage_reporter) = usage_reporter {
+        meltdown = meltdown
+            .register(TaggedService::new("usage-reporter", usage_reporter));
+        tasks.push("usage-reporter");
+    }
+
+    if let Some(dlq_retrier) = dlq_retrier {
+        meltdown =
+            meltdown.register(TaggedService::new("dlq-retrier", dlq_retrier));
+        tasks.push("dlq-retrier");
+    }
+
+    if let Some(state_sync_listener) = state_sync_listener {
+        meltdown = meltdown.register(TaggedService::new(
+            "state-sync-listener",
+            state_sync_listener,
+        ));
+        tasks.push("state-sync-listener");
+    }
+
     info!(tasks = ?tasks, "starting services");
 
     while let Some((service, result)) = meltdown.next().await {
@@ -199,22 +426,26 @@ async fn run_app(config: Config) -> Result<(), RuntimeError> {
     Ok(())
 }
 
-fn shutdown_telemetry(
-    logger_provider: Option<SdkLoggerProvider>,
-    tracer_provider: &SdkTracerProvider,
-    metrics_provider: Option<SdkMeterProvider>,
-) {
-    if let Some(logger_provider) = logger_provider {
-        if let Err(e) = logger_provider.shutdown() {
-            println!("error shutting down logger provider: {e}");
-        }
+/// Shuts down the provider of each signal, flushing what it has yet to
+/// export. A signal failing to shut down doesn't keep the others from
+/// shutting down.
+fn shutdown_telemetry(providers: Providers) {
+    let Providers {
+        logger,
+        tracer,
+        meter,
+    } = providers;
+    if let Some(logger) = logger
+        && let Err(e) = logger.shutdown()
+    {
+        println!("error shutting down logger provider: {e}");
     }
-    if let Err(e) = tracer_provider.shutdown() {
+    if let Err(e) = tracer.shutdown() {
         println!("error shutting down tracer provider: {e}");
     }
-    if let Some(metrics_provider) = metrics_provider {
-        if let Err(e) = metrics_provider.shutdown() {
-            println!("error shutting down metrics provider: {e}");
-        }
+    if let Some(meter) = meter
+        && let Err(e) = meter.shutdown()
+    {
+        println!("error shutting down metrics provider: {e}");
     }
 }
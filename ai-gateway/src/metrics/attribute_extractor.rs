@@ -2,12 +2,31 @@ use http::uri::PathAndQuery;
 use opentelemetry::KeyValue;
 use tower_otel_http_metrics::ResponseAttributeExtractor;
 
-use crate::types::{
-    extensions::MapperContext, provider::InferenceProvider, router::RouterId,
+use crate::{
+    metrics::labels::{MetricLabels, NONE},
+    types::{
+        extensions::MapperContext, provider::InferenceProvider,
+        router::RouterId,
+    },
 };
 
+/// Extracts the labels of responses:
+/// - `provider`
+/// - `model`
+/// - `router_id`
+/// - `stream`
+/// - `provider_path`
 #[derive(Debug, Clone)]
-pub struct AttributeExtractor;
+pub struct AttributeExtractor {
+    labels: MetricLabels,
+}
+
+impl AttributeExtractor {
+    #[must_use]
+    pub fn new(labels: MetricLabels) -> Self {
+        Self { labels }
+    }
+}
 
 impl<B> ResponseAttributeExtractor<B> for AttributeExtractor {
     fn extract_attributes(
@@ -15,23 +34,33 @@ impl<B> ResponseAttributeExtractor<B> for AttributeExtractor {
         response: &http::Response<B>,
     ) -> Vec<KeyValue> {
         let resp_extensions = response.extensions();
-        let mut attributes = Vec::new();
-        if let Some(mapper_ctx) = resp_extensions.get::<MapperContext>() {
-            attributes.push(KeyValue::new("stream", mapper_ctx.is_stream));
-            if let Some(model) = &mapper_ctx.model {
-                attributes.push(KeyValue::new("model", model.to_string()));
-            }
-        }
-        if let Some(path) = resp_extensions.get::<PathAndQuery>() {
-            attributes
-                .push(KeyValue::new("provider_path", path.path().to_string()));
-        }
-        if let Some(provider) = resp_extensions.get::<InferenceProvider>() {
-            attributes.push(KeyValue::new("provider", provider.to_string()));
-        }
-        if let Some(router_id) = resp_extensions.get::<RouterId>() {
-            attributes.push(KeyValue::new("router_id", router_id.to_string()));
-        }
-        attributes
+        let mapper_ctx = resp_extensions.get::<MapperContext>();
+        let model = mapper_ctx
+            .and_then(|mapper_ctx| mapper_ctx.model.as_ref())
+            .map_or_else(
+                || NONE.to_string(),
+                |model| self.labels.models.value(&model.to_string()),
+            );
+        let provider = resp_extensions.get::<InferenceProvider>().map_or_else(
+            || NONE.to_string(),
+            |provider| self.labels.providers.value(&provider.to_string()),
+        );
+        let router_id = resp_extensions.get::<RouterId>().map_or_else(
+            || NONE.to_string(),
+            |router_id| self.labels.router_ids.value(&router_id.to_string()),
+        );
+        let provider_path = resp_extensions
+            .get::<PathAndQuery>()
+            .map_or_else(|| NONE.to_string(), |path| path.path().to_string());
+        vec![
+            KeyValue::new("provider", provider),
+            KeyValue::new("model", model),
+            KeyValue::new("router_id", router_id),
+            KeyValue::new(
+                "stream",
+                mapper_ctx.is_some_and(|mapper_ctx| mapper_ctx.is_stream),
+            ),
+            KeyValue::new("provider_path", provider_path),
+        ]
     }
 }
@@ -0,0 +1,197 @@
+//! Sizes of the bodies sent to and received from providers.
+//!
+//! Every size is recorded in the body size histograms, and, if size
+//! anomalies are configured, compared to the recent sizes of the provider
+//! and model's bodies, so that a client starting to send bloated prompts is
+//! noticed before its bill is.
+use std::sync::{Arc, RwLock};
+
+use opentelemetry::KeyValue;
+use rust_decimal::Decimal;
+use rustc_hash::FxHashMap as HashMap;
+
+use crate::{
+    app_state::AppState,
+    config::size_anomaly::SizeAnomalyConfig,
+    metrics::RollingPercentile,
+    types::{model_id::ModelId, provider::InferenceProvider},
+};
+
+const MEDIAN: f64 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, strum::AsRefStr)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Direction {
+    Request,
+    Response,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Anomaly {
+    /// Larger than the configured limit.
+    OverLimit { limit: u64 },
+    /// Larger than the spike factor times the recent median.
+    Spike { median: u64 },
+}
+
+/// Recent body sizes, per provider, model, and direction.
+#[derive(Debug)]
+pub struct SizeAnomalies {
+    config: SizeAnomalyConfig,
+    sizes: RwLock<
+        HashMap<(String, String, Direction), Arc<RollingPercentile<u64>>>,
+    >,
+}
+
+impl SizeAnomalies {
+    #[must_use]
+    pub fn new(config: SizeAnomalyConfig) -> Self {
+        Self {
+            config,
+            sizes: RwLock::default(),
+        }
+    }
+
+    fn sizes_for(
+        &self,
+        key: (String, String, Direction),
+    ) -> Arc<RollingPercentile<u64>> {
+        if let Some(sizes) = self
+            .sizes
+            .read()
+            .expect("size anomalies lock poisoned")
+            .get(&key)
+        {
+            return Arc::clone(sizes);
+        }
+        let mut sizes =
+            self.sizes.write().expect("size anomalies lock poisoned");
+        Arc::clone(sizes.entry(key).or_insert_with(|| {
+            Arc::new(RollingPercentile::new(self.config.window))
+        }))
+    }
+
+    /// Records `bytes`, returning why it's anomalous, if it is. The size is
+    /// compared to the median before it's recorded, so that a spike doesn't
+    /// raise its own baseline.
+    fn observe(
+        &self,
+        key: (String, String, Direction),
+        bytes: u64,
+    ) -> Option<Anomaly> {
+        let limit = match key.2 {
+            Direction::Request => self.config.request_bytes,
+            Direction::Response => self.config.response_bytes,
+        };
+        let sizes = self.sizes_for(key);
+        let median = (sizes.len() >= self.config.min_samples)
+            .then(|| sizes.percentile(MEDIAN))
+            .flatten();
+        sizes.record(bytes);
+        if let Some(limit) = limit
+            && bytes > limit
+        {
+            return Some(Anomaly::OverLimit { limit });
+        }
+        let median = median?;
+        (Decimal::from(bytes)
+            > self.config.spike_factor * Decimal::from(median))
+        .then_some(Anomaly::Spike { median })
+    }
+}
+
+/// Records the size of a body sent to or received from `provider`, and
+/// warns if it's anomalous.
+pub fn record(
+    app_state: &AppState,
+    provider: &InferenceProvider,
+    model: Option<&ModelId>,
+    direction: Direction,
+    bytes: u64,
+) {
+    let metrics = &app_state.0.metrics;
+    let provider = metrics.labels.providers.value(&provider.to_string());
+    let model = model.map_or_else(
+        || "unknown".to_string(),
+        |model| metrics.labels.models.value(&model.to_string()),
+    );
+    let attributes = [
+        KeyValue::new("provider", provider.clone()),
+        KeyValue::new("model", model.clone()),
+    ];
+    match direction {
+        Direction::Request => {
+            metrics.request_body_size.record(bytes, &attributes);
+        }
+        Direction::Response => {
+            metrics.response_body_size.record(bytes, &attributes);
+        }
+    }
+
+    let Some(size_anomalies) = &app_state.0.size_anomalies else {
+        return;
+    };
+    let Some(anomaly) = size_anomalies
+        .observe((provider.clone(), model.clone(), direction), bytes)
+    else {
+        return;
+    };
+    match anomaly {
+        Anomaly::OverLimit { limit } => tracing::warn!(
+            provider = %provider,
+            model = %model,
+            direction = direction.as_ref(),
+            bytes,
+            limit,
+            "body larger than the size limit"
+        ),
+        Anomaly::Spike { median } => tracing::warn!(
+            provider = %provider,
+            model = %model,
+            direction = direction.as_ref(),
+            bytes,
+            median,
+            "body size spiked"
+        ),
+    }
+    metrics.body_size_anomalies.add(
+        1,
+        &[
+            KeyValue::new("provider", provider),
+            KeyValue::new("model", model),
+            KeyValue::new("direction", direction.as_ref().to_string()),
+        ],
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(direction: Direction) -> (String, String, Direction) {
+        ("openai".to_string(), "gpt-4o".to_string(), direction)
+    }
+
+    #[test]
+    fn spikes_and_limits_are_anomalous() {
+        let anomalies = SizeAnomalies::new(SizeAnomalyConfig {
+            response_bytes: Some(10_000),
+            min_samples: 3,
+            ..Default::default()
+        });
+        for _ in 0..2 {
+            assert_eq!(anomalies.observe(key(Direction::Request), 1000), None);
+        }
+        // too few samples to know what's normal yet
+        assert_eq!(anomalies.observe(key(Direction::Request), 8000), None);
+        assert_eq!(anomalies.observe(key(Direction::Request), 3000), None);
+        assert_eq!(
+            anomalies.observe(key(Direction::Request), 5000),
+            Some(Anomaly::Spike { median: 1000 })
+        );
+        assert_eq!(
+            anomalies.observe(key(Direction::Response), 20_000),
+            Some(Anomaly::OverLimit { limit: 10_000 })
+        );
+    }
+}
@@ -0,0 +1,86 @@
+//! The labels of the request and response metrics.
+//!
+//! Every data point has every label of its metric, with [`NONE`] for labels
+//! which don't apply, so that series can be aggregated consistently. Values
+//! taken from requests, such as model names, are bounded by a
+//! [`LabelGuard`].
+use std::sync::{Arc, RwLock};
+
+use rustc_hash::FxHashSet as HashSet;
+
+/// The value of labels which don't apply to a request.
+pub const NONE: &str = "none";
+/// The value of labels whose number of distinct values exceeds their limit.
+pub const OTHER: &str = "other";
+
+const MAX_MODELS: usize = 256;
+const MAX_ROUTERS: usize = 256;
+const MAX_PROVIDERS: usize = 64;
+
+/// Bounds the number of distinct values of a label. The first `max` values
+/// are recorded as they are, and later ones as [`OTHER`].
+#[derive(Debug, Clone)]
+pub struct LabelGuard {
+    values: Arc<RwLock<HashSet<String>>>,
+    max: usize,
+}
+
+impl LabelGuard {
+    #[must_use]
+    pub fn new(max: usize) -> Self {
+        Self {
+            values: Arc::default(),
+            max,
+        }
+    }
+
+    #[must_use]
+    pub fn value(&self, value: &str) -> String {
+        let values = self.values.read().expect("label guard poisoned");
+        if values.contains(value) {
+            return value.to_string();
+        }
+        drop(values);
+        let mut values = self.values.write().expect("label guard poisoned");
+        if values.len() < self.max {
+            values.insert(value.to_string());
+            value.to_string()
+        } else if values.contains(value) {
+            value.to_string()
+        } else {
+            OTHER.to_string()
+        }
+    }
+}
+
+/// Guards of the labels of the request and response metrics.
+#[derive(Debug, Clone)]
+pub struct MetricLabels {
+    pub models: LabelGuard,
+    pub router_ids: LabelGuard,
+    pub providers: LabelGuard,
+}
+
+impl Default for MetricLabels {
+    fn default() -> Self {
+        Self {
+            models: LabelGuard::new(MAX_MODELS),
+            router_ids: LabelGuard::new(MAX_ROUTERS),
+            providers: LabelGuard::new(MAX_PROVIDERS),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn label_guard_bounds_values() {
+        let guard = LabelGuard::new(2);
+        assert_eq!(guard.value("gpt-4o"), "gpt-4o");
+        assert_eq!(guard.value("claude-3-5-sonnet"), "claude-3-5-sonnet");
+        assert_eq!(guard.value("gemini-2.0-flash"), OTHER);
+        assert_eq!(guard.value("gpt-4o"), "gpt-4o");
+    }
+}
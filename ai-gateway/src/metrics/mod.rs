@@ -1,12 +1,21 @@
 pub mod attribute_extractor;
+pub mod body_size;
+pub mod labels;
 pub mod request_count;
 pub mod rolling_counter;
+pub mod rolling_percentile;
+pub mod spend;
 pub mod system;
 pub mod tfft;
+pub mod throughput;
 
 use opentelemetry::metrics::{Counter, Gauge, Histogram, Meter, UpDownCounter};
+use telemetry::histogram::{self, HistogramBuckets};
 
-pub use self::rolling_counter::RollingCounter;
+pub use self::{
+    rolling_counter::RollingCounter, rolling_percentile::RollingPercentile,
+};
+use crate::metrics::labels::MetricLabels;
 
 /// The top level struct that contains all metrics
 /// which are exported to OpenTelemetry.
@@ -16,16 +25,87 @@ pub struct Metrics {
     pub provider_health: Gauge<u64>,
     pub auth_attempts: Counter<u64>,
     pub auth_rejections: Counter<u64>,
+    /// labels:
+    /// - `router_id`
+    /// - `provider`
     pub request_count: Counter<u64>,
+    /// labels:
+    /// - `provider`
+    /// - `model`
+    /// - `router_id`
+    /// - `stream`
+    /// - `provider_path`
+    /// - `status`
     pub response_count: Counter<u64>,
+    /// Bounds the values of the labels of `request_count` and
+    /// `response_count`.
+    pub labels: MetricLabels,
     pub tfft_duration: Histogram<f64>,
+    /// Time from receiving a request to the end of its response body.
+    ///
+    /// labels:
+    /// - `provider`
+    /// - `model`
+    /// - `path`
+    pub request_duration: Histogram<f64>,
+    /// Tokens per second of streamed responses, after the first token.
+    ///
+    /// labels:
+    /// - `provider`
+    /// - `model`
+    pub tokens_per_second: Histogram<f64>,
+    /// The most response chunks buffered at once for a sink of a response
+    /// body, such as the logger.
+    ///
+    /// labels:
+    /// - `sink`
+    pub body_sink_lag: Histogram<u64>,
+    /// How long a response body's stream waited for a sink to catch up.
+    ///
+    /// labels:
+    /// - `sink`
+    pub body_sink_blocked: Histogram<f64>,
+    /// labels:
+    /// - `provider`
+    /// - `model`
+    pub request_body_size: Histogram<u64>,
+    /// labels:
+    /// - `provider`
+    /// - `model`
+    pub response_body_size: Histogram<u64>,
+    /// labels:
+    /// - `provider`
+    /// - `model`
+    /// - `direction`: `request` or `response`
+    pub body_size_anomalies: Counter<u64>,
+    /// labels:
+    /// - `provider`
+    /// - `bulkhead`: `static` or `adaptive`
+    pub bulkhead_rejections: Counter<u64>,
+    /// labels:
+    /// - `provider`
+    pub adaptive_concurrency_limit: Gauge<u64>,
+    /// labels:
+    /// - `router_id`
+    /// - `outcome`: `allowed` or `exhausted`
+    pub retry_budget: Counter<u64>,
+    /// labels:
+    /// - `priority`
+    /// - `reason`: `in_flight`, `scheduler_delay` or `memory`
+    pub shed_requests: Counter<u64>,
+    /// labels:
+    /// - `scope`: `key` or `router`
+    pub spend_anomalies: Counter<u64>,
     pub cache: CacheMetrics,
+    pub differential: DifferentialMetrics,
+    pub stream_transforms: StreamTransformMetrics,
     pub routers: RouterMetrics,
+    pub logger: LoggerMetrics,
 }
 
 impl Metrics {
     #[must_use]
-    pub fn new(meter: &Meter) -> Self {
+    pub fn new(meter: &Meter, buckets: &HistogramBuckets) -> Self {
         let error_count = meter
             .u64_counter("error_count")
             .with_description("Number of error occurences")
@@ -51,12 +131,83 @@ impl Metrics {
             .with_description("Number of successful responses")
             .build();
         let tfft_duration = meter
-            .f64_histogram("tfft_duration")
+            .f64_histogram(histogram::TFFT_DURATION)
             .with_unit("ms")
             .with_description("Time to first token duration")
+            .with_boundaries(histogram::boundaries(&buckets.tfft_duration))
+            .build();
+        let request_duration = meter
+            .f64_histogram(histogram::REQUEST_DURATION)
+            .with_unit("ms")
+            .with_description("Time to the end of response bodies")
+            .with_boundaries(histogram::boundaries(&buckets.request_duration))
+            .build();
+        let tokens_per_second = meter
+            .f64_histogram("tokens_per_second")
+            .with_unit("{token}/s")
+            .with_description("Tokens per second of streamed responses")
+            .build();
+        let body_sink_lag = meter
+            .u64_histogram("body_sink_lag")
+            .with_unit("{chunk}")
+            .with_description(
+                "Most response chunks buffered at once for a body sink",
+            )
+            .build();
+        let body_sink_blocked = meter
+            .f64_histogram("body_sink_blocked")
+            .with_unit("ms")
+            .with_description("Time response streams waited for a body sink")
+            .build();
+        let request_body_size = meter
+            .u64_histogram("request_body_size")
+            .with_unit("By")
+            .with_description("Size of request bodies sent to providers")
+            .build();
+        let response_body_size = meter
+            .u64_histogram("response_body_size")
+            .with_unit("By")
+            .with_description("Size of response bodies received from providers")
+            .build();
+        let body_size_anomalies = meter
+            .u64_counter("body_size_anomalies")
+            .with_description(
+                "Number of unusually large request and response bodies",
+            )
+            .build();
+        let bulkhead_rejections = meter
+            .u64_counter("bulkhead_rejections")
+            .with_description(
+                "Number of requests rejected due to a full provider bulkhead",
+            )
+            .build();
+        let adaptive_concurrency_limit = meter
+            .u64_gauge("adaptive_concurrency_limit")
+            .with_description("Current adaptive concurrency limit per provider")
+            .build();
+        let retry_budget = meter
+            .u64_counter("retry_budget")
+            .with_description(
+                "Number of retries withdrawn from router retry budgets",
+            )
+            .build();
+        let shed_requests = meter
+            .u64_counter("shed_requests")
+            .with_description(
+                "Number of requests rejected because the gateway is overloaded",
+            )
+            .build();
+        let spend_anomalies = meter
+            .u64_counter("spend_anomalies")
+            .with_description(
+                "Number of API keys and routers frozen after their spend rose",
+            )
             .build();
         let cache = CacheMetrics::new(meter);
+        let differential = DifferentialMetrics::new(meter);
+        let stream_transforms = StreamTransformMetrics::new(meter);
         let routers = RouterMetrics::new(meter);
+        let logger = LoggerMetrics::new(meter);
         Self {
             error_count,
             provider_health,
@@ -64,9 +215,25 @@ impl Metrics {
             auth_rejections,
             request_count,
             response_count,
+            labels: MetricLabels::default(),
             tfft_duration,
+            request_duration,
+            tokens_per_second,
+            body_sink_lag,
+            body_sink_blocked,
+            request_body_size,
+            response_body_size,
+            body_size_anomalies,
+            bulkhead_rejections,
+            adaptive_concurrency_limit,
+            retry_budget,
+            shed_requests,
+            spend_anomalies,
             cache,
+            differential,
+            stream_transforms,
             routers,
+            logger,
         }
     }
 }
@@ -101,6 +268,173 @@ impl CacheMetrics {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct LoggerMetrics {
+    /// Bytes of response bodies held in memory until they're logged.
+    pub buffered_bytes: UpDownCounter<i64>,
+    pub spilled_bodies: Counter<u64>,
+    /// Logs waiting to be delivered.
+    pub queued_logs: UpDownCounter<i64>,
+    /// labels:
+    /// - `policy`: `drop-oldest` or `drop-new`
+    pub dropped_logs: Counter<u64>,
+    /// labels:
+    /// - `batched`
+    pub delivery_latency: Histogram<f64>,
+    pub batch_size: Histogram<u64>,
+    /// Logs in the dead-letter queue.
+    pub dead_letters: UpDownCounter<i64>,
+    /// labels:
+    /// - `success`
+    pub dead_letter_retries: Counter<u64>,
+    /// Requests in flight during a crash, found in the request journal.
+    pub unknown_outcomes: Counter<u64>,
+}
+
+impl LoggerMetrics {
+    #[must_use]
+    pub fn new(meter: &Meter) -> Self {
+        let buffered_bytes = meter
+            .i64_up_down_counter("logger_buffered_bytes")
+            .with_unit("By")
+            .with_description(
+                "Bytes of response bodies held in memory for logging",
+            )
+            .build();
+        let spilled_bodies = meter
+            .u64_counter("logger_spilled_bodies")
+            .with_description(
+                "Number of response bodies written to disk for logging",
+            )
+            .build();
+        let queued_logs = meter
+            .i64_up_down_counter("logger_queued_logs")
+            .with_description("Number of logs waiting to be delivered")
+            .build();
+        let dropped_logs = meter
+            .u64_counter("logger_dropped_logs")
+            .with_description("Number of logs dropped due to a full queue")
+            .build();
+        let delivery_latency = meter
+            .f64_histogram("logger_delivery_latency")
+            .with_unit("ms")
+            .with_description("Time taken to send logs to Helicone")
+            .build();
+        let batch_size = meter
+            .u64_histogram("logger_batch_size")
+            .with_description("Number of logs sent to Helicone per batch")
+            .build();
+        let dead_letters = meter
+            .i64_up_down_counter("logger_dead_letters")
+            .with_description(
+                "Number of logs in the dead-letter queue waiting for a retry",
+            )
+            .build();
+        let dead_letter_retries = meter
+            .u64_counter("logger_dead_letter_retries")
+            .with_description(
+                "Number of retried deliveries of logs in the dead-letter queue",
+            )
+            .build();
+        let unknown_outcomes = meter
+            .u64_counter("logger_unknown_outcomes")
+            .with_description(
+                "Number of requests in flight during a crash of the gateway",
+            )
+            .build();
+        Self {
+            buffered_bytes,
+            spilled_bodies,
+            queued_logs,
+            dropped_logs,
+            delivery_latency,
+            batch_size,
+            dead_letters,
+            dead_letter_retries,
+            unknown_outcomes,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DifferentialMetrics {
+    /// labels:
+    /// - `model`
+    /// - `candidate_model`
+    /// - `matched`
+    pub comparisons: Counter<u64>,
+    /// labels:
+    /// - `model`
+    /// - `candidate_model`
+    pub errors: Counter<u64>,
+    /// labels:
+    /// - `model`
+    /// - `candidate_model`
+    pub scores: Histogram<f64>,
+}
+
+impl DifferentialMetrics {
+    #[must_use]
+    pub fn new(meter: &Meter) -> Self {
+        let comparisons = meter
+            .u64_counter("differential_comparisons")
+            .with_description(
+                "Number of responses compared with a candidate model's",
+            )
+            .build();
+        let errors = meter
+            .u64_counter("differential_errors")
+            .with_description(
+                "Number of candidate model requests that could not be compared",
+            )
+            .build();
+        let scores = meter
+            .f64_histogram("differential_scores")
+            .with_description(
+                "Comparison score, from 0 to 1, of responses with those of a \
+                 candidate model",
+            )
+            .build();
+        Self {
+            comparisons,
+            errors,
+            scores,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StreamTransformMetrics {
+    /// labels:
+    /// - `transform`
+    pub duration: Histogram<f64>,
+    /// labels:
+    /// - `transform`
+    pub budget_exceeded: Counter<u64>,
+}
+
+impl StreamTransformMetrics {
+    #[must_use]
+    pub fn new(meter: &Meter) -> Self {
+        let duration = meter
+            .f64_histogram("stream_transform_duration")
+            .with_unit("ms")
+            .with_description("Time spent transforming a streamed chunk")
+            .build();
+        let budget_exceeded = meter
+            .u64_counter("stream_transform_budget_exceeded")
+            .with_description(
+                "Number of stream transforms bypassed for exceeding their \
+                 latency budget",
+            )
+            .build();
+        Self {
+            duration,
+            budget_exceeded,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RouterMetrics {
     /// labels:
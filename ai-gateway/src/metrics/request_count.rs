@@ -1,14 +1,22 @@
 use std::{
     future::Future,
     pin::Pin,
+    str::FromStr,
     task::{Context, Poll},
 };
 
 use futures::ready;
+use opentelemetry::KeyValue;
 use tower_otel_http_metrics::ResponseAttributeExtractor;
 
 use crate::{
-    app_state::AppState, metrics::attribute_extractor::AttributeExtractor,
+    app_state::AppState,
+    config::providers::ProvidersConfig,
+    metrics::{
+        attribute_extractor::AttributeExtractor,
+        labels::{MetricLabels, NONE},
+    },
+    types::provider::InferenceProvider,
 };
 
 #[derive(Clone)]
@@ -60,8 +68,15 @@ where
         let response = ready!(this.inner.poll(cx));
         match response {
             Ok(resp) => {
-                let attributes = AttributeExtractor.extract_attributes(&resp);
-                this.app_state.0.metrics.response_count.add(1, &attributes);
+                let metrics = &this.app_state.0.metrics;
+                let mut attributes =
+                    AttributeExtractor::new(metrics.labels.clone())
+                        .extract_attributes(&resp);
+                attributes.push(KeyValue::new(
+                    "status",
+                    i64::from(resp.status().as_u16()),
+                ));
+                metrics.response_count.add(1, &attributes);
                 Poll::Ready(Ok(resp))
             }
             Err(e) => Poll::Ready(Err(e)),
@@ -69,9 +84,12 @@ where
     }
 }
 
-impl<S, Request, RespBody> tower::Service<Request> for Service<S>
+impl<S, ReqBody, RespBody> tower::Service<http::Request<ReqBody>> for Service<S>
 where
-    S: tower::Service<Request, Response = http::Response<RespBody>>,
+    S: tower::Service<
+            http::Request<ReqBody>,
+            Response = http::Response<RespBody>,
+        >,
 {
     type Response = S::Response;
     type Error = S::Error;
@@ -84,8 +102,14 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, request: Request) -> Self::Future {
-        self.app_state.0.metrics.request_count.add(1, &[]);
+    fn call(&mut self, request: http::Request<ReqBody>) -> Self::Future {
+        let metrics = &self.app_state.0.metrics;
+        let attributes = request_attributes(
+            &metrics.labels,
+            &self.app_state.config().providers,
+            request.uri().path(),
+        );
+        metrics.request_count.add(1, &attributes);
         let inner = self.inner.call(request);
         ResponseFuture {
             inner,
@@ -93,3 +117,59 @@ where
         }
     }
 }
+
+/// The labels of requests, which haven't been routed yet and are therefore
+/// taken from the path:
+/// - `router_id`: for `/router/{id}` requests
+/// - `provider`: for direct proxy requests to configured providers
+fn request_attributes(
+    labels: &MetricLabels,
+    providers: &ProvidersConfig,
+    path: &str,
+) -> [KeyValue; 2] {
+    let mut segments = path.trim_start_matches('/').split('/');
+    let first_segment = segments.next().unwrap_or_default();
+    let mut router_id = NONE.to_string();
+    let mut provider = NONE.to_string();
+    match first_segment {
+        "router" => {
+            if let Some(id) = segments.next().filter(|id| !id.is_empty()) {
+                router_id = labels.router_ids.value(id);
+            }
+        }
+        "ai" => {}
+        first_segment => {
+            let Ok(inference_provider) =
+                InferenceProvider::from_str(first_segment);
+            if providers.contains_key(&inference_provider) {
+                provider = labels.providers.value(first_segment);
+            }
+        }
+    }
+    [
+        KeyValue::new("router_id", router_id),
+        KeyValue::new("provider", provider),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_attributes_from_path() {
+        let labels = MetricLabels::default();
+        let providers = ProvidersConfig::default();
+        let attributes = |path| {
+            request_attributes(&labels, &providers, path)
+                .map(|attribute| attribute.value.to_string())
+        };
+        assert_eq!(
+            attributes("/router/my-router/chat/completions"),
+            ["my-router", NONE]
+        );
+        assert_eq!(attributes("/ai/chat/completions"), [NONE, NONE]);
+        assert_eq!(attributes("/openai/v1/chat/completions"), [NONE, "openai"]);
+        assert_eq!(attributes("/health"), [NONE, NONE]);
+    }
+}
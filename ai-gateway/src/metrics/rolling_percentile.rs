@@ -0,0 +1,129 @@
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Upper bound on the number of samples retained per window, so that a burst
+/// of traffic can't grow the sample buffer without bound.
+const MAX_SAMPLES: usize = 4096;
+
+/// Tracks samples, durations by default, over a rolling time window and
+/// computes percentiles over the samples currently in the window.
+#[derive(Debug)]
+pub struct RollingPercentile<T = Duration> {
+    window: Duration,
+    samples: Mutex<VecDeque<(Instant, T)>>,
+}
+
+impl<T: Copy + Ord> RollingPercentile<T> {
+    #[must_use]
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            samples: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    pub fn record(&self, value: T) {
+        let now = Instant::now();
+        let mut samples = self
+            .samples
+            .lock()
+            .expect("rolling percentile lock poisoned");
+        Self::evict_expired(&mut samples, now, self.window);
+        if samples.len() >= MAX_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back((now, value));
+    }
+
+    /// Number of samples currently in the window.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        let mut samples = self
+            .samples
+            .lock()
+            .expect("rolling percentile lock poisoned");
+        Self::evict_expired(&mut samples, Instant::now(), self.window);
+        samples.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the given percentile (in `0.0..=1.0`) of the samples in the
+    /// window, or `None` if there are no samples.
+    #[must_use]
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn percentile(&self, percentile: f64) -> Option<T> {
+        let mut samples = self
+            .samples
+            .lock()
+            .expect("rolling percentile lock poisoned");
+        Self::evict_expired(&mut samples, Instant::now(), self.window);
+        if samples.is_empty() {
+            return None;
+        }
+        let mut values =
+            samples.iter().map(|(_, value)| *value).collect::<Vec<_>>();
+        drop(samples);
+        values.sort_unstable();
+        let percentile = percentile.clamp(0.0, 1.0);
+        let rank = (percentile * values.len() as f64).ceil() as usize;
+        let idx = rank.saturating_sub(1).min(values.len() - 1);
+        Some(values[idx])
+    }
+
+    fn evict_expired(
+        samples: &mut VecDeque<(Instant, T)>,
+        now: Instant,
+        window: Duration,
+    ) {
+        while let Some((recorded_at, _)) = samples.front() {
+            if now.duration_since(*recorded_at) > window {
+                samples.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let tracker = RollingPercentile::new(Duration::from_secs(60));
+        assert_eq!(tracker.percentile(0.95), None);
+        for ms in 1..=100 {
+            tracker.record(Duration::from_millis(ms));
+        }
+        assert_eq!(tracker.len(), 100);
+        assert_eq!(tracker.percentile(0.95), Some(Duration::from_millis(95)));
+        assert_eq!(tracker.percentile(0.5), Some(Duration::from_millis(50)));
+        assert_eq!(tracker.percentile(1.0), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn test_expiry() {
+        let tracker = RollingPercentile::new(Duration::from_millis(50));
+        for _ in 0..10 {
+            tracker.record(Duration::from_secs(1));
+        }
+        assert_eq!(tracker.len(), 10);
+        thread::sleep(Duration::from_millis(70));
+        assert!(tracker.is_empty());
+        assert_eq!(tracker.percentile(0.95), None);
+    }
+}
@@ -0,0 +1,337 @@
+//! Spend of API keys and routers, and automatic freezes of those whose
+//! spend suddenly rises.
+//!
+//! Each response's spend is estimated from the usage its provider reported
+//! and the prices of its model's capabilities, so responses of models
+//! without known prices aren't counted. Spend is summed per window, and a
+//! window is anomalous if its spend is more than the configured factor
+//! times the average spend per window of the trailing baseline. The API key
+//! or router is then frozen until an operator lifts its freeze through the
+//! admin API.
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use opentelemetry::KeyValue;
+use rust_decimal::Decimal;
+use rustc_hash::FxHashMap as HashMap;
+
+use crate::{
+    app_state::AppState,
+    config::{
+        model_capabilities::ModelCapability, router::RouterConfig,
+        spend_anomaly::SpendAnomalyConfig,
+    },
+    control_plane::types::hash_key,
+    discover::monitor::webhook::Event,
+    logger::usage::Usage,
+    types::{extensions::RequestContext, model_id::ModelId, router::RouterId},
+    utils::freeze::Freeze,
+};
+
+const TOKENS_PER_MTOK: u64 = 1_000_000;
+
+/// The estimated cost in USD of `usage` of a model with `capability`.
+#[must_use]
+pub fn cost(capability: &ModelCapability, usage: &Usage) -> Decimal {
+    let output_cost_per_mtok = capability
+        .output_cost_per_mtok
+        .unwrap_or(capability.input_cost_per_mtok);
+    (Decimal::from(usage.prompt_tokens) * capability.input_cost_per_mtok
+        + Decimal::from(usage.completion_tokens) * output_cost_per_mtok)
+        / Decimal::from(TOKENS_PER_MTOK)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Subject {
+    /// The hash of an API key.
+    Key(String),
+    Router(RouterId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Anomaly {
+    spend: Decimal,
+    baseline: Decimal,
+}
+
+#[derive(Debug)]
+struct History {
+    first_window: u64,
+    /// The spend of each window with any, oldest first.
+    windows: VecDeque<(u64, Decimal)>,
+    /// The last window that was anomalous, so that it's only frozen once.
+    anomalous: Option<u64>,
+}
+
+/// Recent spend, per API key and router.
+#[derive(Debug)]
+pub struct SpendAnomalies {
+    config: SpendAnomalyConfig,
+    start: Instant,
+    history: Mutex<HashMap<Subject, History>>,
+}
+
+impl SpendAnomalies {
+    #[must_use]
+    pub fn new(config: SpendAnomalyConfig) -> Self {
+        Self {
+            config,
+            start: Instant::now(),
+            history: Mutex::default(),
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn window_at(&self, now: Instant) -> u64 {
+        (now.duration_since(self.start).as_nanos()
+            / self.config.window.as_nanos().max(1)) as u64
+    }
+
+    #[allow(clippy::cast_possible_truncation)]
+    fn baseline_windows(&self) -> u64 {
+        (self
+            .config
+            .baseline
+            .as_nanos()
+            .div_ceil(self.config.window.as_nanos().max(1)) as u64)
+            .max(1)
+    }
+
+    /// Adds `cost` to the spend of `subject`, returning the spend of the
+    /// window and its baseline if the window just became anomalous.
+    fn observe(
+        &self,
+        subject: Subject,
+        cost: Decimal,
+        now: Instant,
+    ) -> Option<Anomaly> {
+        let window = self.window_at(now);
+        let baseline_windows = self.baseline_windows();
+        let mut history =
+            self.history.lock().expect("spend anomalies lock poisoned");
+        let history = history.entry(subject).or_insert_with(|| History {
+            first_window: window,
+            windows: VecDeque::new(),
+            anomalous: None,
+        });
+        while history
+            .windows
+            .front()
+            .is_some_and(|(start, _)| start + baseline_windows < window)
+        {
+            history.windows.pop_front();
+        }
+        let spend = match history.windows.back_mut() {
+            Some((start, spend)) if *start == window => {
+                *spend += cost;
+                *spend
+            }
+            _ => {
+                history.windows.push_back((window, cost));
+                cost
+            }
+        };
+        if window - history.first_window < baseline_windows
+            || history.anomalous == Some(window)
+            || spend < self.config.min_spend
+        {
+            return None;
+        }
+        let baseline = history
+            .windows
+            .iter()
+            .filter(|(start, _)| *start != window)
+            .map(|(_, spend)| *spend)
+            .sum::<Decimal>()
+            / Decimal::from(baseline_windows);
+        if spend <= self.config.factor * baseline {
+            return None;
+        }
+        history.anomalous = Some(window);
+        Some(Anomaly { spend, baseline })
+    }
+}
+
+/// The API key and router a response's spend is attributed to.
+#[derive(Debug, Clone)]
+pub struct SpendScope {
+    key_hash: Option<String>,
+    router: Option<(RouterId, Option<Arc<RouterConfig>>)>,
+}
+
+impl SpendScope {
+    /// The scope of a request, or `None` if spend anomalies aren't
+    /// configured.
+    #[must_use]
+    pub fn new(
+        app_state: &AppState,
+        req_ctx: &RequestContext,
+        router_id: Option<&RouterId>,
+    ) -> Option<Self> {
+        app_state.0.spend_anomalies.as_ref()?;
+        Some(Self {
+            key_hash: req_ctx
+                .auth_context
+                .as_ref()
+                .map(|auth_ctx| hash_key(auth_ctx.api_key.expose())),
+            router: router_id.map(|router_id| {
+                (router_id.clone(), req_ctx.router_config.clone())
+            }),
+        })
+    }
+
+    /// Records the spend of a response with `usage` of `model`, and freezes
+    /// the API key or router if its spend is anomalous.
+    pub fn record(
+        &self,
+        app_state: &AppState,
+        model: Option<&ModelId>,
+        usage: &Usage,
+    ) {
+        let Some(spend_anomalies) = &app_state.0.spend_anomalies else {
+            return;
+        };
+        let Some(capability) = model
+            .and_then(|model| app_state.config().model_capabilities.get(model))
+        else {
+            return;
+        };
+        let cost = cost(capability, usage);
+        if cost.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        if let Some(key_hash) = &self.key_hash
+            && let Some(anomaly) = spend_anomalies.observe(
+                Subject::Key(key_hash.clone()),
+                cost,
+                now,
+            )
+        {
+            self.freeze(app_state, Some(key_hash), anomaly);
+        }
+        if let Some((router_id, _)) = &self.router
+            && let Some(anomaly) = spend_anomalies.observe(
+                Subject::Router(router_id.clone()),
+                cost,
+                now,
+            )
+        {
+            self.freeze(app_state, None, anomaly);
+        }
+    }
+
+    /// Freezes the API key of `key_hash`, or the router if `None`.
+    fn freeze(
+        &self,
+        app_state: &AppState,
+        key_hash: Option<&String>,
+        anomaly: Anomaly,
+    ) {
+        let router_id = self.router.as_ref().map(|(router_id, _)| router_id);
+        tracing::warn!(
+            key = key_hash.map(|key| key.get(..8).unwrap_or(key)),
+            router_id = router_id.map(tracing::field::display),
+            spend = %anomaly.spend,
+            baseline = %anomaly.baseline,
+            "spend anomaly, freezing"
+        );
+        let scope = if key_hash.is_some() { "key" } else { "router" };
+        app_state
+            .0
+            .metrics
+            .spend_anomalies
+            .add(1, &[KeyValue::new("scope", scope)]);
+        app_state.0.freezes.add(Freeze {
+            message: Some(format!(
+                "Frozen after the spend of this {scope} rose"
+            )),
+            router: router_id.filter(|_| key_hash.is_none()).cloned(),
+            key: key_hash.cloned(),
+            ..Freeze::default()
+        });
+        if let Some((router_id, Some(router_config))) = &self.router
+            && let Some(webhooks) = app_state.webhooks(router_id, router_config)
+        {
+            webhooks.notify(Event::SpendAnomaly {
+                key: key_hash.cloned(),
+                spend: anomaly.spend,
+                baseline: anomaly.baseline,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn key() -> Subject {
+        Subject::Key("hash".to_string())
+    }
+
+    #[test]
+    fn spend_past_the_baseline_is_anomalous_once() {
+        let anomalies = SpendAnomalies::new(SpendAnomalyConfig {
+            window: Duration::from_secs(60),
+            baseline: Duration::from_secs(5 * 60),
+            ..Default::default()
+        });
+        let minute = |n| anomalies.start + Duration::from_secs(60 * n);
+        for n in 0..5 {
+            assert_eq!(
+                anomalies.observe(key(), Decimal::from(2), minute(n)),
+                None
+            );
+        }
+        // the baseline is 2 per window
+        assert_eq!(anomalies.observe(key(), Decimal::from(9), minute(5)), None);
+        assert_eq!(
+            anomalies.observe(key(), Decimal::from(2), minute(5)),
+            Some(Anomaly {
+                spend: Decimal::from(11),
+                baseline: Decimal::from(2),
+            })
+        );
+        assert_eq!(anomalies.observe(key(), Decimal::from(2), minute(5)), None);
+        // spend below the minimum is never anomalous
+        let subject = Subject::Router(RouterId::Named("my-router".into()));
+        anomalies.observe(subject.clone(), Decimal::ZERO, minute(0));
+        assert_eq!(
+            anomalies.observe(subject, Decimal::new(5, 1), minute(5)),
+            None
+        );
+    }
+
+    #[test]
+    fn spend_is_not_anomalous_while_warming_up() {
+        let anomalies = SpendAnomalies::new(SpendAnomalyConfig::default());
+        assert_eq!(
+            anomalies.observe(key(), Decimal::from(100), Instant::now()),
+            None
+        );
+    }
+
+    #[test]
+    fn cost_uses_the_output_price_if_known() {
+        let mut capability = ModelCapability {
+            context_window: 128_000,
+            input_cost_per_mtok: Decimal::from(2),
+            output_cost_per_mtok: None,
+            logprobs: false,
+        };
+        let usage = Usage {
+            requests: 1,
+            prompt_tokens: 500_000,
+            completion_tokens: 250_000,
+        };
+        assert_eq!(cost(&capability, &usage), Decimal::new(15, 1));
+        capability.output_cost_per_mtok = Some(Decimal::from(8));
+        assert_eq!(cost(&capability, &usage), Decimal::from(3));
+    }
+}
@@ -1,10 +1,77 @@
+//! Metrics of the gateway process: CPU and memory usage from
+//! `opentelemetry_system_metrics`, plus the Tokio runtime and file
+//! descriptor metrics needed to diagnose saturation.
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
 use futures::future::BoxFuture;
 use meltdown::Token;
+use opentelemetry::metrics::{Gauge, Histogram, Meter};
+use tokio::{
+    runtime::Handle,
+    time::{Instant, MissedTickBehavior},
+};
 use tracing::error;
 
-use crate::error::{init::InitError, runtime::RuntimeError};
+use crate::{
+    error::{init::InitError, runtime::RuntimeError},
+    utils::task,
+};
+
+/// How often the runtime and file descriptor metrics are sampled.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The latest samples of the system's pressure, for shedding load before
+/// the process degrades.
+#[derive(Debug, Clone, Default)]
+pub struct SystemPressure(Arc<PressureSamples>);
+
+#[derive(Debug, Default)]
+struct PressureSamples {
+    scheduler_delay_us: AtomicU64,
+    resident_memory: AtomicU64,
+}
+
+impl SystemPressure {
+    /// How late the runtime woke the sampling task the last time.
+    #[must_use]
+    pub fn scheduler_delay(&self) -> Duration {
+        Duration::from_micros(self.0.scheduler_delay_us.load(Ordering::Relaxed))
+    }
+
+    /// The resident memory of the process in bytes, or 0 if unknown.
+    #[must_use]
+    pub fn resident_memory(&self) -> u64 {
+        self.0.resident_memory.load(Ordering::Relaxed)
+    }
+
+    fn record_scheduler_delay(&self, delay: Duration) {
+        self.0.scheduler_delay_us.store(
+            u64::try_from(delay.as_micros()).unwrap_or(u64::MAX),
+            Ordering::Relaxed,
+        );
+    }
+
+    fn record_resident_memory(&self, bytes: u64) {
+        self.0.resident_memory.store(bytes, Ordering::Relaxed);
+    }
+}
+
+pub struct SystemMetrics {
+    pressure: SystemPressure,
+}
 
-pub struct SystemMetrics;
+impl SystemMetrics {
+    #[must_use]
+    pub fn new(pressure: SystemPressure) -> Self {
+        Self { pressure }
+    }
+}
 
 impl meltdown::Service for SystemMetrics {
     type Future = BoxFuture<'static, Result<(), RuntimeError>>;
@@ -12,16 +79,20 @@ impl meltdown::Service for SystemMetrics {
     fn run(self, mut token: Token) -> Self::Future {
         let provider = opentelemetry::global::meter_provider();
         let system_metrics = provider.meter("system-metrics");
+        let runtime_metrics =
+            RuntimeMetrics::new(&system_metrics, self.pressure);
         Box::pin(async move {
             // TODO: is sysinfo blocking? might want to spawn this in a thread
             // pool instead
-            let mut handle = tokio::task::spawn(async {
+            let mut handle = task::spawn("system-metrics", async {
                 opentelemetry_system_metrics::init_process_observer(
                     system_metrics,
                 )
                 .await
                 .map_err(|_| InitError::InitSystemMetrics)
             });
+            let mut runtime_handle =
+                task::spawn("runtime-metrics", runtime_metrics.run());
 
             tokio::select! {
                 result = &mut handle => {
@@ -30,11 +101,20 @@ impl meltdown::Service for SystemMetrics {
                     } else {
                         tracing::debug!(name = "system-metrics-task", "System metrics task shut down successfully");
                     }
+                    runtime_handle.abort();
+                    token.trigger();
+                }
+                result = &mut runtime_handle => {
+                    if let Err(e) = result {
+                        error!(name = "runtime-metrics-task", error = ?e, "Runtime metrics task encountered error, shutting down");
+                    }
+                    handle.abort();
                     token.trigger();
                 }
                 () = &mut token => {
                     tracing::debug!(name = "system-metrics-task", "Shutdown signal received, aborting system metrics task");
                     handle.abort();
+                    runtime_handle.abort();
                     token.trigger();
                 }
             }
@@ -42,3 +122,188 @@ impl meltdown::Service for SystemMetrics {
         })
     }
 }
+
+/// Samples the Tokio runtime and the process' file descriptors every
+/// [`SAMPLE_INTERVAL`].
+struct RuntimeMetrics {
+    /// The fraction of time the runtime's workers spent polling tasks since
+    /// the previous sample, from 0 to 1.
+    worker_busy_ratio: Gauge<f64>,
+    /// How late the sampling task was woken, in milliseconds, which
+    /// approximates how long ready tasks wait to be polled.
+    scheduler_delay: Histogram<f64>,
+    /// The number of tasks alive in the runtime.
+    alive_tasks: Gauge<u64>,
+    /// The number of tasks in the runtime's global queue.
+    global_queue_depth: Gauge<u64>,
+    /// The number of file descriptors open by the process.
+    open_fds: Gauge<u64>,
+    /// The number of sockets open by the process.
+    open_sockets: Gauge<u64>,
+    pressure: SystemPressure,
+}
+
+impl RuntimeMetrics {
+    fn new(meter: &Meter, pressure: SystemPressure) -> Self {
+        Self {
+            worker_busy_ratio: meter
+                .f64_gauge("tokio_worker_busy_ratio")
+                .with_description(
+                    "Fraction of time the runtime workers spent polling tasks",
+                )
+                .build(),
+            scheduler_delay: meter
+                .f64_histogram("tokio_scheduler_delay")
+                .with_unit("ms")
+                .with_description(
+                    "How late a periodic task was woken by the runtime",
+                )
+                .build(),
+            alive_tasks: meter
+                .u64_gauge("tokio_alive_tasks")
+                .with_description("Number of tasks alive in the runtime")
+                .build(),
+            global_queue_depth: meter
+                .u64_gauge("tokio_global_queue_depth")
+                .with_description(
+                    "Number of tasks in the runtime's global queue",
+                )
+                .build(),
+            open_fds: meter
+                .u64_gauge("process_open_fds")
+                .with_description("Number of open file descriptors")
+                .build(),
+            open_sockets: meter
+                .u64_gauge("process_open_sockets")
+                .with_description("Number of open sockets")
+                .build(),
+            pressure,
+        }
+    }
+
+    async fn run(self) {
+        let runtime = Handle::current().metrics();
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut previous_busy = total_busy_duration(&runtime);
+        let mut previous_sample = Instant::now();
+        loop {
+            let scheduled = interval.tick().await;
+            let now = Instant::now();
+            let delay = now.saturating_duration_since(scheduled);
+            self.scheduler_delay
+                .record(delay.as_secs_f64() * 1000.0, &[]);
+            self.pressure.record_scheduler_delay(delay);
+
+            let busy = total_busy_duration(&runtime);
+            let elapsed = now.saturating_duration_since(previous_sample);
+            if let Some(ratio) = busy_ratio(
+                busy.saturating_sub(previous_busy),
+                elapsed,
+                runtime.num_workers(),
+            ) {
+                self.worker_busy_ratio.record(ratio, &[]);
+            }
+            previous_busy = busy;
+            previous_sample = now;
+
+            self.alive_tasks
+                .record(runtime.num_alive_tasks() as u64, &[]);
+            self.global_queue_depth
+                .record(runtime.global_queue_depth() as u64, &[]);
+
+            let samples = tokio::task::spawn_blocking(|| {
+                (FdCounts::read(), resident_memory())
+            });
+            match samples.await {
+                Ok((counts, memory)) => {
+                    if let Some(counts) = counts {
+                        self.open_fds.record(counts.fds, &[]);
+                        self.open_sockets.record(counts.sockets, &[]);
+                    }
+                    if let Some(memory) = memory {
+                        self.pressure.record_resident_memory(memory);
+                    }
+                }
+                Err(e) => {
+                    error!(error = %e, "failed to sample process resources");
+                }
+            }
+        }
+    }
+}
+
+fn total_busy_duration(runtime: &tokio::runtime::RuntimeMetrics) -> Duration {
+    (0..runtime.num_workers())
+        .map(|worker| runtime.worker_total_busy_duration(worker))
+        .sum()
+}
+
+/// The fraction of `elapsed` that `workers` spent busy, if any time elapsed.
+#[allow(clippy::cast_precision_loss)]
+fn busy_ratio(
+    busy: Duration,
+    elapsed: Duration,
+    workers: usize,
+) -> Option<f64> {
+    let capacity = elapsed.as_secs_f64() * workers as f64;
+    if capacity > 0.0 {
+        Some((busy.as_secs_f64() / capacity).min(1.0))
+    } else {
+        None
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct FdCounts {
+    fds: u64,
+    sockets: u64,
+}
+
+impl FdCounts {
+    /// Counts the process' file descriptors from `/proc/self/fd`, returning
+    /// `None` on platforms without it.
+    fn read() -> Option<Self> {
+        let entries = std::fs::read_dir("/proc/self/fd").ok()?;
+        let mut counts = Self::default();
+        for entry in entries.flatten() {
+            counts.fds += 1;
+            let is_socket =
+                std::fs::read_link(entry.path()).is_ok_and(|target| {
+                    target.to_string_lossy().starts_with("socket:")
+                });
+            if is_socket {
+                counts.sockets += 1;
+            }
+        }
+        Some(counts)
+    }
+}
+
+/// Reads the resident memory of the process in bytes from
+/// `/proc/self/status`, returning `None` on platforms without it.
+fn resident_memory() -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    let kilobytes = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<u64>()
+        .ok()?;
+    Some(kilobytes * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn busy_ratio_is_bounded() {
+        let second = Duration::from_secs(1);
+        assert_eq!(busy_ratio(second, second, 4), Some(0.25));
+        assert_eq!(busy_ratio(second * 8, second, 4), Some(1.0));
+        assert_eq!(busy_ratio(second, Duration::ZERO, 4), None);
+    }
+}
@@ -5,12 +5,16 @@ use std::{
 };
 
 use futures::ready;
+use opentelemetry::KeyValue;
 use pin_project_lite::pin_project;
+use telemetry::{exemplar, histogram};
 use tokio::{
     sync::oneshot::{self, error::RecvError},
     time::Instant,
 };
 
+use crate::metrics::Metrics;
+
 pin_project! {
     pub struct TFFTFuture {
         start_time: Instant,
@@ -40,3 +44,31 @@ impl Future for TFFTFuture {
         }
     }
 }
+
+/// Records `tfft` on the `tfft_duration` histogram, with the trace of the
+/// current span as its exemplar.
+pub fn record_tfft(metrics: &Metrics, tfft: Duration, attributes: &[KeyValue]) {
+    #[allow(clippy::cast_precision_loss)]
+    exemplar::record(
+        &metrics.tfft_duration,
+        histogram::TFFT_DURATION,
+        tfft.as_millis() as f64,
+        attributes,
+    );
+}
+
+/// Records `duration` on the `request_duration` histogram, with the trace of
+/// the current span as its exemplar.
+pub fn record_request_duration(
+    metrics: &Metrics,
+    duration: Duration,
+    attributes: &[KeyValue],
+) {
+    #[allow(clippy::cast_precision_loss)]
+    exemplar::record(
+        &metrics.request_duration,
+        histogram::REQUEST_DURATION,
+        duration.as_millis() as f64,
+        attributes,
+    );
+}
@@ -0,0 +1,184 @@
+//! Tokens per second of streamed responses.
+//!
+//! A stream's throughput is its completion tokens over the time between its
+//! first and last chunk, so that it measures how fast tokens are generated
+//! independently of the time to first token. Completion tokens are taken
+//! from the usage the provider reported, or else counted from the chunk
+//! deltas, at roughly a token per delta.
+use std::{
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use opentelemetry::KeyValue;
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::Value;
+
+use crate::{
+    app_state::AppState,
+    logger::usage::Usage,
+    types::{model_id::ModelId, provider::InferenceProvider},
+};
+
+/// The weight of a new sample in the moving average of a model's
+/// throughput.
+const SMOOTHING: f64 = 0.2;
+
+/// The tokens per second of a stream whose body is `body`, or `None` if it
+/// streamed too few tokens to tell.
+#[must_use]
+pub fn tokens_per_second(
+    body: &[u8],
+    tfft: Duration,
+    duration: Duration,
+) -> Option<f64> {
+    let usage = Usage::from_response(body, true);
+    let tokens = if usage.completion_tokens > 0 {
+        usage.completion_tokens
+    } else {
+        count_deltas(body)
+    };
+    let generation = duration.checked_sub(tfft)?;
+    if tokens < 2 || generation.is_zero() {
+        return None;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    Some(tokens as f64 / generation.as_secs_f64())
+}
+
+/// Counts the chunks with generated content, in the OpenAI or Anthropic
+/// stream formats.
+fn count_deltas(body: &[u8]) -> u64 {
+    let is_delta = |event: &Value| {
+        let content = event
+            .pointer("/choices/0/delta/content")
+            .or_else(|| event.pointer("/delta/text"))
+            .and_then(Value::as_str);
+        content.is_some_and(|content| !content.is_empty())
+    };
+    let deltas = body
+        .split(|byte| *byte == b'\n')
+        .filter_map(|line| line.strip_prefix(b"data:"))
+        .filter_map(|data| serde_json::from_slice::<Value>(data).ok())
+        .filter(is_delta)
+        .count();
+    u64::try_from(deltas).unwrap_or(u64::MAX)
+}
+
+/// Moving averages of the tokens per second of each provider's models, as
+/// an input to latency based routing.
+#[derive(Debug, Clone, Default)]
+pub struct Throughputs(Arc<RwLock<HashMap<(InferenceProvider, String), f64>>>);
+
+impl Throughputs {
+    pub fn observe(
+        &self,
+        provider: &InferenceProvider,
+        model: &ModelId,
+        tokens_per_second: f64,
+    ) {
+        let mut throughputs =
+            self.0.write().expect("throughputs lock poisoned");
+        throughputs
+            .entry((provider.clone(), model.to_string()))
+            .and_modify(|average| {
+                *average += SMOOTHING * (tokens_per_second - *average);
+            })
+            .or_insert(tokens_per_second);
+    }
+
+    /// The moving average of `model`'s tokens per second, if any of its
+    /// streams have been observed.
+    #[must_use]
+    pub fn get(
+        &self,
+        provider: &InferenceProvider,
+        model: &ModelId,
+    ) -> Option<f64> {
+        self.0
+            .read()
+            .expect("throughputs lock poisoned")
+            .get(&(provider.clone(), model.to_string()))
+            .copied()
+    }
+}
+
+/// Records the throughput of a stream from `provider`, whose body is
+/// `body`.
+pub fn record(
+    app_state: &AppState,
+    provider: &InferenceProvider,
+    model: Option<&ModelId>,
+    body: &[u8],
+    tfft: Duration,
+    duration: Duration,
+) {
+    let Some(throughput) = tokens_per_second(body, tfft, duration) else {
+        return;
+    };
+    let metrics = &app_state.0.metrics;
+    let model_label = model.map_or_else(
+        || "unknown".to_string(),
+        |model| metrics.labels.models.value(&model.to_string()),
+    );
+    let attributes = [
+        KeyValue::new(
+            "provider",
+            metrics.labels.providers.value(&provider.to_string()),
+        ),
+        KeyValue::new("model", model_label),
+    ];
+    metrics.tokens_per_second.record(throughput, &attributes);
+    if let Some(model) = model {
+        app_state.0.throughputs.observe(provider, model, throughput);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn throughput_excludes_time_to_first_token() {
+        let body = b"\
+            data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n\
+            data: {\"choices\":[{\"delta\":{\"content\":\"!\"}}]}\n\n\
+            data: [DONE]\n\n";
+        let throughput = tokens_per_second(
+            body,
+            Duration::from_secs(1),
+            Duration::from_millis(1500),
+        );
+        assert_eq!(throughput, Some(6.0));
+
+        let usage =
+            b"data: {\"choices\":[],\"usage\":{\"completion_tokens\":20}}\n";
+        assert_eq!(
+            tokens_per_second(usage, Duration::ZERO, Duration::from_secs(2)),
+            Some(10.0)
+        );
+        assert_eq!(
+            tokens_per_second(
+                body,
+                Duration::from_secs(2),
+                Duration::from_secs(1)
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn throughputs_are_averaged() {
+        let throughputs = Throughputs::default();
+        let provider = InferenceProvider::OpenAI;
+        let model = ModelId::from_str("openai/gpt-4o").unwrap();
+        assert_eq!(throughputs.get(&provider, &model), None);
+        throughputs.observe(&provider, &model, 100.0);
+        throughputs.observe(&provider, &model, 50.0);
+        assert_eq!(throughputs.get(&provider, &model), Some(90.0));
+    }
+}
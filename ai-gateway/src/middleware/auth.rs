@@ -12,11 +12,21 @@ use crate::{
     },
     types::{
         extensions::{AuthContext, RequestKind},
+        priority::Priority,
         router::RouterId,
         secret::Secret,
     },
 };
 
+/// Authenticates requests in place of the gateway's own authentication, for
+/// applications embedding the gateway which have their own.
+pub trait Authenticator: Send + Sync + std::fmt::Debug {
+    fn authenticate<'a>(
+        &'a self,
+        request: &'a http::request::Parts,
+    ) -> BoxFuture<'a, Result<AuthContext, AuthError>>;
+}
+
 #[derive(Clone)]
 pub struct AuthService {
     app_state: AppState,
@@ -72,6 +82,8 @@ impl AuthService {
                             api_key: Secret::from(api_key_without_bearer),
                             user_id: key.owner_id,
                             org_id: key.organization_id,
+                            max_priority: key.max_priority(),
+                            tier: key.tier,
                         })
                     } else {
                         Err(AuthError::InvalidCredentials.into())
@@ -82,6 +94,8 @@ impl AuthService {
                         api_key: Secret::from(api_key_without_bearer),
                         user_id: key.owner_id,
                         org_id: key.organization_id,
+                        max_priority: key.max_priority(),
+                        tier: key.tier,
                     })
                 }
             }
@@ -97,6 +111,8 @@ impl AuthService {
                     api_key: Secret::from(api_key_without_bearer),
                     user_id: key.owner_id,
                     org_id: control_plane_state.auth.organization_id,
+                    max_priority: key.max_priority(),
+                    tier: key.tier.clone(),
                 })
             } else {
                 Err(AuthError::InvalidCredentials.into())
@@ -120,9 +136,22 @@ where
     fn authorize(&mut self, mut request: Request<B>) -> Self::Future {
         let app_state = self.app_state.clone();
         Box::pin(async move {
+            if let Some(authenticator) = app_state.0.authenticator.as_deref() {
+                let (mut parts, body) = request.into_parts();
+                return match authenticator.authenticate(&parts).await {
+                    Ok(auth_ctx) => {
+                        parts.extensions.insert(auth_ctx);
+                        with_priority(Request::from_parts(parts, body))
+                    }
+                    Err(e) => {
+                        app_state.0.metrics.auth_rejections.add(1, &[]);
+                        Err(ApiError::from(e).into_response())
+                    }
+                };
+            }
             if app_state.0.config.helicone.is_auth_disabled() {
                 tracing::trace!("auth middleware: auth disabled");
-                return Ok(request);
+                return with_priority(request);
             }
             tracing::trace!("auth middleware");
             let Some(api_key) = request
@@ -149,7 +178,7 @@ where
             {
                 Ok(auth_ctx) => {
                     request.extensions_mut().insert(auth_ctx);
-                    Ok(request)
+                    with_priority(request)
                 }
                 Err(e) => {
                     if let ApiError::Authentication(auth_error) = &e {
@@ -167,3 +196,18 @@ where
         })
     }
 }
+
+/// Adds the request's [`Priority`] to its extensions, once the API key it
+/// is scoped by is known.
+fn with_priority<B>(
+    mut request: Request<B>,
+) -> Result<Request<B>, http::Response<axum_core::body::Body>> {
+    let max_priority = request
+        .extensions()
+        .get::<AuthContext>()
+        .map_or(Priority::Normal, |auth_ctx| auth_ctx.max_priority);
+    let priority = Priority::from_headers(request.headers(), max_priority)
+        .map_err(IntoResponse::into_response)?;
+    request.extensions_mut().insert(priority);
+    Ok(request)
+}
@@ -0,0 +1,150 @@
+//! Hashing of request bodies into cache keys.
+use std::hash::{Hash, Hasher};
+
+use bytes::Bytes;
+use serde_json::Value;
+
+use crate::config::cache::CacheKeyConfig;
+
+/// Hashes the parts of `body` selected by `config`.
+///
+/// Without a config, or if the body isn't a JSON object, the raw body is
+/// hashed. Otherwise the selected fields are hashed independently of their
+/// order and formatting in the body.
+pub(super) fn hash_body<H: Hasher>(
+    body: &Bytes,
+    config: Option<&CacheKeyConfig>,
+    hasher: &mut H,
+) {
+    let object =
+        config.and_then(|config| match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Object(object)) => Some((config, object)),
+            _ => None,
+        });
+    let Some((config, object)) = object else {
+        body.hash(hasher);
+        return;
+    };
+    let mut fields = object
+        .iter()
+        .filter(|(field, _)| config.includes(field))
+        .collect::<Vec<_>>();
+    fields.sort_unstable_by_key(|(field, _)| *field);
+    fields.len().hash(hasher);
+    for (field, value) in fields {
+        field.hash(hasher);
+        hash_value(value, config.normalize_whitespace, hasher);
+    }
+}
+
+fn hash_value<H: Hasher>(value: &Value, normalize_whitespace: bool, h: &mut H) {
+    match value {
+        Value::Null => 0u8.hash(h),
+        Value::Bool(b) => {
+            1u8.hash(h);
+            b.hash(h);
+        }
+        Value::Number(n) => {
+            2u8.hash(h);
+            n.to_string().hash(h);
+        }
+        Value::String(s) if normalize_whitespace => {
+            3u8.hash(h);
+            for word in s.split_whitespace() {
+                word.hash(h);
+            }
+            // terminate the sequence of words so that adjacent strings can't
+            // collide
+            0xffu8.hash(h);
+        }
+        Value::String(s) => {
+            3u8.hash(h);
+            s.hash(h);
+        }
+        Value::Array(values) => {
+            4u8.hash(h);
+            values.len().hash(h);
+            for value in values {
+                hash_value(value, normalize_whitespace, h);
+            }
+        }
+        Value::Object(object) => {
+            5u8.hash(h);
+            object.len().hash(h);
+            let mut fields = object.iter().collect::<Vec<_>>();
+            fields.sort_unstable_by_key(|(field, _)| *field);
+            for (field, value) in fields {
+                field.hash(h);
+                hash_value(value, normalize_whitespace, h);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustc_hash::FxHasher;
+    use serde_json::json;
+
+    use super::*;
+
+    fn key(body: &Value, config: Option<&CacheKeyConfig>) -> u64 {
+        let body = Bytes::from(serde_json::to_vec(body).unwrap());
+        let mut hasher = FxHasher::default();
+        hash_body(&body, config, &mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn excluded_fields_are_ignored() {
+        let config = CacheKeyConfig {
+            exclude: vec!["user".to_string()],
+            ..Default::default()
+        };
+        let a = json!({"model": "gpt-4o", "user": "a"});
+        let b = json!({"model": "gpt-4o", "user": "b"});
+        assert_ne!(key(&a, None), key(&b, None));
+        assert_eq!(key(&a, Some(&config)), key(&b, Some(&config)));
+    }
+
+    #[test]
+    fn only_included_fields_are_used() {
+        let config = CacheKeyConfig {
+            include: Some(vec!["model".to_string(), "messages".to_string()]),
+            ..Default::default()
+        };
+        let a = json!({"model": "gpt-4o", "messages": [], "temperature": 1});
+        let b = json!({"model": "gpt-4o", "messages": [], "seed": 7});
+        let c = json!({"model": "gpt-4o-mini", "messages": []});
+        assert_eq!(key(&a, Some(&config)), key(&b, Some(&config)));
+        assert_ne!(key(&a, Some(&config)), key(&c, Some(&config)));
+    }
+
+    #[test]
+    fn whitespace_is_normalized() {
+        let config = CacheKeyConfig {
+            normalize_whitespace: true,
+            ..Default::default()
+        };
+        let a = json!({"messages": [{"content": "Hello,  world!\n"}]});
+        let b = json!({"messages": [{"content": " Hello, world!"}]});
+        assert_ne!(
+            key(&a, Some(&CacheKeyConfig::default())),
+            key(&b, Some(&CacheKeyConfig::default()))
+        );
+        assert_eq!(key(&a, Some(&config)), key(&b, Some(&config)));
+    }
+
+    #[test]
+    fn field_order_is_irrelevant() {
+        let config = CacheKeyConfig::default();
+        let a = Bytes::from_static(br#"{"model": "gpt-4o", "n": 1}"#);
+        let b = Bytes::from_static(br#"{"n":1,"model":"gpt-4o"}"#);
+        let hash = |body: &Bytes| {
+            let mut hasher = FxHasher::default();
+            hash_body(body, Some(&config), &mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&a), hash(&b));
+    }
+}
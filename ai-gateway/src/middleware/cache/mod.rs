@@ -1,3 +1,4 @@
+mod key;
 pub mod optional;
 mod service;
 
@@ -5,6 +5,7 @@ use std::{
     str::FromStr,
     sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use bytes::Bytes;
@@ -26,7 +27,9 @@ use crate::{
     app_state::AppState,
     cache::CacheClient,
     config::{
-        cache::{CacheConfig, DEFAULT_BUCKETS, MAX_BUCKET_SIZE},
+        cache::{
+            CacheConfig, CacheKeyConfig, DEFAULT_BUCKETS, MAX_BUCKET_SIZE,
+        },
         router::RouterConfig,
     },
     error::{
@@ -34,16 +37,18 @@ use crate::{
         invalid_req::InvalidRequestError,
     },
     logger::service::LoggerService,
-    metrics::tfft::TFFTFuture,
+    metrics::tfft::{self, TFFTFuture},
+    middleware::cache::key::hash_body,
     types::{
         body::BodyReader,
-        extensions::{AuthContext, MapperContext},
+        extensions::{AuthContext, ClientIp, MapperContext},
         model_id::ModelId,
         provider::InferenceProvider,
         request::Request,
         response::Response,
         router::RouterId,
     },
+    utils::task,
 };
 
 const CACHE_HIT_HEADER: HeaderName = HeaderName::from_static("helicone-cache");
@@ -51,6 +56,9 @@ const CACHE_BUCKET_IDX: HeaderName =
     HeaderName::from_static("helicone-cache-bucket-idx");
 const CACHE_HIT_HEADER_VALUE: HeaderValue = HeaderValue::from_static("HIT");
 const CACHE_MISS_HEADER_VALUE: HeaderValue = HeaderValue::from_static("MISS");
+const X_CACHE_HEADER: HeaderName = HeaderName::from_static("x-cache");
+const CACHE_BYPASS_HEADER_VALUE: HeaderValue =
+    HeaderValue::from_static("BYPASS");
 const DEFAULT_UUID: Uuid = Uuid::from_u128(0);
 
 #[derive(Debug)]
@@ -62,12 +70,22 @@ struct CacheContext {
     buckets: Option<u8>,
     seed: Option<String>,
     options: Option<CacheOptions>,
+    /// Whether the directives of a client's `Cache-Control` header are
+    /// honored.
+    trust_client_directives: Option<bool>,
+    request_directives: RequestDirectives,
+    /// Only configurable per router, never per request.
+    key: Option<Arc<CacheKeyConfig>>,
 }
 
 impl CacheContext {
-    /// Merge two cache configs. `Other` takes precedence over `Self`.
+    /// Merge two cache configs. `Other` takes precedence over `Self`, except
+    /// for client directives which are only taken from `Other` if `Self`
+    /// trusts them.
     #[must_use]
     pub fn merge(&self, other: &Self) -> Self {
+        let trust_client_directives =
+            self.trust_client_directives.unwrap_or(true);
         let enabled = if let Some(other_explicitly_set) = other.enabled {
             // if other is set, just use that value (so req headers can disable
             // whether caching is enabled or not per request)
@@ -77,15 +95,51 @@ impl CacheContext {
             // can enable caching if explicitly enabled)
             self.enabled.unwrap_or(false)
         };
+        let (directive, request_directives) = if trust_client_directives {
+            (
+                other.directive.clone().or_else(|| self.directive.clone()),
+                other.request_directives,
+            )
+        } else {
+            (self.directive.clone(), self.request_directives)
+        };
         Self {
             enabled: Some(enabled),
-            directive: other
-                .directive
-                .clone()
-                .or_else(|| self.directive.clone()),
+            directive,
             buckets: other.buckets.or(self.buckets),
             seed: other.seed.clone().or_else(|| self.seed.clone()),
             options: other.options.or(self.options),
+            trust_client_directives: Some(trust_client_directives),
+            request_directives,
+            key: self.key.clone(),
+        }
+    }
+}
+
+/// The directives of a client's `Cache-Control` request header which
+/// affect how the cache is used.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct RequestDirectives {
+    /// Neither serve a cached response nor store the upstream response.
+    no_store: bool,
+    /// Don't serve a cached response, but store the upstream response.
+    no_cache: bool,
+    /// Only serve cached responses at most this old.
+    max_age: Option<Duration>,
+}
+
+impl RequestDirectives {
+    fn parse(value: &str) -> Self {
+        let Some(value) = cache_control::CacheControl::from_value(value) else {
+            return Self::default();
+        };
+        Self {
+            no_store: value.no_store,
+            no_cache: matches!(
+                value.cachability,
+                Some(cache_control::Cachability::NoCache)
+            ),
+            max_age: value.max_age,
         }
     }
 }
@@ -116,6 +170,9 @@ impl CacheLayer {
                 shared: false,
                 ..Default::default()
             }),
+            trust_client_directives: Some(config.trust_client_directives),
+            request_directives: RequestDirectives::default(),
+            key: config.key.map(Arc::new),
         };
         Ok(Self {
             app_state,
@@ -235,14 +292,27 @@ async fn check_cache(
         return Ok(CacheCheckResult::Miss);
     };
 
+    let age = policy.age(now);
+    if ctx
+        .request_directives
+        .max_age
+        .is_some_and(|max_age| age > max_age)
+    {
+        tracing::trace!(?age, "cached response is older than client max-age");
+        return Ok(CacheCheckResult::Stale);
+    }
+
     match policy.before_request(&req, now) {
         BeforeRequest::Fresh(parts) => {
             let additional_headers = vec![
                 (CACHE_HIT_HEADER, CACHE_HIT_HEADER_VALUE),
                 (CACHE_BUCKET_IDX, bucket_header_value(bucket)),
             ];
-            let response =
+            let mut response =
                 build_response(http_resp, parts.status, additional_headers)?;
+            response
+                .headers_mut()
+                .insert(http::header::AGE, HeaderValue::from(age.as_secs()));
 
             let start_instant = req
                 .extensions()
@@ -297,7 +367,8 @@ async fn check_cache(
                     .get("helicone-id")
                     .and_then(|hv| Uuid::parse_str(hv.to_str().unwrap()).ok())
                     .unwrap_or(DEFAULT_UUID);
-                tokio::spawn(
+                task::spawn(
+                    "cache-log",
                     async move {
                         let Ok(deserialized_body) = deserialized_body else {
                             tracing::error!(
@@ -358,6 +429,9 @@ async fn check_cache(
                                 helicone_request_id.to_string(),
                             ))
                             .request_id(helicone_request_id)
+                            .client_ip(
+                                req_parts.extensions.get::<ClientIp>().copied(),
+                            )
                             .build();
                         if let Err(e) = response_logger.log().await {
                             let error_str = e.as_ref().to_string();
@@ -372,7 +446,8 @@ async fn check_cache(
                 );
                 Ok(CacheCheckResult::Fresh(response))
             } else {
-                tokio::spawn(
+                task::spawn(
+                    "cache-metrics",
                     async move {
                         let tfft_future = TFFTFuture::new(start_instant, tfft_rx);
                         let collect_future = body_reader.collect();
@@ -382,8 +457,7 @@ async fn check_cache(
                             let attributes = [
                                 KeyValue::new("path", target_url.path().to_string()),
                             ];
-                            #[allow(clippy::cast_precision_loss)]
-                            app_state.0.metrics.tfft_duration.record(tfft_duration.as_millis() as f64, &attributes);
+                            tfft::record_tfft(&app_state.0.metrics, tfft_duration, &attributes);
                         } else { tracing::error!("Failed to get TFFT signal") }
                     }
                     .instrument(tracing::Span::current()),
@@ -416,7 +490,7 @@ async fn handle_response_for_cache_miss(
     ctx: &CacheContext,
     key: String,
     req: Request,
-    resp: Response,
+    mut resp: Response,
     bucket: u8,
     now: std::time::SystemTime,
 ) -> Result<Response, ApiError> {
@@ -432,6 +506,8 @@ async fn handle_response_for_cache_miss(
             is_storable = policy.is_storable(),
             "got response that is not storable"
         );
+        resp.headers_mut()
+            .insert(X_CACHE_HEADER, CACHE_MISS_HEADER_VALUE);
         return Ok(resp);
     }
     tracing::trace!("caching storable response");
@@ -462,6 +538,7 @@ async fn handle_response_for_cache_miss(
         vec![
             (CACHE_HIT_HEADER, CACHE_MISS_HEADER_VALUE),
             (CACHE_BUCKET_IDX, bucket_header_value(bucket)),
+            (X_CACHE_HEADER, CACHE_MISS_HEADER_VALUE),
         ],
     )
     .map_err(Into::into)
@@ -480,14 +557,23 @@ where
         + Send
         + 'static,
 {
-    // just call inner service if caching is disabled
-    if ctx.enabled.is_none_or(|enabled| !enabled) {
-        return inner.call(req).await.map_err(|e| {
+    // just call inner service if caching is disabled, or the client asked
+    // for nothing to be stored
+    if ctx.enabled.is_none_or(|enabled| !enabled)
+        || ctx.request_directives.no_store
+    {
+        let mut resp = inner.call(req).await.map_err(|e| {
             tracing::error!(error = %e, "encountered infallible error");
             ApiError::Internal(InternalError::Internal)
-        });
+        })?;
+        resp.headers_mut()
+            .insert(X_CACHE_HEADER, CACHE_BYPASS_HEADER_VALUE);
+        return Ok(resp);
     }
 
+    if ctx.trust_client_directives == Some(false) {
+        req.headers_mut().remove(http::header::CACHE_CONTROL);
+    }
     if let Some(directive) = &ctx.directive {
         if req.headers().get(http::header::CACHE_CONTROL).is_none() {
             req.headers_mut().insert(
@@ -509,7 +595,12 @@ where
 
     // Try each bucket in parallel
     let mut futures = FuturesUnordered::new();
-    let hasher = get_hasher(&parts, &body_bytes, ctx.seed.as_deref());
+    let hasher = get_hasher(
+        &parts,
+        &body_bytes,
+        ctx.seed.as_deref(),
+        ctx.key.as_deref(),
+    );
     // fairly sample different buckets
     let mut bucket_indices: Vec<u8> = (0..buckets).collect();
     {
@@ -517,6 +608,10 @@ where
         let mut rng = rand::rng();
         bucket_indices.shuffle(&mut rng);
     }
+    // the client asked for a response from upstream, which we may still store
+    if ctx.request_directives.no_cache {
+        bucket_indices.clear();
+    }
 
     let ctx_ref = &ctx;
     for bucket in bucket_indices {
@@ -549,6 +644,7 @@ where
                 resp.headers_mut().extend([
                     (CACHE_HIT_HEADER, CACHE_HIT_HEADER_VALUE),
                     (CACHE_BUCKET_IDX, bucket_header_value(bucket)),
+                    (X_CACHE_HEADER, CACHE_HIT_HEADER_VALUE),
                 ]);
                 return Ok(resp);
             }
@@ -614,7 +710,12 @@ where
     .await
 }
 
-fn get_hasher(parts: &Parts, body: &Bytes, seed: Option<&str>) -> FxHasher {
+fn get_hasher(
+    parts: &Parts,
+    body: &Bytes,
+    seed: Option<&str>,
+    key: Option<&CacheKeyConfig>,
+) -> FxHasher {
     let mut hasher = FxHasher::default();
     if let Some(s) = seed {
         s.hash(&mut hasher);
@@ -622,7 +723,7 @@ fn get_hasher(parts: &Parts, body: &Bytes, seed: Option<&str>) -> FxHasher {
     if let Some(pq) = parts.uri.path_and_query() {
         pq.hash(&mut hasher);
     }
-    body.hash(&mut hasher);
+    hash_body(body, key, &mut hasher);
     hasher
 }
 
@@ -661,12 +762,19 @@ fn get_cache_ctx(req: &Request) -> Result<CacheContext, InvalidRequestError> {
     let directive = headers
         .get(http::header::CACHE_CONTROL)
         .and_then(|v| v.to_str().ok().map(String::from));
+    let request_directives = directive
+        .as_deref()
+        .map(RequestDirectives::parse)
+        .unwrap_or_default();
     Ok(CacheContext {
         enabled,
         directive,
         buckets,
         seed,
         options: None,
+        trust_client_directives: None,
+        request_directives,
+        key: None,
     })
 }
 
@@ -0,0 +1,436 @@
+//! Context-length aware routing.
+//!
+//! Estimates the number of tokens a request needs and, when the requested
+//! model's context window can't accommodate it, rewrites the request to use
+//! the cheapest equivalent model (per the router's model mappings) that can.
+//! If no such model exists, the request is rejected before it consumes a
+//! routing slot. Only routers with a `context-length` config buffer their
+//! requests to do so.
+use std::{
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http_body_util::BodyExt;
+use indexmap::IndexSet;
+use serde_json::Value;
+
+use crate::{
+    app_state::AppState,
+    config::{
+        model_capabilities::ModelCapabilitiesConfig,
+        model_mapping::ModelMappingConfig, router::RouterConfig,
+    },
+    error::{
+        api::ApiError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    types::{
+        model_id::{ModelId, ModelName},
+        provider::InferenceProvider,
+        request::Request,
+        response::Response,
+    },
+    utils::tokenizer::count_tokens,
+};
+
+/// Tokens counted for each image. Tokenizing an image's data would count
+/// its encoding rather than what the model sees, so images are counted at
+/// about what providers charge for a high resolution one.
+const IMAGE_TOKENS: usize = 1_000;
+/// Tokens each message adds for its role and delimiters.
+const MESSAGE_TOKENS: usize = 4;
+/// Requests with larger bodies are tokenized on the blocking thread pool,
+/// so that they don't stall the executor.
+const BLOCKING_BODY_SIZE: usize = 64 * 1024;
+
+/// Estimate the number of prompt tokens in a request, counted with the
+/// tokenizer of its model.
+///
+/// Only the text of the prompt and the tool schemas are tokenized; images
+/// are counted at a fixed cost.
+pub(crate) fn estimate_prompt_tokens(request: &Value) -> u32 {
+    let model = request.get("model").and_then(Value::as_str);
+    let messages = request
+        .get("messages")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .map(|message| MESSAGE_TOKENS + message_tokens(model, message))
+        .sum::<usize>();
+    let system = request
+        .get("system")
+        .map_or(0, |system| content_tokens(model, system));
+    let prompt = request
+        .get("prompt")
+        .map_or(0, |prompt| content_tokens(model, prompt));
+    let tools = request
+        .get("tools")
+        .map_or(0, |tools| count_tokens(model, &tools.to_string()));
+    u32::try_from(messages + system + prompt + tools).unwrap_or(u32::MAX)
+}
+
+/// Runs `estimate` on `request`, on the blocking thread pool if its body of
+/// `body_len` bytes is large, and returns the request along with the
+/// estimate.
+pub(crate) async fn estimate_blocking<T, F>(
+    body_len: usize,
+    request: Value,
+    estimate: F,
+) -> Result<(Value, T), InternalError>
+where
+    T: Send + 'static,
+    F: FnOnce(&Value) -> T + Send + 'static,
+{
+    if body_len < BLOCKING_BODY_SIZE {
+        let estimate = estimate(&request);
+        return Ok((request, estimate));
+    }
+    tokio::task::spawn_blocking(move || {
+        let estimate = estimate(&request);
+        (request, estimate)
+    })
+    .await
+    .map_err(InternalError::TokenCountTaskError)
+}
+
+/// Estimate the number of tokens (prompt plus requested completion) a request
+/// will need.
+fn estimate_tokens(request: &Value) -> u32 {
+    let completion_tokens = request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .and_then(Value::as_u64)
+        .unwrap_or_default();
+    estimate_prompt_tokens(request)
+        .saturating_add(u32::try_from(completion_tokens).unwrap_or(u32::MAX))
+}
+
+fn message_tokens(model: Option<&str>, message: &Value) -> usize {
+    let content = message
+        .get("content")
+        .map_or(0, |content| content_tokens(model, content));
+    // tool calls of assistant messages in the OpenAI format
+    let tool_calls = message
+        .get("tool_calls")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|call| call.get("function"))
+        .flat_map(|function| ["name", "arguments"].map(|f| function.get(f)))
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(|text| count_tokens(model, text))
+        .sum::<usize>();
+    content + tool_calls
+}
+
+/// Tokens of a message's content, given either as text or as content parts.
+fn content_tokens(model: Option<&str>, content: &Value) -> usize {
+    match content {
+        Value::String(text) => count_tokens(model, text),
+        Value::Array(parts) => {
+            parts.iter().map(|part| part_tokens(model, part)).sum()
+        }
+        _ => 0,
+    }
+}
+
+fn part_tokens(model: Option<&str>, part: &Value) -> usize {
+    if let Value::String(text) = part {
+        // completion prompts may be given as an array of strings
+        return count_tokens(model, text);
+    }
+    match part.get("type").and_then(Value::as_str) {
+        Some("image_url" | "image" | "input_image") => IMAGE_TOKENS,
+        // Anthropic's tool calls and their results
+        Some("tool_use") => part
+            .get("input")
+            .map_or(0, |input| count_tokens(model, &input.to_string())),
+        Some("tool_result") => part
+            .get("content")
+            .map_or(0, |content| content_tokens(model, content)),
+        _ => ["text", "thinking"]
+            .iter()
+            .filter_map(|field| part.get(field))
+            .filter_map(Value::as_str)
+            .map(|text| count_tokens(model, text))
+            .sum(),
+    }
+}
+
+#[derive(Debug)]
+struct ContextRouter {
+    capabilities: ModelCapabilitiesConfig,
+    model_mapping: ModelMappingConfig,
+    providers: IndexSet<InferenceProvider>,
+    /// Tokens added to the estimate of each request.
+    headroom: u32,
+}
+
+impl ContextRouter {
+    /// Returns `Ok(None)` if the requested model can serve the request as is,
+    /// `Ok(Some(model))` with the cheapest adequate replacement otherwise.
+    fn select(
+        &self,
+        model: &ModelId,
+        tokens: u32,
+    ) -> Result<Option<ModelId>, InvalidRequestError> {
+        let Some(capability) = self.capabilities.get(model) else {
+            // nothing we can say about models we don't know of
+            return Ok(None);
+        };
+        if tokens <= capability.context_window {
+            return Ok(None);
+        }
+
+        let source_model_name = ModelName::from_model(model);
+        let Some(possible_mappings) =
+            self.model_mapping.as_ref().get(&source_model_name)
+        else {
+            return Err(InvalidRequestError::ContextLengthExceeded(tokens));
+        };
+        possible_mappings
+            .iter()
+            .filter(|candidate| {
+                candidate
+                    .inference_provider()
+                    .is_some_and(|provider| self.providers.contains(&provider))
+            })
+            .filter_map(|candidate| {
+                self.capabilities
+                    .get(candidate)
+                    .filter(|capability| tokens <= capability.context_window)
+                    .map(|capability| (candidate, capability))
+            })
+            .min_by_key(|(_, capability)| capability.input_cost_per_mtok)
+            .map(|(candidate, _)| Some(candidate.clone()))
+            .ok_or(InvalidRequestError::ContextLengthExceeded(tokens))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    router: Arc<ContextRouter>,
+}
+
+impl Layer {
+    #[must_use]
+    pub fn for_router(
+        app_state: &AppState,
+        router_config: &RouterConfig,
+    ) -> Option<Self> {
+        let context_length = router_config.context_length.as_ref()?;
+        let config = app_state.config();
+        let model_mapping = router_config
+            .model_mappings()
+            .unwrap_or(&config.default_model_mapping)
+            .clone();
+        Some(Self {
+            router: Arc::new(ContextRouter {
+                capabilities: config.model_capabilities.clone(),
+                model_mapping,
+                providers: router_config.load_balance.providers(),
+                headroom: context_length.headroom,
+            }),
+        })
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            router: Arc::clone(&self.router),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    router: Arc<ContextRouter>,
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "context_length", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let router = Arc::clone(&self.router);
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+
+            let Some(request_json) =
+                serde_json::from_slice::<Value>(&body_bytes).ok()
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+            let Some(model) = request_json
+                .get("model")
+                .and_then(Value::as_str)
+                .and_then(|model| ModelId::from_str(model).ok())
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+
+            let (mut request_json, tokens) = estimate_blocking(
+                body_bytes.len(),
+                request_json,
+                estimate_tokens,
+            )
+            .await?;
+            let tokens = tokens.saturating_add(router.headroom);
+            let body_bytes = match router.select(&model, tokens)? {
+                Some(replacement) => {
+                    tracing::debug!(
+                        model = %model,
+                        replacement = %replacement,
+                        tokens,
+                        "requested model context window too small, rerouting"
+                    );
+                    request_json["model"] = serde_json::to_value(&replacement)
+                        .map_err(|error| InternalError::Serialize {
+                            ty: "ModelId",
+                            error,
+                        })?;
+                    serde_json::to_vec(&request_json).map(Bytes::from).map_err(
+                        |error| InternalError::Serialize {
+                            ty: "serde_json::Value",
+                            error,
+                        },
+                    )?
+                }
+                None => body_bytes,
+            };
+
+            let req = Request::from_parts(parts, body_bytes.into());
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn model(s: &str) -> ModelId {
+        ModelId::from_str(s).unwrap()
+    }
+
+    fn router() -> ContextRouter {
+        ContextRouter {
+            capabilities: ModelCapabilitiesConfig::default(),
+            model_mapping: ModelMappingConfig::default(),
+            providers: IndexSet::from([
+                InferenceProvider::OpenAI,
+                InferenceProvider::Anthropic,
+                InferenceProvider::GoogleGemini,
+            ]),
+            headroom: 0,
+        }
+    }
+
+    #[test]
+    fn estimate_counts_prompt_and_completion() {
+        let request = json!({
+            "model": "openai/gpt-4o",
+            "messages": [{"role": "user", "content": "Hello, world"}],
+            "max_tokens": 100,
+        });
+        // 4 tokens for the message and 3 for "Hello, world"
+        assert_eq!(estimate_tokens(&request), 107);
+    }
+
+    #[test]
+    fn images_are_counted_at_a_fixed_cost() {
+        let image = format!("data:image/png;base64,{}", "A".repeat(100_000));
+        let request = json!({
+            "model": "openai/gpt-4o",
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "Hello, world"},
+                    {"type": "image_url", "image_url": {"url": image}},
+                ],
+            }],
+        });
+        assert_eq!(
+            estimate_prompt_tokens(&request),
+            u32::try_from(MESSAGE_TOKENS + 3 + IMAGE_TOKENS).unwrap()
+        );
+    }
+
+    #[test]
+    fn fitting_request_is_unchanged() {
+        let router = router();
+        assert_eq!(
+            router.select(&model("openai/gpt-4o"), 1_000).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn unknown_model_is_unchanged() {
+        let router = router();
+        assert_eq!(
+            router.select(&model("openai/gpt-9"), 10_000_000).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn long_request_routes_to_cheapest_adequate_model() {
+        let router = router();
+        let selected = router
+            .select(&model("openai/gpt-4o"), 500_000)
+            .unwrap()
+            .unwrap();
+        let capability = router.capabilities.get(&selected).unwrap();
+        assert!(capability.context_window >= 500_000);
+        assert_eq!(selected, model("gemini/gemini-2.5-pro"));
+    }
+
+    #[test]
+    fn oversized_request_is_rejected() {
+        let router = router();
+        let result = router.select(&model("openai/gpt-4o"), 5_000_000);
+        assert!(matches!(
+            result,
+            Err(InvalidRequestError::ContextLengthExceeded(5_000_000))
+        ));
+    }
+}
@@ -0,0 +1,701 @@
+//! Multi-turn conversation state.
+//!
+//! Clients continue a conversation by passing the `id` of its last response
+//! as `previous_response_id`, instead of resending the full history. The
+//! stored messages are prepended to the request's messages before it is
+//! dispatched, and the request's messages together with the response are
+//! stored under the new response's `id` once the response completes.
+//!
+//! Conversations can only be continued with the API key which started them.
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use futures::{StreamExt, TryStreamExt, future::BoxFuture};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+
+use crate::{
+    app_state::AppState,
+    config::{conversation::ConversationsConfig, router::RouterConfig},
+    control_plane::types::hash_key,
+    error::{
+        api::ApiError, init::InitError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    store::conversation::{Conversation, ConversationStore},
+    types::{extensions::AuthContext, request::Request, response::Response},
+    utils::task,
+};
+
+/// Request body field referencing the conversation to continue.
+const PREVIOUS_RESPONSE_ID: &str = "previous_response_id";
+
+/// Returns the assistant message of a chat completion, or of an Anthropic
+/// message.
+fn assistant_message(response: &Value) -> Option<Value> {
+    if let Some(message) = response.pointer("/choices/0/message") {
+        return Some(message.clone());
+    }
+    (response.get("type")? == "message").then(|| {
+        json!({
+            "role": "assistant",
+            "content": response.get("content").cloned().unwrap_or_default(),
+        })
+    })
+}
+
+/// Whether `message` holds the results of tool calls, which can't be sent
+/// without the assistant message that made the calls.
+fn is_tool_result(message: &Value) -> bool {
+    match message.get("role").and_then(Value::as_str) {
+        Some("tool") => true,
+        Some("user") => message
+            .get("content")
+            .and_then(Value::as_array)
+            .is_some_and(|blocks| {
+                blocks.iter().any(|block| {
+                    block.get("type").and_then(Value::as_str)
+                        == Some("tool_result")
+                })
+            }),
+        _ => false,
+    }
+}
+
+/// Drops the oldest messages, other than leading system messages, until the
+/// conversation is within the configured limits. The results of tool calls
+/// are dropped together with the assistant message that made the calls.
+fn trim(messages: &mut Vec<Value>, config: &ConversationsConfig) {
+    let system = messages
+        .iter()
+        .take_while(|message| {
+            matches!(
+                message.get("role").and_then(Value::as_str),
+                Some("system" | "developer")
+            )
+        })
+        .count();
+    let mut sizes = messages
+        .iter()
+        .map(|message| serde_json::to_vec(message).map_or(0, |m| m.len()))
+        .collect::<Vec<_>>();
+    let mut total = sizes.iter().sum::<usize>();
+    while messages.len() > system
+        && (messages.len() > config.max_messages || total > config.max_bytes)
+    {
+        messages.remove(system);
+        total -= sizes.remove(system);
+        while messages.get(system).is_some_and(is_tool_result) {
+            messages.remove(system);
+            total -= sizes.remove(system);
+        }
+    }
+}
+
+/// Accumulates the reply of a streamed response from its SSE events.
+#[derive(Debug, Default)]
+pub(crate) struct StreamedReply {
+    /// Incomplete line of the stream.
+    buffer: String,
+    id: Option<String>,
+    content: String,
+    /// Tool calls streamed as OpenAI `tool_calls` or Anthropic `tool_use`
+    /// blocks, by index.
+    tool_calls: BTreeMap<u64, ToolCall>,
+    /// Whether the stream was made of Anthropic message events.
+    anthropic: bool,
+}
+
+#[derive(Debug, Default)]
+struct ToolCall {
+    id: String,
+    name: String,
+    /// The JSON arguments of the call, as streamed.
+    arguments: String,
+}
+
+impl ToolCall {
+    fn openai(self) -> Value {
+        json!({
+            "id": self.id,
+            "type": "function",
+            "function": {"name": self.name, "arguments": self.arguments},
+        })
+    }
+
+    fn anthropic(self) -> Value {
+        let input = serde_json::from_str::<Value>(&self.arguments)
+            .unwrap_or_else(|_| json!({}));
+        json!({
+            "type": "tool_use",
+            "id": self.id,
+            "name": self.name,
+            "input": input,
+        })
+    }
+}
+
+impl StreamedReply {
+    pub(crate) fn push(&mut self, bytes: &[u8]) {
+        self.buffer.push_str(&String::from_utf8_lossy(bytes));
+        while let Some(end) = self.buffer.find('\n') {
+            let line = self.buffer.drain(..=end).collect::<String>();
+            self.line(&line);
+        }
+    }
+
+    fn line(&mut self, line: &str) {
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            return;
+        };
+        let Ok(event) = serde_json::from_str::<Value>(data.trim()) else {
+            return;
+        };
+        // OpenAI chat completion chunks
+        if let Some(choices) = event.get("choices") {
+            if self.id.is_none() {
+                self.id = event
+                    .get("id")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string);
+            }
+            if let Some(content) =
+                choices.pointer("/0/delta/content").and_then(Value::as_str)
+            {
+                self.content.push_str(content);
+            }
+            let tool_calls = choices
+                .pointer("/0/delta/tool_calls")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten();
+            for delta in tool_calls {
+                let index = delta
+                    .get("index")
+                    .and_then(Value::as_u64)
+                    .unwrap_or_default();
+                let call = self.tool_calls.entry(index).or_default();
+                if let Some(id) = delta.get("id").and_then(Value::as_str) {
+                    call.id = id.to_string();
+                }
+                if let Some(name) =
+                    delta.pointer("/function/name").and_then(Value::as_str)
+                {
+                    call.name.push_str(name);
+                }
+                if let Some(arguments) =
+                    delta.pointer("/function/arguments").and_then(Value::as_str)
+                {
+                    call.arguments.push_str(arguments);
+                }
+            }
+            return;
+        }
+        // Anthropic message events
+        let index = event
+            .get("index")
+            .and_then(Value::as_u64)
+            .unwrap_or_default();
+        match event.get("type").and_then(Value::as_str) {
+            Some("message_start") => {
+                self.anthropic = true;
+                self.id = event
+                    .pointer("/message/id")
+                    .and_then(Value::as_str)
+                    .map(ToString::to_string);
+            }
+            Some("content_block_start") => {
+                let block = event.get("content_block");
+                if block.and_then(|block| block.get("type"))
+                    == Some(&Value::from("tool_use"))
+                {
+                    let field = |field: &str| {
+                        block
+                            .and_then(|block| block.get(field))
+                            .and_then(Value::as_str)
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+                    self.tool_calls.insert(
+                        index,
+                        ToolCall {
+                            id: field("id"),
+                            name: field("name"),
+                            arguments: String::new(),
+                        },
+                    );
+                }
+            }
+            Some("content_block_delta") => {
+                if let Some(text) =
+                    event.pointer("/delta/text").and_then(Value::as_str)
+                {
+                    self.content.push_str(text);
+                }
+                if let Some(partial_json) =
+                    event.pointer("/delta/partial_json").and_then(Value::as_str)
+                    && let Some(call) = self.tool_calls.get_mut(&index)
+                {
+                    call.arguments.push_str(partial_json);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the content of the assistant message of a completed stream.
+    pub(crate) fn into_content(mut self) -> String {
+        let line = std::mem::take(&mut self.buffer);
+        self.line(&line);
+        self.content
+    }
+
+    /// Returns the response id and assistant message of a completed stream.
+    fn finish(&mut self) -> Option<(String, Value)> {
+        let line = std::mem::take(&mut self.buffer);
+        self.line(&line);
+        let id = self.id.take()?;
+        let content = std::mem::take(&mut self.content);
+        if self.tool_calls.is_empty() {
+            return Some((
+                id,
+                json!({"role": "assistant", "content": content}),
+            ));
+        }
+        let tool_calls = std::mem::take(&mut self.tool_calls).into_values();
+        let message = if self.anthropic {
+            let text = (!content.is_empty())
+                .then(|| json!({"type": "text", "text": content}));
+            let blocks = text
+                .into_iter()
+                .chain(tool_calls.map(ToolCall::anthropic))
+                .collect::<Vec<_>>();
+            json!({"role": "assistant", "content": blocks})
+        } else {
+            let tool_calls =
+                tool_calls.map(ToolCall::openai).collect::<Vec<_>>();
+            json!({
+                "role": "assistant",
+                "content": (!content.is_empty()).then_some(content),
+                "tool_calls": tool_calls,
+            })
+        };
+        Some((id, message))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    store: ConversationStore,
+    config: Arc<ConversationsConfig>,
+}
+
+impl Layer {
+    pub fn for_router(
+        app_state: &AppState,
+        router_config: &RouterConfig,
+    ) -> Result<Option<Self>, InitError> {
+        let Some(config) = &router_config.conversations else {
+            return Ok(None);
+        };
+        let store = app_state
+            .0
+            .conversation_store
+            .clone()
+            .ok_or(InitError::StoreNotConfigured("conversation_store"))?;
+        Ok(Some(Self {
+            store,
+            config: Arc::new(config.clone()),
+        }))
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            store: self.store.clone(),
+            config: Arc::clone(&self.config),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    store: ConversationStore,
+    config: Arc<ConversationsConfig>,
+}
+
+impl<S> Service<S> {
+    /// Stores the conversation made of `messages` and the assistant's
+    /// `reply` under the `id` of the reply, in the background.
+    fn save(
+        &self,
+        id: String,
+        owner: Option<String>,
+        mut messages: Vec<Value>,
+        reply: Value,
+    ) {
+        let store = self.store.clone();
+        let config = Arc::clone(&self.config);
+        task::spawn("conversation-store", async move {
+            messages.push(reply);
+            trim(&mut messages, &config);
+            let conversation = Conversation { owner, messages };
+            if let Err(error) = store.put(&id, &conversation, config.ttl).await
+            {
+                tracing::warn!(error = %error, "failed to store conversation");
+            }
+        });
+    }
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "conversation", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let this = self.clone();
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let Ok(Value::Object(mut request)) =
+                serde_json::from_slice::<Value>(&body_bytes)
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+            let owner = parts
+                .extensions
+                .get::<AuthContext>()
+                .map(|auth_ctx| hash_key(auth_ctx.api_key.expose()));
+
+            let mut messages = Vec::new();
+            if let Some(previous_response_id) =
+                request.remove(PREVIOUS_RESPONSE_ID)
+            {
+                let previous_response_id =
+                    previous_response_id.as_str().unwrap_or_default();
+                let conversation = this
+                    .store
+                    .get(previous_response_id)
+                    .await?
+                    .filter(|conversation| conversation.owner == owner)
+                    .ok_or_else(|| {
+                        InvalidRequestError::NotFound(format!(
+                            "conversation for response {previous_response_id}"
+                        ))
+                    })?;
+                messages = conversation.messages;
+            }
+            if let Some(Value::Array(new_messages)) = request.get("messages") {
+                messages.extend(new_messages.iter().cloned());
+            }
+            request
+                .insert("messages".to_string(), Value::from(messages.clone()));
+            let is_stream =
+                request.get("stream").and_then(Value::as_bool) == Some(true);
+            let body = serde_json::to_vec(&request).map_err(|error| {
+                InternalError::Serialize {
+                    ty: "serde_json::Value",
+                    error,
+                }
+            })?;
+
+            let response =
+                inner.call(Request::from_parts(parts, body.into())).await?;
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+            let (parts, body) = response.into_parts();
+            if is_stream {
+                let reply = Arc::new(Mutex::new(StreamedReply::default()));
+                let stream = body
+                    .into_data_stream()
+                    .inspect_ok({
+                        let reply = Arc::clone(&reply);
+                        move |bytes| {
+                            reply
+                                .lock()
+                                .expect("streamed reply lock poisoned")
+                                .push(bytes);
+                        }
+                    })
+                    .map(Some)
+                    .chain(futures::stream::once(async move {
+                        let finished = reply
+                            .lock()
+                            .expect("streamed reply lock poisoned")
+                            .finish();
+                        if let Some((id, reply)) = finished {
+                            this.save(id, owner, messages, reply);
+                        }
+                        None
+                    }))
+                    .filter_map(futures::future::ready);
+                let body = axum_core::body::Body::from_stream(stream);
+                return Ok(Response::from_parts(parts, body));
+            }
+
+            let response_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let response_json =
+                serde_json::from_slice::<Value>(&response_bytes).ok();
+            let saved = response_json.as_ref().and_then(|response_json| {
+                Some((
+                    response_json.get("id")?.as_str()?.to_string(),
+                    assistant_message(response_json)?,
+                ))
+            });
+            if let Some((id, reply)) = saved {
+                this.save(id, owner, messages, reply);
+            }
+            Ok(Response::from_parts(parts, response_bytes.into()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn assistant_messages() {
+        let completion = json!({
+            "id": "chatcmpl-1",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "hi"},
+            }],
+        });
+        assert_eq!(
+            assistant_message(&completion),
+            Some(json!({"role": "assistant", "content": "hi"}))
+        );
+        let message = json!({
+            "id": "msg_1",
+            "type": "message",
+            "content": [{"type": "text", "text": "hi"}],
+        });
+        assert_eq!(
+            assistant_message(&message),
+            Some(json!({
+                "role": "assistant",
+                "content": [{"type": "text", "text": "hi"}],
+            }))
+        );
+        assert_eq!(assistant_message(&json!({"error": {}})), None);
+    }
+
+    #[test]
+    fn trim_keeps_system_messages() {
+        let config = ConversationsConfig {
+            max_messages: 3,
+            ..Default::default()
+        };
+        let mut messages = vec![
+            json!({"role": "system", "content": "be brief"}),
+            json!({"role": "user", "content": "1"}),
+            json!({"role": "assistant", "content": "2"}),
+            json!({"role": "user", "content": "3"}),
+        ];
+        trim(&mut messages, &config);
+        assert_eq!(
+            messages,
+            vec![
+                json!({"role": "system", "content": "be brief"}),
+                json!({"role": "assistant", "content": "2"}),
+                json!({"role": "user", "content": "3"}),
+            ]
+        );
+
+        let config = ConversationsConfig {
+            max_bytes: 70,
+            ..Default::default()
+        };
+        trim(&mut messages, &config);
+        assert_eq!(
+            messages,
+            vec![
+                json!({"role": "system", "content": "be brief"}),
+                json!({"role": "user", "content": "3"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn trim_drops_tool_results_with_their_calls() {
+        let config = ConversationsConfig {
+            max_messages: 2,
+            ..Default::default()
+        };
+        let mut messages = vec![
+            json!({"role": "user", "content": "weather?"}),
+            json!({"role": "assistant", "content": null, "tool_calls": []}),
+            json!({"role": "tool", "tool_call_id": "1", "content": "sunny"}),
+            json!({"role": "tool", "tool_call_id": "2", "content": "warm"}),
+            json!({"role": "assistant", "content": "sunny and warm"}),
+        ];
+        trim(&mut messages, &config);
+        assert_eq!(
+            messages,
+            vec![json!({"role": "assistant", "content": "sunny and warm"})]
+        );
+
+        let mut messages = vec![
+            json!({"role": "assistant", "content": [{"type": "tool_use"}]}),
+            json!({"role": "user", "content": [{"type": "tool_result"}]}),
+            json!({"role": "user", "content": "thanks"}),
+            json!({"role": "assistant", "content": "welcome"}),
+        ];
+        trim(&mut messages, &config);
+        assert_eq!(
+            messages,
+            vec![
+                json!({"role": "user", "content": "thanks"}),
+                json!({"role": "assistant", "content": "welcome"}),
+            ]
+        );
+    }
+
+    #[test]
+    fn streamed_replies() {
+        let mut reply = StreamedReply::default();
+        reply.push(b"data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"delta\":");
+        reply.push(b"{\"content\":\"Hel\"}}]}\n\n");
+        reply.push(
+            b"data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\ndata: [DONE]\n\n",
+        );
+        assert_eq!(
+            reply.finish(),
+            Some((
+                "chatcmpl-1".to_string(),
+                json!({"role": "assistant", "content": "Hello"})
+            ))
+        );
+
+        let mut reply = StreamedReply::default();
+        reply.push(
+            b"event: message_start\ndata: {\"type\":\"message_start\",\"message\":{\"id\":\"msg_1\"}}\n\n",
+        );
+        reply.push(
+            b"data: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+        );
+        assert_eq!(
+            reply.finish(),
+            Some((
+                "msg_1".to_string(),
+                json!({"role": "assistant", "content": "Hi"})
+            ))
+        );
+    }
+
+    #[test]
+    fn streamed_tool_calls() {
+        let mut reply = StreamedReply::default();
+        for chunk in [
+            json!({"id": "chatcmpl-1", "choices": [{"delta": {"tool_calls": [
+                {"index": 0, "id": "call_1", "type": "function",
+                 "function": {"name": "weather", "arguments": ""}},
+            ]}}]}),
+            json!({"id": "chatcmpl-1", "choices": [{"delta": {"tool_calls": [
+                {"index": 0, "function": {"arguments": "{\"city\":"}},
+            ]}}]}),
+            json!({"id": "chatcmpl-1", "choices": [{"delta": {"tool_calls": [
+                {"index": 0, "function": {"arguments": "\"Paris\"}"}},
+            ]}}]}),
+        ] {
+            reply.push(format!("data: {chunk}\n\n").as_bytes());
+        }
+        assert_eq!(
+            reply.finish(),
+            Some((
+                "chatcmpl-1".to_string(),
+                json!({
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {
+                            "name": "weather",
+                            "arguments": "{\"city\":\"Paris\"}",
+                        },
+                    }],
+                })
+            ))
+        );
+
+        let mut reply = StreamedReply::default();
+        for event in [
+            json!({"type": "message_start", "message": {"id": "msg_1"}}),
+            json!({"type": "content_block_start", "index": 0,
+                   "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0,
+                   "delta": {"type": "text_delta", "text": "Checking"}}),
+            json!({"type": "content_block_start", "index": 1,
+                   "content_block": {"type": "tool_use", "id": "toolu_1",
+                                     "name": "weather", "input": {}}}),
+            json!({"type": "content_block_delta", "index": 1,
+                   "delta": {"type": "input_json_delta",
+                             "partial_json": "{\"city\": \"Pa"}}),
+            json!({"type": "content_block_delta", "index": 1,
+                   "delta": {"type": "input_json_delta",
+                             "partial_json": "ris\"}"}}),
+        ] {
+            reply.push(format!("data: {event}\n\n").as_bytes());
+        }
+        assert_eq!(
+            reply.finish(),
+            Some((
+                "msg_1".to_string(),
+                json!({
+                    "role": "assistant",
+                    "content": [
+                        {"type": "text", "text": "Checking"},
+                        {
+                            "type": "tool_use",
+                            "id": "toolu_1",
+                            "name": "weather",
+                            "input": {"city": "Paris"},
+                        },
+                    ],
+                })
+            ))
+        );
+    }
+}
@@ -0,0 +1,277 @@
+//! Per-request cost caps.
+//!
+//! A request with `helicone-max-cost-usd: 0.05` may cost at most 5 cents.
+//! Its cost is estimated as the price of its estimated prompt tokens plus
+//! the price of its max completion tokens, per the requested model's
+//! capabilities. If that exceeds the cap, the request's max tokens are
+//! clamped to what the rest of the cap affords, and if the cap doesn't even
+//! afford the prompt and a single completion token, the request is rejected.
+use std::{
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::HeaderName;
+use http_body_util::BodyExt;
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use serde_json::Value;
+
+use crate::{
+    app_state::AppState,
+    config::model_capabilities::{ModelCapabilitiesConfig, ModelCapability},
+    error::{
+        api::ApiError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    logger::usage::Usage,
+    metrics::spend::cost,
+    middleware::context_length::{estimate_blocking, estimate_prompt_tokens},
+    types::{model_id::ModelId, request::Request, response::Response},
+};
+
+pub const MAX_COST_HEADER: HeaderName =
+    HeaderName::from_static("helicone-max-cost-usd");
+
+/// The max completion tokens of `request`, with an estimated
+/// `prompt_tokens`, which keep its estimated cost under `max_cost`, or `None`
+/// if its own max tokens already do.
+fn capped_max_tokens(
+    capability: &ModelCapability,
+    request: &Value,
+    prompt_tokens: u32,
+    max_cost: Decimal,
+) -> Result<Option<u32>, InvalidRequestError> {
+    let prompt_cost = cost(
+        capability,
+        &Usage {
+            requests: 1,
+            prompt_tokens: prompt_tokens.into(),
+            completion_tokens: 0,
+        },
+    );
+    let cost_per_completion_token = cost(
+        capability,
+        &Usage {
+            requests: 1,
+            prompt_tokens: 0,
+            completion_tokens: 1,
+        },
+    );
+    let remaining = max_cost - prompt_cost;
+    if remaining < cost_per_completion_token {
+        return Err(InvalidRequestError::CostCapExceeded(
+            prompt_cost.round_dp(6).normalize(),
+            max_cost,
+        ));
+    }
+    if cost_per_completion_token.is_zero() {
+        return Ok(None);
+    }
+    let affordable = (remaining / cost_per_completion_token)
+        .floor()
+        .to_u32()
+        .unwrap_or(u32::MAX);
+    let requested = request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .and_then(Value::as_u64);
+    match requested {
+        Some(requested) if requested <= u64::from(affordable) => Ok(None),
+        _ => Ok(Some(affordable)),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    capabilities: Arc<ModelCapabilitiesConfig>,
+}
+
+impl Layer {
+    #[must_use]
+    pub fn new(app_state: &AppState) -> Self {
+        Self {
+            capabilities: Arc::new(
+                app_state.config().model_capabilities.clone(),
+            ),
+        }
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            capabilities: Arc::clone(&self.capabilities),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    capabilities: Arc<ModelCapabilitiesConfig>,
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "cost_cap", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let capabilities = Arc::clone(&self.capabilities);
+        Box::pin(async move {
+            let Some(max_cost) = req.headers().get(MAX_COST_HEADER) else {
+                return inner.call(req).await;
+            };
+            let max_cost = max_cost
+                .to_str()
+                .ok()
+                .and_then(|max_cost| Decimal::from_str(max_cost.trim()).ok())
+                .filter(|max_cost| *max_cost > Decimal::ZERO)
+                .ok_or_else(|| {
+                    InvalidRequestError::InvalidMaxCost(format!("{max_cost:?}"))
+                })?;
+
+            let (parts, body) = req.into_parts();
+            let body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let Some(request_json) =
+                serde_json::from_slice::<Value>(&body_bytes).ok()
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+            let Some(model) = request_json
+                .get("model")
+                .and_then(Value::as_str)
+                .and_then(|model| ModelId::from_str(model).ok())
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+            let capability = capabilities.get(&model).ok_or_else(|| {
+                InvalidRequestError::CostCapUnknownPrice(model.to_string())
+            })?;
+
+            let (mut request_json, prompt_tokens) = estimate_blocking(
+                body_bytes.len(),
+                request_json,
+                estimate_prompt_tokens,
+            )
+            .await?;
+            let capped = capped_max_tokens(
+                capability,
+                &request_json,
+                prompt_tokens,
+                max_cost,
+            )?;
+            let body_bytes = match capped {
+                Some(max_tokens) => {
+                    tracing::debug!(
+                        model = %model,
+                        max_cost = %max_cost,
+                        max_tokens,
+                        "clamped max tokens to cost cap"
+                    );
+                    let field = if request_json
+                        .get("max_completion_tokens")
+                        .is_some()
+                    {
+                        "max_completion_tokens"
+                    } else {
+                        "max_tokens"
+                    };
+                    request_json[field] = Value::from(max_tokens);
+                    serde_json::to_vec(&request_json).map(Bytes::from).map_err(
+                        |error| InternalError::Serialize {
+                            ty: "serde_json::Value",
+                            error,
+                        },
+                    )?
+                }
+                None => body_bytes,
+            };
+
+            let req = Request::from_parts(parts, body_bytes.into());
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn max_tokens_are_clamped_to_the_cap() {
+        let capability = ModelCapability {
+            context_window: 128_000,
+            input_cost_per_mtok: Decimal::from(2),
+            output_cost_per_mtok: Some(Decimal::from(8)),
+            logprobs: false,
+        };
+        // 1000 prompt tokens cost $0.002
+        let prompt_tokens = 1000;
+        let mut request = json!({"model": "openai/gpt-4o"});
+
+        // $0.008 affords 750 completion tokens
+        let max_cost = Decimal::new(8, 3);
+        assert_eq!(
+            capped_max_tokens(&capability, &request, prompt_tokens, max_cost)
+                .unwrap(),
+            Some(750)
+        );
+        request["max_tokens"] = json!(500);
+        assert_eq!(
+            capped_max_tokens(&capability, &request, prompt_tokens, max_cost)
+                .unwrap(),
+            None
+        );
+        request["max_tokens"] = json!(1000);
+        assert_eq!(
+            capped_max_tokens(&capability, &request, prompt_tokens, max_cost)
+                .unwrap(),
+            Some(750)
+        );
+
+        let result = capped_max_tokens(
+            &capability,
+            &request,
+            prompt_tokens,
+            Decimal::new(2, 3),
+        );
+        assert!(matches!(
+            result,
+            Err(InvalidRequestError::CostCapExceeded(prompt_cost, _))
+                if prompt_cost == Decimal::new(2, 3)
+        ));
+    }
+}
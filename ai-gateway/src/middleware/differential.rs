@@ -0,0 +1,479 @@
+//! Differential testing of candidate models.
+//!
+//! A sample of the non-streaming requests for each configured model is also
+//! sent to a candidate model once the client's response is ready. The
+//! completions of both models are compared in the background and the result
+//! is logged and recorded in metrics, without affecting the client's
+//! response. Candidate requests are marked as [`Shadow`] requests, so they
+//! aren't logged or counted toward usage and spend.
+use std::{
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http_body_util::BodyExt;
+use opentelemetry::KeyValue;
+use rust_decimal::prelude::ToPrimitive;
+use rustc_hash::FxHashMap as HashMap;
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{
+    app_state::AppState,
+    config::{
+        differential::{Comparison, DifferentialConfig},
+        router::RouterConfig,
+    },
+    error::{api::ApiError, internal::InternalError},
+    types::{
+        extensions::Shadow, model_id::ModelId, request::Request,
+        response::Response,
+    },
+    utils::task,
+};
+
+/// Term overlap above which two completions are considered a match.
+const TERM_OVERLAP_THRESHOLD: f64 = 0.9;
+/// Maximum number of differing JSON paths included in the log.
+const MAX_LOGGED_DIFFERENCES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+struct DiffOutcome {
+    matched: bool,
+    score: f64,
+    /// Paths at which the completions differ, for JSON comparisons.
+    differences: Vec<String>,
+}
+
+/// Extracts the completion text from a chat completion response, joining the
+/// content of all choices.
+fn completion(response: &Value) -> Option<String> {
+    let contents = response
+        .get("choices")?
+        .as_array()?
+        .iter()
+        .filter_map(|choice| choice.pointer("/message/content"))
+        .filter_map(Value::as_str)
+        .collect::<Vec<_>>();
+    (!contents.is_empty()).then(|| contents.join("\n"))
+}
+
+fn compare(
+    comparison: Comparison,
+    primary: &str,
+    candidate: &str,
+) -> DiffOutcome {
+    match comparison {
+        Comparison::Exact => {
+            let matched = primary == candidate;
+            DiffOutcome {
+                matched,
+                score: if matched { 1.0 } else { 0.0 },
+                differences: Vec::new(),
+            }
+        }
+        Comparison::Json => {
+            let parsed = (
+                serde_json::from_str::<Value>(primary),
+                serde_json::from_str::<Value>(candidate),
+            );
+            let mut differences = Vec::new();
+            match parsed {
+                (Ok(primary), Ok(candidate)) => {
+                    json_diff(&primary, &candidate, "", &mut differences);
+                }
+                _ => differences.push("<invalid json>".to_string()),
+            }
+            let matched = differences.is_empty();
+            DiffOutcome {
+                matched,
+                score: if matched { 1.0 } else { 0.0 },
+                differences,
+            }
+        }
+        Comparison::TermOverlap => {
+            let score = term_overlap(primary, candidate);
+            DiffOutcome {
+                matched: score >= TERM_OVERLAP_THRESHOLD,
+                score,
+                differences: Vec::new(),
+            }
+        }
+    }
+}
+
+/// Collects the JSON pointers at which `a` and `b` differ structurally.
+fn json_diff(a: &Value, b: &Value, path: &str, differences: &mut Vec<String>) {
+    match (a, b) {
+        (Value::Object(a), Value::Object(b)) => {
+            let mut keys = a.keys().chain(b.keys()).collect::<Vec<_>>();
+            keys.sort_unstable();
+            keys.dedup();
+            for key in keys {
+                let path = format!("{path}/{key}");
+                match (a.get(key), b.get(key)) {
+                    (Some(a), Some(b)) => json_diff(a, b, &path, differences),
+                    _ => differences.push(path),
+                }
+            }
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            for i in 0..a.len().max(b.len()) {
+                let path = format!("{path}/{i}");
+                match (a.get(i), b.get(i)) {
+                    (Some(a), Some(b)) => json_diff(a, b, &path, differences),
+                    _ => differences.push(path),
+                }
+            }
+        }
+        (a, b) if a == b => {}
+        _ => differences.push(if path.is_empty() {
+            "/".to_string()
+        } else {
+            path.to_string()
+        }),
+    }
+}
+
+/// Cosine similarity of the term frequency vectors of `a` and `b`.
+///
+/// This is a lexical measure: paraphrases score low even if they mean the
+/// same.
+#[allow(clippy::cast_precision_loss)]
+fn term_overlap(a: &str, b: &str) -> f64 {
+    fn term_frequencies(text: &str) -> HashMap<String, usize> {
+        let mut frequencies = HashMap::default();
+        for term in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|term| !term.is_empty())
+        {
+            *frequencies.entry(term.to_lowercase()).or_default() += 1;
+        }
+        frequencies
+    }
+
+    let (a, b) = (term_frequencies(a), term_frequencies(b));
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let dot = a
+        .iter()
+        .filter_map(|(term, count)| b.get(term).map(|other| count * other))
+        .sum::<usize>() as f64;
+    let norm = |frequencies: &HashMap<String, usize>| {
+        (frequencies
+            .values()
+            .map(|count| count * count)
+            .sum::<usize>() as f64)
+            .sqrt()
+    };
+    let norms = norm(&a) * norm(&b);
+    if norms == 0.0 { 0.0 } else { dot / norms }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    app_state: AppState,
+    config: Arc<DifferentialConfig>,
+}
+
+impl Layer {
+    #[must_use]
+    pub fn for_router(
+        app_state: &AppState,
+        router_config: &RouterConfig,
+    ) -> Option<Self> {
+        router_config.differential.as_ref().map(|config| Self {
+            app_state: app_state.clone(),
+            config: Arc::new(config.clone()),
+        })
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            app_state: self.app_state.clone(),
+            config: Arc::clone(&self.config),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    app_state: AppState,
+    config: Arc<DifferentialConfig>,
+}
+
+impl<S> Service<S> {
+    /// Returns the candidate model and candidate request body if `request`
+    /// is sampled for differential testing.
+    fn candidate_request(&self, request: &Value) -> Option<(ModelId, Bytes)> {
+        if request.get("stream").and_then(Value::as_bool) == Some(true) {
+            return None;
+        }
+        let model = request
+            .get("model")
+            .and_then(Value::as_str)
+            .and_then(|model| ModelId::from_str(model).ok())?;
+        let candidate = self.config.candidate(&model)?;
+        let sample_rate = self.config.sample_rate.to_f64().unwrap_or_default();
+        if rand::random::<f64>() >= sample_rate {
+            return None;
+        }
+        let mut request = request.clone();
+        request["model"] = serde_json::to_value(candidate).ok()?;
+        let body = serde_json::to_vec(&request).ok()?;
+        Some((candidate.clone(), Bytes::from(body)))
+    }
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "differential", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let this = self.clone();
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let request_json =
+                serde_json::from_slice::<Value>(&body_bytes).ok();
+            let candidate = request_json.as_ref().and_then(|request_json| {
+                Some((
+                    request_json
+                        .get("model")
+                        .and_then(Value::as_str)?
+                        .to_string(),
+                    this.candidate_request(request_json)?,
+                ))
+            });
+            let Some((model, (candidate_model, candidate_body))) = candidate
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+
+            let mut candidate_req =
+                Request::from_parts(parts.clone(), candidate_body.into());
+            candidate_req.extensions_mut().insert(Shadow);
+            let response = inner
+                .call(Request::from_parts(parts, body_bytes.into()))
+                .await?;
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+            let (parts, body) = response.into_parts();
+            let response_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+
+            let candidate = this.inner.clone();
+            let response_json =
+                serde_json::from_slice::<Value>(&response_bytes).ok();
+            task::spawn("differential", async move {
+                let Some(primary) = response_json.as_ref().and_then(completion)
+                else {
+                    return;
+                };
+                this.run_candidate(
+                    candidate,
+                    candidate_req,
+                    &model,
+                    &candidate_model,
+                    &primary,
+                )
+                .await;
+            });
+
+            Ok(Response::from_parts(parts, response_bytes.into()))
+        })
+    }
+}
+
+impl<S> Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    async fn run_candidate(
+        &self,
+        candidate: S,
+        req: Request,
+        model: &str,
+        candidate_model: &ModelId,
+        primary: &str,
+    ) {
+        let candidate_model = candidate_model.to_string();
+        let comparison = self.config.comparison;
+        let attributes = [
+            KeyValue::new("model", model.to_string()),
+            KeyValue::new("candidate_model", candidate_model.clone()),
+        ];
+        let metrics = &self.app_state.0.metrics;
+
+        let response = match candidate.oneshot(req).await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => {
+                tracing::info!(
+                    model,
+                    candidate_model = %candidate_model,
+                    status = %response.status(),
+                    "differential candidate request failed"
+                );
+                metrics.differential.errors.add(1, &attributes);
+                return;
+            }
+            Err(error) => {
+                tracing::info!(
+                    model,
+                    candidate_model = %candidate_model,
+                    error = %error,
+                    "differential candidate request failed"
+                );
+                metrics.differential.errors.add(1, &attributes);
+                return;
+            }
+        };
+        let candidate_completion = match response.into_body().collect().await {
+            Ok(body) => serde_json::from_slice::<Value>(&body.to_bytes())
+                .ok()
+                .as_ref()
+                .and_then(completion),
+            Err(_) => None,
+        };
+        let Some(candidate_completion) = candidate_completion else {
+            metrics.differential.errors.add(1, &attributes);
+            return;
+        };
+
+        let outcome = compare(comparison, primary, &candidate_completion);
+        let differences = outcome
+            .differences
+            .iter()
+            .take(MAX_LOGGED_DIFFERENCES)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(",");
+        tracing::info!(
+            model,
+            candidate_model = %candidate_model,
+            comparison = ?comparison,
+            matched = outcome.matched,
+            score = outcome.score,
+            differences = %differences,
+            "differential comparison"
+        );
+        metrics.differential.comparisons.add(
+            1,
+            &[
+                attributes[0].clone(),
+                attributes[1].clone(),
+                KeyValue::new("matched", outcome.matched),
+            ],
+        );
+        metrics
+            .differential
+            .scores
+            .record(outcome.score, &attributes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn completion_joins_choices() {
+        let response = json!({
+            "choices": [
+                {"message": {"role": "assistant", "content": "a"}},
+                {"message": {"role": "assistant", "content": "b"}},
+            ]
+        });
+        assert_eq!(completion(&response).as_deref(), Some("a\nb"));
+        assert_eq!(completion(&json!({"choices": []})), None);
+    }
+
+    #[test]
+    fn exact_comparison() {
+        assert!(compare(Comparison::Exact, "Paris", "Paris").matched);
+        assert!(!compare(Comparison::Exact, "Paris", "paris").matched);
+    }
+
+    #[test]
+    fn json_comparison_ignores_formatting_and_order() {
+        let outcome = compare(
+            Comparison::Json,
+            r#"{"city": "Paris", "tags": [1, 2]}"#,
+            r#"{ "tags":[1,2],"city":"Paris" }"#,
+        );
+        assert!(outcome.matched);
+    }
+
+    #[test]
+    fn json_comparison_reports_differing_paths() {
+        let outcome = compare(
+            Comparison::Json,
+            r#"{"city": "Paris", "tags": [1, 2], "a": 1}"#,
+            r#"{"city": "Lyon", "tags": [1], "b": 1}"#,
+        );
+        assert!(!outcome.matched);
+        assert_eq!(outcome.differences, ["/a", "/b", "/city", "/tags/1"]);
+
+        let outcome = compare(Comparison::Json, "{}", "not json");
+        assert_eq!(outcome.differences, ["<invalid json>"]);
+    }
+
+    #[test]
+    fn term_overlap_comparison() {
+        let outcome = compare(
+            Comparison::TermOverlap,
+            "The capital of France is Paris.",
+            "the capital of france is paris",
+        );
+        assert!(outcome.matched);
+        assert!((outcome.score - 1.0).abs() < 1e-9);
+
+        let outcome =
+            compare(Comparison::TermOverlap, "The capital is Paris", "No idea");
+        assert!(!outcome.matched);
+        assert!(outcome.score.abs() < 1e-9);
+    }
+}
@@ -0,0 +1,563 @@
+//! Validation of streamed JSON mode output.
+//!
+//! Streamed completions which request JSON with `response_format` are
+//! validated once their stream ends: their output must parse as JSON and, for
+//! `json_schema` requests, match the requested schema. Depending on the
+//! router's config, invalid output either ends the stream with an error
+//! event, or is replaced by the output of the request retried without
+//! streaming and at temperature 0.
+//!
+//! Schemas are checked for `type`, `enum`, `const`, `properties`, `required`,
+//! `additionalProperties`, `items` and `anyOf`; other keywords are ignored.
+use std::{
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt, future::BoxFuture};
+use http_body_util::BodyExt;
+use serde_json::{Value, json};
+use tower::ServiceExt;
+
+use crate::{
+    config::{json_mode::OnInvalidJson, router::RouterConfig},
+    error::{api::ApiError, internal::InternalError},
+    middleware::{
+        conversation::StreamedReply, mapper::openai::SERVER_ERROR_TYPE,
+    },
+    types::{request::Request, response::Response},
+};
+
+const INVALID_JSON_OUTPUT_CODE: &str = "invalid_json_output";
+const DONE_EVENT: &[u8] = b"data: [DONE]";
+
+/// The output demanded by a request.
+#[derive(Debug, Clone, PartialEq)]
+enum ExpectedOutput {
+    Json,
+    Schema(Value),
+}
+
+impl ExpectedOutput {
+    /// Returns the output demanded by a streamed request, if it demands JSON.
+    fn of(request: &Value) -> Option<Self> {
+        if request.get("stream").and_then(Value::as_bool) != Some(true) {
+            return None;
+        }
+        let response_format = request.get("response_format")?;
+        match response_format.get("type").and_then(Value::as_str)? {
+            "json_object" => Some(Self::Json),
+            "json_schema" => Some(
+                response_format
+                    .pointer("/json_schema/schema")
+                    .cloned()
+                    .map_or(Self::Json, Self::Schema),
+            ),
+            _ => None,
+        }
+    }
+
+    fn validate(&self, output: &str) -> Result<(), String> {
+        let value = serde_json::from_str::<Value>(output)
+            .map_err(|error| format!("output is not valid JSON: {error}"))?;
+        match self {
+            Self::Json => Ok(()),
+            Self::Schema(schema) => {
+                validate(&value, schema, "$").map_err(|error| {
+                    format!(
+                        "output does not match the requested schema: {error}"
+                    )
+                })
+            }
+        }
+    }
+}
+
+fn validate(value: &Value, schema: &Value, path: &str) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        // boolean schemas: `true` accepts anything, `false` nothing
+        return if schema.as_bool() == Some(false) {
+            Err(format!("{path} is not allowed"))
+        } else {
+            Ok(())
+        };
+    };
+    if let Some(ty) = schema.get("type") {
+        let matches = match ty {
+            Value::String(ty) => has_type(value, ty),
+            Value::Array(types) => types
+                .iter()
+                .filter_map(Value::as_str)
+                .any(|ty| has_type(value, ty)),
+            _ => true,
+        };
+        if !matches {
+            return Err(format!("{path} is not of type {ty}"));
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            return Err(format!("{path} is not one of the allowed values"));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            return Err(format!("{path} is not {constant}"));
+        }
+    }
+    if let Some(Value::Array(any_of)) = schema.get("anyOf") {
+        if !any_of
+            .iter()
+            .any(|schema| validate(value, schema, path).is_ok())
+        {
+            return Err(format!("{path} matches none of the allowed schemas"));
+        }
+    }
+    if let Value::Object(object) = value {
+        let required = schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str);
+        for key in required {
+            if !object.contains_key(key) {
+                return Err(format!("{path}.{key} is missing"));
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        for (key, value) in object {
+            let property =
+                properties.and_then(|properties| properties.get(key));
+            if let Some(schema) =
+                property.or_else(|| schema.get("additionalProperties"))
+            {
+                validate(value, schema, &format!("{path}.{key}"))?;
+            }
+        }
+    }
+    if let (Value::Array(items), Some(schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            validate(item, schema, &format!("{path}[{index}]"))?;
+        }
+    }
+    Ok(())
+}
+
+fn has_type(value: &Value, ty: &str) -> bool {
+    match ty {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        _ => true,
+    }
+}
+
+/// Returns `request` with streaming disabled and its sampling made
+/// deterministic, for retrying a request whose output was invalid.
+fn strict_request(mut request: Value) -> Value {
+    if let Some(object) = request.as_object_mut() {
+        object.insert("stream".to_string(), Value::Bool(false));
+        object.insert("temperature".to_string(), Value::from(0));
+        object.remove("stream_options");
+    }
+    if let Some(json_schema) = request
+        .pointer_mut("/response_format/json_schema")
+        .and_then(Value::as_object_mut)
+    {
+        json_schema.insert("strict".to_string(), Value::Bool(true));
+    }
+    request
+}
+
+/// Splits the `[DONE]` event ending an `OpenAI` stream off `bytes`, so that
+/// further events can be sent before it.
+fn split_done(mut bytes: Bytes) -> (Bytes, Option<Bytes>) {
+    match bytes
+        .windows(DONE_EVENT.len())
+        .position(|window| window == DONE_EVENT)
+    {
+        Some(start) => {
+            let done = bytes.split_off(start);
+            (bytes, Some(done))
+        }
+        None => (bytes, None),
+    }
+}
+
+fn sse_event(data: &Value) -> Bytes {
+    Bytes::from(format!("data: {data}\n\n"))
+}
+
+fn error_event(message: &str) -> Bytes {
+    sse_event(&json!({
+        "error": {
+            "message": message,
+            "type": SERVER_ERROR_TYPE,
+            "param": null,
+            "code": INVALID_JSON_OUTPUT_CODE,
+        }
+    }))
+}
+
+/// Returns the events streaming a chat completion as a single chunk.
+fn completion_events(completion: &Value) -> Bytes {
+    let chunk = |delta: Value, finish_reason: Value| {
+        json!({
+            "id": completion["id"],
+            "object": "chat.completion.chunk",
+            "created": completion["created"],
+            "model": completion["model"],
+            "choices": [{
+                "index": 0,
+                "delta": delta,
+                "finish_reason": finish_reason,
+            }],
+        })
+    };
+    let content = completion
+        .pointer("/choices/0/message/content")
+        .cloned()
+        .unwrap_or(Value::Null);
+    let finish_reason = completion
+        .pointer("/choices/0/finish_reason")
+        .cloned()
+        .unwrap_or(Value::Null);
+    let mut last = chunk(json!({}), finish_reason);
+    if let Some(usage) = completion.get("usage") {
+        last["usage"] = usage.clone();
+    }
+    let mut events = BytesMut::new();
+    events.put(sse_event(&chunk(
+        json!({"role": "assistant", "content": content}),
+        Value::Null,
+    )));
+    events.put(sse_event(&last));
+    events.freeze()
+}
+
+/// Passes `response` through, ending its stream with an error event if its
+/// output is invalid.
+fn validate_stream(response: Response, expected: ExpectedOutput) -> Response {
+    let (parts, body) = response.into_parts();
+    let reply = Arc::new(Mutex::new(StreamedReply::default()));
+    let done = Arc::new(Mutex::new(None));
+    let stream = body
+        .into_data_stream()
+        .map_ok({
+            let reply = Arc::clone(&reply);
+            let done = Arc::clone(&done);
+            move |bytes| {
+                reply
+                    .lock()
+                    .expect("streamed reply lock poisoned")
+                    .push(&bytes);
+                let (bytes, done_event) = split_done(bytes);
+                if done_event.is_some() {
+                    *done.lock().expect("done event lock poisoned") =
+                        done_event;
+                }
+                bytes
+            }
+        })
+        .chain(futures::stream::once(async move {
+            let output = std::mem::take(
+                &mut *reply.lock().expect("streamed reply lock poisoned"),
+            )
+            .into_content();
+            let mut tail = BytesMut::new();
+            if let Err(error) = expected.validate(&output) {
+                tracing::debug!(error = %error, "invalid json mode output");
+                tail.put(error_event(&error));
+            }
+            if let Some(done) =
+                done.lock().expect("done event lock poisoned").take()
+            {
+                tail.put(done);
+            }
+            Ok(tail.freeze())
+        }));
+    Response::from_parts(parts, axum_core::body::Body::from_stream(stream))
+}
+
+/// Sends the strict retry of `request`, returning its completion, or why it
+/// failed, if its output is still invalid.
+async fn retry<S>(
+    inner: S,
+    parts: http::request::Parts,
+    request: Value,
+    expected: &ExpectedOutput,
+) -> Result<Result<Value, String>, ApiError>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>,
+{
+    let body =
+        serde_json::to_vec(&strict_request(request)).map_err(|error| {
+            InternalError::Serialize {
+                ty: "serde_json::Value",
+                error,
+            }
+        })?;
+    let response = inner
+        .oneshot(Request::from_parts(parts, body.into()))
+        .await?;
+    if !response.status().is_success() {
+        return Ok(Err(format!(
+            "retry failed with status {}",
+            response.status()
+        )));
+    }
+    let response_bytes = response
+        .into_body()
+        .collect()
+        .await
+        .map_err(InternalError::CollectBodyError)?
+        .to_bytes();
+    let Ok(completion) = serde_json::from_slice::<Value>(&response_bytes)
+    else {
+        return Ok(Err("retry returned an invalid completion".to_string()));
+    };
+    let content = completion
+        .pointer("/choices/0/message/content")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    Ok(expected.validate(content).map(|()| completion))
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    on_invalid: OnInvalidJson,
+}
+
+impl Layer {
+    #[must_use]
+    pub fn for_router(router_config: &RouterConfig) -> Option<Self> {
+        router_config.json_mode.as_ref().map(|config| Self {
+            on_invalid: config.on_invalid,
+        })
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            on_invalid: self.on_invalid,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    on_invalid: OnInvalidJson,
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "json_mode", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let on_invalid = self.on_invalid;
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let Some((request, expected)) =
+                serde_json::from_slice::<Value>(&body_bytes).ok().and_then(
+                    |request| {
+                        let expected = ExpectedOutput::of(&request)?;
+                        Some((request, expected))
+                    },
+                )
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+
+            let retry_inner = inner.clone();
+            let retry_parts = parts.clone();
+            let req = Request::from_parts(parts, body_bytes.into());
+            let response = inner.call(req).await?;
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+            if on_invalid == OnInvalidJson::Error {
+                return Ok(validate_stream(response, expected));
+            }
+
+            let (mut response_parts, body) = response.into_parts();
+            let response_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let (events, done) = split_done(response_bytes.clone());
+            let mut reply = StreamedReply::default();
+            reply.push(&events);
+            let Err(error) = expected.validate(&reply.into_content()) else {
+                return Ok(Response::from_parts(
+                    response_parts,
+                    response_bytes.into(),
+                ));
+            };
+            tracing::debug!(
+                error = %error,
+                "invalid json mode output, retrying without streaming"
+            );
+            let mut events = BytesMut::new();
+            match retry(retry_inner, retry_parts, request, &expected).await? {
+                Ok(completion) => events.put(completion_events(&completion)),
+                Err(error) => {
+                    tracing::debug!(
+                        error = %error,
+                        "invalid json mode output after retry"
+                    );
+                    events.put(error_event(&error));
+                }
+            }
+            if let Some(done) = done {
+                events.put(done);
+            }
+            response_parts.headers.remove(http::header::CONTENT_LENGTH);
+            Ok(Response::from_parts(response_parts, events.freeze().into()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schema() -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "name": {"type": "string"},
+                "age": {"type": ["integer", "null"]},
+                "tags": {"type": "array", "items": {"enum": ["a", "b"]}},
+            },
+            "required": ["name"],
+            "additionalProperties": false,
+        })
+    }
+
+    #[test]
+    fn only_streamed_json_requests_are_validated() {
+        let request = json!({
+            "stream": true,
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {"name": "person", "schema": schema()},
+            },
+        });
+        assert_eq!(
+            ExpectedOutput::of(&request),
+            Some(ExpectedOutput::Schema(schema()))
+        );
+        let request = json!({
+            "stream": true,
+            "response_format": {"type": "json_object"},
+        });
+        assert_eq!(ExpectedOutput::of(&request), Some(ExpectedOutput::Json));
+        let request = json!({
+            "stream": false,
+            "response_format": {"type": "json_object"},
+        });
+        assert_eq!(ExpectedOutput::of(&request), None);
+        let request = json!({
+            "stream": true,
+            "response_format": {"type": "text"},
+        });
+        assert_eq!(ExpectedOutput::of(&request), None);
+    }
+
+    #[test]
+    fn output_is_validated_against_schema() {
+        let expected = ExpectedOutput::Schema(schema());
+        assert!(
+            expected
+                .validate(r#"{"name": "Ada", "age": null, "tags": ["a"]}"#)
+                .is_ok()
+        );
+        assert!(expected.validate(r#"{"name": "Ada", "age": 36}"#).is_ok());
+        assert!(expected.validate(r#"{"age": 36}"#).is_err());
+        assert!(
+            expected
+                .validate(r#"{"name": "Ada", "age": "36"}"#)
+                .is_err()
+        );
+        assert!(
+            expected
+                .validate(r#"{"name": "Ada", "tags": ["c"]}"#)
+                .is_err()
+        );
+        assert!(
+            expected
+                .validate(r#"{"name": "Ada", "email": "ada@example.com"}"#)
+                .is_err()
+        );
+        assert!(expected.validate(r#"{"name": "Ada""#).is_err());
+        assert!(ExpectedOutput::Json.validate("[1, 2]").is_ok());
+        assert!(ExpectedOutput::Json.validate("not json").is_err());
+    }
+
+    #[test]
+    fn done_event_is_split_off() {
+        let (events, done) = split_done(Bytes::from_static(
+            b"data: {\"choices\":[]}\n\ndata: [DONE]\n\n",
+        ));
+        assert_eq!(events, Bytes::from_static(b"data: {\"choices\":[]}\n\n"));
+        assert_eq!(done, Some(Bytes::from_static(b"data: [DONE]\n\n")));
+        let (events, done) =
+            split_done(Bytes::from_static(b"data: {\"choices\":[]}\n\n"));
+        assert_eq!(events, Bytes::from_static(b"data: {\"choices\":[]}\n\n"));
+        assert_eq!(done, None);
+    }
+
+    #[test]
+    fn retry_is_strict() {
+        let request = strict_request(json!({
+            "stream": true,
+            "stream_options": {"include_usage": true},
+            "temperature": 1.2,
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {"name": "person", "schema": schema()},
+            },
+        }));
+        assert_eq!(request["stream"], false);
+        assert_eq!(request["temperature"], 0);
+        assert!(request.get("stream_options").is_none());
+        assert_eq!(request["response_format"]["json_schema"]["strict"], true);
+    }
+}
@@ -0,0 +1,369 @@
+//! Latency SLO based model downgrade.
+//!
+//! Tracks the rolling p95 latency of each requested model and, while it
+//! exceeds the router's configured SLO, transparently rewrites requests to use
+//! the configured fallback model.
+//!
+//! Streamed responses are measured to their first chunk, since that's when
+//! the client sees the first token, and other responses to their headers.
+//! Failed attempts, and attempts cancelled before then such as by a timeout,
+//! are recorded at the SLO at least, so that an erroring model is downgraded
+//! rather than looking fast.
+use std::{
+    str::FromStr,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::{HeaderName, HeaderValue};
+use http_body_util::BodyExt;
+use rustc_hash::FxHashMap as HashMap;
+
+use crate::{
+    config::{latency_slo::LatencySloConfig, router::RouterConfig},
+    error::{api::ApiError, internal::InternalError},
+    metrics::RollingPercentile,
+    types::{
+        body::Body, model_id::ModelId, request::Request, response::Response,
+    },
+};
+
+const DOWNGRADED_FROM_HEADER: HeaderName =
+    HeaderName::from_static("helicone-downgraded-from");
+const SLO_PERCENTILE: f64 = 0.95;
+
+#[derive(Debug)]
+struct LatencyTracker {
+    config: LatencySloConfig,
+    latencies: RwLock<HashMap<ModelId, Arc<RollingPercentile>>>,
+}
+
+impl LatencyTracker {
+    fn latencies_for(&self, model: &ModelId) -> Arc<RollingPercentile> {
+        if let Some(latencies) = self
+            .latencies
+            .read()
+            .expect("latency tracker lock poisoned")
+            .get(model)
+        {
+            return Arc::clone(latencies);
+        }
+        let mut latencies = self
+            .latencies
+            .write()
+            .expect("latency tracker lock poisoned");
+        Arc::clone(latencies.entry(model.clone()).or_insert_with(|| {
+            Arc::new(RollingPercentile::new(self.config.window))
+        }))
+    }
+
+    /// Returns the model to use in place of `model`, if the SLO for `model`
+    /// is currently breached and a fallback is configured.
+    fn downgrade(&self, model: &ModelId) -> Option<ModelId> {
+        let fallback = self.config.fallback(model)?;
+        let latencies = self
+            .latencies
+            .read()
+            .expect("latency tracker lock poisoned")
+            .get(model)
+            .cloned()?;
+        let min_requests =
+            usize::try_from(self.config.min_requests).unwrap_or(usize::MAX);
+        if latencies.len() < min_requests {
+            return None;
+        }
+        let p95 = latencies.percentile(SLO_PERCENTILE)?;
+        if p95 >= self.config.p95 {
+            tracing::debug!(
+                model = %model,
+                fallback = %fallback,
+                p95 = ?p95,
+                slo = ?self.config.p95,
+                "latency slo breached, downgrading model"
+            );
+            Some(fallback.clone())
+        } else {
+            None
+        }
+    }
+}
+
+/// The latency of one attempt, recorded when dropped. Unless the attempt
+/// succeeded, it's recorded at the SLO at least.
+#[derive(Debug)]
+struct Sample {
+    latencies: Arc<RollingPercentile>,
+    start: tokio::time::Instant,
+    slo: Duration,
+    succeeded: bool,
+}
+
+impl Sample {
+    fn start(latencies: Arc<RollingPercentile>, slo: Duration) -> Self {
+        Self {
+            latencies,
+            start: tokio::time::Instant::now(),
+            slo,
+            succeeded: false,
+        }
+    }
+
+    fn succeed(mut self) {
+        self.succeeded = true;
+    }
+}
+
+impl Drop for Sample {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        self.latencies.record(if self.succeeded {
+            elapsed
+        } else {
+            elapsed.max(self.slo)
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    tracker: Arc<LatencyTracker>,
+}
+
+impl Layer {
+    #[must_use]
+    pub fn new(config: LatencySloConfig) -> Self {
+        Self {
+            tracker: Arc::new(LatencyTracker {
+                config,
+                latencies: RwLock::default(),
+            }),
+        }
+    }
+
+    #[must_use]
+    pub fn for_router(router_config: &RouterConfig) -> Option<Self> {
+        router_config
+            .latency_slo
+            .as_ref()
+            .map(|config| Self::new(config.clone()))
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            tracker: Arc::clone(&self.tracker),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    tracker: Arc<LatencyTracker>,
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "latency_slo", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let tracker = Arc::clone(&self.tracker);
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+
+            let Some(mut request_json) =
+                serde_json::from_slice::<serde_json::Value>(&body_bytes).ok()
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+            let Some((requested_model, requested_model_str)) = request_json
+                .get("model")
+                .and_then(serde_json::Value::as_str)
+                .and_then(|model| {
+                    ModelId::from_str(model)
+                        .ok()
+                        .map(|model_id| (model_id, model.to_string()))
+                })
+            else {
+                let req = Request::from_parts(parts, body_bytes.into());
+                return inner.call(req).await;
+            };
+
+            let (target_model, body_bytes) =
+                match tracker.downgrade(&requested_model) {
+                    Some(fallback) => {
+                        request_json["model"] = serde_json::to_value(&fallback)
+                            .map_err(|error| InternalError::Serialize {
+                                ty: "ModelId",
+                                error,
+                            })?;
+                        let body_bytes = serde_json::to_vec(&request_json)
+                            .map(Bytes::from)
+                            .map_err(|error| InternalError::Serialize {
+                                ty: "serde_json::Value",
+                                error,
+                            })?;
+                        (fallback, body_bytes)
+                    }
+                    None => (requested_model.clone(), body_bytes),
+                };
+
+            let is_stream = request_json
+                .get("stream")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or(false);
+            let sample = Sample::start(
+                tracker.latencies_for(&target_model),
+                tracker.config.p95,
+            );
+            let req = Request::from_parts(parts, body_bytes.into());
+            let response = inner.call(req).await?;
+            let mut response = if response.status().is_server_error() {
+                drop(sample);
+                response
+            } else if is_stream {
+                let mut sample = Some(sample);
+                response.map(|body| {
+                    Body::new(body.map_frame(move |frame| {
+                        if frame.is_data()
+                            && let Some(sample) = sample.take()
+                        {
+                            sample.succeed();
+                        }
+                        frame
+                    }))
+                })
+            } else {
+                sample.succeed();
+                response
+            };
+
+            if target_model != requested_model {
+                if let Ok(header_value) =
+                    HeaderValue::from_str(&requested_model_str)
+                {
+                    response
+                        .headers_mut()
+                        .insert(DOWNGRADED_FROM_HEADER, header_value);
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn model(s: &str) -> ModelId {
+        ModelId::from_str(s).unwrap()
+    }
+
+    fn tracker(min_requests: u32) -> LatencyTracker {
+        LatencyTracker {
+            config: LatencySloConfig {
+                p95: Duration::from_millis(500),
+                window: Duration::from_secs(60),
+                min_requests,
+                fallbacks: std::collections::HashMap::from([(
+                    model("openai/gpt-4o"),
+                    model("openai/gpt-4o-mini"),
+                )]),
+            },
+            latencies: RwLock::default(),
+        }
+    }
+
+    #[test]
+    fn no_downgrade_without_enough_samples() {
+        let tracker = tracker(10);
+        let latencies = tracker.latencies_for(&model("openai/gpt-4o"));
+        for _ in 0..5 {
+            latencies.record(Duration::from_secs(2));
+        }
+        assert_eq!(tracker.downgrade(&model("openai/gpt-4o")), None);
+    }
+
+    #[test]
+    fn downgrade_when_slo_breached() {
+        let tracker = tracker(10);
+        let latencies = tracker.latencies_for(&model("openai/gpt-4o"));
+        for _ in 0..10 {
+            latencies.record(Duration::from_secs(2));
+        }
+        assert_eq!(
+            tracker.downgrade(&model("openai/gpt-4o")),
+            Some(model("openai/gpt-4o-mini"))
+        );
+    }
+
+    #[test]
+    fn no_downgrade_within_slo() {
+        let tracker = tracker(10);
+        let latencies = tracker.latencies_for(&model("openai/gpt-4o"));
+        for _ in 0..10 {
+            latencies.record(Duration::from_millis(100));
+        }
+        assert_eq!(tracker.downgrade(&model("openai/gpt-4o")), None);
+    }
+
+    #[test]
+    fn failed_attempts_are_recorded_at_the_slo_at_least() {
+        let tracker = tracker(2);
+        let gpt_4o = model("openai/gpt-4o");
+        let latencies = tracker.latencies_for(&gpt_4o);
+        Sample::start(Arc::clone(&latencies), tracker.config.p95).succeed();
+        drop(Sample::start(latencies, tracker.config.p95));
+        assert_eq!(
+            tracker.downgrade(&gpt_4o),
+            Some(model("openai/gpt-4o-mini"))
+        );
+    }
+
+    #[test]
+    fn no_downgrade_without_fallback() {
+        let tracker = tracker(1);
+        let latencies =
+            tracker.latencies_for(&model("anthropic/claude-3-5-sonnet"));
+        latencies.record(Duration::from_secs(2));
+        assert_eq!(
+            tracker.downgrade(&model("anthropic/claude-3-5-sonnet")),
+            None
+        );
+    }
+}
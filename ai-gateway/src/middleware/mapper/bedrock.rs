@@ -10,6 +10,7 @@ use super::{
     MapperError, TryConvert, TryConvertStreamData, model::ModelMapper,
 };
 use crate::{
+    config::bedrock_guardrail::GuardrailTrace,
     middleware::mapper::{DEFAULT_MAX_TOKENS, TryConvertError},
     types::{model_id::ModelId, provider::InferenceProvider},
 };
@@ -296,6 +297,28 @@ impl
                 .set_stop_sequences(stop_sequences)
                 .build(),
         );
+        if let Some(guardrail) = self
+            .model_mapper
+            .router_config()
+            .and_then(|config| config.bedrock_guardrail.as_ref())
+        {
+            let trace = match guardrail.trace {
+                GuardrailTrace::Disabled => bedrock::GuardrailTrace::Disabled,
+                GuardrailTrace::Enabled => bedrock::GuardrailTrace::Enabled,
+                GuardrailTrace::EnabledFull => {
+                    bedrock::GuardrailTrace::EnabledFull
+                }
+            };
+            let guardrail_config = bedrock::GuardrailConfiguration::builder()
+                .guardrail_identifier(&guardrail.identifier)
+                .guardrail_version(&guardrail.version)
+                .trace(trace)
+                .build()
+                .map_err(|e| {
+                    MapperError::FailedToMapBedrockMessage(e.into())
+                })?;
+            builder = builder.guardrail_config(guardrail_config);
+        }
         let converse_input = builder
             .set_inference_config(inference_config)
             .build()
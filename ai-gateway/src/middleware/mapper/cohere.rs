@@ -0,0 +1,436 @@
+use std::str::FromStr;
+
+use http::response::Parts;
+
+use super::{TryConvert, TryConvertStreamData};
+use crate::{
+    endpoints::cohere::{CohereApiError, chat as cohere},
+    error::mapper::MapperError,
+    middleware::mapper::{
+        TryConvertError, anthropic::OPENAI_CHAT_COMPLETION_OBJECT,
+        model::ModelMapper,
+    },
+    types::{model_id::ModelId, provider::InferenceProvider},
+};
+
+const CHAT_COMPLETION_CHUNK_OBJECT: &str = "chat.completion.chunk";
+/// Cohere's responses don't name their model, nor stream chunks their
+/// response.
+const PLACEHOLDER_MODEL_NAME: &str = "cohere-model";
+const PLACEHOLDER_STREAM_ID: &str = "cohere-stream-id";
+const FUNCTION_TOOL_TYPE: &str = "function";
+
+pub struct CohereConverter {
+    model_mapper: ModelMapper,
+}
+
+impl CohereConverter {
+    #[must_use]
+    pub fn new(model_mapper: ModelMapper) -> Self {
+        Self { model_mapper }
+    }
+}
+
+fn finish_reason(
+    finish_reason: cohere::FinishReason,
+) -> async_openai::types::FinishReason {
+    use async_openai::types::FinishReason;
+    match finish_reason {
+        cohere::FinishReason::Complete
+        | cohere::FinishReason::StopSequence
+        | cohere::FinishReason::Error
+        | cohere::FinishReason::Timeout => FinishReason::Stop,
+        cohere::FinishReason::MaxTokens => FinishReason::Length,
+        cohere::FinishReason::ToolCall => FinishReason::ToolCalls,
+    }
+}
+
+fn usage(usage: cohere::Usage) -> Option<async_openai::types::CompletionUsage> {
+    let tokens = usage.tokens.or(usage.billed_units)?;
+    Some(async_openai::types::CompletionUsage {
+        prompt_tokens: tokens.input_tokens,
+        completion_tokens: tokens.output_tokens,
+        total_tokens: tokens.input_tokens + tokens.output_tokens,
+        prompt_tokens_details: None,
+        completion_tokens_details: None,
+    })
+}
+
+fn text_parts<'a>(parts: impl Iterator<Item = &'a str>) -> String {
+    parts.collect::<Vec<_>>().join("\n")
+}
+
+impl
+    TryConvert<
+        async_openai::types::CreateChatCompletionRequest,
+        cohere::ChatRequest,
+    > for CohereConverter
+{
+    type Error = MapperError;
+
+    #[allow(clippy::too_many_lines)]
+    fn try_convert(
+        &self,
+        value: async_openai::types::CreateChatCompletionRequest,
+    ) -> Result<cohere::ChatRequest, Self::Error> {
+        use async_openai::types as openai;
+        let source_model = ModelId::from_str(&value.model)?;
+        let target_model = self
+            .model_mapper
+            .map_model(&source_model, &InferenceProvider::Cohere)?;
+        tracing::trace!(source_model = ?source_model, target_model = ?target_model, "mapped model");
+
+        let mut messages = Vec::with_capacity(value.messages.len());
+        for message in value.messages {
+            let mapped = match message {
+                openai::ChatCompletionRequestMessage::Developer(message) => {
+                    let content = match message.content {
+                        openai::ChatCompletionRequestDeveloperMessageContent::Text(text) => text,
+                        openai::ChatCompletionRequestDeveloperMessageContent::Array(parts) => {
+                            text_parts(parts.iter().map(|part| part.text.as_str()))
+                        }
+                    };
+                    cohere::Message::System {
+                        content: cohere::MessageContent::Text(content),
+                    }
+                }
+                openai::ChatCompletionRequestMessage::System(message) => {
+                    let content = match message.content {
+                        openai::ChatCompletionRequestSystemMessageContent::Text(text) => text,
+                        openai::ChatCompletionRequestSystemMessageContent::Array(parts) => {
+                            text_parts(parts.iter().map(|part| match part {
+                                openai::ChatCompletionRequestSystemMessageContentPart::Text(text) => text.text.as_str(),
+                            }))
+                        }
+                    };
+                    cohere::Message::System {
+                        content: cohere::MessageContent::Text(content),
+                    }
+                }
+                openai::ChatCompletionRequestMessage::User(message) => {
+                    let content = match message.content {
+                        openai::ChatCompletionRequestUserMessageContent::Text(text) => {
+                            cohere::MessageContent::Text(text)
+                        }
+                        openai::ChatCompletionRequestUserMessageContent::Array(parts) => {
+                            cohere::MessageContent::Parts(parts.into_iter().filter_map(|part| match part {
+                                openai::ChatCompletionRequestUserMessageContentPart::Text(text) => {
+                                    Some(cohere::Content::Text { text: text.text })
+                                }
+                                openai::ChatCompletionRequestUserMessageContentPart::ImageUrl(image) => {
+                                    Some(cohere::Content::ImageUrl {
+                                        image_url: cohere::ImageUrl { url: image.image_url.url },
+                                    })
+                                }
+                                // Cohere does not support audio
+                                openai::ChatCompletionRequestUserMessageContentPart::InputAudio(_) => None,
+                            }).collect())
+                        }
+                    };
+                    cohere::Message::User { content }
+                }
+                openai::ChatCompletionRequestMessage::Assistant(message) => {
+                    let content = match message.content {
+                        Some(openai::ChatCompletionRequestAssistantMessageContent::Text(text)) => {
+                            Some(cohere::MessageContent::Text(text))
+                        }
+                        Some(openai::ChatCompletionRequestAssistantMessageContent::Array(parts)) => {
+                            Some(cohere::MessageContent::Text(text_parts(parts.iter().map(|part| match part {
+                                openai::ChatCompletionRequestAssistantMessageContentPart::Text(text) => text.text.as_str(),
+                                openai::ChatCompletionRequestAssistantMessageContentPart::Refusal(refusal) => refusal.refusal.as_str(),
+                            }))))
+                        }
+                        None => None,
+                    };
+                    let tool_calls = message.tool_calls.map(|tool_calls| {
+                        tool_calls
+                            .into_iter()
+                            .map(|tool_call| cohere::ToolCall {
+                                id: tool_call.id,
+                                kind: FUNCTION_TOOL_TYPE.to_string(),
+                                function: cohere::FunctionCall {
+                                    name: tool_call.function.name,
+                                    arguments: tool_call.function.arguments,
+                                },
+                            })
+                            .collect()
+                    });
+                    cohere::Message::Assistant {
+                        content,
+                        tool_calls,
+                        tool_plan: None,
+                    }
+                }
+                openai::ChatCompletionRequestMessage::Tool(message) => {
+                    let content = match message.content {
+                        openai::ChatCompletionRequestToolMessageContent::Text(text) => text,
+                        openai::ChatCompletionRequestToolMessageContent::Array(parts) => {
+                            text_parts(parts.iter().map(|part| match part {
+                                openai::ChatCompletionRequestToolMessageContentPart::Text(text) => text.text.as_str(),
+                            }))
+                        }
+                    };
+                    cohere::Message::Tool {
+                        tool_call_id: message.tool_call_id,
+                        content: cohere::MessageContent::Text(content),
+                    }
+                }
+                // deprecated in favor of tool messages, which carry the id
+                // of their tool call that Cohere requires
+                openai::ChatCompletionRequestMessage::Function(_) => continue,
+            };
+            messages.push(mapped);
+        }
+
+        let tools = value.tools.map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| cohere::Tool {
+                    kind: FUNCTION_TOOL_TYPE.to_string(),
+                    function: cohere::FunctionDefinition {
+                        name: tool.function.name,
+                        description: tool.function.description,
+                        parameters: tool.function.parameters,
+                    },
+                })
+                .collect()
+        });
+        // Cohere can't be made to call a specific tool, so naming one only
+        // requires that a tool is called
+        let tool_choice = match value.tool_choice {
+            Some(
+                openai::ChatCompletionToolChoiceOption::Required
+                | openai::ChatCompletionToolChoiceOption::Named(_),
+            ) => Some(cohere::ToolChoice::Required),
+            Some(openai::ChatCompletionToolChoiceOption::None) => {
+                Some(cohere::ToolChoice::None)
+            }
+            Some(openai::ChatCompletionToolChoiceOption::Auto) | None => None,
+        };
+        let stop_sequences = match value.stop {
+            Some(openai::Stop::String(stop)) => Some(vec![stop]),
+            Some(openai::Stop::StringArray(stops)) => Some(stops),
+            None => None,
+        };
+        let response_format = match value.response_format {
+            Some(openai::ResponseFormat::Text) => {
+                Some(cohere::ResponseFormat::Text)
+            }
+            Some(openai::ResponseFormat::JsonObject) => {
+                Some(cohere::ResponseFormat::JsonObject { json_schema: None })
+            }
+            Some(openai::ResponseFormat::JsonSchema { json_schema }) => {
+                Some(cohere::ResponseFormat::JsonObject {
+                    json_schema: json_schema.schema,
+                })
+            }
+            None => None,
+        };
+        #[allow(deprecated)]
+        let max_tokens = value.max_completion_tokens.or(value.max_tokens);
+
+        Ok(cohere::ChatRequest {
+            model: target_model.to_string(),
+            messages,
+            tools,
+            tool_choice,
+            stream: value.stream,
+            max_tokens,
+            temperature: value.temperature,
+            p: value.top_p,
+            stop_sequences,
+            seed: value.seed,
+            frequency_penalty: value.frequency_penalty,
+            presence_penalty: value.presence_penalty,
+            response_format,
+        })
+    }
+}
+
+impl
+    TryConvert<
+        cohere::ChatResponse,
+        async_openai::types::CreateChatCompletionResponse,
+    > for CohereConverter
+{
+    type Error = MapperError;
+
+    fn try_convert(
+        &self,
+        value: cohere::ChatResponse,
+    ) -> Result<async_openai::types::CreateChatCompletionResponse, Self::Error>
+    {
+        use async_openai::types as openai;
+        let text = value
+            .message
+            .content
+            .iter()
+            .filter_map(|content| match content {
+                cohere::Content::Text { text } => Some(text.as_str()),
+                cohere::Content::ImageUrl { .. } | cohere::Content::Other => {
+                    None
+                }
+            })
+            .collect::<String>();
+        let tool_calls = value.message.tool_calls.map(|tool_calls| {
+            tool_calls
+                .into_iter()
+                .map(|tool_call| openai::ChatCompletionMessageToolCall {
+                    id: tool_call.id,
+                    r#type: openai::ChatCompletionToolType::Function,
+                    function: openai::FunctionCall {
+                        name: tool_call.function.name,
+                        arguments: tool_call.function.arguments,
+                    },
+                })
+                .collect()
+        });
+
+        #[allow(deprecated)]
+        let message = openai::ChatCompletionResponseMessage {
+            content: (!text.is_empty()).then_some(text),
+            refusal: None,
+            tool_calls,
+            role: openai::Role::Assistant,
+            function_call: None,
+            audio: None,
+        };
+        Ok(openai::CreateChatCompletionResponse {
+            id: value.id,
+            choices: vec![openai::ChatChoice {
+                index: 0,
+                message,
+                finish_reason: value.finish_reason.map(finish_reason),
+                logprobs: None,
+            }],
+            created: 0,
+            model: PLACEHOLDER_MODEL_NAME.to_string(),
+            object: OPENAI_CHAT_COMPLETION_OBJECT.to_string(),
+            usage: value.usage.and_then(usage),
+            service_tier: None,
+            system_fingerprint: None,
+        })
+    }
+}
+
+impl
+    TryConvertStreamData<
+        cohere::StreamEvent,
+        async_openai::types::CreateChatCompletionStreamResponse,
+    > for CohereConverter
+{
+    type Error = MapperError;
+
+    fn try_convert_chunk(
+        &self,
+        value: cohere::StreamEvent,
+    ) -> Result<
+        Option<async_openai::types::CreateChatCompletionStreamResponse>,
+        Self::Error,
+    > {
+        use async_openai::types as openai;
+        let chunk =
+            |id: Option<String>,
+             delta: openai::ChatCompletionStreamResponseDelta,
+             finish_reason: Option<openai::FinishReason>,
+             usage: Option<openai::CompletionUsage>| {
+                openai::CreateChatCompletionStreamResponse {
+                    id: id.unwrap_or_else(|| PLACEHOLDER_STREAM_ID.to_string()),
+                    choices: vec![openai::ChatChoiceStream {
+                        index: 0,
+                        delta,
+                        finish_reason,
+                        logprobs: None,
+                    }],
+                    created: 0,
+                    model: PLACEHOLDER_MODEL_NAME.to_string(),
+                    object: CHAT_COMPLETION_CHUNK_OBJECT.to_string(),
+                    system_fingerprint: None,
+                    service_tier: None,
+                    usage,
+                }
+            };
+        #[allow(deprecated)]
+        let delta = |role: Option<openai::Role>,
+                     content: Option<String>,
+                     tool_calls: Option<
+            Vec<openai::ChatCompletionMessageToolCallChunk>,
+        >| openai::ChatCompletionStreamResponseDelta {
+            role,
+            content,
+            tool_calls,
+            refusal: None,
+            function_call: None,
+        };
+
+        match value {
+            cohere::StreamEvent::MessageStart { id } => Ok(Some(chunk(
+                id,
+                delta(Some(openai::Role::Assistant), None, None),
+                None,
+                None,
+            ))),
+            cohere::StreamEvent::ContentDelta { delta: content, .. } => {
+                let Some(text) = content.message.content.text else {
+                    return Ok(None);
+                };
+                Ok(Some(chunk(None, delta(None, Some(text), None), None, None)))
+            }
+            cohere::StreamEvent::ToolCallStart {
+                index,
+                delta: tool_call,
+            }
+            | cohere::StreamEvent::ToolCallDelta {
+                index,
+                delta: tool_call,
+            } => {
+                let tool_call = tool_call.message.tool_calls;
+                let is_start = tool_call.id.is_some();
+                let tool_call_chunk =
+                    openai::ChatCompletionMessageToolCallChunk {
+                        index,
+                        id: tool_call.id,
+                        r#type: is_start.then_some(
+                            openai::ChatCompletionToolType::Function,
+                        ),
+                        function: tool_call.function.map(|function| {
+                            openai::FunctionCallStream {
+                                name: function.name,
+                                arguments: function.arguments,
+                            }
+                        }),
+                    };
+                Ok(Some(chunk(
+                    None,
+                    delta(None, None, Some(vec![tool_call_chunk])),
+                    None,
+                    None,
+                )))
+            }
+            cohere::StreamEvent::MessageEnd { delta: end } => Ok(Some(chunk(
+                None,
+                delta(None, None, None),
+                end.finish_reason.map(finish_reason),
+                end.usage.and_then(usage),
+            ))),
+            cohere::StreamEvent::Other => Ok(None),
+        }
+    }
+}
+
+impl TryConvertError<CohereApiError, async_openai::error::WrappedError>
+    for CohereConverter
+{
+    type Error = MapperError;
+
+    fn try_convert_error(
+        &self,
+        resp_parts: &Parts,
+        value: CohereApiError,
+    ) -> Result<async_openai::error::WrappedError, Self::Error> {
+        Ok(super::openai_error_from_status(
+            resp_parts.status,
+            Some(value.message),
+        ))
+    }
+}
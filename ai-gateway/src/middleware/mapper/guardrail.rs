@@ -0,0 +1,91 @@
+//! Bedrock guardrail assessments in mapped responses.
+//!
+//! OpenAI responses have no equivalent of a Bedrock guardrail trace, so it
+//! is added to them as `guardrail`:
+//!
+//! ```json
+//! { "guardrail": { "intervened": true, "trace": { "inputAssessment": {} } } }
+//! ```
+//!
+//! and the `finish_reason` of a response the guardrail intervened in is
+//! `content_filter`.
+use bytes::Bytes;
+use serde_json::{Value, json};
+
+/// The `stopReason` of a Bedrock converse response which a guardrail
+/// intervened in.
+const GUARDRAIL_INTERVENED: &str = "guardrail_intervened";
+
+/// Adds the guardrail assessment of the Bedrock converse response
+/// `provider_response` to its mapped `response`, if it has one.
+#[must_use]
+pub fn add_assessment(provider_response: &[u8], response: Bytes) -> Bytes {
+    let Ok(provider_response) =
+        serde_json::from_slice::<Value>(provider_response)
+    else {
+        return response;
+    };
+    let intervened =
+        provider_response.get("stopReason").and_then(Value::as_str)
+            == Some(GUARDRAIL_INTERVENED);
+    let trace = provider_response.pointer("/trace/guardrail").cloned();
+    if !intervened && trace.is_none() {
+        return response;
+    }
+    let Ok(mut value) = serde_json::from_slice::<Value>(&response) else {
+        return response;
+    };
+    let Some(object) = value.as_object_mut() else {
+        return response;
+    };
+    if intervened && let Some(Value::Array(choices)) = object.get_mut("choices")
+    {
+        for choice in choices {
+            choice["finish_reason"] = json!("content_filter");
+        }
+    }
+    object.insert(
+        "guardrail".to_string(),
+        json!({ "intervened": intervened, "trace": trace }),
+    );
+    serde_json::to_vec(&value).map_or(response, Bytes::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn intervened_response_has_assessment() {
+        let provider_response = json!({
+            "stopReason": "guardrail_intervened",
+            "trace": {
+                "guardrail": {
+                    "inputAssessment": {
+                        "abc123": { "topicPolicy": { "topics": [] } },
+                    },
+                },
+            },
+        });
+        let response = json!({
+            "choices": [{ "index": 0, "finish_reason": null }],
+        });
+        let response = add_assessment(
+            &serde_json::to_vec(&provider_response).unwrap(),
+            Bytes::from(serde_json::to_vec(&response).unwrap()),
+        );
+        let response: Value = serde_json::from_slice(&response).unwrap();
+        assert_eq!(response["choices"][0]["finish_reason"], "content_filter");
+        assert_eq!(response["guardrail"]["intervened"], true);
+        assert!(
+            response["guardrail"]["trace"]["inputAssessment"]["abc123"]
+                .is_object()
+        );
+
+        let untouched = Bytes::from_static(b"{\"choices\":[]}");
+        assert_eq!(
+            add_assessment(b"{\"stopReason\":\"end_turn\"}", untouched.clone()),
+            untouched
+        );
+    }
+}
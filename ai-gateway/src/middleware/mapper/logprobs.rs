@@ -0,0 +1,177 @@
+//! Compatibility of the `logprobs` family of parameters across providers.
+//!
+//! `logprobs` and `top_logprobs` are passed through to providers with an
+//! OpenAI compatible API, unless the capability matrix lists the target model
+//! as not supporting them. Providers without an equivalent would reject or
+//! silently ignore them, so they are removed from the request instead and
+//! the client is told which parameters were dropped. `echo` is a parameter of
+//! the legacy completions API, so it is always dropped.
+use bytes::Bytes;
+use http::{HeaderName, HeaderValue};
+use serde_json::Value;
+
+use crate::{
+    config::model_capabilities::ModelCapabilitiesConfig,
+    endpoints::ApiEndpoint, types::model_id::ModelId,
+};
+
+/// Lists the parameters that were removed from the request because the
+/// target model doesn't support them.
+pub const UNSUPPORTED_PARAMS_HEADER: HeaderName =
+    HeaderName::from_static("helicone-unsupported-params");
+
+const LOGPROBS_PARAMS: [&str; 2] = ["logprobs", "top_logprobs"];
+const ECHO_PARAM: &str = "echo";
+
+/// Parameters removed from a request.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UnsupportedParams(Vec<&'static str>);
+
+impl UnsupportedParams {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    #[must_use]
+    pub fn header_value(&self) -> Option<HeaderValue> {
+        if self.0.is_empty() {
+            return None;
+        }
+        HeaderValue::from_str(&self.0.join(", ")).ok()
+    }
+}
+
+/// Returns the logprobs parameters set in a request body.
+///
+/// Bodies that don't mention any of them are not parsed.
+#[must_use]
+pub fn requested(body: &Bytes) -> Vec<&'static str> {
+    let mentions = |param: &str| {
+        body.windows(param.len())
+            .any(|window| window == param.as_bytes())
+    };
+    if !mentions(LOGPROBS_PARAMS[0]) && !mentions(ECHO_PARAM) {
+        return Vec::new();
+    }
+    let Ok(body) = serde_json::from_slice::<Value>(body) else {
+        return Vec::new();
+    };
+    LOGPROBS_PARAMS
+        .into_iter()
+        .chain(std::iter::once(ECHO_PARAM))
+        .filter(|param| {
+            body.get(param)
+                .is_some_and(|value| !value.is_null() && value != false)
+        })
+        .collect()
+}
+
+/// Whether `model` at `target_endpoint` returns logprobs.
+#[must_use]
+pub fn supported(
+    capabilities: &ModelCapabilitiesConfig,
+    target_endpoint: &ApiEndpoint,
+    model: Option<&ModelId>,
+) -> bool {
+    match target_endpoint {
+        ApiEndpoint::Anthropic(_)
+        | ApiEndpoint::Bedrock(_)
+        | ApiEndpoint::Cohere(_) => false,
+        _ => model
+            .and_then(|model| capabilities.get(model))
+            .is_none_or(|capability| capability.logprobs),
+    }
+}
+
+/// Removes the `requested` parameters that the target doesn't support from
+/// the already converted request `body`.
+pub fn strip(
+    requested: &[&'static str],
+    supported: bool,
+    body: &mut Value,
+) -> UnsupportedParams {
+    let unsupported = requested
+        .iter()
+        .copied()
+        .filter(|param| *param == ECHO_PARAM || !supported)
+        .collect::<Vec<_>>();
+    if let Value::Object(body) = body {
+        for param in &unsupported {
+            body.remove(*param);
+        }
+    }
+    UnsupportedParams(unsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use serde_json::json;
+
+    use super::*;
+    use crate::endpoints::{
+        anthropic::Anthropic, bedrock::Bedrock, openai::OpenAI,
+    };
+
+    fn body(value: &Value) -> Bytes {
+        Bytes::from(serde_json::to_vec(value).unwrap())
+    }
+
+    #[test]
+    fn requested_params() {
+        assert!(
+            requested(&body(&json!({"model": "openai/gpt-4o"}))).is_empty()
+        );
+        assert!(
+            requested(&body(&json!({"logprobs": false, "echo": null})))
+                .is_empty()
+        );
+        assert_eq!(
+            requested(&body(&json!({
+                "logprobs": true,
+                "top_logprobs": 2,
+                "echo": true,
+            }))),
+            vec!["logprobs", "top_logprobs", "echo"]
+        );
+    }
+
+    #[test]
+    fn support_follows_capability_matrix() {
+        let capabilities = ModelCapabilitiesConfig::default();
+        let openai = ApiEndpoint::OpenAI(OpenAI::chat_completions());
+        let gpt_4o = ModelId::from_str("openai/gpt-4o").unwrap();
+        let o3 = ModelId::from_str("openai/o3").unwrap();
+        let unknown = ModelId::from_str("openai/gpt-unknown").unwrap();
+        assert!(supported(&capabilities, &openai, Some(&gpt_4o)));
+        assert!(!supported(&capabilities, &openai, Some(&o3)));
+        assert!(supported(&capabilities, &openai, Some(&unknown)));
+
+        let claude = ModelId::from_str("anthropic/claude-3-5-sonnet").unwrap();
+        let anthropic = ApiEndpoint::Anthropic(Anthropic::messages());
+        assert!(!supported(&capabilities, &anthropic, Some(&claude)));
+        let bedrock = ApiEndpoint::Bedrock(Bedrock::converse());
+        assert!(!supported(&capabilities, &bedrock, None));
+    }
+
+    #[test]
+    fn unsupported_params_are_stripped() {
+        let requested = ["logprobs", "top_logprobs", "echo"];
+        let mut request =
+            json!({"logprobs": true, "top_logprobs": 2, "echo": true});
+        let stripped = strip(&requested, true, &mut request);
+        assert_eq!(request, json!({"logprobs": true, "top_logprobs": 2}));
+        assert_eq!(stripped.header_value().unwrap(), "echo");
+
+        let stripped = strip(&requested, false, &mut request);
+        assert_eq!(request, json!({}));
+        assert_eq!(
+            stripped.header_value().unwrap(),
+            "logprobs, top_logprobs, echo"
+        );
+
+        assert!(strip(&[], false, &mut request).is_empty());
+    }
+}
@@ -1,11 +1,19 @@
 pub mod anthropic;
 mod bedrock;
+pub mod cohere;
+pub mod guardrail;
+pub mod logprobs;
 pub mod model;
 pub mod ollama;
 pub mod openai;
 pub mod openai_compatible;
+pub mod output_limits;
+pub mod prefill;
 pub mod registry;
 pub mod service;
+pub mod similarity;
+pub mod transform;
+pub mod usage;
 
 use async_openai::error::WrappedError;
 use base64::Engine;
@@ -1,18 +1,116 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 
 use derive_more::{AsRef, Deref, DerefMut};
+use nonempty_collections::NESet;
+use rust_decimal::prelude::ToPrimitive;
 use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     app_state::AppState,
     config::{model_mapping::ModelMappingConfig, router::RouterConfig},
     error::mapper::MapperError,
+    middleware::mapper::similarity,
     types::{
         model_id::{ModelId, ModelIdWithoutVersion, ModelName},
         provider::InferenceProvider,
+        router::RouterId,
     },
 };
 
+/// A model mapping managed through the admin API.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StoredModelMapping {
+    /// The router whose mapping this is, or `None` for a mapping of the
+    /// default model mapping.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub router: Option<RouterId>,
+    pub source_model: ModelName<'static>,
+    pub targets: NESet<ModelId>,
+}
+
+/// The model mappings stored in the database of a cloud deployment. They
+/// take precedence over the configured mappings of the same scope, so that
+/// a mapping can be fixed, such as for a new model snapshot, without a
+/// config deployment.
+#[derive(Debug, Clone, Default)]
+pub struct StoredModelMappings(Arc<RwLock<Mappings>>);
+
+/// The targets of each source model, per router.
+type Mappings = HashMap<Option<RouterId>, HashMap<String, NESet<ModelId>>>;
+
+impl StoredModelMappings {
+    #[must_use]
+    pub fn get(
+        &self,
+        router: Option<&RouterId>,
+        source_model: &ModelName<'_>,
+    ) -> Option<NESet<ModelId>> {
+        self.0
+            .read()
+            .expect("model mappings lock poisoned")
+            .get(&router.cloned())?
+            .get(source_model.as_ref())
+            .cloned()
+    }
+
+    pub fn set(&self, mapping: StoredModelMapping) {
+        self.0
+            .write()
+            .expect("model mappings lock poisoned")
+            .entry(mapping.router)
+            .or_default()
+            .insert(mapping.source_model.to_string(), mapping.targets);
+    }
+
+    pub fn remove(&self, router: Option<&RouterId>, source_model: &str) {
+        let mut mappings =
+            self.0.write().expect("model mappings lock poisoned");
+        let router = router.cloned();
+        if let Some(models) = mappings.get_mut(&router) {
+            models.remove(source_model);
+            if models.is_empty() {
+                mappings.remove(&router);
+            }
+        }
+    }
+
+    /// Replaces every mapping, such as after reloading them from the
+    /// database.
+    pub fn replace(
+        &self,
+        mappings: impl IntoIterator<Item = StoredModelMapping>,
+    ) {
+        let mut replaced = Mappings::default();
+        for mapping in mappings {
+            replaced
+                .entry(mapping.router)
+                .or_default()
+                .insert(mapping.source_model.to_string(), mapping.targets);
+        }
+        *self.0.write().expect("model mappings lock poisoned") = replaced;
+    }
+
+    #[must_use]
+    pub fn list(&self) -> Vec<StoredModelMapping> {
+        self.0
+            .read()
+            .expect("model mappings lock poisoned")
+            .iter()
+            .flat_map(|(router, models)| {
+                models.iter().map(|(source_model, targets)| {
+                    StoredModelMapping {
+                        router: router.clone(),
+                        source_model: ModelName::owned(source_model.clone()),
+                        targets: targets.clone(),
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
 #[derive(Debug, Clone, Eq, PartialEq, Deref, DerefMut, AsRef)]
 struct ProviderModels(
     HashMap<InferenceProvider, HashSet<ModelIdWithoutVersion>>,
@@ -33,6 +131,7 @@ impl ProviderModels {
 #[derive(Debug, Clone)]
 pub struct ModelMapper {
     app_state: AppState,
+    router_id: Option<RouterId>,
     router_config: Option<Arc<RouterConfig>>,
     model_id: Option<ModelId>,
     provider_models: ProviderModels,
@@ -42,11 +141,13 @@ impl ModelMapper {
     #[must_use]
     pub fn new_for_router(
         app_state: AppState,
+        router_id: RouterId,
         router_config: Arc<RouterConfig>,
     ) -> Self {
         let provider_models = ProviderModels::new(&app_state);
         Self {
             app_state,
+            router_id: Some(router_id),
             router_config: Some(router_config),
             model_id: None,
             provider_models,
@@ -62,6 +163,7 @@ impl ModelMapper {
         let provider_models = ProviderModels::new(&app_state);
         Self {
             app_state,
+            router_id: None,
             router_config: Some(router_config),
             model_id: Some(model_id),
             provider_models,
@@ -73,24 +175,44 @@ impl ModelMapper {
         let provider_models = ProviderModels::new(&app_state);
         Self {
             app_state,
+            router_id: None,
             router_config: None,
             model_id: None,
             provider_models,
         }
     }
 
+    #[must_use]
+    pub fn app_state(&self) -> &AppState {
+        &self.app_state
+    }
+
+    /// The config of the router this mapper maps requests for, if any.
+    #[must_use]
+    pub fn router_config(&self) -> Option<&RouterConfig> {
+        self.router_config.as_deref()
+    }
+
     fn default_model_mapping(&self) -> &ModelMappingConfig {
         &self.app_state.0.config.default_model_mapping
     }
 
     /// Map a model to a new model name for a target provider.
     ///
-    /// If the source model is offered by the target provider, return the source
-    /// model name. Otherwise, use the model mapping from router config.
-    /// If that doesn't have a mapping, use the default model mapping from the
+    /// If the source model names a model group, return the group's member for
+    /// the target provider. If the source model is offered by the target
+    /// provider, return the source model name. Otherwise, use the router's
+    /// stored model mapping, or else the model mapping from router config.
+    /// If the router config doesn't have a model mapping, use the stored
+    /// default model mapping, or else the default model mapping from the
     /// global config. (maybe we should put usage of the default mapping
-    /// behind a flag so its up to the user,  although declaring mappings
-    /// for _every_ model seems onerous)
+    /// behind a flag so its up to the user, although declaring mappings for
+    /// _every_ model seems onerous)
+    ///
+    /// If no mapping has a model the target provider offers, the most similar
+    /// model it offers is substituted, if fuzzy model mapping is enabled.
+    /// Custom providers which don't list their models are sent the source
+    /// model as is.
     pub fn map_model(
         &self,
         source_model: &ModelId,
@@ -101,6 +223,14 @@ impl ModelMapper {
         if let Some(model_id) = self.model_id.clone() {
             return Ok(model_id);
         }
+        let group = source_model.to_string();
+        if let Some(members) =
+            self.app_state.config().model_groups.members(&group)
+        {
+            return members.get(target_provider).cloned().ok_or_else(|| {
+                MapperError::NoModelMapping(target_provider.clone(), group)
+            });
+        }
         let models_offered_by_target_provider =
             self.provider_models.0.get(target_provider).ok_or_else(|| {
                 MapperError::NoProviderConfig(target_provider.clone())
@@ -115,42 +245,136 @@ impl ModelMapper {
             return Ok(source_model.clone());
         }
 
-        let model_mapping_config = if let Some(router_model_mapping) =
-            self.router_config.as_ref().and_then(|c| c.model_mappings())
-        {
-            router_model_mapping
-        } else {
-            self.default_model_mapping()
+        let source_model_name = ModelName::from_model(source_model);
+        let stored = &self.app_state.0.model_mappings;
+        let configured = |config: &ModelMappingConfig| {
+            config.as_ref().get(&source_model_name).cloned()
         };
+        let possible_mappings = stored
+            .get(self.router_id.as_ref(), &source_model_name)
+            .or_else(|| {
+                match self
+                    .router_config
+                    .as_ref()
+                    .and_then(|c| c.model_mappings())
+                {
+                    Some(router_model_mapping) => {
+                        configured(router_model_mapping)
+                    }
+                    None => stored
+                        .get(None, &source_model_name)
+                        .or_else(|| configured(self.default_model_mapping())),
+                }
+            });
 
-        let source_model_name = ModelName::from_model(source_model);
-        let possible_mappings = model_mapping_config
-            .as_ref()
-            .get(&source_model_name)
+        // get the first model from the router model mapping that the target
+        // provider supports
+        let target_model = possible_mappings.and_then(|possible_mappings| {
+            possible_mappings
+                .iter()
+                .find(|m| {
+                    let possible_mapping = (*m).clone().into();
+                    (models_offered_by_target_provider.is_empty()
+                        || models_offered_by_target_provider
+                            .contains(&possible_mapping))
+                        && m.inference_provider()
+                            == Some(target_provider.clone())
+                })
+                .cloned()
+        });
+        if let Some(target_model) = target_model {
+            return Ok(target_model);
+        }
+        if models_offered_by_target_provider.is_empty() {
+            return Ok(source_model.clone());
+        }
+        let target_model = self
+            .most_similar(&source_model_name, target_provider)
             .ok_or_else(|| {
                 MapperError::NoModelMapping(
                     target_provider.clone(),
                     source_model_name.as_ref().to_string(),
                 )
             })?;
+        tracing::debug!(
+            source_model = %source_model,
+            target_model = %target_model,
+            "substituted similar model"
+        );
+        similarity::record_substitution(source_model);
+        Ok(target_model)
+    }
 
-        // get the first model from the router model mapping that the target
-        // provider supports
-        let target_model = possible_mappings
+    /// The model offered by `target_provider` which is most similar to
+    /// `source_model`, if fuzzy model mapping is enabled and any is similar
+    /// enough.
+    fn most_similar(
+        &self,
+        source_model: &ModelName<'_>,
+        target_provider: &InferenceProvider,
+    ) -> Option<ModelId> {
+        let config = self.app_state.config();
+        let min_score = config.fuzzy_model_mapping.as_ref()?.min_score;
+        let min_score = min_score.to_f64().unwrap_or(1.0);
+        config
+            .providers
+            .get(target_provider)?
+            .models
             .iter()
-            .find(|m| {
-                let possible_mapping = (*m).clone().into();
-                models_offered_by_target_provider.contains(&possible_mapping)
-                    && m.inference_provider() == Some(target_provider.clone())
+            .map(|candidate| {
+                let score = similarity::score(
+                    source_model.as_ref(),
+                    ModelName::from_model(candidate).as_ref(),
+                );
+                (candidate, score)
             })
-            .ok_or_else(|| {
-                MapperError::NoModelMapping(
-                    target_provider.clone(),
-                    source_model_name.as_ref().to_string(),
-                )
-            })?
-            .clone();
+            .filter(|(_, score)| *score >= min_score)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(candidate, _)| candidate.clone())
+    }
+}
 
-        Ok(target_model)
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use nonempty_collections::nes;
+
+    use super::*;
+
+    fn mapping(router: Option<&str>, target: &str) -> StoredModelMapping {
+        StoredModelMapping {
+            router: router.map(|router| RouterId::Named(router.into())),
+            source_model: ModelName::owned("gpt-4o".to_string()),
+            targets: nes![ModelId::from_str(target).unwrap()],
+        }
+    }
+
+    #[test]
+    fn stored_mappings_are_scoped_to_their_router() {
+        let mappings = StoredModelMappings::default();
+        let router = RouterId::Named("my-router".into());
+        let model = ModelName::borrowed("gpt-4o");
+        mappings.set(mapping(None, "anthropic/claude-3-5-sonnet"));
+        mappings.set(mapping(Some("my-router"), "anthropic/claude-3-opus"));
+        assert_eq!(
+            mappings.get(Some(&router), &model),
+            Some(nes![ModelId::from_str("anthropic/claude-3-opus").unwrap()])
+        );
+        mappings.remove(Some(&router), "gpt-4o");
+        assert_eq!(mappings.get(Some(&router), &model), None);
+        assert!(mappings.get(None, &model).is_some());
+
+        mappings.replace([mapping(Some("my-router"), "openai/gpt-4o-mini")]);
+        assert_eq!(mappings.get(None, &model), None);
+        assert_eq!(mappings.list().len(), 1);
+        let parsed: StoredModelMapping =
+            serde_json::from_value(serde_json::json!({
+                "router": "my-router",
+                "source-model": "gpt-4o",
+                "targets": ["openai/gpt-4o-mini"],
+            }))
+            .unwrap();
+        assert_eq!(parsed, mappings.list()[0]);
     }
 }
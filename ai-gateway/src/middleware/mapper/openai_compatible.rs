@@ -4,6 +4,7 @@ use http::response::Parts;
 
 use super::{TryConvertStreamData, model::ModelMapper};
 use crate::{
+    dispatcher::{openai_compatible_client, vertex_client},
     endpoints::openai::OpenAICompatibleChatCompletionRequest,
     error::mapper::MapperError,
     middleware::mapper::{TryConvert, TryConvertError},
@@ -40,7 +41,17 @@ impl
         let target_model =
             self.model_mapper.map_model(&source_model, &self.provider)?;
         tracing::trace!(source_model = ?source_model, target_model = ?target_model, "mapped model");
-        value.model = target_model.to_string();
+        value.model = match &self.provider {
+            InferenceProvider::Vertex => {
+                vertex_client::publisher_model(&target_model.to_string())
+            }
+            InferenceProvider::Named(name) if name == "fireworks" => {
+                openai_compatible_client::fireworks_model(
+                    &target_model.to_string(),
+                )
+            }
+            _ => target_model.to_string(),
+        };
 
         Ok(OpenAICompatibleChatCompletionRequest {
             provider: self.provider.clone(),
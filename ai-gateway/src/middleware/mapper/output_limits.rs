@@ -0,0 +1,237 @@
+//! Enforcement of a router's output limits.
+//!
+//! The maximum output tokens are passed on to the provider with the request,
+//! and both the stop sequences and the maximum output tokens are enforced on
+//! the completion, for providers that ignore or lack those parameters.
+use bytes::Bytes;
+use serde_json::Value;
+
+use super::transform::{Flow, StopSequences, StreamTransform};
+use crate::{
+    config::output_limits::OutputLimitsConfig, endpoints::ApiEndpoint,
+    utils::tokenizer::truncate_tokens,
+};
+
+/// Request body fields limiting the number of output tokens.
+const MAX_TOKENS_FIELDS: [&str; 2] = ["max_completion_tokens", "max_tokens"];
+
+/// Caps the maximum output tokens of a request to `source_endpoint`.
+pub fn limit_request(
+    limits: &OutputLimitsConfig,
+    source_endpoint: &ApiEndpoint,
+    request: &mut Value,
+) {
+    let (Some(max_output_tokens), Value::Object(request)) =
+        (limits.max_output_tokens, request)
+    else {
+        return;
+    };
+    let mut limited = false;
+    for field in MAX_TOKENS_FIELDS {
+        if let Some(max_tokens) = request.get_mut(field)
+            && !max_tokens.is_null()
+        {
+            limited = true;
+            if max_tokens
+                .as_u64()
+                .is_none_or(|tokens| tokens > u64::from(max_output_tokens))
+            {
+                *max_tokens = Value::from(max_output_tokens);
+            }
+        }
+    }
+    if !limited {
+        let field = match source_endpoint {
+            ApiEndpoint::Anthropic(_) => "max_tokens",
+            _ => "max_completion_tokens",
+        };
+        request.insert(field.to_string(), Value::from(max_output_tokens));
+    }
+}
+
+/// The transforms enforcing `limits` on a completion of `model`.
+#[must_use]
+pub fn transforms(
+    limits: &OutputLimitsConfig,
+    model: Option<&str>,
+) -> Vec<Box<dyn StreamTransform>> {
+    let mut transforms: Vec<Box<dyn StreamTransform>> = Vec::new();
+    if !limits.stop.is_empty() {
+        transforms.push(Box::new(StopSequences::new(limits.stop.clone())));
+    }
+    if let Some(max_output_tokens) = limits.max_output_tokens {
+        transforms.push(Box::new(MaxOutputTokens::new(
+            max_output_tokens,
+            model.map(ToString::to_string),
+        )));
+    }
+    transforms
+}
+
+/// Enforces `limits` on a non-streaming chat completion of `model`.
+///
+/// Returns the completion unchanged if it isn't a chat completion or is
+/// within the limits.
+#[must_use]
+pub fn limit_completion(
+    limits: &OutputLimitsConfig,
+    model: Option<&str>,
+    completion: Bytes,
+) -> Bytes {
+    let Ok(mut value) = serde_json::from_slice::<Value>(&completion) else {
+        return completion;
+    };
+    let Some(choices) = value.get_mut("choices").and_then(Value::as_array_mut)
+    else {
+        return completion;
+    };
+    let mut limited = false;
+    for choice in choices {
+        let Some(mut content) = choice
+            .pointer("/message/content")
+            .and_then(Value::as_str)
+            .map(ToString::to_string)
+        else {
+            continue;
+        };
+        let mut flow = Flow::Continue;
+        for mut transform in transforms(limits, model) {
+            if flow == Flow::Continue {
+                flow = transform.transform(&mut content, true);
+            }
+        }
+        if let Some(finish_reason) = flow.finish_reason() {
+            limited = true;
+            choice["message"]["content"] = Value::String(content);
+            choice["finish_reason"] = Value::from(finish_reason);
+        }
+    }
+    if !limited {
+        return completion;
+    }
+    serde_json::to_vec(&value).map_or(completion, Bytes::from)
+}
+
+/// Ends the completion once its number of tokens, counted with the model's
+/// tokenizer, reaches the maximum.
+///
+/// Each chunk is counted on its own. Providers stream whole tokens, so this
+/// matches the count of the whole completion, and a token split across
+/// chunks is counted twice rather than not at all.
+struct MaxOutputTokens {
+    model: Option<String>,
+    remaining_tokens: usize,
+}
+
+impl MaxOutputTokens {
+    fn new(max_output_tokens: u32, model: Option<String>) -> Self {
+        Self {
+            model,
+            remaining_tokens: usize::try_from(max_output_tokens)
+                .unwrap_or(usize::MAX),
+        }
+    }
+}
+
+impl StreamTransform for MaxOutputTokens {
+    fn name(&self) -> &'static str {
+        "max-output-tokens"
+    }
+
+    fn transform(&mut self, content: &mut String, _finished: bool) -> Flow {
+        let tokens = truncate_tokens(
+            self.model.as_deref(),
+            content,
+            self.remaining_tokens,
+        );
+        if tokens > self.remaining_tokens {
+            self.remaining_tokens = 0;
+            Flow::Length
+        } else {
+            self.remaining_tokens -= tokens;
+            Flow::Continue
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::endpoints::{anthropic::Anthropic, openai::OpenAI};
+
+    fn limits(
+        stop: &[&str],
+        max_output_tokens: Option<u32>,
+    ) -> OutputLimitsConfig {
+        OutputLimitsConfig {
+            stop: stop.iter().map(ToString::to_string).collect(),
+            max_output_tokens,
+        }
+    }
+
+    #[test]
+    fn requests_are_capped() {
+        let limits = limits(&[], Some(100));
+        let openai = ApiEndpoint::OpenAI(OpenAI::chat_completions());
+        let mut request = json!({"max_tokens": 1000});
+        limit_request(&limits, &openai, &mut request);
+        assert_eq!(request, json!({"max_tokens": 100}));
+
+        let mut request = json!({"max_completion_tokens": 10});
+        limit_request(&limits, &openai, &mut request);
+        assert_eq!(request, json!({"max_completion_tokens": 10}));
+
+        let mut request = json!({});
+        limit_request(&limits, &openai, &mut request);
+        assert_eq!(request, json!({"max_completion_tokens": 100}));
+
+        let anthropic = ApiEndpoint::Anthropic(Anthropic::messages());
+        let mut request = json!({});
+        limit_request(&limits, &anthropic, &mut request);
+        assert_eq!(request, json!({"max_tokens": 100}));
+    }
+
+    #[test]
+    fn max_output_tokens_across_chunks() {
+        let mut max = MaxOutputTokens::new(2, Some("gpt-4o".to_string()));
+        let mut first = "hello".to_string();
+        assert_eq!(max.transform(&mut first, false), Flow::Continue);
+        // "world!" is two tokens
+        let mut second = "world!".to_string();
+        assert_eq!(max.transform(&mut second, false), Flow::Length);
+        assert_eq!(second, "world");
+    }
+
+    #[test]
+    fn completions_are_limited() {
+        let completion = |content: &str| {
+            Bytes::from(
+                serde_json::to_vec(&json!({
+                    "choices": [{
+                        "index": 0,
+                        "message": {"role": "assistant", "content": content},
+                        "finish_reason": "stop",
+                    }]
+                }))
+                .unwrap(),
+            )
+        };
+        let limits = limits(&["\n\n"], Some(2));
+
+        let limited = limit_completion(&limits, None, completion("one\n\ntwo"));
+        let limited = serde_json::from_slice::<Value>(&limited).unwrap();
+        assert_eq!(limited["choices"][0]["message"]["content"], "one");
+        assert_eq!(limited["choices"][0]["finish_reason"], "stop");
+
+        let limited =
+            limit_completion(&limits, None, completion("a long answer"));
+        let limited = serde_json::from_slice::<Value>(&limited).unwrap();
+        assert_eq!(limited["choices"][0]["message"]["content"], "a long");
+        assert_eq!(limited["choices"][0]["finish_reason"], "length");
+
+        let short = completion("short");
+        assert_eq!(limit_completion(&limits, None, short.clone()), short);
+    }
+}
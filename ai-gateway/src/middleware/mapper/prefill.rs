@@ -0,0 +1,289 @@
+//! Assistant prefill across providers.
+//!
+//! A request whose last message is an assistant message asks the model to
+//! continue that message, as Anthropic does natively. Providers which support
+//! this natively receive the request as is, Mistral and DeepSeek need the
+//! message flagged as a prefix, and for every other provider the prefill is
+//! simulated by instructing the model to start its response with it.
+//!
+//! As with Anthropic, the response only contains the continuation: a
+//! simulated prefill is stripped from the start of the response.
+use bytes::Bytes;
+use serde_json::Value;
+
+use super::transform::{Flow, StreamTransform};
+use crate::{endpoints::ApiEndpoint, types::provider::InferenceProvider};
+
+/// Providers which continue a final assistant message flagged with
+/// `prefix: true`.
+const PREFIX_FLAG_PROVIDERS: [&str; 2] = ["mistral", "deepseek"];
+
+/// How a provider supports assistant prefill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Support {
+    /// A final assistant message is continued.
+    Native,
+    /// A final assistant message is continued if flagged with
+    /// `prefix: true`.
+    PrefixFlag,
+    /// The model is instructed to start its response with the prefill.
+    Simulated,
+}
+
+impl Support {
+    #[must_use]
+    pub fn of(target_endpoint: &ApiEndpoint) -> Self {
+        match target_endpoint {
+            ApiEndpoint::Anthropic(_)
+            | ApiEndpoint::Bedrock(_)
+            | ApiEndpoint::Ollama(_) => Self::Native,
+            ApiEndpoint::OpenAICompatible {
+                provider: InferenceProvider::Named(name),
+                ..
+            } if PREFIX_FLAG_PROVIDERS.contains(&name.as_str()) => {
+                Self::PrefixFlag
+            }
+            ApiEndpoint::OpenAI(_)
+            | ApiEndpoint::Google(_)
+            | ApiEndpoint::Cohere(_)
+            | ApiEndpoint::OpenAICompatible { .. } => Self::Simulated,
+        }
+    }
+}
+
+/// Whether a request body may end with an assistant message, without parsing
+/// it.
+#[must_use]
+pub fn may_have_prefill(body: &Bytes) -> bool {
+    const ASSISTANT: &[u8] = b"\"assistant\"";
+    body.windows(ASSISTANT.len())
+        .any(|window| window == ASSISTANT)
+}
+
+/// Returns the text of the final assistant message of an OpenAI style
+/// request, if any.
+fn prefill(request: &Value) -> Option<String> {
+    let message = request.get("messages")?.as_array()?.last()?;
+    if message.get("role")? != "assistant"
+        || message
+            .get("tool_calls")
+            .is_some_and(|calls| !calls.is_null())
+    {
+        return None;
+    }
+    let text = match message.get("content")? {
+        Value::String(text) => text.clone(),
+        Value::Array(parts) => parts
+            .iter()
+            .filter_map(|part| part.get("text").and_then(Value::as_str))
+            .collect(),
+        _ => return None,
+    };
+    (!text.is_empty()).then_some(text)
+}
+
+/// Adapts the prefill of an OpenAI style request, already mapped to the
+/// target provider, to how the provider supports it.
+///
+/// Returns the prefill if it is simulated, in which case it must be stripped
+/// from the response.
+pub fn apply(support: Support, request: &mut Value) -> Option<String> {
+    if support == Support::Native {
+        return None;
+    }
+    let prefill = prefill(request)?;
+    let messages = request.get_mut("messages")?.as_array_mut()?;
+    match support {
+        Support::Native => None,
+        Support::PrefixFlag => {
+            if let Some(Value::Object(message)) = messages.last_mut() {
+                message.insert("prefix".to_string(), Value::Bool(true));
+            }
+            None
+        }
+        Support::Simulated => {
+            messages.pop();
+            messages.push(serde_json::json!({
+                "role": "user",
+                "content": format!(
+                    "Begin your response with exactly the following text, \
+                     then continue it:\n\n{prefill}"
+                ),
+            }));
+            Some(prefill)
+        }
+    }
+}
+
+/// Strips a simulated prefill from the start of a non-streaming response,
+/// either a chat completion or an Anthropic message.
+#[must_use]
+pub fn strip_from_response(prefill: &str, response: Bytes) -> Bytes {
+    let Ok(mut value) = serde_json::from_slice::<Value>(&response) else {
+        return response;
+    };
+    let mut stripped = false;
+    let texts = match value.get_mut("choices") {
+        Some(Value::Array(choices)) => choices
+            .iter_mut()
+            .filter_map(|choice| choice.pointer_mut("/message/content"))
+            .collect::<Vec<_>>(),
+        _ => value
+            .get_mut("content")
+            .and_then(Value::as_array_mut)
+            .and_then(|blocks| blocks.first_mut())
+            .and_then(|block| block.get_mut("text"))
+            .into_iter()
+            .collect(),
+    };
+    for text in texts {
+        if let Some(rest) = text.as_str().and_then(|t| t.strip_prefix(prefill))
+        {
+            *text = Value::String(rest.to_string());
+            stripped = true;
+        }
+    }
+    if !stripped {
+        return response;
+    }
+    serde_json::to_vec(&value).map_or(response, Bytes::from)
+}
+
+/// Strips a simulated prefill from the start of a streamed completion.
+///
+/// Content is held back until it either starts with the prefill, or can no
+/// longer start with it, in which case it is released unchanged.
+pub(super) struct StripPrefill {
+    prefill: String,
+    held: String,
+    done: bool,
+}
+
+impl StripPrefill {
+    pub(super) fn new(prefill: String) -> Self {
+        Self {
+            prefill,
+            held: String::new(),
+            done: false,
+        }
+    }
+}
+
+impl StreamTransform for StripPrefill {
+    fn name(&self) -> &'static str {
+        "strip-prefill"
+    }
+
+    fn transform(&mut self, content: &mut String, finished: bool) -> Flow {
+        if self.done {
+            return Flow::Continue;
+        }
+        self.held.push_str(content);
+        if let Some(rest) = self.held.strip_prefix(self.prefill.as_str()) {
+            *content = rest.to_string();
+            self.held.clear();
+            self.done = true;
+        } else if self.prefill.starts_with(self.held.as_str()) && !finished {
+            content.clear();
+        } else {
+            *content = std::mem::take(&mut self.held);
+            self.done = true;
+        }
+        Flow::Continue
+    }
+
+    fn take_pending(&mut self) -> String {
+        std::mem::take(&mut self.held)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::endpoints::{anthropic::Anthropic, openai::OpenAI};
+
+    fn request() -> Value {
+        json!({
+            "model": "gpt-4o",
+            "messages": [
+                {"role": "user", "content": "Give me JSON"},
+                {"role": "assistant", "content": "{\"answer\":"},
+            ],
+        })
+    }
+
+    #[test]
+    fn support_by_provider() {
+        assert_eq!(
+            Support::of(&ApiEndpoint::Anthropic(Anthropic::messages())),
+            Support::Native
+        );
+        assert_eq!(
+            Support::of(&ApiEndpoint::OpenAI(OpenAI::chat_completions())),
+            Support::Simulated
+        );
+        assert_eq!(
+            Support::of(&ApiEndpoint::OpenAICompatible {
+                provider: InferenceProvider::Named("mistral".into()),
+                openai_endpoint: OpenAI::chat_completions(),
+            }),
+            Support::PrefixFlag
+        );
+    }
+
+    #[test]
+    fn prefill_is_flagged() {
+        let mut request = request();
+        assert_eq!(apply(Support::PrefixFlag, &mut request), None);
+        assert_eq!(request["messages"][1]["prefix"], true);
+    }
+
+    #[test]
+    fn prefill_is_simulated() {
+        let mut request = request();
+        assert_eq!(
+            apply(Support::Simulated, &mut request).as_deref(),
+            Some("{\"answer\":")
+        );
+        let messages = request["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[1]["role"], "user");
+
+        let mut request = json!({
+            "messages": [{"role": "user", "content": "hi"}],
+        });
+        assert_eq!(apply(Support::Simulated, &mut request), None);
+        assert_eq!(request["messages"].as_array().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn simulated_prefill_is_stripped() {
+        let response = Bytes::from(
+            serde_json::to_vec(&json!({
+                "choices": [{
+                    "message": {"role": "assistant", "content": "{\"answer\": 42}"},
+                }],
+            }))
+            .unwrap(),
+        );
+        let stripped = strip_from_response("{\"answer\":", response);
+        let stripped = serde_json::from_slice::<Value>(&stripped).unwrap();
+        assert_eq!(stripped["choices"][0]["message"]["content"], " 42}");
+
+        let mut strip = StripPrefill::new("Hello".to_string());
+        let mut output = String::new();
+        for chunk in ["He", "llo wor", "ld"] {
+            let mut content = chunk.to_string();
+            strip.transform(&mut content, false);
+            output.push_str(&content);
+        }
+        assert_eq!(output, " world");
+
+        let mut strip = StripPrefill::new("Hello".to_string());
+        let mut content = "Hey".to_string();
+        strip.transform(&mut content, false);
+        assert_eq!(content, "Hey");
+    }
+}
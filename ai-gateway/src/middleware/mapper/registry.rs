@@ -10,9 +10,12 @@ use super::{
 use crate::{
     endpoints::{
         self, ApiEndpoint, anthropic::Anthropic, bedrock::Bedrock,
-        google::Google, ollama::Ollama, openai::OpenAI,
+        cohere::Cohere, google::Google, ollama::Ollama, openai::OpenAI,
+    },
+    middleware::mapper::{
+        bedrock::BedrockConverter, cohere::CohereConverter,
+        ollama::OllamaConverter,
     },
-    middleware::mapper::{bedrock::BedrockConverter, ollama::OllamaConverter},
     types::provider::InferenceProvider,
 };
 
@@ -146,6 +149,18 @@ impl EndpointConverterRegistryInner {
 
         registry.register_converter(key, converter);
 
+        let key = RegistryKey::new(
+            ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+            ApiEndpoint::Cohere(Cohere::chat()),
+        );
+        let converter =
+            TypedEndpointConverter::<
+                endpoints::openai::ChatCompletions,
+                endpoints::cohere::Chat,
+                CohereConverter,
+            >::new(CohereConverter::new(model_mapper.clone()));
+        registry.register_converter(key, converter);
+
         let key = RegistryKey::new(
             ApiEndpoint::OpenAI(OpenAI::chat_completions()),
             ApiEndpoint::OpenAICompatible {
@@ -231,6 +246,119 @@ impl EndpointConverterRegistryInner {
         ));
         registry.register_converter(key, converter);
 
+        let key = RegistryKey::new(
+            ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+            ApiEndpoint::OpenAICompatible {
+                provider: InferenceProvider::Named("together".into()),
+                openai_endpoint: OpenAI::chat_completions(),
+            },
+        );
+        let converter = TypedEndpointConverter::<
+            endpoints::openai::ChatCompletions,
+            endpoints::openai::OpenAICompatibleChatCompletions,
+            OpenAICompatibleConverter,
+        >::new(OpenAICompatibleConverter::new(
+            InferenceProvider::Named("together".into()),
+            model_mapper.clone(),
+        ));
+        registry.register_converter(key, converter);
+
+        let key = RegistryKey::new(
+            ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+            ApiEndpoint::OpenAICompatible {
+                provider: InferenceProvider::Named("fireworks".into()),
+                openai_endpoint: OpenAI::chat_completions(),
+            },
+        );
+        let converter = TypedEndpointConverter::<
+            endpoints::openai::ChatCompletions,
+            endpoints::openai::OpenAICompatibleChatCompletions,
+            OpenAICompatibleConverter,
+        >::new(OpenAICompatibleConverter::new(
+            InferenceProvider::Named("fireworks".into()),
+            model_mapper.clone(),
+        ));
+        registry.register_converter(key, converter);
+
+        let key = RegistryKey::new(
+            ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+            ApiEndpoint::OpenAICompatible {
+                provider: InferenceProvider::Named("cloudflare".into()),
+                openai_endpoint: OpenAI::chat_completions(),
+            },
+        );
+        let converter = TypedEndpointConverter::<
+            endpoints::openai::ChatCompletions,
+            endpoints::openai::OpenAICompatibleChatCompletions,
+            OpenAICompatibleConverter,
+        >::new(OpenAICompatibleConverter::new(
+            InferenceProvider::Named("cloudflare".into()),
+            model_mapper.clone(),
+        ));
+        registry.register_converter(key, converter);
+
+        let key = RegistryKey::new(
+            ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+            ApiEndpoint::OpenAICompatible {
+                provider: InferenceProvider::AzureOpenAI,
+                openai_endpoint: OpenAI::chat_completions(),
+            },
+        );
+        let converter = TypedEndpointConverter::<
+            endpoints::openai::ChatCompletions,
+            endpoints::openai::OpenAICompatibleChatCompletions,
+            OpenAICompatibleConverter,
+        >::new(OpenAICompatibleConverter::new(
+            InferenceProvider::AzureOpenAI,
+            model_mapper.clone(),
+        ));
+        registry.register_converter(key, converter);
+
+        let key = RegistryKey::new(
+            ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+            ApiEndpoint::OpenAICompatible {
+                provider: InferenceProvider::Vertex,
+                openai_endpoint: OpenAI::chat_completions(),
+            },
+        );
+        let converter = TypedEndpointConverter::<
+            endpoints::openai::ChatCompletions,
+            endpoints::openai::OpenAICompatibleChatCompletions,
+            OpenAICompatibleConverter,
+        >::new(OpenAICompatibleConverter::new(
+            InferenceProvider::Vertex,
+            model_mapper.clone(),
+        ));
+        registry.register_converter(key, converter);
+
+        // custom `OpenAI` compatible providers, which are only known from the
+        // config
+        let config = model_mapper.app_state().config();
+        for provider in config.providers.keys() {
+            let InferenceProvider::Named(_) = provider else {
+                continue;
+            };
+            let key = RegistryKey::new(
+                ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+                ApiEndpoint::OpenAICompatible {
+                    provider: provider.clone(),
+                    openai_endpoint: OpenAI::chat_completions(),
+                },
+            );
+            if registry.converters.contains_key(&key) {
+                continue;
+            }
+            let converter = TypedEndpointConverter::<
+                endpoints::openai::ChatCompletions,
+                endpoints::openai::OpenAICompatibleChatCompletions,
+                OpenAICompatibleConverter,
+            >::new(OpenAICompatibleConverter::new(
+                provider.clone(),
+                model_mapper.clone(),
+            ));
+            registry.register_converter(key, converter);
+        }
+
         registry
     }
 
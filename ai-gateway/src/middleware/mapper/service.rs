@@ -1,40 +1,69 @@
 use std::{
     str::FromStr,
+    sync::{Arc, Mutex},
     task::{Context, Poll},
 };
 
 use bytes::{BufMut, BytesMut};
-use futures::{TryStreamExt, future::BoxFuture};
-use http::uri::PathAndQuery;
+use futures::{StreamExt, TryStreamExt, future::BoxFuture};
+use http::{HeaderValue, uri::PathAndQuery};
 use tracing::{Instrument, info_span};
 
 use crate::{
+    app_state::AppState,
+    config::{
+        model_capabilities::ModelCapabilitiesConfig,
+        output_limits::OutputLimitsConfig,
+        stream_transform::StreamTransformsConfig,
+    },
+    dispatcher::gemini_cache,
     endpoints::ApiEndpoint,
     error::{
-        api::ApiError, internal::InternalError, mapper::MapperError,
+        api::ApiError, internal::InternalError,
+        invalid_req::InvalidRequestError, mapper::MapperError,
         stream::StreamError,
     },
-    middleware::mapper::registry::EndpointConverterRegistry,
+    logger::properties::RequestMetadata,
+    metrics::StreamTransformMetrics,
+    middleware::mapper::{
+        guardrail,
+        logprobs::{self, UnsupportedParams},
+        output_limits, prefill,
+        registry::EndpointConverterRegistry,
+        similarity::{self, SUBSTITUTED_FROM_HEADER},
+        transform::{self, StreamTransformer},
+        usage::{ESTIMATED_IF_MISSING, StreamUsage},
+    },
     types::{
-        extensions::MapperContext, provider::InferenceProvider,
-        request::Request, response::Response,
+        extensions::{MapperContext, RequestContext},
+        model_id::ModelId,
+        provider::InferenceProvider,
+        request::Request,
+        response::Response,
     },
+    utils::tokenizer::TOKEN_COUNT_ESTIMATED_HEADER,
 };
 
 #[derive(Debug, Clone)]
 pub struct Service<S> {
     inner: S,
     endpoint_converter_registry: EndpointConverterRegistry,
+    stream_transform_metrics: StreamTransformMetrics,
+    model_capabilities: Arc<ModelCapabilitiesConfig>,
 }
 
 impl<S> Service<S> {
     pub fn new(
         inner: S,
         endpoint_converter_registry: EndpointConverterRegistry,
+        stream_transform_metrics: StreamTransformMetrics,
+        model_capabilities: Arc<ModelCapabilitiesConfig>,
     ) -> Self {
         Self {
             inner,
             endpoint_converter_registry,
+            stream_transform_metrics,
+            model_capabilities,
         }
     }
 }
@@ -67,7 +96,22 @@ where
         // see: https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
         let mut inner = self.inner.clone();
         let converter_registry = self.endpoint_converter_registry.clone();
+        let model_capabilities = Arc::clone(&self.model_capabilities);
         std::mem::swap(&mut self.inner, &mut inner);
+        let router_config = req
+            .extensions()
+            .get::<Arc<RequestContext>>()
+            .and_then(|req_ctx| req_ctx.router_config.clone());
+        let output_limits = router_config
+            .as_ref()
+            .and_then(|router_config| router_config.output_limits.clone());
+        let mut stream_transformer = router_config.and_then(|router_config| {
+            StreamTransformer::for_router(
+                &router_config,
+                self.stream_transform_metrics.clone(),
+            )
+        });
+        let stream_transform_metrics = self.stream_transform_metrics.clone();
         Box::pin(async move {
             let target_provider = req
                 .extensions()
@@ -98,53 +142,120 @@ where
             let converter_registry_cloned = converter_registry.clone();
             let source_endpoint_for_req = source_endpoint_cloned.clone();
             let target_endpoint_for_req = target_endpoint_cloned.clone();
-            let req = tokio::task::spawn_blocking(move || async move {
-                map_request(
-                    converter_registry_cloned,
-                    source_endpoint_for_req,
-                    target_endpoint_for_req,
-                    &extracted_path_and_query,
-                    req,
-                )
-                .instrument(info_span!("map_request"))
+            let output_limits_for_req = output_limits.clone();
+            let (req, changes) =
+                tokio::task::spawn_blocking(move || async move {
+                    map_request(
+                        converter_registry_cloned,
+                        source_endpoint_for_req,
+                        target_endpoint_for_req,
+                        &extracted_path_and_query,
+                        req,
+                        output_limits_for_req.as_ref(),
+                        &model_capabilities,
+                    )
+                    .instrument(info_span!("map_request"))
+                    .await
+                })
                 .await
-            })
-            .await
-            .map_err(InternalError::MappingTaskError)?
-            .await?;
+                .map_err(InternalError::MappingTaskError)?
+                .await?;
+            let model = req
+                .extensions()
+                .get::<MapperContext>()
+                .and_then(|mapper_ctx| mapper_ctx.model.as_ref())
+                .map(ToString::to_string);
+            if let Some(prefill) = changes.simulated_prefill.clone() {
+                stream_transformer
+                    .get_or_insert_with(|| {
+                        StreamTransformer::new(
+                            StreamTransformsConfig::default(),
+                            OutputLimitsConfig::default(),
+                            stream_transform_metrics,
+                        )
+                    })
+                    .strip_prefill(prefill);
+            }
+            if let Some(stream_transformer) = stream_transformer.as_mut() {
+                stream_transformer.set_model(model.clone());
+                stream_transformer.expect_choices(changes.choices);
+            }
             let response = inner.call(req).await?;
-            let response = tokio::task::spawn_blocking(move || async move {
-                map_response(
-                    converter_registry,
-                    target_endpoint_cloned,
-                    source_endpoint_cloned,
-                    response,
-                )
+            let mut response =
+                tokio::task::spawn_blocking(move || async move {
+                    map_response(
+                        converter_registry,
+                        target_endpoint_cloned,
+                        source_endpoint_cloned,
+                        response,
+                        stream_transformer,
+                        output_limits,
+                        model,
+                        changes.simulated_prefill,
+                        changes.stream_usage,
+                    )
+                    .await
+                })
+                .instrument(info_span!("map_response"))
                 .await
-            })
-            .instrument(info_span!("map_response"))
-            .await
-            .map_err(InternalError::MappingTaskError)?
-            .await?;
+                .map_err(InternalError::MappingTaskError)?
+                .await?;
+            if let Some(unsupported_params) =
+                changes.unsupported_params.header_value()
+            {
+                response.headers_mut().insert(
+                    logprobs::UNSUPPORTED_PARAMS_HEADER,
+                    unsupported_params,
+                );
+            }
+            if let Some(substituted) =
+                changes.substituted_from.and_then(|model| {
+                    HeaderValue::from_str(&model.to_string()).ok()
+                })
+            {
+                response
+                    .headers_mut()
+                    .insert(SUBSTITUTED_FROM_HEADER, substituted);
+            }
             Ok(response)
         })
     }
 }
 
+/// Changes made to a request while mapping it, which are reflected in the
+/// response.
+#[derive(Debug, Default)]
+struct RequestChanges {
+    unsupported_params: UnsupportedParams,
+    /// The assistant prefill, if simulated by instructing the model.
+    simulated_prefill: Option<String>,
+    /// Set if the client asked for the usage of a streamed completion.
+    stream_usage: Option<StreamUsage>,
+    /// The requested model, if a similar model was substituted for it.
+    substituted_from: Option<ModelId>,
+    /// The number of choices requested.
+    choices: usize,
+}
+
 async fn map_request(
     converter_registry: EndpointConverterRegistry,
     source_endpoint: ApiEndpoint,
     target_endpoint: ApiEndpoint,
     target_path_and_query: &PathAndQuery,
     req: Request,
-) -> Result<Request, ApiError> {
+    output_limits: Option<&OutputLimitsConfig>,
+    model_capabilities: &ModelCapabilitiesConfig,
+) -> Result<(Request, RequestChanges), ApiError> {
     use http_body_util::BodyExt;
-    let (parts, body) = req.into_parts();
+    let (mut parts, body) = req.into_parts();
     let body = body
         .collect()
         .await
         .map_err(InternalError::CollectBodyError)?
         .to_bytes();
+    // the metadata is logged as properties, even if the target provider
+    // doesn't support it
+    parts.extensions.insert(RequestMetadata::from_body(&body));
     let converter = converter_registry
         .get_converter(&source_endpoint, &target_endpoint)
         .ok_or_else(|| {
@@ -154,7 +265,82 @@ async fn map_request(
             )
         })?;
 
-    let (body, mapper_ctx) = converter.convert_req_body(body)?;
+    // Gemini cached contents referenced by OpenAI style requests are dropped
+    // when the request is converted, and added back afterwards
+    let cached_content = if matches!(target_endpoint, ApiEndpoint::Google(_)) {
+        serde_json::from_slice::<serde_json::Value>(&body)
+            .ok()
+            .as_ref()
+            .and_then(gemini_cache::openai_cached_content)
+            .map(ToString::to_string)
+    } else {
+        None
+    };
+    let body = match output_limits {
+        Some(output_limits) if output_limits.max_output_tokens.is_some() => {
+            let mut value = serde_json::from_slice::<serde_json::Value>(&body)
+                .map_err(InvalidRequestError::InvalidRequestBody)?;
+            output_limits::limit_request(
+                output_limits,
+                &source_endpoint,
+                &mut value,
+            );
+            serde_json::to_vec(&value).map(bytes::Bytes::from).map_err(
+                |error| InternalError::Serialize {
+                    ty: "serde_json::Value",
+                    error,
+                },
+            )?
+        }
+        _ => body,
+    };
+    let requested_logprobs = logprobs::requested(&body);
+    let requested_choices = transform::requested_choices(&body);
+    let stream_usage = StreamUsage::for_request(&source_endpoint, &body);
+    let (converted, substituted_from) =
+        similarity::track_substitution(|| converter.convert_req_body(body));
+    let (body, mapper_ctx) = converted?;
+    let prefill_support = prefill::Support::of(&target_endpoint);
+    let may_have_prefill = prefill_support != prefill::Support::Native
+        && prefill::may_have_prefill(&body);
+    let mut changes = RequestChanges {
+        stream_usage,
+        substituted_from,
+        choices: requested_choices,
+        ..RequestChanges::default()
+    };
+    let body = if cached_content.is_some()
+        || !requested_logprobs.is_empty()
+        || may_have_prefill
+    {
+        let mut value = serde_json::from_slice::<serde_json::Value>(&body)
+            .map_err(|error| InternalError::Deserialize {
+                ty: "serde_json::Value",
+                error,
+            })?;
+        if let Some(cached_content) = cached_content {
+            gemini_cache::with_cached_content(&mut value, &cached_content);
+        }
+        let logprobs_supported = logprobs::supported(
+            model_capabilities,
+            &target_endpoint,
+            mapper_ctx.model.as_ref(),
+        );
+        changes.unsupported_params = logprobs::strip(
+            &requested_logprobs,
+            logprobs_supported,
+            &mut value,
+        );
+        changes.simulated_prefill = prefill::apply(prefill_support, &mut value);
+        serde_json::to_vec(&value)
+            .map(bytes::Bytes::from)
+            .map_err(|error| InternalError::Serialize {
+                ty: "serde_json::Value",
+                error,
+            })?
+    } else {
+        body
+    };
     let base_path = target_endpoint
         .path(mapper_ctx.model.as_ref(), mapper_ctx.is_stream)?;
 
@@ -178,21 +364,27 @@ async fn map_request(
     req.extensions_mut().insert(target_path_and_query);
     req.extensions_mut().insert(mapper_ctx);
     req.extensions_mut().insert(target_endpoint);
-    Ok(req)
+    Ok((req, changes))
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn map_response(
     converter_registry: EndpointConverterRegistry,
     source_endpoint: ApiEndpoint,
     target_endpoint: ApiEndpoint,
     resp: http::Response<crate::types::body::Body>,
+    stream_transformer: Option<StreamTransformer>,
+    output_limits: Option<OutputLimitsConfig>,
+    model: Option<String>,
+    simulated_prefill: Option<String>,
+    stream_usage: Option<StreamUsage>,
 ) -> Result<Response, ApiError> {
     let mapper_ctx = resp
         .extensions()
         .get::<MapperContext>()
         .ok_or(InternalError::ExtensionNotFound("MapperContext"))?;
     let is_stream = mapper_ctx.is_stream;
-    let (parts, body) = resp.into_parts();
+    let (mut parts, body) = resp.into_parts();
 
     let converter = converter_registry
         .get_converter(&target_endpoint, &source_endpoint)
@@ -213,6 +405,16 @@ async fn map_response(
         // constructed in the dispatcher from either an SSE stream or a
         // stream of bytes, we can safely assume each frame is a single
         // SSE event in this branch
+        if stream_usage.is_some() && parts.status.is_success() {
+            parts
+                .headers
+                .insert(TOKEN_COUNT_ESTIMATED_HEADER, ESTIMATED_IF_MISSING);
+        }
+        let stream_transformer =
+            stream_transformer.map(|t| Arc::new(Mutex::new(t)));
+        let stream_usage =
+            stream_usage.map(|usage| Arc::new(Mutex::new(usage)));
+        let transformer_for_end = stream_transformer.clone();
         let mapped_stream = body
             .into_data_stream()
             .map_err(|e| ApiError::StreamError(StreamError::BodyError(e)))
@@ -221,11 +423,14 @@ async fn map_response(
                 let resp_parts = parts.clone();
                 let target_endpoint_cloned = target_endpoint.clone();
                 let source_endpoint_cloned = source_endpoint.clone();
+                let stream_usage = stream_usage.clone();
                 move |bytes| {
                     let registry_for_future = captured_registry.clone();
                     let resp_parts = resp_parts.clone();
                     let target_endpoint = target_endpoint_cloned.clone();
                     let source_endpoint = source_endpoint_cloned.clone();
+                    let stream_transformer = stream_transformer.clone();
+                    let stream_usage = stream_usage.clone();
                     async move {
                         let converter = registry_for_future
                             .get_converter(&target_endpoint, &source_endpoint)
@@ -238,21 +443,65 @@ async fn map_response(
 
                         let converted_data = converter
                             .convert_resp_body(resp_parts, bytes, is_stream)?;
-
-                        // add the `data: ` prefix expected by the OpenAI SDK
-                        if let Some(converted_data) = converted_data {
-                            let mut new_bytes = BytesMut::new();
-                            new_bytes.put("data: ".as_bytes());
-                            new_bytes.put(converted_data);
-                            new_bytes.put("\n\n".as_bytes());
-                            let data = new_bytes.freeze();
-                            Ok(Some(data))
-                        } else {
-                            Ok(converted_data)
+                        let converted_data = match stream_transformer {
+                            Some(transformer) => {
+                                converted_data.and_then(|data| {
+                                    transformer
+                                        .lock()
+                                        .expect(
+                                            "stream transformer lock poisoned",
+                                        )
+                                        .transform_chunk(data)
+                                })
+                            }
+                            None => converted_data,
+                        };
+                        if let (Some(usage), Some(data)) =
+                            (stream_usage, converted_data.as_ref())
+                        {
+                            usage
+                                .lock()
+                                .expect("stream usage lock poisoned")
+                                .observe(data);
                         }
+
+                        Ok(converted_data.map(sse_event))
                     }
                 }
             });
+        // once a transform ends the completion, the provider's stream is
+        // dropped rather than read to the end
+        let mapped_stream = futures::stream::unfold(
+            Some(Box::pin(mapped_stream)),
+            move |upstream| {
+                let stream_transformer = transformer_for_end.clone();
+                async move {
+                    let mut upstream = upstream?;
+                    let chunk = upstream.next().await?;
+                    let finished =
+                        stream_transformer.is_some_and(|transformer| {
+                            transformer
+                                .lock()
+                                .expect("stream transformer lock poisoned")
+                                .is_finished()
+                        });
+                    Some((chunk, (!finished).then_some(upstream)))
+                }
+            },
+        );
+        // sent once the provider's stream has ended, or was ended by a
+        // transform
+        let usage_chunk = futures::stream::once(async move {
+            Ok::<_, ApiError>(stream_usage.and_then(|usage| {
+                usage
+                    .lock()
+                    .expect("stream usage lock poisoned")
+                    .finish()
+                    .map(sse_event)
+            }))
+        })
+        .try_filter_map(|chunk| async move { Ok(chunk) });
+        let mapped_stream = mapped_stream.chain(usage_chunk);
         let final_body = axum_core::body::Body::new(
             reqwest::Body::wrap_stream(mapped_stream),
         );
@@ -267,9 +516,31 @@ async fn map_response(
             .to_bytes();
 
         let mapped_body_bytes = converter
-            .convert_resp_body(parts.clone(), body_bytes, is_stream)?
+            .convert_resp_body(parts.clone(), body_bytes.clone(), is_stream)?
             .ok_or(MapperError::EmptyResponseBody)
             .map_err(InternalError::MapperError)?;
+        let mapped_body_bytes = match &target_endpoint {
+            ApiEndpoint::Bedrock(_) if parts.status.is_success() => {
+                guardrail::add_assessment(&body_bytes, mapped_body_bytes)
+            }
+            _ => mapped_body_bytes,
+        };
+        let mapped_body_bytes = match output_limits {
+            Some(output_limits) if parts.status.is_success() => {
+                output_limits::limit_completion(
+                    &output_limits,
+                    model.as_deref(),
+                    mapped_body_bytes,
+                )
+            }
+            _ => mapped_body_bytes,
+        };
+        let mapped_body_bytes = match simulated_prefill {
+            Some(prefill) if parts.status.is_success() => {
+                prefill::strip_from_response(&prefill, mapped_body_bytes)
+            }
+            _ => mapped_body_bytes,
+        };
         let final_body = axum_core::body::Body::from(mapped_body_bytes);
         let new_resp = Response::from_parts(parts, final_body);
         tracing::trace!(
@@ -281,16 +552,38 @@ async fn map_response(
     }
 }
 
+/// Adds the `data: ` prefix expected by the OpenAI SDK.
+fn sse_event(data: bytes::Bytes) -> bytes::Bytes {
+    let mut event = BytesMut::new();
+    event.put("data: ".as_bytes());
+    event.put(data);
+    event.put("\n\n".as_bytes());
+    event.freeze()
+}
+
 #[derive(Debug, Clone)]
 pub struct Layer {
     endpoint_converter_registry: EndpointConverterRegistry,
+    stream_transform_metrics: StreamTransformMetrics,
+    model_capabilities: Arc<ModelCapabilitiesConfig>,
 }
 
 impl Layer {
     #[must_use]
-    pub fn new(endpoint_converter_registry: EndpointConverterRegistry) -> Self {
+    pub fn new(
+        endpoint_converter_registry: EndpointConverterRegistry,
+        app_state: &AppState,
+    ) -> Self {
         Self {
             endpoint_converter_registry,
+            stream_transform_metrics: app_state
+                .0
+                .metrics
+                .stream_transforms
+                .clone(),
+            model_capabilities: Arc::new(
+                app_state.config().model_capabilities.clone(),
+            ),
         }
     }
 }
@@ -299,6 +592,11 @@ impl<S> tower::Layer<S> for Layer {
     type Service = Service<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        Service::new(inner, self.endpoint_converter_registry.clone())
+        Service::new(
+            inner,
+            self.endpoint_converter_registry.clone(),
+            self.stream_transform_metrics.clone(),
+            Arc::clone(&self.model_capabilities),
+        )
     }
 }
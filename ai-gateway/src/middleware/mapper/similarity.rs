@@ -0,0 +1,126 @@
+//! Similarity of model names, for mapping a model without an explicit
+//! mapping to the closest equivalent offered by the target provider.
+//!
+//! Names are split into words and version numbers. Two models are only
+//! similar if they're of the same family, which is the first word of the
+//! source model's name, and are scored by the share of their words in
+//! common and how much of their versions match, from the major version
+//! down. Dates of snapshots are ignored, since any snapshot of a model is
+//! an equivalent.
+use std::cell::RefCell;
+
+use http::HeaderName;
+
+use crate::types::model_id::ModelId;
+
+/// The requested model, on responses of a model substituted for it.
+pub const SUBSTITUTED_FROM_HEADER: HeaderName =
+    HeaderName::from_static("helicone-substituted-from");
+
+/// Numbers with this many digits or more are dates, not versions.
+const DATE_DIGITS: usize = 6;
+
+tokio::task_local! {
+    /// The model substituted while mapping the current request, if any.
+    static SUBSTITUTED: RefCell<Option<ModelId>>;
+}
+
+/// Runs `f`, returning the source model which was substituted by a similar
+/// one while it ran, if any.
+pub fn track_substitution<T>(f: impl FnOnce() -> T) -> (T, Option<ModelId>) {
+    SUBSTITUTED.sync_scope(RefCell::new(None), || {
+        let value = f();
+        (value, SUBSTITUTED.with(RefCell::take))
+    })
+}
+
+/// Records that `source` was substituted, if substitutions are tracked.
+pub fn record_substitution(source: &ModelId) {
+    let _ = SUBSTITUTED.try_with(|substituted| {
+        substituted.replace(Some(source.clone()));
+    });
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Parts<'a> {
+    words: Vec<&'a str>,
+    version: Vec<&'a str>,
+}
+
+impl<'a> Parts<'a> {
+    fn of(name: &'a str) -> Self {
+        let mut parts = Self {
+            words: Vec::new(),
+            version: Vec::new(),
+        };
+        for token in name
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|token| !token.is_empty())
+        {
+            if token.bytes().all(|byte| byte.is_ascii_digit()) {
+                if token.len() < DATE_DIGITS {
+                    parts.version.push(token);
+                }
+            } else {
+                parts.words.push(token);
+            }
+        }
+        parts
+    }
+}
+
+/// How similar `candidate` is to `source`, from 0 to 1.
+#[must_use]
+pub fn score(source: &str, candidate: &str) -> f64 {
+    let source = source.to_ascii_lowercase();
+    let candidate = candidate.to_ascii_lowercase();
+    let source = Parts::of(&source);
+    let candidate = Parts::of(&candidate);
+    let Some(family) = source.words.first() else {
+        return 0.0;
+    };
+    if !candidate.words.contains(family) {
+        return 0.0;
+    }
+    let shared = source
+        .words
+        .iter()
+        .filter(|word| candidate.words.contains(word))
+        .count();
+    let words = source.words.len() + candidate.words.len() - shared;
+    let versions = source.version.len().max(candidate.version.len());
+    let version_score = if versions == 0 {
+        1.0
+    } else {
+        let matching = source
+            .version
+            .iter()
+            .zip(&candidate.version)
+            .take_while(|(source, candidate)| source == candidate)
+            .count();
+        ratio(matching, versions)
+    };
+    0.5 * ratio(shared, words) + 0.5 * version_score
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn ratio(part: usize, whole: usize) -> f64 {
+    part as f64 / whole as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn closest_model_of_the_same_family_scores_highest() {
+        let source = "claude-3-5-sonnet-latest";
+        let sonnet = score(source, "claude-3-5-sonnet-20241022");
+        let opus = score(source, "claude-3-opus");
+        assert!(sonnet > opus);
+        assert!((sonnet - 5.0 / 6.0).abs() < 1e-9);
+        assert!(score(source, "anthropic.claude-3-5-sonnet-v2:0") > opus);
+        assert!(score("gpt-4o", "claude-3-5-sonnet").abs() < f64::EPSILON);
+        assert!((score("gpt-4o", "gpt-4o") - 1.0).abs() < f64::EPSILON);
+    }
+}
@@ -0,0 +1,731 @@
+//! Transformation of streamed completions.
+//!
+//! Transforms operate on the content deltas of chat completion chunks, after
+//! they have been mapped to the client's format, so that they work the same
+//! way for every provider. Each transform is given a latency budget per
+//! chunk; a transform that exceeds it is bypassed for the rest of the stream
+//! rather than delaying every following chunk, unless it is enforced, such as
+//! the mask, whose bypass would leak what it hides.
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use opentelemetry::KeyValue;
+use regex::Regex;
+use rustc_hash::{FxHashMap as HashMap, FxHashSet as HashSet};
+use serde_json::Value;
+
+use super::{output_limits, prefill::StripPrefill};
+use crate::{
+    config::{
+        output_limits::OutputLimitsConfig,
+        router::RouterConfig,
+        stream_transform::{StreamTransformConfig, StreamTransformsConfig},
+    },
+    metrics::StreamTransformMetrics,
+};
+
+/// Whether the stream should continue after a transformed chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flow {
+    Continue,
+    /// End the completion after this chunk.
+    Stop,
+    /// End the completion after this chunk, because it reached its maximum
+    /// length.
+    Length,
+}
+
+impl Flow {
+    /// The `finish_reason` of a completion ended by a transform.
+    #[must_use]
+    pub fn finish_reason(self) -> Option<&'static str> {
+        match self {
+            Self::Continue => None,
+            Self::Stop => Some("stop"),
+            Self::Length => Some("length"),
+        }
+    }
+}
+
+/// A stateful transformation of the content of a single streamed
+/// completion.
+pub trait StreamTransform: Send {
+    /// Name of the transform, used in metrics and logs.
+    fn name(&self) -> &'static str;
+
+    /// Transforms the content delta of a chunk in place. `finished` is set
+    /// for the last chunk of the completion.
+    fn transform(&mut self, content: &mut String, finished: bool) -> Flow;
+
+    /// Returns content held back from previous chunks, if any. Called when
+    /// the transform is bypassed.
+    fn take_pending(&mut self) -> String {
+        String::new()
+    }
+
+    /// Whether the transform is applied even when it exceeds its latency
+    /// budget.
+    fn enforced(&self) -> bool {
+        false
+    }
+}
+
+/// The number of choices requested by a chat completion request.
+#[must_use]
+pub fn requested_choices(body: &[u8]) -> usize {
+    let param = b"\"n\"";
+    if !body.windows(param.len()).any(|window| window == param) {
+        return 1;
+    }
+    serde_json::from_slice::<Value>(body)
+        .ok()
+        .and_then(|body| body.get("n").and_then(Value::as_u64))
+        .and_then(|n| usize::try_from(n).ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+#[must_use]
+pub fn build(config: &StreamTransformConfig) -> Box<dyn StreamTransform> {
+    match config {
+        StreamTransformConfig::Mask { words } => Box::new(Mask::new(words)),
+        StreamTransformConfig::StripMarkdown => {
+            Box::new(StripMarkdown::default())
+        }
+        StreamTransformConfig::StopSequences { sequences } => {
+            Box::new(StopSequences::new(sequences.clone()))
+        }
+    }
+}
+
+/// Masks words in the completion.
+///
+/// Words may be split across chunks, so the end of each chunk that could be
+/// the start of a word is held back until the next chunk.
+struct Mask {
+    words: Words,
+    /// The number of characters of the longest word.
+    max_chars: usize,
+    /// The end of the content seen so far, which may be the start of a word.
+    pending: String,
+}
+
+enum Words {
+    Empty,
+    Regex(Regex),
+    /// The words couldn't be compiled, so the whole completion is masked
+    /// rather than leaking them.
+    Invalid,
+}
+
+impl Mask {
+    fn new(words: &[String]) -> Self {
+        let alternatives = words
+            .iter()
+            .map(|word| regex::escape(word))
+            .collect::<Vec<_>>()
+            .join("|");
+        let regex = if words.is_empty() {
+            Words::Empty
+        } else {
+            match Regex::new(&format!(r"(?i)\b(?:{alternatives})\b")) {
+                Ok(regex) => Words::Regex(regex),
+                Err(error) => {
+                    tracing::error!(
+                        error = %error,
+                        "failed to compile masked words, masking everything"
+                    );
+                    Words::Invalid
+                }
+            }
+        };
+        Self {
+            words: regex,
+            max_chars: words
+                .iter()
+                .map(|word| word.chars().count())
+                .max()
+                .unwrap_or_default(),
+            pending: String::new(),
+        }
+    }
+
+    /// The start of the end of `text` that may be the start of a word
+    /// completed by the next chunk.
+    ///
+    /// A word starting before the last `max_chars` characters ends within
+    /// `text`, and the held back end starts at a word boundary so that the
+    /// boundaries of the words are the same in both parts.
+    fn held_from(&self, text: &str) -> usize {
+        let mut start = text
+            .char_indices()
+            .rev()
+            .nth(self.max_chars.saturating_sub(1))
+            .map_or(0, |(i, _)| i);
+        while let Some(c) = text[..start].chars().next_back()
+            && is_word_char(c)
+        {
+            start -= c.len_utf8();
+        }
+        start
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+impl StreamTransform for Mask {
+    fn name(&self) -> &'static str {
+        "mask"
+    }
+
+    fn transform(&mut self, content: &mut String, finished: bool) -> Flow {
+        let regex = match &self.words {
+            Words::Empty => return Flow::Continue,
+            Words::Regex(regex) => regex,
+            Words::Invalid => {
+                *content = content
+                    .chars()
+                    .map(|c| if c.is_whitespace() { c } else { '*' })
+                    .collect();
+                return Flow::Continue;
+            }
+        };
+        let mut text = std::mem::take(&mut self.pending);
+        text.push_str(content);
+        if !finished {
+            let held = self.held_from(&text);
+            self.pending = text.split_off(held);
+        }
+        *content = if regex.is_match(&text) {
+            regex
+                .replace_all(&text, |captures: &regex::Captures<'_>| {
+                    "*".repeat(captures[0].chars().count())
+                })
+                .into_owned()
+        } else {
+            text
+        };
+        Flow::Continue
+    }
+
+    fn enforced(&self) -> bool {
+        true
+    }
+}
+
+#[derive(Default)]
+struct StripMarkdown {
+    /// Whether the last character seen ended a line.
+    mid_line: bool,
+}
+
+impl StreamTransform for StripMarkdown {
+    fn name(&self) -> &'static str {
+        "strip-markdown"
+    }
+
+    fn transform(&mut self, content: &mut String, _finished: bool) -> Flow {
+        let mut stripped = String::with_capacity(content.len());
+        let mut heading = false;
+        for c in content.chars() {
+            match c {
+                '#' if !self.mid_line || heading => heading = true,
+                '*' | '`' => {}
+                ' ' if heading => heading = false,
+                c => {
+                    heading = false;
+                    stripped.push(c);
+                }
+            }
+            self.mid_line = c != '\n';
+        }
+        *content = stripped;
+        Flow::Continue
+    }
+}
+
+pub(super) struct StopSequences {
+    sequences: Vec<String>,
+    /// The end of the content seen so far, which may be the start of a stop
+    /// sequence.
+    pending: String,
+}
+
+impl StopSequences {
+    pub(super) fn new(sequences: Vec<String>) -> Self {
+        Self {
+            sequences: sequences
+                .into_iter()
+                .filter(|s| !s.is_empty())
+                .collect(),
+            pending: String::new(),
+        }
+    }
+}
+
+impl StreamTransform for StopSequences {
+    fn name(&self) -> &'static str {
+        "stop-sequences"
+    }
+
+    fn transform(&mut self, content: &mut String, finished: bool) -> Flow {
+        let mut text = std::mem::take(&mut self.pending);
+        text.push_str(content);
+        if let Some(stop) = self
+            .sequences
+            .iter()
+            .filter_map(|sequence| text.find(sequence.as_str()))
+            .min()
+        {
+            text.truncate(stop);
+            *content = text;
+            return Flow::Stop;
+        }
+        if !finished {
+            // hold back the longest suffix that could start a stop sequence
+            let held = self
+                .sequences
+                .iter()
+                .flat_map(|sequence| {
+                    sequence
+                        .char_indices()
+                        .skip(1)
+                        .map(|(i, _)| &sequence[..i])
+                        .filter(|prefix| text.ends_with(prefix))
+                        .map(str::len)
+                })
+                .max()
+                .unwrap_or_default();
+            self.pending = text.split_off(text.len() - held);
+        }
+        *content = text;
+        Flow::Continue
+    }
+
+    fn take_pending(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+struct BudgetedTransform {
+    transform: Box<dyn StreamTransform>,
+    /// Whether the transform is applied even when it exceeds its budget.
+    enforced: bool,
+    bypassed: bool,
+}
+
+/// Applies the configured transforms and output limits to the chunks of a
+/// single stream.
+pub struct StreamTransformer {
+    config: StreamTransformsConfig,
+    output_limits: OutputLimitsConfig,
+    /// Simulated assistant prefill to strip from the start of the stream.
+    prefill: Option<String>,
+    /// The model generating the completion, whose tokenizer counts its
+    /// output tokens.
+    model: Option<String>,
+    metrics: StreamTransformMetrics,
+    /// Transforms of each choice in the stream.
+    choices: HashMap<u64, Vec<BudgetedTransform>>,
+    /// The number of choices in the stream.
+    expected_choices: usize,
+    /// The choices ended by a transform, whose chunks are no longer
+    /// forwarded.
+    stopped: HashSet<u64>,
+}
+
+impl std::fmt::Debug for StreamTransformer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StreamTransformer")
+            .field("config", &self.config)
+            .field("output_limits", &self.output_limits)
+            .field("stopped", &self.stopped)
+            .finish_non_exhaustive()
+    }
+}
+
+impl StreamTransformer {
+    #[must_use]
+    pub fn new(
+        config: StreamTransformsConfig,
+        output_limits: OutputLimitsConfig,
+        metrics: StreamTransformMetrics,
+    ) -> Self {
+        Self {
+            config,
+            output_limits,
+            prefill: None,
+            model: None,
+            metrics,
+            choices: HashMap::default(),
+            expected_choices: 1,
+            stopped: HashSet::default(),
+        }
+    }
+
+    /// Returns the transformer for the streams of a router, if it has any
+    /// stream transforms or output limits configured.
+    #[must_use]
+    pub fn for_router(
+        router_config: &RouterConfig,
+        metrics: StreamTransformMetrics,
+    ) -> Option<Self> {
+        if router_config.stream_transforms.is_none()
+            && router_config.output_limits.is_none()
+        {
+            return None;
+        }
+        let config =
+            router_config.stream_transforms.clone().unwrap_or_default();
+        let output_limits =
+            router_config.output_limits.clone().unwrap_or_default();
+        Some(Self::new(config, output_limits, metrics))
+    }
+
+    /// Strips a simulated assistant prefill from the start of the stream.
+    pub fn strip_prefill(&mut self, prefill: String) {
+        self.prefill = Some(prefill);
+    }
+
+    /// Sets the model generating the completion.
+    pub fn set_model(&mut self, model: Option<String>) {
+        self.model = model;
+    }
+
+    /// Sets the number of choices in the stream, requested with `n`.
+    pub fn expect_choices(&mut self, choices: usize) {
+        self.expected_choices = choices;
+    }
+
+    /// Whether transforms ended every choice of the completion, so that no
+    /// more chunks will be forwarded.
+    #[must_use]
+    pub fn is_finished(&self) -> bool {
+        self.stopped.len() >= self.expected_choices
+    }
+
+    /// Transforms a chat completion chunk.
+    ///
+    /// The choices a transform already ended are removed from the chunk, and
+    /// `None` is returned if none are left.
+    pub fn transform_chunk(&mut self, chunk: Bytes) -> Option<Bytes> {
+        let Ok(mut value) = serde_json::from_slice::<Value>(&chunk) else {
+            return Some(chunk);
+        };
+        let Some(choices) =
+            value.get_mut("choices").and_then(Value::as_array_mut)
+        else {
+            return Some(chunk);
+        };
+        let had_choices = !choices.is_empty();
+        choices.retain(|choice| !self.stopped.contains(&choice_index(choice)));
+        if had_choices && choices.is_empty() {
+            return None;
+        }
+        for choice in choices {
+            let index = choice_index(choice);
+            let finished = choice
+                .get("finish_reason")
+                .is_some_and(|reason| !reason.is_null());
+            let Some(delta) = choice.get_mut("delta") else {
+                continue;
+            };
+            let mut content = delta
+                .get("content")
+                .and_then(Value::as_str)
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            let had_content = delta.get("content").is_some();
+            let flow = self.apply(index, &mut content, finished);
+            if let Some(finish_reason) = flow.finish_reason() {
+                self.stopped.insert(index);
+                choice["finish_reason"] = Value::from(finish_reason);
+            }
+            if had_content || !content.is_empty() {
+                choice["delta"]["content"] = Value::String(content);
+            }
+        }
+        serde_json::to_vec(&value).ok().map(Bytes::from)
+    }
+
+    fn apply(
+        &mut self,
+        index: u64,
+        content: &mut String,
+        finished: bool,
+    ) -> Flow {
+        let budget = self.config.latency_budget;
+        let transforms = self.choices.entry(index).or_insert_with(|| {
+            Self::chain(
+                &self.config,
+                &self.output_limits,
+                self.prefill.as_deref(),
+                self.model.as_deref(),
+            )
+        });
+        let mut flow = Flow::Continue;
+        for transform in transforms.iter_mut() {
+            if transform.bypassed {
+                continue;
+            }
+            let start = Instant::now();
+            let transformed = transform
+                .transform
+                .transform(content, finished || flow != Flow::Continue);
+            if flow == Flow::Continue {
+                flow = transformed;
+            }
+            let elapsed = start.elapsed();
+            Self::record(&self.metrics, transform, elapsed, budget, content);
+        }
+        flow
+    }
+
+    /// Builds the transforms of a choice. The simulated prefill is stripped
+    /// and the output limits are applied to the completion as generated,
+    /// before it is transformed.
+    fn chain(
+        config: &StreamTransformsConfig,
+        output_limits: &OutputLimitsConfig,
+        prefill: Option<&str>,
+        model: Option<&str>,
+    ) -> Vec<BudgetedTransform> {
+        let enforced = prefill
+            .map(|prefill| {
+                Box::new(StripPrefill::new(prefill.to_string()))
+                    as Box<dyn StreamTransform>
+            })
+            .into_iter()
+            .chain(output_limits::transforms(output_limits, model))
+            .map(|transform| BudgetedTransform {
+                transform,
+                enforced: true,
+                bypassed: false,
+            });
+        let transforms = config.transforms.iter().map(|config| {
+            let transform = build(config);
+            BudgetedTransform {
+                enforced: transform.enforced(),
+                transform,
+                bypassed: false,
+            }
+        });
+        enforced.chain(transforms).collect()
+    }
+
+    fn record(
+        metrics: &StreamTransformMetrics,
+        transform: &mut BudgetedTransform,
+        elapsed: Duration,
+        budget: Duration,
+        content: &mut String,
+    ) {
+        let attributes =
+            [KeyValue::new("transform", transform.transform.name())];
+        metrics
+            .duration
+            .record(elapsed.as_secs_f64() * 1000.0, &attributes);
+        if elapsed > budget && !transform.enforced {
+            tracing::warn!(
+                transform = transform.transform.name(),
+                elapsed = ?elapsed,
+                budget = ?budget,
+                "stream transform exceeded its latency budget, bypassing it"
+            );
+            metrics.budget_exceeded.add(1, &attributes);
+            transform.bypassed = true;
+            content.push_str(&transform.transform.take_pending());
+        }
+    }
+}
+
+fn choice_index(choice: &Value) -> u64 {
+    choice
+        .get("index")
+        .and_then(Value::as_u64)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn transform(
+        transform: &mut dyn StreamTransform,
+        chunks: &[&str],
+    ) -> (String, Flow) {
+        let mut output = String::new();
+        let mut flow = Flow::Continue;
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut content = (*chunk).to_string();
+            flow = transform.transform(&mut content, i == chunks.len() - 1);
+            output.push_str(&content);
+            if flow == Flow::Stop {
+                break;
+            }
+        }
+        (output, flow)
+    }
+
+    #[test]
+    fn mask_words() {
+        let mut mask = Mask::new(&["darn".to_string()]);
+        let (output, _) =
+            transform(&mut mask, &["Darn it, ", "darning is darn hard"]);
+        assert_eq!(output, "**** it, darning is **** hard");
+    }
+
+    #[test]
+    fn mask_words_across_chunks() {
+        let mut mask = Mask::new(&["darn".to_string(), "heck no".to_string()]);
+        let (output, _) = transform(
+            &mut mask,
+            &["it's da", "rn, da", "rning, heck", " no", " and darn"],
+        );
+        assert_eq!(output, "it's ****, darning, ******* and ****");
+
+        let mut mask = Mask::new(&["darn".to_string()]);
+        let mut first = "so darn".to_string();
+        mask.transform(&mut first, false);
+        assert_eq!(first, "so ");
+        assert!(mask.enforced());
+    }
+
+    #[test]
+    fn strip_markdown() {
+        let mut strip = StripMarkdown::default();
+        let (output, _) = transform(
+            &mut strip,
+            &["# Title\nSome **bold**", " and `code`.\n## Sub", "#tag"],
+        );
+        assert_eq!(output, "Title\nSome bold and code.\nSub#tag");
+    }
+
+    #[test]
+    fn stop_sequences_across_chunks() {
+        let mut stop = StopSequences::new(vec!["STOP".to_string()]);
+        let (output, flow) =
+            transform(&mut stop, &["Hello ST", "OP world", "ignored"]);
+        assert_eq!(output, "Hello ");
+        assert_eq!(flow, Flow::Stop);
+
+        let mut stop = StopSequences::new(vec!["STOP".to_string()]);
+        let (output, flow) = transform(&mut stop, &["Hello ST", "ART"]);
+        assert_eq!(output, "Hello START");
+        assert_eq!(flow, Flow::Continue);
+    }
+
+    #[test]
+    fn transformer_stops_stream() {
+        let config = StreamTransformsConfig {
+            latency_budget: Duration::from_secs(1),
+            transforms: vec![StreamTransformConfig::StopSequences {
+                sequences: vec!["\n\n".to_string()],
+            }],
+        };
+        let meter = opentelemetry::global::meter("test");
+        let mut transformer = StreamTransformer::new(
+            config,
+            OutputLimitsConfig::default(),
+            StreamTransformMetrics::new(&meter),
+        );
+        let chunk = |content: &str| {
+            Bytes::from(
+                serde_json::to_vec(&json!({
+                    "choices": [{
+                        "index": 0,
+                        "delta": {"content": content},
+                        "finish_reason": null,
+                    }]
+                }))
+                .unwrap(),
+            )
+        };
+        let first = transformer.transform_chunk(chunk("one\n\ntwo")).unwrap();
+        let first = serde_json::from_slice::<Value>(&first).unwrap();
+        assert_eq!(first["choices"][0]["delta"]["content"], "one");
+        assert_eq!(first["choices"][0]["finish_reason"], "stop");
+        assert!(transformer.is_finished());
+        assert!(transformer.transform_chunk(chunk("three")).is_none());
+    }
+
+    #[test]
+    fn transformer_stops_each_choice() {
+        let config = StreamTransformsConfig {
+            latency_budget: Duration::from_secs(1),
+            transforms: vec![StreamTransformConfig::StopSequences {
+                sequences: vec!["STOP".to_string()],
+            }],
+        };
+        let meter = opentelemetry::global::meter("test");
+        let mut transformer = StreamTransformer::new(
+            config,
+            OutputLimitsConfig::default(),
+            StreamTransformMetrics::new(&meter),
+        );
+        transformer.expect_choices(2);
+        let chunk = |index: u64, content: &str| {
+            Bytes::from(
+                serde_json::to_vec(&json!({
+                    "choices": [{
+                        "index": index,
+                        "delta": {"content": content},
+                        "finish_reason": null,
+                    }]
+                }))
+                .unwrap(),
+            )
+        };
+        let first = transformer.transform_chunk(chunk(0, "a STOP")).unwrap();
+        let first = serde_json::from_slice::<Value>(&first).unwrap();
+        assert_eq!(first["choices"][0]["finish_reason"], "stop");
+        assert!(!transformer.is_finished());
+        assert!(transformer.transform_chunk(chunk(0, "b")).is_none());
+
+        let second = transformer.transform_chunk(chunk(1, "c")).unwrap();
+        let second = serde_json::from_slice::<Value>(&second).unwrap();
+        assert_eq!(second["choices"][0]["delta"]["content"], "c");
+        transformer.transform_chunk(chunk(1, "STOP"));
+        assert!(transformer.is_finished());
+    }
+
+    #[test]
+    fn requested_choices_default_to_one() {
+        assert_eq!(requested_choices(br#"{"model": "gpt-4o"}"#), 1);
+        assert_eq!(requested_choices(br#"{"n": 3}"#), 3);
+        assert_eq!(requested_choices(br#"{"n": 0}"#), 1);
+    }
+
+    #[test]
+    fn transformer_limits_output_tokens() {
+        let meter = opentelemetry::global::meter("test");
+        let mut transformer = StreamTransformer::new(
+            StreamTransformsConfig::default(),
+            OutputLimitsConfig {
+                stop: Vec::new(),
+                max_output_tokens: Some(2),
+            },
+            StreamTransformMetrics::new(&meter),
+        );
+        transformer.set_model(Some("openai/gpt-4o".to_string()));
+        let chunk = Bytes::from(
+            serde_json::to_vec(&json!({
+                "choices": [{
+                    "index": 0,
+                    "delta": {"content": "Hello, world"},
+                    "finish_reason": null,
+                }]
+            }))
+            .unwrap(),
+        );
+        let chunk = transformer.transform_chunk(chunk).unwrap();
+        let chunk = serde_json::from_slice::<Value>(&chunk).unwrap();
+        assert_eq!(chunk["choices"][0]["delta"]["content"], "Hello,");
+        assert_eq!(chunk["choices"][0]["finish_reason"], "length");
+        assert!(transformer.is_finished());
+    }
+}
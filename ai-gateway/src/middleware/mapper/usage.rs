@@ -0,0 +1,241 @@
+//! Usage reporting for streamed completions.
+//!
+//! OpenAI clients request the usage of a streamed completion with
+//! `stream_options.include_usage`, and expect it in a final chunk without
+//! choices. Not every provider reports it in the stream, so when a stream
+//! ends without usage the gateway counts it with the model's tokenizer, the
+//! same way it estimates context lengths, and sends the final chunk itself.
+//! The chunk follows OpenAI's schema, so it can't say that its usage was
+//! estimated. Instead, such streams are sent with the
+//! `helicone-token-count-estimated` header set to [`ESTIMATED_IF_MISSING`],
+//! as whether the provider reports the usage is only known once the stream
+//! ends.
+use bytes::Bytes;
+use http::HeaderValue;
+use serde_json::{Value, json};
+
+use crate::{
+    endpoints::ApiEndpoint, middleware::context_length::estimate_prompt_tokens,
+    utils::tokenizer::count_tokens,
+};
+
+/// The value of the `helicone-token-count-estimated` header on streams whose
+/// usage is estimated if the provider doesn't report it.
+pub const ESTIMATED_IF_MISSING: HeaderValue =
+    HeaderValue::from_static("if-missing");
+
+/// Tracks the usage of a streamed completion whose client requested it.
+#[derive(Debug)]
+pub struct StreamUsage {
+    /// The model of the request, whose tokenizer counts the completion.
+    model: Option<String>,
+    prompt_tokens: u32,
+    /// The completion streamed so far, counted once the stream ends so that
+    /// tokens split across chunks aren't counted twice.
+    completion_text: String,
+    /// Whether the provider reported the usage itself.
+    reported: bool,
+    /// The `id`, `created` and `model` of the completion, copied to the
+    /// synthesized chunk.
+    completion: Option<Value>,
+}
+
+impl StreamUsage {
+    /// Returns `Some` if `body`, a request to `source_endpoint`, asks for
+    /// the usage of a streamed completion.
+    #[must_use]
+    pub fn for_request(
+        source_endpoint: &ApiEndpoint,
+        body: &Bytes,
+    ) -> Option<Self> {
+        if !matches!(source_endpoint, ApiEndpoint::OpenAI(_)) {
+            return None;
+        }
+        let param = b"include_usage";
+        if !body.windows(param.len()).any(|window| window == param) {
+            return None;
+        }
+        let body = serde_json::from_slice::<Value>(body).ok()?;
+        let include_usage = body.get("stream").and_then(Value::as_bool)
+            == Some(true)
+            && body.pointer("/stream_options/include_usage")
+                == Some(&Value::Bool(true));
+        include_usage.then(|| Self {
+            model: body
+                .get("model")
+                .and_then(Value::as_str)
+                .map(ToString::to_string),
+            prompt_tokens: estimate_prompt_tokens(&body),
+            completion_text: String::new(),
+            reported: false,
+            completion: None,
+        })
+    }
+
+    /// Records a chat completion chunk sent to the client.
+    pub fn observe(&mut self, chunk: &Bytes) {
+        if self.reported {
+            return;
+        }
+        let Ok(chunk) = serde_json::from_slice::<Value>(chunk) else {
+            return;
+        };
+        if chunk.get("usage").is_some_and(|usage| !usage.is_null()) {
+            self.reported = true;
+            return;
+        }
+        let Some(choices) = chunk.get("choices").and_then(Value::as_array)
+        else {
+            return;
+        };
+        for delta in choices.iter().filter_map(|choice| choice.get("delta")) {
+            if let Some(content) = delta.get("content").and_then(Value::as_str)
+            {
+                self.completion_text.push_str(content);
+            }
+            let arguments = delta
+                .get("tool_calls")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(|call| call.pointer("/function/arguments"))
+                .filter_map(Value::as_str);
+            for arguments in arguments {
+                self.completion_text.push_str(arguments);
+            }
+        }
+        if self.completion.is_none() {
+            self.completion = Some(json!({
+                "id": chunk.get("id"),
+                "created": chunk.get("created"),
+                "model": chunk.get("model"),
+            }));
+        }
+    }
+
+    /// Returns the usage chunk to end the stream with, if the provider
+    /// didn't report the usage itself.
+    #[must_use]
+    pub fn finish(&self) -> Option<Bytes> {
+        if self.reported {
+            return None;
+        }
+        // streams that never sent a completion chunk, such as errors, are
+        // left as is
+        let completion = self.completion.as_ref()?;
+        let completion_tokens = u32::try_from(count_tokens(
+            self.model.as_deref(),
+            &self.completion_text,
+        ))
+        .unwrap_or(u32::MAX);
+        let chunk = json!({
+            "id": completion["id"],
+            "object": "chat.completion.chunk",
+            "created": completion["created"],
+            "model": completion["model"],
+            "choices": [],
+            "usage": {
+                "prompt_tokens": self.prompt_tokens,
+                "completion_tokens": completion_tokens,
+                "total_tokens":
+                    self.prompt_tokens.saturating_add(completion_tokens),
+            },
+        });
+        serde_json::to_vec(&chunk).ok().map(Bytes::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::endpoints::openai::{ChatCompletions, OpenAI};
+
+    fn chat_completions() -> ApiEndpoint {
+        ApiEndpoint::OpenAI(OpenAI::ChatCompletions(ChatCompletions))
+    }
+
+    fn request(stream_options: &Value) -> Bytes {
+        Bytes::from(
+            serde_json::to_vec(&json!({
+                "model": "anthropic/claude-3-5-sonnet",
+                "stream": true,
+                "stream_options": stream_options,
+                "messages": [{"role": "user", "content": "12345678"}],
+            }))
+            .unwrap(),
+        )
+    }
+
+    fn chunk(chunk: &Value) -> Bytes {
+        Bytes::from(serde_json::to_vec(chunk).unwrap())
+    }
+
+    #[test]
+    fn only_requested_usage_is_tracked() {
+        let endpoint = chat_completions();
+        let body = request(&json!({"include_usage": false}));
+        assert!(StreamUsage::for_request(&endpoint, &body).is_none());
+        let body = request(&json!({"include_usage": true}));
+        assert!(StreamUsage::for_request(&endpoint, &body).is_some());
+    }
+
+    #[test]
+    fn missing_usage_is_synthesized() {
+        let body = request(&json!({"include_usage": true}));
+        let mut usage =
+            StreamUsage::for_request(&chat_completions(), &body).unwrap();
+        usage.observe(&chunk(&json!({
+            "id": "chatcmpl-1",
+            "created": 1,
+            "model": "claude-3-5-sonnet",
+            "choices": [{"index": 0, "delta": {"content": "Hello, wo"}}],
+        })));
+        usage.observe(&chunk(&json!({
+            "id": "chatcmpl-1",
+            "choices": [{"index": 0, "delta": {"content": "rld"}}],
+        })));
+        let synthesized =
+            serde_json::from_slice::<Value>(&usage.finish().unwrap()).unwrap();
+        assert_eq!(synthesized["id"], "chatcmpl-1");
+        assert_eq!(synthesized["choices"], json!([]));
+        // the message adds 4 tokens to the prompt, and "Hello, world" is
+        // counted as a whole rather than per chunk
+        assert_eq!(
+            synthesized["usage"],
+            json!({
+                "prompt_tokens": 7,
+                "completion_tokens": 3,
+                "total_tokens": 10,
+            })
+        );
+        // nothing but the fields of OpenAI's schema
+        let mut fields = synthesized
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        fields.sort_unstable();
+        assert_eq!(
+            fields,
+            ["choices", "created", "id", "model", "object", "usage"]
+        );
+    }
+
+    #[test]
+    fn reported_usage_is_kept() {
+        let body = request(&json!({"include_usage": true}));
+        let mut usage =
+            StreamUsage::for_request(&chat_completions(), &body).unwrap();
+        usage.observe(&chunk(&json!({
+            "id": "chatcmpl-1",
+            "choices": [{"index": 0, "delta": {"content": "Hi"}}],
+        })));
+        usage.observe(&chunk(&json!({
+            "id": "chatcmpl-1",
+            "choices": [],
+            "usage": {"prompt_tokens": 9, "completion_tokens": 1},
+        })));
+        assert!(usage.finish().is_none());
+    }
+}
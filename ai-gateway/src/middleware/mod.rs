@@ -1,8 +1,18 @@
 pub mod add_extension;
 pub mod auth;
 pub mod cache;
+pub mod context_length;
+pub mod conversation;
+pub mod cost_cap;
+pub mod differential;
+pub mod json_mode;
+pub mod latency_slo;
 pub mod mapper;
 pub mod prompts;
+pub mod provenance;
 pub mod rate_limit;
 pub mod request_context;
 pub mod response_headers;
+pub mod script;
+pub mod tier_policy;
+pub mod wasm_filter;
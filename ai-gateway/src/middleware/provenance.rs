@@ -0,0 +1,264 @@
+//! Provenance metadata for compliance traceability.
+//!
+//! Successful responses are tagged with the model and provider which
+//! produced them and the time they were produced, in the
+//! `helicone-provenance` header and optionally in a top level field of JSON
+//! response bodies. With a signing key, the metadata carries an HMAC-SHA256
+//! signature of its canonical form,
+//! `model={model};provider={provider};timestamp={timestamp}`, so that its
+//! origin can be verified.
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use chrono::{SecondsFormat, Utc};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use http::{HeaderName, HeaderValue};
+use http_body_util::BodyExt;
+use serde::Serialize;
+use sha2::Sha256;
+
+use crate::{
+    config::{provenance::ProvenanceConfig, router::RouterConfig},
+    error::{api::ApiError, internal::InternalError},
+    types::{
+        extensions::MapperContext, provider::InferenceProvider,
+        request::Request, response::Response, secret::Secret,
+    },
+};
+
+const PROVENANCE_HEADER: HeaderName =
+    HeaderName::from_static("helicone-provenance");
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+struct Provenance {
+    model: String,
+    provider: String,
+    timestamp: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+impl Provenance {
+    fn new(
+        model: String,
+        provider: String,
+        timestamp: String,
+        signing_key: Option<&Secret<String>>,
+    ) -> Self {
+        let mut provenance = Self {
+            model,
+            provider,
+            timestamp,
+            signature: None,
+        };
+        provenance.signature = signing_key.map(|key| {
+            let mut mac =
+                Hmac::<Sha256>::new_from_slice(key.expose().as_bytes())
+                    .expect("hmac accepts keys of any length");
+            mac.update(provenance.canonical().as_bytes());
+            format!("{:x}", mac.finalize().into_bytes())
+        });
+        provenance
+    }
+
+    fn of(response: &Response, signing_key: Option<&Secret<String>>) -> Self {
+        let model = response
+            .extensions()
+            .get::<MapperContext>()
+            .and_then(|mapper_ctx| mapper_ctx.model.as_ref())
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let provider = response
+            .extensions()
+            .get::<InferenceProvider>()
+            .map(ToString::to_string)
+            .unwrap_or_default();
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+        Self::new(model, provider, timestamp, signing_key)
+    }
+
+    /// The signed form of the metadata.
+    fn canonical(&self) -> String {
+        format!(
+            "model={};provider={};timestamp={}",
+            self.model, self.provider, self.timestamp
+        )
+    }
+
+    fn header_value(&self) -> Option<HeaderValue> {
+        let value = match &self.signature {
+            Some(signature) => {
+                format!("{};signature={signature}", self.canonical())
+            }
+            None => self.canonical(),
+        };
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+fn is_json(response: &Response) -> bool {
+    response
+        .headers()
+        .get(http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| mime.essence_str() == mime::APPLICATION_JSON)
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    config: Arc<ProvenanceConfig>,
+}
+
+impl Layer {
+    #[must_use]
+    pub fn for_router(router_config: &RouterConfig) -> Option<Self> {
+        router_config.provenance.as_ref().map(|config| Self {
+            config: Arc::new(config.clone()),
+        })
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            config: Arc::clone(&self.config),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    config: Arc<ProvenanceConfig>,
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "provenance", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let config = Arc::clone(&self.config);
+        Box::pin(async move {
+            let mut response = inner.call(req).await?;
+            if !response.status().is_success() {
+                return Ok(response);
+            }
+            let provenance =
+                Provenance::of(&response, config.signing_key.as_ref());
+            if config.header
+                && let Some(header_value) = provenance.header_value()
+            {
+                response
+                    .headers_mut()
+                    .insert(PROVENANCE_HEADER, header_value);
+            }
+            let Some(body_field) = &config.body_field else {
+                return Ok(response);
+            };
+            if !is_json(&response) {
+                return Ok(response);
+            }
+
+            let (mut parts, body) = response.into_parts();
+            let body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let Ok(serde_json::Value::Object(mut body_json)) =
+                serde_json::from_slice::<serde_json::Value>(&body_bytes)
+            else {
+                return Ok(Response::from_parts(parts, body_bytes.into()));
+            };
+            let provenance =
+                serde_json::to_value(&provenance).map_err(|error| {
+                    InternalError::Serialize {
+                        ty: "Provenance",
+                        error,
+                    }
+                })?;
+            body_json.insert(body_field.clone(), provenance);
+            let body_bytes =
+                serde_json::to_vec(&body_json).map_err(|error| {
+                    InternalError::Serialize {
+                        ty: "serde_json::Value",
+                        error,
+                    }
+                })?;
+            parts.headers.remove(http::header::CONTENT_LENGTH);
+            Ok(Response::from_parts(parts, body_bytes.into()))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsigned_provenance_header() {
+        let provenance = Provenance::new(
+            "openai/gpt-4o".to_string(),
+            "openai".to_string(),
+            "2025-01-01T00:00:00Z".to_string(),
+            None,
+        );
+        assert_eq!(
+            provenance.header_value().unwrap(),
+            "model=openai/gpt-4o;provider=openai;timestamp=\
+             2025-01-01T00:00:00Z"
+        );
+    }
+
+    #[test]
+    fn signature_covers_canonical_form() {
+        let key = Secret::from("provenance-secret".to_string());
+        let provenance = Provenance::new(
+            "openai/gpt-4o".to_string(),
+            "openai".to_string(),
+            "2025-01-01T00:00:00Z".to_string(),
+            Some(&key),
+        );
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"provenance-secret")
+            .expect("hmac accepts keys of any length");
+        mac.update(provenance.canonical().as_bytes());
+        assert_eq!(
+            provenance.signature,
+            Some(format!("{:x}", mac.finalize().into_bytes()))
+        );
+
+        let tampered = Provenance::new(
+            "openai/gpt-4o-mini".to_string(),
+            "openai".to_string(),
+            "2025-01-01T00:00:00Z".to_string(),
+            Some(&key),
+        );
+        assert_ne!(tampered.signature, provenance.signature);
+    }
+}
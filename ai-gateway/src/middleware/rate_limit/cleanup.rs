@@ -1,11 +1,17 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::future::BoxFuture;
 use meltdown::Token;
-use tracing::{error, info};
+use tracing::{debug, info};
 
 use crate::{app_state::AppState, error::runtime::RuntimeError};
 
+/// Periodically evicts expired in-memory rate limit state.
+///
+/// Most state is already evicted inline with rate limit checks, so this only
+/// has to catch up on keys in shards that haven't seen traffic recently.
+/// Eviction is done in bounded batches, so it never blocks requests for
+/// long regardless of how many keys are tracked.
 pub struct GarbageCollector {
     pub app_state: AppState,
     pub cleanup_interval: Duration,
@@ -19,40 +25,43 @@ impl GarbageCollector {
             cleanup_interval,
         }
     }
+
+    async fn collect(&self) {
+        let now = Instant::now();
+        let mut evicted = 0;
+        if let Some(global_rate_limit) =
+            self.app_state.0.global_rate_limit.as_ref()
+        {
+            evicted += global_rate_limit.evict_expired(now);
+        }
+        let router_limits = self.app_state.0.router_rate_limits.read().await;
+        for limiter in router_limits.values() {
+            evicted += limiter.evict_expired(now);
+            tokio::task::yield_now().await;
+        }
+        debug!(
+            evicted,
+            duration_ms = now.elapsed().as_millis(),
+            "evicted expired rate limit state"
+        );
+    }
 }
 
 impl meltdown::Service for GarbageCollector {
     type Future = BoxFuture<'static, Result<(), RuntimeError>>;
 
     fn run(self, mut token: Token) -> Self::Future {
-        let app_state = self.app_state;
-        let cleanup_interval = self.cleanup_interval;
         Box::pin(async move {
-            tokio::task::spawn_blocking(move || async move {
-                let global_rate_limit = app_state.0.global_rate_limit.clone();
-                loop {
-                    tokio::select! {
-                        () = tokio::time::sleep(cleanup_interval) => {
-                            if let Some(global_rate_limit) = global_rate_limit.as_ref() {
-                                global_rate_limit.limiter().retain_recent();
-                            }
-
-                            let router_limits = app_state.0.router_rate_limits.read().await;
-                            for rate_limit_config in router_limits.values() {
-                                rate_limit_config.limiter().retain_recent();
-                            }
-                        }
-                        () = &mut token => {
-                            info!(name = "rate-limiting-cleanup-task", "task shutting down");
-                            break;
-                        }
+            let mut interval = tokio::time::interval(self.cleanup_interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => self.collect().await,
+                    () = &mut token => {
+                        info!(name = "rate-limiting-cleanup-task", "task shutting down");
+                        break;
                     }
                 }
-            })
-            .await
-            .inspect_err(|e| error!(error = %e, "error running rate limiting cleanup task"))?
-            .await;
-
+            }
             Ok(())
         })
     }
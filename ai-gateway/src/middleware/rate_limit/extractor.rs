@@ -1,37 +1,73 @@
+use std::net::IpAddr;
+
 use http::Request;
-use tower_governor::{GovernorError, key_extractor::KeyExtractor};
 
 use crate::{
     error::internal::InternalError,
-    types::{extensions::AuthContext, router::RouterId, user::UserId},
+    types::{
+        extensions::{AuthContext, ClientIp},
+        router::RouterId,
+        user::UserId,
+    },
 };
 
-#[derive(Debug, Clone)]
-pub struct RateLimitKeyExtractor;
-
-impl KeyExtractor for RateLimitKeyExtractor {
-    type Key = UserId;
-    fn extract<T>(&self, req: &Request<T>) -> Result<Self::Key, GovernorError> {
-        get_user_id(req).map_err(|_| GovernorError::UnableToExtractKey)
-    }
+/// Who a request is rate limited as: its API key's user, or its client's IP
+/// address when it isn't authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RateLimitKey {
+    ApiKey(UserId),
+    ClientIp(IpAddr),
 }
 
-fn get_user_id<T>(req: &Request<T>) -> Result<UserId, InternalError> {
-    let Some(ctx) = req.extensions().get::<AuthContext>() else {
-        return Err(InternalError::ExtensionNotFound("AuthContext"));
-    };
-
-    Ok(ctx.user_id)
+pub(crate) fn get_rl_key<T>(
+    req: &Request<T>,
+) -> Result<RateLimitKey, InternalError> {
+    if let Some(ctx) = req.extensions().get::<AuthContext>() {
+        return Ok(RateLimitKey::ApiKey(ctx.user_id));
+    }
+    if let Some(ClientIp(ip)) = req.extensions().get::<ClientIp>() {
+        return Ok(RateLimitKey::ClientIp(*ip));
+    }
+    Err(InternalError::ExtensionNotFound("AuthContext"))
 }
 
 pub fn get_redis_rl_key<T>(
     req: &Request<T>,
     router_id: Option<&RouterId>,
 ) -> Result<String, InternalError> {
-    let user_id = get_user_id(req)?;
-    if let Some(router_id) = router_id {
-        Ok(format!("rl:per-api-key:{router_id}:{user_id}"))
-    } else {
-        Ok(format!("rl:per-api-key:GLOBAL:{user_id}"))
+    let scope = router_id.map_or("GLOBAL", AsRef::as_ref);
+    match get_rl_key(req)? {
+        RateLimitKey::ApiKey(user_id) => {
+            Ok(format!("rl:per-api-key:{scope}:{user_id}"))
+        }
+        RateLimitKey::ClientIp(ip) => Ok(format!("rl:per-ip:{scope}:{ip}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{org::OrgId, priority::Priority, secret::Secret};
+
+    #[test]
+    fn unauthenticated_requests_are_limited_per_client_ip() {
+        let user_id = UserId::new(uuid::Uuid::new_v4());
+        let mut req = Request::new(());
+        req.extensions_mut()
+            .insert(ClientIp(IpAddr::from([203, 0, 113, 7])));
+        assert_eq!(
+            get_redis_rl_key(&req, None).unwrap(),
+            "rl:per-ip:GLOBAL:203.0.113.7"
+        );
+
+        req.extensions_mut().insert(AuthContext {
+            api_key: Secret::from("sk-test".to_string()),
+            user_id,
+            org_id: OrgId::new(uuid::Uuid::new_v4()),
+            max_priority: Priority::Normal,
+            tier: None,
+        });
+        assert_eq!(get_rl_key(&req).unwrap(), RateLimitKey::ApiKey(user_id));
+        assert!(get_rl_key(&Request::new(())).is_err());
     }
 }
@@ -0,0 +1,434 @@
+//! In-memory GCRA rate limiting.
+//!
+//! State is kept per key as a theoretical arrival time (TAT), in a fixed
+//! number of shards each guarded by their own lock. Once a key's TAT has
+//! passed its quota is full again, so its state can be dropped. Each shard
+//! keeps a queue of keys in the order they may expire, which is drained a
+//! bounded batch at a time, both inline with rate limit checks and by the
+//! background [`GarbageCollector`](super::cleanup::GarbageCollector). This
+//! keeps the time any lock is held independent of the number of keys.
+use std::{
+    collections::VecDeque,
+    hash::{BuildHasher, Hash},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use axum_core::response::Response;
+use futures::future::BoxFuture;
+use http::HeaderValue;
+use rustc_hash::{FxBuildHasher, FxHashMap as HashMap};
+
+use crate::{
+    config::rate_limit::{GcraConfig, default_refill_frequency},
+    error::{
+        api::ApiError,
+        init::InitError,
+        invalid_req::{InvalidRequestError, TooManyRequestsError},
+    },
+    middleware::rate_limit::{
+        extractor::{RateLimitKey, get_rl_key},
+        total::TotalRateLimiter,
+    },
+    types::{priority::Priority, request::Request},
+};
+
+const SHARDS: usize = 64;
+/// Maximum number of expiry queue entries examined while holding a shard
+/// lock.
+pub const EVICTION_BATCH_SIZE: usize = 128;
+/// Number of expiry queue entries examined inline with each rate limit
+/// check, amortizing eviction across requests.
+const INLINE_EVICTIONS: usize = 2;
+
+#[derive(Debug)]
+struct Shard<K> {
+    /// Theoretical arrival time of the next request per key.
+    tats: HashMap<K, Instant>,
+    /// Every key in `tats` appears exactly once in this queue, along with
+    /// the TAT it had when it was queued. It is only approximately sorted,
+    /// so an expired key may wait behind an unexpired one for at most one
+    /// refill period.
+    expiry: VecDeque<(Instant, K)>,
+}
+
+impl<K> Default for Shard<K> {
+    fn default() -> Self {
+        Self {
+            tats: HashMap::default(),
+            expiry: VecDeque::new(),
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone> Shard<K> {
+    /// Evicts expired keys, examining at most `budget` queue entries.
+    /// Returns the number of evicted keys, and whether more entries are
+    /// known to be expired.
+    fn evict_expired(&mut self, now: Instant, budget: usize) -> (usize, bool) {
+        let mut evicted = 0;
+        for _ in 0..budget {
+            match self.expiry.front() {
+                Some((expires_at, _)) if *expires_at <= now => {}
+                _ => return (evicted, false),
+            }
+            let Some((_, key)) = self.expiry.pop_front() else {
+                return (evicted, false);
+            };
+            match self.tats.get(&key) {
+                // the key was used since it was queued
+                Some(tat) if *tat > now => self.expiry.push_back((*tat, key)),
+                Some(_) => {
+                    self.tats.remove(&key);
+                    evicted += 1;
+                }
+                None => {}
+            }
+        }
+        let more = self
+            .expiry
+            .front()
+            .is_some_and(|(expires_at, _)| *expires_at <= now);
+        (evicted, more)
+    }
+}
+
+/// Time between two cells of `gcra` becoming available.
+pub(super) fn emission_interval(
+    gcra: &GcraConfig,
+) -> Result<Duration, InitError> {
+    let emission_interval = gcra
+        .refill_frequency
+        .checked_div(gcra.capacity.get())
+        .unwrap_or_else(|| {
+            tracing::warn!(
+                "fill_frequency is too small for capacity, using default fill \
+                 frequency"
+            );
+            default_refill_frequency()
+        });
+    if emission_interval.is_zero() {
+        return Err(InitError::InvalidRateLimitConfig(
+            "refill frequency is too small for capacity",
+        ));
+    }
+    Ok(emission_interval)
+}
+
+/// A keyed GCRA rate limiter with incrementally evicted state.
+#[derive(Debug)]
+pub struct InMemoryRateLimiter<K = RateLimitKey> {
+    /// Time between two cells becoming available.
+    emission_interval: Duration,
+    capacity: u32,
+    shards: Box<[Mutex<Shard<K>>]>,
+}
+
+impl<K: Hash + Eq + Clone> InMemoryRateLimiter<K> {
+    pub fn new(gcra: &GcraConfig) -> Result<Self, InitError> {
+        Ok(Self {
+            emission_interval: emission_interval(gcra)?,
+            capacity: gcra.capacity.get(),
+            shards: (0..SHARDS).map(|_| Mutex::default()).collect(),
+        })
+    }
+
+    #[must_use]
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    fn shard(&self, key: &K) -> &Mutex<Shard<K>> {
+        let hash =
+            usize::try_from(FxBuildHasher.hash_one(key)).unwrap_or_default();
+        &self.shards[hash % self.shards.len()]
+    }
+
+    /// Checks whether a request for `key` arriving at `now` is allowed,
+    /// returning the remaining quota if it is, or how long until it would be
+    /// allowed otherwise.
+    pub fn check(&self, key: &K, now: Instant) -> Result<u32, Duration> {
+        self.check_with_reserve(key, now, 0)
+    }
+
+    /// Like [`Self::check`], but only allows the request if at least
+    /// `reserve` of the quota remains afterwards, leaving it for other
+    /// requests.
+    pub fn check_with_reserve(
+        &self,
+        key: &K,
+        now: Instant,
+        reserve: u32,
+    ) -> Result<u32, Duration> {
+        let burst =
+            self.emission_interval * self.capacity.saturating_sub(reserve);
+        let mut shard = self.shard(key).lock().expect("shard lock poisoned");
+        let tat = shard
+            .tats
+            .get(key)
+            .copied()
+            .filter(|tat| *tat > now)
+            .unwrap_or(now);
+        let new_tat = tat + self.emission_interval;
+        let allow_at = new_tat.checked_sub(burst).unwrap_or(now);
+        if allow_at > now {
+            return Err(allow_at - now);
+        }
+        if shard.tats.insert(key.clone(), new_tat).is_none() {
+            shard.expiry.push_back((new_tat, key.clone()));
+        }
+        shard.evict_expired(now, INLINE_EVICTIONS);
+        drop(shard);
+
+        let used = (new_tat - now)
+            .as_nanos()
+            .div_ceil(self.emission_interval.as_nanos());
+        let used = u32::try_from(used).unwrap_or(u32::MAX);
+        Ok(self.capacity.saturating_sub(used))
+    }
+
+    /// Evicts the state of every key whose quota is full again, holding
+    /// each shard lock for at most [`EVICTION_BATCH_SIZE`] keys at a time.
+    /// Returns the number of evicted keys.
+    pub fn evict_expired(&self, now: Instant) -> usize {
+        let mut evicted = 0;
+        for shard in &self.shards {
+            loop {
+                let (batch_evicted, more) = shard
+                    .lock()
+                    .expect("shard lock poisoned")
+                    .evict_expired(now, EVICTION_BATCH_SIZE);
+                evicted += batch_evicted;
+                if !more {
+                    break;
+                }
+            }
+        }
+        evicted
+    }
+
+    /// Number of keys with state held.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().expect("shard lock poisoned").tats.len())
+            .sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InMemoryRateLimitLayer {
+    pub limiter: Arc<InMemoryRateLimiter>,
+    pub total: Option<Arc<TotalRateLimiter<RateLimitKey>>>,
+}
+
+impl InMemoryRateLimitLayer {
+    #[must_use]
+    pub fn new(
+        limiter: Arc<InMemoryRateLimiter>,
+        total: Option<Arc<TotalRateLimiter<RateLimitKey>>>,
+    ) -> Self {
+        Self { limiter, total }
+    }
+}
+
+impl<S> tower::layer::Layer<S> for InMemoryRateLimitLayer {
+    type Service = InMemoryRateLimitService<S>;
+
+    fn layer(&self, service: S) -> Self::Service {
+        InMemoryRateLimitService {
+            inner: service,
+            limiter: self.limiter.clone(),
+            total: self.total.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct InMemoryRateLimitService<S> {
+    pub inner: S,
+    pub limiter: Arc<InMemoryRateLimiter>,
+    pub total: Option<Arc<TotalRateLimiter<RateLimitKey>>>,
+}
+
+impl<S> tower::Service<Request> for InMemoryRateLimitService<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Send
+        + Clone
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        // see: https://docs.rs/tower/latest/tower/trait.Service.html#be-careful-when-cloning-inner-services
+        let mut this = self.clone();
+        std::mem::swap(self, &mut this);
+        Box::pin(async move {
+            let key = get_rl_key(&req)?;
+            let ratelimit_limit = u64::from(this.limiter.capacity());
+            let reserve = req
+                .extensions()
+                .get::<Priority>()
+                .copied()
+                .unwrap_or_default()
+                .rate_limit_reserve(this.limiter.capacity());
+            match this
+                .limiter
+                .check_with_reserve(&key, Instant::now(), reserve)
+            {
+                Ok(ratelimit_remaining) => {
+                    if let Some(total) = &this.total
+                        && let Err(wait) = total.acquire(key).await
+                    {
+                        return Err(too_many_requests(
+                            u64::from(total.capacity()),
+                            wait,
+                        ));
+                    }
+                    let mut res = this.inner.call(req).await?;
+                    res.headers_mut().insert(
+                        "x-ratelimit-limit",
+                        HeaderValue::from(ratelimit_limit),
+                    );
+                    res.headers_mut().insert(
+                        "x-ratelimit-remaining",
+                        HeaderValue::from(ratelimit_remaining),
+                    );
+                    Ok(res)
+                }
+                Err(wait) => Err(too_many_requests(ratelimit_limit, wait)),
+            }
+        })
+    }
+}
+
+fn too_many_requests(ratelimit_limit: u64, wait: Duration) -> ApiError {
+    ApiError::InvalidRequest(InvalidRequestError::TooManyRequests(
+        TooManyRequestsError {
+            ratelimit_limit,
+            ratelimit_remaining: 0,
+            // adding a second to retry-after header to prevent rounding
+            // errors
+            retry_after: wait.as_secs() + 1,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use super::*;
+
+    fn limiter(capacity: u32, refill: Duration) -> InMemoryRateLimiter<u64> {
+        InMemoryRateLimiter::new(&GcraConfig {
+            refill_frequency: refill,
+            capacity: NonZeroU32::new(capacity).unwrap(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn capacity_is_enforced() {
+        let limiter = limiter(3, Duration::from_millis(300));
+        let now = Instant::now();
+        assert_eq!(limiter.check(&1, now), Ok(2));
+        assert_eq!(limiter.check(&1, now), Ok(1));
+        assert_eq!(limiter.check(&1, now), Ok(0));
+        assert_eq!(limiter.check(&1, now), Err(Duration::from_millis(100)));
+        // other keys are unaffected
+        assert_eq!(limiter.check(&2, now), Ok(2));
+    }
+
+    #[test]
+    fn reserve_is_left_for_other_requests() {
+        let limiter = limiter(5, Duration::from_millis(500));
+        let now = Instant::now();
+        let reserve = Priority::Low.rate_limit_reserve(limiter.capacity());
+        for remaining in (1..5).rev() {
+            assert_eq!(
+                limiter.check_with_reserve(&1, now, reserve),
+                Ok(remaining)
+            );
+        }
+        assert!(limiter.check_with_reserve(&1, now, reserve).is_err());
+        assert_eq!(limiter.check(&1, now), Ok(0));
+    }
+
+    #[test]
+    fn quota_refills() {
+        let limiter = limiter(3, Duration::from_millis(300));
+        let now = Instant::now();
+        for _ in 0..3 {
+            limiter.check(&1, now).unwrap();
+        }
+        assert!(limiter.check(&1, now).is_err());
+        let later = now + Duration::from_millis(100);
+        assert_eq!(limiter.check(&1, later), Ok(0));
+        let much_later = now + Duration::from_secs(1);
+        assert_eq!(limiter.check(&1, much_later), Ok(2));
+    }
+
+    #[test]
+    fn expired_keys_are_evicted() {
+        let limiter = limiter(10, Duration::from_secs(1));
+        let now = Instant::now();
+        for key in 0..1_000 {
+            limiter.check(&key, now).unwrap();
+        }
+        assert_eq!(limiter.len(), 1_000);
+        assert_eq!(limiter.evict_expired(now), 0);
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(limiter.evict_expired(later), 1_000);
+        assert!(limiter.is_empty());
+    }
+
+    #[test]
+    fn recently_used_keys_are_kept() {
+        let limiter = limiter(10, Duration::from_secs(1));
+        let now = Instant::now();
+        limiter.check(&1, now).unwrap();
+        limiter.check(&2, now).unwrap();
+
+        let later = now + Duration::from_millis(150);
+        limiter.check(&1, later).unwrap();
+        limiter.evict_expired(later);
+        assert_eq!(limiter.len(), 1);
+        // the used key's state is still applied
+        assert_eq!(limiter.check(&1, later), Ok(8));
+    }
+
+    #[test]
+    fn eviction_is_amortized_across_checks() {
+        let limiter = limiter(10, Duration::from_secs(1));
+        let now = Instant::now();
+        for key in 0..100 {
+            limiter.check(&key, now).unwrap();
+        }
+        let later = now + Duration::from_secs(1);
+        for key in 100..10_000 {
+            limiter.check(&key, later).unwrap();
+        }
+        // without a full sweep, the keys from before were still evicted
+        assert_eq!(limiter.len(), 9_900);
+    }
+}
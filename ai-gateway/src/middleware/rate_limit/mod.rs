@@ -1,6 +1,8 @@
 pub mod cleanup;
 pub mod extractor;
+pub mod in_memory;
 pub mod redis_service;
 pub mod service;
+pub mod total;
 
 pub use self::service::{Layer, Service};
@@ -5,38 +5,31 @@ use std::{
     task::{Context, Poll},
 };
 
-use governor::middleware::StateInformationMiddleware;
 use http::Response;
-use tower_governor::GovernorLayer;
 
-use super::extractor::RateLimitKeyExtractor;
 use crate::{
     app_state::AppState,
     config::{
-        rate_limit::{
-            LimitsConfig, RateLimitConfig, RateLimitStore, RateLimiterConfig,
-        },
+        rate_limit::{LimitsConfig, RateLimitConfig, RateLimitStore},
         router::RouterConfig,
     },
     error::init::InitError,
-    middleware::rate_limit::redis_service::{
-        RedisRateLimitLayer, RedisRateLimitService,
+    middleware::rate_limit::{
+        extractor::RateLimitKey,
+        in_memory::{
+            InMemoryRateLimitLayer, InMemoryRateLimitService,
+            InMemoryRateLimiter,
+        },
+        redis_service::{RedisRateLimitLayer, RedisRateLimitService},
+        total::TotalRateLimiter,
     },
     types::router::RouterId,
 };
 
-pub type OptionalGovernorLayer =
-    Option<GovernorLayer<RateLimitKeyExtractor, StateInformationMiddleware>>;
-pub type GovernorService<S> = tower_governor::governor::Governor<
-    RateLimitKeyExtractor,
-    StateInformationMiddleware,
-    S,
->;
-
 #[derive(Clone)]
 pub enum InnerLayer {
     None,
-    InMemory(GovernorLayer<RateLimitKeyExtractor, StateInformationMiddleware>),
+    InMemory(InMemoryRateLimitLayer),
     Redis(RedisRateLimitLayer),
 }
 
@@ -59,9 +52,10 @@ impl Layer {
                     redis_config.host_url.expose().clone(),
                 ))
             } else {
-                Ok(Self::new_in_memory_inner(
+                Self::new_in_memory_inner(
                     app_state.0.global_rate_limit.clone(),
-                ))
+                    &rate_limit_config.limits,
+                )
             }
         } else {
             Ok(Self {
@@ -86,9 +80,10 @@ impl Layer {
                     redis_config.host_url.expose().clone(),
                 ))
             } else {
-                Ok(Self::new_in_memory_inner(
+                Self::new_in_memory_inner(
                     app_state.0.global_rate_limit.clone(),
-                ))
+                    &rate_limit_config.limits,
+                )
             }
         } else {
             Ok(Self {
@@ -99,6 +94,7 @@ impl Layer {
 
     #[must_use]
     fn new_redis_inner(rl: LimitsConfig, url: url::Url) -> Self {
+        warn_total_unsupported(&rl);
         if let Ok(layer) = RedisRateLimitLayer::new(Arc::new(rl), url, None) {
             Self {
                 inner: InnerLayer::Redis(layer),
@@ -110,16 +106,21 @@ impl Layer {
         }
     }
 
-    #[must_use]
-    fn new_in_memory_inner(rl: Option<Arc<RateLimiterConfig>>) -> Self {
+    fn new_in_memory_inner(
+        rl: Option<Arc<InMemoryRateLimiter>>,
+        limits: &LimitsConfig,
+    ) -> Result<Self, InitError> {
         if let Some(rl) = rl {
-            Self {
-                inner: InnerLayer::InMemory(GovernorLayer { config: rl }),
-            }
+            Ok(Self {
+                inner: InnerLayer::InMemory(InMemoryRateLimitLayer::new(
+                    rl,
+                    total_limiter(limits)?,
+                )),
+            })
         } else {
-            Self {
+            Ok(Self {
                 inner: InnerLayer::None,
-            }
+            })
         }
     }
 
@@ -155,30 +156,49 @@ impl Layer {
                         Some(router_id.clone()),
                     )
                 {
+                    warn_total_unsupported(limits);
                     return Ok(Self {
                         inner: InnerLayer::Redis(layer),
                     });
                 }
-                let rl = Arc::new(crate::config::rate_limit::limiter_config(
-                    limits,
-                )?);
+                let rl =
+                    Arc::new(InMemoryRateLimiter::new(&limits.per_api_key)?);
                 add_rate_limit_to_app_state(app_state, router_id, rl.clone())
                     .await;
 
                 Ok(Self {
-                    inner: InnerLayer::InMemory(GovernorLayer {
-                        config: rl.clone(),
-                    }),
+                    inner: InnerLayer::InMemory(InMemoryRateLimitLayer::new(
+                        rl,
+                        total_limiter(limits)?,
+                    )),
                 })
             }
         }
     }
 }
 
+fn total_limiter(
+    limits: &LimitsConfig,
+) -> Result<Option<Arc<TotalRateLimiter<RateLimitKey>>>, InitError> {
+    limits
+        .total
+        .as_ref()
+        .map(|total| TotalRateLimiter::new(total).map(Arc::new))
+        .transpose()
+}
+
+fn warn_total_unsupported(limits: &LimitsConfig) {
+    if limits.total.is_some() {
+        tracing::warn!(
+            "total rate limits are only enforced with the in-memory store"
+        );
+    }
+}
+
 async fn add_rate_limit_to_app_state(
     app_state: &AppState,
     router_id: RouterId,
-    rl_config: Arc<RateLimiterConfig>,
+    rl_config: Arc<InMemoryRateLimiter>,
 ) {
     let mut write_guard = app_state.0.router_rate_limits.write().await;
     write_guard.insert(router_id, rl_config);
@@ -202,9 +222,15 @@ impl<S> tower::layer::Layer<S> for Layer {
 
 #[derive(Debug, Clone)]
 pub enum Service<S> {
-    Disabled { service: S },
-    InMemory { service: GovernorService<S> },
-    Redis { service: RedisRateLimitService<S> },
+    Disabled {
+        service: S,
+    },
+    InMemory {
+        service: InMemoryRateLimitService<S>,
+    },
+    Redis {
+        service: RedisRateLimitService<S>,
+    },
 }
 
 pin_project_lite::pin_project! {
@@ -217,29 +243,6 @@ pin_project_lite::pin_project! {
     }
 }
 
-// add a second to the retry after header to prevent rounding errors
-fn increment_retry_after_header<ResponseBody>(
-    res: &mut http::Response<ResponseBody>,
-) {
-    if let Some(retry_after) = res.headers().get("retry-after") {
-        if let Some(retry_after_value) = retry_after
-            .to_str()
-            .ok()
-            .and_then(|s| s.parse::<u64>().ok())
-        {
-            let new_retry_after = retry_after_value + 1;
-            res.headers_mut().insert(
-                "retry-after",
-                new_retry_after.to_string().parse().unwrap(),
-            );
-            res.headers_mut().insert(
-                "x-ratelimit-after",
-                new_retry_after.to_string().parse().unwrap(),
-            );
-        }
-    }
-}
-
 impl<InMemoryFuture, RedisFuture, DisabledFuture, ResponseBody, Error> Future
     for ResponseFuture<InMemoryFuture, RedisFuture, DisabledFuture>
 where
@@ -251,15 +254,7 @@ where
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         match self.project() {
-            EnumProj::InMemory { future } => {
-                let result = std::task::ready!(future.poll(cx));
-                if let Ok(mut res) = result {
-                    increment_retry_after_header(&mut res);
-                    Poll::Ready(Ok(res))
-                } else {
-                    Poll::Ready(result)
-                }
-            }
+            EnumProj::InMemory { future } => future.poll(cx),
             EnumProj::Redis { future } => future.poll(cx),
             EnumProj::Disabled { future } => future.poll(cx),
         }
@@ -269,7 +264,7 @@ where
 impl<S, Request, ResponseBody> tower::Service<Request> for Service<S>
 where
     S: tower::Service<Request, Response = Response<ResponseBody>>,
-    GovernorService<S>: tower::Service<
+    InMemoryRateLimitService<S>: tower::Service<
             Request,
             Response = Response<ResponseBody>,
             Error = S::Error,
@@ -283,7 +278,7 @@ where
     type Response = Response<ResponseBody>;
     type Error = S::Error;
     type Future = ResponseFuture<
-        <GovernorService<S> as tower::Service<Request>>::Future,
+        <InMemoryRateLimitService<S> as tower::Service<Request>>::Future,
         <RedisRateLimitService<S> as tower::Service<Request>>::Future,
         S::Future,
     >;
@@ -293,14 +288,7 @@ where
         cx: &mut std::task::Context<'_>,
     ) -> std::task::Poll<Result<(), Self::Error>> {
         match self {
-            Service::InMemory { service } => match service.poll_ready(cx) {
-                Poll::Ready(Ok(())) => {
-                    tracing::trace!("in memory rate limit ready");
-                    Poll::Ready(Ok(()))
-                }
-                Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
-                Poll::Pending => Poll::Pending,
-            },
+            Service::InMemory { service } => service.poll_ready(cx),
             Service::Redis { service } => service.poll_ready(cx),
             Service::Disabled { service } => service.poll_ready(cx),
         }
@@ -365,6 +353,7 @@ mod tests {
                 capacity: NonZeroU32::new(10).unwrap(),
                 refill_frequency: Duration::from_secs(1),
             },
+            total: None,
         }
     }
 
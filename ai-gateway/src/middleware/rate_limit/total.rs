@@ -0,0 +1,298 @@
+//! In-memory GCRA rate limiting of the requests of all keys combined.
+//!
+//! Once the limit is exhausted, requests are either rejected in arrival
+//! order, or wait for capacity with fair queuing. Waiting requests are
+//! granted capacity in order of their start-time fair queuing tags: a key's
+//! next request is tagged one past its previous one, but never before the
+//! tag of the last granted request. Keys with waiting requests are therefore
+//! served in turn, and a key queuing a burst of requests only delays its
+//! own requests.
+//!
+//! There is no background task granting capacity. Every waiting request
+//! sleeps until capacity is expected to be available and then grants it to
+//! the requests at the front of the queue, which may not include itself.
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    hash::Hash,
+    sync::Mutex,
+    time::Duration,
+};
+
+use rustc_hash::FxHashMap as HashMap;
+use tokio::{sync::oneshot, time::Instant};
+
+use crate::{
+    config::rate_limit::{Fairness, TotalLimitConfig},
+    error::init::InitError,
+    middleware::rate_limit::in_memory::emission_interval,
+};
+
+/// Number of tracked keys above the number of waiting requests at which
+/// keys without waiting requests are forgotten.
+const PRUNE_THRESHOLD: usize = 1024;
+
+#[derive(Debug)]
+struct Waiter {
+    tag: u64,
+    /// Breaks ties between equal tags in arrival order.
+    seq: u64,
+    tx: oneshot::Sender<()>,
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        (self.tag, self.seq) == (other.tag, other.seq)
+    }
+}
+
+impl Eq for Waiter {}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.tag, self.seq).cmp(&(other.tag, other.seq))
+    }
+}
+
+#[derive(Debug)]
+struct State<K> {
+    /// Theoretical arrival time of the next request.
+    tat: Instant,
+    /// Tag of the last request granted capacity.
+    virtual_time: u64,
+    /// Tag of the next request per key.
+    next_tags: HashMap<K, u64>,
+    waiters: BinaryHeap<Reverse<Waiter>>,
+    next_seq: u64,
+}
+
+impl<K: Hash + Eq> State<K> {
+    fn enqueue(&mut self, key: K) -> oneshot::Receiver<()> {
+        let tag = self
+            .next_tags
+            .get(&key)
+            .copied()
+            .unwrap_or_default()
+            .max(self.virtual_time);
+        self.next_tags.insert(key, tag + 1);
+        if self.next_tags.len() > self.waiters.len() + PRUNE_THRESHOLD {
+            let virtual_time = self.virtual_time;
+            self.next_tags
+                .retain(|_, next_tag| *next_tag > virtual_time);
+        }
+        let (tx, rx) = oneshot::channel();
+        self.waiters.push(Reverse(Waiter {
+            tag,
+            seq: self.next_seq,
+            tx,
+        }));
+        self.next_seq += 1;
+        rx
+    }
+}
+
+/// A GCRA rate limiter shared by all keys.
+#[derive(Debug)]
+pub struct TotalRateLimiter<K> {
+    emission_interval: Duration,
+    capacity: u32,
+    fairness: Fairness,
+    max_wait: Duration,
+    state: Mutex<State<K>>,
+}
+
+impl<K: Hash + Eq> TotalRateLimiter<K> {
+    pub fn new(config: &TotalLimitConfig) -> Result<Self, InitError> {
+        Ok(Self {
+            emission_interval: emission_interval(&config.gcra())?,
+            capacity: config.capacity.get(),
+            fairness: config.fairness,
+            max_wait: config.max_wait,
+            state: Mutex::new(State {
+                tat: Instant::now(),
+                virtual_time: 0,
+                next_tags: HashMap::default(),
+                waiters: BinaryHeap::new(),
+                next_seq: 0,
+            }),
+        })
+    }
+
+    #[must_use]
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Takes a cell if one is available at `now`, returning when the next
+    /// one is available otherwise.
+    fn try_take(
+        &self,
+        state: &mut State<K>,
+        now: Instant,
+    ) -> Result<(), Instant> {
+        let burst = self.emission_interval * self.capacity;
+        let new_tat = state.tat.max(now) + self.emission_interval;
+        let allow_at = new_tat.checked_sub(burst).unwrap_or(now);
+        if allow_at > now {
+            return Err(allow_at);
+        }
+        state.tat = new_tat;
+        Ok(())
+    }
+
+    /// Grants capacity to waiting requests in tag order. Returns when the
+    /// next cell is available if requests are still waiting.
+    fn grant(&self, now: Instant) -> Option<Instant> {
+        let mut state = self.state.lock().expect("total limit lock poisoned");
+        while let Some(Reverse(waiter)) = state.waiters.peek() {
+            if waiter.tx.is_closed() {
+                // the request stopped waiting
+                state.waiters.pop();
+                continue;
+            }
+            if let Err(available_at) = self.try_take(&mut state, now) {
+                return Some(available_at);
+            }
+            let Some(Reverse(waiter)) = state.waiters.pop() else {
+                break;
+            };
+            state.virtual_time = waiter.tag;
+            if waiter.tx.send(()).is_err() {
+                state.tat -= self.emission_interval;
+            }
+        }
+        None
+    }
+
+    /// Waits until a request for `key` may be sent. Returns how long until
+    /// capacity is expected to be available if the request is rejected.
+    pub async fn acquire(&self, key: K) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut rx = {
+            let mut state =
+                self.state.lock().expect("total limit lock poisoned");
+            // capacity goes to waiting requests first
+            if state.waiters.is_empty() {
+                match self.try_take(&mut state, now) {
+                    Ok(()) => return Ok(()),
+                    Err(available_at)
+                        if self.fairness == Fairness::ArrivalOrder =>
+                    {
+                        return Err(available_at - now);
+                    }
+                    Err(_) => {}
+                }
+            }
+            state.enqueue(key)
+        };
+
+        let deadline = now + self.max_wait;
+        loop {
+            let now = Instant::now();
+            let available_at = self.grant(now);
+            if rx.try_recv().is_ok() {
+                return Ok(());
+            }
+            if now >= deadline {
+                rx.close();
+                // capacity may have been granted before the channel closed
+                return match rx.try_recv() {
+                    Ok(()) => Ok(()),
+                    Err(_) => Err(available_at.map_or(
+                        self.emission_interval,
+                        |available_at| {
+                            available_at.saturating_duration_since(now)
+                        },
+                    )),
+                };
+            }
+            let wake_at = available_at
+                .map_or(deadline, |available_at| available_at.min(deadline));
+            tokio::select! {
+                result = &mut rx => {
+                    if result.is_ok() {
+                        return Ok(());
+                    }
+                }
+                () = tokio::time::sleep_until(wake_at) => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{num::NonZeroU32, sync::Arc};
+
+    use super::*;
+
+    fn limiter(fairness: Fairness) -> Arc<TotalRateLimiter<u64>> {
+        Arc::new(
+            TotalRateLimiter::new(&TotalLimitConfig {
+                refill_frequency: Duration::from_millis(100),
+                capacity: NonZeroU32::new(2).unwrap(),
+                fairness,
+                max_wait: Duration::from_secs(1),
+            })
+            .unwrap(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn arrival_order_rejects_when_exhausted() {
+        let limiter = limiter(Fairness::ArrivalOrder);
+        assert!(limiter.acquire(1).await.is_ok());
+        assert!(limiter.acquire(1).await.is_ok());
+        assert!(limiter.acquire(2).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fair_queuing_serves_keys_in_turn() {
+        let limiter = limiter(Fairness::FairQueuing);
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        // a burst from key 1 exhausts the limit and queues up
+        for key in [1, 1, 1, 1, 1, 1, 2] {
+            let limiter = limiter.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                if limiter.acquire(key).await.is_ok() {
+                    tx.send(key).unwrap();
+                }
+            });
+            tokio::task::yield_now().await;
+        }
+        drop(tx);
+        let mut order = Vec::new();
+        while let Some(key) = rx.recv().await {
+            order.push(key);
+        }
+        // the first two requests of key 1 were sent immediately, after which
+        // key 2 is served before the rest of the burst
+        assert_eq!(order[..4], [1, 1, 1, 2]);
+        assert_eq!(order.len(), 7);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waiting_requests_time_out() {
+        let limiter = Arc::new(
+            TotalRateLimiter::new(&TotalLimitConfig {
+                refill_frequency: Duration::from_secs(10),
+                capacity: NonZeroU32::new(1).unwrap(),
+                fairness: Fairness::FairQueuing,
+                max_wait: Duration::from_millis(100),
+            })
+            .unwrap(),
+        );
+        assert!(limiter.acquire(1).await.is_ok());
+        assert!(limiter.acquire(2).await.is_err());
+        // the abandoned request isn't granted capacity
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert!(limiter.acquire(3).await.is_ok());
+    }
+}
@@ -10,9 +10,17 @@ use pin_project_lite::pin_project;
 
 use crate::{
     config::response_headers::ResponseHeadersConfig,
-    types::{extensions::ProviderRequestId, provider::InferenceProvider},
+    types::{
+        extensions::{MapperContext, ProviderRequestId},
+        provider::InferenceProvider,
+    },
 };
 
+const SELECTED_PROVIDER_HEADER: http::HeaderName =
+    http::HeaderName::from_static("helicone-selected-provider");
+const SELECTED_MODEL_HEADER: http::HeaderName =
+    http::HeaderName::from_static("helicone-selected-model");
+
 #[derive(Debug, Clone)]
 pub struct ResponseHeaderService<S> {
     config: ResponseHeadersConfig,
@@ -119,6 +127,33 @@ where
                     .insert("helicone-provider-req-id", provider_request_id.0);
             }
         }
+
+        if this.config.selected_provider
+            && let Some(header_value) = response
+                .extensions()
+                .get::<InferenceProvider>()
+                .and_then(|provider| {
+                    http::HeaderValue::from_str(provider.as_ref()).ok()
+                })
+        {
+            response
+                .headers_mut()
+                .insert(SELECTED_PROVIDER_HEADER, header_value);
+        }
+
+        if this.config.selected_model
+            && let Some(header_value) = response
+                .extensions()
+                .get::<MapperContext>()
+                .and_then(|mapper_ctx| mapper_ctx.model.as_ref())
+                .and_then(|model| {
+                    http::HeaderValue::from_str(&model.to_string()).ok()
+                })
+        {
+            response
+                .headers_mut()
+                .insert(SELECTED_MODEL_HEADER, header_value);
+        }
         Poll::Ready(Ok(response))
     }
 }
@@ -154,6 +189,8 @@ mod tests {
         let config = ResponseHeadersConfig {
             provider: false,
             provider_request_id: false,
+            selected_provider: false,
+            selected_model: false,
         };
 
         let mut service = ResponseHeaderService::new(
@@ -181,6 +218,8 @@ mod tests {
         let config = ResponseHeadersConfig {
             provider: true,
             provider_request_id: false,
+            selected_provider: false,
+            selected_model: false,
         };
 
         let mut service = ResponseHeaderService::new(
@@ -210,6 +249,8 @@ mod tests {
         let config = ResponseHeadersConfig {
             provider: false,
             provider_request_id: true,
+            selected_provider: false,
+            selected_model: false,
         };
 
         let mut service = ResponseHeaderService::new(
@@ -239,6 +280,8 @@ mod tests {
         let config = ResponseHeadersConfig {
             provider: true,
             provider_request_id: true,
+            selected_provider: false,
+            selected_model: false,
         };
 
         let mut service = ResponseHeaderService::new(
@@ -274,6 +317,8 @@ mod tests {
         let config = ResponseHeadersConfig {
             provider: true,
             provider_request_id: false,
+            selected_provider: false,
+            selected_model: false,
         };
 
         let mut service = ResponseHeaderService::new(
@@ -293,6 +338,8 @@ mod tests {
         let config = ResponseHeadersConfig {
             provider: false,
             provider_request_id: true,
+            selected_provider: false,
+            selected_model: false,
         };
 
         let mut service = ResponseHeaderService::new(
@@ -306,4 +353,45 @@ mod tests {
 
         assert!(!response.headers().contains_key("helicone-provider-req-id"));
     }
+
+    #[tokio::test]
+    async fn test_selected_provider_and_model_headers() {
+        let config = ResponseHeadersConfig {
+            provider: false,
+            provider_request_id: false,
+            selected_provider: true,
+            selected_model: true,
+        };
+
+        let mut service = ResponseHeaderService::new(
+            config,
+            create_mock_service(|| {
+                let mut response = Response::new("test".to_string());
+                response
+                    .extensions_mut()
+                    .insert(InferenceProvider::Anthropic);
+                response.extensions_mut().insert(MapperContext {
+                    is_stream: true,
+                    model: Some(
+                        "anthropic/claude-3-7-sonnet-latest".parse().unwrap(),
+                    ),
+                });
+                response
+            }),
+        );
+
+        let request = Request::new(());
+        let response =
+            service.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(
+            response.headers().get(SELECTED_PROVIDER_HEADER).unwrap(),
+            "anthropic"
+        );
+        assert_eq!(
+            response.headers().get(SELECTED_MODEL_HEADER).unwrap(),
+            "claude-3-7-sonnet-latest"
+        );
+        assert!(!response.headers().contains_key("helicone-provider"));
+    }
 }
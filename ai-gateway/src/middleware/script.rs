@@ -0,0 +1,397 @@
+//! Rhai scripts for lightweight request policies.
+//!
+//! The script is compiled when the router is created and run on every
+//! request, in a sandbox: it can only see and change the `request` it's
+//! given, and is stopped once it exceeds its operation or time limit.
+//!
+//! ```rhai
+//! if request.headers["x-team"] == () {
+//!     throw "requests must set x-team";
+//! }
+//! if request.model == "openai/gpt-4o" && request.headers["x-team"] == "ci" {
+//!     request.model = "openai/gpt-4o-mini";
+//! }
+//! request.headers["x-policy"] = "checked";
+//! ```
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::{HeaderMap, HeaderName, HeaderValue, request::Parts};
+use http_body_util::BodyExt;
+use rhai::{AST, Dynamic, Engine, EvalAltResult, Scope};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config::{router::RouterConfig, script::ScriptConfig},
+    error::{
+        api::ApiError, init::InitError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    types::{request::Request, response::Response},
+};
+
+/// How many operations a script runs between checks of its deadline.
+const DEADLINE_CHECK_INTERVAL: u64 = 256;
+
+thread_local! {
+    /// The deadline of the script running on this thread, if any.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// The `request` object map given to scripts. `method` and `path` are read
+/// only.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct ScriptRequest {
+    method: String,
+    path: String,
+    headers: BTreeMap<String, String>,
+    /// `()` if the body isn't JSON.
+    body: Value,
+    model: Option<String>,
+}
+
+impl ScriptRequest {
+    fn new(parts: &Parts, body: Value) -> Self {
+        let headers = parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((name.to_string(), value.to_str().ok()?.to_string()))
+            })
+            .collect();
+        let model = body
+            .get("model")
+            .and_then(Value::as_str)
+            .map(str::to_string);
+        Self {
+            method: parts.method.to_string(),
+            path: parts.uri.path().to_string(),
+            headers,
+            body,
+            model,
+        }
+    }
+
+    /// Applies the changes the script made to `original` to `headers`.
+    /// Returns the new body, if the script changed it.
+    fn apply(
+        mut self,
+        original: &Self,
+        headers: &mut HeaderMap,
+    ) -> Result<Option<Value>, InternalError> {
+        for name in original.headers.keys() {
+            if !self.headers.contains_key(name) {
+                headers.remove(name.as_str());
+            }
+        }
+        for (name, value) in &self.headers {
+            if original.headers.get(name) == Some(value) {
+                continue;
+            }
+            let name = HeaderName::try_from(name.as_str()).map_err(|e| {
+                InternalError::FilterFailed(format!(
+                    "script set invalid header name {name}: {e}"
+                ))
+            })?;
+            let value = HeaderValue::try_from(value.as_str()).map_err(|e| {
+                InternalError::FilterFailed(format!(
+                    "script set invalid value for {name}: {e}"
+                ))
+            })?;
+            headers.insert(name, value);
+        }
+        if self.model != original.model
+            && let Some(model) = self.model
+            && let Some(body) = self.body.as_object_mut()
+        {
+            body.insert("model".to_string(), Value::String(model));
+        }
+        Ok((self.body != original.body).then_some(self.body))
+    }
+}
+
+#[derive(Debug)]
+struct Script {
+    engine: Engine,
+    ast: AST,
+    timeout: Duration,
+}
+
+impl Script {
+    fn new(config: &ScriptConfig) -> Result<Self, InitError> {
+        let engine = engine(config);
+        let ast = engine.compile_file(config.path.clone()).map_err(|e| {
+            InitError::Script(config.path.display().to_string(), e.to_string())
+        })?;
+        Ok(Self {
+            engine,
+            ast,
+            timeout: config.timeout,
+        })
+    }
+
+    fn run(&self, request: &ScriptRequest) -> Result<ScriptRequest, ApiError> {
+        let failed = |e: &dyn std::fmt::Display| {
+            InternalError::FilterFailed(format!("script: {e}"))
+        };
+        let mut scope = Scope::new();
+        let input = rhai::serde::to_dynamic(request).map_err(|e| failed(&e))?;
+        scope.push("request", input);
+
+        DEADLINE.set(Some(Instant::now() + self.timeout));
+        let result = self.engine.run_ast_with_scope(&mut scope, &self.ast);
+        DEADLINE.set(None);
+        if let Err(error) = result {
+            return Err(match error.unwrap_inner() {
+                EvalAltResult::ErrorRuntime(message, _) => {
+                    InvalidRequestError::FilterRejected(message.to_string())
+                        .into()
+                }
+                error => failed(error).into(),
+            });
+        }
+
+        let output = scope
+            .get_value::<Dynamic>("request")
+            .ok_or_else(|| failed(&"`request` was removed"))?;
+        rhai::serde::from_dynamic(&output).map_err(|e| failed(&e).into())
+    }
+}
+
+/// A sandboxed engine, limited to `config`'s operations, data sizes, call
+/// depth and timeout.
+fn engine(config: &ScriptConfig) -> Engine {
+    let mut engine = Engine::new();
+    engine.set_max_operations(config.max_operations);
+    engine.set_max_string_size(config.max_string_size);
+    engine.set_max_array_size(config.max_array_size);
+    engine.set_max_map_size(config.max_map_size);
+    engine.set_max_call_levels(config.max_call_levels);
+    engine.disable_symbol("eval");
+    engine.on_print(|text| tracing::debug!(text, "script print"));
+    engine.on_debug(|text, _, position| {
+        tracing::debug!(text, %position, "script debug");
+    });
+    engine.on_progress(|operations| {
+        if operations % DEADLINE_CHECK_INTERVAL != 0 {
+            return None;
+        }
+        DEADLINE
+            .get()
+            .filter(|deadline| Instant::now() >= *deadline)
+            .map(|_| Dynamic::from("script timed out"))
+    });
+    engine
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    script: Arc<Script>,
+}
+
+impl Layer {
+    /// Returns `None` if the router doesn't configure a script.
+    pub fn for_router(
+        router_config: &RouterConfig,
+    ) -> Result<Option<Self>, InitError> {
+        router_config
+            .script
+            .as_ref()
+            .map(|config| {
+                Ok(Self {
+                    script: Arc::new(Script::new(config)?),
+                })
+            })
+            .transpose()
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            script: Arc::clone(&self.script),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    script: Arc<Script>,
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "script", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let script = Arc::clone(&self.script);
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let mut body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let body_json =
+                serde_json::from_slice(&body_bytes).unwrap_or(Value::Null);
+            let original = ScriptRequest::new(&parts, body_json);
+
+            let input = original.clone();
+            let output =
+                tokio::task::spawn_blocking(move || script.run(&input))
+                    .await
+                    .map_err(|e| {
+                        InternalError::FilterFailed(format!("script: {e}"))
+                    })??;
+
+            if let Some(body) = output.apply(&original, &mut parts.headers)? {
+                body_bytes = serde_json::to_vec(&body)
+                    .map(Bytes::from)
+                    .map_err(|error| InternalError::Serialize {
+                        ty: "serde_json::Value",
+                        error,
+                    })?;
+                parts.headers.remove(http::header::CONTENT_LENGTH);
+            }
+            let req = Request::from_parts(parts, body_bytes.into());
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn script(source: &str) -> Script {
+        let config = ScriptConfig {
+            path: "policy.rhai".into(),
+            timeout: Duration::from_millis(50),
+            max_operations: 1_000_000,
+            max_string_size: 1024,
+            max_array_size: 100,
+            max_map_size: 100,
+            max_call_levels: 8,
+        };
+        let engine = engine(&config);
+        let ast = engine.compile(source).unwrap();
+        Script {
+            engine,
+            ast,
+            timeout: config.timeout,
+        }
+    }
+
+    fn request() -> (Parts, ScriptRequest) {
+        let (parts, ()) = http::Request::builder()
+            .method("POST")
+            .uri("/v1/chat/completions")
+            .header("x-team", "ci")
+            .header("x-debug", "1")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let body = serde_json::json!({ "model": "openai/gpt-4o" });
+        let request = ScriptRequest::new(&parts, body);
+        (parts, request)
+    }
+
+    #[test]
+    fn script_changes_headers_and_model() {
+        let script = script(
+            r#"
+            request.model = "openai/gpt-4o-mini";
+            request.headers["x-policy"] = "checked";
+            request.headers.remove("x-debug");
+            "#,
+        );
+        let (mut parts, original) = request();
+        let output = script.run(&original).unwrap();
+        let body = output.apply(&original, &mut parts.headers).unwrap();
+        assert_eq!(
+            body,
+            Some(serde_json::json!({ "model": "openai/gpt-4o-mini" }))
+        );
+        assert_eq!(parts.headers.get("x-policy").unwrap(), "checked");
+        assert_eq!(parts.headers.get("x-team").unwrap(), "ci");
+        assert!(parts.headers.get("x-debug").is_none());
+    }
+
+    #[test]
+    fn throwing_rejects_the_request() {
+        let script = script(
+            r#"
+            fn check(request) {
+                if request.headers["x-team"] == "ci" { throw "no ci" }
+            }
+            check(request);
+            "#,
+        );
+        let (_, original) = request();
+        assert!(matches!(
+            script.run(&original),
+            Err(ApiError::InvalidRequest(
+                InvalidRequestError::FilterRejected(message)
+            )) if message == "no ci"
+        ));
+    }
+
+    #[test]
+    fn scripts_are_limited_in_the_data_they_build() {
+        let (_, original) = request();
+        for source in [
+            r#"let s = "x"; for i in 0..20 { s += s; }"#,
+            "let a = []; for i in 0..1000 { a.push(i); }",
+            "fn f(n) { f(n + 1) } f(0);",
+        ] {
+            assert!(
+                matches!(
+                    script(source).run(&original),
+                    Err(ApiError::Internal(InternalError::FilterFailed(_)))
+                ),
+                "{source}"
+            );
+        }
+    }
+
+    #[test]
+    fn scripts_are_stopped_at_their_deadline() {
+        let script = script("loop {}");
+        let (_, original) = request();
+        assert!(matches!(
+            script.run(&original),
+            Err(ApiError::Internal(InternalError::FilterFailed(_)))
+        ));
+    }
+}
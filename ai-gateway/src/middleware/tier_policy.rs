@@ -0,0 +1,240 @@
+//! Enforces the policy of the tier of a request's API key.
+//!
+//! Requests to endpoints the tier doesn't allow are rejected with a 403,
+//! and requests whose `max_tokens` or `temperature` exceed the tier's caps
+//! with a 400, both naming the violated policy.
+use std::{
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::future::BoxFuture;
+use http_body_util::BodyExt;
+use rust_decimal::{Decimal, prelude::FromPrimitive};
+use serde_json::Value;
+
+use crate::{
+    app_state::AppState,
+    config::key_tier::{KeyTiersConfig, TierPolicy},
+    control_plane::types::hash_key,
+    error::{
+        api::ApiError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    router::router_details::RouteType,
+    types::{extensions::AuthContext, request::Request, response::Response},
+};
+
+/// Where providers' request bodies set the maximum completion tokens.
+const MAX_TOKENS_FIELDS: [&str; 3] = [
+    "/max_tokens",
+    "/max_completion_tokens",
+    "/generationConfig/maxOutputTokens",
+];
+const TEMPERATURE_FIELDS: [&str; 2] =
+    ["/temperature", "/generationConfig/temperature"];
+
+/// Checks `path` against the endpoints the tier allows.
+fn check_endpoint(
+    tier: &str,
+    policy: &TierPolicy,
+    path: &str,
+) -> Result<(), InvalidRequestError> {
+    let Some(endpoints) = &policy.endpoints else {
+        return Ok(());
+    };
+    if endpoints
+        .iter()
+        .any(|endpoint| endpoint.trim_start_matches('/') == path)
+    {
+        Ok(())
+    } else {
+        Err(InvalidRequestError::TierEndpointNotAllowed(
+            tier.to_string(),
+            format!("/{path}"),
+        ))
+    }
+}
+
+/// Checks the parameters of a request body against the tier's caps.
+fn check_body(
+    tier: &str,
+    policy: &TierPolicy,
+    body: &Value,
+) -> Result<(), InvalidRequestError> {
+    let violated = |message: String| {
+        InvalidRequestError::TierPolicyViolated(tier.to_string(), message)
+    };
+    if let Some(limit) = policy.max_tokens
+        && let Some(max_tokens) = MAX_TOKENS_FIELDS
+            .iter()
+            .find_map(|field| body.pointer(field).and_then(Value::as_u64))
+        && max_tokens > u64::from(limit)
+    {
+        return Err(violated(format!(
+            "max-tokens: {max_tokens} is above the limit of {limit}"
+        )));
+    }
+    if let Some(range) = policy.temperature
+        && let Some(temperature) = TEMPERATURE_FIELDS
+            .iter()
+            .find_map(|field| body.pointer(field).and_then(Value::as_f64))
+            .and_then(Decimal::from_f64)
+        && (temperature < range.min || temperature > range.max)
+    {
+        return Err(violated(format!(
+            "temperature: {temperature} is outside of {} to {}",
+            range.min, range.max
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    config: Option<Arc<KeyTiersConfig>>,
+}
+
+impl Layer {
+    #[must_use]
+    pub fn new(app_state: &AppState) -> Self {
+        Self {
+            config: app_state.config().key_tiers.clone().map(Arc::new),
+        }
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            config: self.config.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    config: Option<Arc<KeyTiersConfig>>,
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response> + Clone + Send + 'static,
+    S::Error: Into<ApiError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    #[tracing::instrument(name = "tier_policy", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let config = self.config.clone();
+        Box::pin(async move {
+            let tier =
+                config.as_deref().zip(req.extensions().get::<AuthContext>());
+            let Some((config, auth_ctx)) = tier else {
+                return inner.call(req).await.map_err(Into::into);
+            };
+            let key_hash = hash_key(auth_ctx.api_key.expose());
+            let Some((tier, policy)) =
+                config.tier(auth_ctx.tier.as_deref(), &key_hash)
+            else {
+                return inner.call(req).await.map_err(Into::into);
+            };
+
+            let path = match req.extensions().get::<RouteType>() {
+                Some(
+                    RouteType::Router { path, .. }
+                    | RouteType::UnifiedApi { path }
+                    | RouteType::DirectProxy { path, .. },
+                ) => path.as_str(),
+                None => req.uri().path().trim_start_matches('/'),
+            };
+            check_endpoint(tier, policy, path)?;
+            if policy.max_tokens.is_none() && policy.temperature.is_none() {
+                return inner.call(req).await.map_err(Into::into);
+            }
+
+            let (parts, body) = req.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            if let Ok(json) = serde_json::from_slice::<Value>(&body) {
+                check_body(tier, policy, &json)?;
+            }
+            let req = Request::from_parts(parts, body.into());
+            inner.call(req).await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::config::key_tier::TemperatureRange;
+
+    fn policy() -> TierPolicy {
+        TierPolicy {
+            max_tokens: Some(1024),
+            temperature: Some(TemperatureRange {
+                min: Decimal::ZERO,
+                max: Decimal::ONE,
+            }),
+            endpoints: Some(vec!["/v1/chat/completions".to_string()]),
+        }
+    }
+
+    #[test]
+    fn endpoints_are_allowed_by_path() {
+        let policy = policy();
+        assert!(check_endpoint("free", &policy, "v1/chat/completions").is_ok());
+        assert!(matches!(
+            check_endpoint("free", &policy, "v1/embeddings"),
+            Err(InvalidRequestError::TierEndpointNotAllowed(tier, path))
+                if tier == "free" && path == "/v1/embeddings"
+        ));
+        assert!(
+            check_endpoint("pro", &TierPolicy::default(), "v1/embeddings")
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn body_parameters_are_capped() {
+        let policy = policy();
+        let check = |body| check_body("free", &policy, &body);
+        assert!(
+            check(json!({ "max_tokens": 1024, "temperature": 0.7 })).is_ok()
+        );
+        assert!(check(json!({ "model": "openai/gpt-4o" })).is_ok());
+        assert!(matches!(
+            check(json!({ "max_completion_tokens": 4096 })),
+            Err(InvalidRequestError::TierPolicyViolated(_, message))
+                if message.starts_with("max-tokens")
+        ));
+        assert!(matches!(
+            check(json!({ "temperature": 1.5 })),
+            Err(InvalidRequestError::TierPolicyViolated(_, message))
+                if message.starts_with("temperature")
+        ));
+    }
+}
@@ -0,0 +1,200 @@
+//! Runs filters with wasmtime.
+//!
+//! Every call gets a fresh instance, so that filters can't keep state
+//! between requests, and is bounded by the filter's fuel and memory limits.
+use wasmtime::{
+    Config, Engine, Instance, Module, Store, StoreLimits, StoreLimitsBuilder,
+};
+
+use super::Phase;
+use crate::{config::wasm_filter::WasmFilterConfig, error::init::InitError};
+
+struct Filter {
+    config: WasmFilterConfig,
+    module: Module,
+}
+
+pub struct Filters {
+    engine: Engine,
+    filters: Vec<Filter>,
+}
+
+impl std::fmt::Debug for Filters {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Filters")
+            .field(
+                "filters",
+                &self.filters.iter().map(|f| &f.config).collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+impl Filters {
+    pub fn new(configs: &[WasmFilterConfig]) -> Result<Self, InitError> {
+        let mut engine_config = Config::new();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config)
+            .map_err(|e| InitError::WasmFilter(format!("{e:#}")))?;
+        let filters = configs
+            .iter()
+            .map(|config| {
+                let module =
+                    Module::from_file(&engine, &config.path).map_err(|e| {
+                        InitError::WasmFilter(format!(
+                            "{}: {e:#}",
+                            config.path.display()
+                        ))
+                    })?;
+                Ok(Filter {
+                    config: config.clone(),
+                    module,
+                })
+            })
+            .collect::<Result<_, InitError>>()?;
+        Ok(Self { engine, filters })
+    }
+
+    pub fn count(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn config(&self, index: usize) -> &WasmFilterConfig {
+        &self.filters[index].config
+    }
+
+    pub fn exports(&self, index: usize, phase: Phase) -> bool {
+        self.filters[index]
+            .module
+            .get_export(phase.export())
+            .is_some()
+    }
+
+    /// Calls the filter's `phase` export with `input`, returning the verdict
+    /// it wrote to its memory.
+    pub fn call(
+        &self,
+        index: usize,
+        phase: Phase,
+        input: &[u8],
+    ) -> Result<Vec<u8>, String> {
+        let filter = &self.filters[index];
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(filter.config.max_memory)
+            .instances(1)
+            .build();
+        let mut store: Store<StoreLimits> = Store::new(&self.engine, limits);
+        store.limiter(|limits| limits);
+        store
+            .set_fuel(filter.config.fuel)
+            .map_err(|e| format!("{e:#}"))?;
+        Self::call_in(&mut store, &filter.module, phase, input)
+            .map_err(|e| format!("{e:#}"))
+    }
+
+    fn call_in(
+        store: &mut Store<StoreLimits>,
+        module: &Module,
+        phase: Phase,
+        input: &[u8],
+    ) -> wasmtime::Result<Vec<u8>> {
+        let instance = Instance::new(&mut *store, module, &[])?;
+        let memory = instance
+            .get_memory(&mut *store, "memory")
+            .ok_or_else(|| wasmtime::Error::msg("no `memory` export"))?;
+        let alloc =
+            instance.get_typed_func::<i32, i32>(&mut *store, "alloc")?;
+        let run = instance
+            .get_typed_func::<(i32, i32), i64>(&mut *store, phase.export())?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut *store, len)?;
+        memory.write(&mut *store, usize::try_from(ptr)?, input)?;
+        let packed = run.call(&mut *store, (ptr, len))?;
+        let packed = u64::try_from(packed)?;
+        let verdict_ptr = usize::try_from(packed >> 32)?;
+        let verdict_len = usize::try_from(packed & u64::from(u32::MAX))?;
+        // checked before allocating, since the length is the filter's
+        if verdict_ptr
+            .checked_add(verdict_len)
+            .is_none_or(|end| end > memory.data_size(&*store))
+        {
+            return Err(wasmtime::Error::msg(
+                "verdict is out of the bounds of memory",
+            ));
+        }
+        let mut verdict = vec![0; verdict_len];
+        memory.read(&*store, verdict_ptr, &mut verdict)?;
+        Ok(verdict)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REJECT: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (data (i32.const 0) "{\"reject\":\"no\"}")
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "on_request") (param i32 i32) (result i64)
+    (i64.const 15)))
+"#;
+
+    const LOOP: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "on_request") (param i32 i32) (result i64)
+    (loop $forever (br $forever))
+    (i64.const 0)))
+"#;
+
+    const OUT_OF_BOUNDS: &str = r#"
+(module
+  (memory (export "memory") 1)
+  (func (export "alloc") (param i32) (result i32) (i32.const 1024))
+  (func (export "on_request") (param i32 i32) (result i64)
+    (i64.const 0xffffffff)))
+"#;
+
+    fn filters(wat: &str) -> Filters {
+        let mut engine_config = Config::new();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config).unwrap();
+        let module = Module::new(&engine, wat).unwrap();
+        let config = WasmFilterConfig {
+            path: "filter.wat".into(),
+            fuel: 100_000,
+            max_memory: 1024 * 1024,
+            fail_open: false,
+        };
+        Filters {
+            engine,
+            filters: vec![Filter { config, module }],
+        }
+    }
+
+    #[test]
+    fn verdict_is_read_from_memory() {
+        let filters = filters(REJECT);
+        assert!(filters.exports(0, Phase::Request));
+        assert!(!filters.exports(0, Phase::Response));
+        let verdict = filters.call(0, Phase::Request, b"{}").unwrap();
+        assert_eq!(verdict, br#"{"reject":"no"}"#);
+    }
+
+    #[test]
+    fn filters_run_out_of_fuel() {
+        let filters = filters(LOOP);
+        assert!(filters.call(0, Phase::Request, b"{}").is_err());
+    }
+
+    #[test]
+    fn verdicts_out_of_bounds_are_rejected() {
+        let filters = filters(OUT_OF_BOUNDS);
+        let error = filters.call(0, Phase::Request, b"{}").unwrap_err();
+        assert!(error.contains("out of the bounds"), "{error}");
+    }
+}
@@ -0,0 +1,366 @@
+//! Experimental request and response filters compiled to WASM.
+//!
+//! Filters let users add custom logic to a router, such as header mutation,
+//! body inspection, or rejecting requests, without forking the gateway.
+//! Each filter is given a JSON description of the request, and of the
+//! response headers, and returns a [`Verdict`]. Filters run in order on
+//! requests, and in reverse order on responses. Response bodies are
+//! streamed, so filters only see response headers.
+//!
+//! Running filters requires a build with the `wasm-filters` feature.
+use std::{
+    collections::BTreeMap,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::{HeaderMap, HeaderName, HeaderValue};
+use http_body_util::BodyExt;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    config::router::RouterConfig,
+    error::{
+        api::ApiError, init::InitError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    types::{request::Request, response::Response},
+};
+
+#[cfg(feature = "wasm-filters")]
+mod engine;
+
+#[cfg(not(feature = "wasm-filters"))]
+mod engine {
+    use super::Phase;
+    use crate::{
+        config::wasm_filter::WasmFilterConfig, error::init::InitError,
+    };
+
+    #[derive(Debug)]
+    pub enum Filters {}
+
+    impl Filters {
+        pub fn new(_configs: &[WasmFilterConfig]) -> Result<Self, InitError> {
+            Err(InitError::WasmFiltersDisabled)
+        }
+
+        pub fn count(&self) -> usize {
+            match *self {}
+        }
+
+        pub fn config(&self, _index: usize) -> &WasmFilterConfig {
+            match *self {}
+        }
+
+        pub fn exports(&self, _index: usize, _phase: Phase) -> bool {
+            match *self {}
+        }
+
+        pub fn call(
+            &self,
+            _index: usize,
+            _phase: Phase,
+            _input: &[u8],
+        ) -> Result<Vec<u8>, String> {
+            match *self {}
+        }
+    }
+}
+
+use engine::Filters;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Request,
+    Response,
+}
+
+impl Phase {
+    #[must_use]
+    pub fn export(self) -> &'static str {
+        match self {
+            Self::Request => "on_request",
+            Self::Response => "on_response",
+        }
+    }
+}
+
+/// What a filter is given.
+#[derive(Debug, Serialize)]
+#[serde(tag = "phase", rename_all = "kebab-case")]
+enum Input<'a> {
+    Request {
+        method: &'a str,
+        path: &'a str,
+        headers: BTreeMap<&'a str, &'a str>,
+        /// `None` if the body isn't JSON.
+        body: Option<&'a Value>,
+    },
+    Response {
+        status: u16,
+        headers: BTreeMap<&'a str, &'a str>,
+    },
+}
+
+fn header_map(headers: &HeaderMap) -> BTreeMap<&str, &str> {
+    headers
+        .iter()
+        .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+        .collect()
+}
+
+/// What a filter returns. Every field is optional, so an empty object lets
+/// the request through unchanged.
+#[derive(Debug, Default, Deserialize, PartialEq)]
+#[serde(default, rename_all = "kebab-case")]
+struct Verdict {
+    /// Rejects the request with this message.
+    reject: Option<String>,
+    set_headers: BTreeMap<String, String>,
+    remove_headers: Vec<String>,
+    /// Replaces the request body. Ignored for responses.
+    body: Option<Value>,
+}
+
+impl Verdict {
+    fn apply_headers(&self, headers: &mut HeaderMap) -> Result<(), String> {
+        for name in &self.remove_headers {
+            headers.remove(name.as_str());
+        }
+        for (name, value) in &self.set_headers {
+            let name = HeaderName::try_from(name.as_str())
+                .map_err(|e| format!("invalid header name {name}: {e}"))?;
+            let value = HeaderValue::try_from(value.as_str())
+                .map_err(|e| format!("invalid value for {name}: {e}"))?;
+            headers.insert(name, value);
+        }
+        Ok(())
+    }
+}
+
+/// Runs filter `index` over `input`, off the async runtime since filters
+/// are synchronous. Returns `None` if the filter doesn't run in `phase`, or
+/// if it failed and fails open.
+async fn run(
+    filters: &Arc<Filters>,
+    index: usize,
+    phase: Phase,
+    input: &Input<'_>,
+) -> Result<Option<Verdict>, InternalError> {
+    if !filters.exports(index, phase) {
+        return Ok(None);
+    }
+    let input = serde_json::to_vec(input).map_err(|error| {
+        InternalError::Serialize {
+            ty: "wasm_filter::Input",
+            error,
+        }
+    })?;
+    let task_filters = Arc::clone(filters);
+    let result = tokio::task::spawn_blocking(move || {
+        task_filters.call(index, phase, &input)
+    })
+    .await
+    .map_err(|e| e.to_string())
+    .and_then(|result| result)
+    .and_then(|output| {
+        serde_json::from_slice::<Verdict>(&output)
+            .map_err(|e| format!("invalid verdict: {e}"))
+    });
+    match result {
+        Ok(verdict) => Ok(Some(verdict)),
+        Err(error) => fail(filters, index, error).map(|()| None),
+    }
+}
+
+/// Fails the request, unless filter `index` fails open.
+fn fail(
+    filters: &Filters,
+    index: usize,
+    error: String,
+) -> Result<(), InternalError> {
+    let config = filters.config(index);
+    if config.fail_open {
+        tracing::warn!(
+            filter = %config.path.display(),
+            error = %error,
+            "wasm filter failed, letting the request through"
+        );
+        Ok(())
+    } else {
+        Err(InternalError::FilterFailed(format!(
+            "{}: {error}",
+            config.path.display()
+        )))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Layer {
+    filters: Arc<Filters>,
+}
+
+impl Layer {
+    /// Returns `None` if the router doesn't configure any filters.
+    pub fn for_router(
+        router_config: &RouterConfig,
+    ) -> Result<Option<Self>, InitError> {
+        let Some(configs) = router_config
+            .wasm_filters
+            .as_ref()
+            .filter(|configs| !configs.is_empty())
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Self {
+            filters: Arc::new(Filters::new(configs)?),
+        }))
+    }
+}
+
+impl<S> tower::Layer<S> for Layer {
+    type Service = Service<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Service {
+            inner,
+            filters: Arc::clone(&self.filters),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Service<S> {
+    inner: S,
+    filters: Arc<Filters>,
+}
+
+impl<S> tower::Service<Request> for Service<S>
+where
+    S: tower::Service<Request, Response = Response, Error = ApiError>
+        + Clone
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    #[tracing::instrument(name = "wasm_filter", skip_all)]
+    fn call(&mut self, req: Request) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let filters = Arc::clone(&self.filters);
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let mut body_bytes = body
+                .collect()
+                .await
+                .map_err(InternalError::CollectBodyError)?
+                .to_bytes();
+            let mut body_json =
+                serde_json::from_slice::<Value>(&body_bytes).ok();
+            let mut body_replaced = false;
+
+            for index in 0..filters.count() {
+                let input = Input::Request {
+                    method: parts.method.as_str(),
+                    path: parts.uri.path(),
+                    headers: header_map(&parts.headers),
+                    body: body_json.as_ref(),
+                };
+                let Some(verdict) =
+                    run(&filters, index, Phase::Request, &input).await?
+                else {
+                    continue;
+                };
+                if let Some(message) = verdict.reject {
+                    return Err(
+                        InvalidRequestError::FilterRejected(message).into()
+                    );
+                }
+                if let Err(error) = verdict.apply_headers(&mut parts.headers) {
+                    fail(&filters, index, error)?;
+                }
+                if let Some(body) = verdict.body {
+                    body_json = Some(body);
+                    body_replaced = true;
+                }
+            }
+            if body_replaced && let Some(body_json) = &body_json {
+                body_bytes = serde_json::to_vec(body_json)
+                    .map(Bytes::from)
+                    .map_err(|error| InternalError::Serialize {
+                        ty: "serde_json::Value",
+                        error,
+                    })?;
+                parts.headers.remove(http::header::CONTENT_LENGTH);
+            }
+
+            let req = Request::from_parts(parts, body_bytes.into());
+            let mut response = inner.call(req).await?;
+
+            for index in (0..filters.count()).rev() {
+                let input = Input::Response {
+                    status: response.status().as_u16(),
+                    headers: header_map(response.headers()),
+                };
+                let Some(verdict) =
+                    run(&filters, index, Phase::Response, &input).await?
+                else {
+                    continue;
+                };
+                if let Some(message) = verdict.reject {
+                    return Err(
+                        InvalidRequestError::FilterRejected(message).into()
+                    );
+                }
+                if let Err(error) =
+                    verdict.apply_headers(response.headers_mut())
+                {
+                    fail(&filters, index, error)?;
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verdict_mutates_headers() {
+        let verdict: Verdict = serde_json::from_str(
+            r#"{
+                "set-headers": { "x-tenant": "acme" },
+                "remove-headers": ["x-internal"]
+            }"#,
+        )
+        .unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert("x-internal", HeaderValue::from_static("secret"));
+        verdict.apply_headers(&mut headers).unwrap();
+        assert_eq!(headers.get("x-tenant").unwrap(), "acme");
+        assert!(headers.get("x-internal").is_none());
+
+        let verdict: Verdict =
+            serde_json::from_str(r#"{ "set-headers": { "bad name": "x" } }"#)
+                .unwrap();
+        assert!(verdict.apply_headers(&mut headers).is_err());
+    }
+}
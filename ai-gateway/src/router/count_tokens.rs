@@ -0,0 +1,194 @@
+//! First class support for Anthropic's token counting endpoint.
+//!
+//! Requests for Anthropic models are proxied to Anthropic, as long as the
+//! router the request was sent to balances over Anthropic. For every other
+//! provider the token count is estimated by the gateway, so that clients can
+//! budget their prompts the same way regardless of the backing provider.
+use std::str::FromStr;
+
+use axum_core::response::IntoResponse;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use http::{HeaderValue, StatusCode, uri::PathAndQuery};
+use http_body_util::BodyExt;
+use indexmap::IndexSet;
+use serde::Serialize;
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{
+    error::{
+        api::ApiError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    middleware::context_length::{estimate_blocking, estimate_prompt_tokens},
+    router::direct::DirectProxiesWithoutMapper,
+    types::{
+        extensions::{MapperContext, RequestKind},
+        json::Json,
+        model_id::ModelId,
+        provider::InferenceProvider,
+        request::Request,
+        response::Response,
+    },
+    utils::tokenizer::TOKEN_COUNT_ESTIMATED_HEADER,
+};
+
+/// Path of the token counting endpoint on Anthropic's API.
+const ANTHROPIC_COUNT_TOKENS_PATH: &str = "v1/messages/count_tokens";
+
+#[derive(Debug, Serialize)]
+struct CountTokensResponse {
+    input_tokens: u32,
+}
+
+/// Whether `path`, relative to a router or the unified API, is the token
+/// counting endpoint.
+#[must_use]
+pub fn is_count_tokens_path(path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    path == ANTHROPIC_COUNT_TOKENS_PATH
+        || Some(path) == ANTHROPIC_COUNT_TOKENS_PATH.strip_prefix("v1/")
+}
+
+/// Counts the tokens of a request whose `model` is given as
+/// `{provider}/{model}`. Requests to a router pass the providers it balances
+/// over, and are only proxied to those.
+pub fn count_tokens(
+    req: Request,
+    direct_proxies: DirectProxiesWithoutMapper,
+    router_providers: Option<IndexSet<InferenceProvider>>,
+) -> BoxFuture<'static, Result<Response, ApiError>> {
+    Box::pin(async move {
+        let (mut parts, body) = req.into_parts();
+        let body = body
+            .collect()
+            .await
+            .map_err(InternalError::CollectBodyError)?
+            .to_bytes();
+        let mut request = serde_json::from_slice::<Value>(&body)
+            .map_err(InvalidRequestError::InvalidRequestBody)?;
+        let model = request
+            .get("model")
+            .and_then(Value::as_str)
+            .ok_or(InvalidRequestError::MissingModelId)?;
+        let model = ModelId::from_str(model)
+            .map_err(|_| InvalidRequestError::InvalidModelId)?;
+
+        let anthropic = direct_proxies
+            .get(&InferenceProvider::Anthropic)
+            .filter(|_| {
+                router_providers.as_ref().is_none_or(|providers| {
+                    providers.contains(&InferenceProvider::Anthropic)
+                })
+            });
+        let (Some(anthropic), Some(InferenceProvider::Anthropic)) =
+            (anthropic, model.inference_provider())
+        else {
+            let (_, response) =
+                estimate_blocking(body.len(), request, estimate).await?;
+            return Ok(response);
+        };
+
+        tracing::trace!(model = %model, "proxying count tokens request");
+        request["model"] = Value::String(model.to_string());
+        let body =
+            serde_json::to_vec(&request)
+                .map(Bytes::from)
+                .map_err(|error| InternalError::Serialize {
+                    ty: "serde_json::Value",
+                    error,
+                })?;
+        let path_and_query = match parts.uri.query() {
+            Some(query) => {
+                format!("{ANTHROPIC_COUNT_TOKENS_PATH}?{query}")
+            }
+            None => ANTHROPIC_COUNT_TOKENS_PATH.to_string(),
+        };
+        let path_and_query = PathAndQuery::from_str(&path_and_query)
+            .map_err(InternalError::InvalidUri)?;
+        parts.extensions.insert(path_and_query);
+        parts.extensions.insert(RequestKind::DirectProxy);
+        parts.extensions.insert(MapperContext {
+            is_stream: false,
+            model: Some(model),
+        });
+        let req = Request::from_parts(parts, body.into());
+        anthropic.clone().oneshot(req).await
+    })
+}
+
+/// Responds with the estimated token count of `request`.
+///
+/// Used for providers without a token counting endpoint.
+#[must_use]
+fn estimate(request: &Value) -> Response {
+    let input_tokens = estimate_prompt_tokens(request);
+    let mut response =
+        (StatusCode::OK, Json(CountTokensResponse { input_tokens }))
+            .into_response();
+    response.headers_mut().insert(
+        TOKEN_COUNT_ESTIMATED_HEADER,
+        HeaderValue::from_static("true"),
+    );
+    response
+}
+
+/// Estimates the token count of a direct proxy request to a provider without
+/// a token counting endpoint.
+pub fn estimate_direct(
+    req: Request,
+) -> BoxFuture<'static, Result<Response, ApiError>> {
+    Box::pin(async move {
+        let body = req
+            .into_body()
+            .collect()
+            .await
+            .map_err(InternalError::CollectBodyError)?
+            .to_bytes();
+        let request = serde_json::from_slice::<Value>(&body)
+            .map_err(InvalidRequestError::InvalidRequestBody)?;
+        let (_, response) =
+            estimate_blocking(body.len(), request, estimate).await?;
+        Ok(response)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn count_tokens_paths() {
+        assert!(is_count_tokens_path("messages/count_tokens"));
+        assert!(is_count_tokens_path("v1/messages/count_tokens"));
+        assert!(is_count_tokens_path("/v1/messages/count_tokens"));
+        assert!(!is_count_tokens_path("chat/completions"));
+        assert!(!is_count_tokens_path("v2/messages/count_tokens"));
+    }
+
+    #[tokio::test]
+    async fn estimated_count() {
+        let request = json!({
+            "model": "openai/gpt-4o",
+            "system": "1234",
+            "messages": [{"role": "user", "content": "12345678"}],
+        });
+        let response = estimate(&request);
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response
+                .headers()
+                .get(TOKEN_COUNT_ESTIMATED_HEADER)
+                .unwrap(),
+            "true"
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = serde_json::from_slice::<Value>(&body).unwrap();
+        // 2 tokens for the system prompt, 4 for the message and 3 for its
+        // content
+        assert_eq!(body, json!({"input_tokens": 9}));
+    }
+}
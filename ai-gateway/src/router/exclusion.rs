@@ -0,0 +1,274 @@
+//! Per-request exclusion of providers from the balance decision.
+//!
+//! A request with `helicone-exclude-providers: anthropic,bedrock` is only
+//! sent to the router's other providers, such as when its data may not leave
+//! approved processors. The balancers pick a provider before seeing the
+//! request, so such requests bypass them and are sent to one of the
+//! remaining targets at random, by their configured weights.
+use std::{
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use futures::{FutureExt, future::BoxFuture};
+use http::{HeaderMap, HeaderName};
+use indexmap::IndexSet;
+use rand::seq::IndexedRandom;
+use rust_decimal::prelude::ToPrimitive;
+use tower::ServiceExt;
+
+use crate::{
+    app_state::AppState,
+    config::{balance::BalanceConfigInner, router::RouterConfig},
+    dispatcher::{Dispatcher, DispatcherService},
+    error::{api::ApiError, init::InitError, invalid_req::InvalidRequestError},
+    router::strategy::RoutingStrategyService,
+    types::{
+        provider::InferenceProvider, request::Request, response::Response,
+        router::RouterId,
+    },
+};
+
+pub const EXCLUDE_PROVIDERS_HEADER: HeaderName =
+    HeaderName::from_static("helicone-exclude-providers");
+
+/// The providers excluded by a request, if it excludes any.
+///
+/// # Errors
+/// If the header isn't valid UTF-8.
+pub fn excluded_providers(
+    headers: &HeaderMap,
+) -> Result<Option<IndexSet<InferenceProvider>>, InvalidRequestError> {
+    let Some(header) = headers.get(EXCLUDE_PROVIDERS_HEADER) else {
+        return Ok(None);
+    };
+    let excluded = header
+        .to_str()
+        .map_err(InvalidRequestError::InvalidRequestHeader)?
+        .split(',')
+        .map(str::trim)
+        .filter(|provider| !provider.is_empty())
+        .map(|provider| {
+            let Ok(provider) = InferenceProvider::from_str(provider);
+            provider
+        })
+        .collect::<IndexSet<_>>();
+    Ok((!excluded.is_empty()).then_some(excluded))
+}
+
+/// A target of a balance config, with its own dispatcher.
+#[derive(Debug)]
+struct Target {
+    provider: InferenceProvider,
+    weight: f64,
+    service: DispatcherService,
+}
+
+/// Sends requests which exclude providers to one of the remaining targets of
+/// the balance config, and every other request to the balancer.
+#[derive(Debug)]
+pub struct ExclusionService {
+    router_id: RouterId,
+    balancer: RoutingStrategyService,
+    targets: Vec<Target>,
+}
+
+impl ExclusionService {
+    pub async fn new(
+        app_state: &AppState,
+        router_id: &RouterId,
+        router_config: &Arc<RouterConfig>,
+        balance_config: &BalanceConfigInner,
+        balancer: RoutingStrategyService,
+    ) -> Result<Self, InitError> {
+        let mut targets = Vec::new();
+        match balance_config {
+            BalanceConfigInner::ProviderWeighted { providers } => {
+                for target in providers {
+                    let service = Dispatcher::new(
+                        app_state.clone(),
+                        router_id,
+                        router_config,
+                        target.provider.clone(),
+                    )
+                    .await?;
+                    targets.push(Target {
+                        provider: target.provider.clone(),
+                        weight: target.weight.to_f64().ok_or_else(|| {
+                            InitError::InvalidWeight(target.provider.clone())
+                        })?,
+                        service,
+                    });
+                }
+            }
+            BalanceConfigInner::BalancedLatency { providers } => {
+                for provider in providers {
+                    let service = Dispatcher::new(
+                        app_state.clone(),
+                        router_id,
+                        router_config,
+                        provider.clone(),
+                    )
+                    .await?;
+                    targets.push(Target {
+                        provider: provider.clone(),
+                        weight: 1.0,
+                        service,
+                    });
+                }
+            }
+            BalanceConfigInner::ModelWeighted { models } => {
+                for target in models {
+                    let provider =
+                        target.model.inference_provider().ok_or_else(|| {
+                            InitError::ModelIdNotRecognized(
+                                target.model.to_string(),
+                            )
+                        })?;
+                    let service = Dispatcher::new_with_model_id(
+                        app_state.clone(),
+                        router_id,
+                        router_config,
+                        provider.clone(),
+                        target.model.clone(),
+                    )
+                    .await?;
+                    targets.push(Target {
+                        weight: target.weight.to_f64().ok_or_else(|| {
+                            InitError::InvalidWeight(provider.clone())
+                        })?,
+                        provider,
+                        service,
+                    });
+                }
+            }
+            BalanceConfigInner::ModelLatency { models } => {
+                for model in models {
+                    let provider =
+                        model.inference_provider().ok_or_else(|| {
+                            InitError::ModelIdNotRecognized(model.to_string())
+                        })?;
+                    let service = Dispatcher::new_with_model_id(
+                        app_state.clone(),
+                        router_id,
+                        router_config,
+                        provider.clone(),
+                        model.clone(),
+                    )
+                    .await?;
+                    targets.push(Target {
+                        provider,
+                        weight: 1.0,
+                        service,
+                    });
+                }
+            }
+        }
+        Ok(Self {
+            router_id: router_id.clone(),
+            balancer,
+            targets,
+        })
+    }
+
+    /// Picks one of the targets whose provider isn't excluded.
+    fn pick(
+        &self,
+        excluded: &IndexSet<InferenceProvider>,
+    ) -> Result<DispatcherService, InvalidRequestError> {
+        if let Some(unknown) = excluded.iter().find(|provider| {
+            !self
+                .targets
+                .iter()
+                .any(|target| &target.provider == *provider)
+        }) {
+            return Err(InvalidRequestError::UnknownExcludedProvider(
+                unknown.clone(),
+            ));
+        }
+        let remaining = self
+            .targets
+            .iter()
+            .filter(|target| !excluded.contains(&target.provider))
+            .collect::<Vec<_>>();
+        remaining
+            .choose_weighted(&mut rand::rng(), |target| target.weight)
+            .map(|target| target.service.clone())
+            .map_err(|_| InvalidRequestError::AllProvidersExcluded)
+    }
+}
+
+impl tower::Service<Request> for ExclusionService {
+    type Response = Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    #[inline]
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.balancer.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let excluded = match excluded_providers(req.headers()) {
+            Ok(Some(excluded)) => excluded,
+            Ok(None) => return self.balancer.call(req).boxed(),
+            Err(e) => return futures::future::ready(Err(e.into())).boxed(),
+        };
+        let service = match self.pick(&excluded) {
+            Ok(service) => service,
+            Err(e) => {
+                tracing::info!(
+                    router_id = %self.router_id,
+                    excluded = ?excluded,
+                    error = %e,
+                    "rejected request excluding providers"
+                );
+                return futures::future::ready(Err(e.into())).boxed();
+            }
+        };
+        tracing::info!(
+            router_id = %self.router_id,
+            excluded = ?excluded,
+            "excluded providers from balance decision"
+        );
+        async move {
+            match service.oneshot(req).await {
+                Ok(response) => Ok(response),
+                Err(infallible) => match infallible {},
+            }
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn excluded_providers_are_parsed_from_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(excluded_providers(&headers).unwrap(), None);
+
+        headers.insert(
+            EXCLUDE_PROVIDERS_HEADER,
+            HeaderValue::from_static("anthropic, bedrock,,"),
+        );
+        assert_eq!(
+            excluded_providers(&headers).unwrap(),
+            Some(IndexSet::from([
+                InferenceProvider::Anthropic,
+                InferenceProvider::Bedrock,
+            ]))
+        );
+
+        headers.insert(EXCLUDE_PROVIDERS_HEADER, HeaderValue::from_static(" "));
+        assert_eq!(excluded_providers(&headers).unwrap(), None);
+    }
+}
@@ -10,14 +10,14 @@ use futures::{Future, ready};
 use http_body_util::{BodyExt, combinators::Collect};
 use pin_project_lite::pin_project;
 use tokio::sync::mpsc::channel;
-use tower::{Service, buffer::Buffer, load::PeakEwmaDiscover};
+use tower::{Service, buffer::Buffer};
 
 use crate::{
     app_state::AppState,
     config::router::RouterConfig,
     discover::{
         dispatcher::{DispatcherDiscovery, factory::DispatcherDiscoverFactory},
-        model,
+        model::{self, shared_ewma::SharedPeakEwmaDiscover},
     },
     error::{
         api::ApiError, init::InitError, internal::InternalError,
@@ -35,7 +35,7 @@ const CHANNEL_CAPACITY: usize = 16;
 
 type ConcreteLatencyRouter = latency_router::router::LatencyRouter<
     ModelName<'static>,
-    PeakEwmaDiscover<DispatcherDiscovery<model::key::Key>>,
+    SharedPeakEwmaDiscover<DispatcherDiscovery<model::key::Key>>,
     axum_core::body::Body,
 >;
 
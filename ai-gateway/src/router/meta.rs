@@ -5,6 +5,7 @@ use std::{
 };
 
 use dynamic_router::router::DynamicRouter;
+use futures::future::BoxFuture;
 use pin_project_lite::pin_project;
 use tower::{
     Service as _, ServiceBuilder, buffer::BufferLayer, util::BoxCloneService,
@@ -25,14 +26,20 @@ use crate::{
         rate_limit::service::{
             Layer as RateLimitLayer, Service as RateLimitService,
         },
+        tier_policy,
     },
     router::{
+        count_tokens,
         direct::{DirectProxiesWithoutMapper, DirectProxyServiceWithoutMapper},
+        models,
         router_details::{RouteType, RouterDetailsLayer},
         unified_api,
     },
     types::{provider::InferenceProvider, router::RouterId},
-    utils::handle_error::{ErrorHandler, ErrorHandlerLayer},
+    utils::{
+        handle_error::{ErrorHandler, ErrorHandlerLayer},
+        load_shed::LoadShedLayer,
+    },
 };
 
 pub(crate) const MIDDLEWARE_BUFFER_SIZE: usize = 256;
@@ -45,6 +52,7 @@ pub struct MetaRouter {
     dynamic_router: DynamicRouter<RouterDiscovery, axum_core::body::Body>,
     unified_api: UnifiedApiService,
     direct_proxies: DirectProxiesWithoutMapper,
+    app_state: AppState,
 }
 
 pub type MetaRouterService = BoxCloneService<
@@ -68,9 +76,11 @@ impl MetaRouter {
             .layer(AsyncRequireAuthorizationLayer::new(
                 crate::middleware::auth::AuthService::new(app_state.clone()),
             ))
+            .layer(LoadShedLayer::new(&app_state))
             .layer(RateLimitLayer::global(&app_state)?)
             .layer(CacheLayer::global(&app_state)?)
             .layer(ErrorHandlerLayer::new(app_state.clone()))
+            .layer(tier_policy::Layer::new(&app_state))
             .map_err(crate::error::internal::InternalError::BufferError)
             .layer(BufferLayer::new(MIDDLEWARE_BUFFER_SIZE))
             .layer(ErrorHandlerLayer::new(app_state.clone()))
@@ -81,7 +91,8 @@ impl MetaRouter {
     pub async fn cloud(app_state: AppState) -> Result<Self, InitError> {
         let discovery_factory = RouterDiscoverFactory::new(app_state.clone());
         let mut router_factory =
-            dynamic_router::router::make::MakeRouter::new(discovery_factory);
+            dynamic_router::router::make::MakeRouter::new(discovery_factory)
+                .with_drain_timeout(app_state.config().server.shutdown_timeout);
         let (tx, rx) = tokio::sync::mpsc::channel(100);
         app_state.set_router_tx(tx).await;
         let dynamic_router = router_factory.call(Some(rx)).await?;
@@ -91,13 +102,13 @@ impl MetaRouter {
             .layer(CacheLayer::unified_api(&app_state)?)
             .layer(ErrorHandlerLayer::new(app_state.clone()))
             .service(unified_api::Service::new(&app_state).await?);
-        let direct_proxies =
-            DirectProxiesWithoutMapper::new(&app_state).await?;
+        let direct_proxies = app_state.direct_proxies().await?;
 
         let meta_router = Self {
             dynamic_router,
             unified_api,
             direct_proxies,
+            app_state,
         };
         Ok(meta_router)
     }
@@ -105,19 +116,20 @@ impl MetaRouter {
     pub async fn sidecar(app_state: AppState) -> Result<Self, InitError> {
         let discovery_factory = RouterDiscoverFactory::new(app_state.clone());
         let mut router_factory =
-            dynamic_router::router::make::MakeRouter::new(discovery_factory);
+            dynamic_router::router::make::MakeRouter::new(discovery_factory)
+                .with_drain_timeout(app_state.config().server.shutdown_timeout);
         let dynamic_router = router_factory.call(None).await?;
         let unified_api = ServiceBuilder::new()
             .layer(RateLimitLayer::unified_api(&app_state)?)
             .layer(CacheLayer::unified_api(&app_state)?)
             .layer(ErrorHandlerLayer::new(app_state.clone()))
             .service(unified_api::Service::new(&app_state).await?);
-        let direct_proxies =
-            DirectProxiesWithoutMapper::new(&app_state).await?;
+        let direct_proxies = app_state.direct_proxies().await?;
         let meta_router = Self {
             dynamic_router,
             unified_api,
             direct_proxies,
+            app_state,
         };
         Ok(meta_router)
     }
@@ -208,6 +220,38 @@ impl tower::Service<crate::types::request::Request> for MetaRouter {
     fn call(&mut self, req: crate::types::request::Request) -> Self::Future {
         let route_type = req.extensions().get::<RouteType>().cloned();
         match route_type {
+            Some(RouteType::UnifiedApi { path })
+                if count_tokens::is_count_tokens_path(&path) =>
+            {
+                ResponseFuture::CountTokens {
+                    future: count_tokens::count_tokens(
+                        req,
+                        self.direct_proxies.clone(),
+                        None,
+                    ),
+                }
+            }
+            Some(RouteType::UnifiedApi { path })
+                if req.method() == http::Method::GET
+                    && models::is_models_path(&path) =>
+            {
+                ResponseFuture::Models {
+                    future: models::list_models(
+                        req,
+                        self.app_state.clone(),
+                        self.direct_proxies.clone(),
+                        None,
+                    ),
+                }
+            }
+            Some(RouteType::DirectProxy { provider, path })
+                if provider != InferenceProvider::Anthropic
+                    && count_tokens::is_count_tokens_path(&path) =>
+            {
+                ResponseFuture::CountTokens {
+                    future: count_tokens::estimate_direct(req),
+                }
+            }
             Some(RouteType::Router { id, path }) => {
                 self.handle_router_request(req, &id, &path)
             }
@@ -250,6 +294,14 @@ pin_project! {
             #[pin]
             future: <DirectProxyServiceWithoutMapper as tower::Service<crate::types::request::Request>>::Future,
         },
+        CountTokens {
+            #[pin]
+            future: BoxFuture<'static, Result<crate::types::response::Response, ApiError>>,
+        },
+        Models {
+            #[pin]
+            future: BoxFuture<'static, Result<crate::types::response::Response, ApiError>>,
+        },
     }
 }
 
@@ -269,6 +321,8 @@ impl std::future::Future for ResponseFuture {
             ResponseFutureProj::DirectProxy { future } => future
                 .poll(cx)
                 .map_err(|_| ApiError::Internal(InternalError::Internal)),
+            ResponseFutureProj::CountTokens { future } => future.poll(cx),
+            ResponseFutureProj::Models { future } => future.poll(cx),
         }
     }
 }
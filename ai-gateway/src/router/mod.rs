@@ -1,6 +1,10 @@
+pub mod count_tokens;
 pub mod direct;
+pub mod exclusion;
 pub mod latency;
 pub mod meta;
+pub mod models;
+pub mod residency;
 pub mod router_details;
 pub mod service;
 pub mod strategy;
@@ -0,0 +1,382 @@
+//! Listing of the models available through a router or the unified API.
+//!
+//! `/v1/models` merges the model lists of the providers, with model ids in
+//! the `{provider}/{model}` form accepted by the unified API. Model lists
+//! rarely change, so each provider's list is cached for the configured `ttl`
+//! instead of being fetched on every request, which would count against the
+//! provider's rate limits. An expired list is still served while it's
+//! refreshed in the background, and the response is marked as stale.
+use std::{
+    sync::RwLock,
+    time::{Duration, Instant},
+};
+
+use axum_core::response::IntoResponse;
+use futures::future::{BoxFuture, join_all};
+use http::{
+    Extensions, HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri,
+    uri::PathAndQuery,
+};
+use http_body_util::BodyExt;
+use indexmap::IndexSet;
+use rustc_hash::FxHashMap as HashMap;
+use serde::Serialize;
+use serde_json::Value;
+use tower::ServiceExt;
+
+use crate::{
+    app_state::AppState,
+    error::{api::ApiError, internal::InternalError},
+    router::direct::DirectProxiesWithoutMapper,
+    types::{
+        extensions::{MapperContext, RequestKind},
+        json::Json,
+        provider::InferenceProvider,
+        request::Request,
+        response::Response,
+    },
+    utils::task,
+};
+
+const MODELS_PATH: &str = "v1/models";
+/// Path of the OpenAI compatible model list on Gemini's API.
+const GEMINI_MODELS_PATH: &str = "v1beta/openai/models";
+/// Set on responses including a model list which is being refreshed.
+const MODELS_STALE_HEADER: HeaderName =
+    HeaderName::from_static("helicone-models-stale");
+
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+struct Model {
+    id: String,
+    object: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created: Option<i64>,
+    owned_by: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ModelList {
+    object: &'static str,
+    data: Vec<Model>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    models: Vec<Model>,
+    fetched_at: Instant,
+    refreshing: bool,
+}
+
+/// The cached model lists of the providers.
+#[derive(Debug, Default)]
+pub struct ModelLists(RwLock<HashMap<InferenceProvider, Entry>>);
+
+impl ModelLists {
+    /// Returns the cached models of `provider`, and whether they're older
+    /// than `ttl`.
+    fn get(
+        &self,
+        provider: &InferenceProvider,
+        ttl: Duration,
+    ) -> Option<(Vec<Model>, bool)> {
+        let lists = self.0.read().expect("model lists lock poisoned");
+        lists.get(provider).map(|entry| {
+            (entry.models.clone(), entry.fetched_at.elapsed() >= ttl)
+        })
+    }
+
+    /// Marks the list of `provider` as being refreshed, returning `false`
+    /// if it already is.
+    fn start_refresh(&self, provider: &InferenceProvider) -> bool {
+        let mut lists = self.0.write().expect("model lists lock poisoned");
+        match lists.get_mut(provider) {
+            Some(entry) if !entry.refreshing => {
+                entry.refreshing = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Stores the fetched models of `provider`. If fetching failed, the
+    /// previous list is kept.
+    fn store(&self, provider: InferenceProvider, models: Option<Vec<Model>>) {
+        let mut lists = self.0.write().expect("model lists lock poisoned");
+        match models {
+            Some(models) => {
+                lists.insert(
+                    provider,
+                    Entry {
+                        models,
+                        fetched_at: Instant::now(),
+                        refreshing: false,
+                    },
+                );
+            }
+            None => {
+                if let Some(entry) = lists.get_mut(&provider) {
+                    entry.refreshing = false;
+                }
+            }
+        }
+    }
+}
+
+/// Whether `path`, relative to a router or the unified API, is the model
+/// list endpoint.
+#[must_use]
+pub fn is_models_path(path: &str) -> bool {
+    let path = path.trim_start_matches('/');
+    path == MODELS_PATH || Some(path) == MODELS_PATH.strip_prefix("v1/")
+}
+
+/// The path of the model list on the API of `provider`, if it has one in
+/// the OpenAI format.
+fn models_path(provider: &InferenceProvider) -> Option<&'static str> {
+    match provider {
+        // Azure lists its models rather than the deployments requests target,
+        // Vertex only lists models through its own API, and Cohere's list
+        // isn't in the OpenAI format
+        InferenceProvider::Bedrock
+        | InferenceProvider::AzureOpenAI
+        | InferenceProvider::Vertex
+        | InferenceProvider::Cohere => None,
+        InferenceProvider::GoogleGemini => Some(GEMINI_MODELS_PATH),
+        _ => Some(MODELS_PATH),
+    }
+}
+
+/// The parts of a client's request reused for the requests to the
+/// providers.
+#[derive(Clone)]
+struct RequestTemplate {
+    uri: Uri,
+    headers: HeaderMap,
+    extensions: Extensions,
+}
+
+/// Lists the models of `router_providers`, the providers of a router, or of
+/// every provider for the unified API.
+pub fn list_models(
+    req: Request,
+    app_state: AppState,
+    direct_proxies: DirectProxiesWithoutMapper,
+    router_providers: Option<IndexSet<InferenceProvider>>,
+) -> BoxFuture<'static, Result<Response, ApiError>> {
+    Box::pin(async move {
+        let (parts, _body) = req.into_parts();
+        let template = RequestTemplate {
+            uri: parts.uri,
+            headers: parts.headers,
+            extensions: parts.extensions,
+        };
+        let ttl = app_state.config().model_list.ttl;
+        let model_lists = &app_state.0.model_lists;
+        let mut data = Vec::new();
+        let mut stale = false;
+        let mut missing = Vec::new();
+        for provider in providers(&direct_proxies, router_providers) {
+            match model_lists.get(&provider, ttl) {
+                Some((models, expired)) => {
+                    if expired {
+                        stale = true;
+                        refresh(
+                            &app_state,
+                            &direct_proxies,
+                            &template,
+                            provider,
+                        );
+                    }
+                    data.extend(models);
+                }
+                None => missing.push(provider),
+            }
+        }
+        let fetched = join_all(missing.into_iter().map(|provider| {
+            let direct_proxies = direct_proxies.clone();
+            let template = template.clone();
+            async move {
+                let models = fetch(&direct_proxies, template, &provider).await;
+                (provider, models)
+            }
+        }))
+        .await;
+        for (provider, models) in fetched {
+            if let Some(models) = &models {
+                data.extend(models.iter().cloned());
+            }
+            model_lists.store(provider, models);
+        }
+
+        let mut response = (
+            StatusCode::OK,
+            Json(ModelList {
+                object: "list",
+                data,
+            }),
+        )
+            .into_response();
+        if stale {
+            response
+                .headers_mut()
+                .insert(MODELS_STALE_HEADER, HeaderValue::from_static("true"));
+        }
+        Ok(response)
+    })
+}
+
+/// The providers with a model list, of `router_providers` if given, or every
+/// provider otherwise.
+fn providers(
+    direct_proxies: &DirectProxiesWithoutMapper,
+    router_providers: Option<IndexSet<InferenceProvider>>,
+) -> IndexSet<InferenceProvider> {
+    let providers = router_providers
+        .unwrap_or_else(|| direct_proxies.keys().cloned().collect());
+    providers
+        .into_iter()
+        .filter(|provider| {
+            direct_proxies.contains_key(provider)
+                && models_path(provider).is_some()
+        })
+        .collect()
+}
+
+/// Refreshes the model list of `provider` in the background, unless it's
+/// already being refreshed.
+fn refresh(
+    app_state: &AppState,
+    direct_proxies: &DirectProxiesWithoutMapper,
+    template: &RequestTemplate,
+    provider: InferenceProvider,
+) {
+    if !app_state.0.model_lists.start_refresh(&provider) {
+        return;
+    }
+    let app_state = app_state.clone();
+    let direct_proxies = direct_proxies.clone();
+    let template = template.clone();
+    task::spawn("model-list-refresh", async move {
+        tracing::debug!(provider = %provider, "refreshing model list");
+        let models = fetch(&direct_proxies, template, &provider).await;
+        app_state.0.model_lists.store(provider, models);
+    });
+}
+
+async fn fetch(
+    direct_proxies: &DirectProxiesWithoutMapper,
+    template: RequestTemplate,
+    provider: &InferenceProvider,
+) -> Option<Vec<Model>> {
+    let direct_proxy = direct_proxies.get(provider)?.clone();
+    let path = models_path(provider)?;
+    let mut req = Request::new(axum_core::body::Body::empty());
+    *req.method_mut() = Method::GET;
+    *req.uri_mut() = template.uri;
+    *req.headers_mut() = template.headers;
+    *req.extensions_mut() = template.extensions;
+    req.extensions_mut().insert(PathAndQuery::from_static(path));
+    req.extensions_mut().insert(RequestKind::DirectProxy);
+    req.extensions_mut().insert(MapperContext {
+        is_stream: false,
+        model: None,
+    });
+    let result = async {
+        let response = direct_proxy.oneshot(req).await?;
+        if !response.status().is_success() {
+            tracing::warn!(
+                provider = %provider,
+                status = %response.status(),
+                "failed to fetch model list"
+            );
+            return Ok(None);
+        }
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(InternalError::CollectBodyError)?
+            .to_bytes();
+        Ok::<_, ApiError>(Some(parse_models(provider, &body)))
+    }
+    .await;
+    result
+        .inspect_err(|error| {
+            tracing::warn!(
+                provider = %provider,
+                error = %error,
+                "failed to fetch model list"
+            );
+        })
+        .ok()
+        .flatten()
+}
+
+/// Reads an OpenAI formatted model list.
+fn parse_models(provider: &InferenceProvider, body: &[u8]) -> Vec<Model> {
+    let Ok(body) = serde_json::from_slice::<Value>(body) else {
+        return Vec::new();
+    };
+    body.get("data")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(|model| {
+            let id = model.get("id")?.as_str()?;
+            // Gemini prefixes its model ids
+            let id = id.strip_prefix("models/").unwrap_or(id);
+            Some(Model {
+                id: format!("{provider}/{id}"),
+                object: "model",
+                created: model.get("created").and_then(Value::as_i64),
+                owned_by: provider.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn models_paths() {
+        assert!(is_models_path("models"));
+        assert!(is_models_path("/v1/models"));
+        assert!(!is_models_path("v1/models/gpt-4o"));
+    }
+
+    #[test]
+    fn model_ids_are_prefixed_with_the_provider() {
+        let body = br#"{"object": "list", "data": [
+            {"id": "models/gemini-2.5-flash", "object": "model"},
+            {"id": "gemini-2.5-pro", "created": 1700000000}
+        ]}"#;
+        let models = parse_models(&InferenceProvider::GoogleGemini, body);
+        let ids = models.iter().map(|m| m.id.as_str()).collect::<Vec<_>>();
+        assert_eq!(ids, ["gemini/gemini-2.5-flash", "gemini/gemini-2.5-pro"]);
+        assert_eq!(models[1].created, Some(1_700_000_000));
+    }
+
+    #[test]
+    fn expired_lists_are_refreshed_once() {
+        let lists = ModelLists::default();
+        let provider = InferenceProvider::OpenAI;
+        assert!(lists.get(&provider, Duration::ZERO).is_none());
+        assert!(!lists.start_refresh(&provider));
+
+        lists.store(provider.clone(), Some(Vec::new()));
+        let (_, expired) =
+            lists.get(&provider, Duration::from_secs(60)).unwrap();
+        assert!(!expired);
+        let (_, expired) = lists.get(&provider, Duration::ZERO).unwrap();
+        assert!(expired);
+        assert!(lists.start_refresh(&provider));
+        assert!(!lists.start_refresh(&provider));
+
+        // a failed refresh keeps the previous list
+        lists.store(provider.clone(), None);
+        assert!(lists.get(&provider, Duration::ZERO).is_some());
+        assert!(lists.start_refresh(&provider));
+    }
+}
@@ -0,0 +1,205 @@
+//! Data residency policies of routers.
+//!
+//! A router's policy is applied to its config when the router is created:
+//! balance targets and failover regions the policy doesn't allow are
+//! removed, so that neither the balancers nor the health and rate limit
+//! monitors can send requests to them. Endpoints left without a target
+//! reject their requests. Each decision is logged.
+use std::{collections::HashSet, sync::Arc};
+
+use nonempty_collections::NESet;
+
+use crate::{
+    config::{
+        balance::BalanceConfigInner, providers::ProvidersConfig,
+        router::RouterConfig,
+    },
+    endpoints::EndpointType,
+    types::{model_id::ModelId, provider::InferenceProvider, router::RouterId},
+};
+
+/// A router config with its data residency policy applied.
+#[derive(Debug)]
+pub struct Resident {
+    pub router_config: Arc<RouterConfig>,
+    /// Endpoints none of whose targets the policy allows.
+    pub blocked: HashSet<EndpointType>,
+}
+
+/// Applies the data residency policy of `router_config`, if it has one.
+#[must_use]
+pub fn apply(
+    router_id: &RouterId,
+    router_config: Arc<RouterConfig>,
+    providers: &ProvidersConfig,
+) -> Resident {
+    let Some(policy) = router_config.data_residency.clone() else {
+        return Resident {
+            router_config,
+            blocked: HashSet::new(),
+        };
+    };
+    let allows = |provider: &InferenceProvider| {
+        let region = providers
+            .get(provider)
+            .and_then(|provider_config| provider_config.region());
+        let allowed = policy.allows(provider, region);
+        if !allowed {
+            tracing::info!(
+                router_id = %router_id,
+                provider = %provider,
+                region = ?region,
+                "data residency policy excludes provider"
+            );
+        }
+        allowed
+    };
+
+    let mut resident = RouterConfig::clone(&router_config);
+    let mut blocked = HashSet::new();
+    resident
+        .load_balance
+        .0
+        .retain(|endpoint_type, balance_config| {
+            if let Some(allowed) = allowed_targets(balance_config, &allows) {
+                *balance_config = allowed;
+                true
+            } else {
+                tracing::warn!(
+                    router_id = %router_id,
+                    endpoint = endpoint_type.as_ref(),
+                    "data residency policy excludes every provider of endpoint"
+                );
+                blocked.insert(*endpoint_type);
+                false
+            }
+        });
+    if let Some(router_providers) = resident.providers.as_mut() {
+        for (provider, provider_config) in router_providers.iter_mut() {
+            provider_config.regions.retain(|region| {
+                let allowed = policy.allows_region(Some(&region.name));
+                if !allowed {
+                    tracing::info!(
+                        router_id = %router_id,
+                        provider = %provider,
+                        region = %region.name,
+                        "data residency policy excludes failover region"
+                    );
+                }
+                allowed
+            });
+        }
+    }
+    Resident {
+        router_config: Arc::new(resident),
+        blocked,
+    }
+}
+
+/// The targets of `balance_config` whose provider `allows` allows, or `None`
+/// if it allows none of them.
+fn allowed_targets(
+    balance_config: &BalanceConfigInner,
+    allows: impl Fn(&InferenceProvider) -> bool,
+) -> Option<BalanceConfigInner> {
+    let allows_model = |model: &ModelId| {
+        model
+            .inference_provider()
+            .is_some_and(|provider| allows(&provider))
+    };
+    match balance_config {
+        BalanceConfigInner::ProviderWeighted { providers } => {
+            let providers = providers
+                .into_iter()
+                .filter(|target| allows(&target.provider))
+                .cloned()
+                .collect::<HashSet<_>>();
+            NESet::try_from_set(providers).map(|providers| {
+                BalanceConfigInner::ProviderWeighted { providers }
+            })
+        }
+        BalanceConfigInner::BalancedLatency { providers } => {
+            let providers = providers
+                .into_iter()
+                .filter(|provider| allows(*provider))
+                .cloned()
+                .collect::<HashSet<_>>();
+            NESet::try_from_set(providers).map(|providers| {
+                BalanceConfigInner::BalancedLatency { providers }
+            })
+        }
+        BalanceConfigInner::ModelWeighted { models } => {
+            let models = models
+                .into_iter()
+                .filter(|target| allows_model(&target.model))
+                .cloned()
+                .collect::<HashSet<_>>();
+            NESet::try_from_set(models)
+                .map(|models| BalanceConfigInner::ModelWeighted { models })
+        }
+        BalanceConfigInner::ModelLatency { models } => {
+            let models = models
+                .into_iter()
+                .filter(|model| allows_model(*model))
+                .cloned()
+                .collect::<HashSet<_>>();
+            NESet::try_from_set(models)
+                .map(|models| BalanceConfigInner::ModelLatency { models })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use indexmap::IndexSet;
+    use nonempty_collections::nes;
+
+    use super::*;
+    use crate::config::{
+        balance::BalanceConfig, data_residency::DataResidencyConfig,
+    };
+
+    #[test]
+    fn providers_outside_allowed_regions_are_removed() {
+        let balance = |providers| {
+            BalanceConfig(HashMap::from([(
+                EndpointType::Chat,
+                BalanceConfigInner::BalancedLatency { providers },
+            )]))
+        };
+        let router_config = RouterConfig {
+            load_balance: balance(nes![
+                InferenceProvider::OpenAI,
+                InferenceProvider::Bedrock,
+            ]),
+            data_residency: Some(DataResidencyConfig {
+                allowed_regions: IndexSet::from(["us".to_string()]),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let router_id = RouterId::Named("my-router".into());
+        let providers = ProvidersConfig::default();
+
+        let resident =
+            apply(&router_id, Arc::new(router_config.clone()), &providers);
+        assert_eq!(
+            resident.router_config.load_balance,
+            balance(nes![InferenceProvider::Bedrock])
+        );
+        assert!(resident.blocked.is_empty());
+
+        let router_config = RouterConfig {
+            data_residency: Some(DataResidencyConfig {
+                allowed_regions: IndexSet::from(["eu".to_string()]),
+                ..Default::default()
+            }),
+            ..router_config
+        };
+        let resident = apply(&router_id, Arc::new(router_config), &providers);
+        assert!(resident.router_config.load_balance.0.is_empty());
+        assert_eq!(resident.blocked, HashSet::from([EndpointType::Chat]));
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    collections::HashSet,
     convert::Infallible,
     future::Future,
     pin::Pin,
@@ -7,7 +8,9 @@ use std::{
 };
 
 use axum_core::response::IntoResponse;
-use http::uri::PathAndQuery;
+use futures::future::BoxFuture;
+use http::{Method, uri::PathAndQuery};
+use indexmap::IndexSet;
 use pin_project_lite::pin_project;
 use rustc_hash::FxHashMap as HashMap;
 use tower::{ServiceBuilder, buffer, util::BoxCloneService};
@@ -21,10 +24,20 @@ use crate::{
         invalid_req::InvalidRequestError,
     },
     middleware::{
-        cache::CacheLayer, prompts::PromptLayer, rate_limit, request_context,
+        cache::CacheLayer, context_length, conversation, cost_cap,
+        differential, json_mode, latency_slo, prompts::PromptLayer, provenance,
+        rate_limit, request_context, script, wasm_filter,
     },
-    router::{meta::MIDDLEWARE_BUFFER_SIZE, strategy::RoutingStrategyService},
-    types::router::RouterId,
+    router::{
+        count_tokens,
+        direct::DirectProxiesWithoutMapper,
+        exclusion::ExclusionService,
+        meta::MIDDLEWARE_BUFFER_SIZE,
+        models,
+        residency::{self, Resident},
+        strategy::RoutingStrategyService,
+    },
+    types::{provider::InferenceProvider, router::RouterId},
     utils::handle_error::ErrorHandlerLayer,
 };
 
@@ -38,7 +51,13 @@ type InnerRouterService = BoxCloneService<
 /// middleware along with the routing strategy service.
 #[derive(Debug)]
 pub struct Router {
+    id: RouterId,
     inner: HashMap<EndpointType, InnerRouterService>,
+    /// Endpoints the router's data residency policy allows no provider for.
+    blocked: HashSet<EndpointType>,
+    /// Serves the token counting and model list endpoints, which are
+    /// answered by the gateway rather than balanced over the providers.
+    meta_endpoints: InnerRouterService,
     pub(crate) router_config: Arc<RouterConfig>,
 }
 
@@ -49,6 +68,10 @@ impl Router {
         app_state: AppState,
     ) -> Result<Self, InitError> {
         router_config.validate()?;
+        let Resident {
+            router_config,
+            blocked,
+        } = residency::apply(&id, router_config, &app_state.config().providers);
 
         let mut inner = HashMap::default();
         let rl_layer = rate_limit::Layer::per_router(
@@ -57,8 +80,20 @@ impl Router {
             &router_config,
         )
         .await?;
+        let wasm_filter_layer = wasm_filter::Layer::for_router(&router_config)?;
+        let script_layer = script::Layer::for_router(&router_config)?;
         let prompt_layer = PromptLayer::new(&app_state)?;
+        let conversation_layer =
+            conversation::Layer::for_router(&app_state, &router_config)?;
         let cache_layer = CacheLayer::for_router(&app_state, &router_config)?;
+        let latency_slo_layer = latency_slo::Layer::for_router(&router_config);
+        let differential_layer =
+            differential::Layer::for_router(&app_state, &router_config);
+        let json_mode_layer = json_mode::Layer::for_router(&router_config);
+        let provenance_layer = provenance::Layer::for_router(&router_config);
+        let context_length_layer =
+            context_length::Layer::for_router(&app_state, &router_config);
+        let cost_cap_layer = cost_cap::Layer::new(&app_state);
         let request_context_layer =
             request_context::Layer::for_router(router_config.clone());
         for (endpoint_type, balance_config) in
@@ -71,12 +106,29 @@ impl Router {
                 balance_config,
             )
             .await?;
+            let routing_strategy = ExclusionService::new(
+                &app_state,
+                &id,
+                &router_config,
+                balance_config,
+                routing_strategy,
+            )
+            .await?;
             let service_stack = ServiceBuilder::new()
                 .layer(ErrorHandlerLayer::new(app_state.clone()))
+                .option_layer(provenance_layer.clone())
+                .option_layer(wasm_filter_layer.clone())
+                .option_layer(script_layer.clone())
                 .layer(prompt_layer.clone())
+                .option_layer(conversation_layer.clone())
                 .layer(cache_layer.clone())
                 .layer(ErrorHandlerLayer::new(app_state.clone()))
                 .layer(rl_layer.clone())
+                .option_layer(latency_slo_layer.clone())
+                .option_layer(differential_layer.clone())
+                .option_layer(json_mode_layer.clone())
+                .option_layer(context_length_layer.clone())
+                .layer(cost_cap_layer.clone())
                 .map_err(|e| ApiError::from(InternalError::BufferError(e)))
                 .layer(buffer::BufferLayer::new(MIDDLEWARE_BUFFER_SIZE))
                 .layer(request_context_layer.clone())
@@ -85,10 +137,22 @@ impl Router {
             inner.insert(*endpoint_type, BoxCloneService::new(service_stack));
         }
 
+        let meta_endpoints = ServiceBuilder::new()
+            .layer(ErrorHandlerLayer::new(app_state.clone()))
+            .layer(rl_layer)
+            .service(MetaEndpoints {
+                app_state: app_state.clone(),
+                direct_proxies: app_state.direct_proxies().await?,
+                providers: router_config.load_balance.providers(),
+            });
+
         tracing::info!(id = %id, "router created");
 
         Ok(Self {
+            id,
             inner,
+            blocked,
+            meta_endpoints: BoxCloneService::new(meta_endpoints),
             router_config,
         })
     }
@@ -104,7 +168,7 @@ impl tower::Service<crate::types::request::Request> for Router {
         &mut self,
         ctx: &mut Context<'_>,
     ) -> Poll<Result<(), Self::Error>> {
-        let mut any_pending = false;
+        let mut any_pending = self.meta_endpoints.poll_ready(ctx).is_pending();
         for balancer in self.inner.values_mut() {
             if balancer.poll_ready(ctx).is_pending() {
                 any_pending = true;
@@ -135,9 +199,31 @@ impl tower::Service<crate::types::request::Request> for Router {
             };
         };
 
-        let api_endpoint = ApiEndpoint::new(extracted_path_and_query.path());
+        let path = extracted_path_and_query.path();
+        if MetaEndpoints::serves(req.method(), path) {
+            return ResponseFuture::Inner {
+                future: self.meta_endpoints.call(req),
+            };
+        }
+
+        let api_endpoint = ApiEndpoint::new(path);
         if let Some(api_endpoint) = api_endpoint {
             let endpoint_type = api_endpoint.endpoint_type();
+            if self.blocked.contains(&endpoint_type) {
+                tracing::info!(
+                    router_id = %self.id,
+                    endpoint = endpoint_type.as_ref(),
+                    "data residency policy rejected request"
+                );
+                let api_error = ApiError::InvalidRequest(
+                    InvalidRequestError::DataResidencyViolated(
+                        endpoint_type.as_ref().to_string(),
+                    ),
+                );
+                return ResponseFuture::Ready {
+                    response: Some(api_error.into_response()),
+                };
+            }
             if let Some(balancer) = self.inner.get_mut(&endpoint_type) {
                 req.extensions_mut().insert(api_endpoint);
                 ResponseFuture::Inner {
@@ -166,6 +252,57 @@ impl tower::Service<crate::types::request::Request> for Router {
     }
 }
 
+/// The token counting and model list endpoints of a router, limited to the
+/// providers it balances over.
+#[derive(Debug, Clone)]
+struct MetaEndpoints {
+    app_state: AppState,
+    direct_proxies: DirectProxiesWithoutMapper,
+    providers: IndexSet<InferenceProvider>,
+}
+
+impl MetaEndpoints {
+    fn serves(method: &Method, path: &str) -> bool {
+        count_tokens::is_count_tokens_path(path)
+            || (method == Method::GET && models::is_models_path(path))
+    }
+}
+
+impl tower::Service<crate::types::request::Request> for MetaEndpoints {
+    type Response = crate::types::response::Response;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        _ctx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: crate::types::request::Request) -> Self::Future {
+        let providers = Some(self.providers.clone());
+        let is_count_tokens =
+            req.extensions().get::<PathAndQuery>().is_some_and(|path| {
+                count_tokens::is_count_tokens_path(path.path())
+            });
+        if is_count_tokens {
+            count_tokens::count_tokens(
+                req,
+                self.direct_proxies.clone(),
+                providers,
+            )
+        } else {
+            models::list_models(
+                req,
+                self.app_state.clone(),
+                self.direct_proxies.clone(),
+                providers,
+            )
+        }
+    }
+}
+
 pin_project! {
     #[project = ResponseFutureProj]
     pub enum ResponseFuture
@@ -198,3 +335,62 @@ impl Future for ResponseFuture {
         }
     }
 }
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use compact_str::CompactString;
+    use http_body_util::BodyExt;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        config::{Config, router::RouterConfigs},
+        tests::TestDefault,
+        utils::tokenizer::TOKEN_COUNT_ESTIMATED_HEADER,
+    };
+
+    #[tokio::test]
+    async fn routers_discovered_outside_the_config_count_tokens() {
+        let app = crate::app::App::new(Config::test_default())
+            .await
+            .expect("failed to create app");
+        // routers of cloud deployments are read from the database, so they
+        // aren't in the config
+        let router_config = RouterConfigs::test_default()
+            .as_ref()
+            .values()
+            .next()
+            .cloned()
+            .unwrap();
+        let router_id = RouterId::Named(CompactString::new("db-router"));
+        assert!(!app.state.config().routers.as_ref().contains_key(&router_id));
+        let router =
+            Router::new(router_id, Arc::new(router_config), app.state.clone())
+                .await
+                .unwrap();
+
+        let body = serde_json::json!({
+            "model": "anthropic/claude-3-5-haiku",
+            "messages": [{"role": "user", "content": "hello"}],
+        });
+        let req = http::Request::builder()
+            .method(Method::POST)
+            .uri("http://localhost/router/db-router/v1/messages/count_tokens")
+            .extension(PathAndQuery::from_static("/v1/messages/count_tokens"))
+            .body(axum_core::body::Body::from(body.to_string()))
+            .unwrap();
+        let response = router.oneshot(req).await.unwrap();
+
+        assert_eq!(response.status(), http::StatusCode::OK);
+        // the router only balances over OpenAI, so the count isn't proxied
+        // to Anthropic
+        assert!(
+            response
+                .headers()
+                .contains_key(TOKEN_COUNT_ESTIMATED_HEADER)
+        );
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        let body = serde_json::from_slice::<serde_json::Value>(&body).unwrap();
+        assert!(body["input_tokens"].as_u64().is_some_and(|n| n > 0));
+    }
+}
@@ -0,0 +1,222 @@
+//! Storage of the conversations continued with `previous_response_id`.
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use meltdown::Token;
+use redis::AsyncCommands;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sqlx::PgPool;
+use tracing::{debug, error, info};
+
+use crate::{
+    app_state::AppState,
+    error::{internal::InternalError, runtime::RuntimeError},
+};
+
+/// The messages of a conversation so far, and the API key which may continue
+/// it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Conversation {
+    /// Hash of the API key which created the conversation, if the request
+    /// was authenticated.
+    pub owner: Option<String>,
+    pub messages: Vec<Value>,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+struct DbConversation {
+    owner_key_hash: Option<String>,
+    messages: Value,
+}
+
+#[derive(Clone)]
+pub enum ConversationStore {
+    /// Reconnects on its own if the connection is lost.
+    Redis(redis::aio::ConnectionManager),
+    Postgres(PgPool),
+}
+
+impl std::fmt::Debug for ConversationStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Redis(_) => f.write_str("ConversationStore::Redis"),
+            Self::Postgres(_) => f.write_str("ConversationStore::Postgres"),
+        }
+    }
+}
+
+impl ConversationStore {
+    pub async fn get(
+        &self,
+        id: &str,
+    ) -> Result<Option<Conversation>, InternalError> {
+        match self {
+            Self::Redis(conn) => {
+                let value: Option<String> = conn
+                    .clone()
+                    .get(redis_key(id))
+                    .await
+                    .map_err(InternalError::RedisError)?;
+                value
+                    .map(|value| serde_json::from_str(&value))
+                    .transpose()
+                    .map_err(|error| InternalError::Deserialize {
+                        ty: "Conversation",
+                        error,
+                    })
+            }
+            Self::Postgres(pool) => {
+                let conversation = sqlx::query_as::<_, DbConversation>(
+                    r"SELECT owner_key_hash, messages
+                      FROM gateway_conversations
+                      WHERE id = $1 AND expires_at > now()",
+                )
+                .bind(id)
+                .fetch_optional(pool)
+                .await?;
+                conversation
+                    .map(|conversation| {
+                        Ok(Conversation {
+                            owner: conversation.owner_key_hash,
+                            messages: serde_json::from_value(
+                                conversation.messages,
+                            )?,
+                        })
+                    })
+                    .transpose()
+                    .map_err(|error| InternalError::Deserialize {
+                        ty: "Conversation",
+                        error,
+                    })
+            }
+        }
+    }
+
+    pub async fn put(
+        &self,
+        id: &str,
+        conversation: &Conversation,
+        ttl: Duration,
+    ) -> Result<(), InternalError> {
+        match self {
+            Self::Redis(conn) => {
+                let value =
+                    serde_json::to_string(conversation).map_err(|error| {
+                        InternalError::Serialize {
+                            ty: "Conversation",
+                            error,
+                        }
+                    })?;
+                let _: () = conn
+                    .clone()
+                    .set_ex(redis_key(id), value, ttl.as_secs().max(1))
+                    .await
+                    .map_err(InternalError::RedisError)?;
+                Ok(())
+            }
+            Self::Postgres(pool) => {
+                let expires_at = chrono::Duration::from_std(ttl)
+                    .ok()
+                    .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+                    .unwrap_or(DateTime::<Utc>::MAX_UTC);
+                sqlx::query(
+                    r"INSERT INTO gateway_conversations
+                          (id, owner_key_hash, messages, expires_at)
+                      VALUES ($1, $2, $3, $4)
+                      ON CONFLICT (id) DO UPDATE
+                      SET owner_key_hash = EXCLUDED.owner_key_hash,
+                          messages = EXCLUDED.messages,
+                          expires_at = EXCLUDED.expires_at",
+                )
+                .bind(id)
+                .bind(&conversation.owner)
+                .bind(Value::from(conversation.messages.clone()))
+                .bind(expires_at)
+                .execute(pool)
+                .await?;
+                Ok(())
+            }
+        }
+    }
+
+    /// Deletes expired conversations, returning how many were deleted.
+    /// Redis expires conversations by itself.
+    pub async fn delete_expired(&self) -> Result<u64, InternalError> {
+        match self {
+            Self::Redis(_) => Ok(0),
+            Self::Postgres(pool) => {
+                let result = sqlx::query(
+                    "DELETE FROM gateway_conversations WHERE expires_at <= \
+                     now()",
+                )
+                .execute(pool)
+                .await?;
+                Ok(result.rows_affected())
+            }
+        }
+    }
+}
+
+/// Periodically deletes expired conversations from Postgres.
+///
+/// Since the table is shared by every replica, only the leader deletes them.
+#[derive(Debug)]
+pub struct ConversationReaper {
+    app_state: AppState,
+    store: ConversationStore,
+    interval: Duration,
+}
+
+impl ConversationReaper {
+    #[must_use]
+    pub fn new(
+        app_state: AppState,
+        store: ConversationStore,
+        interval: Duration,
+    ) -> Self {
+        Self {
+            app_state,
+            store,
+            interval,
+        }
+    }
+
+    async fn reap(&self) {
+        if !self.app_state.0.leadership.is_leader() {
+            debug!("not the leader, skipping expired conversation cleanup");
+            return;
+        }
+        match self.store.delete_expired().await {
+            Ok(deleted) => debug!(deleted, "deleted expired conversations"),
+            Err(e) => {
+                error!(error = %e, "failed to delete expired conversations")
+            }
+        }
+    }
+}
+
+impl meltdown::Service for ConversationReaper {
+    type Future = BoxFuture<'static, Result<(), RuntimeError>>;
+
+    fn run(self, mut token: Token) -> Self::Future {
+        Box::pin(async move {
+            let mut interval = tokio::time::interval(self.interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => self.reap().await,
+                    () = &mut token => {
+                        info!(name = "conversation-reaper", "task shutting down");
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+fn redis_key(id: &str) -> String {
+    format!("conversation:{id}")
+}
@@ -19,8 +19,11 @@ use crate::{
     control_plane::types::Key,
     error::{init::InitError, internal::InternalError, runtime::RuntimeError},
     router::service::Router,
-    store::router::RouterStore,
-    types::{org::OrgId, router::RouterId, user::UserId},
+    store::{
+        model_mapping::{DbModelMapping, ModelMappingStore},
+        router::RouterStore,
+    },
+    types::{org::OrgId, priority::Priority, router::RouterId, user::UserId},
 };
 
 /// A database listener service that handles LISTEN/NOTIFY functionality.
@@ -30,6 +33,7 @@ pub struct DatabaseListener {
     app_state: AppState,
     pg_listener: PgListener,
     router_store: RouterStore,
+    model_mapping_store: ModelMappingStore,
     tx: Sender<Change<RouterId, Router>>,
     /// Track last seen router config versions to detect missed events
     last_router_config_versions: HashMap<String, DateTime<Utc>>,
@@ -72,6 +76,14 @@ enum ConnectedCloudGatewaysNotification {
         organization_id: OrgId,
         api_key_hash: String,
         soft_delete: bool,
+        #[serde(default)]
+        max_priority: Option<Priority>,
+        op: Op,
+    },
+    ModelMappingUpdated {
+        router_hash: String,
+        source_model: String,
+        targets: serde_json::Value,
         op: Op,
     },
     Unknown {
@@ -127,10 +139,14 @@ impl DatabaseListener {
             .ok_or(InitError::StoreNotConfigured("router_store"))?
             .clone();
 
+        let model_mapping_store =
+            ModelMappingStore::new(router_store.pool.clone());
+
         Ok(Self {
             app_state,
             pg_listener,
             router_store,
+            model_mapping_store,
             tx,
             last_router_config_versions: HashMap::default(),
             last_api_key_created_at: HashMap::default(),
@@ -192,6 +208,8 @@ impl DatabaseListener {
                             organization_id: OrgId::new(
                                 api_key.organization_id,
                             ),
+                            tier: None,
+                            max_priority: api_key.max_priority(),
                         })
                         .await?;
                     self.last_api_key_created_at
@@ -260,6 +278,16 @@ impl DatabaseListener {
             }
         }
 
+        // mappings are few, so they're reloaded in full, which also catches
+        // up on missed deletions
+        let model_mappings =
+            self.model_mapping_store.all().await.inspect_err(|e| {
+                error!(error = %e, "failed to poll model mappings");
+            })?;
+        self.app_state.0.model_mappings.replace(
+            model_mappings.into_iter().filter_map(DbModelMapping::parse),
+        );
+
         let end = Utc::now();
         self.last_poll_time = Some(end);
         info!(
@@ -500,6 +528,7 @@ impl DatabaseListener {
                     organization_id,
                     api_key_hash,
                     soft_delete,
+                    max_priority,
                     op,
                 } => match op {
                     Op::Insert => {
@@ -508,6 +537,8 @@ impl DatabaseListener {
                                 key_hash: api_key_hash.clone(),
                                 owner_id,
                                 organization_id,
+                                tier: None,
+                                max_priority,
                             })
                             .await
                             .map_err(|e| {
@@ -562,6 +593,20 @@ impl DatabaseListener {
                             // Remove from state tracking when soft deleted
                             self.last_api_key_created_at.remove(&api_key_hash);
                         } else {
+                            // the key's scope may have changed
+                            self.app_state
+                                .set_helicone_api_key(Key {
+                                    key_hash: api_key_hash.clone(),
+                                    owner_id,
+                                    organization_id,
+                                    tier: None,
+                                    max_priority,
+                                })
+                                .await
+                                .map_err(|e| {
+                                    error!(error = %e, "failed to set helicone api key");
+                                    e
+                                })?;
                             // Update state tracking for non-soft-delete updates
                             self.last_api_key_created_at
                                 .insert(api_key_hash, Utc::now());
@@ -573,6 +618,46 @@ impl DatabaseListener {
                         Ok(())
                     }
                 },
+                ConnectedCloudGatewaysNotification::ModelMappingUpdated {
+                    router_hash,
+                    source_model,
+                    targets,
+                    op,
+                } => {
+                    match op {
+                        Op::Insert | Op::Update => {
+                            let mapping = DbModelMapping {
+                                router_hash,
+                                source_model,
+                                targets,
+                                updated_at: Utc::now(),
+                            };
+                            if let Some(mapping) = mapping.parse() {
+                                info!(
+                                    source_model = %mapping.source_model,
+                                    "model mapping updated"
+                                );
+                                self.app_state.0.model_mappings.set(mapping);
+                            }
+                        }
+                        Op::Delete => {
+                            let router = (!router_hash.is_empty())
+                                .then(|| RouterId::Named(router_hash.into()));
+                            self.app_state
+                                .0
+                                .model_mappings
+                                .remove(router.as_ref(), &source_model);
+                            info!(
+                                source_model = %source_model,
+                                "model mapping removed"
+                            );
+                        }
+                        Op::Truncate => {
+                            self.app_state.0.model_mappings.replace([]);
+                        }
+                    }
+                    Ok(())
+                }
                 ConnectedCloudGatewaysNotification::Unknown { data } => {
                     debug!("Unknown notification event");
                     debug!("data: {:?}", data);
@@ -0,0 +1,145 @@
+//! Leader election between gateway replicas sharing a database.
+//!
+//! The leader is whichever replica holds a session level Postgres advisory
+//! lock. Since the lock is released as soon as the holding connection is
+//! closed, a crashed or partitioned leader is replaced automatically.
+use std::sync::{
+    Arc,
+    atomic::{AtomicBool, Ordering},
+};
+
+use futures::future::BoxFuture;
+use meltdown::Token;
+use sqlx::{Connection, PgConnection};
+use tokio::time::{MissedTickBehavior, interval};
+use tracing::{debug, error, info, warn};
+
+use crate::{
+    app_state::AppState, config::leader_election::LeaderElectionConfig,
+    error::runtime::RuntimeError,
+};
+
+/// Whether this replica is currently the leader.
+#[derive(Debug, Clone)]
+pub struct Leadership(Arc<AtomicBool>);
+
+impl Leadership {
+    /// Leadership of a replica which doesn't take part in leader election,
+    /// and therefore always leads.
+    #[must_use]
+    pub fn unelected() -> Self {
+        Self(Arc::new(AtomicBool::new(true)))
+    }
+
+    /// Leadership of a replica which takes part in leader election, and
+    /// therefore follows until it is elected.
+    #[must_use]
+    pub fn elected() -> Self {
+        Self(Arc::new(AtomicBool::new(false)))
+    }
+
+    #[must_use]
+    pub fn is_leader(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+
+    /// Returns whether leadership changed.
+    fn set_leader(&self, is_leader: bool) -> bool {
+        self.0.swap(is_leader, Ordering::AcqRel) != is_leader
+    }
+}
+
+/// Background service which campaigns for, and holds on to, leadership.
+#[derive(Debug)]
+pub struct LeaderElector {
+    app_state: AppState,
+    config: LeaderElectionConfig,
+}
+
+impl LeaderElector {
+    #[must_use]
+    pub fn new(app_state: AppState, config: LeaderElectionConfig) -> Self {
+        Self { app_state, config }
+    }
+
+    async fn campaign(&self) -> Result<(), sqlx::Error> {
+        let leadership = &self.app_state.0.leadership;
+        let mut connection = PgConnection::connect(
+            self.app_state.config().database.url.expose(),
+        )
+        .await?;
+        let mut renew_interval = interval(self.config.renew_interval);
+        renew_interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            renew_interval.tick().await;
+            if leadership.is_leader() {
+                // the lock is held for as long as the connection is alive
+                connection.ping().await?;
+            } else {
+                let acquired = sqlx::query_scalar::<_, bool>(
+                    "SELECT pg_try_advisory_lock($1)",
+                )
+                .bind(self.config.lock_id)
+                .fetch_one(&mut connection)
+                .await?;
+                if acquired && leadership.set_leader(true) {
+                    info!(lock_id = self.config.lock_id, "elected leader");
+                }
+            }
+        }
+    }
+
+    pub async fn run_forever(self) -> Result<(), RuntimeError> {
+        loop {
+            if let Err(e) = self.campaign().await {
+                error!(error = %e, "leader election connection failed");
+            }
+            // the connection was dropped, and the lock along with it
+            if self.app_state.0.leadership.set_leader(false) {
+                warn!("lost leadership");
+            }
+            tokio::time::sleep(self.config.renew_interval).await;
+        }
+    }
+}
+
+impl meltdown::Service for LeaderElector {
+    type Future = BoxFuture<'static, Result<(), RuntimeError>>;
+
+    fn run(self, mut token: Token) -> Self::Future {
+        Box::pin(async move {
+            tokio::select! {
+                result = self.run_forever() => {
+                    if let Err(e) = result {
+                        error!(name = "leader-election-task", error = ?e, "Leader election encountered error, shutting down");
+                    } else {
+                        debug!(name = "leader-election-task", "Leader election shut down successfully");
+                    }
+                    token.trigger();
+                }
+                () = &mut token => {
+                    debug!(name = "leader-election-task", "task shut down successfully");
+                }
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leadership_transitions() {
+        let leadership = Leadership::elected();
+        assert!(!leadership.is_leader());
+        assert!(leadership.set_leader(true));
+        assert!(leadership.is_leader());
+        assert!(!leadership.set_leader(true));
+        assert!(leadership.set_leader(false));
+        assert!(!leadership.is_leader());
+
+        assert!(Leadership::unelected().is_leader());
+    }
+}
@@ -1,5 +1,6 @@
-use std::time::Duration;
+use std::{path::Path, time::Duration};
 
+use base64::Engine;
 use bytes::Bytes;
 use reqwest::Client;
 use rusty_s3::{
@@ -7,18 +8,155 @@ use rusty_s3::{
     actions::{GetObject, PutObject},
 };
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use url::Url;
 use uuid::Uuid;
 
 use crate::{
     app_state::AppState,
-    config::minio::Config,
+    config::{logger::LoggerConfig, minio::Config},
     error::{init::InitError, logger::LoggerError, prompts::PromptError},
-    logger::service::JawnClient,
-    types::{extensions::AuthContext, logger::S3Log, response::JawnResponse},
+    logger::{
+        service::JawnClient,
+        spill::{CollectedBody, SpilledPayload},
+    },
+    types::{
+        extensions::AuthContext, logger::S3Log, org::OrgId,
+        response::JawnResponse,
+    },
 };
 
 const DEFAULT_MINIO_TIMEOUT: Duration = Duration::from_secs(10);
+/// S3 rejects uploads whose body doesn't match this checksum, and returns the
+/// checksum of stored objects when [`CHECKSUM_MODE_HEADER`] is enabled.
+///
+/// Both are only sent on urls the gateway signs itself, since S3 rejects
+/// `x-amz-*` headers a presigned url wasn't signed with, and Jawn doesn't
+/// sign them.
+const CHECKSUM_HEADER: &str = "x-amz-checksum-sha256";
+const CHECKSUM_MODE_HEADER: &str = "x-amz-checksum-mode";
+
+/// The SHA-256 checksum of a stored payload, base64 encoded as S3 expects.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum(String);
+
+impl Checksum {
+    #[must_use]
+    pub fn sha256(payload: &[u8]) -> Self {
+        Self::from_digest(Sha256::new_with_prefix(payload))
+    }
+
+    /// The checksum of a payload hashed incrementally.
+    #[must_use]
+    pub fn from_digest(digest: Sha256) -> Self {
+        Self(
+            base64::engine::general_purpose::STANDARD.encode(digest.finalize()),
+        )
+    }
+
+    #[must_use]
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Verifies `payload` against the checksum S3 returned for it.
+    ///
+    /// Checksums of multipart uploads are composite checksums of their parts,
+    /// which can't be verified against the whole payload, so they're
+    /// skipped.
+    fn verify(payload: &[u8], stored: &str) -> bool {
+        stored.contains('-') || Self::sha256(payload).as_str() == stored
+    }
+}
+
+/// The request and response bodies of a log, as stored in object storage.
+#[derive(Debug)]
+pub enum Payload {
+    Memory { payload: Bytes, checksum: Checksum },
+    Spilled(SpilledPayload),
+}
+
+impl Payload {
+    /// Serializes the bodies, on disk if the response body was spilled.
+    pub async fn new(
+        config: &LoggerConfig,
+        request_id: Uuid,
+        request_body: Bytes,
+        response_body: CollectedBody,
+    ) -> Result<Self, LoggerError> {
+        match response_body {
+            CollectedBody::Memory(response_body) => {
+                let request_body = String::from_utf8(request_body.to_vec())?;
+                let response_body = String::from_utf8(response_body.to_vec())?;
+                let s3_log = S3Log::new(request_body, response_body);
+                let payload = serde_json::to_vec(&s3_log).map_err(|e| {
+                    tracing::error!(error = %e, "failed to serialize s3 log");
+                    LoggerError::InvalidLogMessage
+                })?;
+                Ok(Self::from_bytes(payload.into()))
+            }
+            CollectedBody::Spilled(response_body) => {
+                let payload = SpilledPayload::write(
+                    &config.spill_dir(),
+                    request_id,
+                    &request_body,
+                    response_body,
+                )
+                .await?;
+                Ok(Self::Spilled(payload))
+            }
+        }
+    }
+
+    /// A payload which was already serialized.
+    #[must_use]
+    pub fn from_bytes(payload: Bytes) -> Self {
+        let checksum = Checksum::sha256(&payload);
+        Self::Memory { payload, checksum }
+    }
+
+    #[must_use]
+    pub fn len(&self) -> u64 {
+        match self {
+            Self::Memory { payload, .. } => payload.len() as u64,
+            Self::Spilled(payload) => payload.len(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    #[must_use]
+    pub fn checksum(&self) -> Checksum {
+        match self {
+            Self::Memory { checksum, .. } => checksum.clone(),
+            Self::Spilled(payload) => payload.checksum.clone(),
+        }
+    }
+
+    /// Writes the payload to `path`.
+    pub async fn persist(&self, path: &Path) -> std::io::Result<()> {
+        match self {
+            Self::Memory { payload, .. } => {
+                tokio::fs::write(path, payload).await
+            }
+            Self::Spilled(payload) => payload.copy_to(path).await,
+        }
+    }
+
+    async fn body(&self) -> Result<reqwest::Body, LoggerError> {
+        match self {
+            Self::Memory { payload, .. } => {
+                Ok(reqwest::Body::from(payload.clone()))
+            }
+            Self::Spilled(payload) => {
+                Ok(reqwest::Body::wrap_stream(payload.stream().await?))
+            }
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct BaseMinioClient {
@@ -81,7 +219,7 @@ const GET_OBJECT_SIGN_DURATION: Duration = Duration::from_secs(120);
 #[serde(rename_all = "camelCase")]
 struct SignedUrlRequest {
     request_id: Uuid,
-    payload_size: usize,
+    payload_size: u64,
 }
 
 #[derive(Debug, Serialize)]
@@ -113,30 +251,43 @@ impl<'a> MinioClient<'a> {
         Self::SignedByJawn(jawn_client)
     }
 
+    /// The client for the deployment target of `app_state`.
+    #[must_use]
+    pub fn new(app_state: &'a AppState) -> Self {
+        if app_state.config().deployment_target.is_cloud() {
+            Self::cloud(&app_state.0.minio)
+        } else {
+            Self::sidecar(&app_state.0.jawn_http_client)
+        }
+    }
+
+    /// Stores the request and response bodies of a log.
     #[tracing::instrument(skip_all)]
-    pub async fn log_bodies(
+    pub async fn upload(
         &self,
         app_state: &AppState,
-        auth_ctx: &AuthContext,
+        org_id: &OrgId,
+        api_key: &str,
         request_id: Uuid,
-        request_body: Bytes,
-        response_body: Bytes,
+        payload: &Payload,
     ) -> Result<(), LoggerError> {
-        let (signed_url, s3_log) = match self {
+        let payload_size = payload.len();
+        let checksum = payload.checksum();
+        let signed_url = match self {
             Self::SelfSigned(minio) => {
                 let object_path = format!(
                     "organizations/{}/requests/{}/raw_request_response_body",
-                    auth_ctx.org_id.as_ref(),
+                    org_id.as_ref(),
                     request_id
                 );
-                let action = minio.put_object(&object_path);
+                let mut action = minio.put_object(&object_path);
+                action
+                    .headers_mut()
+                    .insert(CHECKSUM_HEADER, checksum.as_str());
                 let signed_url = action.sign(PUT_OBJECT_SIGN_DURATION);
-                let request_body = String::from_utf8(request_body.to_vec())?;
-                let response_body = String::from_utf8(response_body.to_vec())?;
 
                 tracing::trace!("got signed url for self hosted minio");
-                let s3_log = S3Log::new(request_body, response_body);
-                (signed_url, s3_log)
+                signed_url
             }
             Self::SignedByJawn(client) => {
                 let signed_request_url =
@@ -145,23 +296,15 @@ impl<'a> MinioClient<'a> {
                         .helicone
                         .base_url
                         .join("/v1/router/control-plane/sign-s3-url")?;
-                let request_body = String::from_utf8(request_body.to_vec())?;
-                let response_body = String::from_utf8(response_body.to_vec())?;
-
-                let s3_log = S3Log::new(request_body, response_body);
-                let bytes = serde_json::to_vec(&s3_log).map_err(|e| {
-                    tracing::error!(error = %e, "failed to serialize s3 log");
-                    LoggerError::InvalidLogMessage
-                })?;
 
                 let signed_url = client
                   .request_client
                   .post(signed_request_url)
-                  .json(&SignedUrlRequest { request_id, payload_size: bytes.len() })
-                  .header(
-                    "authorization",
-                    format!("Bearer {}", auth_ctx.api_key.expose()),
-                  )
+                  .json(&SignedUrlRequest {
+                    request_id,
+                    payload_size,
+                  })
+                  .header("authorization", format!("Bearer {api_key}"))
                   .send()
                   .await
                   .map_err(|e| {
@@ -183,16 +326,22 @@ impl<'a> MinioClient<'a> {
                 })?;
                 tracing::trace!("got signed url for sidecar");
 
-                (signed_url.url, s3_log)
+                signed_url.url
             }
         };
 
-        let _resp = app_state
+        let mut request = app_state
             .0
             .minio
             .client
             .put(signed_url)
-            .json(&s3_log)
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(http::header::CONTENT_LENGTH, payload_size);
+        if let Self::SelfSigned(_) = self {
+            request = request.header(CHECKSUM_HEADER, checksum.as_str());
+        }
+        let _resp = request
+            .body(payload.body().await?)
             .send()
             .await
             .map_err(|e| {
@@ -224,7 +373,8 @@ impl<'a> MinioClient<'a> {
 
         let signed_url = match self {
             Self::SelfSigned(minio) => {
-                let action = minio.get_object(&object_path);
+                let mut action = minio.get_object(&object_path);
+                action.headers_mut().insert(CHECKSUM_MODE_HEADER, "ENABLED");
                 action.sign(GET_OBJECT_SIGN_DURATION)
             }
             Self::SignedByJawn(client) => {
@@ -271,11 +421,11 @@ impl<'a> MinioClient<'a> {
             }
         };
 
-        let response = app_state
-            .0
-            .minio
-            .client
-            .get(signed_url)
+        let mut request = app_state.0.minio.client.get(signed_url);
+        if let Self::SelfSigned(_) = self {
+            request = request.header(CHECKSUM_MODE_HEADER, "ENABLED");
+        }
+        let response = request
             .send()
             .await
             .map_err(|e| {
@@ -288,10 +438,24 @@ impl<'a> MinioClient<'a> {
                 PromptError::FailedToGetPromptBody(e)
             })?;
 
+        let stored_checksum = response
+            .headers()
+            .get(CHECKSUM_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(ToString::to_string);
         let response_bytes = response.bytes().await.map_err(|e| {
             tracing::error!(error = %e, "failed to read prompt body bytes");
             PromptError::FailedToGetPromptBody(e)
         })?;
+        if let Some(stored_checksum) = stored_checksum
+            && !Checksum::verify(&response_bytes, &stored_checksum)
+        {
+            tracing::error!(
+                object_path = %object_path,
+                "prompt body does not match its stored checksum"
+            );
+            return Err(PromptError::ChecksumMismatch);
+        }
 
         serde_json::from_slice(&response_bytes).map_err(|e| {
             tracing::error!(error = %e, "failed to deserialize prompt body JSON");
@@ -299,3 +463,18 @@ impl<'a> MinioClient<'a> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncated_payloads_fail_verification() {
+        let payload = br#"{"request":"{}","response":"{}"}"#;
+        let checksum = Checksum::sha256(payload);
+        assert_eq!(checksum.as_str().len(), 44);
+        assert!(Checksum::verify(payload, checksum.as_str()));
+        assert!(!Checksum::verify(&payload[..10], checksum.as_str()));
+        assert!(Checksum::verify(&payload[..10], "c2hhMjU2-2"));
+    }
+}
@@ -2,9 +2,13 @@ use sqlx::{PgPool, postgres::PgPoolOptions};
 
 use crate::{config::database::DatabaseConfig, error::init::InitError};
 
+pub mod conversation;
 pub mod db_listener;
+pub mod leader;
 pub mod minio;
+pub mod model_mapping;
 pub mod router;
+pub mod usage;
 
 pub async fn connect(config: &DatabaseConfig) -> Result<PgPool, InitError> {
     let pool = PgPoolOptions::new()
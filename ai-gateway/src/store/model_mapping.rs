@@ -0,0 +1,113 @@
+//! Storage of the model mappings managed through the admin API, so that
+//! every replica of a cloud deployment maps models the same way without a
+//! config deployment.
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    error::internal::InternalError,
+    middleware::mapper::model::StoredModelMapping,
+    types::{model_id::ModelName, router::RouterId},
+};
+
+#[derive(Debug, Clone)]
+pub struct ModelMappingStore {
+    pool: PgPool,
+}
+
+#[derive(Debug, sqlx::FromRow)]
+pub struct DbModelMapping {
+    /// Empty for mappings of the default model mapping.
+    pub router_hash: String,
+    pub source_model: String,
+    pub targets: serde_json::Value,
+    pub updated_at: DateTime<Utc>,
+}
+
+impl DbModelMapping {
+    /// Parses the mapping, or returns `None` if its targets are invalid.
+    #[must_use]
+    pub fn parse(self) -> Option<StoredModelMapping> {
+        let targets = serde_json::from_value(self.targets)
+            .inspect_err(|e| {
+                tracing::error!(
+                    error = %e,
+                    router_hash = %self.router_hash,
+                    source_model = %self.source_model,
+                    "failed to parse stored model mapping"
+                );
+            })
+            .ok()?;
+        Some(StoredModelMapping {
+            router: (!self.router_hash.is_empty())
+                .then(|| RouterId::Named(self.router_hash.into())),
+            source_model: ModelName::owned(self.source_model),
+            targets,
+        })
+    }
+}
+
+fn router_hash(router: Option<&RouterId>) -> &str {
+    router.map_or("", AsRef::as_ref)
+}
+
+impl ModelMappingStore {
+    #[must_use]
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    pub async fn all(&self) -> Result<Vec<DbModelMapping>, InternalError> {
+        let mappings = sqlx::query_as::<_, DbModelMapping>(
+            r"SELECT router_hash, source_model, targets, updated_at
+              FROM gateway_model_mappings",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(mappings)
+    }
+
+    /// Adds `mapping`, replacing the targets of its source model if it's
+    /// already mapped.
+    pub async fn upsert(
+        &self,
+        mapping: &StoredModelMapping,
+    ) -> Result<(), InternalError> {
+        let targets = serde_json::to_value(&mapping.targets).map_err(|e| {
+            InternalError::Serialize {
+                ty: "StoredModelMapping",
+                error: e,
+            }
+        })?;
+        sqlx::query(
+            r"INSERT INTO gateway_model_mappings
+                  (router_hash, source_model, targets)
+              VALUES ($1, $2, $3)
+              ON CONFLICT (router_hash, source_model) DO UPDATE
+              SET targets = EXCLUDED.targets, updated_at = now()",
+        )
+        .bind(router_hash(mapping.router.as_ref()))
+        .bind(mapping.source_model.as_ref())
+        .bind(targets)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Deletes the mapping of `source_model`, returning whether it existed.
+    pub async fn delete(
+        &self,
+        router: Option<&RouterId>,
+        source_model: &str,
+    ) -> Result<bool, InternalError> {
+        let result = sqlx::query(
+            r"DELETE FROM gateway_model_mappings
+              WHERE router_hash = $1 AND source_model = $2",
+        )
+        .bind(router_hash(router))
+        .bind(source_model)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+}
@@ -1,4 +1,4 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, str::FromStr};
 
 use chrono::{DateTime, Utc};
 use rustc_hash::FxHashMap;
@@ -11,6 +11,7 @@ use crate::{
     error::{init::InitError, internal::InternalError},
     types::{
         org::OrgId,
+        priority::Priority,
         provider::{InferenceProvider, ProviderKey, ProviderKeyMap},
         secret::Secret,
         user::UserId,
@@ -40,6 +41,28 @@ pub struct DbApiKey {
     pub updated_at: Option<DateTime<Utc>>,
     #[sqlx(default)]
     pub soft_delete: Option<bool>,
+    /// The highest priority the key's requests may ask for, if scoped.
+    /// Selected from the row as json, so that keys are still read from
+    /// databases without the `max_priority` column.
+    #[sqlx(default)]
+    pub max_priority: Option<String>,
+}
+
+impl DbApiKey {
+    /// The highest priority the key's requests may ask for, if scoped to
+    /// a valid priority.
+    #[must_use]
+    pub fn max_priority(&self) -> Option<Priority> {
+        let max_priority = self.max_priority.as_deref()?;
+        Priority::from_str(max_priority)
+            .inspect_err(|_| {
+                warn!(
+                    max_priority,
+                    "ignoring invalid max priority of helicone api key"
+                );
+            })
+            .ok()
+    }
 }
 
 #[derive(Debug, sqlx::FromRow)]
@@ -107,9 +130,11 @@ impl RouterStore {
         let keys = res
             .into_iter()
             .map(|k| Key {
+                max_priority: k.max_priority(),
                 key_hash: k.key_hash,
                 owner_id: UserId::new(k.owner_id),
                 organization_id: OrgId::new(k.organization_id),
+                tier: None,
             })
             .collect();
 
@@ -124,7 +149,8 @@ impl RouterStore {
              helicone_api_keys.user_id as owner_id,
              helicone_api_keys.organization_id as organization_id,
              helicone_api_keys.created_at as created_at,
-             helicone_api_keys.updated_at as updated_at
+             helicone_api_keys.updated_at as updated_at,
+             to_jsonb(helicone_api_keys) ->> 'max_priority' as max_priority
              FROM helicone_api_keys
              WHERE helicone_api_keys.soft_delete = false",
         )
@@ -147,7 +173,8 @@ impl RouterStore {
              helicone_api_keys.organization_id as organization_id,
              helicone_api_keys.created_at as created_at,
              helicone_api_keys.updated_at as updated_at,
-             helicone_api_keys.soft_delete as soft_delete
+             helicone_api_keys.soft_delete as soft_delete,
+             to_jsonb(helicone_api_keys) ->> 'max_priority' as max_priority
              FROM helicone_api_keys
              WHERE helicone_api_keys.updated_at > $1 
              OR helicone_api_keys.created_at > $1",
@@ -0,0 +1,90 @@
+//! Storage of the usage of logged requests per provider, model and day.
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    error::internal::InternalError,
+    logger::usage::{Usage, UsageKey},
+};
+
+#[derive(Debug, Clone)]
+pub struct UsageStore {
+    pool: PgPool,
+}
+
+/// The usage of a provider's model on a day.
+#[derive(
+    Debug, Clone, PartialEq, Eq, Serialize, Deserialize, sqlx::FromRow,
+)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyUsage {
+    pub day: NaiveDate,
+    pub provider: String,
+    pub model: String,
+    pub requests: i64,
+    pub prompt_tokens: i64,
+    pub completion_tokens: i64,
+}
+
+impl UsageStore {
+    #[must_use]
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+
+    /// Adds `usage` to the stored usage, in a single transaction so that
+    /// usage is either added in full or not at all.
+    pub async fn add(
+        &self,
+        usage: impl IntoIterator<Item = (&UsageKey, &Usage)>,
+    ) -> Result<(), InternalError> {
+        let mut transaction = self.pool.begin().await?;
+        for (key, usage) in usage {
+            sqlx::query(
+                r"INSERT INTO gateway_usage_daily
+                      (day, provider, model, requests, prompt_tokens,
+                       completion_tokens)
+                  VALUES ($1, $2, $3, $4, $5, $6)
+                  ON CONFLICT (day, provider, model) DO UPDATE
+                  SET requests =
+                          gateway_usage_daily.requests + EXCLUDED.requests,
+                      prompt_tokens = gateway_usage_daily.prompt_tokens
+                          + EXCLUDED.prompt_tokens,
+                      completion_tokens =
+                          gateway_usage_daily.completion_tokens
+                          + EXCLUDED.completion_tokens",
+            )
+            .bind(key.day)
+            .bind(&key.provider)
+            .bind(&key.model)
+            .bind(i64::try_from(usage.requests).unwrap_or(i64::MAX))
+            .bind(i64::try_from(usage.prompt_tokens).unwrap_or(i64::MAX))
+            .bind(i64::try_from(usage.completion_tokens).unwrap_or(i64::MAX))
+            .execute(&mut *transaction)
+            .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Returns the usage from `from` to `to`, both inclusive.
+    pub async fn report(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<DailyUsage>, InternalError> {
+        let usage = sqlx::query_as::<_, DailyUsage>(
+            r"SELECT day, provider, model, requests, prompt_tokens,
+                     completion_tokens
+              FROM gateway_usage_daily
+              WHERE day >= $1 AND day <= $2
+              ORDER BY day, provider, model",
+        )
+        .bind(from)
+        .bind(to)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(usage)
+    }
+}
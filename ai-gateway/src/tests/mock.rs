@@ -6,13 +6,15 @@ use stubr::{
 };
 use typed_builder::TypedBuilder;
 use url::Url;
-use workspace_root::get_workspace_root;
 
 use crate::{config::Config, types::provider::InferenceProvider};
 
 fn get_stubs_path(provider: &str) -> String {
-    let workspace_root = get_workspace_root();
-    let stubs_path = workspace_root.join("ai-gateway/stubs").join(provider);
+    // relative to this crate rather than the workspace, so that the stubs
+    // are found by downstream crates depending on it as well
+    let stubs_path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("stubs")
+        .join(provider);
     stubs_path.to_string_lossy().to_string()
 }
 
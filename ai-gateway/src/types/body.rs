@@ -1,37 +1,49 @@
 use std::{
     convert::Infallible,
     pin::Pin,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+    },
     task::{Context, Poll},
+    time::{Duration, Instant},
 };
 
 pub use axum_core::body::Body;
 use bytes::{BufMut, Bytes, BytesMut};
 use futures::{Stream, StreamExt};
 use hyper::body::{Body as _, Frame, SizeHint};
+use opentelemetry::KeyValue;
 use tokio::sync::{
-    mpsc::{self, UnboundedReceiver},
+    mpsc::{self, Receiver},
     oneshot,
 };
 
-use crate::error::api::ApiError;
+use crate::{error::api::ApiError, metrics::Metrics};
+
+/// The number of chunks buffered for a sink which is behind the client,
+/// after which the client's stream waits for it.
+const SINK_CAPACITY: usize = 1024;
 
 /// Reads a stream of HTTP data frames as `Bytes` from a channel.
 #[derive(Debug)]
 pub struct BodyReader {
-    rx: UnboundedReceiver<Bytes>,
+    rx: Receiver<Bytes>,
     tfft_tx: Option<oneshot::Sender<()>>,
     is_end_stream: bool,
     size_hint: SizeHint,
     append_newlines: bool,
+    lag: SinkLag,
 }
 
 impl BodyReader {
     #[must_use]
     pub fn new(
-        rx: UnboundedReceiver<Bytes>,
+        rx: Receiver<Bytes>,
         tfft_tx: oneshot::Sender<()>,
         size_hint: SizeHint,
         append_newlines: bool,
+        lag: SinkLag,
     ) -> Self {
         Self {
             rx,
@@ -39,6 +51,7 @@ impl BodyReader {
             is_end_stream: false,
             size_hint,
             append_newlines,
+            lag,
         }
     }
 
@@ -48,23 +61,166 @@ impl BodyReader {
         stream: impl Stream<Item = Result<Bytes, ApiError>> + Send + 'static,
         append_newlines: bool,
     ) -> (axum_core::body::Body, BodyReader, oneshot::Receiver<()>) {
-        // unbounded channel is okay since we limit memory usage higher in the
-        // stack by limiting concurrency and request/response body size.
-        let (tx, rx) = mpsc::unbounded_channel();
+        let mut tee = BodyTee::new(stream);
+        let (response_body_for_logger, tfft_rx) =
+            tee.sink("logger", append_newlines);
+        (tee.into_body(), response_body_for_logger, tfft_rx)
+    }
+
+    /// How far this reader fell behind the client's stream.
+    #[must_use]
+    pub fn lag(&self) -> SinkLag {
+        self.lag.clone()
+    }
+}
+
+/// Broadcasts the chunks of a response body to the client and to any number
+/// of sinks, such as the logger, each reading them through a
+/// [`BodyReader`].
+///
+/// Chunks are shared rather than copied. Each sink buffers up to
+/// [`SINK_CAPACITY`] chunks, beyond which the client's stream waits for the
+/// sink to catch up, so that a slow sink can't grow the gateway's memory
+/// unboundedly. Sinks which are dropped stop receiving chunks.
+pub struct BodyTee<S> {
+    stream: S,
+    sinks: Vec<Sink>,
+}
+
+impl<S> BodyTee<S>
+where
+    S: Stream<Item = Result<Bytes, ApiError>> + Send + 'static,
+{
+    #[must_use]
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Adds a sink named `name`, which labels its lag metrics. The receiver
+    /// is signalled once the sink reads its first chunk.
+    pub fn sink(
+        &mut self,
+        name: &'static str,
+        append_newlines: bool,
+    ) -> (BodyReader, oneshot::Receiver<()>) {
+        let (tx, rx) = mpsc::channel(SINK_CAPACITY);
         let (tfft_tx, tfft_rx) = oneshot::channel();
-        let s = stream.map(move |b| {
-            if let Ok(b) = &b {
-                if let Err(e) = tx.send(b.clone()) {
-                    tracing::error!(error = %e, "BodyReader dropped before stream ended");
-                }
-            }
-            b
+        let lag = SinkLag::new(name);
+        self.sinks.push(Sink {
+            tx,
+            lag: lag.clone(),
         });
-        let client_response = axum_core::body::Body::from_stream(s);
-        let size_hint = client_response.size_hint();
-        let response_body_for_logger =
-            BodyReader::new(rx, tfft_tx, size_hint, append_newlines);
-        (client_response, response_body_for_logger, tfft_rx)
+        let reader = BodyReader::new(
+            rx,
+            tfft_tx,
+            SizeHint::default(),
+            append_newlines,
+            lag,
+        );
+        (reader, tfft_rx)
+    }
+
+    /// The body streamed to the client.
+    #[must_use]
+    pub fn into_body(self) -> Body {
+        let stream = futures::stream::unfold(
+            (Box::pin(self.stream), self.sinks),
+            |(mut stream, sinks)| async move {
+                let item = stream.next().await?;
+                let sinks = match &item {
+                    Ok(bytes) => {
+                        let mut open = Vec::with_capacity(sinks.len());
+                        for sink in sinks {
+                            if sink.send(bytes.clone()).await {
+                                open.push(sink);
+                            }
+                        }
+                        open
+                    }
+                    Err(_) => sinks,
+                };
+                Some((item, (stream, sinks)))
+            },
+        );
+        Body::from_stream(stream)
+    }
+}
+
+struct Sink {
+    tx: mpsc::Sender<Bytes>,
+    lag: SinkLag,
+}
+
+impl Sink {
+    /// Returns `false` if the sink was dropped.
+    async fn send(&self, bytes: Bytes) -> bool {
+        let queued = SINK_CAPACITY - self.tx.capacity();
+        self.lag.max_queued.fetch_max(queued, Ordering::Relaxed);
+        let start = (queued == SINK_CAPACITY).then(Instant::now);
+        if let Err(e) = self.tx.send(bytes).await {
+            tracing::error!(
+                sink = self.lag.name,
+                error = %e,
+                "BodyReader dropped before stream ended"
+            );
+            return false;
+        }
+        if let Some(start) = start {
+            let blocked =
+                u64::try_from(start.elapsed().as_nanos()).unwrap_or(u64::MAX);
+            self.lag.blocked_ns.fetch_add(blocked, Ordering::Relaxed);
+        }
+        true
+    }
+}
+
+/// How far a sink of a [`BodyTee`] fell behind the client's stream.
+#[derive(Debug, Clone)]
+pub struct SinkLag {
+    name: &'static str,
+    max_queued: Arc<AtomicUsize>,
+    blocked_ns: Arc<AtomicU64>,
+}
+
+impl SinkLag {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            max_queued: Arc::default(),
+            blocked_ns: Arc::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The most chunks which were buffered for the sink at once.
+    #[must_use]
+    pub fn max_queued(&self) -> usize {
+        self.max_queued.load(Ordering::Relaxed)
+    }
+
+    /// How long the client's stream waited for the sink to catch up.
+    #[must_use]
+    pub fn blocked(&self) -> Duration {
+        Duration::from_nanos(self.blocked_ns.load(Ordering::Relaxed))
+    }
+
+    /// Records the lag once the body has been streamed.
+    pub fn record(&self, metrics: &Metrics) {
+        let attributes = [KeyValue::new("sink", self.name)];
+        metrics.body_sink_lag.record(
+            u64::try_from(self.max_queued()).unwrap_or(u64::MAX),
+            &attributes,
+        );
+        metrics
+            .body_sink_blocked
+            .record(self.blocked().as_secs_f64() * 1000.0, &attributes);
     }
 }
 
@@ -110,3 +266,30 @@ impl hyper::body::Body for BodyReader {
         self.size_hint.clone()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn every_sink_reads_every_chunk() {
+        let chunks = ["data: a", "data: b", "data: c"].map(|chunk| {
+            Ok::<_, ApiError>(Bytes::from_static(chunk.as_bytes()))
+        });
+        let mut tee = BodyTee::new(futures::stream::iter(chunks));
+        let (logger, _) = tee.sink("logger", false);
+        let (dropped, _) = tee.sink("dropped", false);
+        drop(dropped);
+        let body = tee.into_body();
+
+        let client = body.collect().await.unwrap().to_bytes();
+        let lag = logger.lag();
+        let logged = logger.collect().await.unwrap().to_bytes();
+        assert_eq!(client, "data: adata: bdata: c");
+        assert_eq!(logged, client);
+        assert_eq!(lag.name(), "logger");
+        assert_eq!(lag.max_queued(), 2);
+    }
+}
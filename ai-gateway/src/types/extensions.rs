@@ -1,8 +1,8 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::IpAddr, sync::Arc};
 
-use derive_more::{AsRef, From, Into};
+use derive_more::{AsRef, Display, From, Into};
 
-use super::{model_id::ModelId, org::OrgId, user::UserId};
+use super::{model_id::ModelId, org::OrgId, priority::Priority, user::UserId};
 use crate::{config::router::RouterConfig, types::secret::Secret};
 
 #[derive(Debug, Clone, AsRef, From, Into)]
@@ -13,6 +13,11 @@ pub struct AuthContext {
     pub api_key: Secret<String>,
     pub user_id: UserId,
     pub org_id: OrgId,
+    /// The highest priority the API key may request. Requests without an
+    /// API key may request at most [`Priority::Normal`].
+    pub max_priority: Priority,
+    /// The tier the control plane assigned the API key, if any.
+    pub tier: Option<String>,
 }
 
 #[derive(Debug)]
@@ -47,3 +52,19 @@ pub enum RequestKind {
     UnifiedApi,
     DirectProxy,
 }
+
+/// The IP address of the client, which is the address of the connection
+/// unless it's from a trusted proxy.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display)]
+pub struct ClientIp(pub IpAddr);
+
+/// Marks a request that is only sent to compare its response with the one
+/// returned to the client, such as a differential testing candidate. Shadow
+/// requests are neither logged nor counted toward usage and spend.
+#[derive(Debug, Clone, Copy)]
+pub struct Shadow;
+
+/// The time by which the client needs a response, from its
+/// `x-request-deadline` or `grpc-timeout` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestDeadline(pub tokio::time::Instant);
@@ -154,6 +154,11 @@ pub struct RequestLog {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[builder(default)]
     pub cache_reference_id: Option<String>,
+    /// Base64 encoded SHA-256 checksum of the bodies stored in S3, to detect
+    /// truncated uploads.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[builder(default, setter(strip_option))]
+    pub payload_sha256: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Default, TypedBuilder)]
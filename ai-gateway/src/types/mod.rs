@@ -4,6 +4,7 @@ pub mod json;
 pub mod logger;
 pub mod model_id;
 pub mod org;
+pub mod priority;
 pub mod provider;
 pub mod rate_limit;
 pub mod request;
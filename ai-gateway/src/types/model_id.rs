@@ -9,7 +9,13 @@ use derive_more::AsRef;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::provider::InferenceProvider;
-use crate::error::mapper::MapperError;
+use crate::{
+    dispatcher::{
+        openai_compatible_client::FIREWORKS_MODEL_PREFIX,
+        vertex_client::GOOGLE_PUBLISHER_PREFIX,
+    },
+    error::mapper::MapperError,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Version {
@@ -186,7 +192,38 @@ impl ModelId {
                     id: model_with_version,
                 })
             }
+            InferenceProvider::AzureOpenAI => {
+                let model_with_version = ModelIdWithVersion::from_str(s)?;
+                Ok(ModelId::ModelIdWithVersion {
+                    provider: InferenceProvider::AzureOpenAI,
+                    id: model_with_version,
+                })
+            }
+            InferenceProvider::Vertex => {
+                // Vertex names Google's models with their publisher
+                let model_with_version = ModelIdWithVersion::from_str(
+                    s.strip_prefix(GOOGLE_PUBLISHER_PREFIX).unwrap_or(s),
+                )?;
+                Ok(ModelId::ModelIdWithVersion {
+                    provider: InferenceProvider::Vertex,
+                    id: model_with_version,
+                })
+            }
+            InferenceProvider::Cohere => {
+                let model_with_version = ModelIdWithVersion::from_str(s)?;
+                Ok(ModelId::ModelIdWithVersion {
+                    provider: InferenceProvider::Cohere,
+                    id: model_with_version,
+                })
+            }
             InferenceProvider::Named(name) => {
+                // Fireworks names its own models with the path of its
+                // account
+                let s = if name == "fireworks" {
+                    s.strip_prefix(FIREWORKS_MODEL_PREFIX).unwrap_or(s)
+                } else {
+                    s
+                };
                 let model_with_version = ModelIdWithVersion::from_str(s)?;
                 Ok(ModelId::ModelIdWithVersion {
                     provider: InferenceProvider::Named(name),
@@ -248,6 +285,7 @@ impl ModelId {
             }
             ModelId::Bedrock(bedrock_model_id) => {
                 ModelId::Bedrock(BedrockModelId {
+                    arn: bedrock_model_id.arn,
                     geo: bedrock_model_id.geo,
                     provider: bedrock_model_id.provider,
                     model: bedrock_model_id.model,
@@ -526,8 +564,19 @@ impl Serialize for OllamaModelId {
 /// Has the format of:
 /// `{geo}?.{provider}.{model}(-version)?-{bedrock_internal_version}`
 /// amazon.nova-pro-v1:0
+///
+/// where a `geo` prefix, such as `us.` or `eu.`, names a cross-region
+/// inference profile. The ARN of an inference profile or provisioned model,
+/// `arn:aws:bedrock:{region}:{account}:{resource_type}/{id}`, is also
+/// accepted.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BedrockModelId {
+    /// The ARN this model id was parsed from, if any. ARNs of system-defined
+    /// inference profiles end in a model id, which the other fields are
+    /// parsed from; for other resources, such as application inference
+    /// profiles or provisioned models, `provider` is the resource type and
+    /// `model` is the resource id.
+    pub arn: Option<String>,
     pub geo: Option<String>,
     pub provider: String,
     pub model: String,
@@ -539,6 +588,9 @@ impl FromStr for BedrockModelId {
     type Err = MapperError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("arn:") {
+            return Self::from_arn(s);
+        }
         // Count the number of dots to determine if geo is present
         let dot_count = s.chars().filter(|&c| c == '.').count();
 
@@ -583,6 +635,7 @@ impl FromStr for BedrockModelId {
         let (model, version) = parse_model_and_version(model_part, '-');
 
         Ok(BedrockModelId {
+            arn: None,
             geo,
             provider: provider_str.to_string(),
             model: model.to_string(),
@@ -592,8 +645,45 @@ impl FromStr for BedrockModelId {
     }
 }
 
+impl BedrockModelId {
+    /// Parses an ARN of the form
+    /// `arn:{partition}:bedrock:{region}:{account}:{resource_type}/{id}`.
+    fn from_arn(s: &str) -> Result<Self, MapperError> {
+        let invalid = || MapperError::InvalidModelName(s.to_string());
+        // the resource id may itself contain `:`, e.g. `-v1:0`
+        let parts = s.splitn(6, ':').collect::<Vec<_>>();
+        let [_, _, "bedrock", _, _, resource] = parts.as_slice() else {
+            return Err(invalid());
+        };
+        let (resource_type, id) =
+            resource.split_once('/').ok_or_else(invalid)?;
+        if id.is_empty() {
+            return Err(invalid());
+        }
+        let model = if resource_type == "inference-profile" {
+            Self::from_str(id)?
+        } else {
+            BedrockModelId {
+                arn: None,
+                geo: None,
+                provider: resource_type.to_string(),
+                model: id.to_string(),
+                version: Version::ImplicitLatest,
+                bedrock_internal_version: String::new(),
+            }
+        };
+        Ok(BedrockModelId {
+            arn: Some(s.to_string()),
+            ..model
+        })
+    }
+}
+
 impl Display for BedrockModelId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(arn) = &self.arn {
+            return f.write_str(arn);
+        }
         match (&self.geo, &self.version) {
             (Some(geo), Version::ImplicitLatest) => write!(
                 f,
@@ -1412,6 +1502,57 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_bedrock_cross_region_inference_profile() {
+        let model_id_str = "eu.anthropic.claude-3-5-sonnet-20240620-v1:0";
+        let result = ModelId::from_str_and_provider(
+            InferenceProvider::Bedrock,
+            model_id_str,
+        );
+        let Ok(ModelId::Bedrock(bedrock_model)) = &result else {
+            panic!("Expected Bedrock ModelId");
+        };
+        assert_eq!(bedrock_model.geo.as_deref(), Some("eu"));
+        assert_eq!(bedrock_model.model, "claude-3-5-sonnet");
+        assert_eq!(result.as_ref().unwrap().to_string(), model_id_str);
+    }
+
+    #[test]
+    fn test_bedrock_inference_profile_arn() {
+        let model_id_str = "arn:aws:bedrock:us-east-1:123456789012:\
+                            inference-profile/us.anthropic.\
+                            claude-3-5-sonnet-20240620-v1:0";
+        let result = ModelId::from_str(&format!("bedrock/{model_id_str}"));
+        let Ok(ModelId::Bedrock(bedrock_model)) = &result else {
+            panic!("Expected Bedrock ModelId");
+        };
+        assert_eq!(bedrock_model.geo.as_deref(), Some("us"));
+        assert_eq!(bedrock_model.provider, "anthropic");
+        assert_eq!(bedrock_model.model, "claude-3-5-sonnet");
+        assert_eq!(bedrock_model.bedrock_internal_version, "v1:0");
+        assert_eq!(result.as_ref().unwrap().to_string(), model_id_str);
+
+        let model_id_str = "arn:aws:bedrock:us-east-1:123456789012:\
+                            application-inference-profile/a1b2c3d4e5f6";
+        let result = ModelId::from_str_and_provider(
+            InferenceProvider::Bedrock,
+            model_id_str,
+        );
+        let Ok(ModelId::Bedrock(bedrock_model)) = &result else {
+            panic!("Expected Bedrock ModelId");
+        };
+        assert_eq!(bedrock_model.provider, "application-inference-profile");
+        assert_eq!(bedrock_model.model, "a1b2c3d4e5f6");
+        assert_eq!(result.as_ref().unwrap().to_string(), model_id_str);
+
+        assert!(
+            BedrockModelId::from_str(
+                "arn:aws:s3:us-east-1:123456789012:inference-profile/x"
+            )
+            .is_err()
+        );
+    }
+
     #[test]
     fn test_bedrock_anthropic_claude_sonnet_4_model_proper_format() {
         let model_id_str = "anthropic.claude-sonnet-4-20250514-v1:0";
@@ -1995,6 +2136,32 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_from_str_vertex_model_with_publisher() {
+        let with_publisher = ModelId::from_str_and_provider(
+            InferenceProvider::Vertex,
+            "google/gemini-2.0-flash",
+        )
+        .unwrap();
+        let without_publisher =
+            ModelId::from_str("vertex/gemini-2.0-flash").unwrap();
+        assert_eq!(with_publisher, without_publisher);
+        assert_eq!(with_publisher.to_string(), "gemini-2.0-flash");
+    }
+
+    #[test]
+    fn test_from_str_fireworks_model_with_account() {
+        let with_account = ModelId::from_str_and_provider(
+            InferenceProvider::Named("fireworks".into()),
+            "accounts/fireworks/models/llama-v3p3-70b-instruct",
+        )
+        .unwrap();
+        let without_account =
+            ModelId::from_str("fireworks/llama-v3p3-70b-instruct").unwrap();
+        assert_eq!(with_account, without_account);
+        assert_eq!(with_account.to_string(), "llama-v3p3-70b-instruct");
+    }
+
     #[test]
     fn test_from_str_invalid_no_slash() {
         let result = ModelId::from_str("gpt-4");
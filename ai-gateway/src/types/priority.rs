@@ -0,0 +1,128 @@
+//! Request priorities, requested with the `helicone-priority` header.
+//!
+//! Under load, lower priority requests yield to higher priority ones:
+//! - `low` requests don't wait for provider bulkhead capacity, leaving it to
+//!   the requests queued behind them.
+//! - `low` requests can't use the last fifth of a rate limit, so it's left for
+//!   interactive traffic once batch traffic saturates a limit.
+//! - `low` requests aren't hedged, so they don't add to the load of a slow
+//!   provider.
+//!
+//! Requests waiting for provider bulkhead capacity are served highest
+//! priority first.
+use std::str::FromStr;
+
+use http::{HeaderMap, HeaderName};
+use serde::{Deserialize, Serialize};
+use ts_rs::TS;
+
+use crate::error::invalid_req::InvalidRequestError;
+
+pub const PRIORITY_HEADER: HeaderName =
+    HeaderName::from_static("helicone-priority");
+
+#[derive(
+    Debug,
+    Default,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Serialize,
+    Deserialize,
+    TS,
+    strum::Display,
+    strum::EnumString,
+)]
+#[ts(export)]
+#[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    /// Reads the priority requested in `headers`, which may be at most
+    /// `max`, the highest priority the request's API key is scoped to.
+    pub fn from_headers(
+        headers: &HeaderMap,
+        max: Self,
+    ) -> Result<Self, InvalidRequestError> {
+        let Some(value) = headers.get(PRIORITY_HEADER) else {
+            return Ok(Self::default().min(max));
+        };
+        let priority = value
+            .to_str()
+            .ok()
+            .and_then(|value| Self::from_str(value.trim()).ok())
+            .ok_or_else(|| {
+                InvalidRequestError::InvalidPriority(
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })?;
+        if priority > max {
+            return Err(InvalidRequestError::PriorityNotAllowed(priority));
+        }
+        Ok(priority)
+    }
+
+    /// Whether slow attempts of requests of this priority may be hedged.
+    #[must_use]
+    pub fn may_hedge(self) -> bool {
+        self != Self::Low
+    }
+
+    /// The number of requests of a rate limit of `capacity` which requests
+    /// of this priority can't use.
+    #[must_use]
+    pub fn rate_limit_reserve(self, capacity: u32) -> u32 {
+        match self {
+            Self::Low => capacity / 5,
+            Self::Normal | Self::High => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn headers(priority: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(
+            PRIORITY_HEADER,
+            HeaderValue::from_static(priority),
+        )])
+    }
+
+    #[test]
+    fn priority_is_validated_against_scope() {
+        assert_eq!(
+            Priority::from_headers(&HeaderMap::new(), Priority::High).unwrap(),
+            Priority::Normal
+        );
+        assert_eq!(
+            Priority::from_headers(&headers("high"), Priority::High).unwrap(),
+            Priority::High
+        );
+        assert_eq!(
+            Priority::from_headers(&headers("low"), Priority::Normal).unwrap(),
+            Priority::Low
+        );
+        assert!(matches!(
+            Priority::from_headers(&headers("high"), Priority::Normal),
+            Err(InvalidRequestError::PriorityNotAllowed(Priority::High))
+        ));
+        assert!(matches!(
+            Priority::from_headers(&headers("urgent"), Priority::High),
+            Err(InvalidRequestError::InvalidPriority(_))
+        ));
+    }
+}
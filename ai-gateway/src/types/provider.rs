@@ -77,6 +77,10 @@ pub enum InferenceProvider {
     Ollama,
     #[serde(rename = "gemini")]
     GoogleGemini,
+    #[serde(rename = "azure")]
+    AzureOpenAI,
+    Vertex,
+    Cohere,
     #[serde(untagged)]
     Named(CompactString),
 }
@@ -110,7 +114,14 @@ impl InferenceProvider {
                     .map(ApiEndpoint::Google)
                     .collect()
             }
-            InferenceProvider::Named(_) => {
+            InferenceProvider::Cohere => {
+                crate::endpoints::cohere::Cohere::iter()
+                    .map(ApiEndpoint::Cohere)
+                    .collect()
+            }
+            InferenceProvider::AzureOpenAI
+            | InferenceProvider::Vertex
+            | InferenceProvider::Named(_) => {
                 crate::endpoints::openai::OpenAI::iter()
                     .map(|endpoint| ApiEndpoint::OpenAICompatible {
                         provider: self.clone(),
@@ -133,11 +144,16 @@ impl InferenceProvider {
             "AWS Bedrock" => Ok(InferenceProvider::Bedrock),
             "Ollama" => Ok(InferenceProvider::Ollama),
             "Google AI (Gemini)" => Ok(InferenceProvider::GoogleGemini),
+            "Azure OpenAI" => Ok(InferenceProvider::AzureOpenAI),
+            "Cohere" => Ok(InferenceProvider::Cohere),
             "Groq" => Ok(InferenceProvider::Named("groq".into())),
             "Mistral AI" => Ok(InferenceProvider::Named("mistral".into())),
             "Hyperbolic" => Ok(InferenceProvider::Named("hyperbolic".into())),
             "Deepseek" => Ok(InferenceProvider::Named("deepseek".into())),
             "X.AI (Grok)" => Ok(InferenceProvider::Named("xai".into())),
+            "Together AI" => Ok(InferenceProvider::Named("together".into())),
+            "Fireworks" => Ok(InferenceProvider::Named("fireworks".into())),
+            "Cloudflare" => Ok(InferenceProvider::Named("cloudflare".into())),
             _ => Err(ProviderError::InvalidProviderName(provider_name.into())),
         }
     }
@@ -155,6 +171,9 @@ impl FromStr for InferenceProvider {
             "bedrock" => Ok(InferenceProvider::Bedrock),
             "ollama" => Ok(InferenceProvider::Ollama),
             "gemini" => Ok(InferenceProvider::GoogleGemini),
+            "azure" => Ok(InferenceProvider::AzureOpenAI),
+            "vertex" => Ok(InferenceProvider::Vertex),
+            "cohere" => Ok(InferenceProvider::Cohere),
             s => Ok(InferenceProvider::Named(s.into())),
         }
     }
@@ -169,6 +188,9 @@ impl AsRef<str> for InferenceProvider {
             InferenceProvider::Bedrock => "bedrock",
             InferenceProvider::Ollama => "ollama",
             InferenceProvider::GoogleGemini => "gemini",
+            InferenceProvider::AzureOpenAI => "azure",
+            InferenceProvider::Vertex => "vertex",
+            InferenceProvider::Cohere => "cohere",
         }
     }
 }
@@ -182,6 +204,10 @@ impl std::fmt::Display for InferenceProvider {
     }
 }
 
+/// The path of the service account key Vertex AI requests are authenticated
+/// with, as for Google Cloud client libraries.
+const GOOGLE_APPLICATION_CREDENTIALS: &str = "GOOGLE_APPLICATION_CREDENTIALS";
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ProviderKey {
     Secret(Secret<String>),
@@ -189,6 +215,8 @@ pub enum ProviderKey {
         access_key: Secret<String>,
         secret_key: Secret<String>,
     },
+    /// The JSON key of a Google Cloud service account.
+    ServiceAccount(Secret<String>),
     NotRequired,
 }
 
@@ -228,18 +256,44 @@ impl ProviderKey {
             } else {
                 None
             }
-        } else {
-            let provider_str = provider.to_string().to_uppercase();
-            let env_var = format!("{provider_str}_API_KEY");
-            if let Ok(key) = std::env::var(&env_var) {
-                Some(ProviderKey::Secret(Secret::from(key)))
-            } else {
-                None
+        } else if *provider == InferenceProvider::Vertex {
+            let path = std::env::var(GOOGLE_APPLICATION_CREDENTIALS).ok()?;
+            match std::fs::read_to_string(&path) {
+                Ok(key) => Some(ProviderKey::ServiceAccount(Secret::from(key))),
+                Err(error) => {
+                    tracing::warn!(
+                        %error,
+                        path,
+                        "failed to read Vertex AI service account key"
+                    );
+                    None
+                }
             }
+        } else {
+            api_key_env_vars(provider)
+                .iter()
+                .find_map(|env_var| std::env::var(env_var).ok())
+                .map(|key| ProviderKey::Secret(Secret::from(key)))
         }
     }
 }
 
+/// The environment variables the API key of `provider` is read from, in
+/// order of precedence.
+///
+/// Custom providers may be named e.g. `my-vllm`, which isn't a valid
+/// environment variable name, so dashes are replaced with underscores. The
+/// name with dashes is still read, as keys used to be read from it.
+fn api_key_env_vars(provider: &InferenceProvider) -> Vec<String> {
+    let provider_str = provider.to_string().to_uppercase();
+    let mut env_vars =
+        vec![format!("{}_API_KEY", provider_str.replace('-', "_"))];
+    if provider_str.contains('-') {
+        env_vars.push(format!("{provider_str}_API_KEY"));
+    }
+    env_vars
+}
+
 #[derive(Debug)]
 pub enum ProviderKeys {
     Cloud(RwLock<HashMap<OrgId, ProviderKeyMap>>),
@@ -356,10 +410,52 @@ mod tests {
         assert_eq!("test", named_provider_str);
     }
 
+    #[test]
+    fn azure_openai_round_trip() {
+        let provider = "azure".parse::<InferenceProvider>().unwrap();
+        assert_eq!(provider, InferenceProvider::AzureOpenAI);
+        assert_eq!(provider.to_string(), "azure");
+        let deserialized =
+            serde_json::from_str::<InferenceProvider>("\"azure\"").unwrap();
+        assert_eq!(deserialized, InferenceProvider::AzureOpenAI);
+    }
+
+    #[test]
+    fn vertex_round_trip() {
+        let provider = "vertex".parse::<InferenceProvider>().unwrap();
+        assert_eq!(provider, InferenceProvider::Vertex);
+        assert_eq!(provider.to_string(), "vertex");
+        let deserialized =
+            serde_json::from_str::<InferenceProvider>("\"vertex\"").unwrap();
+        assert_eq!(deserialized, InferenceProvider::Vertex);
+    }
+
+    #[test]
+    fn cohere_round_trip() {
+        let provider = "cohere".parse::<InferenceProvider>().unwrap();
+        assert_eq!(provider, InferenceProvider::Cohere);
+        assert_eq!(provider.to_string(), "cohere");
+        let deserialized =
+            serde_json::from_str::<InferenceProvider>("\"cohere\"").unwrap();
+        assert_eq!(deserialized, InferenceProvider::Cohere);
+    }
+
     #[test]
     fn inference_provider_to_string() {
         let named_provider = InferenceProvider::Named("test".into());
         let named_provider_str = named_provider.to_string();
         assert_eq!("test", named_provider_str);
     }
+
+    #[test]
+    fn api_key_env_vars_fall_back_to_dashed_name() {
+        assert_eq!(
+            api_key_env_vars(&InferenceProvider::OpenAI),
+            ["OPENAI_API_KEY"]
+        );
+        assert_eq!(
+            api_key_env_vars(&InferenceProvider::Named("my-vllm".into())),
+            ["MY_VLLM_API_KEY", "MY-VLLM_API_KEY"]
+        );
+    }
 }
@@ -0,0 +1,479 @@
+//! Endpoints for operating the gateway, under `/admin/v1`.
+//!
+//! They're only served if `server.admin-token` is set, and require it as a
+//! bearer token.
+//!
+//! - `GET /admin/v1/dlq`: lists the logs in the dead-letter queue.
+//! - `POST /admin/v1/dlq/replay`: retries all logs in the dead-letter queue.
+//! - `POST /admin/v1/dlq/{id}/replay`: retries a log in the dead-letter queue.
+//! - `GET /admin/v1/usage/report?from=&to=`: the usage per provider, model and
+//!   day from `from` to `to`, both inclusive `YYYY-MM-DD` dates.
+//! - `GET /admin/v1/requests/{id}`: the gateway, Helicone, provider and trace
+//!   IDs of recent requests known by `id`, any one of them.
+//! - `GET /admin/v1/chaos/faults`: lists the faults being injected, if `chaos`
+//!   is enabled.
+//! - `POST /admin/v1/chaos/faults`: injects the fault in the body.
+//! - `DELETE /admin/v1/chaos/faults`: stops injecting every fault.
+//! - `DELETE /admin/v1/chaos/faults/{id}`: stops injecting a fault.
+//! - `GET /admin/v1/freezes`: lists the freezes of traffic in effect.
+//! - `POST /admin/v1/freezes`: freezes the traffic of the body's scope.
+//! - `DELETE /admin/v1/freezes`: lifts every freeze.
+//! - `DELETE /admin/v1/freezes/{id}`: lifts a freeze.
+//! - `GET /admin/v1/model-mappings`: lists the model mappings stored in the
+//!   database, in cloud deployments.
+//! - `PUT /admin/v1/model-mappings`: stores the model mapping in the body,
+//!   replacing the targets of its source model, if it's already mapped.
+//! - `DELETE /admin/v1/model-mappings?model=&router=`: deletes the stored
+//!   mapping of `model`, of the default model mapping if `router` is unset.
+use std::{
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+
+use axum_core::response::{IntoResponse, Response};
+use bytes::Bytes;
+use chrono::NaiveDate;
+use futures::future::{BoxFuture, Either};
+use http::{Method, Request, StatusCode, header};
+use http_body_util::BodyExt;
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    error::{
+        api::ApiError, auth::AuthError, internal::InternalError,
+        invalid_req::InvalidRequestError,
+    },
+    logger::{correlation::Correlation, dlq::DeadLetter},
+    middleware::mapper::model::StoredModelMapping,
+    store::{
+        model_mapping::ModelMappingStore,
+        usage::{DailyUsage, UsageStore},
+    },
+    types::{json::Json, router::RouterId},
+    utils::freeze::{ActiveFreeze, Freeze},
+};
+
+pub use crate::dispatcher::chaos::{ActiveFault, Fault, FaultKind};
+
+const ADMIN_PATH_PREFIX: &str = "/admin/v1/";
+
+#[derive(Debug, Clone)]
+pub struct AdminLayer<ReqBody> {
+    app_state: AppState,
+    _marker: PhantomData<ReqBody>,
+}
+
+impl<ReqBody> AdminLayer<ReqBody> {
+    #[must_use]
+    pub fn new(app_state: AppState) -> Self {
+        Self {
+            app_state,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody> Layer<S> for AdminLayer<ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Service = Admin<S, ReqBody>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Admin {
+            inner,
+            app_state: self.app_state.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Admin<S, ReqBody> {
+    inner: S,
+    app_state: AppState,
+    _marker: PhantomData<ReqBody>,
+}
+
+impl<S: Clone, ReqBody> Clone for Admin<S, ReqBody> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            app_state: self.app_state.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for Admin<S, ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+    S::Error: Send + 'static,
+    ReqBody: http_body::Body + Send + 'static,
+    ReqBody::Data: Send,
+    ReqBody::Error: std::error::Error + Send,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future =
+        Either<BoxFuture<'static, Result<Response, S::Error>>, S::Future>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let Some(admin_token) = &self.app_state.config().server.admin_token
+        else {
+            return Either::Right(self.inner.call(req));
+        };
+        let Some(route) = req.uri().path().strip_prefix(ADMIN_PATH_PREFIX)
+        else {
+            return Either::Right(self.inner.call(req));
+        };
+        let token = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let authorized = match token {
+            None => Err(AuthError::MissingAuthorizationHeader),
+            Some(token)
+                if constant_time_eq(
+                    token.as_bytes(),
+                    admin_token.expose().as_bytes(),
+                ) =>
+            {
+                Ok(())
+            }
+            Some(_) => Err(AuthError::InvalidCredentials),
+        };
+        let app_state = self.app_state.clone();
+        let method = req.method().clone();
+        let route = route.to_string();
+        let path = req.uri().path().to_string();
+        let query = req.uri().query().unwrap_or_default().to_string();
+        Either::Left(Box::pin(async move {
+            if let Err(e) = authorized {
+                return Ok(e.into_response());
+            }
+            let body = match req.into_body().collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(e) => return Ok(internal_error(&e)),
+            };
+            let response = handle(&app_state, &method, &route, &query, body)
+                .await
+                .unwrap_or_else(|| {
+                    InvalidRequestError::NotFound(path).into_response()
+                });
+            Ok(response)
+        }))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeadLettersResponse {
+    pub entries: Vec<DeadLetter>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReplayResponse {
+    pub scheduled: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RequestsResponse {
+    pub requests: Vec<Correlation>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FaultsResponse {
+    pub faults: Vec<ActiveFault>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RemovedFaultsResponse {
+    pub removed: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FreezesResponse {
+    pub freezes: Vec<ActiveFreeze>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LiftedFreezesResponse {
+    pub lifted: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ModelMappingsResponse {
+    pub mappings: Vec<StoredModelMapping>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UsageReportResponse {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    pub usage: Vec<DailyUsage>,
+}
+
+/// Serves the admin endpoint at `route`, returning `None` if there is none.
+#[allow(clippy::too_many_lines)]
+async fn handle(
+    app_state: &AppState,
+    method: &Method,
+    route: &str,
+    query: &str,
+    body: Bytes,
+) -> Option<Response> {
+    let segments = route.split('/').collect::<Vec<_>>();
+    match (method, segments.as_slice()) {
+        (&Method::GET, ["dlq"]) => {
+            let dlq = app_state.0.dlq.as_ref()?;
+            Some(match dlq.entries().await {
+                Ok(entries) => {
+                    Json(DeadLettersResponse { entries }).into_response()
+                }
+                Err(e) => internal_error(&e),
+            })
+        }
+        (&Method::POST, ["dlq", "replay"]) => {
+            let dlq = app_state.0.dlq.as_ref()?;
+            Some(match dlq.replay(None).await {
+                Ok(scheduled) => {
+                    Json(ReplayResponse { scheduled }).into_response()
+                }
+                Err(e) => internal_error(&e),
+            })
+        }
+        (&Method::POST, ["dlq", id, "replay"]) => {
+            let dlq = app_state.0.dlq.as_ref()?;
+            let id = Uuid::parse_str(id).ok()?;
+            match dlq.replay(Some(id)).await {
+                Ok(0) => None,
+                Ok(scheduled) => {
+                    Some(Json(ReplayResponse { scheduled }).into_response())
+                }
+                Err(e) => Some(internal_error(&e)),
+            }
+        }
+        (&Method::GET, ["requests", id]) => {
+            let requests = app_state.0.correlations.lookup(id);
+            if requests.is_empty() {
+                return None;
+            }
+            Some(Json(RequestsResponse { requests }).into_response())
+        }
+        (&Method::GET, ["usage", "report"]) => {
+            app_state.0.usage.as_ref()?;
+            let store = UsageStore::new(
+                app_state.0.router_store.as_ref()?.pool.clone(),
+            );
+            let (from, to) = match report_range(query) {
+                Ok(range) => range,
+                Err(e) => return Some(e.into_response()),
+            };
+            Some(match store.report(from, to).await {
+                Ok(usage) => Json(UsageReportResponse { from, to, usage })
+                    .into_response(),
+                Err(e) => internal_error(&e),
+            })
+        }
+        (&Method::GET, ["chaos", "faults"]) => {
+            let faults = app_state.0.chaos.as_ref()?.active();
+            Some(Json(FaultsResponse { faults }).into_response())
+        }
+        (&Method::POST, ["chaos", "faults"]) => {
+            let faults = app_state.0.chaos.as_ref()?;
+            let fault = serde_json::from_slice::<Fault>(&body)
+                .map_err(InvalidRequestError::InvalidRequestBody)
+                .and_then(|fault| fault.validate().map(|()| fault));
+            Some(match fault {
+                Ok(fault) => (StatusCode::CREATED, Json(faults.add(fault)))
+                    .into_response(),
+                Err(e) => e.into_response(),
+            })
+        }
+        (&Method::DELETE, ["chaos", "faults"]) => {
+            let removed = app_state.0.chaos.as_ref()?.remove(None);
+            Some(Json(RemovedFaultsResponse { removed }).into_response())
+        }
+        (&Method::DELETE, ["chaos", "faults", id]) => {
+            let faults = app_state.0.chaos.as_ref()?;
+            let id = Uuid::parse_str(id).ok()?;
+            match faults.remove(Some(id)) {
+                0 => None,
+                removed => Some(
+                    Json(RemovedFaultsResponse { removed }).into_response(),
+                ),
+            }
+        }
+        (&Method::GET, ["freezes"]) => {
+            let freezes = app_state.0.freezes.active();
+            Some(Json(FreezesResponse { freezes }).into_response())
+        }
+        (&Method::POST, ["freezes"]) => {
+            let freeze = if body.is_empty() {
+                Ok(Freeze::default())
+            } else {
+                serde_json::from_slice::<Freeze>(&body)
+            };
+            Some(match freeze {
+                Ok(freeze) => {
+                    (StatusCode::CREATED, Json(app_state.0.freezes.add(freeze)))
+                        .into_response()
+                }
+                Err(e) => {
+                    InvalidRequestError::InvalidRequestBody(e).into_response()
+                }
+            })
+        }
+        (&Method::DELETE, ["freezes"]) => {
+            let lifted = app_state.0.freezes.remove(None);
+            Some(Json(LiftedFreezesResponse { lifted }).into_response())
+        }
+        (&Method::DELETE, ["freezes", id]) => {
+            let id = Uuid::parse_str(id).ok()?;
+            match app_state.0.freezes.remove(Some(id)) {
+                0 => None,
+                lifted => {
+                    Some(Json(LiftedFreezesResponse { lifted }).into_response())
+                }
+            }
+        }
+        (_, ["model-mappings"]) => {
+            handle_model_mappings(app_state, method, query, &body).await
+        }
+        _ => None,
+    }
+}
+
+/// Serves the model mappings endpoints. Stored mappings are propagated to
+/// every replica by the database listener, and applied to this one right
+/// away.
+async fn handle_model_mappings(
+    app_state: &AppState,
+    method: &Method,
+    query: &str,
+    body: &[u8],
+) -> Option<Response> {
+    let store =
+        ModelMappingStore::new(app_state.0.router_store.as_ref()?.pool.clone());
+    match *method {
+        Method::GET => {
+            let mappings = app_state.0.model_mappings.list();
+            Some(Json(ModelMappingsResponse { mappings }).into_response())
+        }
+        Method::PUT => {
+            let mapping =
+                match serde_json::from_slice::<StoredModelMapping>(body) {
+                    Ok(mapping) => mapping,
+                    Err(e) => {
+                        return Some(
+                            InvalidRequestError::InvalidRequestBody(e)
+                                .into_response(),
+                        );
+                    }
+                };
+            Some(match store.upsert(&mapping).await {
+                Ok(()) => {
+                    app_state.0.model_mappings.set(mapping.clone());
+                    Json(mapping).into_response()
+                }
+                Err(e) => internal_error(&e),
+            })
+        }
+        Method::DELETE => {
+            let param = |name: &str| {
+                url::form_urlencoded::parse(query.as_bytes())
+                    .find_map(|(key, value)| (key == name).then_some(value))
+            };
+            let Some(model) = param("model") else {
+                return Some(
+                    InvalidRequestError::InvalidUrl(
+                        "missing `model` query parameter".to_string(),
+                    )
+                    .into_response(),
+                );
+            };
+            let router = param("router")
+                .map(|router| RouterId::Named(router.as_ref().into()));
+            match store.delete(router.as_ref(), &model).await {
+                Ok(true) => {
+                    app_state.0.model_mappings.remove(router.as_ref(), &model);
+                    Some(StatusCode::NO_CONTENT.into_response())
+                }
+                Ok(false) => None,
+                Err(e) => Some(internal_error(&e)),
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parses the `from` and `to` dates of a usage report.
+fn report_range(
+    query: &str,
+) -> Result<(NaiveDate, NaiveDate), InvalidRequestError> {
+    let date = |name: &str| {
+        let value = url::form_urlencoded::parse(query.as_bytes())
+            .find_map(|(key, value)| (key == name).then_some(value))
+            .ok_or_else(|| {
+                InvalidRequestError::InvalidUrl(format!(
+                    "missing `{name}` query parameter"
+                ))
+            })?;
+        NaiveDate::parse_from_str(&value, "%Y-%m-%d").map_err(|_| {
+            InvalidRequestError::InvalidUrl(format!(
+                "`{name}` must be a YYYY-MM-DD date"
+            ))
+        })
+    };
+    let (from, to) = (date("from")?, date("to")?);
+    if from > to {
+        return Err(InvalidRequestError::InvalidUrl(
+            "`from` must not be after `to`".to_string(),
+        ));
+    }
+    Ok((from, to))
+}
+
+fn internal_error(error: &dyn std::error::Error) -> Response {
+    tracing::error!(error = %error, "admin request failed");
+    ApiError::Internal(InternalError::Internal).into_response()
+}
+
+/// Compares the tokens in time independent of where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_comparison() {
+        assert!(constant_time_eq(b"admin-token", b"admin-token"));
+        assert!(!constant_time_eq(b"admin-token", b"admin-tokem"));
+        assert!(!constant_time_eq(b"admin-token", b"admin"));
+    }
+
+    #[test]
+    fn usage_report_range() {
+        let (from, to) = report_range("from=2025-06-01&to=2025-06-30").unwrap();
+        assert_eq!(from, NaiveDate::from_ymd_opt(2025, 6, 1).unwrap());
+        assert_eq!(to, NaiveDate::from_ymd_opt(2025, 6, 30).unwrap());
+        assert!(report_range("from=2025-06-01").is_err());
+        assert!(report_range("from=2025-06-30&to=2025-06-01").is_err());
+        assert!(report_range("from=yesterday&to=2025-06-01").is_err());
+    }
+}
@@ -0,0 +1,250 @@
+//! Resolves the IP address of the client of each request.
+//!
+//! Behind load balancers or CDNs, the address of the connection is the
+//! proxy's, and the client's is in a forwarding header. Those headers are
+//! only read from trusted proxies, since clients can set them to anything.
+use std::{
+    net::{IpAddr, SocketAddr},
+    str::FromStr,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use http::{HeaderMap, HeaderName, Request};
+use tower::{Layer, Service};
+
+use crate::{
+    config::server::{ForwardingHeader, TrustedProxiesConfig},
+    types::extensions::ClientIp,
+};
+
+const CF_CONNECTING_IP: HeaderName =
+    HeaderName::from_static("cf-connecting-ip");
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+#[derive(Debug, Clone)]
+pub struct ClientIpLayer {
+    trusted_proxies: Option<Arc<TrustedProxiesConfig>>,
+}
+
+impl ClientIpLayer {
+    #[must_use]
+    pub fn new(trusted_proxies: Option<TrustedProxiesConfig>) -> Self {
+        Self {
+            trusted_proxies: trusted_proxies.map(Arc::new),
+        }
+    }
+}
+
+impl<S> Layer<S> for ClientIpLayer {
+    type Service = ClientIpService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ClientIpService {
+            inner,
+            trusted_proxies: self.trusted_proxies.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientIpService<S> {
+    inner: S,
+    trusted_proxies: Option<Arc<TrustedProxiesConfig>>,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for ClientIpService<S>
+where
+    S: Service<Request<ReqBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        if let Some(peer) = req.extensions().get::<SocketAddr>() {
+            let client_ip = match &self.trusted_proxies {
+                Some(trusted_proxies) => {
+                    resolve(trusted_proxies, peer.ip(), req.headers())
+                }
+                None => peer.ip(),
+            };
+            tracing::Span::current()
+                .record("client_ip", tracing::field::display(client_ip));
+            req.extensions_mut().insert(ClientIp(client_ip));
+        }
+        self.inner.call(req)
+    }
+}
+
+/// The client's IP address, given the address of the connection.
+fn resolve(
+    config: &TrustedProxiesConfig,
+    peer: IpAddr,
+    headers: &HeaderMap,
+) -> IpAddr {
+    if !config.is_trusted(peer) {
+        return peer;
+    }
+    config
+        .headers
+        .iter()
+        .find_map(|header| {
+            let hops = match header {
+                ForwardingHeader::Forwarded => {
+                    forwarded_hops(headers, &http::header::FORWARDED)
+                }
+                ForwardingHeader::XForwardedFor => {
+                    forwarded_hops(headers, &X_FORWARDED_FOR)
+                }
+                ForwardingHeader::CfConnectingIp => headers
+                    .get(CF_CONNECTING_IP)
+                    .map(|value| vec![value.to_str().ok().and_then(parse_ip)])
+                    .unwrap_or_default(),
+            };
+            client_of_chain(config, &hops)
+        })
+        .unwrap_or(peer)
+}
+
+/// The addresses of each hop of a `Forwarded` or `X-Forwarded-For` chain,
+/// from the client to the last proxy. Hops which aren't IP addresses, such
+/// as `unknown` or obfuscated identifiers, are `None`.
+fn forwarded_hops(
+    headers: &HeaderMap,
+    name: &HeaderName,
+) -> Vec<Option<IpAddr>> {
+    let is_forwarded = *name == http::header::FORWARDED;
+    headers
+        .get_all(name)
+        .iter()
+        .flat_map(|value| value.to_str().unwrap_or_default().split(','))
+        .map(|hop| {
+            if is_forwarded {
+                hop.split(';')
+                    .filter_map(|pair| pair.split_once('='))
+                    .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| {
+                        parse_ip(node.trim().trim_matches('"'))
+                    })
+            } else {
+                parse_ip(hop)
+            }
+        })
+        .collect()
+}
+
+/// The first untrusted address of the chain from its end, or its first
+/// address if every hop is trusted. `None` if a hop before the client isn't
+/// an IP address, since anything further is set by the client.
+fn client_of_chain(
+    config: &TrustedProxiesConfig,
+    hops: &[Option<IpAddr>],
+) -> Option<IpAddr> {
+    let mut client = None;
+    for hop in hops.iter().rev() {
+        let ip = (*hop)?;
+        client = Some(ip);
+        if !config.is_trusted(ip) {
+            break;
+        }
+    }
+    client
+}
+
+/// Parses an IP address, which may have a port or be in brackets.
+fn parse_ip(node: &str) -> Option<IpAddr> {
+    let node = node.trim();
+    IpAddr::from_str(node)
+        .ok()
+        .or_else(|| SocketAddr::from_str(node).ok().map(|addr| addr.ip()))
+        .or_else(|| {
+            let node = node.strip_prefix('[')?.strip_suffix(']')?;
+            IpAddr::from_str(node).ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn config(headers: Vec<ForwardingHeader>) -> TrustedProxiesConfig {
+        TrustedProxiesConfig {
+            cidrs: vec!["10.0.0.0/8".parse().unwrap()],
+            headers,
+        }
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn forwarding_headers_are_only_trusted_from_proxies() {
+        let config = config(vec![ForwardingHeader::XForwardedFor]);
+        let headers =
+            headers(&[("x-forwarded-for", "1.1.1.1, 2.2.2.2, 10.0.0.2")]);
+        let proxy = IpAddr::from([10, 0, 0, 1]);
+        let client = IpAddr::from([3, 3, 3, 3]);
+        // 1.1.1.1 was prepended by the client
+        assert_eq!(
+            resolve(&config, proxy, &headers),
+            IpAddr::from([2, 2, 2, 2])
+        );
+        assert_eq!(resolve(&config, client, &headers), client);
+        assert_eq!(resolve(&config, proxy, &HeaderMap::new()), proxy);
+    }
+
+    #[test]
+    fn headers_are_read_in_order_of_precedence() {
+        let headers = headers(&[
+            (
+                "forwarded",
+                "for=\"[2001:db8::1]:4711\";proto=https, for=10.0.0.3",
+            ),
+            ("x-forwarded-for", "4.4.4.4"),
+            ("cf-connecting-ip", "5.5.5.5"),
+        ]);
+        let proxy = IpAddr::from([10, 0, 0, 1]);
+        let resolve_with =
+            |precedence| resolve(&config(precedence), proxy, &headers);
+        assert_eq!(
+            resolve_with(vec![
+                ForwardingHeader::CfConnectingIp,
+                ForwardingHeader::Forwarded,
+            ]),
+            IpAddr::from([5, 5, 5, 5])
+        );
+        assert_eq!(
+            resolve_with(vec![
+                ForwardingHeader::Forwarded,
+                ForwardingHeader::XForwardedFor,
+            ]),
+            IpAddr::from_str("2001:db8::1").unwrap()
+        );
+        assert_eq!(
+            client_of_chain(
+                &config(vec![]),
+                &[Some(IpAddr::from([1, 1, 1, 1])), None]
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Deadlines set by clients for their requests.
+//!
+//! Clients enforcing their own SLOs can send the time by which they need a
+//! response, either as an RFC 3339 timestamp in `x-request-deadline` or as
+//! a gRPC style `grpc-timeout` budget such as `1500m`. The remaining budget
+//! bounds the request, including its retries, which are skipped once they
+//! can't start before the deadline, and a `504` is returned once it
+//! expires.
+use std::{
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use axum_core::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use http::{HeaderMap, HeaderName, Request};
+use tokio::time::Instant;
+use tower::{Layer, Service};
+
+use crate::{
+    error::{internal::InternalError, invalid_req::InvalidRequestError},
+    types::extensions::RequestDeadline,
+};
+
+const X_REQUEST_DEADLINE: HeaderName =
+    HeaderName::from_static("x-request-deadline");
+const GRPC_TIMEOUT: HeaderName = HeaderName::from_static("grpc-timeout");
+
+#[derive(Debug, Clone, Default)]
+pub struct DeadlineLayer;
+
+impl DeadlineLayer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl<S> Layer<S> for DeadlineLayer {
+    type Service = Deadline<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Deadline { inner }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Deadline<S> {
+    inner: S,
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for Deadline<S>
+where
+    S: Service<Request<ReqBody>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Response, S::Error>>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let mut inner = self.inner.clone();
+        std::mem::swap(&mut self.inner, &mut inner);
+        let budget = match budget(req.headers(), Utc::now()) {
+            Ok(Some(budget)) => budget,
+            Ok(None) => return Box::pin(inner.call(req)),
+            Err(error) => {
+                tracing::debug!(error = %error, "rejecting request deadline");
+                return Box::pin(std::future::ready(Ok(error.into_response())));
+            }
+        };
+        if budget.is_zero() {
+            return Box::pin(std::future::ready(Ok(
+                InternalError::DeadlineExceeded.into_response(),
+            )));
+        }
+        // the deadline is the gateway's to enforce, not the provider's
+        req.headers_mut().remove(X_REQUEST_DEADLINE);
+        req.headers_mut().remove(GRPC_TIMEOUT);
+        let deadline = Instant::now() + budget;
+        req.extensions_mut().insert(RequestDeadline(deadline));
+        Box::pin(async move {
+            tokio::time::timeout_at(deadline, inner.call(req))
+                .await
+                .unwrap_or_else(|_| {
+                    tracing::debug!(?budget, "request deadline expired");
+                    Ok(InternalError::DeadlineExceeded.into_response())
+                })
+        })
+    }
+}
+
+/// The time remaining until the earliest deadline the client set, if any.
+fn budget(
+    headers: &HeaderMap,
+    now: DateTime<Utc>,
+) -> Result<Option<Duration>, InvalidRequestError> {
+    let deadline = headers
+        .get(X_REQUEST_DEADLINE)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+                .map(|deadline| {
+                    (deadline.with_timezone(&Utc) - now)
+                        .to_std()
+                        .unwrap_or_default()
+                })
+                .ok_or(InvalidRequestError::InvalidDeadline(
+                    "x-request-deadline",
+                ))
+        })
+        .transpose()?;
+    let timeout = headers
+        .get(GRPC_TIMEOUT)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(parse_grpc_timeout)
+                .ok_or(InvalidRequestError::InvalidDeadline("grpc-timeout"))
+        })
+        .transpose()?;
+    Ok(match (deadline, timeout) {
+        (Some(deadline), Some(timeout)) => Some(deadline.min(timeout)),
+        (deadline, timeout) => deadline.or(timeout),
+    })
+}
+
+/// Parses a `grpc-timeout`: at most 8 digits followed by a unit, `H`ours,
+/// `M`inutes, `S`econds, `m`illiseconds, `u`microseconds or `n`anoseconds.
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (amount, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    if amount.is_empty()
+        || amount.len() > 8
+        || !amount.bytes().all(|byte| byte.is_ascii_digit())
+    {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 3600)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn grpc_timeouts() {
+        assert_eq!(
+            parse_grpc_timeout("1500m"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_grpc_timeout("2M"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("S"), None);
+        assert_eq!(parse_grpc_timeout("10"), None);
+        assert_eq!(parse_grpc_timeout("-1S"), None);
+    }
+
+    #[test]
+    fn earliest_deadline_is_the_budget() {
+        let now = DateTime::parse_from_rfc3339("2025-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let mut headers = HeaderMap::new();
+        assert_eq!(budget(&headers, now).unwrap(), None);
+        headers.insert(
+            X_REQUEST_DEADLINE,
+            HeaderValue::from_static("2025-01-01T00:00:05Z"),
+        );
+        assert_eq!(
+            budget(&headers, now).unwrap(),
+            Some(Duration::from_secs(5))
+        );
+        headers.insert(GRPC_TIMEOUT, HeaderValue::from_static("2S"));
+        assert_eq!(
+            budget(&headers, now).unwrap(),
+            Some(Duration::from_secs(2))
+        );
+        headers.insert(
+            X_REQUEST_DEADLINE,
+            HeaderValue::from_static("2024-12-31T23:59:59Z"),
+        );
+        assert_eq!(budget(&headers, now).unwrap(), Some(Duration::ZERO));
+        headers.insert(X_REQUEST_DEADLINE, HeaderValue::from_static("soon"));
+        assert!(matches!(
+            budget(&headers, now),
+            Err(InvalidRequestError::InvalidDeadline("x-request-deadline"))
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    future::{Ready, ready},
+    marker::PhantomData,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use axum_core::response::Response;
+use futures::future::Either;
+use http::{Method, Request};
+use tower::{Layer, Service};
+
+use crate::config::deployment::DeploymentInfo;
+
+#[derive(Debug, Clone)]
+pub struct DeploymentInfoLayer<ReqBody, E> {
+    info: Arc<DeploymentInfo>,
+    _marker: PhantomData<(ReqBody, E)>,
+}
+
+impl<ReqBody, E> DeploymentInfoLayer<ReqBody, E> {
+    #[must_use]
+    pub fn new(info: DeploymentInfo) -> Self {
+        Self {
+            info: Arc::new(info),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody, E> Layer<S> for DeploymentInfoLayer<ReqBody, E>
+where
+    S: tower::Service<http::Request<ReqBody>, Response = Response, Error = E>,
+{
+    type Service = DeploymentInfoService<S, ReqBody, E>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        DeploymentInfoService {
+            inner,
+            info: self.info.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct DeploymentInfoService<S, ReqBody, E> {
+    inner: S,
+    info: Arc<DeploymentInfo>,
+    _marker: PhantomData<(ReqBody, E)>,
+}
+
+impl<S: Clone, ReqBody, E> Clone for DeploymentInfoService<S, ReqBody, E> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            info: self.info.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody, E> Service<Request<ReqBody>>
+    for DeploymentInfoService<S, ReqBody, E>
+where
+    S: Service<Request<ReqBody>, Response = Response, Error = E>
+        + Send
+        + 'static,
+    S::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Self::Response, Self::Error>>, S::Future>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        if req.method() == Method::GET && req.uri().path() == "/deployment" {
+            Either::Left(ready(Ok(deployment_response(&self.info))))
+        } else {
+            Either::Right(self.inner.call(req))
+        }
+    }
+}
+
+fn deployment_response(info: &DeploymentInfo) -> Response {
+    let body = serde_json::to_vec(info).expect("always serializable");
+    http::Response::builder()
+        .status(http::StatusCode::OK)
+        .header(http::header::CONTENT_TYPE, "application/json")
+        .body(axum_core::body::Body::from(body))
+        .expect("always valid if tests pass")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deployment_response() {
+        let info = DeploymentInfo {
+            version: "1.0.0".to_string(),
+            region: Some("us-east-1".to_string()),
+            ..Default::default()
+        };
+        let response = deployment_response(&info);
+        assert_eq!(response.status(), http::StatusCode::OK);
+        assert_eq!(
+            response.headers()[http::header::CONTENT_TYPE],
+            "application/json"
+        );
+    }
+}
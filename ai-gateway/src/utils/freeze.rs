@@ -0,0 +1,287 @@
+//! Freezes of the gateway's traffic, for incident response.
+//!
+//! Operators freeze traffic through the admin API to stop spend instantly,
+//! such as during a runaway-cost incident, without taking the gateway down.
+//! Frozen requests are rejected with a `503` and the freeze's message. A
+//! freeze applies to every request except admin and health checks, or only
+//! to those dispatched to a provider, by a router, or with an API key.
+use std::{
+    cell::LazyCell,
+    marker::PhantomData,
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+use axum_core::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use futures::future::{Either, Ready, ready};
+use http::Request;
+use serde::{Deserialize, Serialize};
+use tower::{Layer, Service};
+use uuid::Uuid;
+
+use crate::{
+    app_state::AppState,
+    control_plane::types::hash_key,
+    error::internal::InternalError,
+    types::{
+        extensions::AuthContext, provider::InferenceProvider, router::RouterId,
+    },
+};
+
+const DEFAULT_MESSAGE: &str = "The gateway is temporarily frozen";
+
+/// A freeze, as added through the admin API.
+#[derive(Debug, Clone, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Freeze {
+    /// Returned to frozen requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Only freezes requests dispatched to this provider.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub provider: Option<InferenceProvider>,
+    /// Only freezes requests to this router.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub router: Option<RouterId>,
+    /// Only freezes requests with the API key of this hash, as stored by
+    /// the control plane.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+}
+
+impl Freeze {
+    fn is_global(&self) -> bool {
+        self.provider.is_none() && self.router.is_none() && self.key.is_none()
+    }
+
+    fn applies_to(
+        &self,
+        provider: &InferenceProvider,
+        router_id: Option<&RouterId>,
+        key_hash: &LazyCell<Option<String>, impl FnOnce() -> Option<String>>,
+    ) -> bool {
+        self.provider
+            .as_ref()
+            .is_none_or(|frozen| frozen == provider)
+            && self
+                .router
+                .as_ref()
+                .is_none_or(|frozen| Some(frozen) == router_id)
+            && self
+                .key
+                .as_ref()
+                .is_none_or(|frozen| key_hash.as_ref() == Some(frozen))
+    }
+
+    fn error(&self) -> InternalError {
+        InternalError::Frozen(
+            self.message
+                .clone()
+                .unwrap_or_else(|| DEFAULT_MESSAGE.to_string()),
+        )
+    }
+}
+
+/// A freeze which is in effect.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ActiveFreeze {
+    pub id: Uuid,
+    pub since: DateTime<Utc>,
+    #[serde(flatten)]
+    pub freeze: Freeze,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Freezes(Arc<RwLock<Vec<ActiveFreeze>>>);
+
+impl Freezes {
+    pub fn add(&self, freeze: Freeze) -> ActiveFreeze {
+        let active = ActiveFreeze {
+            id: Uuid::new_v4(),
+            since: Utc::now(),
+            freeze,
+        };
+        tracing::warn!(
+            id = %active.id,
+            freeze = ?active.freeze,
+            "freezing traffic"
+        );
+        self.0
+            .write()
+            .expect("freezes lock poisoned")
+            .push(active.clone());
+        active
+    }
+
+    /// Lifts the freeze with `id`, or every freeze if `None`. Returns the
+    /// number of freezes lifted.
+    pub fn remove(&self, id: Option<Uuid>) -> usize {
+        let mut freezes = self.0.write().expect("freezes lock poisoned");
+        let before = freezes.len();
+        freezes.retain(|active| id.is_some_and(|id| active.id != id));
+        let lifted = before - freezes.len();
+        if lifted > 0 {
+            tracing::warn!(lifted, "lifted freezes");
+        }
+        lifted
+    }
+
+    #[must_use]
+    pub fn active(&self) -> Vec<ActiveFreeze> {
+        self.0.read().expect("freezes lock poisoned").clone()
+    }
+
+    /// The error of the freeze of every request, if any.
+    #[must_use]
+    pub fn global(&self) -> Option<InternalError> {
+        self.0
+            .read()
+            .expect("freezes lock poisoned")
+            .iter()
+            .find(|active| active.freeze.is_global())
+            .map(|active| active.freeze.error())
+    }
+
+    /// The error of the freeze of requests dispatched to `provider` by
+    /// `router_id` with the API key of `auth_ctx`, if any.
+    pub fn check(
+        &self,
+        provider: &InferenceProvider,
+        router_id: Option<&RouterId>,
+        auth_ctx: Option<&AuthContext>,
+    ) -> Result<(), InternalError> {
+        // keys are only hashed if a freeze is scoped to one
+        let key_hash = LazyCell::new(|| {
+            auth_ctx.map(|auth_ctx| hash_key(auth_ctx.api_key.expose()))
+        });
+        self.0
+            .read()
+            .expect("freezes lock poisoned")
+            .iter()
+            .find(|active| {
+                active.freeze.applies_to(provider, router_id, &key_hash)
+            })
+            .map_or(Ok(()), |active| Err(active.freeze.error()))
+    }
+}
+
+/// Rejects every request while the gateway is frozen. Freezes of a
+/// provider or router are enforced when requests are dispatched.
+#[derive(Debug, Clone)]
+pub struct FreezeLayer<ReqBody> {
+    app_state: AppState,
+    _marker: PhantomData<ReqBody>,
+}
+
+impl<ReqBody> FreezeLayer<ReqBody> {
+    #[must_use]
+    pub fn new(app_state: AppState) -> Self {
+        Self {
+            app_state,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody> Layer<S> for FreezeLayer<ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Service = FreezeService<S, ReqBody>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        FreezeService {
+            inner,
+            app_state: self.app_state.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FreezeService<S, ReqBody> {
+    inner: S,
+    app_state: AppState,
+    _marker: PhantomData<ReqBody>,
+}
+
+impl<S: Clone, ReqBody> Clone for FreezeService<S, ReqBody> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            app_state: self.app_state.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for FreezeService<S, ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Response, S::Error>>, S::Future>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        match self.app_state.0.freezes.global() {
+            Some(error) => Either::Left(ready(Ok(error.into_response()))),
+            None => Either::Right(self.inner.call(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freezes_apply_to_their_scope() {
+        let freezes = Freezes::default();
+        let router = RouterId::Named("my-router".into());
+        let openai = freezes.add(Freeze {
+            provider: Some(InferenceProvider::OpenAI),
+            ..Freeze::default()
+        });
+        freezes.add(Freeze {
+            message: Some("runaway spend".to_string()),
+            router: Some(router.clone()),
+            ..Freeze::default()
+        });
+        assert!(freezes.global().is_none());
+        assert!(
+            freezes
+                .check(&InferenceProvider::OpenAI, None, None)
+                .is_err()
+        );
+        assert!(
+            freezes
+                .check(&InferenceProvider::Anthropic, None, None)
+                .is_ok()
+        );
+        assert!(matches!(
+            freezes.check(&InferenceProvider::Anthropic, Some(&router), None),
+            Err(InternalError::Frozen(message)) if message == "runaway spend"
+        ));
+
+        assert_eq!(freezes.remove(Some(openai.id)), 1);
+        assert!(
+            freezes
+                .check(&InferenceProvider::OpenAI, None, None)
+                .is_ok()
+        );
+        freezes.add(Freeze::default());
+        assert!(freezes.global().is_some());
+        assert_eq!(freezes.remove(None), 2);
+        assert!(freezes.active().is_empty());
+    }
+}
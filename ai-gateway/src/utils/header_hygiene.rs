@@ -0,0 +1,245 @@
+//! Hardening of client request headers against request smuggling.
+//!
+//! Hop-by-hop headers only apply to the connection to the gateway, so they
+//! are removed instead of being forwarded to providers. Requests with
+//! conflicting framing headers are rejected, since proxies in front of the
+//! gateway may have framed them differently.
+use std::{
+    future::{Ready, ready},
+    marker::PhantomData,
+    task::{Context, Poll},
+};
+
+use axum_core::response::{IntoResponse, Response};
+use futures::future::Either;
+use http::{HeaderMap, HeaderName, HeaderValue, Request, header};
+use tower::{Layer, Service};
+
+use crate::{
+    config::server::HeaderLimitsConfig, error::invalid_req::InvalidRequestError,
+};
+
+const HOP_BY_HOP_HEADERS: [HeaderName; 7] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+#[derive(Debug, Clone)]
+pub struct HeaderHygieneLayer<ReqBody> {
+    limits: HeaderLimitsConfig,
+    _marker: PhantomData<ReqBody>,
+}
+
+impl<ReqBody> HeaderHygieneLayer<ReqBody> {
+    #[must_use]
+    pub fn new(limits: HeaderLimitsConfig) -> Self {
+        Self {
+            limits,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody> Layer<S> for HeaderHygieneLayer<ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Service = HeaderHygiene<S, ReqBody>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HeaderHygiene {
+            inner,
+            limits: self.limits.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct HeaderHygiene<S, ReqBody> {
+    inner: S,
+    limits: HeaderLimitsConfig,
+    _marker: PhantomData<ReqBody>,
+}
+
+impl<S: Clone, ReqBody> Clone for HeaderHygiene<S, ReqBody> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            limits: self.limits.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for HeaderHygiene<S, ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Response, S::Error>>, S::Future>;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        match sanitize(req.headers_mut(), &self.limits) {
+            Ok(()) => Either::Right(self.inner.call(req)),
+            Err(error) => {
+                tracing::debug!(error = %error, "rejecting request headers");
+                Either::Left(ready(Ok(error.into_response())))
+            }
+        }
+    }
+}
+
+/// Checks the headers against `limits` and for ambiguous framing, and
+/// removes hop-by-hop headers.
+fn sanitize(
+    headers: &mut HeaderMap,
+    limits: &HeaderLimitsConfig,
+) -> Result<(), InvalidRequestError> {
+    if headers.len() > limits.max_count {
+        return Err(InvalidRequestError::HeadersTooLarge("maximum count"));
+    }
+    let size = headers
+        .iter()
+        .map(|(name, value)| name.as_str().len() + value.len())
+        .sum::<usize>();
+    if size > limits.max_size {
+        return Err(InvalidRequestError::HeadersTooLarge("maximum size"));
+    }
+
+    if let Some(content_length) = content_length(headers)? {
+        if headers.contains_key(header::TRANSFER_ENCODING) {
+            return Err(InvalidRequestError::AmbiguousFraming(
+                "both content-length and transfer-encoding are set",
+            ));
+        }
+        // identical duplicates are collapsed into one header
+        headers
+            .insert(header::CONTENT_LENGTH, HeaderValue::from(content_length));
+    }
+
+    // headers listed in `connection` are hop-by-hop as well
+    let connection_headers = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+        .collect::<Vec<_>>();
+    for name in connection_headers.into_iter().chain(HOP_BY_HOP_HEADERS) {
+        headers.remove(name);
+    }
+    Ok(())
+}
+
+/// The content length, if all `content-length` values agree.
+fn content_length(
+    headers: &HeaderMap,
+) -> Result<Option<u64>, InvalidRequestError> {
+    let mut content_length = None;
+    for value in headers.get_all(header::CONTENT_LENGTH) {
+        let values = value.to_str().map_err(|_| {
+            InvalidRequestError::AmbiguousFraming("invalid content-length")
+        })?;
+        for value in values.split(',') {
+            let value = value.trim().parse::<u64>().map_err(|_| {
+                InvalidRequestError::AmbiguousFraming("invalid content-length")
+            })?;
+            if content_length.is_some_and(|length| length != value) {
+                return Err(InvalidRequestError::AmbiguousFraming(
+                    "conflicting content-length values",
+                ));
+            }
+            content_length = Some(value);
+        }
+    }
+    Ok(content_length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(headers: &[(&'static str, &'static str)]) -> HeaderMap {
+        headers
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hop_by_hop_headers_are_removed() {
+        let mut headers = headers(&[
+            ("connection", "keep-alive, x-internal"),
+            ("keep-alive", "timeout=5"),
+            ("te", "trailers"),
+            ("x-internal", "secret"),
+            ("authorization", "Bearer sk-test"),
+        ]);
+        sanitize(&mut headers, &HeaderLimitsConfig::default()).unwrap();
+        assert_eq!(headers.len(), 1);
+        assert!(headers.contains_key(header::AUTHORIZATION));
+    }
+
+    #[test]
+    fn duplicate_content_lengths() {
+        let limits = HeaderLimitsConfig::default();
+        let mut same =
+            headers(&[("content-length", "42"), ("content-length", "42, 42")]);
+        sanitize(&mut same, &limits).unwrap();
+        assert_eq!(same.get_all(header::CONTENT_LENGTH).iter().count(), 1);
+        assert_eq!(same[header::CONTENT_LENGTH], "42");
+
+        let mut conflicting =
+            headers(&[("content-length", "42"), ("content-length", "7")]);
+        assert!(matches!(
+            sanitize(&mut conflicting, &limits),
+            Err(InvalidRequestError::AmbiguousFraming(_))
+        ));
+
+        let mut chunked = headers(&[
+            ("content-length", "42"),
+            ("transfer-encoding", "chunked"),
+        ]);
+        assert!(matches!(
+            sanitize(&mut chunked, &limits),
+            Err(InvalidRequestError::AmbiguousFraming(_))
+        ));
+    }
+
+    #[test]
+    fn header_limits() {
+        let limits = HeaderLimitsConfig {
+            max_count: 2,
+            max_size: 32,
+        };
+        let mut too_many = headers(&[("a", "1"), ("b", "2"), ("c", "3")]);
+        assert!(matches!(
+            sanitize(&mut too_many, &limits),
+            Err(InvalidRequestError::HeadersTooLarge(_))
+        ));
+        let mut too_large = headers(&[("x-large", "a value longer than 32b")]);
+        assert!(matches!(
+            sanitize(&mut too_large, &limits),
+            Err(InvalidRequestError::HeadersTooLarge(_))
+        ));
+    }
+}
@@ -0,0 +1,286 @@
+//! Overload protection, rejecting requests while the gateway is under more
+//! pressure than configured in `server.load-shedding`.
+//!
+//! Pressure is the highest fraction of any threshold in use: requests in
+//! flight, the async runtime's scheduler delay, and resident memory. Lower
+//! priority requests are shed first, so that interactive traffic keeps
+//! being served while batch traffic backs off.
+use std::{
+    marker::PhantomData,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+};
+
+use axum_core::{
+    body::Body,
+    response::{IntoResponse, Response},
+};
+use futures::{
+    FutureExt,
+    future::{Either, Map, Ready, ready},
+};
+use http::Request;
+use http_body_util::BodyExt;
+use opentelemetry::{KeyValue, metrics::Counter};
+use tower::{Layer, Service};
+
+use crate::{
+    app_state::AppState, config::server::LoadSheddingConfig,
+    error::internal::InternalError, metrics::system::SystemPressure,
+    types::priority::Priority,
+};
+
+/// Fraction of any threshold from which `low` priority requests are shed.
+const LOW_PRIORITY_PRESSURE: f64 = 0.8;
+
+/// What the gateway is under pressure from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, strum::AsRefStr)]
+#[strum(serialize_all = "snake_case")]
+enum Reason {
+    InFlight,
+    SchedulerDelay,
+    Memory,
+}
+
+#[derive(Debug, Clone)]
+pub struct LoadShedLayer<ReqBody> {
+    shedder: Option<Arc<LoadShedder>>,
+    _marker: PhantomData<ReqBody>,
+}
+
+impl<ReqBody> LoadShedLayer<ReqBody> {
+    #[must_use]
+    pub fn new(app_state: &AppState) -> Self {
+        let shedder =
+            app_state
+                .config()
+                .server
+                .load_shedding
+                .clone()
+                .map(|config| {
+                    Arc::new(LoadShedder {
+                        config,
+                        in_flight: Arc::default(),
+                        pressure: app_state.0.system_pressure.clone(),
+                        shed_requests: app_state
+                            .0
+                            .metrics
+                            .shed_requests
+                            .clone(),
+                    })
+                });
+        Self {
+            shedder,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<S, ReqBody> Layer<S> for LoadShedLayer<ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Service = LoadShed<S, ReqBody>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        LoadShed {
+            inner,
+            shedder: self.shedder.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct LoadShed<S, ReqBody> {
+    inner: S,
+    shedder: Option<Arc<LoadShedder>>,
+    _marker: PhantomData<ReqBody>,
+}
+
+impl<S: Clone, ReqBody> Clone for LoadShed<S, ReqBody> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            shedder: self.shedder.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+type TrackedFuture<F, E> =
+    Map<F, Box<dyn FnOnce(Result<Response, E>) -> Result<Response, E> + Send>>;
+
+impl<S, ReqBody> Service<Request<ReqBody>> for LoadShed<S, ReqBody>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Either<
+        Ready<Result<Response, S::Error>>,
+        Either<S::Future, TrackedFuture<S::Future, S::Error>>,
+    >;
+
+    fn poll_ready(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        let Some(shedder) = &self.shedder else {
+            return Either::Right(Either::Left(self.inner.call(req)));
+        };
+        let priority = req
+            .extensions()
+            .get::<Priority>()
+            .copied()
+            .unwrap_or_default();
+        if let Some(reason) = shedder.should_shed(priority) {
+            tracing::warn!(
+                %priority,
+                reason = reason.as_ref(),
+                "gateway overloaded, shedding request"
+            );
+            shedder.shed_requests.add(
+                1,
+                &[
+                    KeyValue::new("priority", priority.to_string()),
+                    KeyValue::new("reason", reason.as_ref().to_string()),
+                ],
+            );
+            let error = InternalError::Overloaded {
+                retry_after: shedder.config.retry_after,
+            };
+            return Either::Left(ready(Ok(error.into_response())));
+        }
+        let guard = InFlight::new(Arc::clone(&shedder.in_flight));
+        let track = Box::new(move |result: Result<Response, S::Error>| {
+            // requests are in flight until their response body is sent,
+            // which for streams is long after the response is returned
+            result.map(|response| {
+                response.map(|body| {
+                    Body::new(body.map_frame(move |frame| {
+                        let _in_flight = &guard;
+                        frame
+                    }))
+                })
+            })
+        });
+        Either::Right(Either::Right(self.inner.call(req).map(track)))
+    }
+}
+
+#[derive(Debug)]
+struct LoadShedder {
+    config: LoadSheddingConfig,
+    in_flight: Arc<AtomicUsize>,
+    pressure: SystemPressure,
+    shed_requests: Counter<u64>,
+}
+
+impl LoadShedder {
+    /// Returns what the gateway is under too much pressure from to serve a
+    /// request of `priority`, if anything.
+    fn should_shed(&self, priority: Priority) -> Option<Reason> {
+        let limit = match priority {
+            Priority::Low => LOW_PRIORITY_PRESSURE,
+            Priority::Normal => 1.0,
+            Priority::High => return None,
+        };
+        let (reason, pressure) = self.pressure();
+        (pressure >= limit).then_some(reason)
+    }
+
+    /// The highest fraction of any threshold, and what it's of.
+    #[allow(clippy::cast_precision_loss)]
+    fn pressure(&self) -> (Reason, f64) {
+        let in_flight = self.in_flight.load(Ordering::Relaxed) as f64
+            / self.config.max_in_flight.max(1) as f64;
+        let scheduler_delay = self.pressure.scheduler_delay().as_secs_f64()
+            / self
+                .config
+                .max_scheduler_delay
+                .as_secs_f64()
+                .max(f64::EPSILON);
+        let memory = self.config.max_memory.map_or(0.0, |max_memory| {
+            self.pressure.resident_memory() as f64 / max_memory.max(1) as f64
+        });
+        [
+            (Reason::InFlight, in_flight),
+            (Reason::SchedulerDelay, scheduler_delay),
+            (Reason::Memory, memory),
+        ]
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .unwrap_or((Reason::InFlight, 0.0))
+    }
+}
+
+/// Counts a request as in flight until dropped.
+#[derive(Debug)]
+struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    fn new(in_flight: Arc<AtomicUsize>) -> Self {
+        in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(in_flight)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shedder(max_in_flight: usize) -> LoadShedder {
+        let meter = opentelemetry::global::meter("test");
+        LoadShedder {
+            config: LoadSheddingConfig {
+                max_in_flight,
+                ..Default::default()
+            },
+            in_flight: Arc::default(),
+            pressure: SystemPressure::default(),
+            shed_requests: meter.u64_counter("shed_requests").build(),
+        }
+    }
+
+    #[test]
+    fn lower_priorities_are_shed_first() {
+        let shedder = shedder(10);
+        let _in_flight = (0..8)
+            .map(|_| InFlight::new(Arc::clone(&shedder.in_flight)))
+            .collect::<Vec<_>>();
+        assert_eq!(shedder.should_shed(Priority::Low), Some(Reason::InFlight));
+        assert_eq!(shedder.should_shed(Priority::Normal), None);
+
+        let _more_in_flight = (0..2)
+            .map(|_| InFlight::new(Arc::clone(&shedder.in_flight)))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            shedder.should_shed(Priority::Normal),
+            Some(Reason::InFlight)
+        );
+        assert_eq!(shedder.should_shed(Priority::High), None);
+    }
+
+    #[test]
+    fn finished_requests_are_no_longer_in_flight() {
+        let shedder = shedder(1);
+        let in_flight = InFlight::new(Arc::clone(&shedder.in_flight));
+        assert!(shedder.should_shed(Priority::Normal).is_some());
+        drop(in_flight);
+        assert!(shedder.should_shed(Priority::Normal).is_none());
+    }
+}
@@ -1,9 +1,18 @@
+pub mod admin;
 pub mod catch_panic;
+pub mod client_ip;
+pub mod deadline;
+pub mod deployment_info;
+pub mod freeze;
 pub mod handle_error;
+pub mod header_hygiene;
 pub mod health_check;
+pub mod load_shed;
 pub mod meltdown;
 pub mod retry;
+pub mod task;
 pub mod timer;
+pub mod tokenizer;
 pub mod validate_config;
 
 use std::{fmt, fmt::Display, marker::PhantomData, str::FromStr};
@@ -74,6 +83,35 @@ where
     serializer.serialize_str(&value.to_string())
 }
 
+/// The URL of `path_and_query` under `base_url` and then `path_prefix`.
+///
+/// Unlike [`Url::join`], the path of `base_url` is kept whether or not it
+/// ends in a slash, and even if `path_and_query` is absolute, so that
+/// providers served under a sub-path are reached there.
+pub(crate) fn join_url(
+    base_url: &Url,
+    path_prefix: Option<&str>,
+    path_and_query: &str,
+) -> Url {
+    let (path, query) = match path_and_query.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path_and_query, None),
+    };
+    let mut full_path = base_url.path().trim_end_matches('/').to_string();
+    if let Some(prefix) = path_prefix.map(|prefix| prefix.trim_matches('/'))
+        && !prefix.is_empty()
+    {
+        full_path.push('/');
+        full_path.push_str(prefix);
+    }
+    full_path.push('/');
+    full_path.push_str(path.trim_start_matches('/'));
+    let mut url = base_url.clone();
+    url.set_path(&full_path);
+    url.set_query(query);
+    url
+}
+
 pub(crate) fn host_header(url: &Url) -> HeaderValue {
     match url.host() {
         Some(url::Host::Domain(host)) => HeaderValue::from_str(host).unwrap(),
@@ -90,3 +128,37 @@ pub(crate) fn host_header(url: &Url) -> HeaderValue {
 pub(crate) fn default_true() -> bool {
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn join_url_keeps_base_path_and_prefix() {
+        let url = |base: &str| Url::parse(base).unwrap();
+        assert_eq!(
+            join_url(&url("https://api.openai.com/"), None, "v1/models")
+                .as_str(),
+            "https://api.openai.com/v1/models"
+        );
+        // `Url::join` drops `llm` without a trailing slash
+        assert_eq!(
+            join_url(
+                &url("http://ingress.internal/llm"),
+                None,
+                "/v1/chat/completions?stream=true"
+            )
+            .as_str(),
+            "http://ingress.internal/llm/v1/chat/completions?stream=true"
+        );
+        assert_eq!(
+            join_url(
+                &url("http://ingress.internal/team/"),
+                Some("/llm/"),
+                "v1/chat/completions"
+            )
+            .as_str(),
+            "http://ingress.internal/team/llm/v1/chat/completions"
+        );
+    }
+}
@@ -0,0 +1,100 @@
+//! Observability of panics, and of background tasks which die of them.
+//!
+//! A panic in a spawned task only ends that task, so without these a logger
+//! delivery or a monitor could die without a trace until the behavior it
+//! was responsible for degrades. Panics are counted in `error_count`, with
+//! the `type` of `Panic`, or `TaskPanic` and the `task` which died for
+//! tasks spawned with [`spawn`].
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    panic::{AssertUnwindSafe, PanicHookInfo},
+    sync::OnceLock,
+};
+
+use futures::{Future, FutureExt};
+use opentelemetry::{KeyValue, metrics::Counter};
+use tokio::task::JoinHandle;
+
+static ERROR_COUNT: OnceLock<Counter<u64>> = OnceLock::new();
+
+/// Logs and counts every panic, including those caught by the server, in
+/// place of printing them to stderr.
+pub fn install_panic_hook(error_count: Counter<u64>) {
+    if ERROR_COUNT.set(error_count).is_err() {
+        return;
+    }
+    std::panic::set_hook(Box::new(|info: &PanicHookInfo<'_>| {
+        let location = info
+            .location()
+            .map_or_else(String::new, ToString::to_string);
+        tracing::error!(
+            message = panic_message(info.payload()),
+            %location,
+            backtrace = %Backtrace::capture(),
+            "panicked"
+        );
+        if let Some(error_count) = ERROR_COUNT.get() {
+            error_count.add(1, &[KeyValue::new("type", "Panic")]);
+        }
+    }));
+}
+
+/// Spawns a background task named `task`, logging and counting its death
+/// if it panics.
+///
+/// The panic is resumed afterwards, so that awaiting the returned handle
+/// still reports it.
+pub fn spawn<F>(task: &'static str, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio::spawn(async move {
+        match AssertUnwindSafe(future).catch_unwind().await {
+            Ok(output) => output,
+            Err(panic) => {
+                tracing::error!(
+                    task,
+                    message = panic_message(&*panic),
+                    "background task panicked"
+                );
+                if let Some(error_count) = ERROR_COUNT.get() {
+                    error_count.add(
+                        1,
+                        &[
+                            KeyValue::new("type", "TaskPanic"),
+                            KeyValue::new("task", task),
+                        ],
+                    );
+                }
+                std::panic::resume_unwind(panic)
+            }
+        }
+    })
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn panics_still_reach_the_handle() {
+        let handle = spawn("test", async { panic!("task failed") });
+        let error = handle.await.unwrap_err();
+        assert!(error.is_panic());
+        assert_eq!(panic_message(&*error.into_panic()), "task failed");
+
+        assert_eq!(spawn("test", async { 1 }).await.unwrap(), 1);
+    }
+}
@@ -0,0 +1,95 @@
+//! Token counting with the embedded `tiktoken` encodings.
+//!
+//! `OpenAI` models are counted with their own encoding. The tokenizers of
+//! other providers aren't published, so their tokens are counted with
+//! `o200k_base`, which is a far closer estimate than a fixed number of
+//! characters per token.
+use http::HeaderName;
+use tiktoken_rs::{CoreBPE, tokenizer::Tokenizer};
+
+/// Marks token counts that were estimated by the gateway rather than
+/// reported by the provider.
+pub const TOKEN_COUNT_ESTIMATED_HEADER: HeaderName =
+    HeaderName::from_static("helicone-token-count-estimated");
+
+/// Counts the tokens of `text` for `model`, given with or without its
+/// provider.
+#[must_use]
+pub fn count_tokens(model: Option<&str>, text: &str) -> usize {
+    if text.is_empty() {
+        return 0;
+    }
+    encoding(model).encode_ordinary(text).len()
+}
+
+/// Truncates `text` to its first `max_tokens` tokens for `model`, and
+/// returns its number of tokens before it was truncated.
+pub fn truncate_tokens(
+    model: Option<&str>,
+    text: &mut String,
+    max_tokens: usize,
+) -> usize {
+    let encoding = encoding(model);
+    let tokens = encoding.encode_ordinary(text);
+    if let Some(kept) = tokens.get(..max_tokens)
+        && kept.len() < tokens.len()
+    {
+        let mut end = encoding
+            ._decode_native_and_split(kept.to_vec())
+            .map(|bytes| bytes.len())
+            .sum::<usize>();
+        // a token may end in the middle of a character
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    tokens.len()
+}
+
+fn encoding(model: Option<&str>) -> &'static CoreBPE {
+    let model = model
+        .map(|model| model.split_once('/').map_or(model, |(_, model)| model));
+    match model.and_then(tiktoken_rs::tokenizer::get_tokenizer) {
+        Some(Tokenizer::Cl100kBase) => tiktoken_rs::cl100k_base_singleton(),
+        Some(Tokenizer::P50kBase) => tiktoken_rs::p50k_base_singleton(),
+        Some(Tokenizer::R50kBase | Tokenizer::Gpt2) => {
+            tiktoken_rs::r50k_base_singleton()
+        }
+        Some(Tokenizer::P50kEdit) => tiktoken_rs::p50k_edit_singleton(),
+        Some(Tokenizer::O200kBase) | None => {
+            tiktoken_rs::o200k_base_singleton()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_are_counted_with_the_model_encoding() {
+        assert_eq!(count_tokens(Some("openai/gpt-4o"), "Hello, world"), 3);
+        assert_eq!(count_tokens(Some("gpt-4"), "12345678"), 3);
+        assert_eq!(
+            count_tokens(Some("anthropic/claude-3-5-sonnet"), "Hello, world"),
+            3
+        );
+        assert_eq!(count_tokens(None, ""), 0);
+    }
+
+    #[test]
+    fn text_is_truncated_on_token_boundaries() {
+        let mut text = "Hello, world".to_string();
+        assert_eq!(truncate_tokens(None, &mut text, 2), 3);
+        assert_eq!(text, "Hello,");
+
+        let mut text = "hello".to_string();
+        assert_eq!(truncate_tokens(None, &mut text, 1), 1);
+        assert_eq!(text, "hello");
+
+        let mut text = "héllo wörld".to_string();
+        truncate_tokens(None, &mut text, 1);
+        assert_eq!(text, "hé");
+    }
+}
@@ -11,7 +11,7 @@ use serde::Serialize;
 use tower::{Layer, Service};
 
 use crate::{
-    config::router::RouterConfig,
+    config::{router::RouterConfig, validation::Issue},
     error::{api::ApiError, internal::InternalError},
     types::json::Json,
 };
@@ -78,6 +78,8 @@ where
 pub struct ValidateRouterConfigResponse {
     pub valid: bool,
     pub error: Option<String>,
+    /// Each problem found, with paths relative to the router config.
+    pub errors: Vec<Issue>,
 }
 
 impl<S, ReqBody> Service<Request<ReqBody>> for ValidateRouterConfig<S, ReqBody>
@@ -120,6 +122,12 @@ where
                             let body = Json(ValidateRouterConfigResponse {
                                 valid: false,
                                 error: Some(e.to_string()),
+                                errors: vec![Issue {
+                                    code: "invalid-router-config",
+                                    path: None,
+                                    message: e.to_string(),
+                                    suggestion: None,
+                                }],
                             });
                             return Ok(body.into_response());
                         }
@@ -130,12 +138,14 @@ where
                     let body = Json(ValidateRouterConfigResponse {
                         valid: false,
                         error: Some(e.to_string()),
+                        errors: e.issues(),
                     });
                     Ok(body.into_response())
                 } else {
                     let body = Json(ValidateRouterConfigResponse {
                         valid: true,
                         error: None,
+                        errors: Vec::new(),
                     });
                     Ok(body.into_response())
                 }
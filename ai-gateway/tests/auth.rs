@@ -2,7 +2,9 @@ use std::collections::HashMap;
 
 use ai_gateway::{
     config::{Config, helicone::HeliconeFeatures},
+    control_plane::types::{ControlPlaneState, hash_key},
     tests::{TestDefault, harness::Harness, mock::MockArgs},
+    types::priority::Priority,
 };
 use http::{Method, Request, StatusCode};
 use http_body_util::BodyExt;
@@ -194,3 +196,60 @@ async fn require_auth_disabled_with_token() {
     assert_eq!(response.status(), StatusCode::OK);
     // mocks are verified on drop
 }
+
+#[tokio::test]
+#[serial_test::serial]
+async fn high_priority_requires_a_scoped_key() {
+    let mut config = Config::test_default();
+    config.helicone.features = HeliconeFeatures::Auth;
+
+    let mock_args = MockArgs::builder()
+        .stubs(HashMap::from([(
+            "success:openai:chat_completion",
+            1.into(),
+        )]))
+        .build();
+    let mut unscoped = ControlPlaneState::test_default();
+    let mut scoped_key = unscoped.keys[0].clone();
+    scoped_key.key_hash = hash_key("sk-helicone-scoped-key");
+    scoped_key.max_priority = Some(Priority::High);
+    unscoped.keys.push(scoped_key);
+    let mut harness = Harness::builder()
+        .with_config(config)
+        .with_mock_args(mock_args)
+        .with_control_plane_state(unscoped)
+        .build()
+        .await;
+
+    let request = |api_key: &str| {
+        let body_bytes = serde_json::to_vec(&json!({
+            "model": "openai/gpt-4o-mini",
+            "messages": [
+                {
+                    "role": "user",
+                    "content": "Hello, world!"
+                }
+            ]
+        }))
+        .unwrap();
+        Request::builder()
+            .method(Method::POST)
+            .header("authorization", format!("Bearer {api_key}"))
+            .header("helicone-priority", "high")
+            .uri("http://router.helicone.com/router/my-router/chat/completions")
+            .body(axum_core::body::Body::from(body_bytes))
+            .unwrap()
+    };
+
+    let response = harness.call(request("sk-helicone-test-key")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = harness
+        .call(request("sk-helicone-scoped-key"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let _response_body = response.into_body().collect().await.unwrap();
+
+    // mocks are verified on drop
+}
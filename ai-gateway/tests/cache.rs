@@ -314,6 +314,8 @@ async fn cache_enabled_per_router() {
                     directive: None,
                     buckets: 1,
                     seed: Some("router-cached-seed".to_string()),
+                    trust_client_directives: true,
+                    key: None,
                 }),
                 load_balance:
                     ai_gateway::config::balance::BalanceConfig::openai_chat(),
@@ -432,3 +434,104 @@ async fn cache_enabled_per_router() {
          default router"
     );
 }
+
+/// Test that `no-store`, `no-cache` and `max-age` request directives are
+/// honored, and reported in the `x-cache` and `age` response headers.
+#[tokio::test]
+#[serial_test::serial(default_mock)]
+async fn cache_respects_client_directives() {
+    let mut config = Config::test_default();
+    config.global.cache = Some(CacheConfig::test_default());
+
+    let mock_args = MockArgs::builder()
+        .stubs(HashMap::from([
+            ("success:openai:chat_completion_cacheable", 3.into()),
+            ("success:minio:upload_request", 4.into()),
+            ("success:jawn:sign_s3_url", 4.into()),
+            ("success:jawn:log_request", 4.into()),
+        ]))
+        .build();
+
+    let mut harness = Harness::builder()
+        .with_config(config)
+        .with_mock_args(mock_args)
+        .with_mock_auth()
+        .build()
+        .await;
+    let url = "http://router.helicone.com/ai/chat/completions";
+
+    let request = make_request(url, Some(("cache-control", "max-age=3600")));
+    let response = harness.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("x-cache").unwrap(), "MISS");
+    assert!(response.headers().get("age").is_none());
+    let _response_body = response.into_body().collect().await.unwrap();
+
+    let request = make_request(url, Some(("cache-control", "max-age=3600")));
+    let response = harness.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("x-cache").unwrap(), "HIT");
+    assert_eq!(response.headers().get("age").unwrap(), "0");
+    let _response_body = response.into_body().collect().await.unwrap();
+
+    // no-store bypasses the cache entirely
+    let request = make_request(url, Some(("cache-control", "no-store")));
+    let response = harness.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("x-cache").unwrap(), "BYPASS");
+    assert!(response.headers().get("helicone-cache").is_none());
+    let _response_body = response.into_body().collect().await.unwrap();
+
+    // no-cache skips the lookup, even though a fresh response is cached
+    let request =
+        make_request(url, Some(("cache-control", "no-cache, max-age=3600")));
+    let response = harness.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("x-cache").unwrap(), "MISS");
+    let _response_body = response.into_body().collect().await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
+
+/// Test that client directives are ignored when they aren't trusted.
+#[tokio::test]
+#[serial_test::serial(default_mock)]
+async fn cache_ignores_untrusted_client_directives() {
+    let mut config = Config::test_default();
+    config.global.cache = Some(CacheConfig {
+        directive: Some("max-age=3600".to_string()),
+        trust_client_directives: false,
+        ..CacheConfig::test_default()
+    });
+
+    let mock_args = MockArgs::builder()
+        .stubs(HashMap::from([
+            ("success:openai:chat_completion_cacheable", 1.into()),
+            ("success:minio:upload_request", 2.into()),
+            ("success:jawn:sign_s3_url", 2.into()),
+            ("success:jawn:log_request", 2.into()),
+        ]))
+        .build();
+
+    let mut harness = Harness::builder()
+        .with_config(config)
+        .with_mock_args(mock_args)
+        .with_mock_auth()
+        .build()
+        .await;
+    let url = "http://router.helicone.com/ai/chat/completions";
+
+    let request = make_request(url, Some(("cache-control", "no-store")));
+    let response = harness.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("x-cache").unwrap(), "MISS");
+    let _response_body = response.into_body().collect().await.unwrap();
+
+    let request = make_request(url, Some(("cache-control", "no-cache")));
+    let response = harness.call(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("x-cache").unwrap(), "HIT");
+    let _response_body = response.into_body().collect().await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+}
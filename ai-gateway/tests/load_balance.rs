@@ -36,6 +36,19 @@ fn p2c_config_openai_anthropic_google() -> RouterConfigs {
             retries: None,
             rate_limit: None,
             providers: None,
+            latency_slo: None,
+            differential: None,
+            stream_transforms: None,
+            output_limits: None,
+            conversations: None,
+            retry_budget: None,
+            retry_on: None,
+            webhooks: None,
+            wasm_filters: None,
+            script: None,
+            prefer_throughput: None,
+            bedrock_guardrail: None,
+            data_residency: None,
         },
     )]))
 }
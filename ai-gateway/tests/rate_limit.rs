@@ -165,11 +165,15 @@ async fn rate_limit_per_user_isolation_impl(
                 key_hash: hash_key(user1_auth),
                 owner_id: user1_id.into(),
                 organization_id: OrgId::new(org1_id),
+                tier: None,
+                max_priority: None,
             },
             Key {
                 key_hash: hash_key(user2_auth),
                 owner_id: user2_id.into(),
                 organization_id: OrgId::new(org2_id),
+                tier: None,
+                max_priority: None,
             },
         ])
         .build()
@@ -24,6 +24,7 @@ fn create_test_limits(capacity: u32, duration_ms: u64) -> LimitsConfig {
             capacity: capacity.try_into().unwrap(),
             refill_frequency: Duration::from_millis(duration_ms),
         },
+        total: None,
     }
 }
 
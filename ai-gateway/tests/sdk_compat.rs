@@ -0,0 +1,417 @@
+//! Runs request and response bodies recorded from the official `OpenAI`,
+//! Anthropic and Cohere SDKs, under `tests/fixtures/sdk`, through every mapper
+//! direction.
+//!
+//! A fixture is `{"description": ..., "body": ...}`, with a
+//! `"lossy-round-trip"` reason if mapping it to the other API and back is
+//! known to drop part of it. When a provider SDK is bumped, re-record the
+//! fixtures with the new SDK version: a mapping regression then fails here
+//! rather than in production.
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Arc,
+};
+
+use ai_gateway::{
+    app::App,
+    app_state::AppState,
+    config::{Config, router::RouterConfig},
+    endpoints::{
+        ApiEndpoint,
+        anthropic::Anthropic,
+        cohere::{Cohere, chat::ChatResponse},
+        openai::OpenAI,
+    },
+    middleware::mapper::{
+        TryConvert, anthropic::AnthropicConverter, model::ModelMapper,
+        openai::OpenAIConverter, registry::EndpointConverterRegistry,
+    },
+    tests::TestDefault,
+    types::{model_id::ModelId, provider::InferenceProvider},
+};
+use anthropic_ai_sdk::types::message::{
+    CreateMessageParams, CreateMessageResponse,
+};
+use async_openai::types::{
+    CreateChatCompletionRequest, CreateChatCompletionResponse,
+};
+use bytes::Bytes;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
+
+/// The providers `OpenAI` requests are mapped to, with the model each is
+/// mapped to.
+const TARGETS: &[(InferenceProvider, &str)] = &[
+    (InferenceProvider::OpenAI, "openai/gpt-4o-mini"),
+    (InferenceProvider::Anthropic, "anthropic/claude-3-5-haiku"),
+    (InferenceProvider::GoogleGemini, "gemini/gemini-2.0-flash"),
+    (
+        InferenceProvider::Bedrock,
+        "bedrock/anthropic.claude-3-5-haiku-20241022-v1:0",
+    ),
+    (InferenceProvider::Ollama, "ollama/llama3:8b"),
+    (InferenceProvider::AzureOpenAI, "azure/gpt-4o-prod"),
+    (InferenceProvider::Cohere, "cohere/command-r"),
+];
+
+/// The keys of the strings a mapping must carry over: message and system
+/// texts, and tool names.
+const CONTENT_KEYS: &[&str] = &["content", "text", "system", "name"];
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Fixture {
+    description: String,
+    body: Value,
+    lossy_round_trip: Option<String>,
+}
+
+fn fixtures(dir: &str) -> Vec<(PathBuf, Fixture)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures/sdk")
+        .join(dir);
+    let mut paths = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("failed to read {}: {e}", dir.display()))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    assert!(!paths.is_empty(), "no fixtures in {}", dir.display());
+    paths
+        .into_iter()
+        .map(|path| {
+            let fixture = serde_json::from_slice(&fs::read(&path).unwrap())
+                .unwrap_or_else(|e| {
+                    panic!("invalid fixture {}: {e}", path.display())
+                });
+            (path, fixture)
+        })
+        .collect()
+}
+
+/// Deserializes `fixture` into its SDK type, asserting that serializing it
+/// again keeps every field the SDK sent.
+fn parse<T: Serialize + DeserializeOwned>(path: &Path, fixture: &Fixture) -> T {
+    let parsed: T = serde_json::from_value(fixture.body.clone())
+        .unwrap_or_else(|e| {
+            panic!(
+                "{} ({}) doesn't deserialize: {e}",
+                path.display(),
+                fixture.description
+            )
+        });
+    let reserialized = serde_json::to_value(&parsed).unwrap();
+    assert_subset(&fixture.body, &reserialized, &path.display().to_string());
+    parsed
+}
+
+/// Asserts that `actual` has every field of `expected`, with equal values.
+/// `null` fields may be omitted.
+fn assert_subset(expected: &Value, actual: &Value, at: &str) {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => {
+            for (key, value) in expected {
+                match actual.get(key) {
+                    Some(actual) => {
+                        assert_subset(value, actual, &format!("{at}.{key}"));
+                    }
+                    None => {
+                        assert!(value.is_null(), "{at}.{key} was dropped");
+                    }
+                }
+            }
+        }
+        (Value::Array(expected), Value::Array(actual)) => {
+            assert_eq!(expected.len(), actual.len(), "{at} changed length");
+            for (i, (expected, actual)) in
+                expected.iter().zip(actual).enumerate()
+            {
+                assert_subset(expected, actual, &format!("{at}[{i}]"));
+            }
+        }
+        (Value::Number(expected), Value::Number(actual)) => {
+            assert_eq!(expected.as_f64(), actual.as_f64(), "{at} changed");
+        }
+        (expected, actual) => assert_eq!(expected, actual, "{at} changed"),
+    }
+}
+
+/// The strings under [`CONTENT_KEYS`] in `value`, sorted.
+fn content(value: &Value) -> Vec<String> {
+    fn collect(value: &Value, out: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                for (key, value) in map {
+                    match value {
+                        Value::String(s) if CONTENT_KEYS.contains(&&**key) => {
+                            out.push(s.clone());
+                        }
+                        value => collect(value, out),
+                    }
+                }
+            }
+            Value::Array(values) => {
+                values.iter().for_each(|value| collect(value, out));
+            }
+            _ => {}
+        }
+    }
+    let mut out = Vec::new();
+    collect(value, &mut out);
+    out.sort();
+    out
+}
+
+/// Every string in `value`, whatever its key, since the target formats
+/// name their fields differently.
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(s) => vec![s.clone()],
+        Value::Array(values) => values.iter().flat_map(strings).collect(),
+        Value::Object(map) => map.values().flat_map(strings).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn assert_content_kept(source: &Value, target: &Value, at: &str) {
+    let target = strings(target);
+    for text in content(source) {
+        assert!(target.contains(&text), "{at} dropped {text:?}");
+    }
+}
+
+fn model_mapper(app_state: &AppState, model: &str) -> ModelMapper {
+    ModelMapper::new_with_model_id(
+        app_state.clone(),
+        Arc::new(RouterConfig::default()),
+        ModelId::from_str(model).unwrap(),
+    )
+}
+
+fn ok_parts() -> http::response::Parts {
+    http::Response::new(()).into_parts().0
+}
+
+async fn app_state() -> AppState {
+    App::new(Config::test_default()).await.unwrap().state
+}
+
+#[tokio::test]
+async fn openai_requests_map_to_every_provider() {
+    let app_state = app_state().await;
+    let source = ApiEndpoint::OpenAI(OpenAI::chat_completions());
+    for (provider, model) in TARGETS {
+        let registry =
+            EndpointConverterRegistry::new(&model_mapper(&app_state, model));
+        let target = ApiEndpoint::mapped(source.clone(), provider).unwrap();
+        let converter = registry
+            .get_converter(&source, &target)
+            .unwrap_or_else(|| panic!("no converter to {provider}"));
+        for (path, fixture) in fixtures("openai/requests") {
+            let _: CreateChatCompletionRequest = parse(&path, &fixture);
+            let at = format!("{} to {provider}", path.display());
+            let (mapped, ctx) = converter
+                .convert_req_body(Bytes::from(fixture.body.to_string()))
+                .unwrap_or_else(|e| panic!("{at} failed: {e:?}"));
+            let mapped: Value = serde_json::from_slice(&mapped).unwrap();
+            assert_content_kept(&fixture.body, &mapped, &at);
+            assert_eq!(
+                ctx.is_stream,
+                fixture.body["stream"] == Value::Bool(true),
+                "{at} changed streaming"
+            );
+        }
+    }
+}
+
+#[tokio::test]
+async fn anthropic_responses_map_to_openai() {
+    let app_state = app_state().await;
+    let registry = EndpointConverterRegistry::new(&model_mapper(
+        &app_state,
+        "anthropic/claude-3-5-haiku",
+    ));
+    let converter = registry
+        .get_converter(
+            &ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+            &ApiEndpoint::Anthropic(Anthropic::messages()),
+        )
+        .unwrap();
+    for (path, fixture) in fixtures("anthropic/responses") {
+        let _: CreateMessageResponse = parse(&path, &fixture);
+        let at = path.display().to_string();
+        let mapped = converter
+            .convert_resp_body(
+                ok_parts(),
+                Bytes::from(fixture.body.to_string()),
+                false,
+            )
+            .unwrap_or_else(|e| panic!("{at} failed: {e:?}"))
+            .unwrap();
+        let mapped: CreateChatCompletionResponse =
+            serde_json::from_slice(&mapped).unwrap();
+        let mapped = serde_json::to_value(&mapped).unwrap();
+        assert_content_kept(&fixture.body, &mapped, &at);
+        let usage = &fixture.body["usage"];
+        assert_eq!(mapped["usage"]["prompt_tokens"], usage["input_tokens"]);
+        assert_eq!(
+            mapped["usage"]["completion_tokens"],
+            usage["output_tokens"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn cohere_responses_map_to_openai() {
+    let app_state = app_state().await;
+    let registry = EndpointConverterRegistry::new(&model_mapper(
+        &app_state,
+        "cohere/command-r",
+    ));
+    let converter = registry
+        .get_converter(
+            &ApiEndpoint::OpenAI(OpenAI::chat_completions()),
+            &ApiEndpoint::Cohere(Cohere::chat()),
+        )
+        .unwrap();
+    for (path, fixture) in fixtures("cohere/responses") {
+        let _: ChatResponse = parse(&path, &fixture);
+        let at = path.display().to_string();
+        let mapped = converter
+            .convert_resp_body(
+                ok_parts(),
+                Bytes::from(fixture.body.to_string()),
+                false,
+            )
+            .unwrap_or_else(|e| panic!("{at} failed: {e:?}"))
+            .unwrap();
+        let mapped: CreateChatCompletionResponse =
+            serde_json::from_slice(&mapped).unwrap();
+        let mapped = serde_json::to_value(&mapped).unwrap();
+        assert_content_kept(&fixture.body, &mapped, &at);
+        let tokens = &fixture.body["usage"]["tokens"];
+        assert_eq!(mapped["usage"]["prompt_tokens"], tokens["input_tokens"]);
+        assert_eq!(
+            mapped["usage"]["completion_tokens"],
+            tokens["output_tokens"]
+        );
+    }
+}
+
+#[tokio::test]
+async fn openai_responses_pass_through() {
+    let app_state = app_state().await;
+    let registry = EndpointConverterRegistry::new(&model_mapper(
+        &app_state,
+        "openai/gpt-4o-mini",
+    ));
+    let endpoint = ApiEndpoint::OpenAI(OpenAI::chat_completions());
+    let converter = registry.get_converter(&endpoint, &endpoint).unwrap();
+    for (path, fixture) in fixtures("openai/responses") {
+        let _: CreateChatCompletionResponse = parse(&path, &fixture);
+        let mapped = converter
+            .convert_resp_body(
+                ok_parts(),
+                Bytes::from(fixture.body.to_string()),
+                false,
+            )
+            .unwrap()
+            .unwrap();
+        let mapped: Value = serde_json::from_slice(&mapped).unwrap();
+        assert_subset(&fixture.body, &mapped, &path.display().to_string());
+    }
+}
+
+#[tokio::test]
+async fn requests_round_trip() {
+    let app_state = app_state().await;
+    let to_anthropic = AnthropicConverter::new(model_mapper(
+        &app_state,
+        "anthropic/claude-3-5-haiku",
+    ));
+    let to_openai =
+        OpenAIConverter::new(model_mapper(&app_state, "openai/gpt-4o-mini"));
+
+    for (path, fixture) in fixtures("openai/requests") {
+        if fixture.lossy_round_trip.is_some() {
+            continue;
+        }
+        let request: CreateChatCompletionRequest = parse(&path, &fixture);
+        let mut anthropic: CreateMessageParams =
+            to_anthropic.try_convert(request).unwrap();
+        anthropic.model = format!("anthropic/{}", anthropic.model);
+        let request: CreateChatCompletionRequest =
+            to_openai.try_convert(anthropic).unwrap();
+        assert_eq!(
+            content(&serde_json::to_value(&request).unwrap()),
+            content(&fixture.body),
+            "{} changed",
+            path.display()
+        );
+    }
+
+    for (path, fixture) in fixtures("anthropic/requests") {
+        if fixture.lossy_round_trip.is_some() {
+            continue;
+        }
+        let request: CreateMessageParams = parse(&path, &fixture);
+        let mut openai: CreateChatCompletionRequest =
+            to_openai.try_convert(request).unwrap();
+        openai.model = format!("openai/{}", openai.model);
+        let request: CreateMessageParams =
+            to_anthropic.try_convert(openai).unwrap();
+        assert_eq!(
+            content(&serde_json::to_value(&request).unwrap()),
+            content(&fixture.body),
+            "{} changed",
+            path.display()
+        );
+    }
+}
+
+#[tokio::test]
+async fn responses_round_trip() {
+    let app_state = app_state().await;
+    let anthropic_converter = AnthropicConverter::new(model_mapper(
+        &app_state,
+        "anthropic/claude-3-5-haiku",
+    ));
+    let openai_converter =
+        OpenAIConverter::new(model_mapper(&app_state, "openai/gpt-4o-mini"));
+
+    for (path, fixture) in fixtures("openai/responses") {
+        if fixture.lossy_round_trip.is_some() {
+            continue;
+        }
+        let response: CreateChatCompletionResponse = parse(&path, &fixture);
+        let anthropic: CreateMessageResponse =
+            openai_converter.try_convert(response).unwrap();
+        let response: CreateChatCompletionResponse =
+            anthropic_converter.try_convert(anthropic).unwrap();
+        assert_eq!(
+            content(&serde_json::to_value(&response).unwrap()),
+            content(&fixture.body),
+            "{} changed",
+            path.display()
+        );
+    }
+
+    for (path, fixture) in fixtures("anthropic/responses") {
+        if fixture.lossy_round_trip.is_some() {
+            continue;
+        }
+        let response: CreateMessageResponse = parse(&path, &fixture);
+        let openai: CreateChatCompletionResponse =
+            anthropic_converter.try_convert(response).unwrap();
+        let response: CreateMessageResponse =
+            openai_converter.try_convert(openai).unwrap();
+        assert_eq!(
+            content(&serde_json::to_value(&response).unwrap()),
+            content(&fixture.body),
+            "{} changed",
+            path.display()
+        );
+    }
+}
@@ -31,6 +31,7 @@ use std::{
     marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use futures::ready;
@@ -38,7 +39,7 @@ use http::Request;
 use pin_project_lite::pin_project;
 use tower::{Service, discover::Discover};
 
-use super::DynamicRouter;
+use super::{DEFAULT_DRAIN_TIMEOUT, DynamicRouter};
 
 /// Constructs load balancers over dynamic service sets produced by a wrapped
 /// "inner" service.
@@ -57,6 +58,7 @@ use super::DynamicRouter;
 /// [`Balance`]: crate::balance::p2c::Balance
 pub struct MakeRouter<S, ReqBody> {
     inner: S,
+    drain_timeout: Duration,
     _marker: PhantomData<fn(ReqBody)>,
 }
 
@@ -64,6 +66,7 @@ pin_project! {
     pub struct MakeFuture<F, ReqBody> {
         #[pin]
         inner: F,
+        drain_timeout: Duration,
         _marker: PhantomData<fn(ReqBody)>,
     }
 }
@@ -73,9 +76,18 @@ impl<S, ReqBody> MakeRouter<S, ReqBody> {
     pub const fn new(make_discover: S) -> Self {
         Self {
             inner: make_discover,
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
             _marker: PhantomData,
         }
     }
+
+    /// Sets how long the services removed from the routers may take to
+    /// complete their in-flight requests before they are evicted anyway.
+    #[must_use]
+    pub const fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
 }
 
 impl<S, ReqBody> Clone for MakeRouter<S, ReqBody>
@@ -85,6 +97,7 @@ where
     fn clone(&self) -> Self {
         Self {
             inner: self.inner.clone(),
+            drain_timeout: self.drain_timeout,
             _marker: PhantomData,
         }
     }
@@ -112,6 +125,7 @@ where
     fn call(&mut self, target: Target) -> Self::Future {
         MakeFuture {
             inner: self.inner.call(target),
+            drain_timeout: self.drain_timeout,
             _marker: PhantomData,
         }
     }
@@ -122,8 +136,15 @@ where
     S: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let Self { inner, _marker } = self;
-        f.debug_struct("MakeRouter").field("inner", inner).finish()
+        let Self {
+            inner,
+            drain_timeout,
+            _marker,
+        } = self;
+        f.debug_struct("MakeRouter")
+            .field("inner", inner)
+            .field("drain_timeout", drain_timeout)
+            .finish()
     }
 }
 
@@ -139,7 +160,8 @@ where
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
         let inner = ready!(this.inner.poll(cx))?;
-        let svc = DynamicRouter::new(inner);
+        let svc =
+            DynamicRouter::new(inner).with_drain_timeout(*this.drain_timeout);
         Poll::Ready(Ok(svc))
     }
 }
@@ -149,7 +171,14 @@ where
     F: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let Self { inner, _marker } = self;
-        f.debug_struct("MakeFuture").field("inner", inner).finish()
+        let Self {
+            inner,
+            drain_timeout,
+            _marker,
+        } = self;
+        f.debug_struct("MakeFuture")
+            .field("inner", inner)
+            .field("drain_timeout", drain_timeout)
+            .finish()
     }
 }
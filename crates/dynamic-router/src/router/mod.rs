@@ -26,22 +26,32 @@
 pub mod make;
 
 use std::{
+    collections::HashMap,
     convert::Infallible,
     fmt::{self, Display},
     hash::Hash,
     marker::PhantomData,
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
+    time::Duration,
 };
 
 use futures::ready;
 use pin_project::pin_project;
+use tokio::time::{Instant, Interval, MissedTickBehavior};
 use tower::{
     Service,
     discover::{Change, Discover},
     ready_cache::ReadyCache,
 };
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
+
+/// How long a removed service may take to complete its in-flight requests
+/// before it is evicted anyway.
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often draining services are checked for eviction.
+const EVICT_INTERVAL: Duration = Duration::from_secs(1);
 
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
@@ -62,6 +72,16 @@ where
 
     services: ReadyCache<D::Key, D::Service, http::Request<ReqBody>>,
 
+    /// Held by the in-flight requests of each service.
+    in_flight: HashMap<D::Key, Arc<()>>,
+    /// Removed services which no longer accept requests, with the deadline
+    /// by which they are evicted.
+    draining: HashMap<D::Key, Instant>,
+    drain_timeout: Duration,
+    /// Ticks while services are draining, so that they are evicted once
+    /// drained even if the router isn't polled otherwise.
+    evict_interval: Option<Interval>,
+
     _req: PhantomData<ReqBody>,
 }
 
@@ -72,9 +92,22 @@ where
     D::Service: fmt::Debug,
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let draining = self
+            .draining
+            .iter()
+            .map(|(key, deadline)| {
+                let in_flight = self
+                    .in_flight
+                    .get(key)
+                    .map_or(0, |token| Arc::strong_count(token) - 1);
+                (key, (in_flight, *deadline))
+            })
+            .collect::<HashMap<_, _>>();
         f.debug_struct("DynamicRouter")
             .field("discover", &self.discover)
             .field("services", &self.services)
+            .field("draining", &draining)
+            .field("drain_timeout", &self.drain_timeout)
             .finish_non_exhaustive()
     }
 }
@@ -90,11 +123,29 @@ where
         Self {
             discover,
             services: ReadyCache::default(),
+            in_flight: HashMap::new(),
+            draining: HashMap::new(),
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            evict_interval: None,
 
             _req: PhantomData,
         }
     }
 
+    /// Sets how long a removed service may take to complete its in-flight
+    /// requests before it is evicted anyway.
+    #[must_use]
+    pub fn with_drain_timeout(mut self, drain_timeout: Duration) -> Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Returns the number of removed endpoints waiting for their in-flight
+    /// requests to complete.
+    pub fn draining_len(&self) -> usize {
+        self.draining.len()
+    }
+
     /// Returns the number of endpoints currently tracked by the balancer.
     pub fn len(&self) -> usize {
         self.services.len()
@@ -129,10 +180,22 @@ where
                 None => return Poll::Ready(None),
                 Some(Change::Remove(key)) => {
                     trace!("remove");
-                    self.services.evict(&key);
+                    let now = Instant::now();
+                    self.draining.insert(key, now + self.drain_timeout);
+                    self.evict_interval.get_or_insert_with(|| {
+                        let mut interval = tokio::time::interval_at(
+                            now + EVICT_INTERVAL,
+                            EVICT_INTERVAL,
+                        );
+                        interval.set_missed_tick_behavior(
+                            MissedTickBehavior::Delay,
+                        );
+                        interval
+                    });
                 }
                 Some(Change::Insert(key, svc)) => {
                     trace!("insert");
+                    self.draining.remove(&key);
                     // If this service already existed in the set, it will be
                     // replaced as the new one becomes ready.
                     self.services.push(key, svc);
@@ -141,6 +204,47 @@ where
         }
     }
 
+    /// Evicts the removed services which completed their in-flight requests
+    /// or reached their drain deadline, and schedules the next check while
+    /// services are still draining.
+    fn evict_drained(&mut self, cx: &mut Context<'_>) {
+        let now = Instant::now();
+        let in_flight = &self.in_flight;
+        let drained = self
+            .draining
+            .iter()
+            .filter_map(|(key, deadline)| {
+                let in_flight = in_flight
+                    .get(key)
+                    .map_or(0, |token| Arc::strong_count(token) - 1);
+                if in_flight == 0 {
+                    return Some(key.clone());
+                }
+                if *deadline <= now {
+                    warn!(
+                        key = %key,
+                        in_flight,
+                        "evicting service before its requests completed"
+                    );
+                    return Some(key.clone());
+                }
+                None
+            })
+            .collect::<Vec<_>>();
+        for key in drained {
+            trace!(key = %key, "evict drained");
+            self.draining.remove(&key);
+            self.in_flight.remove(&key);
+            self.services.evict(&key);
+        }
+        if self.draining.is_empty() {
+            self.evict_interval = None;
+        } else if let Some(interval) = &mut self.evict_interval {
+            // registers for a wake up at the next tick
+            while interval.poll_tick(cx).is_ready() {}
+        }
+    }
+
     fn promote_pending_to_ready(&mut self, cx: &mut Context<'_>) {
         loop {
             match self.services.poll_pending(cx) {
@@ -189,6 +293,7 @@ where
         cx: &mut Context<'_>,
     ) -> Poll<Result<(), Self::Error>> {
         let _ = self.update_pending_from_discover(cx)?;
+        self.evict_drained(cx);
         self.promote_pending_to_ready(cx);
         Poll::Ready(Ok(()))
     }
@@ -200,9 +305,19 @@ where
             };
         };
 
-        if let Some((_, _, _)) = self.services.get_ready(&key) {
+        if self.draining.contains_key(&key) {
+            // removed services don't accept new requests while draining
+            ResponseFuture::Ready {
+                error: Some(Error::RouterNotFound(key.to_string())),
+            }
+        } else if let Some((_, _, _)) = self.services.get_ready(&key) {
+            let in_flight =
+                self.in_flight.entry(key.clone()).or_default().clone();
             let future = self.services.call_ready(&key, request);
-            ResponseFuture::Inner { future }
+            ResponseFuture::Inner {
+                future,
+                _in_flight: in_flight,
+            }
         } else {
             ResponseFuture::Ready {
                 error: Some(Error::RouterNotFound(key.to_string())),
@@ -229,6 +344,8 @@ where
     Inner {
         #[pin]
         future: <D::Service as Service<http::Request<ReqBody>>>::Future,
+        /// Keeps the service from being evicted while draining.
+        _in_flight: Arc<()>,
     },
 }
 
@@ -253,7 +370,7 @@ where
             ResponseFutureProj::Ready { error } => Poll::Ready(Err(error
                 .take()
                 .expect("future polled after completion"))),
-            ResponseFutureProj::Inner { future } => {
+            ResponseFutureProj::Inner { future, .. } => {
                 match ready!(future.poll(cx)) {
                     Ok(res) => Poll::Ready(Ok(res)),
                     // never happens due to `Infallible` bound
@@ -263,3 +380,93 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc;
+    use tokio::sync::oneshot;
+    use tokio_test::{assert_pending, assert_ready, assert_ready_ok, task};
+    use tower::service_fn;
+
+    use super::*;
+
+    type Body = Option<oneshot::Receiver<()>>;
+
+    fn request(key: &'static str, body: Body) -> http::Request<Body> {
+        let mut request = http::Request::new(body);
+        request.extensions_mut().insert(key);
+        request
+    }
+
+    /// Responds once the request's body, if any, is sent a value.
+    async fn respond(request: http::Request<Body>) -> Result<(), Infallible> {
+        if let Some(done) = request.into_body() {
+            let _ = done.await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn removed_services_drain_before_eviction() {
+        let (tx, rx) = mpsc::unbounded();
+        tx.unbounded_send(Ok::<_, Infallible>(Change::Insert(
+            "a",
+            service_fn(respond),
+        )))
+        .unwrap();
+        let mut router = DynamicRouter::new(rx);
+        let mut cx = task::spawn(());
+        assert_ready_ok!(cx.enter(|cx, _| router.poll_ready(cx)));
+
+        let (done_tx, done_rx) = oneshot::channel();
+        let mut in_flight =
+            task::spawn(router.call(request("a", Some(done_rx))));
+        assert_pending!(in_flight.poll());
+
+        tx.unbounded_send(Ok(Change::Remove("a"))).unwrap();
+        assert_ready_ok!(cx.enter(|cx, _| router.poll_ready(cx)));
+        assert_eq!(router.draining_len(), 1);
+        assert_eq!(router.len(), 1);
+        let mut rejected = task::spawn(router.call(request("a", None)));
+        assert!(matches!(
+            assert_ready!(rejected.poll()),
+            Err(Error::RouterNotFound(_))
+        ));
+
+        done_tx.send(()).unwrap();
+        assert_ready_ok!(in_flight.poll());
+        drop(in_flight);
+        assert_ready_ok!(cx.enter(|cx, _| router.poll_ready(cx)));
+        assert_eq!(router.draining_len(), 0);
+        assert!(router.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn removed_services_are_evicted_at_their_deadline() {
+        let (tx, rx) = mpsc::unbounded();
+        tx.unbounded_send(Ok::<_, Infallible>(Change::Insert(
+            "a",
+            service_fn(respond),
+        )))
+        .unwrap();
+        let mut router =
+            DynamicRouter::new(rx).with_drain_timeout(Duration::from_secs(5));
+        let mut cx = task::spawn(());
+        assert_ready_ok!(cx.enter(|cx, _| router.poll_ready(cx)));
+
+        let (_done_tx, done_rx) = oneshot::channel();
+        let mut in_flight =
+            task::spawn(router.call(request("a", Some(done_rx))));
+        assert_pending!(in_flight.poll());
+        tx.unbounded_send(Ok(Change::Remove("a"))).unwrap();
+        assert_ready_ok!(cx.enter(|cx, _| router.poll_ready(cx)));
+        assert_eq!(router.draining_len(), 1);
+
+        // the eviction check wakes the router without any new request
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert!(cx.is_woken());
+        assert_ready_ok!(cx.enter(|cx, _| router.poll_ready(cx)));
+        assert_eq!(router.draining_len(), 0);
+        assert!(router.is_empty());
+    }
+}
@@ -0,0 +1,202 @@
+//! Calls to the admin API, under `/admin/v1`.
+//!
+//! Endpoints which are only served with an optional feature, such as the
+//! dead-letter queue or chaos, respond with a `404` if it's disabled.
+use ai_gateway::{
+    logger::{correlation::Correlation, dlq::DeadLetter},
+    middleware::mapper::model::StoredModelMapping,
+    store::usage::DailyUsage,
+    types::router::RouterId,
+    utils::{
+        admin::{
+            ActiveFault, DeadLettersResponse, Fault, FaultsResponse,
+            FreezesResponse, LiftedFreezesResponse, ModelMappingsResponse,
+            RemovedFaultsResponse, ReplayResponse, RequestsResponse,
+            UsageReportResponse,
+        },
+        freeze::{ActiveFreeze, Freeze},
+    },
+};
+use chrono::NaiveDate;
+use uuid::Uuid;
+
+use crate::{Client, Error};
+
+const ADMIN_PATH_PREFIX: &str = "admin/v1/";
+
+#[derive(Debug, Clone)]
+pub struct AdminClient {
+    client: Client,
+}
+
+impl AdminClient {
+    pub(crate) fn new(client: Client) -> Self {
+        Self { client }
+    }
+
+    /// Lists the logs in the dead-letter queue.
+    pub async fn dead_letters(&self) -> Result<Vec<DeadLetter>, Error> {
+        let response: DeadLettersResponse = self.get("dlq").await?;
+        Ok(response.entries)
+    }
+
+    /// Retries the log `id` in the dead-letter queue, or all of them,
+    /// returning the number of logs scheduled to be retried.
+    pub async fn replay_dead_letters(
+        &self,
+        id: Option<Uuid>,
+    ) -> Result<usize, Error> {
+        let route = match id {
+            Some(id) => format!("dlq/{id}/replay"),
+            None => "dlq/replay".to_string(),
+        };
+        let url = self.url(&route)?;
+        let response: ReplayResponse = self
+            .client
+            .send_for_json(self.client.http.post(url))
+            .await?;
+        Ok(response.scheduled)
+    }
+
+    /// The IDs of recent requests known by `id`, any one of their gateway,
+    /// Helicone, provider or trace IDs.
+    pub async fn requests(&self, id: &str) -> Result<Vec<Correlation>, Error> {
+        let response: RequestsResponse =
+            self.get(&format!("requests/{id}")).await?;
+        Ok(response.requests)
+    }
+
+    /// The usage per provider, model and day from `from` to `to`, both
+    /// inclusive.
+    pub async fn usage_report(
+        &self,
+        from: NaiveDate,
+        to: NaiveDate,
+    ) -> Result<Vec<DailyUsage>, Error> {
+        let mut url = self.url("usage/report")?;
+        url.query_pairs_mut()
+            .append_pair("from", &from.format("%Y-%m-%d").to_string())
+            .append_pair("to", &to.format("%Y-%m-%d").to_string());
+        let response: UsageReportResponse =
+            self.client.send_for_json(self.client.http.get(url)).await?;
+        Ok(response.usage)
+    }
+
+    /// Lists the faults being injected.
+    pub async fn faults(&self) -> Result<Vec<ActiveFault>, Error> {
+        let response: FaultsResponse = self.get("chaos/faults").await?;
+        Ok(response.faults)
+    }
+
+    /// Starts injecting `fault`.
+    pub async fn add_fault(&self, fault: &Fault) -> Result<ActiveFault, Error> {
+        let url = self.url("chaos/faults")?;
+        let response = self
+            .client
+            .send_json(self.client.http.post(url), fault)
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Stops injecting the fault `id`, or every fault, returning the number
+    /// of faults removed.
+    pub async fn remove_faults(
+        &self,
+        id: Option<Uuid>,
+    ) -> Result<usize, Error> {
+        let route = match id {
+            Some(id) => format!("chaos/faults/{id}"),
+            None => "chaos/faults".to_string(),
+        };
+        let response: RemovedFaultsResponse = self.delete(&route).await?;
+        Ok(response.removed)
+    }
+
+    /// Lists the freezes of traffic in effect.
+    pub async fn freezes(&self) -> Result<Vec<ActiveFreeze>, Error> {
+        let response: FreezesResponse = self.get("freezes").await?;
+        Ok(response.freezes)
+    }
+
+    /// Freezes the traffic of `freeze`'s scope.
+    pub async fn freeze(&self, freeze: &Freeze) -> Result<ActiveFreeze, Error> {
+        let url = self.url("freezes")?;
+        let response = self
+            .client
+            .send_json(self.client.http.post(url), freeze)
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Lifts the freeze `id`, or every freeze, returning the number of
+    /// freezes lifted.
+    pub async fn lift_freezes(&self, id: Option<Uuid>) -> Result<usize, Error> {
+        let route = match id {
+            Some(id) => format!("freezes/{id}"),
+            None => "freezes".to_string(),
+        };
+        let response: LiftedFreezesResponse = self.delete(&route).await?;
+        Ok(response.lifted)
+    }
+
+    /// Lists the model mappings stored in the database.
+    pub async fn model_mappings(
+        &self,
+    ) -> Result<Vec<StoredModelMapping>, Error> {
+        let response: ModelMappingsResponse =
+            self.get("model-mappings").await?;
+        Ok(response.mappings)
+    }
+
+    /// Stores `mapping`, replacing the targets of its source model, if it's
+    /// already mapped.
+    pub async fn put_model_mapping(
+        &self,
+        mapping: &StoredModelMapping,
+    ) -> Result<StoredModelMapping, Error> {
+        let url = self.url("model-mappings")?;
+        let response = self
+            .client
+            .send_json(self.client.http.put(url), mapping)
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Deletes the stored mapping of `model`, of `router`'s model mapping or
+    /// the default one.
+    pub async fn delete_model_mapping(
+        &self,
+        router: Option<&RouterId>,
+        model: &str,
+    ) -> Result<(), Error> {
+        let mut url = self.url("model-mappings")?;
+        url.query_pairs_mut().append_pair("model", model);
+        if let Some(router) = router {
+            url.query_pairs_mut().append_pair("router", router.as_ref());
+        }
+        self.client.send(self.client.http.delete(url)).await?;
+        Ok(())
+    }
+
+    fn url(&self, route: &str) -> Result<url::Url, Error> {
+        self.client.url(&format!("{ADMIN_PATH_PREFIX}{route}"))
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        route: &str,
+    ) -> Result<T, Error> {
+        let url = self.url(route)?;
+        self.client.send_for_json(self.client.http.get(url)).await
+    }
+
+    async fn delete<T: serde::de::DeserializeOwned>(
+        &self,
+        route: &str,
+    ) -> Result<T, Error> {
+        let url = self.url(route)?;
+        self.client
+            .send_for_json(self.client.http.delete(url))
+            .await
+    }
+}
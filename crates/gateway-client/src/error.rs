@@ -0,0 +1,53 @@
+use displaydoc::Display;
+use http::StatusCode;
+use thiserror::Error;
+
+/// Errors returned by the gateway client.
+#[derive(Debug, Error, Display)]
+pub enum Error {
+    /// Invalid URL: {0}
+    InvalidUrl(#[from] url::ParseError),
+    /// Request failed: {0}
+    Request(#[from] reqwest::Error),
+    /// Gateway responded with {status}: {body}
+    Status {
+        status: StatusCode,
+        /// The error response, in the `OpenAI` error format.
+        body: serde_json::Value,
+    },
+    /// Invalid response body: {0}
+    InvalidBody(#[from] serde_json::Error),
+    /// Stream failed: {0}
+    Stream(String),
+    /// Streamed requests must be sent with `chat_completions_stream`
+    UnexpectedStream,
+}
+
+impl Error {
+    /// Returns `response` if it's successful, and its error otherwise.
+    pub(crate) async fn for_status(
+        response: reqwest::Response,
+    ) -> Result<reqwest::Response, Self> {
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.bytes().await?;
+        let body = serde_json::from_slice(&body).unwrap_or_else(|_| {
+            serde_json::Value::String(
+                String::from_utf8_lossy(&body).into_owned(),
+            )
+        });
+        Err(Self::Status { status, body })
+    }
+
+    /// The status of the gateway's error response, if it responded with one.
+    #[must_use]
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Self::Status { status, .. } => Some(*status),
+            Self::Request(e) => e.status(),
+            _ => None,
+        }
+    }
+}
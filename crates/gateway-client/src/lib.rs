@@ -0,0 +1,194 @@
+//! A typed async client for the AI gateway.
+//!
+//! Requests and responses re-use the gateway's own endpoint types, so a
+//! service consuming the gateway is checked against the same types the
+//! gateway serves:
+//!
+//! - [`Client::chat_completions`] and [`Client::chat_completions_stream`]
+//!   call the unified API (`/ai`) or a router (`/router/{id}`), depending on
+//!   the [`Target`].
+//! - [`Client::admin`] calls the admin API (`/admin/v1`), if the gateway has
+//!   an admin token.
+pub mod admin;
+pub mod error;
+
+use ai_gateway::{endpoints::EndpointRoute, types::router::RouterId};
+use async_openai::types::{
+    CreateChatCompletionRequest, CreateChatCompletionResponse,
+    CreateChatCompletionStreamResponse,
+};
+use eventsource_stream::Eventsource;
+use futures::{Stream, StreamExt, TryStreamExt, future};
+use serde::{Serialize, de::DeserializeOwned};
+use url::Url;
+
+pub use crate::{admin::AdminClient, error::Error};
+
+/// Sent as the last event of streamed chat completions.
+const STREAM_DONE: &str = "[DONE]";
+
+/// The API through which requests are sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// The unified API, for `{provider}/{model}` model ids.
+    UnifiedApi,
+    /// A router, balancing requests between its providers.
+    Router(RouterId),
+}
+
+impl Target {
+    fn path(&self, route: EndpointRoute) -> String {
+        match self {
+            Self::UnifiedApi => format!("ai/{}", route.path()),
+            Self::Router(id) => format!("router/{id}/{}", route.path()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub(crate) http: reqwest::Client,
+    base_url: Url,
+    api_key: Option<String>,
+}
+
+impl Client {
+    /// Creates a client for the gateway at `base_url`, e.g.
+    /// `http://localhost:8080`.
+    #[must_use]
+    pub fn new(base_url: Url) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    /// Creates a client sending requests with `http`, to configure its
+    /// timeouts, proxies or TLS.
+    #[must_use]
+    pub fn with_http_client(http: reqwest::Client, mut base_url: Url) -> Self {
+        if !base_url.path().ends_with('/') {
+            let path = format!("{}/", base_url.path());
+            base_url.set_path(&path);
+        }
+        Self {
+            http,
+            base_url,
+            api_key: None,
+        }
+    }
+
+    /// Authenticates requests with the Helicone API key, which the gateway
+    /// requires if `helicone.features` includes `auth`.
+    #[must_use]
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+
+    /// Returns a client for the admin API, authenticated with the gateway's
+    /// `server.admin-token`.
+    #[must_use]
+    pub fn admin(&self, admin_token: impl Into<String>) -> AdminClient {
+        AdminClient::new(self.clone().api_key(admin_token))
+    }
+
+    /// Creates a chat completion. The request must not be streamed.
+    pub async fn chat_completions(
+        &self,
+        target: &Target,
+        request: &CreateChatCompletionRequest,
+    ) -> Result<CreateChatCompletionResponse, Error> {
+        if request.stream == Some(true) {
+            return Err(Error::UnexpectedStream);
+        }
+        let url = self.url(&target.path(EndpointRoute::ChatCompletions))?;
+        let response = self.send_json(self.http.post(url), request).await?;
+        Ok(response.json().await?)
+    }
+
+    /// Creates a streamed chat completion, yielding its chunks until the
+    /// provider is done.
+    pub async fn chat_completions_stream(
+        &self,
+        target: &Target,
+        request: &CreateChatCompletionRequest,
+    ) -> Result<
+        impl Stream<Item = Result<CreateChatCompletionStreamResponse, Error>>
+        + use<>,
+        Error,
+    > {
+        let mut request = request.clone();
+        request.stream = Some(true);
+        let url = self.url(&target.path(EndpointRoute::ChatCompletions))?;
+        let response = self.send_json(self.http.post(url), &request).await?;
+        let chunks = response
+            .bytes_stream()
+            .eventsource()
+            .map_err(|e| Error::Stream(e.to_string()))
+            .try_take_while(|event| future::ok(event.data != STREAM_DONE))
+            .and_then(|event| {
+                future::ready(
+                    serde_json::from_str(&event.data).map_err(Error::from),
+                )
+            });
+        Ok(chunks)
+    }
+
+    pub(crate) fn url(&self, path: &str) -> Result<Url, Error> {
+        Ok(self.base_url.join(path)?)
+    }
+
+    pub(crate) async fn send_json(
+        &self,
+        request: reqwest::RequestBuilder,
+        body: &impl Serialize,
+    ) -> Result<reqwest::Response, Error> {
+        self.send(request.json(body)).await
+    }
+
+    /// Sends `request`, authenticated with the API key if there is one,
+    /// returning an error for unsuccessful statuses.
+    pub(crate) async fn send(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<reqwest::Response, Error> {
+        let request = match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        };
+        Error::for_status(request.send().await?).await
+    }
+
+    pub(crate) async fn send_for_json<T: DeserializeOwned>(
+        &self,
+        request: reqwest::RequestBuilder,
+    ) -> Result<T, Error> {
+        Ok(self.send(request).await?.json().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_paths() {
+        let route = EndpointRoute::ChatCompletions;
+        assert_eq!(Target::UnifiedApi.path(route), "ai/chat/completions");
+        assert_eq!(
+            Target::Router(RouterId::Named("my-router".into())).path(route),
+            "router/my-router/chat/completions"
+        );
+    }
+
+    #[test]
+    fn base_url_with_path_prefix() {
+        let client =
+            Client::new(Url::parse("http://localhost:8080/gateway").unwrap());
+        assert_eq!(
+            client
+                .url(&Target::UnifiedApi.path(EndpointRoute::ChatCompletions))
+                .unwrap()
+                .as_str(),
+            "http://localhost:8080/gateway/ai/chat/completions"
+        );
+    }
+}
@@ -0,0 +1,195 @@
+//! Integration testing of router configs against an in-process gateway.
+//!
+//! The gateway runs in the test's process, with the providers, Helicone's
+//! API and S3 replaced by mock servers serving canned responses, so that
+//! router configs can be tested in CI without provider keys:
+//!
+//! ```no_run
+//! use gateway_test_utils::{Gateway, http::StatusCode};
+//!
+//! # async fn example() {
+//! let mut gateway = Gateway::builder()
+//!     .routers_yaml(
+//!         r"
+//! my-router:
+//!   load-balance:
+//!     chat:
+//!       strategy: provider-weighted
+//!       providers:
+//!         - provider: openai
+//!           weight: '1'
+//! ",
+//!     )
+//!     .build()
+//!     .await;
+//! let response = gateway
+//!     .chat_completion("/router/my-router", "openai/gpt-4o-mini")
+//!     .await;
+//! assert_eq!(response.status(), StatusCode::OK);
+//! # }
+//! ```
+//!
+//! The mock servers listen on fixed ports, so tests using them can't run
+//! concurrently.
+use ai_gateway::config::{helicone::HeliconeFeatures, router::RouterConfigs};
+pub use ai_gateway::{
+    self,
+    app::AppResponse,
+    config::Config,
+    tests::{
+        TestDefault,
+        harness::{Harness, HarnessBuilder},
+        mock::{Mock, MockArgs},
+    },
+};
+pub use http;
+use serde_json::json;
+use tower::{Service, ServiceExt};
+
+/// A gateway running in-process against mock providers.
+pub struct Gateway {
+    harness: Harness,
+}
+
+#[derive(Default)]
+pub struct GatewayBuilder {
+    config: Option<Config>,
+    routers: Option<RouterConfigs>,
+    mock_args: Option<MockArgs>,
+}
+
+impl GatewayBuilder {
+    /// The config of the gateway, [`Config::test_default`] if not set.
+    #[must_use]
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// The routers under test, replacing those of the config.
+    #[must_use]
+    pub fn routers(mut self, routers: RouterConfigs) -> Self {
+        self.routers = Some(routers);
+        self
+    }
+
+    /// The routers under test, in the format of the `routers` section of a
+    /// config file.
+    ///
+    /// # Panics
+    ///
+    /// If `yaml` isn't a valid routers config.
+    #[must_use]
+    pub fn routers_yaml(self, yaml: &str) -> Self {
+        let routers =
+            serde_yml::from_str(yaml).expect("invalid routers config");
+        self.routers(routers)
+    }
+
+    /// Configures the mock servers, e.g. the latency of the providers or the
+    /// expected number of calls to each stub.
+    #[must_use]
+    pub fn mock_args(mut self, mock_args: MockArgs) -> Self {
+        self.mock_args = Some(mock_args);
+        self
+    }
+
+    fn into_config(self) -> (Config, Option<MockArgs>) {
+        let mut config = self.config.unwrap_or_else(Config::test_default);
+        if let Some(routers) = self.routers {
+            config.routers = routers;
+        }
+        // the routing is under test, not Helicone's authentication
+        config.helicone.features = HeliconeFeatures::None;
+        (config, self.mock_args)
+    }
+
+    /// Starts the mock servers and the gateway.
+    ///
+    /// # Panics
+    ///
+    /// If the config is invalid or the gateway fails to start.
+    pub async fn build(self) -> Gateway {
+        let (config, mock_args) = self.into_config();
+        config.validate().expect("invalid config");
+        let mut builder = Harness::builder().with_config(config);
+        if let Some(mock_args) = mock_args {
+            builder = builder.with_mock_args(mock_args);
+        }
+        Gateway {
+            harness: builder.build().await,
+        }
+    }
+}
+
+impl Gateway {
+    #[must_use]
+    pub fn builder() -> GatewayBuilder {
+        GatewayBuilder::default()
+    }
+
+    /// The mock servers, e.g. to verify the requests they received.
+    #[must_use]
+    pub fn mock(&self) -> &Mock {
+        &self.harness.mock
+    }
+
+    /// Sends `request` through the gateway.
+    pub async fn send(
+        &mut self,
+        request: http::Request<axum_core::body::Body>,
+    ) -> AppResponse {
+        let Ok(harness) = self.harness.ready().await;
+        let Ok(response) = harness.call(request).await;
+        response
+    }
+
+    /// Sends a chat completion request for `model` to the router or unified
+    /// API at `base_path`, e.g. `/router/my-router` or `/ai`.
+    ///
+    /// # Panics
+    ///
+    /// If `base_path` isn't a valid path.
+    pub async fn chat_completion(
+        &mut self,
+        base_path: &str,
+        model: &str,
+    ) -> AppResponse {
+        let body = json!({
+            "model": model,
+            "messages": [{"role": "user", "content": "Hello, world!"}],
+        });
+        let request = http::Request::builder()
+            .method(http::Method::POST)
+            .uri(format!(
+                "http://localhost{}/chat/completions",
+                base_path.trim_end_matches('/')
+            ))
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(axum_core::body::Body::from(body.to_string()))
+            .expect("invalid base path");
+        self.send(request).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routers_replace_those_of_the_config() {
+        let yaml = r"
+my-router:
+  load-balance:
+    chat:
+      strategy: provider-weighted
+      providers:
+        - provider: anthropic
+          weight: '1'
+";
+        let (config, _) = Gateway::builder().routers_yaml(yaml).into_config();
+        assert_eq!(config.routers.len(), 1);
+        assert!(config.validate().is_ok());
+        assert!(!config.helicone.is_auth_enabled());
+    }
+}
@@ -0,0 +1,183 @@
+//! Scripted control plane updates.
+//!
+//! Each websocket connection is first sent the mock config, then the n-th
+//! connection runs the n-th script of the timeline, if there is one:
+//!
+//! ```yaml
+//! control-plane:
+//!   connections:
+//!     - - after-ms: 1000
+//!         action:
+//!           type: push-keys
+//!           keys:
+//!             - key: sk-helicone-test-key
+//!             - key: sk-helicone-other-key
+//!               tier: free
+//!       - after-ms: 1000
+//!         action:
+//!           type: disconnect
+//!     # the reconnection
+//!     - - after-ms: 500
+//!         action:
+//!           type: unauthorized
+//!           message: key revoked
+//! ```
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use ai_gateway::control_plane::types::{
+    AuthData, ControlPlaneError, ControlPlaneState, Key, MessageTypeRX, Update,
+    hash_key,
+};
+use serde::Deserialize;
+use uuid::Uuid;
+
+pub(crate) const TEST_KEY: &str = "sk-helicone-test-key";
+const ORGANIZATION_ID: Uuid =
+    Uuid::from_u128(0xc3bc_2b69_c55c_4dfc_8a29_47db_1245_ee7c);
+const USER_ID: Uuid =
+    Uuid::from_u128(0xa41c_bcd7_5e9e_4104_b29b_2ef4_473d_71a7);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct ControlPlaneScript {
+    /// The script of each connection, in order of connection.
+    pub connections: Vec<Vec<Step>>,
+    #[serde(skip)]
+    connection_count: Arc<AtomicUsize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Step {
+    /// Delay after the previous step, or after connecting.
+    #[serde(default)]
+    pub after_ms: u64,
+    pub action: Action,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum Action {
+    /// Replaces the keys of the gateway.
+    PushKeys {
+        keys: Vec<MockKey>,
+    },
+    /// Replaces the whole control plane state of the gateway.
+    PushConfig {
+        keys: Vec<MockKey>,
+    },
+    Unauthorized {
+        message: String,
+    },
+    /// Closes the connection.
+    Disconnect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct MockKey {
+    pub key: String,
+    #[serde(default)]
+    pub tier: Option<String>,
+}
+
+impl ControlPlaneScript {
+    /// The script of a new connection.
+    pub fn next_connection(&self) -> &[Step] {
+        let connection = self.connection_count.fetch_add(1, Ordering::Relaxed);
+        self.connections.get(connection).map_or(&[], Vec::as_slice)
+    }
+}
+
+impl Action {
+    /// The message sent for this action, or `None` if it closes the
+    /// connection instead.
+    #[must_use]
+    pub fn message(&self) -> Option<MessageTypeRX> {
+        match self {
+            Self::PushKeys { keys } => {
+                Some(MessageTypeRX::Update(Update::Keys {
+                    data: keys.iter().map(MockKey::to_key).collect(),
+                }))
+            }
+            Self::PushConfig { keys } => {
+                Some(MessageTypeRX::Update(Update::Config {
+                    data: mock_state(keys),
+                }))
+            }
+            Self::Unauthorized { message } => {
+                Some(MessageTypeRX::Error(ControlPlaneError::Unauthorized {
+                    message: message.clone(),
+                }))
+            }
+            Self::Disconnect => None,
+        }
+    }
+}
+
+impl MockKey {
+    fn to_key(&self) -> Key {
+        Key {
+            key_hash: hash_key(&self.key),
+            owner_id: USER_ID.into(),
+            organization_id: ORGANIZATION_ID.into(),
+            tier: self.tier.clone(),
+        }
+    }
+}
+
+/// The control plane state of the mock organization with the given keys.
+pub(crate) fn mock_state(keys: &[MockKey]) -> ControlPlaneState {
+    ControlPlaneState {
+        auth: AuthData {
+            user_id: USER_ID.into(),
+            organization_id: ORGANIZATION_ID.into(),
+        },
+        keys: keys.iter().map(MockKey::to_key).collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connections_run_their_own_scripts() {
+        let script: ControlPlaneScript =
+            serde_json::from_value(serde_json::json!({
+                "connections": [
+                    [
+                        {
+                            "after-ms": 1000,
+                            "action": {
+                                "type": "push-keys",
+                                "keys": [{ "key": TEST_KEY, "tier": "free" }],
+                            },
+                        },
+                        { "action": { "type": "disconnect" } },
+                    ],
+                    [],
+                ]
+            }))
+            .unwrap();
+
+        let first = script.next_connection().to_vec();
+        assert_eq!(first.len(), 2);
+        assert_eq!(first[0].after_ms, 1000);
+        let Some(MessageTypeRX::Update(Update::Keys { data })) =
+            first[0].action.message()
+        else {
+            panic!("expected a keys update");
+        };
+        assert_eq!(data[0].key_hash, hash_key(TEST_KEY));
+        assert_eq!(data[0].tier.as_deref(), Some("free"));
+        assert_eq!(first[1].after_ms, 0);
+        assert!(first[1].action.message().is_none());
+
+        assert!(script.next_connection().is_empty());
+        assert!(script.next_connection().is_empty());
+    }
+}
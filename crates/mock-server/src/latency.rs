@@ -0,0 +1,247 @@
+//! Simulated provider latencies.
+//!
+//! A latency is either a number of milliseconds, which is jittered by up to
+//! ±10ms, or a profile:
+//!
+//! ```yaml
+//! openai-latency:
+//!   distribution:
+//!     type: log-normal
+//!     p50-ms: 300
+//!     p99-ms: 1500
+//!   spike:
+//!     probability: 0.01
+//!     latency-ms: 5000
+//!   degradation:
+//!     period-ms: 60000
+//!     duration-ms: 10000
+//!     multiplier: 4.0
+//! ```
+use std::{
+    f64::consts::PI,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use rand::Rng;
+use serde::Deserialize;
+
+/// The z-score of the 99th percentile of the standard normal distribution.
+const P99_Z_SCORE: f64 = 2.326_347_874;
+
+const FIXED_JITTER_MS: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Latency {
+    Fixed(u32),
+    Profile(LatencyProfile),
+}
+
+impl Default for Latency {
+    fn default() -> Self {
+        Self::Fixed(60)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct LatencyProfile {
+    pub distribution: Distribution,
+    /// Occasional latency spikes, e.g. to simulate a p99 tail.
+    #[serde(default)]
+    pub spike: Option<Spike>,
+    /// Periodic windows during which the provider is slower.
+    #[serde(default)]
+    pub degradation: Option<Degradation>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "type")]
+pub enum Distribution {
+    #[serde(rename_all = "kebab-case")]
+    Normal { mean_ms: f64, std_dev_ms: f64 },
+    /// Configured by its median and 99th percentile.
+    #[serde(rename_all = "kebab-case")]
+    LogNormal { p50_ms: f64, p99_ms: f64 },
+    /// Heavy tailed, with a minimum of `scale_ms`. Smaller `shape`s give
+    /// heavier tails.
+    #[serde(rename_all = "kebab-case")]
+    Pareto { scale_ms: f64, shape: f64 },
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Spike {
+    #[serde(default = "default_spike_probability")]
+    pub probability: f64,
+    pub latency_ms: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Degradation {
+    /// Time between the starts of consecutive windows.
+    pub period_ms: u64,
+    /// Length of each window, from the start of each period.
+    pub duration_ms: u64,
+    /// Factor the sampled latency is multiplied by during a window.
+    pub multiplier: f64,
+}
+
+fn default_spike_probability() -> f64 {
+    0.01
+}
+
+impl Latency {
+    #[must_use]
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Self::Fixed(0))
+    }
+
+    /// Samples a latency for a request received `now`, as the time since the
+    /// unix epoch.
+    pub fn sample(&self, rng: &mut impl Rng, now: Duration) -> Duration {
+        match self {
+            Self::Fixed(latency) => {
+                let low = latency.saturating_sub(FIXED_JITTER_MS);
+                let high = latency.saturating_add(FIXED_JITTER_MS);
+                Duration::from_millis(rng.random_range(low..=high).into())
+            }
+            Self::Profile(profile) => profile.sample(rng, now),
+        }
+    }
+}
+
+impl LatencyProfile {
+    fn sample(&self, rng: &mut impl Rng, now: Duration) -> Duration {
+        if let Some(spike) = &self.spike
+            && rng.random_bool(spike.probability.clamp(0.0, 1.0))
+        {
+            return Duration::from_millis(spike.latency_ms.into());
+        }
+        let mut latency_ms = self.distribution.sample(rng);
+        if let Some(degradation) = &self.degradation
+            && degradation.is_active(now)
+        {
+            latency_ms *= degradation.multiplier;
+        }
+        Duration::from_secs_f64(latency_ms.max(0.0) / 1000.0)
+    }
+}
+
+impl Distribution {
+    fn sample(&self, rng: &mut impl Rng) -> f64 {
+        match self {
+            Self::Normal {
+                mean_ms,
+                std_dev_ms,
+            } => mean_ms + std_dev_ms * standard_normal(rng),
+            Self::LogNormal { p50_ms, p99_ms } => {
+                let sigma = (p99_ms / p50_ms).ln() / P99_Z_SCORE;
+                p50_ms * (sigma * standard_normal(rng)).exp()
+            }
+            Self::Pareto { scale_ms, shape } => {
+                // inverse transform sampling, `u` is in (0, 1]
+                let u = 1.0 - rng.random::<f64>();
+                scale_ms / u.powf(1.0 / shape)
+            }
+        }
+    }
+}
+
+impl Degradation {
+    fn is_active(&self, now: Duration) -> bool {
+        if self.period_ms == 0 {
+            return false;
+        }
+        let now_ms = u64::try_from(now.as_millis()).unwrap_or(u64::MAX);
+        now_ms % self.period_ms < self.duration_ms
+    }
+}
+
+/// Samples the standard normal distribution with the Box-Muller transform.
+fn standard_normal(rng: &mut impl Rng) -> f64 {
+    let u1 = 1.0 - rng.random::<f64>();
+    let u2 = rng.random::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+pub(crate) fn since_epoch() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+
+    use super::*;
+
+    fn percentile(samples: &mut [Duration], percentile: usize) -> f64 {
+        samples.sort();
+        samples[samples.len() * percentile / 100].as_secs_f64() * 1000.0
+    }
+
+    #[test]
+    fn log_normal_matches_configured_percentiles() {
+        let latency: Latency = serde_json::from_value(serde_json::json!({
+            "distribution": {
+                "type": "log-normal",
+                "p50-ms": 300.0,
+                "p99-ms": 1500.0,
+            }
+        }))
+        .unwrap();
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut samples = (0..20_000)
+            .map(|_| latency.sample(&mut rng, Duration::ZERO))
+            .collect::<Vec<_>>();
+
+        let p50 = percentile(&mut samples, 50);
+        let p99 = percentile(&mut samples, 99);
+        assert!((280.0..320.0).contains(&p50), "p50 was {p50}");
+        assert!((1300.0..1700.0).contains(&p99), "p99 was {p99}");
+    }
+
+    #[test]
+    fn spikes_and_degradation_windows() {
+        let profile = LatencyProfile {
+            distribution: Distribution::Normal {
+                mean_ms: 100.0,
+                std_dev_ms: 0.0,
+            },
+            spike: None,
+            degradation: Some(Degradation {
+                period_ms: 60_000,
+                duration_ms: 10_000,
+                multiplier: 3.0,
+            }),
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let latency = Latency::Profile(profile.clone());
+        assert_eq!(
+            latency.sample(&mut rng, Duration::from_secs(125)),
+            Duration::from_millis(300)
+        );
+        assert_eq!(
+            latency.sample(&mut rng, Duration::from_secs(135)),
+            Duration::from_millis(100)
+        );
+
+        let latency = Latency::Profile(LatencyProfile {
+            spike: Some(Spike {
+                probability: 1.0,
+                latency_ms: 5000,
+            }),
+            ..profile
+        });
+        assert_eq!(
+            latency.sample(&mut rng, Duration::ZERO),
+            Duration::from_millis(5000)
+        );
+
+        let fixed = Latency::Fixed(5).sample(&mut rng, Duration::ZERO);
+        assert!(fixed <= Duration::from_millis(15));
+    }
+}
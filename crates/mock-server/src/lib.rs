@@ -1,4 +1,7 @@
+pub mod control_plane;
+pub mod latency;
 pub mod routes;
+pub mod usage;
 
 use axum::{
     Router,
@@ -6,21 +9,29 @@ use axum::{
 };
 use serde::Deserialize;
 
+use crate::{
+    control_plane::ControlPlaneScript, latency::Latency, usage::UsageConfig,
+};
+
 #[derive(Debug, Clone, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub struct AppState {
     #[serde(default = "default_provider_latency")]
-    pub openai_latency: u32,
+    pub openai_latency: Latency,
     #[serde(default = "default_provider_latency")]
-    pub anthropic_latency: u32,
+    pub anthropic_latency: Latency,
     #[serde(default = "default_provider_latency")]
-    pub gemini_latency: u32,
+    pub gemini_latency: Latency,
     #[serde(default = "default_provider_latency")]
-    pub bedrock_latency: u32,
+    pub bedrock_latency: Latency,
     #[serde(default = "default_jawn_latency")]
     pub jawn_latency: u32,
     #[serde(default = "default_minio_latency")]
     pub minio_latency: u32,
+    #[serde(default)]
+    pub usage: UsageConfig,
+    #[serde(default)]
+    pub control_plane: ControlPlaneScript,
     #[serde(default = "default_address")]
     pub address: String,
     #[serde(default = "default_port")]
@@ -36,14 +47,16 @@ impl Default for AppState {
             bedrock_latency: default_provider_latency(),
             jawn_latency: 20,
             minio_latency: 5,
+            usage: UsageConfig::default(),
+            control_plane: ControlPlaneScript::default(),
             address: "0.0.0.0".to_string(),
             port: 5150,
         }
     }
 }
 
-fn default_provider_latency() -> u32 {
-    60
+fn default_provider_latency() -> Latency {
+    Latency::default()
 }
 
 fn default_jawn_latency() -> u32 {
@@ -1,6 +1,6 @@
-use axum::{Json, extract::State};
+use axum::{Json, body::Bytes, extract::State};
 
-use crate::AppState;
+use crate::{AppState, usage::UsageFormat};
 
 const RESPONSE: &str = include_str!(
     "../../../../ai-gateway/stubs/gemini/generate_content_success.json"
@@ -8,12 +8,17 @@ const RESPONSE: &str = include_str!(
 
 pub(crate) async fn handler(
     State(state): State<AppState>,
+    body: Bytes,
 ) -> Json<serde_json::Value> {
-    if state.gemini_latency > 0 {
-        crate::routes::sleep(state.gemini_latency).await;
-    }
-    let stub = serde_json::from_str::<serde_json::Value>(RESPONSE).unwrap()
+    crate::routes::sleep(&state.gemini_latency).await;
+    let mut stub = serde_json::from_str::<serde_json::Value>(RESPONSE).unwrap()
         ["response"]["jsonBody"]
         .clone();
+    let request =
+        serde_json::from_slice::<serde_json::Value>(&body).unwrap_or_default();
+    state
+        .usage
+        .synthesize(&mut rand::rng(), UsageFormat::OpenAI, &request)
+        .write(UsageFormat::OpenAI, &mut stub);
     Json(stub)
 }
@@ -11,9 +11,12 @@ use axum::{
     response::IntoResponse,
 };
 use futures::{SinkExt, StreamExt};
-use uuid::Uuid;
 
-use crate::AppState;
+use crate::{
+    AppState,
+    control_plane::{MockKey, Step, TEST_KEY, mock_state},
+    latency::Latency,
+};
 
 pub(crate) async fn log_request(
     State(state): State<AppState>,
@@ -28,9 +31,7 @@ pub(crate) async fn log_request(
 pub(crate) async fn sign_s3_url(
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    if state.jawn_latency > 0 {
-        crate::routes::sleep(state.jawn_latency).await;
-    }
+    crate::routes::sleep(&Latency::Fixed(state.jawn_latency)).await;
     let minio_base_url = format!("http://{}:{}", state.address, state.port);
     let presigned_url = format!(
         "{minio_base_url}/request-response-storage/organizations/\
@@ -46,62 +47,69 @@ pub(crate) async fn sign_s3_url(
 }
 
 pub(crate) async fn websocket_handler(
+    State(state): State<AppState>,
     ws: WebSocketUpgrade,
 ) -> impl IntoResponse {
-    ws.on_upgrade(|socket| websocket(socket))
+    let script = state.control_plane.next_connection().to_vec();
+    ws.on_upgrade(|socket| websocket(socket, script))
 }
 
 // This function deals with a single websocket connection, i.e., a single
 // connected client / user, for which we will spawn two independent tasks (for
 // receiving / sending chat messages).
-async fn websocket(stream: WebSocket) {
+async fn websocket(stream: WebSocket, script: Vec<Step>) {
     // By splitting, we can send and receive at the same time.
     let (mut sender, mut receiver) = stream.split();
 
-    let mock_auth = mock_auth();
+    let mock_auth = mock_state(&[MockKey {
+        key: TEST_KEY.to_string(),
+        tier: None,
+    }]);
     let update_msg = MessageTypeRX::Update(Update::Config { data: mock_auth });
     let body = serde_json::to_vec(&update_msg).unwrap();
     let message = Message::Binary(body.into());
     sender.send(message).await.unwrap();
 
-    // Loop until a text message is found.
-    while let Some(Ok(message)) = receiver.next().await {
-        match message {
-            Message::Text(utf8_bytes) => {
-                tracing::info!("Received text message: {}", utf8_bytes);
-            }
-            Message::Binary(bytes) => {
-                tracing::info!("Received binary message: {:?}", bytes);
-            }
-            Message::Ping(bytes) => {
-                tracing::info!("Received ping message: {:?}", bytes);
-            }
-            Message::Pong(bytes) => {
-                tracing::info!("Received pong message: {:?}", bytes);
-            }
-            Message::Close(close_frame) => {
-                tracing::info!("Received close message: {:?}", close_frame);
+    let mut receive_task = tokio::spawn(async move {
+        // Loop until a text message is found.
+        while let Some(Ok(message)) = receiver.next().await {
+            match message {
+                Message::Text(utf8_bytes) => {
+                    tracing::info!("Received text message: {}", utf8_bytes);
+                }
+                Message::Binary(bytes) => {
+                    tracing::info!("Received binary message: {:?}", bytes);
+                }
+                Message::Ping(bytes) => {
+                    tracing::info!("Received ping message: {:?}", bytes);
+                }
+                Message::Pong(bytes) => {
+                    tracing::info!("Received pong message: {:?}", bytes);
+                }
+                Message::Close(close_frame) => {
+                    tracing::info!("Received close message: {:?}", close_frame);
+                }
             }
         }
-    }
-}
+    });
 
-fn mock_auth() -> ai_gateway::control_plane::types::ControlPlaneState {
-    let test_key = "sk-helicone-test-key";
-    let key_hash = ai_gateway::control_plane::types::hash_key(test_key);
-    let organization_id =
-        Uuid::parse_str("c3bc2b69-c55c-4dfc-8a29-47db1245ee7c").unwrap();
-    let user_id =
-        Uuid::parse_str("a41cbcd7-5e9e-4104-b29b-2ef4473d71a7").unwrap();
-    ai_gateway::control_plane::types::ControlPlaneState {
-        auth: ai_gateway::control_plane::types::AuthData {
-            user_id: user_id.clone().into(),
-            organization_id: organization_id.clone().into(),
-        },
-        keys: vec![ai_gateway::control_plane::types::Key {
-            key_hash: key_hash,
-            owner_id: user_id.into(),
-            organization_id: organization_id.into(),
-        }],
+    for step in script {
+        tokio::select! {
+            () = tokio::time::sleep(Duration::from_millis(step.after_ms)) => {}
+            _ = &mut receive_task => return,
+        }
+        let Some(message) = step.action.message() else {
+            tracing::info!("closing control plane connection");
+            let _ = sender.send(Message::Close(None)).await;
+            receive_task.abort();
+            return;
+        };
+        tracing::info!(action = ?step.action, "sending scripted message");
+        let body = serde_json::to_vec(&message).unwrap();
+        if sender.send(Message::Binary(body.into())).await.is_err() {
+            receive_task.abort();
+            return;
+        }
     }
+    let _ = receive_task.await;
 }
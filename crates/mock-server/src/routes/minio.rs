@@ -1,12 +1,10 @@
 use axum::{extract::State, http::StatusCode, response::IntoResponse};
 
-use crate::AppState;
+use crate::{AppState, latency::Latency};
 
 pub(crate) async fn upload_request(
     State(state): State<AppState>,
 ) -> impl IntoResponse {
-    if state.minio_latency > 0 {
-        crate::routes::sleep(state.minio_latency).await;
-    }
+    crate::routes::sleep(&Latency::Fixed(state.minio_latency)).await;
     StatusCode::OK
 }
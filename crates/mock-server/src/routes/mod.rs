@@ -1,6 +1,4 @@
-use std::time::Duration;
-
-use rand::Rng;
+use crate::latency::{self, Latency};
 
 pub mod anthropic;
 pub mod bedrock;
@@ -9,12 +7,10 @@ pub mod jawn;
 pub mod minio;
 pub mod openai;
 
-pub(crate) async fn sleep(latency: u32) {
-    let n: u32 = {
-        let mut rng = rand::rng();
-        let delta = 10;
-        let range = (latency - delta)..(latency + delta);
-        rng.random_range(range)
-    };
-    tokio::time::sleep(Duration::from_millis(n.into())).await;
+pub(crate) async fn sleep(latency: &Latency) {
+    if latency.is_zero() {
+        return;
+    }
+    let duration = latency.sample(&mut rand::rng(), latency::since_epoch());
+    tokio::time::sleep(duration).await;
 }
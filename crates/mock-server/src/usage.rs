@@ -0,0 +1,189 @@
+//! Synthesized token usage of mock responses.
+//!
+//! Input tokens are estimated from the size of the request and output tokens
+//! are sampled from the configured range, capped by the request's max
+//! tokens, so that the logger, cost and token rate limit code paths see
+//! meaningful numbers.
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields, default)]
+pub struct UsageConfig {
+    /// Characters of request text per input token.
+    pub chars_per_token: f64,
+    pub min_output_tokens: u32,
+    pub max_output_tokens: u32,
+}
+
+impl Default for UsageConfig {
+    fn default() -> Self {
+        Self {
+            chars_per_token: 4.0,
+            min_output_tokens: 10,
+            max_output_tokens: 500,
+        }
+    }
+}
+
+/// The response body formats of the mocked providers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageFormat {
+    OpenAI,
+    Anthropic,
+    Bedrock,
+}
+
+impl UsageFormat {
+    /// JSON pointers to the max tokens of a request, in order of precedence.
+    fn max_tokens_pointers(self) -> &'static [&'static str] {
+        match self {
+            Self::OpenAI => &["/max_completion_tokens", "/max_tokens"],
+            Self::Anthropic => &["/max_tokens"],
+            Self::Bedrock => &["/inferenceConfig/maxTokens"],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub input_tokens: u32,
+    pub output_tokens: u32,
+}
+
+impl UsageConfig {
+    /// Synthesizes the token usage of a response to `request`.
+    pub fn synthesize(
+        &self,
+        rng: &mut impl Rng,
+        format: UsageFormat,
+        request: &Value,
+    ) -> Usage {
+        let chars = text_len(request);
+        #[allow(
+            clippy::cast_precision_loss,
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss
+        )]
+        let input_tokens =
+            (chars as f64 / self.chars_per_token.max(1.0)).ceil() as u32;
+        let min = self.min_output_tokens.min(self.max_output_tokens);
+        let mut output_tokens =
+            rng.random_range(min..=self.max_output_tokens.max(min));
+        if let Some(max_tokens) = format
+            .max_tokens_pointers()
+            .iter()
+            .find_map(|pointer| request.pointer(pointer)?.as_u64())
+        {
+            let max_tokens = u32::try_from(max_tokens).unwrap_or(u32::MAX);
+            output_tokens = output_tokens.min(max_tokens);
+        }
+        Usage {
+            input_tokens: input_tokens.max(1),
+            output_tokens,
+        }
+    }
+}
+
+impl Usage {
+    /// Writes this usage into the `usage` of a response `body`.
+    pub fn write(self, format: UsageFormat, body: &mut Value) {
+        let total_tokens = self.input_tokens + self.output_tokens;
+        let usage = match format {
+            UsageFormat::OpenAI => serde_json::json!({
+                "prompt_tokens": self.input_tokens,
+                "completion_tokens": self.output_tokens,
+                "total_tokens": total_tokens,
+            }),
+            UsageFormat::Anthropic => serde_json::json!({
+                "input_tokens": self.input_tokens,
+                "output_tokens": self.output_tokens,
+            }),
+            UsageFormat::Bedrock => serde_json::json!({
+                "inputTokens": self.input_tokens,
+                "outputTokens": self.output_tokens,
+                "totalTokens": total_tokens,
+            }),
+        };
+        let (Some(body), Value::Object(usage)) = (body.as_object_mut(), usage)
+        else {
+            return;
+        };
+        match body.get_mut("usage") {
+            Some(Value::Object(existing)) => existing.extend(usage),
+            _ => {
+                body.insert("usage".to_string(), Value::Object(usage));
+            }
+        }
+    }
+}
+
+/// The number of characters of the string values in `value`.
+fn text_len(value: &Value) -> usize {
+    match value {
+        Value::String(text) => text.chars().count(),
+        Value::Array(values) => values.iter().map(text_len).sum(),
+        Value::Object(map) => map.values().map(text_len).sum(),
+        Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn usage_is_derived_from_request() {
+        let config = UsageConfig {
+            chars_per_token: 4.0,
+            min_output_tokens: 100,
+            max_output_tokens: 200,
+        };
+        let mut rng = StdRng::seed_from_u64(7);
+        let request = json!({
+            "model": "claude-3-7-sonnet",
+            "max_tokens": 50,
+            "messages": [{ "role": "user", "content": "x".repeat(60) }],
+        });
+        let usage =
+            config.synthesize(&mut rng, UsageFormat::Anthropic, &request);
+        // "claude-3-7-sonnet" + "user" + 60 characters of content
+        assert_eq!(usage.input_tokens, 21);
+        assert_eq!(usage.output_tokens, 50);
+
+        let request = json!({
+            "inferenceConfig": { "maxTokens": 1000 },
+            "messages": [],
+        });
+        let usage = config.synthesize(&mut rng, UsageFormat::Bedrock, &request);
+        assert_eq!(usage.input_tokens, 1);
+        assert!((100..=200).contains(&usage.output_tokens));
+
+        let mut body = json!({
+            "usage": {
+                "prompt_tokens": 19,
+                "prompt_tokens_details": { "cached_tokens": 0 },
+            }
+        });
+        Usage {
+            input_tokens: 21,
+            output_tokens: 50,
+        }
+        .write(UsageFormat::OpenAI, &mut body);
+        assert_eq!(
+            body,
+            json!({
+                "usage": {
+                    "prompt_tokens": 21,
+                    "completion_tokens": 50,
+                    "total_tokens": 71,
+                    "prompt_tokens_details": { "cached_tokens": 0 },
+                }
+            })
+        );
+    }
+}
@@ -0,0 +1,245 @@
+//! Trace exemplars of the latency histograms.
+//!
+//! `opentelemetry_sdk` aggregates histograms without exemplars, so they are
+//! sampled here when a measurement is recorded with its trace context, and
+//! attached to the data points of the histogram by [`ExemplarExporter`] right
+//! before they are exported. This lets Grafana jump from a latency spike to
+//! the trace of the request behind it.
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use opentelemetry::{
+    Context, KeyValue, metrics::Histogram, trace::TraceContextExt,
+};
+use opentelemetry_sdk::{
+    error::OTelSdkResult,
+    metrics::{
+        Temporality,
+        data::{self, Aggregation, Exemplar, ResourceMetrics},
+        exporter::PushMetricExporter,
+    },
+};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+static EXEMPLARS: LazyLock<Exemplars> = LazyLock::new(Exemplars::default);
+
+/// Records `value` on `histogram`, named `name`, with the trace of the
+/// current span as its exemplar.
+pub fn record(
+    histogram: &Histogram<f64>,
+    name: &'static str,
+    value: f64,
+    attributes: &[KeyValue],
+) {
+    histogram.record(value, attributes);
+    EXEMPLARS.offer(
+        name,
+        value,
+        attributes,
+        &tracing::Span::current().context(),
+    );
+}
+
+/// The exemplars sampled since the last export, keyed by histogram and
+/// attributes.
+///
+/// Only the largest measurement of each time series is kept, since that is
+/// the one worth jumping to.
+#[derive(Default)]
+pub struct Exemplars(Mutex<HashMap<(String, Vec<KeyValue>), Exemplar<f64>>>);
+
+impl std::fmt::Debug for Exemplars {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Exemplars").finish_non_exhaustive()
+    }
+}
+
+impl Exemplars {
+    /// Samples a measurement of the histogram `name` recorded within the
+    /// trace of `cx`, if it is sampled.
+    pub fn offer(
+        &self,
+        name: &str,
+        value: f64,
+        attributes: &[KeyValue],
+        cx: &Context,
+    ) {
+        let span = cx.span();
+        let span_context = span.span_context();
+        if !span_context.is_valid() || !span_context.is_sampled() {
+            return;
+        }
+        let exemplar = Exemplar {
+            filtered_attributes: Vec::new(),
+            time: SystemTime::now(),
+            value,
+            span_id: span_context.span_id().to_bytes(),
+            trace_id: span_context.trace_id().to_bytes(),
+        };
+        let key = (name.to_string(), sorted(attributes));
+        let mut exemplars = self.0.lock().expect("exemplars lock poisoned");
+        if exemplars
+            .get(&key)
+            .is_none_or(|sampled| value > sampled.value)
+        {
+            exemplars.insert(key, exemplar);
+        }
+    }
+
+    /// Attaches the sampled exemplars to the data points of their
+    /// histograms in `metrics`, and starts sampling anew.
+    pub fn attach(&self, metrics: &mut ResourceMetrics) {
+        let mut exemplars = std::mem::take(
+            &mut *self.0.lock().expect("exemplars lock poisoned"),
+        );
+        if exemplars.is_empty() {
+            return;
+        }
+        for metric in metrics
+            .scope_metrics
+            .iter_mut()
+            .flat_map(|scope| scope.metrics.iter_mut())
+        {
+            let Some(histogram) = Aggregation::as_mut(&mut *metric.data)
+                .downcast_mut::<data::Histogram<f64>>()
+            else {
+                continue;
+            };
+            for data_point in &mut histogram.data_points {
+                let key =
+                    (metric.name.to_string(), sorted(&data_point.attributes));
+                if let Some(exemplar) = exemplars.remove(&key) {
+                    data_point.exemplars.push(exemplar);
+                }
+            }
+        }
+    }
+}
+
+fn sorted(attributes: &[KeyValue]) -> Vec<KeyValue> {
+    let mut attributes = attributes.to_vec();
+    attributes.sort_by(|a, b| a.key.cmp(&b.key));
+    attributes
+}
+
+/// Attaches the sampled exemplars to the histograms exported by the wrapped
+/// exporter.
+#[derive(Debug)]
+pub struct ExemplarExporter<E> {
+    inner: E,
+}
+
+impl<E> ExemplarExporter<E> {
+    #[must_use]
+    pub fn new(inner: E) -> Self {
+        Self { inner }
+    }
+}
+
+impl<E: PushMetricExporter> PushMetricExporter for ExemplarExporter<E> {
+    async fn export(&self, metrics: &mut ResourceMetrics) -> OTelSdkResult {
+        EXEMPLARS.attach(metrics);
+        self.inner.export(metrics).await
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.inner.force_flush()
+    }
+
+    fn shutdown_with_timeout(&self, timeout: Duration) -> OTelSdkResult {
+        self.inner.shutdown_with_timeout(timeout)
+    }
+
+    fn temporality(&self) -> Temporality {
+        self.inner.temporality()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::{
+        InstrumentationScope,
+        trace::{SpanContext, SpanId, TraceFlags, TraceId, TraceState},
+    };
+    use opentelemetry_sdk::{
+        Resource,
+        metrics::data::{HistogramDataPoint, Metric, ScopeMetrics},
+    };
+
+    use super::*;
+
+    fn resource_metrics(attributes: Vec<KeyValue>) -> ResourceMetrics {
+        let data_point = HistogramDataPoint {
+            attributes,
+            count: 1,
+            bounds: vec![100.0],
+            bucket_counts: vec![0, 1],
+            min: Some(250.0),
+            max: Some(250.0),
+            sum: 250.0,
+            exemplars: Vec::new(),
+        };
+        ResourceMetrics {
+            resource: Resource::builder_empty().build(),
+            scope_metrics: vec![ScopeMetrics {
+                scope: InstrumentationScope::builder("test").build(),
+                metrics: vec![Metric {
+                    name: "tfft_duration".into(),
+                    description: "".into(),
+                    unit: "ms".into(),
+                    data: Box::new(data::Histogram {
+                        data_points: vec![data_point],
+                        start_time: SystemTime::now(),
+                        time: SystemTime::now(),
+                        temporality: Temporality::Cumulative,
+                    }),
+                }],
+            }],
+        }
+    }
+
+    fn attached(metrics: &ResourceMetrics) -> &[Exemplar<f64>] {
+        let histogram = metrics.scope_metrics[0].metrics[0]
+            .data
+            .as_any()
+            .downcast_ref::<data::Histogram<f64>>()
+            .unwrap();
+        &histogram.data_points[0].exemplars
+    }
+
+    #[test]
+    fn exemplars_carry_the_trace_id() {
+        let trace_id = TraceId::from(0x4bf9_2f35_77b3_4da6_a3ce_929d_0e0e_4736);
+        let cx = Context::new().with_remote_span_context(SpanContext::new(
+            trace_id,
+            SpanId::from(0x00f0_67aa_0ba9_02b7),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        ));
+        let attributes = vec![
+            KeyValue::new("provider", "openai"),
+            KeyValue::new("model", "gpt-4o"),
+        ];
+        let sampled = Exemplars::default();
+        sampled.offer("tfft_duration", 100.0, &attributes, &cx);
+        sampled.offer("tfft_duration", 250.0, &attributes, &cx);
+        sampled.offer("tfft_duration", 50.0, &attributes, &Context::new());
+
+        let mut metrics =
+            resource_metrics(attributes.into_iter().rev().collect());
+        sampled.attach(&mut metrics);
+        let exemplars = attached(&metrics);
+        assert_eq!(exemplars.len(), 1);
+        assert_eq!(exemplars[0].trace_id, trace_id.to_bytes());
+        assert!((exemplars[0].value - 250.0).abs() < f64::EPSILON);
+
+        // exemplars are only attached once
+        let mut metrics = resource_metrics(Vec::new());
+        sampled.attach(&mut metrics);
+        assert!(attached(&metrics).is_empty());
+    }
+}
@@ -0,0 +1,82 @@
+//! Bucket boundaries of the latency histograms.
+//!
+//! The default boundaries of the SDK are spread out over ten seconds, which
+//! leaves few of them in the 50 to 500 ms range that latency based routing
+//! decisions are made in.
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use serde::{Deserialize, Serialize};
+
+use crate::TelemetryError;
+
+/// Name of the time to first token histogram.
+pub const TFFT_DURATION: &str = "tfft_duration";
+/// Name of the histogram of the time to the end of response bodies.
+pub const REQUEST_DURATION: &str = "request_duration";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct HistogramBuckets {
+    /// Boundaries of `tfft_duration`, in milliseconds.
+    pub tfft_duration: Vec<Decimal>,
+    /// Boundaries of `request_duration`, in milliseconds.
+    pub request_duration: Vec<Decimal>,
+}
+
+impl Default for HistogramBuckets {
+    fn default() -> Self {
+        Self {
+            tfft_duration: default_buckets(),
+            request_duration: default_buckets(),
+        }
+    }
+}
+
+impl HistogramBuckets {
+    /// Checks that the boundaries of each histogram are increasing, which
+    /// the SDK otherwise only reports by dropping the histogram.
+    pub fn validate(&self) -> Result<(), TelemetryError> {
+        for (histogram, boundaries) in [
+            ("tfft-duration", &self.tfft_duration),
+            ("request-duration", &self.request_duration),
+        ] {
+            if boundaries.windows(2).any(|pair| pair[0] >= pair[1]) {
+                return Err(TelemetryError::InvalidHistogramBuckets(histogram));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The boundaries of a histogram, as the SDK takes them.
+#[must_use]
+pub fn boundaries(buckets: &[Decimal]) -> Vec<f64> {
+    buckets.iter().filter_map(ToPrimitive::to_f64).collect()
+}
+
+fn default_buckets() -> Vec<Decimal> {
+    [
+        10, 25, 50, 75, 100, 150, 200, 250, 300, 400, 500, 750, 1000, 2500,
+        5000, 10000, 30000,
+    ]
+    .into_iter()
+    .map(Decimal::from)
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buckets_must_increase() {
+        assert!(HistogramBuckets::default().validate().is_ok());
+        let buckets = HistogramBuckets {
+            tfft_duration: vec![Decimal::from(100), Decimal::from(50)],
+            ..HistogramBuckets::default()
+        };
+        assert!(matches!(
+            buckets.validate(),
+            Err(TelemetryError::InvalidHistogramBuckets("tfft-duration"))
+        ));
+    }
+}
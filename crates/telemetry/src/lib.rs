@@ -1,9 +1,15 @@
+pub mod exemplar;
+pub mod histogram;
 pub mod make_span;
+pub mod sampling;
 pub mod tracing;
 pub mod utils;
 
+use std::{collections::BTreeMap, time::Duration};
+
+use histogram::HistogramBuckets;
 use opentelemetry::{
-    TraceId, global,
+    KeyValue, TraceId, global,
     trace::{TracerProvider, noop::NoopTextMapPropagator},
 };
 use opentelemetry_otlp::{
@@ -12,16 +18,17 @@ use opentelemetry_otlp::{
 };
 use opentelemetry_sdk::{
     Resource,
-    logs::SdkLoggerProvider,
-    metrics::SdkMeterProvider,
+    logs::{self, BatchLogProcessor, SdkLoggerProvider},
+    metrics::{PeriodicReader, SdkMeterProvider},
     propagation::TraceContextPropagator,
-    trace::{IdGenerator, SdkTracerProvider},
+    trace::{self, BatchSpanProcessor, IdGenerator, SdkTracerProvider},
 };
+use sampling::{SamplingConfig, TailSampler};
 use serde::{Deserialize, Serialize};
 pub use tracing_subscriber::util::TryInitError;
 use tracing_subscriber::{
     EnvFilter, Layer, filter::ParseError, layer::SubscriberExt,
-    util::SubscriberInitExt,
+    registry::LookupSpan, util::SubscriberInitExt,
 };
 use utils::default_true;
 use uuid::Uuid;
@@ -42,6 +49,25 @@ pub struct Config {
     pub propagate: bool,
     #[serde(default)]
     pub format: Format,
+    /// Additional attributes of the resource reporting telemetry, e.g. the
+    /// region it's deployed in.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resource_attributes: BTreeMap<String, String>,
+    /// Which traces are exported to the OTLP endpoint.
+    #[serde(default)]
+    pub sampling: SamplingConfig,
+    /// Overrides of `exporter` and `otlp-endpoint` for logs.
+    #[serde(default)]
+    pub logs: SignalConfig,
+    /// Overrides of `exporter` and `otlp-endpoint` for traces.
+    #[serde(default)]
+    pub traces: SignalConfig,
+    /// Overrides of `exporter` and `otlp-endpoint` for metrics.
+    #[serde(default)]
+    pub metrics: SignalConfig,
+    /// Bucket boundaries of the latency histograms.
+    #[serde(default)]
+    pub histogram_buckets: HistogramBuckets,
 }
 
 impl Default for Config {
@@ -53,10 +79,53 @@ impl Default for Config {
             otlp_endpoint: default_otlp_endpoint(),
             propagate: default_true(),
             format: Format::default(),
+            resource_attributes: BTreeMap::new(),
+            sampling: SamplingConfig::default(),
+            logs: SignalConfig::default(),
+            traces: SignalConfig::default(),
+            metrics: SignalConfig::default(),
+            histogram_buckets: HistogramBuckets::default(),
         }
     }
 }
 
+impl Config {
+    fn signal<'a>(&'a self, signal: &'a SignalConfig) -> Signal<'a> {
+        Signal {
+            exporter: signal.exporter.as_ref().unwrap_or(&self.exporter),
+            otlp_endpoint: signal
+                .otlp_endpoint
+                .as_deref()
+                .unwrap_or(&self.otlp_endpoint),
+            interval: signal.interval,
+        }
+    }
+}
+
+/// The exporter settings of one signal, where unset settings are those of
+/// the other signals.
+#[derive(
+    Default, Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash,
+)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SignalConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exporter: Option<Exporter>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub otlp_endpoint: Option<String>,
+    /// How often the signal is exported: the interval between metric
+    /// readings, or the delay between batches of spans or log records.
+    #[serde(with = "humantime_serde", skip_serializing_if = "Option::is_none")]
+    pub interval: Option<Duration>,
+}
+
+/// The resolved exporter settings of a signal.
+struct Signal<'a> {
+    exporter: &'a Exporter,
+    otlp_endpoint: &'a str,
+    interval: Option<Duration>,
+}
+
 #[derive(
     Default, Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash,
 )]
@@ -68,6 +137,16 @@ pub enum Exporter {
     Both,
 }
 
+impl Exporter {
+    fn uses_stdout(&self) -> bool {
+        matches!(self, Self::Stdout | Self::Both)
+    }
+
+    fn uses_otlp(&self) -> bool {
+        matches!(self, Self::Otlp | Self::Both)
+    }
+}
+
 #[derive(
     Default, Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash,
 )]
@@ -105,33 +184,43 @@ pub enum TelemetryError {
     Subscriber(#[from] TryInitError),
     #[error("Otel http metrics error")]
     OtelHttpMetrics,
+    #[error("Bucket boundaries of {0} must increase")]
+    InvalidHistogramBuckets(&'static str),
 }
 
 fn resource(config: &Config) -> Resource {
     Resource::builder()
         .with_service_name(config.service_name.clone())
+        .with_attributes(
+            config
+                .resource_attributes
+                .iter()
+                .map(|(key, value)| KeyValue::new(key.clone(), value.clone())),
+        )
         .build()
 }
 
+/// The providers of each signal, to be shut down before exiting.
+#[derive(Debug)]
+pub struct Providers {
+    /// Set if logs are exported with OTLP.
+    pub logger: Option<SdkLoggerProvider>,
+    /// Always set, since without it we don't generate trace ids, which are
+    /// useful to have when debugging/developing.
+    pub tracer: SdkTracerProvider,
+    /// Set if metrics are exported with OTLP.
+    pub meter: Option<SdkMeterProvider>,
+}
+
 /// Initialize telemetry with the given config.
 ///
-/// # Notes
-/// - The reason the `TracerProvider` is not optional is because without it we
-///   don't generate trace ids, which is useful to have when
-///   debugging/developing.
+/// Each signal is exported as its own config in `logs`, `traces` and
+/// `metrics` says, falling back to `exporter` and `otlp-endpoint`.
 ///
 /// # Errors
 /// If any of the configuration is invalid.
-pub fn init_telemetry(
-    config: &Config,
-) -> Result<
-    (
-        Option<SdkLoggerProvider>,
-        SdkTracerProvider,
-        Option<SdkMeterProvider>,
-    ),
-    TelemetryError,
-> {
+pub fn init_telemetry(config: &Config) -> Result<Providers, TelemetryError> {
+    config.histogram_buckets.validate()?;
     let resource = resource(config);
 
     if config.propagate {
@@ -140,126 +229,73 @@ pub fn init_telemetry(
         global::set_text_map_propagator(NoopTextMapPropagator::new());
     }
 
-    match config.exporter {
-        Exporter::Stdout => {
-            let tracer_provider = init_stdout(&resource, config)?;
-            Ok((None, tracer_provider, None))
-        }
-        Exporter::Otlp => {
-            let (logger_provider, tracer_provider, metrics_provider) =
-                init_otlp(config)?;
-            Ok((
-                Some(logger_provider),
-                tracer_provider,
-                Some(metrics_provider),
-            ))
-        }
-        Exporter::Both => {
-            let (logger_provider, tracer_provider, metrics_provider) =
-                init_otlp_with_stdout(config)?;
-            Ok((
-                Some(logger_provider),
-                tracer_provider,
-                Some(metrics_provider),
-            ))
-        }
-    }
-}
-
-fn init_otlp(
-    config: &Config,
-) -> Result<
-    (SdkLoggerProvider, SdkTracerProvider, SdkMeterProvider),
-    TelemetryError,
-> {
-    init_otlp_pipeline(config, false)
-}
-
-fn init_otlp_with_stdout(
-    config: &Config,
-) -> Result<
-    (SdkLoggerProvider, SdkTracerProvider, SdkMeterProvider),
-    TelemetryError,
-> {
-    init_otlp_pipeline(config, true)
-}
-
-fn init_otlp_pipeline(
-    config: &Config,
-    with_stdout: bool,
-) -> Result<
-    (SdkLoggerProvider, SdkTracerProvider, SdkMeterProvider),
-    TelemetryError,
-> {
-    let resource = resource(config);
-
     // logging
-    let logger_provider = logger_provider(config, resource.clone())
-        .map_err(TelemetryError::LogExporterBuild)?;
-    let otel_layer =
-        opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge::new(
-            &logger_provider,
-        )
-        .with_filter(env_filter(config)?);
+    let logs = config.signal(&config.logs);
+    let logger_provider = if logs.exporter.uses_otlp() {
+        let logger_provider = logger_provider(&logs, resource.clone())
+            .map_err(TelemetryError::LogExporterBuild)?;
+        Some(logger_provider)
+    } else {
+        None
+    };
+    let otel_layer = match &logger_provider {
+        Some(logger_provider) => Some(
+            opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge::new(
+                logger_provider,
+            )
+            .with_filter(env_filter(config)?),
+        ),
+        None => None,
+    };
+    let stdout_layer = if logs.exporter.uses_stdout() {
+        Some(fmt_layer(config)?)
+    } else {
+        None
+    };
 
     // tracing
-    let tracer_provider = tracer_provider(config, resource.clone())
+    let traces = config.signal(&config.traces);
+    let tracer_provider = tracer_provider(config, &traces, resource.clone())
         .map_err(TelemetryError::TraceExporterBuild)?;
     let tracer = tracer_provider.tracer(config.service_name.clone());
     let tracing_layer = tracing_opentelemetry::layer()
         .with_tracer(tracer)
         .with_filter(env_filter(config)?);
 
-    let stdout_layer = if with_stdout {
-        let layer = match config.format {
-            Format::Pretty => tracing_subscriber::fmt::layer()
-                .pretty()
-                .with_file(true)
-                .with_line_number(true)
-                .with_filter(env_filter(config)?)
-                .boxed(),
-            Format::Compact => tracing_subscriber::fmt::layer()
-                .compact()
-                .with_file(true)
-                .with_line_number(true)
-                .with_filter(env_filter(config)?)
-                .boxed(),
-            Format::Json => tracing_subscriber::fmt::layer()
-                .json()
-                .with_file(true)
-                .with_line_number(true)
-                .with_filter(env_filter(config)?)
-                .boxed(),
-        };
-        Some(layer)
-    } else {
-        None
-    };
-
     tracing_subscriber::registry()
         .with(tracing_layer)
         .with(otel_layer)
         .with(stdout_layer)
         .try_init()?;
+    global::set_tracer_provider(tracer_provider.clone());
 
     // metrics
-    let metrics_provider = metrics_provider(config, resource)
-        .map_err(TelemetryError::MetricExporterBuild)?;
-
-    global::set_meter_provider(metrics_provider.clone());
-    global::set_tracer_provider(tracer_provider.clone());
+    let metrics = config.signal(&config.metrics);
+    let meter_provider = if metrics.exporter.uses_otlp() {
+        let meter_provider = metrics_provider(&metrics, resource)
+            .map_err(TelemetryError::MetricExporterBuild)?;
+        global::set_meter_provider(meter_provider.clone());
+        Some(meter_provider)
+    } else {
+        None
+    };
 
     log_panics::init();
 
-    Ok((logger_provider, tracer_provider, metrics_provider))
+    Ok(Providers {
+        logger: logger_provider,
+        tracer: tracer_provider,
+        meter: meter_provider,
+    })
 }
 
-fn init_stdout(
-    resource: &Resource,
+fn fmt_layer<S>(
     config: &Config,
-) -> Result<SdkTracerProvider, TelemetryError> {
-    // logging
-    let fmt_layer = match config.format {
+) -> Result<Box<dyn Layer<S> + Send + Sync + 'static>, TelemetryError>
+where
+    S: ::tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let layer = match config.format {
         Format::Pretty => tracing_subscriber::fmt::layer()
             .pretty()
             .with_file(true)
@@ -279,22 +315,7 @@ fn init_stdout(
             .with_filter(env_filter(config)?)
             .boxed(),
     };
-    let registry = tracing_subscriber::registry().with(fmt_layer);
-
-    // tracing
-    let tracer_provider = tracer_provider(config, resource.clone())
-        .map_err(TelemetryError::TraceExporterBuild)?;
-    let tracer = tracer_provider.tracer(config.service_name.clone());
-    let filter = env_filter(config)?;
-    let tracing_layer = tracing_opentelemetry::layer()
-        .with_tracer(tracer)
-        .with_filter(filter);
-    registry.with(tracing_layer).try_init()?;
-    opentelemetry::global::set_tracer_provider(tracer_provider.clone());
-
-    log_panics::init();
-
-    Ok(tracer_provider)
+    Ok(layer)
 }
 
 fn env_filter(config: &Config) -> Result<EnvFilter, TelemetryError> {
@@ -312,60 +333,78 @@ fn env_filter(config: &Config) -> Result<EnvFilter, TelemetryError> {
 
 fn tracer_provider(
     config: &Config,
+    signal: &Signal<'_>,
     resource: Resource,
 ) -> Result<SdkTracerProvider, ExporterBuildError> {
-    match &config.exporter {
-        Exporter::Stdout => {
-            Ok(SdkTracerProvider::builder()
-                .with_resource(resource)
-                // we don't need an exporter here for stdout since we really
-                // just want the tracer to generate trace ids
-                .with_id_generator(UuidGenerator)
-                .with_max_events_per_span(256)
-                .with_max_attributes_per_span(16)
-                .build())
-        }
-        Exporter::Otlp | Exporter::Both => {
-            let exporter = SpanExporter::builder()
-                .with_tonic()
-                .with_endpoint(config.otlp_endpoint.clone())
-                .build()?;
-            let provider = SdkTracerProvider::builder()
-                .with_resource(resource)
-                .with_batch_exporter(exporter)
-                .with_id_generator(UuidGenerator)
-                .with_max_events_per_span(256)
-                .with_max_attributes_per_span(16)
-                .build();
-            Ok(provider)
-        }
+    let builder = SdkTracerProvider::builder()
+        .with_resource(resource)
+        .with_id_generator(UuidGenerator)
+        .with_max_events_per_span(256)
+        .with_max_attributes_per_span(16);
+    if !signal.exporter.uses_otlp() {
+        // we don't need an exporter here for stdout since we really
+        // just want the tracer to generate trace ids
+        return Ok(builder.build());
     }
+
+    let exporter = SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(signal.otlp_endpoint)
+        .build()?;
+    let mut batch_config = trace::BatchConfigBuilder::default();
+    if let Some(interval) = signal.interval {
+        batch_config = batch_config.with_scheduled_delay(interval);
+    }
+    let processor = BatchSpanProcessor::builder(exporter)
+        .with_batch_config(batch_config.build())
+        .build();
+    let builder = if config.sampling.samples_all() {
+        builder.with_span_processor(processor)
+    } else {
+        builder.with_span_processor(TailSampler::new(
+            processor,
+            config.sampling.clone(),
+        ))
+    };
+    Ok(builder.build())
 }
 
 fn logger_provider(
-    config: &Config,
+    signal: &Signal<'_>,
     resource: Resource,
 ) -> Result<SdkLoggerProvider, ExporterBuildError> {
     let exporter = LogExporter::builder()
         .with_tonic()
-        .with_endpoint(config.otlp_endpoint.clone())
+        .with_endpoint(signal.otlp_endpoint)
         .build()?;
+    let mut batch_config = logs::BatchConfigBuilder::default();
+    if let Some(interval) = signal.interval {
+        batch_config = batch_config.with_scheduled_delay(interval);
+    }
+    let processor = BatchLogProcessor::builder(exporter)
+        .with_batch_config(batch_config.build())
+        .build();
     Ok(SdkLoggerProvider::builder()
         .with_resource(resource)
-        .with_batch_exporter(exporter)
+        .with_log_processor(processor)
         .build())
 }
 
 fn metrics_provider(
-    config: &Config,
+    signal: &Signal<'_>,
     resource: Resource,
 ) -> Result<SdkMeterProvider, ExporterBuildError> {
     let exporter = MetricExporter::builder()
         .with_tonic()
-        .with_endpoint(config.otlp_endpoint.clone())
+        .with_endpoint(signal.otlp_endpoint)
         .build()?;
+    let mut reader =
+        PeriodicReader::builder(exemplar::ExemplarExporter::new(exporter));
+    if let Some(interval) = signal.interval {
+        reader = reader.with_interval(interval);
+    }
     Ok(SdkMeterProvider::builder()
-        .with_periodic_exporter(exporter)
+        .with_reader(reader.build())
         .with_resource(resource)
         .build())
 }
@@ -382,3 +421,37 @@ impl IdGenerator for UuidGenerator {
         opentelemetry::SpanId::from(Uuid::new_v4().as_u64_pair().0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signals_fall_back_to_shared_settings() {
+        let config = Config {
+            exporter: Exporter::Otlp,
+            logs: SignalConfig {
+                exporter: Some(Exporter::Stdout),
+                ..SignalConfig::default()
+            },
+            traces: SignalConfig {
+                otlp_endpoint: Some("http://tempo:4317".to_string()),
+                ..SignalConfig::default()
+            },
+            ..Config::default()
+        };
+
+        let logs = config.signal(&config.logs);
+        assert!(logs.exporter.uses_stdout());
+        assert!(!logs.exporter.uses_otlp());
+        assert_eq!(logs.otlp_endpoint, config.otlp_endpoint);
+
+        let traces = config.signal(&config.traces);
+        assert_eq!(traces.exporter, &Exporter::Otlp);
+        assert_eq!(traces.otlp_endpoint, "http://tempo:4317");
+
+        let metrics = config.signal(&config.metrics);
+        assert_eq!(metrics.exporter, &Exporter::Otlp);
+        assert_eq!(metrics.interval, None);
+    }
+}
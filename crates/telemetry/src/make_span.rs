@@ -1,7 +1,9 @@
-use http::Request;
+use std::time::Duration;
+
+use http::{Request, Response};
 use opentelemetry::{Context, global, trace::TraceContextExt};
 use opentelemetry_http::HeaderExtractor;
-use tower_http::trace::MakeSpan;
+use tower_http::trace::{DefaultOnResponse, MakeSpan, OnResponse};
 use tracing::{Level, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
@@ -50,6 +52,11 @@ impl<B> MakeSpan<B> for SpanFactory {
                     $level,
                     "request",
                     trace_id = tracing::field::Empty,
+                    client_ip = tracing::field::Empty,
+                    otel.kind = "server",
+                    url.path = %request.uri().path(),
+                    http.response.status_code = tracing::field::Empty,
+                    otel.status_code = tracing::field::Empty,
                 )
             };
         }
@@ -77,6 +84,26 @@ impl<B> MakeSpan<B> for SpanFactory {
     }
 }
 
+/// Records the status of responses on their request span, marking the span
+/// as errored for server errors so that its trace is sampled as such.
+#[derive(Debug, Clone, Default)]
+pub struct RecordStatus(DefaultOnResponse);
+
+impl<B> OnResponse<B> for RecordStatus {
+    fn on_response(
+        self,
+        response: &Response<B>,
+        latency: Duration,
+        span: &Span,
+    ) {
+        span.record("http.response.status_code", response.status().as_u16());
+        if response.status().is_server_error() {
+            span.record("otel.status_code", "ERROR");
+        }
+        self.0.on_response(response, latency, span);
+    }
+}
+
 fn extract_context_from_request<B>(req: &Request<B>) -> Context {
     global::get_text_map_propagator(|propagator| {
         propagator.extract(&HeaderExtractor(req.headers()))
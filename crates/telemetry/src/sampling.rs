@@ -0,0 +1,329 @@
+//! Sampling of exported traces.
+//!
+//! Whether a request is slow or fails is only known once it has been
+//! handled, so traces are sampled at their tail: the spans of a trace are
+//! held until its request span ends, and then exported or dropped together.
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, PoisonError},
+    time::Duration,
+};
+
+use opentelemetry::{
+    Context, TraceId,
+    trace::{SpanKind, Status},
+};
+use opentelemetry_sdk::{
+    Resource,
+    error::OTelSdkResult,
+    trace::{Span, SpanData, SpanProcessor},
+};
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::default_true;
+
+/// The number of traces held at once, so that traces whose request span
+/// never ends don't grow without bound.
+const MAX_TRACES: usize = 8192;
+/// The attribute of request spans with their path.
+const PATH_ATTRIBUTE: &str = "url.path";
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct SamplingConfig {
+    /// The ratio of traces sampled, of those not otherwise kept.
+    pub ratio: Decimal,
+    /// Whether traces with an error, including server error responses, are
+    /// always sampled.
+    #[serde(default = "default_true")]
+    pub always_sample_errors: bool,
+    /// Traces of requests taking at least this long are always sampled.
+    #[serde(
+        default,
+        with = "humantime_serde",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub slow_threshold: Option<Duration>,
+    /// Ratios for requests under a path prefix, overriding `ratio`. The
+    /// first matching route applies.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes: Vec<RouteSampling>,
+}
+
+impl Default for SamplingConfig {
+    fn default() -> Self {
+        Self {
+            ratio: Decimal::ONE,
+            always_sample_errors: true,
+            slow_threshold: None,
+            routes: Vec::new(),
+        }
+    }
+}
+
+impl SamplingConfig {
+    /// Whether every trace is sampled, in which case traces needn't be held.
+    #[must_use]
+    pub fn samples_all(&self) -> bool {
+        self.ratio >= Decimal::ONE
+            && self.routes.iter().all(|route| route.ratio >= Decimal::ONE)
+    }
+
+    fn ratio_for(&self, path: Option<&str>) -> Decimal {
+        path.and_then(|path| {
+            self.routes
+                .iter()
+                .find(|route| path.starts_with(&route.prefix))
+        })
+        .map_or(self.ratio, |route| route.ratio)
+    }
+
+    /// Whether the trace of `request_span`, with the other ended `spans` of
+    /// its trace, is sampled.
+    fn sampled(&self, request_span: &SpanData, spans: &[SpanData]) -> bool {
+        let is_error =
+            |span: &SpanData| matches!(span.status, Status::Error { .. });
+        if self.always_sample_errors
+            && (is_error(request_span) || spans.iter().any(is_error))
+        {
+            return true;
+        }
+        if let Some(threshold) = self.slow_threshold
+            && request_span
+                .end_time
+                .duration_since(request_span.start_time)
+                .is_ok_and(|duration| duration >= threshold)
+        {
+            return true;
+        }
+        let path = request_span
+            .attributes
+            .iter()
+            .find(|attribute| attribute.key.as_str() == PATH_ATTRIBUTE)
+            .map(|attribute| attribute.value.as_str());
+        in_ratio(
+            request_span.span_context.trace_id(),
+            self.ratio_for(path.as_deref()),
+        )
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RouteSampling {
+    pub prefix: String,
+    pub ratio: Decimal,
+}
+
+/// Whether `trace_id` falls within `ratio` of trace ids, so that every
+/// instance makes the same decision for a trace.
+#[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+fn in_ratio(trace_id: TraceId, ratio: Decimal) -> bool {
+    let ratio = ratio.to_f64().unwrap_or(1.0);
+    let bits = u128::from_be_bytes(trace_id.to_bytes()) as u64;
+    // the top 53 bits, which an f64 represents exactly
+    ((bits >> 11) as f64 / (1_u64 << 53) as f64) < ratio
+}
+
+#[derive(Debug)]
+enum Trace {
+    /// The ended spans of a trace whose request span hasn't ended.
+    Pending(Vec<SpanData>),
+    /// Whether the trace was sampled, for spans ending after their request
+    /// span.
+    Decided(bool),
+}
+
+#[derive(Debug, Default)]
+struct Traces {
+    traces: HashMap<TraceId, Trace>,
+    /// The ids of `traces`, oldest first.
+    order: VecDeque<TraceId>,
+}
+
+impl Traces {
+    fn entry(&mut self, trace_id: TraceId) -> &mut Trace {
+        if !self.traces.contains_key(&trace_id) {
+            if self.order.len() >= MAX_TRACES
+                && let Some(oldest) = self.order.pop_front()
+            {
+                self.traces.remove(&oldest);
+            }
+            self.order.push_back(trace_id);
+        }
+        self.traces
+            .entry(trace_id)
+            .or_insert_with(|| Trace::Pending(Vec::new()))
+    }
+}
+
+/// Samples traces at their tail before passing them on to `inner`.
+///
+/// The request span of a trace is its server span, as created by
+/// [`SpanFactory`](crate::make_span::SpanFactory).
+#[derive(Debug)]
+pub struct TailSampler<P> {
+    inner: P,
+    config: SamplingConfig,
+    traces: Mutex<Traces>,
+}
+
+impl<P> TailSampler<P> {
+    pub fn new(inner: P, config: SamplingConfig) -> Self {
+        Self {
+            inner,
+            config,
+            traces: Mutex::new(Traces::default()),
+        }
+    }
+}
+
+impl<P: SpanProcessor> SpanProcessor for TailSampler<P> {
+    fn on_start(&self, span: &mut Span, cx: &Context) {
+        self.inner.on_start(span, cx);
+    }
+
+    fn on_end(&self, span: SpanData) {
+        let exported = {
+            let mut traces =
+                self.traces.lock().unwrap_or_else(PoisonError::into_inner);
+            let trace = traces.entry(span.span_context.trace_id());
+            match trace {
+                Trace::Pending(spans) if span.span_kind == SpanKind::Server => {
+                    let spans = std::mem::take(spans);
+                    let sampled = self.config.sampled(&span, &spans);
+                    *trace = Trace::Decided(sampled);
+                    if sampled {
+                        spans.into_iter().chain(Some(span)).collect()
+                    } else {
+                        Vec::new()
+                    }
+                }
+                Trace::Pending(spans) => {
+                    spans.push(span);
+                    Vec::new()
+                }
+                Trace::Decided(true) => vec![span],
+                Trace::Decided(false) => Vec::new(),
+            }
+        };
+        for span in exported {
+            self.inner.on_end(span);
+        }
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        self.inner.force_flush()
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        self.inner.shutdown()
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.inner.set_resource(resource);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{borrow::Cow, time::SystemTime};
+
+    use opentelemetry::{
+        InstrumentationScope, KeyValue,
+        trace::{SpanContext, SpanId, TraceFlags, TraceState},
+    };
+    use opentelemetry_sdk::trace::{SpanEvents, SpanLinks};
+
+    use super::*;
+
+    fn request_span(
+        trace_id: u128,
+        path: &str,
+        duration: Duration,
+        status: Status,
+    ) -> SpanData {
+        let start_time = SystemTime::UNIX_EPOCH;
+        SpanData {
+            span_context: SpanContext::new(
+                TraceId::from(trace_id),
+                SpanId::from(1),
+                TraceFlags::SAMPLED,
+                false,
+                TraceState::NONE,
+            ),
+            parent_span_id: SpanId::INVALID,
+            span_kind: SpanKind::Server,
+            name: Cow::Borrowed("request"),
+            start_time,
+            end_time: start_time + duration,
+            attributes: vec![KeyValue::new(PATH_ATTRIBUTE, path.to_string())],
+            dropped_attributes_count: 0,
+            events: SpanEvents::default(),
+            links: SpanLinks::default(),
+            status,
+            instrumentation_scope: InstrumentationScope::builder("test")
+                .build(),
+        }
+    }
+
+    #[test]
+    fn errors_and_slow_requests_are_always_sampled() {
+        let config = SamplingConfig {
+            ratio: Decimal::ZERO,
+            slow_threshold: Some(Duration::from_secs(2)),
+            ..SamplingConfig::default()
+        };
+        let fast = Duration::from_millis(100);
+        let ok = request_span(1, "/ai/chat/completions", fast, Status::Ok);
+        assert!(!config.sampled(&ok, &[]));
+
+        let error = request_span(
+            1,
+            "/ai/chat/completions",
+            fast,
+            Status::error("server error"),
+        );
+        assert!(config.sampled(&error, &[]));
+        assert!(config.sampled(&ok, &[error]));
+
+        let slow = request_span(
+            1,
+            "/ai/chat/completions",
+            Duration::from_secs(3),
+            Status::Ok,
+        );
+        assert!(config.sampled(&slow, &[]));
+    }
+
+    #[test]
+    fn routes_override_ratio() {
+        let config = SamplingConfig {
+            ratio: Decimal::ONE,
+            routes: vec![RouteSampling {
+                prefix: "/health".to_string(),
+                ratio: Decimal::ZERO,
+            }],
+            ..SamplingConfig::default()
+        };
+        let fast = Duration::from_millis(1);
+        assert!(
+            !config.sampled(&request_span(1, "/health", fast, Status::Ok), &[])
+        );
+        assert!(config.sampled(
+            &request_span(1, "/ai/chat/completions", fast, Status::Ok),
+            &[]
+        ));
+        assert!(!config.samples_all());
+    }
+
+    #[test]
+    fn ratio_is_decided_by_trace_id() {
+        let half = Decimal::new(5, 1);
+        assert!(in_ratio(TraceId::from(0), half));
+        assert!(!in_ratio(TraceId::from(u128::MAX), half));
+        assert!(in_ratio(TraceId::from(u128::MAX), Decimal::ONE));
+        assert!(!in_ratio(TraceId::from(0), Decimal::ZERO));
+    }
+}
@@ -0,0 +1,10 @@
+create table public.gateway_conversations (
+  id text not null,
+  owner_key_hash text null,
+  messages jsonb not null,
+  expires_at timestamp with time zone not null,
+  constraint gateway_conversations_pkey primary key (id)
+) TABLESPACE pg_default;
+
+create index gateway_conversations_expires_at_idx
+  on public.gateway_conversations using btree (expires_at) TABLESPACE pg_default;
@@ -10,6 +10,7 @@ create table public.helicone_api_keys (
   temp_key boolean not null default false,
   governance boolean not null default false,
   updated_at timestamp with time zone not null default null,
+  max_priority text null,
   constraint helicone_api_keys_pkey primary key (api_key_hash, id),
   constraint helicone_api_keys_id_key unique (id),
   constraint helicone_api_keys_organization_id_fkey foreign KEY (organization_id) references organization (id)
@@ -24,6 +25,7 @@ BEGIN
       'organization_id', COALESCE(NEW.organization_id, OLD.organization_id),
       'api_key_hash', COALESCE(NEW.api_key_hash, OLD.api_key_hash),
       'owner_id', COALESCE(NEW.user_id, OLD.user_id),
+      'max_priority', COALESCE(NEW.max_priority, OLD.max_priority),
       'op', TG_OP
     )::text
   );
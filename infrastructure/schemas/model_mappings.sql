@@ -0,0 +1,32 @@
+-- Model mappings managed through the gateway's admin API. `router_hash` is
+-- empty for mappings that override the default model mapping.
+create table public.gateway_model_mappings (
+  router_hash character varying(255) not null default '',
+  source_model text not null,
+  targets jsonb not null,
+  updated_at timestamp with time zone not null default now(),
+  constraint gateway_model_mappings_pkey primary key (router_hash, source_model)
+) TABLESPACE pg_default;
+
+CREATE OR REPLACE FUNCTION broadcast_model_mapping_change() RETURNS trigger AS $$
+BEGIN
+  PERFORM pg_notify(
+    'connected_cloud_gateways',
+    json_build_object(
+      'event', 'model_mapping_updated',
+      'router_hash', COALESCE(NEW.router_hash, OLD.router_hash),
+      'source_model', COALESCE(NEW.source_model, OLD.source_model),
+      'targets', COALESCE(NEW.targets, OLD.targets),
+      'op', TG_OP
+    )::text
+  );
+  RETURN COALESCE(NEW, OLD);
+END;
+$$ LANGUAGE plpgsql;
+
+create trigger t_gateway_model_mappings_broadcast
+after INSERT
+or DELETE
+or
+update on gateway_model_mappings for EACH row
+execute FUNCTION broadcast_model_mapping_change ();
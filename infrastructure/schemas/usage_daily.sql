@@ -0,0 +1,9 @@
+create table public.gateway_usage_daily (
+  day date not null,
+  provider text not null,
+  model text not null,
+  requests bigint not null default 0,
+  prompt_tokens bigint not null default 0,
+  completion_tokens bigint not null default 0,
+  constraint gateway_usage_daily_pkey primary key (day, provider, model)
+) TABLESPACE pg_default;
@@ -0,0 +1,233 @@
+//! Replays logged requests against a gateway, preserving the pacing of the
+//! original traffic.
+//!
+//! The input is a JSON lines export of logged requests, one per line, each
+//! with the `requestCreatedAt` and `path` fields of the request log sent to
+//! Jawn, and the `requestBody` that was uploaded to MinIO. Whether a request
+//! is streamed is determined by its body, as with any other request.
+//! `ClickHouse` exports with snake case column names are accepted as well.
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use chrono::{DateTime, Utc};
+use clap::Parser;
+use futures::StreamExt;
+use serde::Deserialize;
+use tokio::{
+    sync::{Semaphore, mpsc},
+    time::Instant,
+};
+use url::Url;
+
+#[derive(Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// JSON lines file of logged requests, or `-` to read from stdin.
+    #[arg(short, long)]
+    input: PathBuf,
+
+    /// Base URL of the gateway to replay against, e.g.
+    /// `http://localhost:8080/router/my-router`. The logged path of each
+    /// request is appended to it unless `--path` is given.
+    #[arg(short, long)]
+    target: Url,
+
+    /// Send every request to this path of the target instead of the logged
+    /// one.
+    #[arg(long)]
+    path: Option<String>,
+
+    /// Pacing relative to the original traffic: 2 replays twice as fast, 0
+    /// sends requests as fast as `--concurrency` allows.
+    #[arg(short, long, default_value_t = 1.0)]
+    speed: f64,
+
+    /// Maximum number of requests in flight.
+    #[arg(short, long, default_value_t = 64)]
+    concurrency: usize,
+
+    /// Only replay the first N requests.
+    #[arg(short, long)]
+    limit: Option<usize>,
+
+    /// Helicone API key sent as the bearer token.
+    #[arg(long, env = "HELICONE_API_KEY")]
+    api_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoggedRequest {
+    #[serde(alias = "request_created_at")]
+    request_created_at: DateTime<Utc>,
+    path: String,
+    #[serde(alias = "request_body")]
+    request_body: String,
+}
+
+#[derive(Debug)]
+struct Outcome {
+    status: Option<u16>,
+    latency: Duration,
+    /// How far behind its scheduled send time the request was sent.
+    lag: Duration,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    dotenvy::dotenv().ok();
+    let args = Args::parse();
+    if args.speed < 0.0 || !args.speed.is_finite() {
+        bail!("speed must be a non-negative number");
+    }
+    let mut requests = read_requests(&args)?;
+    if requests.is_empty() {
+        bail!("no requests to replay");
+    }
+    requests.sort_by_key(|request| request.request_created_at);
+    if let Some(limit) = args.limit {
+        requests.truncate(limit);
+    }
+    let first_created_at = requests[0].request_created_at;
+    println!(
+        "replaying {} requests against {}",
+        requests.len(),
+        args.target
+    );
+
+    let client = reqwest::Client::new();
+    let semaphore = Arc::new(Semaphore::new(args.concurrency.max(1)));
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let start = Instant::now();
+    for request in requests {
+        let offset = (request.request_created_at - first_created_at)
+            .to_std()
+            .unwrap_or_default();
+        let scheduled_at = if args.speed == 0.0 {
+            start
+        } else {
+            start + offset.div_f64(args.speed)
+        };
+        tokio::time::sleep_until(scheduled_at).await;
+        let permit = Arc::clone(&semaphore).acquire_owned().await?;
+        let url = request_url(&args, &request);
+        let builder = client
+            .post(url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(request.request_body);
+        let builder = match args.api_key.as_deref() {
+            Some(api_key) => builder.bearer_auth(api_key),
+            None => builder,
+        };
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            let sent_at = Instant::now();
+            let lag = sent_at.saturating_duration_since(scheduled_at);
+            let status = send(builder).await;
+            drop(permit);
+            let _ = tx.send(Outcome {
+                status,
+                latency: sent_at.elapsed(),
+                lag,
+            });
+        });
+    }
+    drop(tx);
+
+    let mut outcomes = Vec::new();
+    while let Some(outcome) = rx.recv().await {
+        outcomes.push(outcome);
+    }
+    report(&outcomes, start.elapsed());
+    Ok(())
+}
+
+fn read_requests(args: &Args) -> anyhow::Result<Vec<LoggedRequest>> {
+    let reader: Box<dyn BufRead> = if args.input.as_os_str() == "-" {
+        Box::new(BufReader::new(std::io::stdin()))
+    } else {
+        let file = std::fs::File::open(&args.input).with_context(|| {
+            format!("failed to open {}", args.input.display())
+        })?;
+        Box::new(BufReader::new(file))
+    };
+    let mut requests = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = serde_json::from_str(&line)
+            .with_context(|| format!("invalid request on line {}", i + 1))?;
+        requests.push(request);
+    }
+    Ok(requests)
+}
+
+fn request_url(args: &Args, request: &LoggedRequest) -> Url {
+    let path = args.path.as_deref().unwrap_or(&request.path);
+    let mut url = args.target.clone();
+    let base = url.path().trim_end_matches('/').to_string();
+    url.set_path(&format!("{base}/{}", path.trim_start_matches('/')));
+    url
+}
+
+/// Sends a request and reads its response in full, returning the status, or
+/// `None` if the request failed.
+async fn send(builder: reqwest::RequestBuilder) -> Option<u16> {
+    let response = match builder.send().await {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("request failed: {e}");
+            return None;
+        }
+    };
+    let status = response.status().as_u16();
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        if let Err(e) = chunk {
+            eprintln!("failed to read response: {e}");
+            return None;
+        }
+    }
+    Some(status)
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn report(outcomes: &[Outcome], elapsed: Duration) {
+    let mut statuses = BTreeMap::<String, usize>::new();
+    for outcome in outcomes {
+        let status = outcome
+            .status
+            .map_or_else(|| "error".to_string(), |status| status.to_string());
+        *statuses.entry(status).or_default() += 1;
+    }
+    let mut latencies = outcomes.iter().map(|o| o.latency).collect::<Vec<_>>();
+    latencies.sort_unstable();
+    let max_lag = outcomes.iter().map(|o| o.lag).max().unwrap_or_default();
+
+    println!();
+    println!("requests:   {}", outcomes.len());
+    println!("elapsed:    {elapsed:.2?}");
+    println!(
+        "throughput: {:.2} req/s",
+        outcomes.len() as f64 / elapsed.as_secs_f64()
+    );
+    println!("max lag:    {max_lag:.2?}");
+    for (status, count) in statuses {
+        println!("status {status}: {count}");
+    }
+    for percentile in [50, 90, 95, 99] {
+        let index = (latencies.len() * percentile / 100)
+            .min(latencies.len().saturating_sub(1));
+        if let Some(latency) = latencies.get(index) {
+            println!("p{percentile} latency: {latency:.2?}");
+        }
+    }
+}